@@ -5,11 +5,7 @@ use std::sync::Arc;
 use tokio::runtime::Runtime;
 
 async fn setup_db() -> Arc<VectorDatabase> {
-    let temp_dir = tempfile::tempdir().unwrap();
-    let db_path = temp_dir.path().to_str().unwrap();
-    let db = Arc::new(VectorDatabase::new(db_path).await.unwrap());
-    std::mem::forget(temp_dir); // Keep temp dir alive
-    db
+    Arc::new(VectorDatabase::in_memory().unwrap())
 }
 
 fn insert_benchmark(c: &mut Criterion) {
@@ -0,0 +1,389 @@
+import type { components } from "./schema.js";
+
+type Schemas = components["schemas"];
+
+export type Vector = Schemas["Vector"];
+export type SparseVector = Schemas["SparseVector"];
+export type WriteConcern = Schemas["WriteConcern"];
+export type WriteReport = Schemas["WriteReport"];
+export type InsertResponse = Schemas["InsertResponse"];
+export type ValidateResponse = Schemas["ValidateResponse"];
+export type SearchRequest = Schemas["SearchRequest"];
+export type SearchResponse = Schemas["SearchResponse"];
+export type SearchResult = Schemas["SearchResult"];
+export type DocumentSearchRequest = Schemas["DocumentSearchRequest"];
+export type DocumentSearchResponse = Schemas["DocumentSearchResponse"];
+export type TextSearchRequest = Schemas["TextSearchRequest"];
+export type TextItem = Schemas["TextItem"];
+export type StatsResponse = Schemas["StatsResponse"];
+export type NamespaceStats = Schemas["NamespaceStats"];
+export type ReadinessResponse = Schemas["ReadinessResponse"];
+export type CollectionStats = Schemas["CollectionStats"];
+export type CollectionConfig = Schemas["CollectionConfig"];
+export type AggregateResponse = Schemas["AggregateResponse"];
+export type ScrollPage = Schemas["ScrollPage"];
+export type SnapshotInfo = Schemas["SnapshotInfo"];
+export type SnapshotDiff = Schemas["SnapshotDiff"];
+export type ChangeEvent = Schemas["ChangeEvent"];
+export type ChangesResponse = Schemas["ChangesResponse"];
+export type BackupRequest = Schemas["BackupRequest"];
+export type BackupResponse = Schemas["BackupResponse"];
+export type Job = Schemas["Job"];
+export type SlowQuery = Schemas["SlowQuery"];
+export type VerifyResponse = Schemas["VerifyResponse"];
+export type Role = Schemas["Role"];
+export type ApiKeyInfo = Schemas["ApiKeyInfo"];
+export type CreateApiKeyResponse = Schemas["CreateApiKeyResponse"];
+export type ImportResponse = Schemas["ImportResponse"];
+
+// A vector to insert; `created_at` is filled in when left out
+export type NewVector = Omit<Vector, "created_at"> & { created_at?: number };
+
+export interface ClientOptions {
+  // Base URL of a node, e.g. "http://localhost:8080"
+  url: string;
+  apiKey?: string;
+  // The server's `[auth] key_header`
+  apiKeyHeader?: string;
+  // Requests go to the default namespace when left out
+  namespace?: string;
+  // Defaults to the global fetch
+  fetch?: typeof fetch;
+}
+
+export interface InsertOptions {
+  // In cluster mode, how many replicas must have the vectors before the
+  // insert answers, and for how long to wait for them
+  writeConcern?: WriteConcern;
+  writeTimeoutMs?: number;
+}
+
+export interface ImportOptions {
+  format: "csv" | "parquet";
+  idColumn?: string;
+  vectorColumn?: string;
+  dimensionPrefix?: string;
+  collectionColumn?: string;
+  collection?: string;
+  metadataColumns?: string[];
+}
+
+type Query = Record<string, string | number | boolean | undefined>;
+
+interface RequestOptions {
+  query?: Query;
+  json?: unknown;
+  body?: BodyInit;
+  contentType?: string;
+}
+
+// A response outside 2xx, with the server's error message when it sent one
+export class ApiError extends Error {
+  constructor(
+    readonly status: number,
+    message: string,
+  ) {
+    super(message);
+    this.name = "ApiError";
+  }
+}
+
+export class SkyPierClient {
+  private readonly options: ClientOptions;
+  private readonly fetch: typeof fetch;
+
+  constructor(options: ClientOptions) {
+    this.options = { ...options, url: options.url.replace(/\/+$/, "") };
+    this.fetch = options.fetch ?? globalThis.fetch.bind(globalThis);
+  }
+
+  // The same node and key, for another namespace
+  withNamespace(namespace: string): SkyPierClient {
+    return new SkyPierClient({ ...this.options, namespace });
+  }
+
+  async health(): Promise<string> {
+    const response = await this.send("GET", "/health");
+    return response.text();
+  }
+
+  // Unlike the other calls, answers rather than throws when the node isn't
+  // ready
+  async ready(): Promise<ReadinessResponse> {
+    const response = await this.send("GET", "/health/ready", {}, [503]);
+    return response.json();
+  }
+
+  stats(): Promise<StatsResponse> {
+    return this.request("GET", "/stats");
+  }
+
+  listNamespaces(): Promise<NamespaceStats[]> {
+    return this.request("GET", "/namespaces");
+  }
+
+  // Without a write concern the server answers with the new ids
+  insert(vectors: NewVector[], options: InsertOptions = {}): Promise<InsertResponse> {
+    return this.request("POST", "/vectors", {
+      json: {
+        vectors: vectors.map(withCreatedAt),
+        write_concern: options.writeConcern,
+        write_timeout_ms: options.writeTimeoutMs,
+      },
+    });
+  }
+
+  validate(vectors: NewVector[]): Promise<ValidateResponse> {
+    return this.request("POST", "/vectors/validate", {
+      json: { vectors: vectors.map(withCreatedAt) },
+    });
+  }
+
+  // Null when there's no such vector. `collection` saves asking every
+  // member in cluster mode.
+  async get(id: string, collection?: string): Promise<Vector | null> {
+    const response = await this.send("GET", `/vectors/${encode(id)}`, { query: { collection } }, [404]);
+    return response.status === 404 ? null : response.json();
+  }
+
+  // False when there was no such vector
+  async delete(id: string, collection?: string): Promise<boolean> {
+    const response = await this.send("DELETE", `/vectors/${encode(id)}`, { query: { collection } }, [404]);
+    return response.status !== 404;
+  }
+
+  // The vector and its kept earlier versions, newest first
+  versions(id: string): Promise<Vector[]> {
+    return this.request("GET", `/vectors/${encode(id)}/versions`);
+  }
+
+  search(request: SearchRequest): Promise<SearchResponse> {
+    return this.request("POST", "/search", { json: request });
+  }
+
+  searchCollection(collection: string, request: SearchRequest): Promise<SearchResponse> {
+    return this.request("POST", `/collections/${encode(collection)}/search`, { json: request });
+  }
+
+  searchDocuments(request: DocumentSearchRequest): Promise<DocumentSearchResponse> {
+    return this.request("POST", "/search/documents", { json: request });
+  }
+
+  // Needs the server's embeddings gateway
+  searchText(request: TextSearchRequest): Promise<SearchResponse> {
+    return this.request("POST", "/search/text", { json: request });
+  }
+
+  embedAndInsert(items: TextItem[]): Promise<string[]> {
+    return this.request("POST", "/embed-and-insert", { json: { items } });
+  }
+
+  collectionStats(collection: string): Promise<CollectionStats> {
+    return this.request("GET", `/collections/${encode(collection)}/stats`);
+  }
+
+  aggregate(collection: string, groupBy: string, limit?: number): Promise<AggregateResponse> {
+    return this.request("GET", `/collections/${encode(collection)}/aggregate`, {
+      query: { group_by: groupBy, limit },
+    });
+  }
+
+  // Pass the page's `next` as `after` for the following one
+  scroll(collection: string, options: { after?: string; limit?: number } = {}): Promise<ScrollPage> {
+    return this.request("GET", `/collections/${encode(collection)}/scroll`, { query: options });
+  }
+
+  collectionConfig(collection: string): Promise<CollectionConfig> {
+    return this.request("GET", `/collections/${encode(collection)}/config`);
+  }
+
+  updateCollectionConfig(collection: string, config: CollectionConfig): Promise<CollectionConfig> {
+    return this.request("PUT", `/collections/${encode(collection)}/config`, { json: config });
+  }
+
+  createSnapshot(collection: string, name: string): Promise<SnapshotInfo> {
+    return this.request("POST", `/collections/${encode(collection)}/snapshots`, { json: { name } });
+  }
+
+  listSnapshots(collection: string): Promise<SnapshotInfo[]> {
+    return this.request("GET", `/collections/${encode(collection)}/snapshots`);
+  }
+
+  async deleteSnapshot(collection: string, name: string): Promise<boolean> {
+    const response = await this.send("DELETE", snapshotPath(collection, name), {}, [404]);
+    return response.status !== 404;
+  }
+
+  searchSnapshot(collection: string, name: string, request: SearchRequest): Promise<SearchResponse> {
+    return this.request("POST", `${snapshotPath(collection, name)}/search`, { json: request });
+  }
+
+  exportSnapshot(collection: string, name: string): Promise<Vector[]> {
+    return this.request("GET", `${snapshotPath(collection, name)}/export`);
+  }
+
+  // Copies the snapshot's vectors into the `target` collection
+  cloneSnapshot(collection: string, name: string, target: string): Promise<string[]> {
+    return this.request("POST", `${snapshotPath(collection, name)}/clone`, { json: { target } });
+  }
+
+  // Against the live collection, or another snapshot
+  diffSnapshot(collection: string, name: string, against?: string): Promise<SnapshotDiff> {
+    return this.request("GET", `${snapshotPath(collection, name)}/diff`, { query: { against } });
+  }
+
+  // Writes after WAL position `since`; pass `next` back for the ones after
+  changes(since = 0, limit?: number): Promise<ChangesResponse> {
+    return this.request("GET", "/changes", { query: { since, limit } });
+  }
+
+  // A CSV or Parquet file, sent as the request body. Streams are sent as
+  // they're read, but the server still takes in the whole file before
+  // loading it.
+  importFile(body: BodyInit, options: ImportOptions): Promise<ImportResponse> {
+    return this.request("POST", "/import", {
+      body,
+      contentType: "application/octet-stream",
+      query: {
+        format: options.format,
+        id_column: options.idColumn,
+        vector_column: options.vectorColumn,
+        dimension_prefix: options.dimensionPrefix,
+        collection_column: options.collectionColumn,
+        collection: options.collection,
+        metadata_columns: options.metadataColumns?.join(","),
+      },
+    });
+  }
+
+  // The download as it arrives, Parquet unless asked for Arrow
+  async exportFile(
+    options: { format?: "parquet" | "arrow"; collection?: string } = {},
+  ): Promise<ReadableStream<Uint8Array>> {
+    const response = await this.send("GET", "/export", { query: options });
+    if (!response.body) {
+      throw new ApiError(response.status, "Export has no body");
+    }
+    return response.body;
+  }
+
+  // A job when `background` is set, otherwise where the backup went
+  backup(request: BackupRequest): Promise<BackupResponse | Job> {
+    return this.request("POST", "/admin/backup", { json: request });
+  }
+
+  compact(): Promise<Job> {
+    return this.request("POST", "/admin/compact");
+  }
+
+  reindex(): Promise<Job> {
+    return this.request("POST", "/admin/reindex");
+  }
+
+  verify(repair = false): Promise<VerifyResponse> {
+    return this.request("POST", "/admin/verify", { query: { repair } });
+  }
+
+  listJobs(): Promise<Job[]> {
+    return this.request("GET", "/admin/jobs");
+  }
+
+  job(id: string): Promise<Job> {
+    return this.request("GET", `/admin/jobs/${encode(id)}`);
+  }
+
+  cancelJob(id: string): Promise<Job> {
+    return this.request("POST", `/admin/jobs/${encode(id)}/cancel`);
+  }
+
+  slowQueries(): Promise<SlowQuery[]> {
+    return this.request("GET", "/admin/slow-queries");
+  }
+
+  listApiKeys(): Promise<ApiKeyInfo[]> {
+    return this.request("GET", "/admin/keys");
+  }
+
+  // The key itself is only ever in this response
+  createApiKey(role: Role): Promise<CreateApiKeyResponse> {
+    return this.request("POST", "/admin/keys", { json: { role } });
+  }
+
+  async updateApiKey(id: string, role: Role): Promise<void> {
+    await this.send("PUT", `/admin/keys/${encode(id)}`, { json: { role } });
+  }
+
+  async revokeApiKey(id: string): Promise<void> {
+    await this.send("DELETE", `/admin/keys/${encode(id)}`);
+  }
+
+  private async request<T>(method: string, path: string, options: RequestOptions = {}): Promise<T> {
+    const response = await this.send(method, path, options);
+    return response.json() as Promise<T>;
+  }
+
+  // Throws ApiError for statuses outside 2xx, unless they're `allowed`
+  private async send(
+    method: string,
+    path: string,
+    options: RequestOptions = {},
+    allowed: number[] = [],
+  ): Promise<Response> {
+    const { namespace, apiKey, apiKeyHeader = "x-api-key" } = this.options;
+    const prefix = namespace === undefined ? "" : `/namespaces/${encode(namespace)}`;
+    const url = new URL(`${this.options.url}${prefix}${path}`);
+    for (const [key, value] of Object.entries(options.query ?? {})) {
+      if (value !== undefined) {
+        url.searchParams.set(key, String(value));
+      }
+    }
+
+    const headers: Record<string, string> = {};
+    if (apiKey !== undefined) {
+      headers[apiKeyHeader] = apiKey;
+    }
+    let body = options.body;
+    if (options.json !== undefined) {
+      headers["content-type"] = "application/json";
+      body = JSON.stringify(options.json);
+    } else if (options.contentType !== undefined) {
+      headers["content-type"] = options.contentType;
+    }
+    // Node only sends stream bodies with half duplex
+    const init: RequestInit & { duplex?: "half" } = { method, headers, body };
+    if (body instanceof ReadableStream) {
+      init.duplex = "half";
+    }
+
+    const response = await this.fetch(url, init);
+    if (!response.ok && !allowed.includes(response.status)) {
+      throw new ApiError(response.status, await errorMessage(response));
+    }
+    return response;
+  }
+}
+
+async function errorMessage(response: Response): Promise<string> {
+  const text = await response.text();
+  try {
+    const error = JSON.parse(text) as { error?: unknown };
+    if (typeof error.error === "string") {
+      return error.error;
+    }
+  } catch {
+    // Not JSON; fall through to the text or status
+  }
+  return text || `${response.status} ${response.statusText}`;
+}
+
+function withCreatedAt(vector: NewVector): Vector {
+  return { ...vector, created_at: vector.created_at ?? Math.floor(Date.now() / 1000) };
+}
+
+function encode(segment: string): string {
+  return encodeURIComponent(segment);
+}
+
+function snapshotPath(collection: string, name: string): string {
+  return `/collections/${encode(collection)}/snapshots/${encode(name)}`;
+}
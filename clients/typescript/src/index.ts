@@ -0,0 +1,3 @@
+export * from "./client.js";
+export * from "./streaming.js";
+export type { components, paths } from "./schema.js";
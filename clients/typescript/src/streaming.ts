@@ -0,0 +1,121 @@
+import type { ChangeEvent, InsertOptions, NewVector, SkyPierClient, Vector } from "./client.js";
+
+export interface InsertStreamOptions extends InsertOptions {
+  // Vectors per POST /vectors request
+  batchSize?: number;
+  // Requests in flight at once
+  concurrency?: number;
+  // Called with each batch's ids as it's written
+  onBatch?: (ids: string[]) => void;
+}
+
+// Inserts vectors as they're produced, e.g. from a file being parsed, in
+// batches with a few requests in flight. Stops at the first failed batch;
+// the batches before it stay written. Resolves to the number inserted.
+export async function insertStream(
+  client: SkyPierClient,
+  vectors: AsyncIterable<NewVector> | Iterable<NewVector>,
+  options: InsertStreamOptions = {},
+): Promise<number> {
+  const batchSize = Math.max(1, options.batchSize ?? 500);
+  const concurrency = Math.max(1, options.concurrency ?? 4);
+  const pending = new Set<Promise<void>>();
+  let inserted = 0;
+  const failures: unknown[] = [];
+
+  const send = (batch: NewVector[]) => {
+    const request: Promise<void> = client
+      .insert(batch, options)
+      .then((response) => {
+        const ids = Array.isArray(response) ? response : response.ids;
+        inserted += ids.length;
+        options.onBatch?.(ids);
+      })
+      .catch((error: unknown) => {
+        failures.push(error);
+      })
+      .finally(() => {
+        pending.delete(request);
+      });
+    pending.add(request);
+  };
+
+  let batch: NewVector[] = [];
+  for await (const vector of vectors) {
+    batch.push(vector);
+    if (batch.length < batchSize) {
+      continue;
+    }
+    send(batch);
+    batch = [];
+    while (pending.size >= concurrency) {
+      await Promise.race(pending);
+    }
+    if (failures.length > 0) {
+      break;
+    }
+  }
+  if (batch.length > 0 && failures.length === 0) {
+    send(batch);
+  }
+  await Promise.all(pending);
+  if (failures.length > 0) {
+    throw failures[0];
+  }
+  return inserted;
+}
+
+// Every vector in a collection, a page at a time, in id order
+export async function* scrollAll(
+  client: SkyPierClient,
+  collection: string,
+  pageSize?: number,
+): AsyncGenerator<Vector> {
+  let after: string | undefined;
+  do {
+    const page = await client.scroll(collection, { after, limit: pageSize });
+    yield* page.vectors;
+    after = page.next ?? undefined;
+  } while (after !== undefined);
+}
+
+export interface FollowChangesOptions {
+  // WAL position to start after
+  since?: number;
+  limit?: number;
+  // How long to wait before asking again once caught up
+  pollIntervalMs?: number;
+  signal?: AbortSignal;
+}
+
+// Writes as they happen, polled from GET /changes, until `signal` aborts.
+// Resume later from the last event's `seq`. A 410 error means the server
+// no longer has the changes after `since`, and the consumer has to start
+// over from a full export.
+export async function* followChanges(
+  client: SkyPierClient,
+  options: FollowChangesOptions = {},
+): AsyncGenerator<ChangeEvent> {
+  const pollIntervalMs = options.pollIntervalMs ?? 1000;
+  let since = options.since ?? 0;
+  while (!options.signal?.aborted) {
+    const { changes, next } = await client.changes(since, options.limit);
+    yield* changes;
+    since = next;
+    if (changes.length === 0) {
+      await sleep(pollIntervalMs, options.signal);
+    }
+  }
+}
+
+function sleep(ms: number, signal?: AbortSignal): Promise<void> {
+  return new Promise((resolve) => {
+    const timer = setTimeout(done, ms);
+    signal?.addEventListener("abort", done, { once: true });
+    function done() {
+      clearTimeout(timer);
+      signal?.removeEventListener("abort", done);
+      resolve();
+    }
+  });
+}
@@ -0,0 +1,226 @@
+use anyhow::{anyhow, Result};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use skypier_index::Metric;
+
+use crate::database::SampleRng;
+use crate::DistanceMetric;
+
+// How a collection is clustered. The assignments go into each vector's
+// metadata under `metadata_key`, so they can be filtered and aggregated on.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClusterOptions {
+    pub k: usize,
+    // Mini-batch steps, each over `batch_size` vectors of the training sample
+    pub iterations: usize,
+    pub batch_size: usize,
+    // Makes the run repeatable, as long as the collection is unchanged
+    pub seed: Option<u64>,
+    pub metadata_key: String,
+}
+
+impl Default for ClusterOptions {
+    fn default() -> Self {
+        Self {
+            k: 8,
+            iterations: 100,
+            batch_size: 1024,
+            seed: None,
+            metadata_key: "cluster".to_string(),
+        }
+    }
+}
+
+// The outcome of the last clustering of a collection
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Clustering {
+    pub collection: String,
+    pub metric: DistanceMetric,
+    pub metadata_key: String,
+    // Cluster i is the one stored as "i" under `metadata_key`
+    pub centroids: Vec<Vec<f32>>,
+    // Vectors assigned to each cluster
+    pub sizes: Vec<u64>,
+    pub created_at: u64,
+}
+
+impl Clustering {
+    // The cluster a vector belongs in
+    pub fn assign(&self, vector: &[f32]) -> usize {
+        nearest(&self.centroids, vector, self.metric.index_metric())
+    }
+}
+
+// The centroid most similar to `vector` under `metric`
+pub fn nearest(centroids: &[Vec<f32>], vector: &[f32], metric: Metric) -> usize {
+    centroids
+        .iter()
+        .map(|centroid| metric.similarity(vector, centroid))
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map_or(0, |(cluster, _)| cluster)
+}
+
+fn normalize(vector: &mut [f32]) {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|x| *x /= norm);
+    }
+}
+
+// k-means++ makes a pass over its candidates per centroid, so it picks from
+// this many per cluster rather than from the whole sample
+const SEED_CANDIDATES_PER_CLUSTER: usize = 16;
+
+fn squared_l2(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum()
+}
+
+// k-means++ over a random subset of the sample: each centroid after the
+// first is picked with odds by its squared distance to the nearest one
+// already picked, so they start spread out
+fn seed_centroids(sample: &[&[f32]], k: usize, rng: &mut SampleRng) -> Vec<Vec<f32>> {
+    let count = (k * SEED_CANDIDATES_PER_CLUSTER).min(sample.len());
+    // The first `count` of a partial shuffle
+    let mut order: Vec<usize> = (0..sample.len()).collect();
+    for i in 0..count {
+        let j = i + rng.below((sample.len() - i) as u64) as usize;
+        order.swap(i, j);
+    }
+    let candidates: Vec<&[f32]> = order[..count].iter().map(|&i| sample[i]).collect();
+
+    let mut centroids = vec![candidates[0].to_vec()];
+    let mut distances: Vec<f32> = candidates
+        .iter()
+        .map(|candidate| squared_l2(candidate, candidates[0]))
+        .collect();
+    while centroids.len() < k {
+        let total: f64 = distances.iter().map(|&d| d as f64).sum();
+        let picked = if total > 0.0 {
+            // A fraction of the total with 2^32 steps is plenty
+            let mut target = rng.below(1 << 32) as f64 / (1u64 << 32) as f64 * total;
+            distances
+                .iter()
+                .position(|&d| {
+                    target -= d as f64;
+                    target < 0.0
+                })
+                .unwrap_or(count - 1)
+        } else {
+            // Every candidate is a centroid already
+            centroids.len()
+        };
+        let centroid = candidates[picked];
+        for (distance, candidate) in distances.iter_mut().zip(&candidates) {
+            *distance = distance.min(squared_l2(candidate, centroid));
+        }
+        centroids.push(centroid.to_vec());
+    }
+    centroids
+}
+
+// Mini-batch k-means (Sculley, 2010) over a sample. Centroids start from
+// `seed_centroids`; each step assigns a random batch to its nearest
+// centroids, then moves each centroid toward its members at a rate that
+// shrinks with how many it has taken in. Under cosine the centroids are
+// kept at unit length.
+pub fn train(sample: &[&[f32]], options: &ClusterOptions, metric: Metric) -> Result<Vec<Vec<f32>>> {
+    let k = options.k;
+    if k == 0 || sample.len() < k {
+        return Err(anyhow!(
+            "Can't make {} clusters from {} vectors",
+            k,
+            sample.len()
+        ));
+    }
+    let dimensions = sample[0].len();
+    if let Some(other) = sample.iter().find(|vector| vector.len() != dimensions) {
+        return Err(anyhow!(
+            "Vectors of {} and {} dimensions can't be clustered together",
+            dimensions,
+            other.len()
+        ));
+    }
+
+    let mut rng = SampleRng::new(options.seed);
+    let mut centroids = seed_centroids(sample, k, &mut rng);
+    if metric == Metric::Cosine {
+        centroids
+            .iter_mut()
+            .for_each(|centroid| normalize(centroid));
+    }
+
+    let mut counts = vec![0u64; k];
+    let batch_size = options.batch_size.clamp(1, sample.len());
+    for _ in 0..options.iterations {
+        let batch: Vec<&[f32]> = (0..batch_size)
+            .map(|_| sample[rng.below(sample.len() as u64) as usize])
+            .collect();
+        let assigned: Vec<usize> = batch
+            .par_iter()
+            .map(|vector| nearest(&centroids, vector, metric))
+            .collect();
+        for (vector, &cluster) in batch.iter().zip(&assigned) {
+            counts[cluster] += 1;
+            let rate = 1.0 / counts[cluster] as f32;
+            for (x, v) in centroids[cluster].iter_mut().zip(*vector) {
+                *x += rate * (v - *x);
+            }
+        }
+        if metric == Metric::Cosine {
+            for &cluster in &assigned {
+                normalize(&mut centroids[cluster]);
+            }
+        }
+    }
+    Ok(centroids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_train_finds_separated_clusters() {
+        // Three tight blobs far apart
+        let centers = [[10.0, 0.0], [-10.0, 0.0], [0.0, 10.0]];
+        let data: Vec<[f32; 2]> = (0..300)
+            .map(|i| {
+                let [x, y] = centers[i % 3];
+                let jitter = (i as f32 * 0.37).sin() * 0.5;
+                [x + jitter, y - jitter]
+            })
+            .collect();
+        let sample: Vec<&[f32]> = data.iter().map(|vector| vector.as_slice()).collect();
+        let options = ClusterOptions {
+            k: 3,
+            iterations: 50,
+            batch_size: 64,
+            seed: Some(7),
+            ..Default::default()
+        };
+        let centroids = train(&sample, &options, Metric::Euclidean).unwrap();
+
+        // Every blob lands in a cluster of its own
+        let clusters: Vec<usize> = centers
+            .iter()
+            .map(|center| nearest(&centroids, center, Metric::Euclidean))
+            .collect();
+        assert!(clusters[0] != clusters[1] && clusters[1] != clusters[2]);
+        assert!(clusters[0] != clusters[2]);
+        for (i, vector) in sample.iter().enumerate() {
+            assert_eq!(
+                nearest(&centroids, vector, Metric::Euclidean),
+                clusters[i % 3]
+            );
+        }
+        assert_eq!(
+            train(&sample, &options, Metric::Euclidean).unwrap(),
+            centroids
+        );
+
+        assert!(train(&sample[..2], &options, Metric::Euclidean).is_err());
+        let mixed: Vec<&[f32]> = vec![&[1.0, 0.0], &[1.0], &[0.0, 1.0]];
+        assert!(train(&mixed, &options, Metric::Euclidean).is_err());
+    }
+}
@@ -0,0 +1,186 @@
+// Deciding between a stored vector and a replicated write of the same id
+// when replicas take writes on their own
+
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::Vector;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    KeepLocal,
+    TakeRemote,
+    // Neither write saw the other; recorded for an operator to settle
+    Conflict,
+}
+
+pub trait ConflictResolver: Send + Sync {
+    fn resolve(&self, local: &Vector, remote: &Vector) -> Resolution;
+}
+
+// Records written before writes were stamped carry no stamp. Replicated
+// writes replace those as they always did.
+fn unstamped(vector: &Vector) -> bool {
+    vector.hlc == 0 && vector.clock.is_empty()
+}
+
+// The write with the later hybrid logical clock timestamp wins, ties going
+// to the replica with the greater id, so every replica settles on the same
+// record
+pub struct LastWriterWins;
+
+impl ConflictResolver for LastWriterWins {
+    fn resolve(&self, local: &Vector, remote: &Vector) -> Resolution {
+        if unstamped(local) && unstamped(remote) {
+            return Resolution::TakeRemote;
+        }
+        let stamp = |vector: &Vector| (vector.hlc, vector.origin.clone());
+        if stamp(remote) > stamp(local) {
+            Resolution::TakeRemote
+        } else {
+            Resolution::KeepLocal
+        }
+    }
+}
+
+// Takes writes that follow the stored one and ignores ones it already
+// follows. Concurrent writes are conflicts.
+pub struct VectorClocks;
+
+impl ConflictResolver for VectorClocks {
+    fn resolve(&self, local: &Vector, remote: &Vector) -> Resolution {
+        if unstamped(local) && unstamped(remote) {
+            return Resolution::TakeRemote;
+        }
+        match compare_clocks(&local.clock, &remote.clock) {
+            Some(Ordering::Less) => Resolution::TakeRemote,
+            Some(_) => Resolution::KeepLocal,
+            None => Resolution::Conflict,
+        }
+    }
+}
+
+// How `a` relates to `b`: Less when `b` has seen everything `a` has and
+// more, None when each has seen writes the other hasn't
+pub fn compare_clocks(a: &BTreeMap<String, u64>, b: &BTreeMap<String, u64>) -> Option<Ordering> {
+    let mut order = Ordering::Equal;
+    for replica in a.keys().chain(b.keys()) {
+        let (x, y) = (
+            a.get(replica).copied().unwrap_or(0),
+            b.get(replica).copied().unwrap_or(0),
+        );
+        match (order, x.cmp(&y)) {
+            (_, Ordering::Equal) => {}
+            (Ordering::Equal, other) => order = other,
+            (current, other) if current != other => return None,
+            _ => {}
+        }
+    }
+    Some(order)
+}
+
+// Every write either clock has seen
+pub fn merge_clocks(a: &BTreeMap<String, u64>, b: &BTreeMap<String, u64>) -> BTreeMap<String, u64> {
+    let mut merged = a.clone();
+    for (replica, count) in b {
+        let entry = merged.entry(replica.clone()).or_default();
+        *entry = (*entry).max(*count);
+    }
+    merged
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictPolicy {
+    #[default]
+    LastWriterWins,
+    VectorClock,
+}
+
+impl ConflictPolicy {
+    pub fn resolver(self) -> Arc<dyn ConflictResolver> {
+        match self {
+            Self::LastWriterWins => Arc::new(LastWriterWins),
+            Self::VectorClock => Arc::new(VectorClocks),
+        }
+    }
+}
+
+// Concurrent writes of an id, kept until an operator picks one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Conflict {
+    pub id: String,
+    pub local: Vector,
+    pub remote: Vector,
+    // Unix seconds
+    pub detected_at: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictSide {
+    Local,
+    Remote,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(replica: &str, hlc: u64, clock: &[(&str, u64)]) -> Vector {
+        let mut vector = Vector::with_id("a".to_string(), vec![1.0]);
+        vector.hlc = hlc;
+        vector.origin = Some(replica.to_string());
+        vector.clock = clock
+            .iter()
+            .map(|(replica, count)| (replica.to_string(), *count))
+            .collect();
+        vector
+    }
+
+    #[test]
+    fn test_resolvers() {
+        let base = write("x", 100, &[("x", 1)]);
+        let later = write("y", 200, &[("x", 1), ("y", 1)]);
+        let concurrent = write("z", 150, &[("x", 1), ("z", 1)]);
+
+        assert_eq!(
+            LastWriterWins.resolve(&base, &later),
+            Resolution::TakeRemote
+        );
+        assert_eq!(LastWriterWins.resolve(&later, &base), Resolution::KeepLocal);
+        // The same write coming back is ignored
+        assert_eq!(
+            LastWriterWins.resolve(&later, &later),
+            Resolution::KeepLocal
+        );
+        assert_eq!(
+            LastWriterWins.resolve(&later, &concurrent),
+            Resolution::KeepLocal
+        );
+
+        assert_eq!(VectorClocks.resolve(&base, &later), Resolution::TakeRemote);
+        assert_eq!(VectorClocks.resolve(&later, &base), Resolution::KeepLocal);
+        assert_eq!(VectorClocks.resolve(&later, &later), Resolution::KeepLocal);
+        assert_eq!(
+            VectorClocks.resolve(&later, &concurrent),
+            Resolution::Conflict
+        );
+
+        let unstamped = Vector::with_id("a".to_string(), vec![1.0]);
+        assert_eq!(
+            VectorClocks.resolve(&unstamped, &unstamped),
+            Resolution::TakeRemote
+        );
+        assert_eq!(
+            merge_clocks(&later.clock, &concurrent.clock),
+            BTreeMap::from([
+                ("x".to_string(), 1),
+                ("y".to_string(), 1),
+                ("z".to_string(), 1)
+            ])
+        );
+    }
+}
@@ -1,169 +1,5071 @@
 use anyhow::{anyhow, Result};
-use std::collections::HashMap;
+use rayon::prelude::*;
+use std::borrow::Cow;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use tokio::sync::RwLock;
-use uuid::Uuid;
+use tokio::sync::{broadcast, Mutex, RwLock};
+use tracing::{info, warn};
 
-use crate::{DatabaseStats, DistanceMetric, SearchResult, Vector};
-use skypier_index::VectorIndex;
-use skypier_storage::Storage;
+use crate::clustering::{self, ClusterOptions, Clustering};
+use crate::conflict::{
+    merge_clocks, Conflict, ConflictResolver, ConflictSide, LastWriterWins, Resolution,
+};
+use crate::filter::FilterIndex;
+use crate::hlc::{self, HybridClock};
+use crate::ids::{sequence_id, IdScheme, UlidGenerator};
+use crate::quota::{Quota, Usage};
+use crate::schema::MetadataSchema;
+use crate::validation::{self, ValidationError, ValidationLimits};
+use crate::ReadOnlyError;
+use crate::{
+    Aggregate, BatchCheck, ChangeEvent, ChangeKind, ChangeSet, CloneOptions, CollectionStats,
+    DatabaseStats, Dedup, DedupAction, DistanceMetric, Dtype, Fusion, Grouping, RowError,
+    ScoreMode, ScrollPage, SearchFilter, SearchGroup, SearchOptions, SearchProfile, SearchResult,
+    SnapshotDiff, SnapshotInfo, SparseVector, TieBreak, ValueCount, Vector, VectorPlugin,
+};
+use skypier_index::{CancellationToken, FlatIndex, SparseIndex, VectorIndex};
+use skypier_storage::time::{self, Instant};
+use skypier_storage::{AuditEntry, Storage, VerifyReport, WalEntry, WalOp};
+
+const INDEX_SNAPSHOT_FILE: &str = "index.snapshot";
+// The named vectors' indexes, snapshotted alongside the main one
+const NAMED_SNAPSHOT_FILE: &str = "named_indexes.snapshot";
+// And the sparse vectors' inverted index
+const SPARSE_SNAPSHOT_FILE: &str = "sparse_index.snapshot";
+// And the indexes of collections with a distance metric of their own
+const COLLECTION_SNAPSHOT_FILE: &str = "collection_indexes.snapshot";
+// Setting holding the id this database stamps its writes with
+const REPLICA_ID_SETTING: &str = "replica_id";
+// Setting holding replicated writes that conflicted with local ones, as JSON
+const CONFLICTS_SETTING: &str = "replication_conflicts";
+// Past this many unresolved conflicts the oldest are forgotten
+const MAX_CONFLICTS: usize = 1000;
+// Setting holding the collections' distance metrics, as a JSON map
+const COLLECTION_METRICS_SETTING: &str = "collection_metrics";
+// Setting holding each collection's dedup settings, as JSON
+const COLLECTION_DEDUP_SETTING: &str = "collection_dedup";
+// Setting holding the id schemes set per collection, as JSON
+const COLLECTION_ID_SCHEMES_SETTING: &str = "collection_id_schemes";
+// Setting holding how many earlier versions each collection keeps, as JSON
+const COLLECTION_VERSIONS_SETTING: &str = "collection_versions";
+// Setting holding the embedding model each collection expects, as JSON
+const COLLECTION_MODELS_SETTING: &str = "collection_models";
+// Setting holding the collection aliases, as a JSON map to their collections
+const COLLECTION_ALIASES_SETTING: &str = "collection_aliases";
+// Setting holding each collection's metadata schema, as JSON
+const COLLECTION_SCHEMAS_SETTING: &str = "collection_schemas";
+// Setting holding each collection's quota, as JSON
+const COLLECTION_QUOTAS_SETTING: &str = "collection_quotas";
+// Followed by the collection name, holds its last auto-increment id
+const ID_SEQUENCE_SETTING_PREFIX: &str = "id_sequence:";
+// Followed by the collection name, holds its last clustering as JSON
+const CLUSTERING_SETTING_PREFIX: &str = "clustering:";
+// Vectors k-means is trained on; the rest are only assigned
+const CLUSTER_SAMPLE_SIZE: usize = 50_000;
+// Subscribers further behind than this miss events
+const CHANGE_EVENT_CAPACITY: usize = 1024;
+// Candidates fetched per hit a grouped search could return
+const GROUP_FETCH_FACTOR: usize = 4;
+// Candidates fetched from each side of a hybrid search per hit returned
+const HYBRID_FETCH_FACTOR: usize = 4;
+// Vectors read from storage at a time while rebuilding indexes
+const REBUILD_PAGE_SIZE: usize = 10_000;
+// Rows an exact search scores between looks at its cancellation token
+const SCORE_CHUNK_ROWS: usize = 4096;
+// Fewer vectors than this are scored faster on the CPU than uploaded
+#[cfg(feature = "gpu")]
+const GPU_MIN_ROWS: usize = 10_000;
+
+// Filter bitmaps keyed by the index's ids where it shares them
+fn filters_for(index: &dyn VectorIndex) -> FilterIndex {
+    match index.shared_ids() {
+        Some(ids) => FilterIndex::with_ids(ids),
+        None => FilterIndex::default(),
+    }
+}
+
+// Adds vectors in one batch so indices can build in parallel. CPU-bound; run
+// it off the async workers.
+fn build_index(index: &dyn VectorIndex, vectors: &[Vector]) -> Result<()> {
+    let ids: Vec<&str> = vectors.iter().map(|vector| vector.id.as_str()).collect();
+    let data: Vec<&[f32]> = vectors
+        .iter()
+        .map(|vector| vector.data.as_slice())
+        .collect();
+    index.build_batch(&ids, &data)
+}
+
+// SplitMix64, for picking samples; nothing depends on it being unguessable
+pub(crate) struct SampleRng(u64);
+
+impl SampleRng {
+    pub(crate) fn new(seed: Option<u64>) -> Self {
+        Self(seed.unwrap_or_else(|| uuid::Uuid::new_v4().as_u128() as u64))
+    }
+
+    // Uniform enough below `bound`, which is tiny next to 2^64
+    pub(crate) fn below(&mut self, bound: u64) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        (z ^ (z >> 31)) % bound
+    }
+}
+
+// Splits `len` bytes off the front of a snapshot being read
+fn take<'a>(rest: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if rest.len() < len {
+        return Err(anyhow!("snapshot is truncated"));
+    }
+    let (bytes, tail) = rest.split_at(len);
+    *rest = tail;
+    Ok(bytes)
+}
+
+fn read_u64(rest: &mut &[u8]) -> Result<u64> {
+    Ok(u64::from_le_bytes(take(rest, 8)?.try_into()?))
+}
+
+// Write then rename, so a crash never leaves a torn snapshot
+fn replace_file(path: &std::path::Path, contents: &[u8]) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, contents)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+// Exact scores of each row, on the GPU when built with the `gpu` feature,
+// there's an adapter and enough rows to be worth uploading; on the CPU
+// otherwise, or if the GPU fails
+fn score_rows(
+    query: &[f32],
+    rows: &[(&str, &[f32])],
+    metric: DistanceMetric,
+    cancel: &CancellationToken,
+) -> Result<Vec<f32>> {
+    #[cfg(feature = "gpu")]
+    if rows.len() >= GPU_MIN_ROWS && rows.iter().all(|(_, data)| data.len() == query.len()) {
+        if let Some(scorer) = skypier_index::GpuScorer::shared() {
+            let arena: Vec<f32> = rows.iter().flat_map(|(_, data)| *data).copied().collect();
+            match scorer.scores(query, &arena, metric.index_metric()) {
+                Ok(scores) => return Ok(scores),
+                Err(e) => warn!("GPU scoring failed, scoring on the CPU: {}", e),
+            }
+        }
+    }
+    // In chunks, so a cancelled search stops between them
+    let scores: Vec<Vec<f32>> = rows
+        .par_chunks(SCORE_CHUNK_ROWS)
+        .map(|chunk| {
+            if cancel.is_cancelled() {
+                return Err(skypier_index::Cancelled.into());
+            }
+            chunk
+                .iter()
+                .map(|(_, data)| metric.score(query, data))
+                .collect()
+        })
+        .collect::<Result<_>>()?;
+    Ok(scores.concat())
+}
+
+// Runs CPU-bound work on tokio's blocking pool, starting it right away like
+// a spawned task. Without the `runtime` feature, e.g. in the browser, it
+// runs in place instead.
+#[cfg(feature = "runtime")]
+fn spawn_blocking<T: Send + 'static>(
+    work: impl FnOnce() -> T + Send + 'static,
+) -> impl std::future::Future<Output = Result<T>> {
+    let handle = tokio::task::spawn_blocking(work);
+    async move { Ok(handle.await?) }
+}
+
+#[cfg(not(feature = "runtime"))]
+fn spawn_blocking<T>(work: impl FnOnce() -> T) -> impl std::future::Future<Output = Result<T>> {
+    std::future::ready(Ok(work()))
+}
+
+async fn read_file(path: &std::path::Path) -> std::io::Result<Vec<u8>> {
+    #[cfg(feature = "runtime")]
+    return tokio::fs::read(path).await;
+    #[cfg(not(feature = "runtime"))]
+    std::fs::read(path)
+}
+
+// WAL seq, then each index's name and contents, both length prefixed
+fn encode_indexes(seq: u64, indexes: &[(String, Vec<u8>)]) -> Vec<u8> {
+    let mut contents = seq.to_le_bytes().to_vec();
+    for (name, data) in indexes {
+        contents.extend_from_slice(&(name.len() as u64).to_le_bytes());
+        contents.extend_from_slice(name.as_bytes());
+        contents.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        contents.extend_from_slice(data);
+    }
+    contents
+}
+
+// The indexes written by `encode_indexes`, past its seq
+fn decode_indexes(mut rest: &[u8]) -> Result<Vec<(String, &[u8])>> {
+    let mut indexes = Vec::new();
+    while !rest.is_empty() {
+        let len = usize::try_from(read_u64(&mut rest)?)?;
+        let name = String::from_utf8(take(&mut rest, len)?.to_vec())?;
+        let len = usize::try_from(read_u64(&mut rest)?)?;
+        indexes.push((name, take(&mut rest, len)?));
+    }
+    Ok(indexes)
+}
+
+async fn save_indexes(
+    indexes: &RwLock<HashMap<String, Arc<dyn VectorIndex>>>,
+) -> Result<Vec<(String, Vec<u8>)>> {
+    let mut saved = Vec::new();
+    for (name, index) in indexes.read().await.iter() {
+        saved.push((name.clone(), index.save()?));
+    }
+    Ok(saved)
+}
+
+// The contents of a snapshot file that starts with the WAL seq it was taken
+// at, if it was taken at `seq`
+async fn read_snapshot_at(path: &std::path::Path, seq: u64) -> Result<Option<Vec<u8>>> {
+    let mut contents = match read_file(path).await {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    if read_u64(&mut contents.as_slice())? != seq {
+        return Ok(None);
+    }
+    Ok(Some(contents.split_off(8)))
+}
+
+fn change_event(entry: WalEntry) -> ChangeEvent {
+    let kind = match entry.op {
+        WalOp::Upsert if entry.replaced => ChangeKind::Update,
+        WalOp::Upsert => ChangeKind::Insert,
+        WalOp::Delete => ChangeKind::Delete,
+    };
+    ChangeEvent {
+        seq: entry.seq,
+        kind,
+        id: entry.id,
+        collection: entry.collection,
+        timestamp: entry.timestamp,
+        hlc: entry.hlc,
+    }
+}
+
+fn elapsed_us(started: Instant) -> u64 {
+    started.elapsed().as_micros() as u64
+}
+
+// Marks `vector` as written by `replica` at `hlc`, following the writes
+// `clock` has seen
+fn stamp(vector: &mut Vector, replica: &str, hlc: u64, mut clock: BTreeMap<String, u64>) {
+    *clock.entry(replica.to_string()).or_default() += 1;
+    vector.hlc = hlc;
+    vector.origin = Some(replica.to_string());
+    vector.clock = clock;
+}
+
+// Where a near duplicate found by `find_duplicate` is
+enum Duplicate {
+    Batch(usize),
+    Stored(String),
+}
+
+type IndexFactory = Arc<dyn Fn() -> Result<Arc<dyn VectorIndex>> + Send + Sync>;
+type MetricIndexFactory = Arc<dyn Fn(DistanceMetric) -> Result<Arc<dyn VectorIndex>> + Send + Sync>;
 
 pub struct VectorDatabase {
     storage: Arc<dyn Storage>,
-    index: Arc<RwLock<dyn VectorIndex>>,
+    index: Arc<dyn VectorIndex>,
+    // One per vector name, made by `named_index` when a name is first written
+    named_indexes: RwLock<HashMap<String, Arc<dyn VectorIndex>>>,
+    named_index: IndexFactory,
+    sparse_index: SparseIndex,
+    // Collections whose distance metric was set get an index of their own,
+    // made by `collection_index`. Their vectors are in the main index too.
+    collection_metrics: RwLock<HashMap<String, DistanceMetric>>,
+    collection_indexes: RwLock<HashMap<String, Arc<dyn VectorIndex>>>,
+    collection_index: MetricIndexFactory,
+    collection_dedup: RwLock<HashMap<String, Dedup>>,
+    // How ids are made for vectors inserted without one, per collection and
+    // for everything else
+    id_schemes: RwLock<HashMap<String, IdScheme>>,
+    default_id_scheme: IdScheme,
+    ulids: UlidGenerator,
+    // Held while auto-increment counters are read and bumped
+    sequence_lock: Mutex<()>,
+    // Earlier versions kept per re-inserted id, per collection and for
+    // everything else. 0 keeps none.
+    collection_versions: RwLock<HashMap<String, usize>>,
+    default_versions: usize,
+    // Embedding model the vectors and queries of a collection must come from
+    collection_models: RwLock<HashMap<String, String>>,
+    // Names that reads resolve to another collection, e.g. "docs" pointing
+    // at "docs_v2" after a re-embedding
+    collection_aliases: RwLock<HashMap<String, String>>,
+    // Metadata the vectors of a collection must have, and which keys of it
+    // are indexed
+    metadata_schemas: RwLock<HashMap<String, MetadataSchema>>,
+    collection_quotas: RwLock<HashMap<String, Quota>>,
+    // Limits the database as a whole, e.g. to its namespace's quota, with
+    // the name errors call it by
+    quota: RwLock<Option<(String, Quota)>>,
+    // Arc'd so a search can take a read guard onto the blocking pool
+    filters: Arc<RwLock<FilterIndex>>,
+    // Held by writes across storage and both indexes, so a snapshot sees
+    // them all at the same WAL position. Searches don't take it.
+    write_lock: Mutex<()>,
     distance_metric: DistanceMetric,
     dimensions: Option<usize>,
+    tie_break: TieBreak,
+    // Storage precision of `data`, per collection and for everything else
+    dtypes: HashMap<String, Dtype>,
+    default_dtype: Dtype,
+    plugins: Vec<Arc<dyn VectorPlugin>>,
+    limits: ValidationLimits,
+    data_dir: PathBuf,
+    snapshot_lock: Mutex<()>,
+    changes: broadcast::Sender<ChangeEvent>,
+    // WAL entries kept past each index snapshot so the changefeed can be
+    // resumed from them
+    changefeed_retention: u64,
+    // Set once `load_index` has brought the index up to date
+    index_loaded: AtomicBool,
+    // Set on followers, which only take changes replicated from a primary
+    read_only: AtomicBool,
+    // Settles replicated writes to ids that were written here too
+    resolver: Arc<dyn ConflictResolver>,
+    // Stamps every write, ahead of any replicated one applied here
+    clock: HybridClock,
+    replica_id: tokio::sync::OnceCell<String>,
+    // Held while the conflict table is read and rewritten
+    conflicts_lock: Mutex<()>,
 }
 
 impl VectorDatabase {
+    #[cfg(feature = "redb")]
     pub async fn new(data_dir: &str) -> Result<Self> {
-        let storage = Arc::new(skypier_storage::RedbStorage::new(data_dir).await?);
-        let index = Arc::new(RwLock::new(skypier_index::HnswIndex::new(768)?));
+        let storage = skypier_storage::RedbStorage::new(data_dir).await?;
+        Self::from_storage(Arc::new(storage), data_dir)
+    }
+
+    // Keeps everything in memory and writes nothing to disk, for tests and
+    // throwaway embedded instances
+    pub fn in_memory() -> Result<Self> {
+        Self::from_storage(Arc::new(skypier_storage::InMemoryStorage::new()), "")
+    }
+
+    // Uses storage that was opened and configured by the caller. `data_dir`
+    // is where the index snapshot goes.
+    pub fn from_storage(storage: Arc<dyn Storage>, data_dir: &str) -> Result<Self> {
+        let index: Arc<dyn VectorIndex> = Arc::new(skypier_index::HnswIndex::new(768)?);
+        let filters = filters_for(index.as_ref());
 
         Ok(Self {
             storage,
             index,
+            named_indexes: RwLock::new(HashMap::new()),
+            named_index: Arc::new(|| Ok(Arc::new(skypier_index::HnswIndex::new(768)?) as _)),
+            sparse_index: SparseIndex::new(),
+            collection_metrics: RwLock::new(HashMap::new()),
+            collection_indexes: RwLock::new(HashMap::new()),
+            collection_index: Arc::new(|metric| {
+                let index = skypier_index::HnswIndex::new(768)?.with_metric(metric.index_metric());
+                Ok(Arc::new(index) as _)
+            }),
+            collection_dedup: RwLock::new(HashMap::new()),
+            id_schemes: RwLock::new(HashMap::new()),
+            default_id_scheme: IdScheme::default(),
+            ulids: UlidGenerator::default(),
+            sequence_lock: Mutex::new(()),
+            collection_versions: RwLock::new(HashMap::new()),
+            default_versions: 0,
+            collection_models: RwLock::new(HashMap::new()),
+            collection_aliases: RwLock::new(HashMap::new()),
+            metadata_schemas: RwLock::new(HashMap::new()),
+            collection_quotas: RwLock::new(HashMap::new()),
+            quota: RwLock::new(None),
+            filters: Arc::new(RwLock::new(filters)),
+            write_lock: Mutex::new(()),
             distance_metric: DistanceMetric::Cosine,
             dimensions: None,
+            tie_break: TieBreak::default(),
+            dtypes: HashMap::new(),
+            default_dtype: Dtype::F32,
+            plugins: Vec::new(),
+            limits: ValidationLimits::default(),
+            data_dir: PathBuf::from(data_dir),
+            snapshot_lock: Mutex::new(()),
+            changes: broadcast::channel(CHANGE_EVENT_CAPACITY).0,
+            changefeed_retention: 0,
+            index_loaded: AtomicBool::new(false),
+            read_only: AtomicBool::new(false),
+            resolver: Arc::new(LastWriterWins),
+            clock: HybridClock::new(),
+            replica_id: tokio::sync::OnceCell::new(),
+            conflicts_lock: Mutex::new(()),
         })
     }
 
-    pub async fn insert_vectors(&self, vectors: Vec<Vector>) -> Result<Vec<String>> {
-        let mut ids = Vec::new();
-        let mut index = self.index.write().await;
+    // Plugins run in registration order
+    pub fn register_plugin(&mut self, plugin: Arc<dyn VectorPlugin>) {
+        self.plugins.push(plugin);
+    }
+
+    fn run_search_plugins(&self, query: &[f32], results: &mut Vec<SearchResult>) -> Result<()> {
+        for plugin in &self.plugins {
+            plugin
+                .on_search(query, results)
+                .map_err(|e| anyhow!("Plugin '{}' rejected search: {}", plugin.name(), e))?;
+        }
+        Ok(())
+    }
 
+    // Replaces the default HNSW index, e.g. with one tuned from config. Call
+    // before inserting anything; existing entries are not carried over.
+    pub fn with_index<I: VectorIndex + 'static>(mut self, index: I) -> Self {
+        self.filters = Arc::new(RwLock::new(filters_for(&index)));
+        self.index = Arc::new(index);
+        self
+    }
+
+    // How indexes for named vectors are made, e.g. tuned like the main one
+    pub fn with_named_index<I, F>(mut self, factory: F) -> Self
+    where
+        I: VectorIndex + 'static,
+        F: Fn() -> Result<I> + Send + Sync + 'static,
+    {
+        self.named_index = Arc::new(move || Ok(Arc::new(factory()?) as _));
+        self
+    }
+
+    // How indexes for collections with their own distance metric are made
+    pub fn with_collection_index<I, F>(mut self, factory: F) -> Self
+    where
+        I: VectorIndex + 'static,
+        F: Fn(DistanceMetric) -> Result<I> + Send + Sync + 'static,
+    {
+        self.collection_index = Arc::new(move |metric| Ok(Arc::new(factory(metric)?) as _));
+        self
+    }
+
+    // The metric of the main index, and of collections without their own.
+    // The index passed to `with_index` should be built for it.
+    pub fn with_distance_metric(mut self, metric: DistanceMetric) -> Self {
+        self.distance_metric = metric;
+        self
+    }
+
+    // The id scheme of collections without one of their own
+    pub fn with_id_scheme(mut self, scheme: IdScheme) -> Self {
+        self.default_id_scheme = scheme;
+        self
+    }
+
+    // How many earlier versions collections without a limit of their own
+    // keep when an id is re-inserted
+    pub fn with_versions(mut self, versions: usize) -> Self {
+        self.default_versions = versions;
+        self
+    }
+
+    // Puts a vector's named and sparse embeddings, and its data if its
+    // collection has an index, in their indexes and takes it out of the
+    // ones it no longer belongs in
+    async fn index_extra(&self, vector: &Vector) -> Result<()> {
+        for (collection, index) in self.collection_indexes.read().await.iter() {
+            if vector.collection.as_ref() == Some(collection) {
+                index.add_vector(&vector.id, &vector.data)?;
+            } else {
+                index.remove_vector(&vector.id)?;
+            }
+        }
+
+        match &vector.sparse {
+            Some(sparse) => {
+                self.sparse_index
+                    .add_vector(&vector.id, &sparse.indices, &sparse.values)?
+            }
+            None => {
+                self.sparse_index.remove_vector(&vector.id)?;
+            }
+        }
+
+        let mut named = self.named_indexes.write().await;
+        for (name, index) in named.iter() {
+            if !vector.vectors.contains_key(name) {
+                index.remove_vector(&vector.id)?;
+            }
+        }
+        for (name, data) in &vector.vectors {
+            let index = match named.get(name) {
+                Some(index) => index,
+                None => named.entry(name.clone()).or_insert((self.named_index)()?),
+            };
+            index.add_vector(&vector.id, data)?;
+        }
+        Ok(())
+    }
+
+    async fn unindex_extra(&self, id: &str) -> Result<()> {
+        for index in self.collection_indexes.read().await.values() {
+            index.remove_vector(id)?;
+        }
+        self.sparse_index.remove_vector(id)?;
+        for index in self.named_indexes.read().await.values() {
+            index.remove_vector(id)?;
+        }
+        Ok(())
+    }
+
+    pub fn with_tie_break(mut self, tie_break: TieBreak) -> Self {
+        self.tie_break = tie_break;
+        self
+    }
+
+    // Vectors written from now on are rounded to their collection's dtype
+    // and stored at it. Already stored vectors keep theirs.
+    pub fn with_dtypes(mut self, default: Dtype, collections: HashMap<String, Dtype>) -> Self {
+        self.default_dtype = default;
+        self.dtypes = collections;
+        self
+    }
+
+    // A narrower dtype set on the vector itself, as clones cast with one
+    // have, wins over the collection's
+    fn apply_dtype(&self, vector: &mut Vector) {
+        let dtype = match vector.dtype {
+            Dtype::F32 => vector
+                .collection
+                .as_ref()
+                .and_then(|collection| self.dtypes.get(collection))
+                .copied()
+                .unwrap_or(self.default_dtype),
+            dtype => dtype,
+        };
+        // Rounded here so the index scores what storage will read back
+        dtype.round(&mut vector.data);
+        vector.dtype = dtype;
+    }
+
+    pub fn with_validation_limits(mut self, limits: ValidationLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn with_changefeed_retention(mut self, entries: u64) -> Self {
+        self.changefeed_retention = entries;
+        self
+    }
+
+    pub fn with_conflict_resolver(mut self, resolver: Arc<dyn ConflictResolver>) -> Self {
+        self.resolver = resolver;
+        self
+    }
+
+    pub fn validation_limits(&self) -> &ValidationLimits {
+        &self.limits
+    }
+
+    // Sorts scored results deterministically and keeps the top k. Each result
+    // is paired with the created_at of its vector for CreatedAt tie-breaking.
+    fn rank_results(&self, mut results: Vec<(SearchResult, u64)>, k: usize) -> Vec<SearchResult> {
+        results.sort_by(|(a, a_created), (b, b_created)| {
+            let by_score = b
+                .score
+                .partial_cmp(&a.score)
+                .unwrap_or(std::cmp::Ordering::Equal);
+            let by_tie_break = match self.tie_break {
+                TieBreak::Id => std::cmp::Ordering::Equal,
+                TieBreak::CreatedAt => a_created.cmp(b_created),
+            };
+            by_score.then(by_tie_break).then_with(|| a.id.cmp(&b.id))
+        });
+
+        results
+            .into_iter()
+            .take(k)
+            .map(|(result, _)| result)
+            .collect()
+    }
+
+    // Events for every insert, update and delete from now on. A receiver
+    // that falls too far behind gets `RecvError::Lagged` and skips ahead.
+    pub fn subscribe(&self) -> broadcast::Receiver<ChangeEvent> {
+        self.changes.subscribe()
+    }
+
+    fn publish(&self, seq: u64, kind: ChangeKind, vector: &Vector, hlc: u64) {
+        // Fails only when nobody is subscribed
+        let _ = self.changes.send(ChangeEvent {
+            seq,
+            kind,
+            id: vector.id.clone(),
+            collection: vector.collection.clone(),
+            timestamp: time::SystemTime::now()
+                .duration_since(time::UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or_default(),
+            hlc,
+        });
+    }
+
+    pub fn validate_vectors(&self, vectors: &[Vector]) -> Result<(), ValidationError> {
         for vector in vectors {
-            // Validate dimensions
-            if let Some(dims) = self.dimensions {
-                if vector.data.len() != dims {
-                    return Err(anyhow!(
-                        "Vector dimension mismatch: expected {}, got {}",
-                        dims,
-                        vector.data.len()
-                    ));
+            self.validate_vector(vector)?;
+        }
+        Ok(())
+    }
+
+    fn validate_vector(&self, vector: &Vector) -> Result<(), ValidationError> {
+        validation::validate_vector(vector, &self.limits)?;
+
+        if let Some(dims) = self.dimensions {
+            if vector.data.len() != dims {
+                return Err(ValidationError::DimensionMismatch {
+                    id: vector.id.clone(),
+                    expected: dims,
+                    actual: vector.data.len(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // Runs the checks an insert would on every vector of the batch and
+    // reports all the failures instead of stopping at the first. Plugins
+    // aren't run, since their hooks may have side effects.
+    pub async fn check_vectors(&self, vectors: &[Vector]) -> Result<BatchCheck> {
+        let mut check = BatchCheck {
+            checked: vectors.len(),
+            ..Default::default()
+        };
+        let mut collections = HashSet::new();
+        let mut valid = Vec::new();
+        for (index, vector) in vectors.iter().enumerate() {
+            // Values can overflow when stored at half precision
+            let mut stored = vector.clone();
+            self.apply_dtype(&mut stored);
+            if stored.id.is_empty() {
+                // Made up on insert; a placeholder keeps it apart from the
+                // rest of the batch
+                stored.id = format!("#{}", index);
+            }
+            let mut error = self.validate_vector(&stored).err();
+            if error.is_none() {
+                error = self.check_not_alias(&stored).await.err();
+            }
+            if error.is_none() {
+                error = self.check_model(&stored).await.err();
+            }
+            if error.is_none() {
+                error = self.check_schema(&stored).await.err();
+            }
+            if error.is_none() {
+                if let Some((dedup, duplicate, score)) =
+                    self.find_duplicate(&stored, &valid).await?
+                {
+                    if dedup.action == DedupAction::Reject {
+                        let existing = match duplicate {
+                            Duplicate::Batch(position) => valid[position].id.clone(),
+                            Duplicate::Stored(id) => id,
+                        };
+                        error = Some(ValidationError::Duplicate {
+                            id: vector.id.clone(),
+                            existing,
+                            score,
+                        });
+                    }
                 }
             }
+            if let Some(e) = error {
+                check.errors.push(RowError {
+                    index,
+                    id: vector.id.clone(),
+                    error: e.to_string(),
+                });
+                continue;
+            }
+            if let Some(collection) = &vector.collection {
+                collections.insert(collection.clone());
+            }
+            valid.push(stored);
+        }
+        for collection in collections {
+            if self.storage.collection_stats(&collection).await?.is_none() {
+                check.new_collections.push(collection);
+            }
+        }
+        check.new_collections.sort();
+        Ok(check)
+    }
 
-            // Store vector in persistent storage
-            self.storage.store_vector(&vector).await?;
+    // Client writes fail with ReadOnlyError while this is set. Replicated
+    // changes still go through `apply_changes`.
+    pub fn set_read_only(&self, read_only: bool) {
+        self.read_only.store(read_only, Ordering::Release);
+    }
 
-            // Add to index
-            index.add_vector(&vector.id, &vector.data)?;
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.load(Ordering::Acquire)
+    }
 
-            ids.push(vector.id);
+    fn check_writable(&self) -> Result<()> {
+        if self.is_read_only() {
+            return Err(ReadOnlyError.into());
         }
+        Ok(())
+    }
 
-        Ok(ids)
+    pub async fn insert_vectors(&self, vectors: Vec<Vector>) -> Result<Vec<String>> {
+        self.write_vectors(vectors, false).await
     }
 
-    pub async fn get_vector(&self, id: &str) -> Result<Option<Vector>> {
-        self.storage.get_vector(id).await
+    // For large batches into a live database: like `insert_vectors`, but the
+    // index is built in parallel off the async workers, as in `bulk_load`.
+    // Writes are logged, so the database doesn't need to be empty.
+    pub async fn import_vectors(&self, vectors: Vec<Vector>) -> Result<Vec<String>> {
+        self.write_vectors(vectors, true).await
     }
 
-    pub async fn search(
-        &self,
-        query: &[f32],
-        k: usize,
-        threshold: f32,
-    ) -> Result<Vec<SearchResult>> {
-        let index = self.index.read().await;
-        let candidates = index.search(query, k * 2)?; // Get more candidates for reranking
+    async fn write_vectors(&self, mut vectors: Vec<Vector>, parallel: bool) -> Result<Vec<String>> {
+        self.check_writable()?;
+        self.assign_ids(&mut vectors).await?;
+        // Run plugins over the whole batch first so a rejection writes nothing
+        for vector in &mut vectors {
+            for plugin in &self.plugins {
+                plugin.on_insert(vector).map_err(|e| {
+                    anyhow!(
+                        "Plugin '{}' rejected vector {}: {}",
+                        plugin.name(),
+                        vector.id,
+                        e
+                    )
+                })?;
+            }
+            self.apply_dtype(vector);
+        }
+        self.validate_vectors(&vectors)?;
+        for vector in &vectors {
+            self.check_not_alias(vector).await?;
+            self.check_model(vector).await?;
+            self.check_schema(vector).await?;
+        }
 
-        let mut results = Vec::new();
+        let _write = self.write_lock.lock().await;
+        let mut vectors = self.dedup(vectors).await?;
+        // Merging can grow metadata past the limit
+        self.validate_vectors(&vectors)?;
 
-        for candidate in candidates {
-            if candidate.score >= threshold {
-                if let Some(vector) = self.storage.get_vector(&candidate.id).await? {
-                    results.push(SearchResult {
-                        id: candidate.id,
-                        score: candidate.score,
-                        metadata: vector.metadata,
-                    });
+        // Storage takes the whole batch in one transaction. If the index
+        // then fails part-way, both are put back: the index directly, and
+        // storage through a compensating batch that the WAL records too.
+        let mut previous = Vec::with_capacity(vectors.len());
+        for vector in &vectors {
+            previous.push(self.storage.get_vector(&vector.id).await?);
+        }
+        // Each write of an id counts up from the one it replaces and is
+        // stamped after it
+        let replica = self.replica_id().await?;
+        let mut latest: HashMap<String, (u64, BTreeMap<String, u64>)> = HashMap::new();
+        for (vector, old) in vectors.iter_mut().zip(&previous) {
+            let (version, clock) = match latest.remove(&vector.id) {
+                Some(last) => last,
+                None => match old {
+                    Some(old) => {
+                        self.clock.advance(old.hlc);
+                        (old.version, old.clock.clone())
+                    }
+                    None => Default::default(),
+                },
+            };
+            stamp(vector, &replica, self.clock.now()?, clock);
+            vector.version = version + 1;
+            latest.insert(vector.id.clone(), (vector.version, vector.clock.clone()));
+        }
+        // Checked once stamped, so the records are sized as they'll be stored
+        self.check_quotas(&vectors, &previous).await?;
+        self.storage.write_batch(&vectors, &[], 0).await?;
+
+        if parallel {
+            let index = Arc::clone(&self.index);
+            let (built, result) = spawn_blocking(move || {
+                let result = build_index(index.as_ref(), &vectors);
+                (vectors, result)
+            })
+            .await?;
+            vectors = built;
+            // There's no telling how far the build got, so every vector is
+            // put back
+            if let Err(e) = result {
+                self.roll_back_insert(&vectors, &previous, vectors.len())
+                    .await?;
+                return Err(anyhow!("Failed to index imported vectors: {}", e));
+            }
+        } else {
+            for (indexed, vector) in vectors.iter().enumerate() {
+                if let Err(e) = self.index.add_vector(&vector.id, &vector.data) {
+                    self.roll_back_insert(&vectors, &previous, indexed).await?;
+                    return Err(anyhow!("Failed to index vector {}: {}", vector.id, e));
                 }
             }
+        }
 
-            if results.len() >= k {
-                break;
+        for vector in &vectors {
+            if let Err(e) = self.index_extra(vector).await {
+                self.roll_back_insert(&vectors, &previous, vectors.len())
+                    .await?;
+                return Err(anyhow!("Failed to index vector {}: {}", vector.id, e));
             }
         }
 
-        Ok(results)
+        // The batch is in by now, so losing its history isn't worth failing
+        // it over
+        if let Err(e) = self.keep_versions(&vectors, &previous).await {
+            warn!("Failed to keep earlier versions: {}", e);
+        }
+
+        // The batch took the WAL entries just below the head, in order
+        let first_seq = self.storage.wal_head().await? + 1 - vectors.len() as u64;
+        let mut filters = self.filters.write().await;
+        for ((vector, old), seq) in vectors.iter().zip(&previous).zip(first_seq..) {
+            filters.insert(vector);
+            let kind = match old {
+                Some(_) => ChangeKind::Update,
+                None => ChangeKind::Insert,
+            };
+            self.publish(seq, kind, vector, vector.hlc);
+        }
+        Ok(vectors.into_iter().map(|vector| vector.id).collect())
     }
 
-    pub async fn search_in_collection(
+    // Undoes an insert whose storage write went through but whose index
+    // update failed after the first `indexed` vectors. `previous` holds what
+    // was stored under each id before the batch.
+    async fn roll_back_insert(
         &self,
-        collection: &str,
-        query: &[f32],
-        k: usize,
-        threshold: f32,
-    ) -> Result<Vec<SearchResult>> {
-        let index = self.index.read().await;
-        let candidates = index.search(query, k * 5)?; // Get more candidates for filtering
+        vectors: &[Vector],
+        previous: &[Option<Vector>],
+        indexed: usize,
+    ) -> Result<()> {
+        for (vector, old) in vectors[..indexed].iter().zip(previous).rev() {
+            match old {
+                Some(old) => {
+                    self.index.add_vector(&old.id, &old.data)?;
+                    self.index_extra(old).await?;
+                }
+                None => {
+                    self.index.remove_vector(&vector.id)?;
+                    self.unindex_extra(&vector.id).await?;
+                }
+            }
+        }
 
-        let mut results = Vec::new();
+        // An id repeated in the batch restores to what it was before the
+        // first occurrence
+        let mut restores = Vec::new();
+        let mut deletes = Vec::new();
+        let mut seen = HashSet::new();
+        for (vector, old) in vectors.iter().zip(previous) {
+            if !seen.insert(vector.id.as_str()) {
+                continue;
+            }
+            match old {
+                Some(old) => restores.push(old.clone()),
+                None => deletes.push(vector.id.clone()),
+            }
+        }
+        self.storage
+            .write_batch(&restores, &deletes, self.clock.now()?)
+            .await
+    }
 
-        for candidate in candidates {
-            if candidate.score >= threshold {
-                if let Some(vector) = self.storage.get_vector(&candidate.id).await? {
-                    if vector.collection.as_ref().map(|s| s.as_str()) == Some(collection) {
-                        results.push(SearchResult {
-                            id: candidate.id,
-                            score: candidate.score,
-                            metadata: vector.metadata,
-                        });
-                    }
+    // Loads an empty database offline: storage is written in a single
+    // transaction, skipping the WAL, while the index is built alongside on
+    // another thread. Finishes with an index snapshot so a server can boot
+    // from the data dir without rebuilding.
+    pub async fn bulk_load(&self, mut vectors: Vec<Vector>) -> Result<usize> {
+        if self.storage.count_vectors().await? > 0 {
+            return Err(anyhow!("Bulk loads need an empty database"));
+        }
+        self.assign_ids(&mut vectors).await?;
+        let replica = self.replica_id().await?;
+        for vector in &mut vectors {
+            vector.version = 1;
+            stamp(vector, &replica, self.clock.now()?, BTreeMap::new());
+            self.apply_dtype(vector);
+        }
+        self.validate_vectors(&vectors)?;
+        for vector in &vectors {
+            self.check_not_alias(vector).await?;
+            self.check_model(vector).await?;
+            self.check_schema(vector).await?;
+        }
+
+        let vectors = Arc::new(vectors);
+        {
+            let _write = self.write_lock.lock().await;
+            let build = {
+                let index = Arc::clone(&self.index);
+                let vectors = Arc::clone(&vectors);
+                spawn_blocking(move || build_index(index.as_ref(), &vectors))
+            };
+
+            {
+                let mut filters = self.filters.write().await;
+                for vector in vectors.iter() {
+                    filters.insert(vector);
                 }
             }
+            self.storage.bulk_load(&vectors).await?;
+            for vector in vectors.iter() {
+                self.index_extra(vector).await?;
+            }
+            build.await??;
+        }
+        self.snapshot_index().await?;
 
-            if results.len() >= k {
-                break;
+        Ok(vectors.len())
+    }
+
+    pub fn distance_metric(&self) -> &DistanceMetric {
+        &self.distance_metric
+    }
+
+    // The metric searches scoped to the collection use
+    pub async fn collection_metric(&self, collection: &str) -> DistanceMetric {
+        self.collection_metrics
+            .read()
+            .await
+            .get(collection)
+            .copied()
+            .unwrap_or(self.distance_metric)
+    }
+
+    // Gives the collection an index of its own scoring with `metric`, built
+    // from its stored vectors. Searches filtered to the collection use it;
+    // unscoped searches keep using the main index.
+    pub async fn set_collection_metric(
+        &self,
+        collection: &str,
+        metric: DistanceMetric,
+    ) -> Result<()> {
+        let _write = self.write_lock.lock().await;
+        let mut metrics = self.collection_metrics.read().await.clone();
+        metrics.insert(collection.to_string(), metric);
+        self.storage
+            .put_setting(
+                COLLECTION_METRICS_SETTING,
+                &serde_json::to_string(&metrics)?,
+            )
+            .await?;
+
+        let index = (self.collection_index)(metric)?;
+        let mut after = None;
+        loop {
+            let page = self
+                .scroll(Some(collection), after.as_deref(), REBUILD_PAGE_SIZE)
+                .await?;
+            let vectors = page.vectors;
+            let built = Arc::clone(&index);
+            spawn_blocking(move || build_index(built.as_ref(), &vectors)).await??;
+            match page.next {
+                Some(next) => after = Some(next),
+                None => break,
             }
         }
 
-        Ok(results)
+        *self.collection_metrics.write().await = metrics;
+        self.collection_indexes
+            .write()
+            .await
+            .insert(collection.to_string(), index);
+        Ok(())
     }
 
-    pub async fn delete_vector(&self, id: &str) -> Result<bool> {
-        let removed = self.storage.delete_vector(id).await?;
-        if removed {
-            let mut index = self.index.write().await;
-            index.remove_vector(id)?;
+    // Empty indexes for the collection metrics in storage
+    async fn open_collection_indexes(&self) -> Result<()> {
+        let metrics: HashMap<String, DistanceMetric> =
+            match self.storage.get_setting(COLLECTION_METRICS_SETTING).await? {
+                Some(json) => serde_json::from_str(&json)?,
+                None => HashMap::new(),
+            };
+        let mut indexes = HashMap::new();
+        for (collection, metric) in &metrics {
+            indexes.insert(collection.clone(), (self.collection_index)(*metric)?);
         }
-        Ok(removed)
+        *self.collection_metrics.write().await = metrics;
+        *self.collection_indexes.write().await = indexes;
+        Ok(())
     }
 
-    pub async fn get_stats(&self) -> Result<DatabaseStats> {
-        let total_vectors = self.storage.count_vectors().await?;
-        let storage_size = self.storage.size_bytes().await?;
+    pub async fn collection_dedup(&self, collection: &str) -> Option<Dedup> {
+        self.collection_dedup.read().await.get(collection).copied()
+    }
 
-        // Calculate dimensions from stored vectors if not set
-        let dimensions = if let Some(dims) = self.dimensions {
-            dims
-        } else if total_vectors > 0 {
-            // Get the first vector to determine dimensions
-            if let Some(first_vector) = self.storage.get_first_vector().await? {
-                first_vector.dimensions()
-            } else {
-                0
-            }
-        } else {
-            0
+    // Checks inserts into the collection for near duplicates from now on,
+    // or stops with None. Vectors already stored aren't checked.
+    pub async fn set_collection_dedup(&self, collection: &str, dedup: Option<Dedup>) -> Result<()> {
+        let _write = self.write_lock.lock().await;
+        self.write_collection_setting(
+            COLLECTION_DEDUP_SETTING,
+            &self.collection_dedup,
+            collection,
+            dedup,
+        )
+        .await
+    }
+
+    // Sets or, for None, clears the collection's entry in a per-collection
+    // setting, saving it before it takes effect
+    async fn write_collection_setting<T: serde::Serialize + Clone>(
+        &self,
+        key: &str,
+        settings: &RwLock<HashMap<String, T>>,
+        collection: &str,
+        value: Option<T>,
+    ) -> Result<()> {
+        let mut settings = settings.write().await;
+        let mut updated = settings.clone();
+        match value {
+            Some(value) => updated.insert(collection.to_string(), value),
+            None => updated.remove(collection),
         };
+        self.storage
+            .put_setting(key, &serde_json::to_string(&updated)?)
+            .await?;
+        *settings = updated;
+        Ok(())
+    }
 
-        Ok(DatabaseStats {
-            total_vectors,
-            dimensions,
-            storage_size_bytes: storage_size,
+    // A per-collection setting kept as a JSON map
+    async fn read_collection_setting<T: serde::de::DeserializeOwned>(
+        &self,
+        key: &str,
+    ) -> Result<HashMap<String, T>> {
+        Ok(match self.storage.get_setting(key).await? {
+            Some(json) => serde_json::from_str(&json)?,
+            None => HashMap::new(),
         })
     }
 
-    pub async fn compact(&self) -> Result<()> {
-        self.storage.compact().await?;
+    // The per-collection settings kept in storage
+    async fn load_collection_settings(&self) -> Result<()> {
+        *self.collection_dedup.write().await = self
+            .read_collection_setting(COLLECTION_DEDUP_SETTING)
+            .await?;
+        *self.id_schemes.write().await = self
+            .read_collection_setting(COLLECTION_ID_SCHEMES_SETTING)
+            .await?;
+        *self.collection_versions.write().await = self
+            .read_collection_setting(COLLECTION_VERSIONS_SETTING)
+            .await?;
+        *self.collection_models.write().await = self
+            .read_collection_setting(COLLECTION_MODELS_SETTING)
+            .await?;
+        *self.collection_aliases.write().await = self
+            .read_collection_setting(COLLECTION_ALIASES_SETTING)
+            .await?;
+        *self.metadata_schemas.write().await = self
+            .read_collection_setting(COLLECTION_SCHEMAS_SETTING)
+            .await?;
+        *self.collection_quotas.write().await = self
+            .read_collection_setting(COLLECTION_QUOTAS_SETTING)
+            .await?;
         Ok(())
     }
 
-    pub async fn backup(&self, backup_path: &str) -> Result<()> {
-        self.storage.backup(backup_path).await?;
-        Ok(())
+    // The id scheme of the collection, or the default one
+    pub async fn id_scheme(&self, collection: Option<&str>) -> IdScheme {
+        let schemes = self.id_schemes.read().await;
+        collection
+            .and_then(|collection| schemes.get(collection))
+            .copied()
+            .unwrap_or(self.default_id_scheme)
+    }
+
+    // Makes ids for the collection's vectors with `scheme` from now on, or
+    // with the default one again for None. Existing ids stay as they are.
+    pub async fn set_collection_id_scheme(
+        &self,
+        collection: &str,
+        scheme: Option<IdScheme>,
+    ) -> Result<()> {
+        self.write_collection_setting(
+            COLLECTION_ID_SCHEMES_SETTING,
+            &self.id_schemes,
+            collection,
+            scheme,
+        )
+        .await
+    }
+
+    // How many earlier versions the collection keeps
+    pub async fn versions_kept(&self, collection: Option<&str>) -> usize {
+        let versions = self.collection_versions.read().await;
+        collection
+            .and_then(|collection| versions.get(collection))
+            .copied()
+            .unwrap_or(self.default_versions)
+    }
+
+    // Keeps up to `versions` earlier versions of the collection's vectors
+    // from now on, or the default number again for None. Lowering it trims
+    // an id's history on its next write.
+    pub async fn set_collection_versions(
+        &self,
+        collection: &str,
+        versions: Option<usize>,
+    ) -> Result<()> {
+        self.write_collection_setting(
+            COLLECTION_VERSIONS_SETTING,
+            &self.collection_versions,
+            collection,
+            versions,
+        )
+        .await
+    }
+
+    pub async fn expected_model(&self, collection: &str) -> Option<String> {
+        self.collection_models.read().await.get(collection).cloned()
+    }
+
+    // Makes inserts into the collection, and searches scoped to it, name
+    // `model` as theirs from now on, or stops checking with None. Vectors
+    // already stored aren't checked.
+    pub async fn set_expected_model(&self, collection: &str, model: Option<String>) -> Result<()> {
+        self.write_collection_setting(
+            COLLECTION_MODELS_SETTING,
+            &self.collection_models,
+            collection,
+            model,
+        )
+        .await
+    }
+
+    pub async fn metadata_schema(&self, collection: &str) -> Option<MetadataSchema> {
+        self.metadata_schemas.read().await.get(collection).cloned()
+    }
+
+    // Checks the metadata of writes to the collection against `schema` from
+    // now on, and indexes only its indexed keys, or goes back to indexing
+    // every key for None. Vectors already stored aren't checked, but are
+    // indexed again under the new keys.
+    pub async fn set_metadata_schema(
+        &self,
+        collection: &str,
+        schema: Option<MetadataSchema>,
+    ) -> Result<()> {
+        {
+            let _write = self.write_lock.lock().await;
+            let mut filters = self.filters.write().await;
+            let keys = schema.as_ref().map(MetadataSchema::indexed_keys);
+            let current = self
+                .metadata_schemas
+                .read()
+                .await
+                .get(collection)
+                .map(MetadataSchema::indexed_keys);
+            self.write_collection_setting(
+                COLLECTION_SCHEMAS_SETTING,
+                &self.metadata_schemas,
+                collection,
+                schema,
+            )
+            .await?;
+            if current == keys {
+                return Ok(());
+            }
+            filters.set_indexed_keys(collection, keys);
+            let mut after = None;
+            loop {
+                let page = self
+                    .storage
+                    .scan_collection(Some(collection), after.as_deref(), REBUILD_PAGE_SIZE)
+                    .await?;
+                for vector in &page {
+                    filters.insert(vector);
+                }
+                if page.len() < REBUILD_PAGE_SIZE {
+                    break;
+                }
+                after = page.last().map(|vector| vector.id.clone());
+            }
+        }
+        // So the filters are saved with their new terms
+        self.snapshot_index().await?;
+        Ok(())
+    }
+
+    pub async fn collection_quota(&self, collection: &str) -> Option<Quota> {
+        self.collection_quotas.read().await.get(collection).copied()
+    }
+
+    // Refuses writes that would take the collection past `quota` from now
+    // on, or lifts its quota with None. A collection already past it keeps
+    // what it holds.
+    pub async fn set_collection_quota(&self, collection: &str, quota: Option<Quota>) -> Result<()> {
+        self.write_collection_setting(
+            COLLECTION_QUOTAS_SETTING,
+            &self.collection_quotas,
+            collection,
+            quota.filter(|quota| !quota.is_empty()),
+        )
+        .await
+    }
+
+    pub async fn quota(&self) -> Option<Quota> {
+        self.quota.read().await.as_ref().map(|(_, quota)| *quota)
+    }
+
+    // Refuses writes that would take the whole database past `quota`, or
+    // lifts it with None. `scope` names the database in QuotaExceeded.
+    pub async fn set_quota(&self, scope: &str, quota: Option<Quota>) {
+        *self.quota.write().await = quota
+            .filter(|quota| !quota.is_empty())
+            .map(|quota| (scope.to_string(), quota));
+    }
+
+    // Dry run of the quota checks a write of `vectors` would get
+    pub async fn check_quota(&self, vectors: &[Vector]) -> Result<()> {
+        let mut stored = Vec::with_capacity(vectors.len());
+        let mut previous = Vec::with_capacity(vectors.len());
+        for vector in vectors {
+            let mut vector = vector.clone();
+            self.apply_dtype(&mut vector);
+            previous.push(self.storage.get_vector(&vector.id).await?);
+            stored.push(vector);
+        }
+        self.check_quotas(&stored, &previous).await
+    }
+
+    // Fails with QuotaExceeded when the batch would take the database or a
+    // collection past its quota. `previous` is what each id held before;
+    // overwrites only count by how much they grow the record. Bytes are
+    // the records' stored size, as the usage is, and the write lock is
+    // held so concurrent writes can't both fit under the same limit.
+    async fn check_quotas(&self, vectors: &[Vector], previous: &[Option<Vector>]) -> Result<()> {
+        let quota = self.quota.read().await;
+        let quotas = self.collection_quotas.read().await;
+        if quota.is_none() && quotas.is_empty() {
+            return Ok(());
+        }
+        let record_len = |vector: &Vector| -> Result<u64> {
+            if vector
+                .collection
+                .as_ref()
+                .is_some_and(|c| quotas.contains_key(c))
+                || quota.is_some()
+            {
+                self.storage.record_len(vector)
+            } else {
+                Ok(0)
+            }
+        };
+        let mut total = Usage::default();
+        let mut added: HashMap<&str, Usage> = HashMap::new();
+        let mut seen = HashSet::new();
+        for (vector, old) in vectors.iter().zip(previous) {
+            // A repeated id replaces its earlier write in the batch
+            if !seen.insert(vector.id.as_str()) {
+                continue;
+            }
+            let len = record_len(vector)?;
+            let old_len = old.as_ref().map(record_len).transpose()?.unwrap_or(0);
+            if old.is_none() {
+                total.vectors += 1;
+            }
+            total.bytes += len.saturating_sub(old_len);
+
+            let Some(collection) = vector.collection.as_deref() else {
+                continue;
+            };
+            if !quotas.contains_key(collection) {
+                continue;
+            }
+            let replaced = old
+                .as_ref()
+                .filter(|old| old.collection.as_deref() == Some(collection));
+            let usage = added.entry(collection).or_default();
+            if replaced.is_none() {
+                usage.vectors += 1;
+            }
+            usage.bytes += len.saturating_sub(replaced.map_or(0, |_| old_len));
+        }
+        if let Some((scope, quota)) = quota.as_ref() {
+            let usage = Usage {
+                vectors: self.storage.count_vectors().await? as u64,
+                bytes: self.storage.vector_bytes().await?.stored,
+            };
+            quota.check(scope, usage, total)?;
+        }
+        for (collection, added) in added {
+            let stats = self
+                .storage
+                .collection_stats(collection)
+                .await?
+                .unwrap_or_default();
+            let usage = Usage {
+                vectors: stats.vector_count,
+                bytes: stats.stored_bytes,
+            };
+            quotas[collection].check(&format!("Collection '{}'", collection), usage, added)?;
+        }
+        Ok(())
+    }
+
+    // Every alias and the collection it points at
+    pub async fn aliases(&self) -> BTreeMap<String, String> {
+        let aliases = self.collection_aliases.read().await;
+        aliases
+            .iter()
+            .map(|(alias, collection)| (alias.clone(), collection.clone()))
+            .collect()
+    }
+
+    // The collection a name stands for: what it points at if it's an
+    // alias, else the name itself
+    pub async fn resolve_collection(&self, name: &str) -> String {
+        match self.collection_aliases.read().await.get(name) {
+            Some(collection) => collection.clone(),
+            None => name.to_string(),
+        }
+    }
+
+    // `filter` scoped to the collection its collection name stands for
+    async fn resolve_filter<'a>(&self, filter: &'a SearchFilter) -> Cow<'a, SearchFilter> {
+        let Some(name) = &filter.collection else {
+            return Cow::Borrowed(filter);
+        };
+        match self.collection_aliases.read().await.get(name) {
+            Some(collection) => Cow::Owned(SearchFilter {
+                collection: Some(collection.clone()),
+                ..filter.clone()
+            }),
+            None => Cow::Borrowed(filter),
+        }
+    }
+
+    // Points `alias` at `collection`, or repoints it, so searches, scrolls
+    // and stats under the alias read `collection` from then on. Aliases
+    // can't shadow a collection with vectors or point at another alias.
+    pub async fn set_alias(&self, alias: &str, collection: &str) -> Result<()> {
+        self.check_writable()?;
+        let invalid = |reason: &str| ValidationError::InvalidAlias {
+            alias: alias.to_string(),
+            reason: reason.to_string(),
+        };
+        if alias.is_empty() || alias == collection {
+            return Err(invalid("it must be a name other than its collection's").into());
+        }
+        if self.storage.collection_stats(alias).await?.is_some() {
+            return Err(invalid("a collection with vectors has that name").into());
+        }
+        if self
+            .collection_aliases
+            .read()
+            .await
+            .contains_key(collection)
+        {
+            return Err(invalid("it would point at another alias").into());
+        }
+        self.write_collection_setting(
+            COLLECTION_ALIASES_SETTING,
+            &self.collection_aliases,
+            alias,
+            Some(collection.to_string()),
+        )
+        .await?;
+        info!("Alias {} now points at {}", alias, collection);
+        Ok(())
+    }
+
+    // Whether there was such an alias
+    pub async fn delete_alias(&self, alias: &str) -> Result<bool> {
+        self.check_writable()?;
+        if !self.collection_aliases.read().await.contains_key(alias) {
+            return Ok(false);
+        }
+        self.write_collection_setting(
+            COLLECTION_ALIASES_SETTING,
+            &self.collection_aliases,
+            alias,
+            None,
+        )
+        .await?;
+        Ok(true)
+    }
+
+    // Writes go to collections by their own names, so an alias can't end up
+    // with vectors of its own hidden behind it
+    async fn check_not_alias(&self, vector: &Vector) -> Result<(), ValidationError> {
+        let Some(alias) = &vector.collection else {
+            return Ok(());
+        };
+        match self.collection_aliases.read().await.get(alias) {
+            Some(collection) => Err(ValidationError::WriteToAlias {
+                id: vector.id.clone(),
+                alias: alias.clone(),
+                collection: collection.clone(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    // Vectors going into a collection that expects a model must be tagged
+    // with it, or their scores against its queries would mean nothing
+    async fn check_model(&self, vector: &Vector) -> Result<(), ValidationError> {
+        let Some(collection) = &vector.collection else {
+            return Ok(());
+        };
+        match self.collection_models.read().await.get(collection) {
+            Some(expected) if vector.model.as_ref() != Some(expected) => {
+                Err(ValidationError::ModelMismatch {
+                    id: vector.id.clone(),
+                    collection: collection.clone(),
+                    expected: expected.clone(),
+                    actual: vector
+                        .model
+                        .clone()
+                        .unwrap_or_else(|| "no model".to_string()),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    async fn check_schema(&self, vector: &Vector) -> Result<(), ValidationError> {
+        let Some(collection) = &vector.collection else {
+            return Ok(());
+        };
+        let Some(schema) = self.metadata_schemas.read().await.get(collection).cloned() else {
+            return Ok(());
+        };
+        let errors = schema.check(vector.metadata.as_ref());
+        if errors.is_empty() {
+            return Ok(());
+        }
+        Err(ValidationError::SchemaViolation {
+            id: vector.id.clone(),
+            collection: collection.clone(),
+            errors,
+        })
+    }
+
+    // A filter on a key the collection's schema doesn't index would match
+    // nothing, so it's refused rather than answered with no results
+    async fn check_filter_indexed(&self, filter: &SearchFilter) -> Result<(), ValidationError> {
+        let Some(collection) = &filter.collection else {
+            return Ok(());
+        };
+        let filters = self.filters.read().await;
+        match filter
+            .metadata
+            .keys()
+            .find(|key| !filters.is_indexed(collection, key))
+        {
+            Some(key) => Err(ValidationError::UnindexedFilterKey {
+                collection: collection.clone(),
+                key: key.clone(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    async fn check_query_model(
+        &self,
+        filter: &SearchFilter,
+        model: Option<&str>,
+    ) -> Result<(), ValidationError> {
+        let Some(collection) = &filter.collection else {
+            return Ok(());
+        };
+        match self.collection_models.read().await.get(collection) {
+            Some(expected) if model != Some(expected.as_str()) => {
+                Err(ValidationError::QueryModelMismatch {
+                    collection: collection.clone(),
+                    expected: expected.clone(),
+                    actual: model.unwrap_or("no model").to_string(),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // The vector followed by its kept earlier versions, newest first. None
+    // if there's no such vector.
+    pub async fn get_versions(&self, id: &str) -> Result<Option<Vec<Vector>>> {
+        let Some(latest) = self.storage.get_vector(id).await? else {
+            return Ok(None);
+        };
+        let mut versions = vec![latest];
+        versions.extend(self.storage.get_versions(id).await?);
+        Ok(Some(versions))
+    }
+
+    // Keeps what each write in the batch replaced as an earlier version of
+    // its id, up to the collection's limit. `previous` holds what was stored
+    // before the batch.
+    async fn keep_versions(&self, vectors: &[Vector], previous: &[Option<Vector>]) -> Result<()> {
+        let mut replaced: HashMap<&str, &Vector> = HashMap::new();
+        let mut histories: HashMap<&str, Vec<Vector>> = HashMap::new();
+        for (vector, old) in vectors.iter().zip(previous) {
+            let prior = replaced.insert(&vector.id, vector).or(old.as_ref());
+            let keep = self.versions_kept(vector.collection.as_deref()).await;
+            let Some(prior) = prior.filter(|_| keep > 0) else {
+                continue;
+            };
+            let history = match histories.entry(&vector.id) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(self.storage.get_versions(&vector.id).await?),
+            };
+            history.insert(0, prior.clone());
+            history.truncate(keep);
+        }
+        for (id, history) in histories {
+            self.storage.put_versions(id, &history).await?;
+        }
+        Ok(())
+    }
+
+    // Gives vectors sent without an id one from their collection's scheme.
+    // Auto-increment counters are saved before the batch is written, so a
+    // failed write leaves a gap rather than handing an id out twice.
+    async fn assign_ids(&self, vectors: &mut [Vector]) -> Result<()> {
+        let mut sequences: HashMap<Option<String>, Vec<usize>> = HashMap::new();
+        for (position, vector) in vectors.iter_mut().enumerate() {
+            if !vector.id.is_empty() {
+                continue;
+            }
+            match self.id_scheme(vector.collection.as_deref()).await {
+                IdScheme::Uuid => vector.id = uuid::Uuid::new_v4().to_string(),
+                IdScheme::Ulid => vector.id = self.ulids.generate(),
+                IdScheme::AutoIncrement => sequences
+                    .entry(vector.collection.clone())
+                    .or_default()
+                    .push(position),
+            }
+        }
+        if sequences.is_empty() {
+            return Ok(());
+        }
+
+        let _sequence = self.sequence_lock.lock().await;
+        for (collection, positions) in sequences {
+            let key = format!(
+                "{}{}",
+                ID_SEQUENCE_SETTING_PREFIX,
+                collection.as_deref().unwrap_or_default()
+            );
+            let last: u64 = match self.storage.get_setting(&key).await? {
+                Some(last) => last.parse()?,
+                None => 0,
+            };
+            self.storage
+                .put_setting(&key, &(last + positions.len() as u64).to_string())
+                .await?;
+            for (n, position) in (last + 1..).zip(positions) {
+                vectors[position].id = sequence_id(n);
+            }
+        }
+        Ok(())
+    }
+
+    // The most similar vector to `vector` in its collection scoring at least
+    // the collection's dedup threshold, if it has one: among `earlier`
+    // (vectors of the same batch, not indexed yet) by position, else among
+    // the stored ones by id. Another vector with the same id isn't a
+    // duplicate, since it gets replaced.
+    async fn find_duplicate(
+        &self,
+        vector: &Vector,
+        earlier: &[Vector],
+    ) -> Result<Option<(Dedup, Duplicate, f32)>> {
+        let Some(collection) = &vector.collection else {
+            return Ok(None);
+        };
+        let Some(dedup) = self.collection_dedup(collection).await else {
+            return Ok(None);
+        };
+        let metric = self.collection_metric(collection).await;
+
+        let mut nearest = None;
+        for (position, other) in earlier.iter().enumerate() {
+            if other.collection != vector.collection || other.id == vector.id {
+                continue;
+            }
+            let score = metric.score(&vector.data, &other.data)?;
+            if score >= dedup.threshold && nearest.as_ref().is_none_or(|(_, best)| score > *best) {
+                nearest = Some((Duplicate::Batch(position), score));
+            }
+        }
+
+        let filter = SearchFilter {
+            collection: Some(collection.clone()),
+            metadata: HashMap::new(),
+        };
+        let (index, _) = self.dense_index(&filter).await;
+        let hits = self
+            .with_filter(&filter, |allowed| {
+                index.search_filtered(&vector.data, 2, allowed)
+            })
+            .await?
+            .unwrap_or_default();
+        if let Some(hit) = hits.into_iter().find(|hit| hit.id != vector.id) {
+            if hit.score >= dedup.threshold
+                && nearest.as_ref().is_none_or(|(_, best)| hit.score > *best)
+            {
+                nearest = Some((Duplicate::Stored(hit.id), hit.score));
+            }
+        }
+        Ok(nearest.map(|(duplicate, score)| (dedup, duplicate, score)))
+    }
+
+    // Applies the collections' dedup settings to a batch about to be
+    // written: a near duplicate fails it, or is merged into its neighbour,
+    // which is written again in its place
+    async fn dedup(&self, vectors: Vec<Vector>) -> Result<Vec<Vector>> {
+        if self.collection_dedup.read().await.is_empty() {
+            return Ok(vectors);
+        }
+        let mut kept: Vec<Vector> = Vec::with_capacity(vectors.len());
+        for vector in vectors {
+            let Some((dedup, duplicate, score)) = self.find_duplicate(&vector, &kept).await? else {
+                kept.push(vector);
+                continue;
+            };
+            let position = match (dedup.action, duplicate) {
+                (DedupAction::Reject, duplicate) => {
+                    let existing = match duplicate {
+                        Duplicate::Batch(position) => kept[position].id.clone(),
+                        Duplicate::Stored(id) => id,
+                    };
+                    return Err(ValidationError::Duplicate {
+                        id: vector.id,
+                        existing,
+                        score,
+                    }
+                    .into());
+                }
+                (DedupAction::Merge, Duplicate::Batch(position)) => position,
+                (DedupAction::Merge, Duplicate::Stored(id)) => {
+                    match kept.iter().position(|other| other.id == id) {
+                        Some(position) => position,
+                        None => {
+                            let Some(existing) = self.storage.get_vector(&id).await? else {
+                                kept.push(vector);
+                                continue;
+                            };
+                            kept.push(existing);
+                            kept.len() - 1
+                        }
+                    }
+                }
+            };
+            if let Some(metadata) = vector.metadata {
+                let merged = kept[position].metadata.get_or_insert_with(HashMap::new);
+                for (key, value) in metadata {
+                    merged.entry(key).or_insert(value);
+                }
+            }
+        }
+        Ok(kept)
+    }
+
+    // The index a dense search with this filter runs against, and its metric
+    async fn dense_index(&self, filter: &SearchFilter) -> (Arc<dyn VectorIndex>, DistanceMetric) {
+        if let Some(collection) = &filter.collection {
+            if let Some(index) = self.collection_indexes.read().await.get(collection) {
+                return (Arc::clone(index), self.collection_metric(collection).await);
+            }
+        }
+        (Arc::clone(&self.index), self.distance_metric)
+    }
+
+    pub async fn get_vector(&self, id: &str) -> Result<Option<Vector>> {
+        self.storage.get_vector(id).await
+    }
+
+    pub async fn search(
+        &self,
+        query: &[f32],
+        k: usize,
+        threshold: f32,
+    ) -> Result<Vec<SearchResult>> {
+        self.search_filtered(query, k, threshold, &SearchFilter::default())
+            .await
+    }
+
+    pub async fn search_in_collection(
+        &self,
+        collection: &str,
+        query: &[f32],
+        k: usize,
+        threshold: f32,
+    ) -> Result<Vec<SearchResult>> {
+        let filter = SearchFilter {
+            collection: Some(collection.to_string()),
+            ..Default::default()
+        };
+        self.search_filtered(query, k, threshold, &filter).await
+    }
+
+    // Filters are resolved to a bitmap up front and checked while the index
+    // is searched, so selective filters still return k results
+    pub async fn search_filtered(
+        &self,
+        query: &[f32],
+        k: usize,
+        threshold: f32,
+        filter: &SearchFilter,
+    ) -> Result<Vec<SearchResult>> {
+        self.search_with(query, k, threshold, filter, &SearchOptions::default())
+            .await
+    }
+
+    // Searches for `groups` groups of hits. The index is asked for several
+    // times as many candidates as could be returned, since many may share a
+    // group.
+    pub async fn search_grouped(
+        &self,
+        query: &[f32],
+        groups: usize,
+        threshold: f32,
+        filter: &SearchFilter,
+        grouping: &Grouping,
+        options: &SearchOptions,
+    ) -> Result<Vec<SearchGroup>> {
+        let fetch = groups * grouping.group_size.max(1) * GROUP_FETCH_FACTOR;
+        let results = self
+            .search_with(query, fetch, threshold, filter, options)
+            .await?;
+        Ok(grouping.apply(results, groups))
+    }
+
+    // `search_filtered` against a named vector and/or with a rescoring pass,
+    // in which case the threshold applies to the rescored results
+    pub async fn search_with(
+        &self,
+        query: &[f32],
+        k: usize,
+        threshold: f32,
+        filter: &SearchFilter,
+        options: &SearchOptions,
+    ) -> Result<Vec<SearchResult>> {
+        let (results, _) = self
+            .search_profiled(query, k, threshold, filter, options)
+            .await?;
+        Ok(results)
+    }
+
+    // `search_with`, also saying how the search went
+    pub async fn search_profiled(
+        &self,
+        query: &[f32],
+        k: usize,
+        threshold: f32,
+        filter: &SearchFilter,
+        options: &SearchOptions,
+    ) -> Result<(Vec<SearchResult>, SearchProfile)> {
+        let mut profile = SearchProfile::default();
+        let filter = self.resolve_filter(filter).await;
+        let filter = filter.as_ref();
+        validation::validate_query(query)?;
+        self.check_filter_indexed(filter).await?;
+        self.check_query_model(filter, options.model.as_deref())
+            .await?;
+        let (index, metric) = match &options.vector_name {
+            Some(name) => match self.named_indexes.read().await.get(name) {
+                Some(index) => (Arc::clone(index), self.distance_metric),
+                None => return Ok((Vec::new(), profile)),
+            },
+            None => self.dense_index(filter).await,
+        };
+        // Scores stay raw, higher closer whatever the metric, until the
+        // results are ranked
+        let mut threshold = metric.raw_score(threshold, options.score_mode);
+        if let Some(max_distance) = options.max_distance {
+            threshold = threshold.max(metric.raw_score(max_distance, ScoreMode::Distance));
+        }
+        let rerank = options.rerank.as_ref();
+        // Get more candidates for reranking
+        let mut fetch = k * rerank.map_or(2, |rerank| rerank.fetch_factor.max(1));
+        // Index work runs off this task, so nothing else stops it when the
+        // search is dropped; the guard cancels it then
+        let cancel = options
+            .cancel
+            .as_ref()
+            .map_or_else(CancellationToken::new, CancellationToken::child_token);
+        let _cancel_on_drop = cancel.clone().drop_guard();
+        let allowed = if options.exact || filter.is_empty() {
+            None
+        } else {
+            let started = Instant::now();
+            let filters = Arc::clone(&self.filters).read_owned().await;
+            let matching = filters.matching(filter);
+            profile.filter_matches = Some(matching.len());
+            profile.filter_us = elapsed_us(started);
+            if matching.is_empty() {
+                return Ok((Vec::new(), profile));
+            }
+            Some(Arc::new((filters, matching)))
+        };
+
+        loop {
+            let started = Instant::now();
+            let candidates = if options.exact {
+                let vector_name = options.vector_name.as_deref();
+                self.exact_search(query, fetch, filter, vector_name, metric, &cancel)
+                    .await?
+            } else {
+                let index = Arc::clone(&index);
+                let allowed = allowed.clone();
+                let query = query.to_vec();
+                let cancel = cancel.clone();
+                spawn_blocking(move || match allowed.as_deref() {
+                    // The bitmap is keyed by the index's own ids, so nodes are
+                    // checked without looking their names up
+                    Some((filters, matching))
+                        if index
+                            .shared_ids()
+                            .is_some_and(|ids| filters.shares_ids(&ids)) =>
+                    {
+                        index.search_ids(
+                            &query,
+                            fetch,
+                            &|internal| matching.contains(internal),
+                            &cancel,
+                        )
+                    }
+                    Some((filters, matching)) => index.search_cancellable(
+                        &query,
+                        fetch,
+                        &|id| filters.contains(matching, id),
+                        &cancel,
+                    ),
+                    None => index.search_cancellable(&query, fetch, &|_| true, &cancel),
+                })
+                .await??
+            };
+            profile.index_us += elapsed_us(started);
+            profile.candidates = candidates.len();
+
+            let started = Instant::now();
+            let cut_off = (candidates.len() == fetch)
+                .then(|| candidates.last().map(|last| last.score))
+                .flatten();
+            let mut results = Vec::new();
+            let mut other_models = 0;
+
+            for candidate in candidates {
+                if rerank.is_none() && candidate.score < threshold {
+                    continue;
+                }
+                let Some(vector) = self.storage.get_vector(&candidate.id).await? else {
+                    continue;
+                };
+                // Scores across models aren't comparable
+                if let (Some(model), Some(stored)) = (&options.model, &vector.model) {
+                    if model != stored {
+                        other_models += 1;
+                        continue;
+                    }
+                }
+                let data = match &options.vector_name {
+                    Some(name) => vector.vectors.get(name).unwrap_or(&vector.data),
+                    None => &vector.data,
+                };
+                let score = match rerank {
+                    Some(rerank) => {
+                        let exact = metric.score(query, data)?;
+                        let boost: f32 = rerank
+                            .boosts
+                            .iter()
+                            .map(|boost| boost.apply(vector.metadata.as_ref()))
+                            .sum();
+                        exact + boost
+                    }
+                    None => candidate.score,
+                };
+                if score >= threshold {
+                    results.push((
+                        SearchResult {
+                            id: candidate.id,
+                            score,
+                            vector: options.include_vector.then(|| data.clone()),
+                            metadata: vector.metadata,
+                        },
+                        vector.created_at,
+                    ));
+                }
+            }
+            profile.fetch_us += elapsed_us(started);
+
+            // The index breaks ties by id, so rows tied with the k-th best
+            // may lie past the cut. Ordering them by created_at needs them all.
+            if self.tie_break == TieBreak::CreatedAt && rerank.is_none() {
+                if let Some(cut_off) = cut_off {
+                    let mut scores: Vec<f32> = results.iter().map(|(r, _)| r.score).collect();
+                    scores.sort_by(|a, b| b.total_cmp(a));
+                    if scores.get(k.max(1) - 1).is_some_and(|&kth| kth <= cut_off) {
+                        fetch *= 2;
+                        continue;
+                    }
+                }
+            }
+            // Hits from other models don't count towards k, so fetch past them
+            if other_models > 0
+                && results.len() < k
+                && cut_off.is_some_and(|score| rerank.is_some() || score >= threshold)
+            {
+                fetch *= 2;
+                continue;
+            }
+
+            let started = Instant::now();
+            let mut results = self.rank_results(results, k);
+            self.run_search_plugins(query, &mut results)?;
+            for result in &mut results {
+                result.score = metric.convert_score(result.score, options.score_mode);
+            }
+            profile.fetch_us += elapsed_us(started);
+            profile.results = results.len();
+            return Ok((results, profile));
+        }
+    }
+
+    // Scores every stored vector the filter allows, in parallel. Vectors
+    // without the named vector are skipped.
+    async fn exact_search(
+        &self,
+        query: &[f32],
+        k: usize,
+        filter: &SearchFilter,
+        vector_name: Option<&str>,
+        metric: DistanceMetric,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<skypier_index::SearchResult>> {
+        let vectors = match &filter.collection {
+            Some(collection) => self.storage.get_vectors_in_collection(collection).await?,
+            None => self.storage.list_vectors().await?,
+        };
+        let query = query.to_vec();
+        let filter = filter.clone();
+        let vector_name = vector_name.map(str::to_string);
+        let cancel = cancel.clone();
+        spawn_blocking(move || {
+            let rows: Vec<(&str, &[f32])> = vectors
+                .par_iter()
+                .filter(|vector| filter.matches(vector))
+                .filter_map(|vector| {
+                    let data = match &vector_name {
+                        Some(name) => vector.vectors.get(name)?,
+                        None => &vector.data,
+                    };
+                    Some((vector.id.as_str(), data.as_slice()))
+                })
+                .collect();
+            let scores = score_rows(&query, &rows, metric, &cancel)?;
+            let mut results: Vec<_> = rows
+                .iter()
+                .zip(scores)
+                .map(|((id, _), score)| skypier_index::SearchResult {
+                    id: id.to_string(),
+                    score,
+                })
+                .collect();
+            skypier_index::sort_results(&mut results);
+            results.truncate(k);
+            Ok(results)
+        })
+        .await?
+    }
+
+    // A query for vectors like the `positive` examples and unlike the
+    // `negative` ones: their means `p` and `n` give `p + negative_weight *
+    // (p - n)`, as Qdrant's average_vector strategy does with a weight of 1.
+    // Examples are read from the named vector when one is given.
+    pub async fn recommendation_query(
+        &self,
+        positive: &[String],
+        negative: &[String],
+        negative_weight: f32,
+        vector_name: Option<&str>,
+    ) -> Result<Vec<f32>> {
+        if positive.is_empty() {
+            return Err(ValidationError::NoPositiveExamples.into());
+        }
+        let positive = self.mean_of_examples(positive, vector_name).await?;
+        if negative.is_empty() {
+            return Ok(positive);
+        }
+        let negative = self.mean_of_examples(negative, vector_name).await?;
+        if negative.len() != positive.len() {
+            return Err(ValidationError::DimensionMismatch {
+                id: "negative examples".to_string(),
+                expected: positive.len(),
+                actual: negative.len(),
+            }
+            .into());
+        }
+        Ok(positive
+            .iter()
+            .zip(&negative)
+            .map(|(p, n)| p + negative_weight * (p - n))
+            .collect())
+    }
+
+    async fn mean_of_examples(
+        &self,
+        ids: &[String],
+        vector_name: Option<&str>,
+    ) -> Result<Vec<f32>> {
+        let mut sum: Vec<f32> = Vec::new();
+        for id in ids {
+            let vector = self.storage.get_vector(id).await?;
+            let data = vector.and_then(|vector| match vector_name {
+                Some(name) => vector.vectors.get(name).cloned(),
+                None => Some(vector.data),
+            });
+            let Some(data) = data else {
+                let id = match vector_name {
+                    Some(name) => format!("{} ({})", id, name),
+                    None => id.clone(),
+                };
+                return Err(ValidationError::MissingExample { id }.into());
+            };
+            if sum.is_empty() {
+                sum = vec![0.0; data.len()];
+            } else if data.len() != sum.len() {
+                return Err(ValidationError::DimensionMismatch {
+                    id: id.clone(),
+                    expected: sum.len(),
+                    actual: data.len(),
+                }
+                .into());
+            }
+            for (total, value) in sum.iter_mut().zip(&data) {
+                *total += value;
+            }
+        }
+        let count = ids.len() as f32;
+        Ok(sum.into_iter().map(|total| total / count).collect())
+    }
+
+    // Dot product against the stored sparse vectors. Vectors without one, or
+    // sharing no dimension with the query, aren't returned.
+    pub async fn search_sparse(
+        &self,
+        query: &SparseVector,
+        k: usize,
+        threshold: f32,
+        filter: &SearchFilter,
+    ) -> Result<Vec<SearchResult>> {
+        validation::validate_sparse_query(query)?;
+        let filter = self.resolve_filter(filter).await;
+        let filter = filter.as_ref();
+        self.check_filter_indexed(filter).await?;
+        let Some(hits) = self
+            .with_filter(filter, |allowed| {
+                self.sparse_index
+                    .search_filtered(&query.indices, &query.values, k, allowed)
+            })
+            .await?
+        else {
+            return Ok(Vec::new());
+        };
+
+        let scored = hits.into_iter().map(|hit| (hit.id, hit.score)).collect();
+        let mut results = self.resolve(scored, k, threshold).await?;
+        // There's no dense query to show plugins
+        self.run_search_plugins(&[], &mut results)?;
+        Ok(results)
+    }
+
+    // Searches the dense and sparse indexes separately and merges the two
+    // lists. The threshold applies to the fused scores.
+    pub async fn search_hybrid(
+        &self,
+        dense: &[f32],
+        sparse: &SparseVector,
+        k: usize,
+        threshold: f32,
+        filter: &SearchFilter,
+        fusion: &Fusion,
+    ) -> Result<Vec<SearchResult>> {
+        validation::validate_query(dense)?;
+        validation::validate_sparse_query(sparse)?;
+        let filter = self.resolve_filter(filter).await;
+        let filter = filter.as_ref();
+        self.check_filter_indexed(filter).await?;
+        let fetch = k * HYBRID_FETCH_FACTOR;
+        let (index, _) = self.dense_index(filter).await;
+        let Some((dense_hits, sparse_hits)) = self
+            .with_filter(filter, |allowed| {
+                Ok((
+                    index.search_filtered(dense, fetch, allowed)?,
+                    self.sparse_index.search_filtered(
+                        &sparse.indices,
+                        &sparse.values,
+                        fetch,
+                        allowed,
+                    )?,
+                ))
+            })
+            .await?
+        else {
+            return Ok(Vec::new());
+        };
+
+        let scored = fusion.fuse(
+            dense_hits.iter().map(|hit| (hit.id.as_str(), hit.score)),
+            sparse_hits.iter().map(|hit| (hit.id.as_str(), hit.score)),
+        );
+        let mut results = self.resolve(scored, k, threshold).await?;
+        self.run_search_plugins(dense, &mut results)?;
+        Ok(results)
+    }
+
+    // Runs `search` with the filter as its check on ids. None when nothing
+    // matches the filter.
+    async fn with_filter<T>(
+        &self,
+        filter: &SearchFilter,
+        search: impl FnOnce(&dyn Fn(&str) -> bool) -> Result<T>,
+    ) -> Result<Option<T>> {
+        if filter.is_empty() {
+            return search(&|_| true).map(Some);
+        }
+        let filters = self.filters.read().await;
+        let matching = filters.matching(filter);
+        if matching.is_empty() {
+            return Ok(None);
+        }
+        search(&|id| filters.contains(&matching, id)).map(Some)
+    }
+
+    // Looks up scored ids in storage and keeps the top k at or above the
+    // threshold
+    async fn resolve(
+        &self,
+        scored: Vec<(String, f32)>,
+        k: usize,
+        threshold: f32,
+    ) -> Result<Vec<SearchResult>> {
+        let mut results = Vec::new();
+        for (id, score) in scored {
+            if score < threshold {
+                continue;
+            }
+            if let Some(vector) = self.storage.get_vector(&id).await? {
+                results.push((
+                    SearchResult {
+                        id,
+                        score,
+                        metadata: vector.metadata,
+                        vector: None,
+                    },
+                    vector.created_at,
+                ));
+            }
+        }
+        Ok(self.rank_results(results, k))
+    }
+
+    pub async fn delete_vector(&self, id: &str) -> Result<bool> {
+        self.check_writable()?;
+        for plugin in &self.plugins {
+            plugin.on_delete(id).map_err(|e| {
+                anyhow!(
+                    "Plugin '{}' rejected delete of {}: {}",
+                    plugin.name(),
+                    id,
+                    e
+                )
+            })?;
+        }
+
+        // Held across the storage write so an index snapshot never sees a
+        // delete in the WAL that hasn't reached the index yet
+        let _write = self.write_lock.lock().await;
+        let Some(vector) = self.storage.get_vector(id).await? else {
+            return Ok(false);
+        };
+        let hlc = self.clock.now()?;
+        let removed = self.storage.delete_vector(id, hlc).await?;
+        if removed {
+            self.index.remove_vector(id)?;
+            self.unindex_extra(id).await?;
+            self.filters.write().await.remove(id);
+            self.publish(
+                self.storage.wal_head().await?,
+                ChangeKind::Delete,
+                &vector,
+                hlc,
+            );
+        }
+        Ok(removed)
+    }
+
+    // Deletes every vector in `collection`, returning how many there were
+    pub async fn delete_collection(&self, collection: &str) -> Result<usize> {
+        self.check_writable()?;
+        let _write = self.write_lock.lock().await;
+        let vectors = self.list_vectors(Some(collection)).await?;
+        let ids: Vec<String> = vectors.iter().map(|vector| vector.id.clone()).collect();
+        for id in &ids {
+            for plugin in &self.plugins {
+                plugin.on_delete(id).map_err(|e| {
+                    anyhow!(
+                        "Plugin '{}' rejected delete of {}: {}",
+                        plugin.name(),
+                        id,
+                        e
+                    )
+                })?;
+            }
+        }
+        let hlc = self.clock.now()?;
+        self.storage.write_batch(&[], &ids, hlc).await?;
+
+        let first_seq = self.storage.wal_head().await? + 1 - ids.len() as u64;
+        let mut filters = self.filters.write().await;
+        for (vector, seq) in vectors.iter().zip(first_seq..) {
+            self.index.remove_vector(&vector.id)?;
+            self.unindex_extra(&vector.id).await?;
+            filters.remove(&vector.id);
+            self.publish(seq, ChangeKind::Delete, vector, hlc);
+        }
+        Ok(vectors.len())
+    }
+
+    pub async fn list_collections(&self) -> Result<Vec<String>> {
+        self.storage.list_collections().await
+    }
+
+    pub async fn get_stats(&self) -> Result<DatabaseStats> {
+        let total_vectors = self.storage.count_vectors().await?;
+        let storage_size = self.storage.size_bytes().await?;
+        let vector_bytes = self.storage.vector_bytes().await?;
+
+        // Storage keeps the dimensions of the last vector written if they
+        // weren't configured
+        let dimensions = match self.dimensions {
+            Some(dimensions) => dimensions,
+            None => self.storage.dimensions().await?,
+        };
+
+        Ok(DatabaseStats {
+            total_vectors,
+            dimensions,
+            storage_size_bytes: storage_size,
+            raw_vector_bytes: vector_bytes.raw as usize,
+            stored_vector_bytes: vector_bytes.stored as usize,
+            index_build_error: self.index.build_error(),
+        })
+    }
+
+    pub async fn collection_stats(&self, collection: &str) -> Result<Option<CollectionStats>> {
+        let collection = self.resolve_collection(collection).await;
+        self.storage.collection_stats(&collection).await
+    }
+
+    // Counts the vectors matching `filter` per value of a metadata key. Only
+    // the filter index is read, not the vectors.
+    pub async fn aggregate(&self, filter: &SearchFilter, key: &str) -> Aggregate {
+        let filter = self.resolve_filter(filter).await;
+        let filter = filter.as_ref();
+        let filters = self.filters.read().await;
+        let within = filters.matching(filter);
+        let mut values: Vec<ValueCount> = filters
+            .value_counts(key, &within)
+            .into_iter()
+            .map(|(value, count)| ValueCount { value, count })
+            .collect();
+        values.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+        // A vector has at most one value per key
+        let counted: u64 = values.iter().map(|value| value.count).sum();
+        Aggregate {
+            total: within.len(),
+            missing: within.len() - counted,
+            values,
+        }
+    }
+
+    pub fn index_type(&self) -> &'static str {
+        self.index.index_type()
+    }
+
+    // Reads through the main, named and collection indexes so the first
+    // searches after startup don't fault their memory in. Returns the bytes
+    // read.
+    pub async fn warm_up(&self) -> Result<usize> {
+        let mut indexes = vec![Arc::clone(&self.index)];
+        indexes.extend(self.named_indexes.read().await.values().cloned());
+        indexes.extend(self.collection_indexes.read().await.values().cloned());
+        spawn_blocking(move || indexes.iter().map(|index| index.warm_up()).sum()).await
+    }
+
+    // The type of the index searches in `collection` use, its own or the
+    // main one
+    pub async fn collection_index_type(&self, collection: &str) -> &'static str {
+        match self.collection_indexes.read().await.get(collection) {
+            Some(index) => index.index_type(),
+            None => self.index.index_type(),
+        }
+    }
+
+    // Why the last background build of that index failed, if it did
+    pub async fn collection_index_build_error(&self, collection: &str) -> Option<String> {
+        match self.collection_indexes.read().await.get(collection) {
+            Some(index) => index.build_error(),
+            None => self.index.build_error(),
+        }
+    }
+
+    pub async fn get_setting(&self, key: &str) -> Result<Option<String>> {
+        self.storage.get_setting(key).await
+    }
+
+    pub async fn put_setting(&self, key: &str, value: &str) -> Result<()> {
+        self.storage.put_setting(key, value).await
+    }
+
+    // The audit log, kept in storage apart from vectors and the WAL
+    pub async fn append_audit(&self, entry: &AuditEntry) -> Result<u64> {
+        self.storage.append_audit(entry).await
+    }
+
+    pub async fn audit_page(&self, seq: u64, limit: usize) -> Result<Vec<AuditEntry>> {
+        self.storage.audit_page(seq, limit).await
+    }
+
+    pub async fn prune_audit(&self, before: u64) -> Result<usize> {
+        self.storage.prune_audit(before).await
+    }
+
+    pub async fn compact(&self) -> Result<()> {
+        self.storage.compact().await?;
+        Ok(())
+    }
+
+    // Waits for in-flight writes to finish, makes them durable and snapshots
+    // the index. The database stays usable, but callers should stop sending
+    // writes first.
+    pub async fn shutdown(&self) -> Result<()> {
+        {
+            let _write = self.write_lock.lock().await;
+            self.storage.flush().await?;
+        }
+        self.snapshot_index().await?;
+        Ok(())
+    }
+
+    // Writes the index and filter bitmaps to disk along with the WAL
+    // position they reflect, then drops the WAL entries they cover. Returns
+    // that WAL position.
+    pub async fn snapshot_index(&self) -> Result<u64> {
+        if !self.storage.persistent() {
+            return self.storage.wal_head().await;
+        }
+        let _snapshot = self.snapshot_lock.lock().await;
+        let (seq, index_data, filter_data, named_data, sparse_data, collection_data) = {
+            // Writes hold the write lock across their storage write, so the
+            // WAL head can't move while we hold it
+            let _write = self.write_lock.lock().await;
+            let filters = self.filters.read().await;
+            (
+                self.storage.wal_head().await?,
+                self.index.save()?,
+                filters.save()?,
+                save_indexes(&self.named_indexes).await?,
+                self.sparse_index.save()?,
+                save_indexes(&self.collection_indexes).await?,
+            )
+        };
+
+        let path = self.data_dir.join(INDEX_SNAPSHOT_FILE);
+        let named_path = self.data_dir.join(NAMED_SNAPSHOT_FILE);
+        let sparse_path = self.data_dir.join(SPARSE_SNAPSHOT_FILE);
+        let collection_path = self.data_dir.join(COLLECTION_SNAPSHOT_FILE);
+        spawn_blocking(move || {
+            // Written first: a main snapshot at another seq makes them
+            // stale, and these indexes get rebuilt
+            replace_file(&named_path, &encode_indexes(seq, &named_data))?;
+            replace_file(&collection_path, &encode_indexes(seq, &collection_data))?;
+
+            let mut sparse = seq.to_le_bytes().to_vec();
+            sparse.extend_from_slice(&sparse_data);
+            replace_file(&sparse_path, &sparse)?;
+
+            // WAL seq, index length, index, filter bitmaps
+            let mut contents = Vec::with_capacity(16 + index_data.len() + filter_data.len());
+            contents.extend_from_slice(&seq.to_le_bytes());
+            contents.extend_from_slice(&(index_data.len() as u64).to_le_bytes());
+            contents.extend_from_slice(&index_data);
+            contents.extend_from_slice(&filter_data);
+            replace_file(&path, &contents)
+        })
+        .await??;
+
+        self.storage
+            .truncate_wal(seq.saturating_sub(self.changefeed_retention))
+            .await?;
+        Ok(seq)
+    }
+
+    // Restores the index and filters from a snapshot file, returning the
+    // snapshot's WAL position
+    fn restore_snapshot(
+        contents: &[u8],
+        index: &dyn VectorIndex,
+        filters: &mut FilterIndex,
+    ) -> Result<u64> {
+        let header = |at: usize| -> Result<u64> {
+            let bytes = contents
+                .get(at..at + 8)
+                .ok_or_else(|| anyhow!("snapshot is truncated"))?;
+            Ok(u64::from_le_bytes(bytes.try_into()?))
+        };
+        let seq = header(0)?;
+        let index_len = usize::try_from(header(8)?)?;
+        let index_data = contents
+            .get(16..16 + index_len)
+            .ok_or_else(|| anyhow!("snapshot is truncated"))?;
+
+        filters.restore(&contents[16 + index_len..])?;
+        index.load(index_data)?;
+        Ok(seq)
+    }
+
+    // Restores the named, sparse and collection indexes from their
+    // snapshots if all were taken at `seq`. False when there are no such
+    // snapshots, or they don't cover every collection with a metric.
+    async fn restore_extra(&self, seq: u64) -> Result<bool> {
+        let named_path = self.data_dir.join(NAMED_SNAPSHOT_FILE);
+        let sparse_path = self.data_dir.join(SPARSE_SNAPSHOT_FILE);
+        let collection_path = self.data_dir.join(COLLECTION_SNAPSHOT_FILE);
+        let (Some(contents), Some(sparse), Some(collections)) = (
+            read_snapshot_at(&named_path, seq).await?,
+            read_snapshot_at(&sparse_path, seq).await?,
+            read_snapshot_at(&collection_path, seq).await?,
+        ) else {
+            return Ok(false);
+        };
+
+        let collections = decode_indexes(&collections)?;
+        {
+            let indexes = self.collection_indexes.read().await;
+            if collections.len() != indexes.len() {
+                return Ok(false);
+            }
+            for (collection, data) in collections {
+                match indexes.get(&collection) {
+                    Some(index) => index.load(data)?,
+                    None => return Ok(false),
+                }
+            }
+        }
+        self.sparse_index.load(&sparse)?;
+
+        let mut named = HashMap::new();
+        for (name, data) in decode_indexes(&contents)? {
+            let index = (self.named_index)()?;
+            index.load(data)?;
+            named.insert(name, index);
+        }
+        *self.named_indexes.write().await = named;
+        Ok(true)
+    }
+
+    // Clears every index and the filters and fills them from storage a page
+    // at a time, passing the number of vectors indexed so far to `progress`
+    // after each page; an error from it stops the rebuild. Callers hold the
+    // write lock, so storage is complete on its own, and have opened empty
+    // collection indexes.
+    async fn rebuild(
+        &self,
+        filters: &mut FilterIndex,
+        progress: &(dyn Fn(usize) -> Result<()> + Sync),
+    ) -> Result<usize> {
+        self.index.clear();
+        filters.clear();
+        self.named_indexes.write().await.clear();
+        self.sparse_index.clear();
+        let mut count = 0;
+        let mut after = None;
+        loop {
+            let page = self
+                .scroll(None, after.as_deref(), REBUILD_PAGE_SIZE)
+                .await?;
+            let vectors = page.vectors;
+            for vector in &vectors {
+                filters.insert(vector);
+                self.index_extra(vector).await?;
+            }
+            count += vectors.len();
+            let index = Arc::clone(&self.index);
+            spawn_blocking(move || build_index(index.as_ref(), &vectors)).await??;
+            progress(count)?;
+            match page.next {
+                Some(next) => after = Some(next),
+                None => break,
+            }
+        }
+        Ok(count)
+    }
+
+    // Rebuilds every index from storage, e.g. to pick up new index settings
+    // or drop the tombstones of deleted vectors, then snapshots the result.
+    // Writes wait until it's done; searches meanwhile see the partly rebuilt
+    // index. `progress` gets the number of vectors indexed after each page,
+    // and can stop the rebuild by returning an error, e.g. when cancelled;
+    // the index is then loaded back as it was. Returns the number of vectors
+    // indexed.
+    pub async fn reindex(&self, progress: impl Fn(usize) -> Result<()> + Sync) -> Result<usize> {
+        let rebuilt = {
+            let _write = self.write_lock.lock().await;
+            let mut filters = self.filters.write().await;
+            self.open_collection_indexes().await?;
+            self.rebuild(&mut filters, &progress).await
+        };
+        let count = match rebuilt {
+            Ok(count) => count,
+            Err(e) => {
+                warn!("Reindex stopped, loading the index back: {}", e);
+                self.load_index().await?;
+                return Err(e);
+            }
+        };
+        info!("Reindexed {} stored vectors", count);
+        self.snapshot_index().await?;
+        Ok(count)
+    }
+
+    // Brings the in-memory index up to date with storage at startup: loads
+    // the latest snapshot and replays the WAL written after it, or rebuilds
+    // from every stored vector when there is no usable snapshot.
+    pub async fn load_index(&self) -> Result<()> {
+        let _write = self.write_lock.lock().await;
+        let index = &self.index;
+        let mut filters = self.filters.write().await;
+        let path = self.data_dir.join(INDEX_SNAPSHOT_FILE);
+        self.open_collection_indexes().await?;
+        self.load_collection_settings().await?;
+        for (collection, schema) in self.metadata_schemas.read().await.iter() {
+            filters.set_indexed_keys(collection, Some(schema.indexed_keys()));
+        }
+
+        let snapshot = match read_file(&path).await {
+            // A leftover snapshot can't describe storage that starts empty
+            _ if !self.storage.persistent() => None,
+            Ok(contents) => match Self::restore_snapshot(&contents, index.as_ref(), &mut filters) {
+                Ok(seq) => Some(seq),
+                Err(e) => {
+                    warn!("Ignoring unreadable index snapshot {:?}: {}", path, e);
+                    None
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+
+        let Some(seq) = snapshot else {
+            let count = self.rebuild(&mut filters, &|_| Ok(())).await?;
+            info!("Rebuilt index from {} stored vectors", count);
+            self.index_loaded.store(true, Ordering::Release);
+            return Ok(());
+        };
+
+        let restored = match self.restore_extra(seq).await {
+            Ok(restored) => restored,
+            Err(e) => {
+                warn!("Ignoring unreadable secondary index snapshot: {}", e);
+                false
+            }
+        };
+        if !restored {
+            self.named_indexes.write().await.clear();
+            self.sparse_index.clear();
+            self.open_collection_indexes().await?;
+            let mut after = None;
+            loop {
+                let page = self
+                    .scroll(None, after.as_deref(), REBUILD_PAGE_SIZE)
+                    .await?;
+                for vector in &page.vectors {
+                    self.index_extra(vector).await?;
+                }
+                match page.next {
+                    Some(next) => after = Some(next),
+                    None => break,
+                }
+            }
+        }
+
+        // Replaying the latest stored state of each touched id is idempotent,
+        // whatever order the entries came in
+        let entries = self.storage.wal_since(seq).await?;
+        let touched: HashSet<&str> = entries.iter().map(|entry| entry.id.as_str()).collect();
+        for id in &touched {
+            match self.storage.get_vector(id).await? {
+                Some(vector) => {
+                    index.add_vector(&vector.id, &vector.data)?;
+                    self.index_extra(&vector).await?;
+                    filters.insert(&vector);
+                }
+                None => {
+                    index.remove_vector(id)?;
+                    self.unindex_extra(id).await?;
+                    filters.remove(id);
+                }
+            }
+        }
+        info!(
+            "Loaded index snapshot with {} vectors and replayed {} WAL entries",
+            index.size(),
+            entries.len()
+        );
+        self.index_loaded.store(true, Ordering::Release);
+
+        Ok(())
+    }
+
+    // Whether `load_index` has completed, so searches see every vector
+    pub fn index_loaded(&self) -> bool {
+        self.index_loaded.load(Ordering::Acquire)
+    }
+
+    // Fails when storage can't be read, e.g. because its file is corrupt
+    pub async fn check_storage(&self) -> Result<()> {
+        self.storage.wal_head().await?;
+        Ok(())
+    }
+
+    // Reads every stored vector back, reporting the ones that are corrupt
+    pub async fn verify(&self) -> Result<VerifyReport> {
+        self.storage.verify().await
+    }
+
+    // Puts a good copy of a corrupt vector, e.g. from a replica, in its
+    // place and indexes it again
+    pub async fn repair_vector(&self, vector: &Vector) -> Result<()> {
+        let _write = self.write_lock.lock().await;
+        self.storage.repair_vector(vector).await?;
+        let seq = self.storage.wal_head().await?;
+        self.index.add_vector(&vector.id, &vector.data)?;
+        self.index_extra(vector).await?;
+        self.filters.write().await.insert(vector);
+        self.publish(seq, ChangeKind::Update, vector, vector.hlc);
+        Ok(())
+    }
+
+    pub async fn backup(&self, backup_path: &str) -> Result<()> {
+        self.storage.backup(backup_path).await?;
+        Ok(())
+    }
+
+    // Every stored vector when `since` is None, otherwise what changed after
+    // that WAL seq. From then on the WAL is kept past the returned seq, so
+    // the next call can pick up where this one stopped.
+    pub async fn export_changes(&self, since: Option<u64>) -> Result<ChangeSet> {
+        let _write = self.write_lock.lock().await;
+        let seq = self.storage.wal_head().await?;
+
+        let changes = match since {
+            None => ChangeSet {
+                seq,
+                upserts: self.storage.list_vectors().await?,
+                deletes: Vec::new(),
+            },
+            Some(since) if since > seq => {
+                return Err(anyhow!(
+                    "Seq {} is ahead of this database's WAL ({})",
+                    since,
+                    seq
+                ));
+            }
+            Some(since) => {
+                let entries = self.storage.wal_since(since).await?;
+                if since < seq && entries.first().map(|entry| entry.seq) != Some(since + 1) {
+                    return Err(anyhow!(
+                        "WAL entries after seq {} were truncated; take a full backup",
+                        since
+                    ));
+                }
+
+                // Only the last operation on each id matters
+                let mut latest = HashMap::new();
+                for entry in entries {
+                    latest.insert(entry.id, entry.op);
+                }
+                let mut changes = ChangeSet {
+                    seq,
+                    ..Default::default()
+                };
+                for (id, op) in latest {
+                    match (op, self.storage.get_vector(&id).await?) {
+                        (WalOp::Upsert, Some(vector)) => changes.upserts.push(vector),
+                        _ => changes.deletes.push(id),
+                    }
+                }
+                changes
+            }
+        };
+
+        self.storage.retain_wal_after(seq).await?;
+        Ok(changes)
+    }
+
+    // Up to `limit` changes logged after WAL seq `since`, oldest first. None
+    // when the feed can't resume from `since`: some of the changes after it
+    // were truncated, or it's past the head (e.g. the store was restored
+    // from an older backup). The consumer then has to start over from a full
+    // export.
+    pub async fn changes_since(
+        &self,
+        since: u64,
+        limit: usize,
+    ) -> Result<Option<Vec<ChangeEvent>>> {
+        let head = self.storage.wal_head().await?;
+        if since > head {
+            return Ok(None);
+        }
+        let entries = self.storage.wal_page(since, limit).await?;
+        if since < head && limit > 0 && entries.first().map(|entry| entry.seq) != Some(since + 1) {
+            return Ok(None);
+        }
+        Ok(Some(entries.into_iter().map(change_event).collect()))
+    }
+
+    // WAL seq of the last write
+    pub async fn wal_head(&self) -> Result<u64> {
+        self.storage.wal_head().await
+    }
+
+    // What up to `limit` WAL entries after `since` changed, with vectors as
+    // they are now, for a follower to apply. The returned seq is the last
+    // entry covered. None when `since` is ahead of the WAL or entries after
+    // it were truncated.
+    pub async fn changes_page(&self, since: u64, limit: usize) -> Result<Option<ChangeSet>> {
+        let Some(events) = self.changes_since(since, limit).await? else {
+            return Ok(None);
+        };
+        let mut changes = ChangeSet {
+            seq: events.last().map_or(since, |event| event.seq),
+            ..Default::default()
+        };
+        let mut seen = HashSet::new();
+        for event in events.into_iter().rev() {
+            if !seen.insert(event.id.clone()) {
+                continue;
+            }
+            match self.storage.get_vector(&event.id).await? {
+                Some(vector) if event.kind != ChangeKind::Delete => changes.upserts.push(vector),
+                _ => changes.deletes.push(event.id),
+            }
+        }
+        Ok(Some(changes))
+    }
+
+    // Applies changes replicated from another node as they are: no plugins,
+    // dedup, or id and version assignment, and read-only databases take them.
+    // Records failing this node's signature checks are dropped, keeping
+    // whatever it holds for their ids, and writes to ids stored here already
+    // go through the conflict resolver.
+    pub async fn apply_changes(&self, changes: &ChangeSet) -> Result<()> {
+        let signed = changes.upserts.iter().filter(|vector| {
+            match validation::validate_signature(vector, &self.limits) {
+                Ok(()) => true,
+                Err(e) => {
+                    warn!("Dropped a replicated write: {}", e);
+                    false
+                }
+            }
+        });
+        let _write = self.write_lock.lock().await;
+        let mut upserts = Vec::new();
+        let mut conflicts = Vec::new();
+        for vector in signed {
+            if let Err(e) = self.clock.observe(vector.hlc) {
+                warn!("Dropped a replicated write of '{}': {}", vector.id, e);
+                continue;
+            }
+            let Some(local) = self.storage.get_vector(&vector.id).await? else {
+                upserts.push(vector.clone());
+                continue;
+            };
+            match self.resolver.resolve(&local, vector) {
+                Resolution::TakeRemote => upserts.push(vector.clone()),
+                Resolution::KeepLocal => {}
+                Resolution::Conflict => conflicts.push(Conflict {
+                    id: vector.id.clone(),
+                    local,
+                    remote: vector.clone(),
+                    detected_at: hlc::physical_millis(self.clock.now()?) / 1000,
+                }),
+            }
+        }
+        if !conflicts.is_empty() {
+            warn!(
+                "{} replicated writes conflict with local ones; see /admin/conflicts",
+                conflicts.len()
+            );
+            self.record_conflicts(conflicts).await?;
+        }
+        self.apply_writes(&upserts, &changes.deletes).await
+    }
+
+    // Writes `upserts` and `deletes` as they are, with the write lock held,
+    // and brings the indexes along
+    async fn apply_writes(&self, upserts: &[Vector], deletes: &[String]) -> Result<()> {
+        let mut existed = Vec::with_capacity(upserts.len());
+        for vector in upserts {
+            existed.push(self.storage.get_vector(&vector.id).await?.is_some());
+        }
+        let mut deleted = Vec::new();
+        for id in deletes {
+            if let Some(vector) = self.storage.get_vector(id).await? {
+                deleted.push(vector);
+            }
+        }
+        let hlc = self.clock.now()?;
+        self.storage.write_batch(upserts, deletes, hlc).await?;
+
+        // Only deletes of stored vectors reach the WAL
+        let logged = (upserts.len() + deleted.len()) as u64;
+        let first_seq = self.storage.wal_head().await? + 1 - logged;
+        let mut filters = self.filters.write().await;
+        let mut seqs = first_seq..;
+        for ((vector, existed), seq) in upserts.iter().zip(existed).zip(&mut seqs) {
+            self.index.add_vector(&vector.id, &vector.data)?;
+            self.index_extra(vector).await?;
+            filters.insert(vector);
+            let kind = if existed {
+                ChangeKind::Update
+            } else {
+                ChangeKind::Insert
+            };
+            self.publish(seq, kind, vector, vector.hlc);
+        }
+        for (vector, seq) in deleted.iter().zip(&mut seqs) {
+            self.index.remove_vector(&vector.id)?;
+            self.unindex_extra(&vector.id).await?;
+            filters.remove(&vector.id);
+            self.publish(seq, ChangeKind::Delete, vector, hlc);
+        }
+        Ok(())
+    }
+
+    // The id this database stamps its writes with, made up on first use
+    pub async fn replica_id(&self) -> Result<String> {
+        self.replica_id
+            .get_or_try_init(|| async {
+                if let Some(id) = self.storage.get_setting(REPLICA_ID_SETTING).await? {
+                    return Ok(id);
+                }
+                let id = uuid::Uuid::new_v4().to_string();
+                self.storage.put_setting(REPLICA_ID_SETTING, &id).await?;
+                Ok(id)
+            })
+            .await
+            .cloned()
+    }
+
+    // Replicated writes that conflicted with local ones and haven't been
+    // resolved, oldest first
+    pub async fn conflicts(&self) -> Result<Vec<Conflict>> {
+        match self.storage.get_setting(CONFLICTS_SETTING).await? {
+            Some(json) => Ok(serde_json::from_str(&json)?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    // A later conflict on an id replaces the earlier one
+    async fn record_conflicts(&self, new: Vec<Conflict>) -> Result<()> {
+        let _conflicts = self.conflicts_lock.lock().await;
+        let mut conflicts = self.conflicts().await?;
+        for conflict in new {
+            conflicts.retain(|known| known.id != conflict.id);
+            conflicts.push(conflict);
+        }
+        let excess = conflicts.len().saturating_sub(MAX_CONFLICTS);
+        conflicts.drain(..excess);
+        self.storage
+            .put_setting(CONFLICTS_SETTING, &serde_json::to_string(&conflicts)?)
+            .await
+    }
+
+    // Settles the conflict on `id` by writing the chosen side as a new write
+    // that follows both, so it replicates over either. False when there's
+    // no conflict on `id`.
+    pub async fn resolve_conflict(&self, id: &str, keep: ConflictSide) -> Result<bool> {
+        self.check_writable()?;
+        let replica = self.replica_id().await?;
+        let _write = self.write_lock.lock().await;
+        let _conflicts = self.conflicts_lock.lock().await;
+        let mut conflicts = self.conflicts().await?;
+        let Some(position) = conflicts.iter().position(|conflict| conflict.id == id) else {
+            return Ok(false);
+        };
+        let conflict = conflicts.remove(position);
+        let stored = self.storage.get_vector(id).await?;
+
+        let mut clock = merge_clocks(&conflict.local.clock, &conflict.remote.clock);
+        let mut version = conflict.local.version.max(conflict.remote.version);
+        // The remote side was observed when the conflict was recorded
+        self.clock
+            .advance(conflict.local.hlc.max(conflict.remote.hlc));
+        if let Some(stored) = &stored {
+            clock = merge_clocks(&clock, &stored.clock);
+            version = version.max(stored.version);
+            self.clock.advance(stored.hlc);
+        }
+        let mut winner = match keep {
+            ConflictSide::Local => conflict.local,
+            ConflictSide::Remote => conflict.remote,
+        };
+        stamp(&mut winner, &replica, self.clock.now()?, clock);
+        winner.version = version + 1;
+        self.apply_writes(&[winner], &[]).await?;
+        self.storage
+            .put_setting(CONFLICTS_SETTING, &serde_json::to_string(&conflicts)?)
+            .await?;
+        Ok(true)
+    }
+
+    pub async fn create_snapshot(&self, collection: &str, name: &str) -> Result<SnapshotInfo> {
+        if name.is_empty() {
+            return Err(anyhow!("Snapshot name must not be empty"));
+        }
+        self.storage.create_snapshot(collection, name).await
+    }
+
+    pub async fn get_snapshot(&self, collection: &str, name: &str) -> Result<Option<SnapshotInfo>> {
+        self.storage.get_snapshot(collection, name).await
+    }
+
+    pub async fn list_snapshots(&self, collection: &str) -> Result<Vec<SnapshotInfo>> {
+        self.storage.list_snapshots(collection).await
+    }
+
+    pub async fn delete_snapshot(&self, collection: &str, name: &str) -> Result<bool> {
+        self.storage.delete_snapshot(collection, name).await
+    }
+
+    // Up to `limit` vectors in id order after the id `after`, from
+    // `collection` or all of them
+    pub async fn scroll(
+        &self,
+        collection: Option<&str>,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<ScrollPage> {
+        let collection = match collection {
+            Some(name) => Some(self.resolve_collection(name).await),
+            None => None,
+        };
+        let vectors = self
+            .storage
+            .scan_collection(collection.as_deref(), after, limit)
+            .await?;
+        let next = match vectors.last() {
+            Some(last) if vectors.len() == limit => Some(last.id.clone()),
+            _ => None,
+        };
+        Ok(ScrollPage { vectors, next })
+    }
+
+    // Up to `n` vectors picked uniformly at random from `collection`, or
+    // from all of them, by reservoir sampling a page-by-page scan of
+    // storage. The same seed picks the same sample of unchanged vectors.
+    pub async fn sample(
+        &self,
+        collection: Option<&str>,
+        n: usize,
+        seed: Option<u64>,
+    ) -> Result<Vec<Vector>> {
+        let collection = match collection {
+            Some(name) => Some(self.resolve_collection(name).await),
+            None => None,
+        };
+        let collection = collection.as_deref();
+        let mut rng = SampleRng::new(seed);
+        let mut sample = Vec::with_capacity(n.min(REBUILD_PAGE_SIZE));
+        let mut seen = 0;
+        let mut after = None;
+        loop {
+            let page = self
+                .storage
+                .scan_collection(collection, after.as_deref(), REBUILD_PAGE_SIZE)
+                .await?;
+            let last_page = page.len() < REBUILD_PAGE_SIZE;
+            after = page.last().map(|vector| vector.id.clone());
+            for vector in page {
+                seen += 1;
+                if sample.len() < n {
+                    sample.push(vector);
+                } else {
+                    // Kept with probability n / seen, in place of a random
+                    // one already kept
+                    let slot = rng.below(seen) as usize;
+                    if slot < n {
+                        sample[slot] = vector;
+                    }
+                }
+            }
+            if last_page {
+                return Ok(sample);
+            }
+        }
+    }
+
+    // Clusters the collection with k-means trained on a sample of it, then
+    // writes each vector's cluster into its metadata and keeps the
+    // centroids. Vectors already in the right cluster aren't rewritten.
+    // Assignments are derived data, not client writes: they keep the
+    // record's version and hlc and skip quotas, history and plugins. A
+    // vector written to since its page was scanned is left for the next
+    // run. `progress` hears how many vectors have been assigned, and stops
+    // the run by failing.
+    pub async fn cluster(
+        &self,
+        collection: &str,
+        options: &ClusterOptions,
+        progress: impl Fn(usize) -> Result<()>,
+    ) -> Result<Clustering> {
+        self.check_writable()?;
+        let collection = &self.resolve_collection(collection).await;
+        let metric = self.collection_metric(collection).await;
+        let sample = self
+            .sample(Some(collection), CLUSTER_SAMPLE_SIZE, options.seed)
+            .await?;
+        let train_options = options.clone();
+        let centroids = spawn_blocking(move || {
+            let data: Vec<&[f32]> = sample.iter().map(|vector| vector.data.as_slice()).collect();
+            clustering::train(&data, &train_options, metric.index_metric())
+        })
+        .await??;
+        progress(0)?;
+
+        let centroids = Arc::new(centroids);
+        let dimensions = centroids[0].len();
+        let mut sizes = vec![0u64; centroids.len()];
+        let mut assigned = 0;
+        let mut after = None;
+        loop {
+            let page = self
+                .storage
+                .scan_collection(Some(collection), after.as_deref(), REBUILD_PAGE_SIZE)
+                .await?;
+            let last_page = page.len() < REBUILD_PAGE_SIZE;
+            after = page.last().map(|vector| vector.id.clone());
+            if let Some(vector) = page.iter().find(|vector| vector.dimensions() != dimensions) {
+                return Err(anyhow!(
+                    "Vector {} has {} dimensions, not the {} clustered on",
+                    vector.id,
+                    vector.dimensions(),
+                    dimensions
+                ));
+            }
+            let centroids = Arc::clone(&centroids);
+            let (page, clusters) = spawn_blocking(move || {
+                let clusters: Vec<usize> = page
+                    .par_iter()
+                    .map(|vector| {
+                        clustering::nearest(&centroids, &vector.data, metric.index_metric())
+                    })
+                    .collect();
+                (page, clusters)
+            })
+            .await?;
+
+            assigned += page.len();
+            let mut changed = Vec::new();
+            for (mut vector, cluster) in page.into_iter().zip(clusters) {
+                sizes[cluster] += 1;
+                let value = cluster.to_string();
+                let metadata = vector.metadata.get_or_insert_with(HashMap::new);
+                if metadata.get(&options.metadata_key) != Some(&value) {
+                    metadata.insert(options.metadata_key.clone(), value);
+                    changed.push(vector);
+                }
+            }
+            if !changed.is_empty() {
+                let _write = self.write_lock.lock().await;
+                let mut unchanged = Vec::with_capacity(changed.len());
+                for vector in changed {
+                    let stored = self.storage.get_vector(&vector.id).await?;
+                    if stored.is_some_and(|stored| {
+                        (stored.version, stored.hlc) == (vector.version, vector.hlc)
+                    }) {
+                        unchanged.push(vector);
+                    }
+                }
+                self.apply_writes(&unchanged, &[]).await?;
+            }
+            progress(assigned)?;
+            if last_page {
+                break;
+            }
+        }
+
+        let clustering = Clustering {
+            collection: collection.to_string(),
+            metric,
+            metadata_key: options.metadata_key.clone(),
+            centroids: Arc::unwrap_or_clone(centroids),
+            sizes,
+            created_at: time::SystemTime::now()
+                .duration_since(time::UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or_default(),
+        };
+        self.storage
+            .put_setting(
+                &format!("{}{}", CLUSTERING_SETTING_PREFIX, collection),
+                &serde_json::to_string(&clustering)?,
+            )
+            .await?;
+        info!(
+            "Clustered {} vectors of {} into {} clusters",
+            assigned, collection, options.k
+        );
+        Ok(clustering)
+    }
+
+    // The collection's last clustering, if it's been clustered
+    pub async fn clustering(&self, collection: &str) -> Result<Option<Clustering>> {
+        let collection = self.resolve_collection(collection).await;
+        let key = format!("{}{}", CLUSTERING_SETTING_PREFIX, collection);
+        match self.storage.get_setting(&key).await? {
+            Some(json) => Ok(Some(serde_json::from_str(&json)?)),
+            None => Ok(None),
+        }
+    }
+
+    // Every stored vector, or just those in `collection`
+    pub async fn list_vectors(&self, collection: Option<&str>) -> Result<Vec<Vector>> {
+        let mut vectors = self.storage.list_vectors().await?;
+        if let Some(collection) = collection {
+            vectors.retain(|vector| vector.collection.as_deref() == Some(collection));
+        }
+        Ok(vectors)
+    }
+
+    pub async fn export_snapshot(&self, collection: &str, name: &str) -> Result<Vec<Vector>> {
+        self.storage.get_snapshot_vectors(collection, name).await
+    }
+
+    pub async fn search_snapshot(
+        &self,
+        collection: &str,
+        name: &str,
+        query: &[f32],
+        k: usize,
+        threshold: f32,
+    ) -> Result<Vec<SearchResult>> {
+        validation::validate_query(query)?;
+        let vectors = self.storage.get_snapshot_vectors(collection, name).await?;
+
+        // Snapshots are not indexed, so build a throwaway flat index over
+        // them, scoring like the collection's own index
+        let metric = self.collection_metric(collection).await;
+        let index = FlatIndex::new().with_metric(metric.index_metric());
+        for vector in &vectors {
+            index.add_vector(&vector.id, &vector.data)?;
+        }
+
+        let mut vectors: HashMap<String, Vector> = vectors
+            .into_iter()
+            .map(|vector| (vector.id.clone(), vector))
+            .collect();
+
+        let results = index
+            .search(query, index.size())?
+            .into_iter()
+            .filter(|candidate| candidate.score >= threshold)
+            .filter_map(|candidate| {
+                let vector = vectors.remove(&candidate.id)?;
+                Some((
+                    SearchResult {
+                        id: candidate.id,
+                        score: candidate.score,
+                        metadata: vector.metadata,
+                        vector: None,
+                    },
+                    vector.created_at,
+                ))
+            })
+            .collect();
+
+        Ok(self.rank_results(results, k))
+    }
+
+    // Copies a snapshot into another collection. Vector ids are global, so the
+    // copies are assigned fresh ids.
+    pub async fn clone_snapshot(
+        &self,
+        collection: &str,
+        name: &str,
+        target: &str,
+    ) -> Result<Vec<String>> {
+        if self.storage.get_snapshot(collection, name).await?.is_none() {
+            return Err(anyhow!(
+                "Snapshot '{}' not found for collection '{}'",
+                name,
+                collection
+            ));
+        }
+
+        let vectors = self
+            .storage
+            .get_snapshot_vectors(collection, name)
+            .await?
+            .into_iter()
+            .map(|vector| {
+                let mut copy = Vector::new(vector.data).with_collection(target.to_string());
+                copy.metadata = vector.metadata;
+                copy.vectors = vector.vectors;
+                copy.sparse = vector.sparse;
+                copy.model = vector.model;
+                copy
+            })
+            .collect();
+
+        self.insert_vectors(vectors).await
+    }
+
+    // Whether `source` can be cloned into `target`: a fresh collection name,
+    // not an alias
+    pub async fn check_clone(&self, source: &str, target: &str) -> Result<()> {
+        let invalid = |reason: &str| ValidationError::InvalidCloneTarget {
+            target: target.to_string(),
+            reason: reason.to_string(),
+        };
+        if target.is_empty() || target == self.resolve_collection(source).await {
+            return Err(invalid("it must be a collection other than the source").into());
+        }
+        if self.collection_aliases.read().await.contains_key(target) {
+            return Err(invalid("it's an alias").into());
+        }
+        if self.storage.collection_stats(target).await?.is_some() {
+            return Err(invalid("it already has vectors").into());
+        }
+        Ok(())
+    }
+
+    // Copies every vector of `source` into `target` a page at a time, under
+    // fresh ids since ids are global, and gives `target` the source's
+    // distance metric. `progress` hears how many have been copied, and
+    // stops the clone by failing; what was copied by then stays. Returns how
+    // many vectors were copied.
+    pub async fn clone_collection(
+        &self,
+        source: &str,
+        target: &str,
+        options: &CloneOptions,
+        progress: impl Fn(usize) -> Result<()>,
+    ) -> Result<usize> {
+        self.check_writable()?;
+        self.check_clone(source, target).await?;
+        let source = self.resolve_collection(source).await;
+        let metric = self.collection_metric(&source).await;
+        if metric != self.collection_metric(target).await {
+            self.set_collection_metric(target, metric).await?;
+        }
+
+        let mut copied = 0;
+        let mut after = None;
+        loop {
+            let page = self
+                .storage
+                .scan_collection(Some(&source), after.as_deref(), REBUILD_PAGE_SIZE)
+                .await?;
+            let last_page = page.len() < REBUILD_PAGE_SIZE;
+            after = page.last().map(|vector| vector.id.clone());
+            let copies: Vec<Vector> = page
+                .into_iter()
+                .map(|vector| {
+                    let mut copy = Vector::new(vector.data).with_collection(target.to_string());
+                    copy.metadata = vector.metadata;
+                    copy.vectors = vector.vectors;
+                    copy.sparse = vector.sparse;
+                    copy.model = vector.model;
+                    if options.normalize {
+                        copy.normalize();
+                    }
+                    if let Some(dtype) = options.dtype {
+                        dtype.round(&mut copy.data);
+                        copy.dtype = dtype;
+                    }
+                    copy
+                })
+                .collect();
+            copied += copies.len();
+            if !copies.is_empty() {
+                self.import_vectors(copies).await?;
+            }
+            progress(copied)?;
+            if last_page {
+                break;
+            }
+        }
+        info!("Cloned {} vectors of {} into {}", copied, source, target);
+        Ok(copied)
+    }
+
+    // Diffs a snapshot against another snapshot of the same collection, or
+    // against the live collection when `against` is None.
+    pub async fn diff_snapshot(
+        &self,
+        collection: &str,
+        name: &str,
+        against: Option<&str>,
+    ) -> Result<SnapshotDiff> {
+        let base = self.storage.get_snapshot_vectors(collection, name).await?;
+        let other = match against {
+            Some(other) => self.storage.get_snapshot_vectors(collection, other).await?,
+            None => self.storage.get_vectors_in_collection(collection).await?,
+        };
+
+        let base: HashMap<String, Vector> = base.into_iter().map(|v| (v.id.clone(), v)).collect();
+        let mut diff = SnapshotDiff::default();
+
+        for vector in &other {
+            match base.get(&vector.id) {
+                None => diff.added.push(vector.id.clone()),
+                Some(old)
+                    if old.data != vector.data
+                        || old.vectors != vector.vectors
+                        || old.sparse != vector.sparse
+                        || old.metadata != vector.metadata =>
+                {
+                    diff.changed.push(vector.id.clone())
+                }
+                Some(_) => {}
+            }
+        }
+
+        let other_ids: std::collections::HashSet<&str> =
+            other.iter().map(|v| v.id.as_str()).collect();
+        diff.removed = base
+            .into_keys()
+            .filter(|id| !other_ids.contains(id.as_str()))
+            .collect();
+
+        diff.added.sort();
+        diff.removed.sort();
+        diff.changed.sort();
+
+        Ok(diff)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{QuotaExceeded, QuotaLimit};
+
+    async fn open(dir: &std::path::Path) -> VectorDatabase {
+        let db = VectorDatabase::new(dir.to_str().unwrap()).await.unwrap();
+        db.load_index().await.unwrap();
+        db
+    }
+
+    async fn top_named(db: &VectorDatabase, name: &str, query: &[f32]) -> Option<String> {
+        let options = SearchOptions {
+            vector_name: Some(name.to_string()),
+            ..Default::default()
+        };
+        let results = db
+            .search_with(query, 1, 0.5, &SearchFilter::default(), &options)
+            .await
+            .unwrap();
+        results.into_iter().next().map(|r| r.id)
+    }
+
+    async fn top_id(db: &VectorDatabase, query: &[f32]) -> Option<String> {
+        let results = db.search(query, 1, 0.5).await.unwrap();
+        results.into_iter().next().map(|r| r.id)
+    }
+
+    #[test]
+    fn test_score_rows_matches_metric() {
+        // Enough rows for the GPU to take them, when there is one
+        let data: Vec<Vec<f32>> = (0..10_000)
+            .map(|i| vec![(i % 13) as f32 - 6.0, (i % 7) as f32, 1.0])
+            .collect();
+        let rows: Vec<(&str, &[f32])> = data.iter().map(|d| ("", d.as_slice())).collect();
+        let query = [0.5, -2.0, 1.0];
+        for metric in [
+            DistanceMetric::Cosine,
+            DistanceMetric::Euclidean,
+            DistanceMetric::DotProduct,
+        ] {
+            let scores = score_rows(&query, &rows, metric, &CancellationToken::new()).unwrap();
+            for ((_, row), score) in rows.iter().zip(scores) {
+                assert!((metric.score(&query, row).unwrap() - score).abs() < 1e-4);
+            }
+        }
+        let cancel = CancellationToken::new();
+        assert!(score_rows(&query, &[("", &[1.0])], DistanceMetric::Cosine, &cancel).is_err());
+        cancel.cancel();
+        let err = score_rows(&query, &rows, DistanceMetric::Cosine, &cancel).unwrap_err();
+        assert!(err.is::<skypier_index::Cancelled>());
+    }
+
+    #[tokio::test]
+    async fn test_index_recovers_from_snapshot_and_wal() {
+        let temp_dir = tempfile::tempdir().unwrap();
+
+        {
+            let db = open(temp_dir.path()).await;
+            db.insert_vectors(vec![
+                Vector::with_id("a".to_string(), vec![1.0, 0.0, 0.0]),
+                Vector::with_id("b".to_string(), vec![0.0, 1.0, 0.0])
+                    .with_collection("docs".to_string()),
+            ])
+            .await
+            .unwrap();
+            assert_eq!(db.snapshot_index().await.unwrap(), 2);
+
+            // Only in the WAL
+            db.insert_vectors(vec![Vector::with_id("c".to_string(), vec![0.0, 0.0, 1.0])
+                .with_collection("docs".to_string())])
+                .await
+                .unwrap();
+            db.delete_vector("a").await.unwrap();
+        }
+
+        let db = open(temp_dir.path()).await;
+        assert_eq!(db.index.size(), 2);
+        assert!(db.warm_up().await.unwrap() >= 2 * 3 * 4);
+        assert_eq!(top_id(&db, &[0.0, 0.1, 1.0]).await.as_deref(), Some("c"));
+        assert_eq!(top_id(&db, &[1.0, 0.0, 0.0]).await, None);
+
+        // Filters are restored along with the index
+        let mut ids: Vec<String> = db
+            .search_in_collection("docs", &[1.0, 0.1, 0.1], 10, 0.0)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| r.id)
+            .collect();
+        ids.sort();
+        assert_eq!(ids, vec!["b", "c"]);
+
+        // The snapshot taken on shutdown covers the whole WAL
+        db.shutdown().await.unwrap();
+        assert!(db.storage.wal_since(0).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_created_at_breaks_ties_past_the_fetch() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = VectorDatabase::new(dir.path().to_str().unwrap())
+            .await
+            .unwrap()
+            .with_tie_break(TieBreak::CreatedAt);
+        db.load_index().await.unwrap();
+        // Ten equal rows, the later ids made first
+        let vectors = (0..10u64)
+            .map(|i| {
+                let mut vector = Vector::with_id(format!("v{}", i), vec![1.0, 0.0]);
+                vector.created_at = 1_000 - i;
+                vector
+            })
+            .collect();
+        db.insert_vectors(vectors).await.unwrap();
+
+        let ids: Vec<String> = db
+            .search(&[1.0, 0.0], 2, 0.0)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|result| result.id)
+            .collect();
+        assert_eq!(ids, vec!["v9", "v8"]);
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_database_stays_off_disk() {
+        // Built like `in_memory`, but with a data dir to watch
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = VectorDatabase::from_storage(
+            Arc::new(skypier_storage::InMemoryStorage::new()),
+            temp_dir.path().to_str().unwrap(),
+        )
+        .unwrap();
+        db.load_index().await.unwrap();
+        db.insert_vectors(vec![
+            Vector::with_id("a".to_string(), vec![1.0, 0.0]),
+            Vector::with_id("b".to_string(), vec![0.0, 1.0]),
+        ])
+        .await
+        .unwrap();
+        assert_eq!(top_id(&db, &[0.1, 1.0]).await.as_deref(), Some("b"));
+
+        db.snapshot_index().await.unwrap();
+        db.shutdown().await.unwrap();
+        assert_eq!(std::fs::read_dir(temp_dir.path()).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_index_rebuilt_without_usable_snapshot() {
+        let temp_dir = tempfile::tempdir().unwrap();
+
+        {
+            let db = open(temp_dir.path()).await;
+            db.insert_vectors(vec![Vector::with_id("a".to_string(), vec![1.0, 0.0])])
+                .await
+                .unwrap();
+        }
+        let db = open(temp_dir.path()).await;
+        assert_eq!(top_id(&db, &[1.0, 0.1]).await.as_deref(), Some("a"));
+        drop(db);
+
+        std::fs::write(
+            temp_dir.path().join(INDEX_SNAPSHOT_FILE),
+            b"garbage!garbage",
+        )
+        .unwrap();
+        let db = open(temp_dir.path()).await;
+        assert_eq!(top_id(&db, &[1.0, 0.1]).await.as_deref(), Some("a"));
+    }
+
+    #[tokio::test]
+    async fn test_reindex_rebuilds_from_storage() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = open(temp_dir.path()).await;
+        db.insert_vectors(vec![
+            Vector::with_id("a".to_string(), vec![1.0, 0.0]),
+            Vector::with_id("b".to_string(), vec![0.0, 1.0]).with_collection("docs".to_string()),
+        ])
+        .await
+        .unwrap();
+
+        let indexed = std::sync::atomic::AtomicUsize::new(0);
+        let count = db
+            .reindex(|count| {
+                indexed.store(count, Ordering::Relaxed);
+                Ok(())
+            })
+            .await
+            .unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(indexed.load(Ordering::Relaxed), 2);
+        assert_eq!(top_id(&db, &[1.0, 0.1]).await.as_deref(), Some("a"));
+        let results = db
+            .search_in_collection("docs", &[1.0, 0.1], 1, 0.0)
+            .await
+            .unwrap();
+        assert_eq!(results[0].id, "b");
+
+        // Stopping partway loads the index back
+        let stopped = db.reindex(|_| Err(anyhow!("cancelled"))).await;
+        assert!(stopped.is_err());
+        assert_eq!(top_id(&db, &[1.0, 0.1]).await.as_deref(), Some("a"));
+    }
+
+    #[tokio::test]
+    async fn test_dedup_rejects_or_merges_near_duplicates() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = open(temp_dir.path()).await;
+        let chunk = |id: &str, data: Vec<f32>, key: &str, collection: &str| {
+            Vector::with_id(id.to_string(), data)
+                .with_collection(collection.to_string())
+                .with_metadata(HashMap::from([(key.to_string(), id.to_string())]))
+        };
+        db.insert_vectors(vec![chunk("a", vec![1.0, 0.0], "source", "docs")])
+            .await
+            .unwrap();
+
+        let reject = Dedup {
+            threshold: 0.99,
+            action: DedupAction::Reject,
+        };
+        db.set_collection_dedup("docs", Some(reject)).await.unwrap();
+        let err = db
+            .insert_vectors(vec![chunk("b", vec![1.0, 0.01], "source", "docs")])
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ValidationError>(),
+            Some(ValidationError::Duplicate { existing, .. }) if existing == "a"
+        ));
+        // Re-inserting the same id, or something far enough away, is fine
+        db.insert_vectors(vec![
+            chunk("a", vec![1.0, 0.01], "source", "docs"),
+            chunk("c", vec![0.0, 1.0], "source", "docs"),
+        ])
+        .await
+        .unwrap();
+        // Duplicates within a batch count too
+        let check = db
+            .check_vectors(&[
+                chunk("d", vec![-1.0, 0.0], "source", "docs"),
+                chunk("e", vec![-1.0, 0.01], "source", "docs"),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(check.errors.len(), 1);
+        assert_eq!(check.errors[0].id, "e");
+
+        let merge = Dedup {
+            threshold: 0.99,
+            action: DedupAction::Merge,
+        };
+        db.set_collection_dedup("docs", Some(merge)).await.unwrap();
+        let ids = db
+            .insert_vectors(vec![
+                chunk("b", vec![1.0, 0.0], "page", "docs"),
+                chunk("b2", vec![1.0, 0.0], "title", "docs"),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(ids, vec!["a"]);
+        assert!(db.get_vector("b").await.unwrap().is_none());
+        let merged = db.get_vector("a").await.unwrap().unwrap().metadata.unwrap();
+        assert_eq!(merged["source"], "a");
+        assert_eq!(merged["page"], "b");
+        assert_eq!(merged["title"], "b2");
+
+        // Other collections aren't checked, and settings survive a restart
+        db.insert_vectors(vec![chunk("x", vec![1.0, 0.0], "source", "other")])
+            .await
+            .unwrap();
+        drop(db);
+        let db = open(temp_dir.path()).await;
+        assert_eq!(db.collection_dedup("docs").await, Some(merge));
+    }
+
+    #[tokio::test]
+    async fn test_vectors_without_ids_get_them_from_the_collection_scheme() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = open(temp_dir.path()).await;
+        let unnamed = |collection: &str| Vector {
+            id: String::new(),
+            ..Vector::new(vec![1.0, 0.0]).with_collection(collection.to_string())
+        };
+        db.set_collection_id_scheme("tickets", Some(IdScheme::AutoIncrement))
+            .await
+            .unwrap();
+        db.set_collection_id_scheme("events", Some(IdScheme::Ulid))
+            .await
+            .unwrap();
+
+        let ids = db
+            .insert_vectors(vec![
+                unnamed("tickets"),
+                Vector::with_id("kept".to_string(), vec![0.0, 1.0])
+                    .with_collection("tickets".to_string()),
+                unnamed("tickets"),
+                unnamed("events"),
+                unnamed("events"),
+                unnamed("docs"),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(
+            ids[..3],
+            [sequence_id(1), "kept".to_string(), sequence_id(2)]
+        );
+        assert_eq!(ids[3].len(), 26);
+        assert!(ids[3] < ids[4]);
+        assert!(uuid::Uuid::parse_str(&ids[5]).is_ok());
+        assert!(db.get_vector(&sequence_id(2)).await.unwrap().is_some());
+
+        // Counters and schemes survive a restart
+        drop(db);
+        let db = open(temp_dir.path()).await;
+        assert_eq!(db.id_scheme(Some("tickets")).await, IdScheme::AutoIncrement);
+        let ids = db.insert_vectors(vec![unnamed("tickets")]).await.unwrap();
+        assert_eq!(ids, vec![sequence_id(3)]);
+
+        db.set_collection_id_scheme("tickets", None).await.unwrap();
+        assert_eq!(db.id_scheme(Some("tickets")).await, IdScheme::Uuid);
+    }
+
+    #[tokio::test]
+    async fn test_reinserts_keep_earlier_versions() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = open(temp_dir.path()).await;
+        db.set_collection_versions("docs", Some(2)).await.unwrap();
+        let doc = |id: &str, data: Vec<f32>, collection: &str| {
+            Vector::with_id(id.to_string(), data).with_collection(collection.to_string())
+        };
+
+        db.insert_vectors(vec![doc("a", vec![1.0, 0.0], "docs")])
+            .await
+            .unwrap();
+        db.insert_vectors(vec![
+            doc("a", vec![0.0, 1.0], "docs"),
+            doc("a", vec![-1.0, 0.0], "docs"),
+            doc("b", vec![1.0, 0.0], "notes"),
+        ])
+        .await
+        .unwrap();
+        db.insert_vectors(vec![
+            doc("a", vec![0.0, -1.0], "docs"),
+            doc("b", vec![0.0, 1.0], "notes"),
+        ])
+        .await
+        .unwrap();
+
+        // Only two earlier versions are kept, and the search index only has
+        // the latest
+        let versions = db.get_versions("a").await.unwrap().unwrap();
+        let numbers: Vec<u64> = versions.iter().map(|vector| vector.version).collect();
+        assert_eq!(numbers, vec![4, 3, 2]);
+        assert_eq!(versions[2].data, vec![0.0, 1.0]);
+        let results = db.search(&[-1.0, 0.0], 1, 0.9).await.unwrap();
+        assert!(results.is_empty());
+
+        // Collections that don't keep versions still count them
+        let b = db.get_versions("b").await.unwrap().unwrap();
+        assert_eq!((b.len(), b[0].version), (1, 2));
+        assert!(db.get_versions("missing").await.unwrap().is_none());
+
+        db.delete_vector("a").await.unwrap();
+        db.insert_vectors(vec![doc("a", vec![1.0, 0.0], "docs")])
+            .await
+            .unwrap();
+        let versions = db.get_versions("a").await.unwrap().unwrap();
+        assert_eq!((versions.len(), versions[0].version), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_model_tags_keep_searches_within_a_model() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = open(temp_dir.path()).await;
+        db.set_expected_model("docs", Some("small".to_string()))
+            .await
+            .unwrap();
+        let doc = |id: &str, model: &str| {
+            Vector::with_id(id.to_string(), vec![1.0, 0.0])
+                .with_collection("docs".to_string())
+                .with_model(model.to_string())
+        };
+
+        let err = db
+            .insert_vectors(vec![doc("a", "large")])
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ValidationError>(),
+            Some(ValidationError::ModelMismatch { actual, .. }) if actual == "large"
+        ));
+        let untagged =
+            Vector::with_id("b".to_string(), vec![1.0, 0.0]).with_collection("docs".to_string());
+        assert_eq!(db.check_vectors(&[untagged]).await.unwrap().errors.len(), 1);
+        db.insert_vectors(vec![
+            doc("a", "small"),
+            Vector::with_id("x".to_string(), vec![1.0, 0.0]).with_model("large".to_string()),
+        ])
+        .await
+        .unwrap();
+
+        let docs = SearchFilter {
+            collection: Some("docs".to_string()),
+            ..Default::default()
+        };
+        let with_model = |model: &str| SearchOptions {
+            model: Some(model.to_string()),
+            ..Default::default()
+        };
+        let err = db
+            .search_with(&[1.0, 0.0], 5, 0.0, &docs, &SearchOptions::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ValidationError>(),
+            Some(ValidationError::QueryModelMismatch { .. })
+        ));
+        let results = db
+            .search_with(&[1.0, 0.0], 5, 0.0, &docs, &with_model("small"))
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 1);
+
+        // Unscoped searches leave out vectors from other models
+        let all = SearchFilter::default();
+        let ids = |results: Vec<SearchResult>| -> Vec<String> {
+            results.into_iter().map(|result| result.id).collect()
+        };
+        let results = db
+            .search_with(&[1.0, 0.0], 5, 0.0, &all, &with_model("large"))
+            .await
+            .unwrap();
+        assert_eq!(ids(results), vec!["x"]);
+        assert_eq!(db.search(&[1.0, 0.0], 5, 0.0).await.unwrap().len(), 2);
+
+        // Closer hits from another model don't crowd out the k asked for
+        let near = (0..8)
+            .map(|i| {
+                Vector::with_id(format!("n{i}"), vec![1.0, 0.01 * i as f32])
+                    .with_model("small".to_string())
+            })
+            .collect();
+        db.insert_vectors(near).await.unwrap();
+        db.insert_vectors(vec![
+            Vector::with_id("y".to_string(), vec![1.0, 1.0]).with_model("large".to_string())
+        ])
+        .await
+        .unwrap();
+        let results = db
+            .search_with(&[1.0, 0.0], 2, 0.0, &all, &with_model("large"))
+            .await
+            .unwrap();
+        assert_eq!(ids(results), vec!["x", "y"]);
+    }
+
+    #[tokio::test]
+    async fn test_bulk_load_boots_from_snapshot() {
+        let temp_dir = tempfile::tempdir().unwrap();
+
+        {
+            let db = open(temp_dir.path()).await;
+            let count = db
+                .bulk_load(vec![
+                    Vector::with_id("a".to_string(), vec![1.0, 0.0]),
+                    Vector::with_id("b".to_string(), vec![0.0, 1.0]),
+                ])
+                .await
+                .unwrap();
+            assert_eq!(count, 2);
+            assert!(db.storage.wal_since(0).await.unwrap().is_empty());
+            assert!(db.bulk_load(vec![]).await.is_err());
+        }
+
+        let db = open(temp_dir.path()).await;
+        assert_eq!(db.get_stats().await.unwrap().total_vectors, 2);
+        assert_eq!(top_id(&db, &[0.1, 1.0]).await.as_deref(), Some("b"));
+    }
+
+    // Adds like a flat index, but refuses one id
+    struct FailingIndex {
+        inner: FlatIndex,
+        fail_on: &'static str,
+    }
+
+    impl VectorIndex for FailingIndex {
+        fn add_vector(&self, id: &str, vector: &[f32]) -> Result<()> {
+            if id == self.fail_on {
+                return Err(anyhow!("refused"));
+            }
+            self.inner.add_vector(id, vector)
+        }
+
+        fn remove_vector(&self, id: &str) -> Result<bool> {
+            self.inner.remove_vector(id)
+        }
+
+        fn search_filtered(
+            &self,
+            query: &[f32],
+            k: usize,
+            allowed: &dyn Fn(&str) -> bool,
+        ) -> Result<Vec<skypier_index::SearchResult>> {
+            self.inner.search_filtered(query, k, allowed)
+        }
+
+        fn size(&self) -> usize {
+            self.inner.size()
+        }
+
+        fn clear(&self) {
+            self.inner.clear()
+        }
+
+        fn index_type(&self) -> &'static str {
+            "failing"
+        }
+
+        fn save(&self) -> Result<Vec<u8>> {
+            self.inner.save()
+        }
+
+        fn load(&self, data: &[u8]) -> Result<()> {
+            self.inner.load(data)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_insert_rolls_back() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = VectorDatabase::new(temp_dir.path().to_str().unwrap())
+            .await
+            .unwrap()
+            .with_index(FailingIndex {
+                inner: FlatIndex::new(),
+                fail_on: "bad",
+            });
+        db.insert_vectors(vec![Vector::with_id("a".to_string(), vec![1.0, 0.0])])
+            .await
+            .unwrap();
+
+        let result = db
+            .insert_vectors(vec![
+                Vector::with_id("a".to_string(), vec![0.0, 1.0]),
+                Vector::with_id("b".to_string(), vec![0.0, 1.0]),
+                Vector::with_id("bad".to_string(), vec![0.0, 1.0]),
+            ])
+            .await;
+        assert!(result.is_err());
+
+        assert_eq!(db.storage.count_vectors().await.unwrap(), 1);
+        assert!(db.storage.get_vector("b").await.unwrap().is_none());
+        let a = db.storage.get_vector("a").await.unwrap().unwrap();
+        assert_eq!(a.data, vec![1.0, 0.0]);
+        assert_eq!(db.index.size(), 1);
+        assert_eq!(top_id(&db, &[1.0, 0.0]).await.as_deref(), Some("a"));
+        assert!(db.search(&[0.0, 1.0], 1, 0.9).await.unwrap().is_empty());
+
+        // The compensation is logged, so replaying the WAL lands in the same place
+        let ops: Vec<WalOp> = db
+            .storage
+            .wal_since(1)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.op)
+            .collect();
+        assert_eq!(
+            ops,
+            vec![
+                WalOp::Upsert,
+                WalOp::Upsert,
+                WalOp::Upsert,
+                WalOp::Upsert,
+                WalOp::Delete,
+                WalOp::Delete,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_writes_publish_change_events() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = open(temp_dir.path()).await;
+        let mut changes = db.subscribe();
+
+        let a = Vector::with_id("a".to_string(), vec![1.0, 0.0]).with_collection("docs".into());
+        db.insert_vectors(vec![a.clone()]).await.unwrap();
+        db.insert_vectors(vec![a]).await.unwrap();
+        assert!(db.delete_vector("a").await.unwrap());
+        assert!(!db.delete_vector("a").await.unwrap());
+
+        let mut events = Vec::new();
+        while let Ok(event) = changes.try_recv() {
+            assert_eq!(
+                (event.id.as_str(), event.collection.as_deref()),
+                ("a", Some("docs"))
+            );
+            events.push((event.seq, event.kind));
+        }
+        assert_eq!(
+            events,
+            vec![
+                (1, ChangeKind::Insert),
+                (2, ChangeKind::Update),
+                (3, ChangeKind::Delete)
+            ]
+        );
+
+        // The persisted feed replays the same events
+        let replayed: Vec<(u64, ChangeKind)> = db
+            .changes_since(0, 10)
+            .await
+            .unwrap()
+            .unwrap()
+            .into_iter()
+            .map(|event| (event.seq, event.kind))
+            .collect();
+        assert_eq!(replayed, events);
+        assert_eq!(db.changes_since(1, 1).await.unwrap().unwrap().len(), 1);
+        assert!(db.changes_since(3, 10).await.unwrap().unwrap().is_empty());
+        assert!(db.changes_since(4, 10).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_import_into_live_database() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = open(temp_dir.path()).await;
+        db.insert_vectors(vec![Vector::with_id("a".to_string(), vec![1.0, 0.0])])
+            .await
+            .unwrap();
+
+        let ids = db
+            .import_vectors(vec![
+                Vector::with_id("a".to_string(), vec![0.0, 1.0]),
+                Vector::with_id("b".to_string(), vec![1.0, 0.0]),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(ids, vec!["a", "b"]);
+        assert_eq!(db.get_stats().await.unwrap().total_vectors, 2);
+        assert_eq!(top_id(&db, &[0.0, 1.0]).await.as_deref(), Some("a"));
+        assert_eq!(top_id(&db, &[1.0, 0.0]).await.as_deref(), Some("b"));
+        let kinds: Vec<ChangeKind> = db
+            .changes_since(1, 10)
+            .await
+            .unwrap()
+            .unwrap()
+            .into_iter()
+            .map(|event| event.kind)
+            .collect();
+        assert_eq!(kinds, vec![ChangeKind::Update, ChangeKind::Insert]);
+    }
+
+    #[tokio::test]
+    async fn test_changefeed_survives_snapshots_within_retention() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = VectorDatabase::new(temp_dir.path().to_str().unwrap())
+            .await
+            .unwrap()
+            .with_changefeed_retention(2);
+        db.load_index().await.unwrap();
+        for id in ["a", "b", "c"] {
+            db.insert_vectors(vec![Vector::with_id(id.to_string(), vec![1.0, 0.0])])
+                .await
+                .unwrap();
+        }
+        assert_eq!(db.snapshot_index().await.unwrap(), 3);
+
+        assert!(db.changes_since(0, 10).await.unwrap().is_none());
+        let ids: Vec<String> = db
+            .changes_since(1, 10)
+            .await
+            .unwrap()
+            .unwrap()
+            .into_iter()
+            .map(|event| event.id)
+            .collect();
+        assert_eq!(ids, vec!["b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn test_followers_apply_changes_read_only() {
+        let primary_dir = tempfile::tempdir().unwrap();
+        let follower_dir = tempfile::tempdir().unwrap();
+        let primary = open(primary_dir.path()).await;
+        let follower = open(follower_dir.path()).await;
+        follower.set_read_only(true);
+        let insert = follower
+            .insert_vectors(vec![Vector::with_id("x".to_string(), vec![1.0, 0.0])])
+            .await
+            .unwrap_err();
+        assert!(insert.downcast_ref::<ReadOnlyError>().is_some());
+        assert!(follower.delete_vector("x").await.is_err());
+
+        for id in ["a", "b", "c"] {
+            primary
+                .insert_vectors(vec![Vector::with_id(id.to_string(), vec![1.0, 0.0])])
+                .await
+                .unwrap();
+        }
+        primary
+            .insert_vectors(vec![Vector::with_id("a".to_string(), vec![0.0, 1.0])])
+            .await
+            .unwrap();
+        assert!(primary.delete_vector("b").await.unwrap());
+
+        // Pages pick up where the last one stopped, and only the last change
+        // to each id is sent
+        let first = primary.changes_page(0, 2).await.unwrap().unwrap();
+        assert_eq!(first.seq, 2);
+        follower.apply_changes(&first).await.unwrap();
+        let rest = primary.changes_page(first.seq, 10).await.unwrap().unwrap();
+        assert_eq!(rest.seq, 5);
+        assert_eq!(rest.upserts.len(), 2);
+        assert_eq!(rest.deletes, vec!["b"]);
+        follower.apply_changes(&rest).await.unwrap();
+        assert!(primary
+            .changes_page(5, 10)
+            .await
+            .unwrap()
+            .unwrap()
+            .upserts
+            .is_empty());
+        assert!(primary.changes_page(6, 10).await.unwrap().is_none());
+
+        assert_eq!(follower.get_stats().await.unwrap().total_vectors, 2);
+        assert!(follower.get_vector("b").await.unwrap().is_none());
+        assert_eq!(top_id(&follower, &[0.0, 1.0]).await.as_deref(), Some("a"));
+
+        // Records that fail their signature check don't get in
+        let mut forged = Vector::with_id("a".to_string(), vec![1.0, 0.0]);
+        let key = ed25519_dalek::SigningKey::from_bytes(&[7; 32]);
+        crate::signing::sign(&mut forged, &key, 1);
+        forged.data = vec![0.5, 0.5];
+        let changes = ChangeSet {
+            seq: 6,
+            upserts: vec![forged],
+            deletes: Vec::new(),
+        };
+        follower.apply_changes(&changes).await.unwrap();
+        let kept = follower.get_vector("a").await.unwrap().unwrap();
+        assert_eq!(kept.data, vec![0.0, 1.0]);
+
+        // Once promoted it takes writes itself
+        follower.set_read_only(false);
+        follower
+            .insert_vectors(vec![Vector::with_id("x".to_string(), vec![1.0, 0.0])])
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_writes_follow_replicated_clocks() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(dir.path()).await;
+        // Made on a node whose clock runs a minute ahead
+        let ahead = HybridClock::new().now().unwrap() + (60_000 << 16);
+        let mut remote = Vector::with_id("x".to_string(), vec![1.0, 0.0]);
+        remote.hlc = ahead;
+        remote.origin = Some("elsewhere".to_string());
+        remote.clock = BTreeMap::from([("elsewhere".to_string(), 1)]);
+        let changes = ChangeSet {
+            seq: 1,
+            upserts: vec![remote.clone()],
+            deletes: Vec::new(),
+        };
+        db.apply_changes(&changes).await.unwrap();
+
+        // Local writes made afterwards still order after it
+        db.insert_vectors(vec![Vector::with_id("x".to_string(), vec![0.0, 1.0])])
+            .await
+            .unwrap();
+        let local = db.get_vector("x").await.unwrap().unwrap();
+        assert!(local.hlc > ahead);
+        db.apply_changes(&changes).await.unwrap();
+        assert_eq!(
+            db.get_vector("x").await.unwrap().unwrap().data,
+            vec![0.0, 1.0]
+        );
+        assert!(db.delete_vector("x").await.unwrap());
+
+        let stamps: Vec<u64> = db
+            .changes_since(0, 10)
+            .await
+            .unwrap()
+            .unwrap()
+            .into_iter()
+            .map(|event| event.hlc)
+            .collect();
+        assert_eq!(stamps.len(), 3);
+        assert_eq!(stamps[..2], [ahead, local.hlc]);
+        assert!(stamps[2] > local.hlc);
+
+        // One from a clock far in the future is dropped, not followed
+        let mut wild = Vector::with_id("y".to_string(), vec![1.0, 0.0]);
+        wild.hlc = u64::MAX;
+        wild.origin = Some("elsewhere".to_string());
+        let changes = ChangeSet {
+            seq: 2,
+            upserts: vec![wild],
+            deletes: Vec::new(),
+        };
+        db.apply_changes(&changes).await.unwrap();
+        assert!(db.get_vector("y").await.unwrap().is_none());
+        db.insert_vectors(vec![Vector::with_id("z".to_string(), vec![1.0, 0.0])])
+            .await
+            .unwrap();
+        let z = db.get_vector("z").await.unwrap().unwrap();
+        assert!(z.hlc > stamps[2] && z.hlc < ahead + (hlc::MAX_DRIFT_MS << 16));
+    }
+
+    #[tokio::test]
+    async fn test_records_ahead_of_a_stepped_back_clock_stay_writable() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open(dir.path()).await;
+        // Stored while the clock ran well ahead of where it is now
+        let ahead = HybridClock::new().now().unwrap() + ((2 * hlc::MAX_DRIFT_MS) << 16);
+        let mut stored = Vector::with_id("x".to_string(), vec![1.0, 0.0]);
+        stored.hlc = ahead;
+        stored.version = 1;
+        db.storage.write_batch(&[stored], &[], ahead).await.unwrap();
+
+        db.insert_vectors(vec![Vector::with_id("x".to_string(), vec![0.0, 1.0])])
+            .await
+            .unwrap();
+        let updated = db.get_vector("x").await.unwrap().unwrap();
+        assert_eq!(updated.version, 2);
+        assert!(updated.hlc > ahead);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_replicated_writes_conflict() {
+        let a_dir = tempfile::tempdir().unwrap();
+        let b_dir = tempfile::tempdir().unwrap();
+        let a = open(a_dir.path())
+            .await
+            .with_conflict_resolver(crate::ConflictPolicy::VectorClock.resolver());
+        let b = open(b_dir.path())
+            .await
+            .with_conflict_resolver(crate::ConflictPolicy::VectorClock.resolver());
+        a.insert_vectors(vec![Vector::with_id("x".to_string(), vec![1.0, 0.0])])
+            .await
+            .unwrap();
+        b.apply_changes(&a.changes_page(0, 10).await.unwrap().unwrap())
+            .await
+            .unwrap();
+        // Coming back to where it was written, it changes nothing
+        a.apply_changes(&b.changes_page(0, 10).await.unwrap().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(a.wal_head().await.unwrap(), 1);
+
+        // Both write x without seeing the other's write
+        a.insert_vectors(vec![Vector::with_id("x".to_string(), vec![0.0, 1.0])])
+            .await
+            .unwrap();
+        b.insert_vectors(vec![Vector::with_id("x".to_string(), vec![1.0, 1.0])])
+            .await
+            .unwrap();
+        b.apply_changes(&a.changes_page(1, 10).await.unwrap().unwrap())
+            .await
+            .unwrap();
+        let conflicts = b.conflicts().await.unwrap();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].remote.data, vec![0.0, 1.0]);
+        assert_eq!(
+            b.get_vector("x").await.unwrap().unwrap().data,
+            vec![1.0, 1.0]
+        );
+
+        assert!(!b.resolve_conflict("y", ConflictSide::Remote).await.unwrap());
+        assert!(b.resolve_conflict("x", ConflictSide::Remote).await.unwrap());
+        assert!(b.conflicts().await.unwrap().is_empty());
+        assert_eq!(
+            b.get_vector("x").await.unwrap().unwrap().data,
+            vec![0.0, 1.0]
+        );
+
+        // The resolution follows both writes, so the other side takes it
+        let head = a.wal_head().await.unwrap();
+        a.apply_changes(&b.changes_page(2, 10).await.unwrap().unwrap())
+            .await
+            .unwrap();
+        assert!(a.conflicts().await.unwrap().is_empty());
+        assert_eq!(a.wal_head().await.unwrap(), head + 1);
+        assert_eq!(
+            a.get_vector("x").await.unwrap().unwrap().clock,
+            b.get_vector("x").await.unwrap().unwrap().clock
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delete_collection() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = open(temp_dir.path()).await;
+        db.insert_vectors(vec![
+            Vector::with_id("a".to_string(), vec![1.0, 0.0]).with_collection("docs".to_string()),
+            Vector::with_id("b".to_string(), vec![0.0, 1.0]).with_collection("docs".to_string()),
+            Vector::with_id("c".to_string(), vec![1.0, 0.0]).with_collection("notes".to_string()),
+        ])
+        .await
+        .unwrap();
+
+        assert_eq!(db.delete_collection("docs").await.unwrap(), 2);
+        assert_eq!(db.list_collections().await.unwrap(), vec!["notes"]);
+        assert!(db.get_vector("a").await.unwrap().is_none());
+        assert_eq!(top_id(&db, &[1.0, 0.0]).await.as_deref(), Some("c"));
+        assert_eq!(top_id(&db, &[0.0, 1.0]).await, None);
+        let events = db.changes_since(3, 10).await.unwrap().unwrap();
+        assert_eq!(events.len(), 2);
+        assert!(events.iter().all(|event| event.kind == ChangeKind::Delete));
+    }
+
+    #[tokio::test]
+    async fn test_rerank_rescores_with_boosts() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = open(temp_dir.path()).await;
+        let priority = |value: &str| HashMap::from([("priority".to_string(), value.to_string())]);
+        db.insert_vectors(vec![
+            Vector::with_id("a".to_string(), vec![1.0, 0.0]).with_metadata(priority("0")),
+            Vector::with_id("b".to_string(), vec![0.9, 0.1]).with_metadata(priority("1")),
+            Vector::with_id("c".to_string(), vec![0.0, 1.0]),
+        ])
+        .await
+        .unwrap();
+        let filter = SearchFilter::default();
+
+        let options = SearchOptions {
+            rerank: Some(crate::Rerank::default()),
+            ..Default::default()
+        };
+        let results = db
+            .search_with(&[1.0, 0.0], 2, 0.5, &filter, &options)
+            .await
+            .unwrap();
+        assert_eq!(results[0].id, "a");
+        assert!((results[0].score - 1.0).abs() < 1e-5);
+
+        let options = SearchOptions {
+            rerank: Some(crate::Rerank {
+                fetch_factor: 3,
+                boosts: vec![crate::MetadataBoost {
+                    field: "priority".to_string(),
+                    weight: 0.1,
+                }],
+            }),
+            ..Default::default()
+        };
+        let results = db
+            .search_with(&[1.0, 0.0], 2, 0.5, &filter, &options)
+            .await
+            .unwrap();
+        let ids: Vec<&str> = results.iter().map(|result| result.id.as_str()).collect();
+        assert_eq!(ids, vec!["b", "a"]);
+    }
+
+    #[tokio::test]
+    async fn test_named_vectors_have_their_own_indexes() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        {
+            let db = open(temp_dir.path()).await;
+            db.insert_vectors(vec![
+                Vector::with_id("a".to_string(), vec![1.0, 0.0])
+                    .with_named("title", vec![0.0, 1.0, 0.0]),
+                Vector::with_id("b".to_string(), vec![0.0, 1.0])
+                    .with_named("title", vec![1.0, 0.0, 0.0]),
+            ])
+            .await
+            .unwrap();
+            assert_eq!(
+                top_named(&db, "title", &[1.0, 0.0, 0.0]).await.as_deref(),
+                Some("b")
+            );
+            assert_eq!(top_id(&db, &[1.0, 0.0]).await.as_deref(), Some("a"));
+            db.snapshot_index().await.unwrap();
+
+            // Only in the WAL: b loses its title, c gets one
+            db.insert_vectors(vec![
+                Vector::with_id("b".to_string(), vec![0.0, 1.0]),
+                Vector::with_id("c".to_string(), vec![1.0, 1.0])
+                    .with_named("title", vec![0.9, 0.1, 0.0]),
+            ])
+            .await
+            .unwrap();
+        }
+
+        let db = open(temp_dir.path()).await;
+        assert_eq!(
+            top_named(&db, "title", &[1.0, 0.0, 0.0]).await.as_deref(),
+            Some("c")
+        );
+        assert_eq!(
+            top_named(&db, "title", &[0.0, 1.0, 0.0]).await.as_deref(),
+            Some("a")
+        );
+        db.delete_vector("c").await.unwrap();
+        assert_eq!(top_named(&db, "title", &[1.0, 0.0, 0.0]).await, None);
+
+        assert_eq!(top_named(&db, "body", &[1.0]).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_sparse_and_hybrid_search() {
+        let sparse = |indices: Vec<u32>, values: Vec<f32>| SparseVector { indices, values };
+        let ids = |results: Vec<SearchResult>| -> Vec<String> {
+            results.into_iter().map(|result| result.id).collect()
+        };
+        let filter = SearchFilter::default();
+        let temp_dir = tempfile::tempdir().unwrap();
+        {
+            let db = open(temp_dir.path()).await;
+            db.insert_vectors(vec![
+                Vector::with_id("a".to_string(), vec![1.0, 0.0])
+                    .with_sparse(sparse(vec![3], vec![0.1])),
+                Vector::with_id("b".to_string(), vec![0.6, 0.8])
+                    .with_sparse(sparse(vec![3, 9], vec![2.0, 1.0])),
+                Vector::with_id("c".to_string(), vec![0.0, 1.0]),
+            ])
+            .await
+            .unwrap();
+            db.snapshot_index().await.unwrap();
+
+            // Only in the WAL: c gets a sparse vector
+            db.insert_vectors(vec![Vector::with_id("c".to_string(), vec![0.0, 1.0])
+                .with_sparse(sparse(vec![9], vec![5.0]))])
+                .await
+                .unwrap();
+        }
+
+        let db = open(temp_dir.path()).await;
+        let query = sparse(vec![3], vec![1.0]);
+        let results = db.search_sparse(&query, 10, 0.0, &filter).await.unwrap();
+        assert_eq!(ids(results), vec!["b", "a"]);
+        let query = sparse(vec![9], vec![1.0]);
+        let results = db.search_sparse(&query, 10, 0.0, &filter).await.unwrap();
+        assert_eq!(ids(results), vec!["c", "b"]);
+
+        // Dense ranks a, b, c and sparse b, c, a
+        let query = sparse(vec![3, 9], vec![1.0, 0.1]);
+        let results = db
+            .search_hybrid(&[1.0, 0.2], &query, 2, 0.0, &filter, &Fusion::default())
+            .await
+            .unwrap();
+        assert_eq!(ids(results), vec!["b", "a"]);
+
+        let query = sparse(vec![3], vec![1.0]);
+
+        db.delete_vector("b").await.unwrap();
+        let results = db.search_sparse(&query, 10, 0.0, &filter).await.unwrap();
+        assert_eq!(ids(results), vec!["a"]);
+        assert!(db
+            .search_sparse(&sparse(vec![3], vec![]), 10, 0.0, &filter)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_collection_dtype_rounds_stored_data() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dtypes = HashMap::from([("docs".to_string(), Dtype::Bf16)]);
+        let db = open(temp_dir.path()).await.with_dtypes(Dtype::F32, dtypes);
+        db.insert_vectors(vec![
+            Vector::with_id("a".to_string(), vec![1.001, 0.5]).with_collection("docs".to_string()),
+            Vector::with_id("b".to_string(), vec![0.1, 0.2]),
+        ])
+        .await
+        .unwrap();
+
+        let a = db.get_vector("a").await.unwrap().unwrap();
+        assert_eq!(a.dtype, Dtype::Bf16);
+        assert_eq!(a.data, vec![1.0, 0.5]);
+        let b = db.get_vector("b").await.unwrap().unwrap();
+        assert_eq!((b.dtype, b.data), (Dtype::F32, vec![0.1, 0.2]));
+    }
+
+    #[tokio::test]
+    async fn test_collection_metric_scopes_its_searches() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let top = |results: Vec<SearchResult>| results.into_iter().next().map(|r| r.id);
+        {
+            let db = open(temp_dir.path()).await;
+            db.insert_vectors(vec![
+                Vector::with_id("a".to_string(), vec![1.0, 0.0]).with_collection("geo".to_string()),
+                Vector::with_id("b".to_string(), vec![10.0, 0.5])
+                    .with_collection("geo".to_string()),
+            ])
+            .await
+            .unwrap();
+            db.set_collection_metric("geo", DistanceMetric::Euclidean)
+                .await
+                .unwrap();
+            db.snapshot_index().await.unwrap();
+
+            // Only in the WAL
+            db.insert_vectors(vec![
+                Vector::with_id("c".to_string(), vec![9.0, 0.2]).with_collection("geo".to_string())
+            ])
+            .await
+            .unwrap();
+        }
+
+        let db = open(temp_dir.path()).await;
+        assert_eq!(db.collection_metric("geo").await, DistanceMetric::Euclidean);
+        assert_eq!(db.collection_metric("other").await, DistanceMetric::Cosine);
+        let results = db
+            .search_in_collection("geo", &[9.0, 0.0], 3, 0.0)
+            .await
+            .unwrap();
+        assert_eq!(top(results).as_deref(), Some("c"));
+        // Unscoped searches stay cosine
+        let results = db.search(&[9.0, 0.0], 3, 0.0).await.unwrap();
+        assert_eq!(top(results).as_deref(), Some("a"));
+
+        std::fs::remove_file(temp_dir.path().join(COLLECTION_SNAPSHOT_FILE)).unwrap();
+        drop(db);
+        let db = open(temp_dir.path()).await;
+        let results = db
+            .search_in_collection("geo", &[10.0, 0.4], 1, 0.0)
+            .await
+            .unwrap();
+        assert_eq!(top(results).as_deref(), Some("b"));
+
+        // A maximum distance cuts in the metric's own terms: b and c are
+        // within 1.5 of the query, a is 9 away
+        let geo = SearchFilter {
+            collection: Some("geo".to_string()),
+            ..Default::default()
+        };
+        let options = SearchOptions {
+            max_distance: Some(1.5),
+            ..Default::default()
+        };
+        let results = db
+            .search_with(&[10.0, 0.0], 3, 0.0, &geo, &options)
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 2);
+
+        // Snapshots are searched with the collection's metric too
+        db.create_snapshot("geo", "before").await.unwrap();
+        let results = db
+            .search_snapshot("geo", "before", &[9.0, 0.0], 3, 0.0)
+            .await
+            .unwrap();
+        assert_eq!(top(results).as_deref(), Some("c"));
+    }
+
+    #[tokio::test]
+    async fn test_exact_search_scans_storage() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = open(temp_dir.path()).await;
+        let lang = |value: &str| HashMap::from([("lang".to_string(), value.to_string())]);
+        db.insert_vectors(vec![
+            Vector::with_id("a".to_string(), vec![1.0, 0.0])
+                .with_metadata(lang("en"))
+                .with_named("title", vec![0.0, 1.0]),
+            Vector::with_id("b".to_string(), vec![0.8, 0.6]).with_metadata(lang("fr")),
+            Vector::with_id("c".to_string(), vec![0.6, 0.8])
+                .with_metadata(lang("en"))
+                .with_collection("docs".to_string()),
+        ])
+        .await
+        .unwrap();
+
+        let exact = SearchOptions {
+            exact: true,
+            ..Default::default()
+        };
+        let ids = |results: Vec<SearchResult>| -> Vec<String> {
+            results.into_iter().map(|result| result.id).collect()
+        };
+        let all = SearchFilter::default();
+        let results = db
+            .search_with(&[1.0, 0.0], 3, 0.0, &all, &exact)
+            .await
+            .unwrap();
+        assert_eq!(ids(results), vec!["a", "b", "c"]);
+
+        let english = SearchFilter {
+            metadata: lang("en"),
+            ..Default::default()
+        };
+        let results = db
+            .search_with(&[0.8, 0.6], 3, 0.0, &english, &exact)
+            .await
+            .unwrap();
+        assert_eq!(ids(results), vec!["c", "a"]);
+        let docs = SearchFilter {
+            collection: Some("docs".to_string()),
+            ..Default::default()
+        };
+        let results = db
+            .search_with(&[1.0, 0.0], 3, 0.0, &docs, &exact)
+            .await
+            .unwrap();
+        assert_eq!(ids(results), vec!["c"]);
+
+        let titles = SearchOptions {
+            vector_name: Some("title".to_string()),
+            ..exact
+        };
+        let results = db
+            .search_with(&[1.0, 0.0], 3, 0.0, &all, &titles)
+            .await
+            .unwrap();
+        assert_eq!(ids(results), vec!["a"]);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_search_stops() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = open(temp_dir.path()).await;
+        let lang = HashMap::from([("lang".to_string(), "en".to_string())]);
+        db.insert_vectors(vec![
+            Vector::with_id("a".to_string(), vec![1.0, 0.0]).with_metadata(lang.clone()),
+            Vector::with_id("b".to_string(), vec![0.0, 1.0]),
+        ])
+        .await
+        .unwrap();
+
+        let cancel = CancellationToken::new();
+        let options = SearchOptions {
+            cancel: Some(cancel.clone()),
+            ..Default::default()
+        };
+        let all = SearchFilter::default();
+        let english = SearchFilter {
+            metadata: lang,
+            ..Default::default()
+        };
+        let results = db
+            .search_with(&[1.0, 0.0], 2, 0.0, &english, &options)
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 1);
+
+        cancel.cancel();
+        let exact = SearchOptions {
+            exact: true,
+            ..options.clone()
+        };
+        for (filter, options) in [(&all, &options), (&english, &options), (&all, &exact)] {
+            let err = db
+                .search_with(&[1.0, 0.0], 2, 0.0, filter, options)
+                .await
+                .unwrap_err();
+            assert!(err.is::<skypier_index::Cancelled>());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sample_is_uniform() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = open(temp_dir.path()).await;
+        let mut vectors: Vec<Vector> = (0..50)
+            .map(|i| {
+                Vector::with_id(format!("{:02}", i), vec![1.0, i as f32])
+                    .with_collection("docs".to_string())
+            })
+            .collect();
+        vectors.push(Vector::with_id("other".to_string(), vec![1.0, 0.0]));
+        db.insert_vectors(vectors).await.unwrap();
+
+        let ids = |sample: Vec<Vector>| -> Vec<String> {
+            sample.into_iter().map(|vector| vector.id).collect()
+        };
+        let sample = ids(db.sample(Some("docs"), 10, Some(7)).await.unwrap());
+        assert_eq!(sample.len(), 10);
+        assert!(!sample.contains(&"other".to_string()));
+        assert_eq!(
+            ids(db.sample(Some("docs"), 10, Some(7)).await.unwrap()),
+            sample
+        );
+        assert_eq!(db.sample(Some("docs"), 100, None).await.unwrap().len(), 50);
+        assert_eq!(db.sample(None, 100, None).await.unwrap().len(), 51);
+        assert!(db.sample(Some("none"), 10, None).await.unwrap().is_empty());
+
+        // Each vector is picked about 10 / 50 of the time
+        let mut picked: HashMap<String, usize> = HashMap::new();
+        for seed in 0..500 {
+            for id in ids(db.sample(Some("docs"), 10, Some(seed)).await.unwrap()) {
+                *picked.entry(id).or_default() += 1;
+            }
+        }
+        assert_eq!(picked.len(), 50);
+        assert!(picked.values().all(|&count| (50..=160).contains(&count)));
+    }
+
+    #[tokio::test]
+    async fn test_aliases_resolve_reads() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = open(temp_dir.path()).await;
+        db.insert_vectors(vec![
+            Vector::with_id("old".to_string(), vec![1.0, 0.0])
+                .with_collection("docs_v1".to_string()),
+            Vector::with_id("new".to_string(), vec![1.0, 0.0])
+                .with_collection("docs_v2".to_string()),
+        ])
+        .await
+        .unwrap();
+
+        async fn search(db: &VectorDatabase) -> Vec<String> {
+            let filter = SearchFilter {
+                collection: Some("docs".to_string()),
+                ..Default::default()
+            };
+            let results = db
+                .search_filtered(&[1.0, 0.0], 5, 0.0, &filter)
+                .await
+                .unwrap();
+            results.into_iter().map(|result| result.id).collect()
+        }
+        assert!(search(&db).await.is_empty());
+        db.set_alias("docs", "docs_v1").await.unwrap();
+        assert_eq!(search(&db).await, vec!["old"]);
+        // Repointing is the cutover, and pointing back undoes it
+        db.set_alias("docs", "docs_v2").await.unwrap();
+        assert_eq!(search(&db).await, vec!["new"]);
+        assert_eq!(
+            db.scroll(Some("docs"), None, 10).await.unwrap().vectors[0].id,
+            "new"
+        );
+        assert_eq!(
+            db.collection_stats("docs")
+                .await
+                .unwrap()
+                .unwrap()
+                .vector_count,
+            1
+        );
+        assert_eq!(db.aliases().await["docs"], "docs_v2");
+
+        // Aliases can't be written to, shadow a collection or chain
+        let write =
+            Vector::with_id("x".to_string(), vec![0.0, 1.0]).with_collection("docs".to_string());
+        assert!(db.insert_vectors(vec![write]).await.is_err());
+        assert!(db.set_alias("docs_v1", "docs_v2").await.is_err());
+        assert!(db.set_alias("latest", "docs").await.is_err());
+        assert!(db.set_alias("docs_v3", "docs_v3").await.is_err());
+
+        assert!(db.delete_alias("docs").await.unwrap());
+        assert!(!db.delete_alias("docs").await.unwrap());
+        assert!(search(&db).await.is_empty());
+
+        // Aliases outlive a restart
+        db.set_alias("docs", "docs_v1").await.unwrap();
+        drop(db);
+        let db = open(temp_dir.path()).await;
+        assert_eq!(db.resolve_collection("docs").await, "docs_v1");
+    }
+
+    #[tokio::test]
+    async fn test_metadata_schema() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = open(temp_dir.path()).await;
+        let doc = |id: &str, pairs: &[(&str, &str)]| {
+            let metadata = pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect();
+            Vector::with_id(id.to_string(), vec![1.0, 0.0])
+                .with_collection("docs".to_string())
+                .with_metadata(metadata)
+        };
+        db.insert_vectors(vec![doc("old", &[("lang", "en"), ("source", "web")])])
+            .await
+            .unwrap();
+        let schema: MetadataSchema = serde_json::from_str(
+            r#"{"fields": {"lang": {"required": true, "indexed": true}, "year": {"type": "integer"}}}"#,
+        )
+        .unwrap();
+        db.set_metadata_schema("docs", Some(schema.clone()))
+            .await
+            .unwrap();
+        assert_eq!(db.metadata_schema("docs").await, Some(schema));
+
+        let error = db
+            .insert_vectors(vec![doc("bad", &[("year", "soon")])])
+            .await
+            .unwrap_err();
+        let Some(ValidationError::SchemaViolation { errors, .. }) = error.downcast_ref() else {
+            panic!("unexpected error {}", error);
+        };
+        assert_eq!(errors.len(), 2);
+        let check = db
+            .check_vectors(&[doc("bad", &[]), doc("good", &[("lang", "fr")])])
+            .await
+            .unwrap();
+        assert_eq!(check.errors.len(), 1);
+        db.insert_vectors(vec![doc("new", &[("lang", "fr"), ("year", "2024")])])
+            .await
+            .unwrap();
+
+        // Only the indexed key can be filtered on, for old vectors too
+        let search = |key: &str, value: &str| {
+            let filter = SearchFilter {
+                collection: Some("docs".to_string()),
+                metadata: HashMap::from([(key.to_string(), value.to_string())]),
+            };
+            let db = &db;
+            async move { db.search_filtered(&[1.0, 0.0], 5, 0.0, &filter).await }
+        };
+        assert_eq!(search("lang", "en").await.unwrap()[0].id, "old");
+        assert!(search("source", "web").await.is_err());
+
+        // The narrower index outlives a restart
+        drop(db);
+        let db = open(temp_dir.path()).await;
+        let filter = SearchFilter {
+            collection: Some("docs".to_string()),
+            metadata: HashMap::from([("source".to_string(), "web".to_string())]),
+        };
+        assert!(db
+            .search_filtered(&[1.0, 0.0], 5, 0.0, &filter)
+            .await
+            .is_err());
+        db.set_metadata_schema("docs", None).await.unwrap();
+        let results = db
+            .search_filtered(&[1.0, 0.0], 5, 0.0, &filter)
+            .await
+            .unwrap();
+        assert_eq!(results[0].id, "old");
+    }
+
+    #[tokio::test]
+    async fn test_collection_quota() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = open(temp_dir.path()).await;
+        let doc = |id: &str, collection: &str| {
+            Vector::with_id(id.to_string(), vec![1.0, 0.0]).with_collection(collection.to_string())
+        };
+        db.set_collection_quota(
+            "docs",
+            Some(Quota {
+                max_vectors: Some(2),
+                max_bytes: None,
+            }),
+        )
+        .await
+        .unwrap();
+        db.insert_vectors(vec![doc("a", "docs"), doc("a", "docs"), doc("b", "docs")])
+            .await
+            .unwrap();
+
+        let error = db.insert_vectors(vec![doc("c", "docs")]).await.unwrap_err();
+        let exceeded = error.downcast_ref::<QuotaExceeded>().unwrap();
+        assert_eq!(exceeded.limit, QuotaLimit::Vectors);
+        assert_eq!(exceeded.usage.vectors, 2);
+        // Overwrites and other collections don't count against it
+        db.insert_vectors(vec![doc("a", "docs"), doc("c", "notes")])
+            .await
+            .unwrap();
+
+        db.set_collection_quota(
+            "docs",
+            Some(Quota {
+                max_vectors: None,
+                max_bytes: Some(1),
+            }),
+        )
+        .await
+        .unwrap();
+        let error = db.insert_vectors(vec![doc("d", "docs")]).await.unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<QuotaExceeded>().unwrap().limit,
+            QuotaLimit::Bytes
+        );
+        db.set_collection_quota("docs", None).await.unwrap();
+        db.insert_vectors(vec![doc("d", "docs")]).await.unwrap();
+        assert_eq!(db.collection_quota("docs").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_quota_holds_under_concurrent_writes() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Arc::new(open(temp_dir.path()).await);
+        let vector = |id: usize| Vector::with_id(id.to_string(), vec![1.0, 0.0]);
+        db.set_quota(
+            "Namespace 'acme'",
+            Some(Quota {
+                max_vectors: Some(5),
+                max_bytes: None,
+            }),
+        )
+        .await;
+
+        let tasks: Vec<_> = (0..20)
+            .map(|id| {
+                let db = Arc::clone(&db);
+                tokio::spawn(async move { db.insert_vectors(vec![vector(id)]).await })
+            })
+            .collect();
+        let mut refused = 0;
+        for task in tasks {
+            if let Err(e) = task.await.unwrap() {
+                assert!(e.downcast_ref::<QuotaExceeded>().is_some());
+                refused += 1;
+            }
+        }
+        assert_eq!(refused, 15);
+        assert_eq!(db.get_stats().await.unwrap().total_vectors, 5);
+
+        // Bytes are counted as stored, for the usage and the write alike
+        let stored = db.storage.vector_bytes().await.unwrap().stored;
+        // Room for two and a half more records, with their longer ids
+        let written = db.storage.get_vector("4").await.unwrap().unwrap();
+        let record_len = db.storage.record_len(&written).unwrap() + 1;
+        let max_bytes = stored + 5 * record_len / 2;
+        db.set_quota(
+            "Namespace 'acme'",
+            Some(Quota {
+                max_vectors: None,
+                max_bytes: Some(max_bytes),
+            }),
+        )
+        .await;
+        let tasks: Vec<_> = (20..30)
+            .map(|id| {
+                let db = Arc::clone(&db);
+                tokio::spawn(async move { db.insert_vectors(vec![vector(id)]).await })
+            })
+            .collect();
+        for task in tasks {
+            let _ = task.await.unwrap();
+        }
+        let stats = db.get_stats().await.unwrap();
+        assert_eq!(stats.total_vectors, 7);
+        assert!(stats.stored_vector_bytes as u64 <= max_bytes);
+    }
+
+    #[tokio::test]
+    async fn test_clone_collection() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = open(temp_dir.path()).await;
+        let mut metadata = HashMap::new();
+        metadata.insert("title".to_string(), "first".to_string());
+        db.insert_vectors(vec![
+            Vector::with_id("a".to_string(), vec![3.0, 4.0])
+                .with_collection("docs".to_string())
+                .with_metadata(metadata),
+            Vector::with_id("b".to_string(), vec![0.0, 2.0]).with_collection("docs".to_string()),
+            Vector::with_id("c".to_string(), vec![1.0, 0.0]),
+        ])
+        .await
+        .unwrap();
+        db.set_collection_metric("docs", DistanceMetric::Euclidean)
+            .await
+            .unwrap();
+
+        let options = CloneOptions {
+            normalize: true,
+            dtype: Some(Dtype::F16),
+        };
+        let copied = std::sync::Mutex::new(Vec::new());
+        let count = db
+            .clone_collection("docs", "docs_v2", &options, |n| {
+                copied.lock().unwrap().push(n);
+                Ok(())
+            })
+            .await
+            .unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(*copied.lock().unwrap(), vec![2]);
+        assert_eq!(
+            db.collection_metric("docs_v2").await,
+            DistanceMetric::Euclidean
+        );
+
+        // Fresh ids, the same metadata, and the source left alone
+        let clones = db.scroll(Some("docs_v2"), None, 10).await.unwrap().vectors;
+        assert_eq!(clones.len(), 2);
+        assert!(clones
+            .iter()
+            .all(|clone| clone.id != "a" && clone.id != "b"));
+        let first = clones
+            .iter()
+            .find(|clone| clone.metadata.is_some())
+            .unwrap();
+        let mut expected = vec![0.6, 0.8];
+        Dtype::F16.round(&mut expected);
+        assert_eq!(first.data, expected);
+        // Stored at half width, not just rounded
+        let stored = db.storage.get_vector(&first.id).await.unwrap().unwrap();
+        assert_eq!(stored.dtype, Dtype::F16);
+        let source = db.storage.get_vector("a").await.unwrap().unwrap();
+        assert_eq!(source.dtype, Dtype::F32);
+        assert_eq!(
+            db.get_vector("a").await.unwrap().unwrap().data,
+            vec![3.0, 4.0]
+        );
+
+        // Only into a new collection
+        let none = |_| Ok(());
+        assert!(db
+            .clone_collection("docs", "docs_v2", &options, none)
+            .await
+            .is_err());
+        assert!(db
+            .clone_collection("docs", "docs", &options, none)
+            .await
+            .is_err());
+        db.set_alias("latest", "docs_v2").await.unwrap();
+        assert!(db
+            .clone_collection("docs", "latest", &options, none)
+            .await
+            .is_err());
+        // Clones can be taken through an alias
+        assert_eq!(
+            db.clone_collection("latest", "docs_v3", &CloneOptions::default(), none)
+                .await
+                .unwrap(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cluster_writes_assignments() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = open(temp_dir.path()).await;
+        let vectors: Vec<Vector> = (0..40)
+            .map(|i| {
+                let spread = (i / 2) as f32 * 0.01;
+                let data = if i % 2 == 0 {
+                    vec![1.0, spread]
+                } else {
+                    vec![spread, 1.0]
+                };
+                Vector::with_id(format!("{:02}", i), data).with_collection("docs".to_string())
+            })
+            .collect();
+        db.insert_vectors(vectors).await.unwrap();
+        db.insert_vectors(vec![Vector::with_id("other".to_string(), vec![1.0, 1.0])])
+            .await
+            .unwrap();
+
+        let options = ClusterOptions {
+            k: 2,
+            seed: Some(3),
+            ..Default::default()
+        };
+        let clustering = db.cluster("docs", &options, |_| Ok(())).await.unwrap();
+        assert_eq!(clustering.sizes, vec![20, 20]);
+        assert_eq!(
+            db.clustering("docs").await.unwrap(),
+            Some(clustering.clone())
+        );
+        assert!(db.clustering("other").await.unwrap().is_none());
+
+        let cluster_of = |vector: Vector| vector.metadata.unwrap()["cluster"].clone();
+        let even = cluster_of(db.get_vector("00").await.unwrap().unwrap());
+        let odd = cluster_of(db.get_vector("01").await.unwrap().unwrap());
+        assert_ne!(even, odd);
+        assert_eq!(
+            cluster_of(db.get_vector("38").await.unwrap().unwrap()),
+            even
+        );
+        assert_eq!(cluster_of(db.get_vector("39").await.unwrap().unwrap()), odd);
+        assert!(db
+            .get_vector("other")
+            .await
+            .unwrap()
+            .unwrap()
+            .metadata
+            .is_none());
+
+        // Assigning isn't a client write: no new version, history entry or
+        // quota check
+        let stored = db.get_vector("00").await.unwrap().unwrap();
+        assert_eq!(stored.version, 1);
+        assert_eq!(db.get_versions("00").await.unwrap().unwrap().len(), 1);
+        let full = Quota {
+            max_vectors: Some(40),
+            max_bytes: Some(1),
+        };
+        db.set_collection_quota("docs", Some(full)).await.unwrap();
+        let regroup = ClusterOptions {
+            metadata_key: "group".to_string(),
+            ..options.clone()
+        };
+        db.cluster("docs", &regroup, |_| Ok(())).await.unwrap();
+        let stored = db.get_vector("00").await.unwrap().unwrap();
+        assert_eq!(stored.version, 1);
+        assert!(stored.metadata.unwrap().contains_key("group"));
+        db.set_collection_quota("docs", None).await.unwrap();
+
+        // Vectors already in their cluster are left alone
+        db.cluster("docs", &options, |_| Ok(())).await.unwrap();
+        assert_eq!(db.get_vector("00").await.unwrap().unwrap().version, 1);
+
+        let too_many = ClusterOptions {
+            k: 100,
+            ..Default::default()
+        };
+        assert!(db.cluster("docs", &too_many, |_| Ok(())).await.is_err());
     }
 }
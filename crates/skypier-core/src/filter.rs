@@ -0,0 +1,324 @@
+use anyhow::{anyhow, Result};
+use roaring::RoaringBitmap;
+use serde::{Deserialize, Serialize};
+use skypier_index::{IdMapper, SharedIds};
+use std::collections::{HashMap, HashSet};
+
+use crate::Vector;
+
+// Restricts a search to one collection and/or exact metadata values. Every
+// condition must match.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SearchFilter {
+    pub collection: Option<String>,
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+}
+
+impl SearchFilter {
+    pub fn is_empty(&self) -> bool {
+        self.collection.is_none() && self.metadata.is_empty()
+    }
+
+    pub fn matches(&self, vector: &Vector) -> bool {
+        if self.collection.is_some() && vector.collection != self.collection {
+            return false;
+        }
+        self.metadata.iter().all(|(key, value)| {
+            vector
+                .metadata
+                .as_ref()
+                .and_then(|metadata| metadata.get(key))
+                == Some(value)
+        })
+    }
+}
+
+// A filterable property of a vector
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+enum Term {
+    Collection(String),
+    Metadata(String, String),
+}
+
+// Roaring bitmaps of the vectors having each collection and metadata value.
+// Vectors get compact u32 ids so filters intersect cheaply. They come from
+// the main index's ids where it shares them, so a graph search checks each
+// node it visits against the bitmap directly.
+#[derive(Debug)]
+pub struct FilterIndex {
+    ids: SharedIds,
+    holder: u8,
+    terms: HashMap<u32, Vec<Term>>,
+    postings: HashMap<Term, RoaringBitmap>,
+    // The metadata keys indexed in collections with a schema; every key is
+    // in the others
+    indexed_keys: HashMap<String, HashSet<String>>,
+}
+
+// What gets saved with the index snapshot; postings are rebuilt on load
+#[derive(Serialize, Deserialize)]
+struct SavedFilterIndex {
+    ids: IdMapper,
+    terms: HashMap<u32, Vec<Term>>,
+}
+
+impl Default for FilterIndex {
+    fn default() -> Self {
+        Self::with_ids(SharedIds::new())
+    }
+}
+
+impl FilterIndex {
+    pub fn with_ids(ids: SharedIds) -> Self {
+        let holder = ids.register();
+        Self {
+            ids,
+            holder,
+            terms: HashMap::new(),
+            postings: HashMap::new(),
+            indexed_keys: HashMap::new(),
+        }
+    }
+
+    // Whether the bitmaps are keyed by `ids`, e.g. the ones of the index
+    // about to be searched
+    pub fn shares_ids(&self, ids: &SharedIds) -> bool {
+        self.ids.ptr_eq(ids)
+    }
+
+    fn terms(&self, vector: &Vector) -> Vec<Term> {
+        let indexed = vector
+            .collection
+            .as_ref()
+            .and_then(|collection| self.indexed_keys.get(collection));
+        let mut terms: Vec<Term> = vector
+            .metadata
+            .iter()
+            .flatten()
+            .filter(|(key, _)| indexed.is_none_or(|indexed| indexed.contains(*key)))
+            .map(|(key, value)| Term::Metadata(key.clone(), value.clone()))
+            .collect();
+        if let Some(collection) = &vector.collection {
+            terms.push(Term::Collection(collection.clone()));
+        }
+        terms
+    }
+
+    // Indexes only `keys` of the collection's vectors from now on, or every
+    // key again for None. Vectors already indexed keep their terms until
+    // they're inserted again.
+    pub fn set_indexed_keys(&mut self, collection: &str, keys: Option<HashSet<String>>) {
+        match keys {
+            Some(keys) => self.indexed_keys.insert(collection.to_string(), keys),
+            None => self.indexed_keys.remove(collection),
+        };
+    }
+
+    // Whether filters on the key can match the collection's vectors
+    pub fn is_indexed(&self, collection: &str, key: &str) -> bool {
+        self.indexed_keys
+            .get(collection)
+            .is_none_or(|indexed| indexed.contains(key))
+    }
+
+    pub fn insert(&mut self, vector: &Vector) {
+        self.remove(&vector.id);
+
+        let internal = self.ids.acquire(self.holder, &vector.id);
+        let terms = self.terms(vector);
+        for term in &terms {
+            self.postings
+                .entry(term.clone())
+                .or_default()
+                .insert(internal);
+        }
+        self.terms.insert(internal, terms);
+    }
+
+    pub fn remove(&mut self, id: &str) {
+        let Some(internal) = self.ids.release(self.holder, id) else {
+            return;
+        };
+        for term in self.terms.remove(&internal).unwrap_or_default() {
+            if let Some(bitmap) = self.postings.get_mut(&term) {
+                bitmap.remove(internal);
+                if bitmap.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+    }
+
+    // Empties the index, keeping which keys are indexed
+    pub fn clear(&mut self) {
+        self.ids.release_all(self.holder);
+        self.terms.clear();
+        self.postings.clear();
+    }
+
+    // The internal ids of vectors matching every condition of the filter
+    pub fn matching(&self, filter: &SearchFilter) -> RoaringBitmap {
+        let mut required = filter
+            .metadata
+            .iter()
+            .map(|(key, value)| Term::Metadata(key.clone(), value.clone()))
+            .chain(filter.collection.clone().map(Term::Collection))
+            .map(|term| self.postings.get(&term));
+
+        let Some(first) = required.next() else {
+            return self.terms.keys().copied().collect();
+        };
+        let mut matching = first.cloned().unwrap_or_default();
+        for bitmap in required {
+            match bitmap {
+                Some(bitmap) => matching &= bitmap,
+                None => return RoaringBitmap::new(),
+            }
+        }
+        matching
+    }
+
+    // How many of the vectors in `within` have each value of a metadata key.
+    // Values none of them have are left out.
+    pub fn value_counts(&self, key: &str, within: &RoaringBitmap) -> Vec<(String, u64)> {
+        self.postings
+            .iter()
+            .filter_map(|(term, bitmap)| match term {
+                Term::Metadata(term_key, value) if term_key == key => {
+                    let count = bitmap.intersection_len(within);
+                    (count > 0).then(|| (value.clone(), count))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn contains(&self, bitmap: &RoaringBitmap, id: &str) -> bool {
+        self.ids
+            .get(id)
+            .is_some_and(|internal| bitmap.contains(internal))
+    }
+
+    pub fn save(&self) -> Result<Vec<u8>> {
+        let ids = self.ids.read();
+        let capacity = self.terms.keys().max().map_or(0, |&max| max as usize + 1);
+        let mut external = vec![None; capacity];
+        for &internal in self.terms.keys() {
+            external[internal as usize] = ids.external(internal).map(str::to_string);
+        }
+        Ok(serde_json::to_vec(&SavedFilterIndex {
+            ids: external.into(),
+            terms: self.terms.clone(),
+        })?)
+    }
+
+    pub fn load(data: &[u8]) -> Result<Self> {
+        let mut filters = Self::default();
+        filters.restore(data)?;
+        Ok(filters)
+    }
+
+    // `load` in place, keeping which keys are indexed and which ids it
+    // shares. Vectors are numbered by the ids as they are now, not as saved.
+    pub fn restore(&mut self, data: &[u8]) -> Result<()> {
+        let saved: SavedFilterIndex = serde_json::from_slice(data)?;
+        self.clear();
+        for (saved_id, terms) in saved.terms {
+            let id = saved
+                .ids
+                .external(saved_id)
+                .ok_or_else(|| anyhow!("Filter snapshot has terms for an unknown vector"))?;
+            let internal = self.ids.acquire(self.holder, id);
+            for term in &terms {
+                self.postings
+                    .entry(term.clone())
+                    .or_default()
+                    .insert(internal);
+            }
+            self.terms.insert(internal, terms);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vector(id: &str, collection: &str, lang: &str) -> Vector {
+        Vector::with_id(id.to_string(), vec![1.0])
+            .with_collection(collection.to_string())
+            .with_metadata(HashMap::from([("lang".to_string(), lang.to_string())]))
+    }
+
+    #[test]
+    fn test_filter_index() {
+        let mut index = FilterIndex::default();
+        index.insert(&vector("a", "docs", "en"));
+        index.insert(&vector("b", "docs", "fr"));
+        index.insert(&vector("c", "notes", "en"));
+
+        let filter = SearchFilter {
+            collection: Some("docs".to_string()),
+            metadata: HashMap::from([("lang".to_string(), "en".to_string())]),
+        };
+        let matching = index.matching(&filter);
+        assert_eq!(matching.len(), 1);
+        assert!(index.contains(&matching, "a"));
+
+        // Re-inserting moves a vector to its new values
+        index.insert(&vector("b", "docs", "en"));
+        assert_eq!(index.matching(&filter).len(), 2);
+
+        index.remove("a");
+        let restored = FilterIndex::load(&index.save().unwrap()).unwrap();
+        let matching = restored.matching(&filter);
+        assert_eq!(matching.len(), 1);
+        assert!(restored.contains(&matching, "b"));
+        assert_eq!(restored.matching(&SearchFilter::default()).len(), 2);
+
+        let unknown = SearchFilter {
+            collection: Some("missing".to_string()),
+            ..Default::default()
+        };
+        assert!(restored.matching(&unknown).is_empty());
+
+        let mut counts =
+            restored.value_counts("lang", &restored.matching(&SearchFilter::default()));
+        counts.sort();
+        assert_eq!(counts, vec![("en".to_string(), 2)]);
+    }
+
+    #[test]
+    fn test_indexed_keys() {
+        let mut index = FilterIndex::default();
+        index.set_indexed_keys("docs", Some(HashSet::from(["topic".to_string()])));
+        let metadata = HashMap::from([
+            ("lang".to_string(), "en".to_string()),
+            ("topic".to_string(), "rust".to_string()),
+        ]);
+        for (id, collection) in [("a", "docs"), ("b", "notes")] {
+            index.insert(
+                &Vector::with_id(id.to_string(), vec![1.0])
+                    .with_collection(collection.to_string())
+                    .with_metadata(metadata.clone()),
+            );
+        }
+
+        let filter = |key: &str, value: &str| SearchFilter {
+            collection: None,
+            metadata: HashMap::from([(key.to_string(), value.to_string())]),
+        };
+        assert_eq!(index.matching(&filter("topic", "rust")).len(), 2);
+        // Only the collection without a schema has its other keys indexed
+        let matching = index.matching(&filter("lang", "en"));
+        assert_eq!(matching.len(), 1);
+        assert!(index.contains(&matching, "b"));
+        assert!(!index.is_indexed("docs", "lang"));
+        assert!(index.is_indexed("notes", "lang"));
+
+        index.clear();
+        assert!(!index.is_indexed("docs", "lang"));
+    }
+}
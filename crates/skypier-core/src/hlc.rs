@@ -0,0 +1,113 @@
+// Hybrid logical clock. Timestamps are unix milliseconds in the high 48
+// bits and a counter in the low 16, so they order like wall-clock time
+// while still moving forward when the clock stalls or steps back, and stay
+// ahead of every timestamp seen from other nodes.
+
+use anyhow::{anyhow, Result};
+use skypier_storage::time::{SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+const LOGICAL_BITS: u32 = 16;
+
+// How far ahead of the local wall clock an observed timestamp may be. One
+// node with a wild clock can't drag everyone's timestamps along.
+pub const MAX_DRIFT_MS: u64 = 5 * 60 * 1000;
+
+#[derive(Debug, Default)]
+pub struct HybridClock {
+    last: AtomicU64,
+}
+
+impl HybridClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // A timestamp later than every one handed out or observed before
+    pub fn now(&self) -> Result<u64> {
+        let wall = wall_millis() << LOGICAL_BITS;
+        let previous = self
+            .last
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |last| {
+                Some(wall.max(last.checked_add(1)?))
+            })
+            .map_err(|_| anyhow!("The clock has no timestamps left"))?;
+        Ok(wall.max(previous + 1))
+    }
+
+    // Takes in a timestamp from elsewhere, e.g. a replicated write, so
+    // later local ones follow it. Refuses ones more than MAX_DRIFT_MS ahead
+    // of the wall clock.
+    pub fn observe(&self, timestamp: u64) -> Result<()> {
+        let limit = wall_millis().saturating_add(MAX_DRIFT_MS);
+        if physical_millis(timestamp) > limit {
+            return Err(anyhow!(
+                "Timestamp {} is more than {}ms ahead of the local clock",
+                timestamp,
+                MAX_DRIFT_MS
+            ));
+        }
+        self.advance(timestamp);
+        Ok(())
+    }
+
+    // Moves past a timestamp this node already holds, such as a stored
+    // record's, however far ahead of the wall clock it is: it was accepted
+    // once, and the wall clock may have been stepped back since
+    pub fn advance(&self, timestamp: u64) {
+        self.last.fetch_max(timestamp, Ordering::AcqRel);
+    }
+}
+
+// The wall-clock part of `timestamp`, in unix milliseconds
+pub fn physical_millis(timestamp: u64) -> u64 {
+    timestamp >> LOGICAL_BITS
+}
+
+fn wall_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timestamps_move_forward() {
+        let clock = HybridClock::new();
+        let first = clock.now().unwrap();
+        assert!(physical_millis(first).abs_diff(wall_millis()) < 1000);
+        let second = clock.now().unwrap();
+        assert!(second > first);
+
+        // A node whose clock runs ahead pulls this one along
+        let ahead = (wall_millis() + 60_000) << LOGICAL_BITS;
+        clock.observe(ahead).unwrap();
+        assert_eq!(clock.now().unwrap(), ahead + 1);
+        clock.observe(first).unwrap();
+        assert_eq!(clock.now().unwrap(), ahead + 2);
+    }
+
+    #[test]
+    fn test_timestamps_too_far_ahead_are_refused() {
+        let clock = HybridClock::new();
+        let before = clock.now().unwrap();
+        assert!(clock.observe(u64::MAX).is_err());
+        let too_far = (wall_millis() + MAX_DRIFT_MS + 60_000) << LOGICAL_BITS;
+        assert!(clock.observe(too_far).is_err());
+        let after = clock.now().unwrap();
+        assert!(after > before);
+        assert!(physical_millis(after).abs_diff(wall_millis()) < 1000);
+
+        // Ones already held are followed anyway
+        clock.advance(too_far);
+        assert!(clock.now().unwrap() > too_far);
+
+        // Even a clock that got to the end stops instead of wrapping around
+        clock.last.store(u64::MAX, Ordering::Release);
+        assert!(clock.now().is_err());
+    }
+}
@@ -0,0 +1,103 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use skypier_storage::time::SystemTime;
+use std::sync::Mutex;
+
+// Crockford's base32, which ULIDs are written in
+const ULID_ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+// Auto-increment ids are zero-padded to this many digits, so storage keys
+// sort in numeric order
+const SEQUENCE_DIGITS: usize = 20;
+
+// How ids are made for vectors inserted without one
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IdScheme {
+    // Random, so inserts land all over the key space
+    #[default]
+    Uuid,
+    // Time-ordered, so recent inserts sit together and range scans over
+    // them stay local
+    Ulid,
+    // 1, 2, 3, ... per collection
+    AutoIncrement,
+}
+
+impl std::str::FromStr for IdScheme {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "uuid" => Ok(Self::Uuid),
+            "ulid" => Ok(Self::Ulid),
+            "auto_increment" => Ok(Self::AutoIncrement),
+            other => Err(anyhow!("Unknown id scheme '{}'", other)),
+        }
+    }
+}
+
+// The storage key of the nth auto-increment id
+pub fn sequence_id(n: u64) -> String {
+    format!("{:0width$}", n, width = SEQUENCE_DIGITS)
+}
+
+// Makes ULIDs that keep increasing within the process, even when several
+// are made in the same millisecond
+#[derive(Default)]
+pub struct UlidGenerator {
+    // Millisecond and random part of the last ULID made
+    last: Mutex<(u64, u128)>,
+}
+
+impl UlidGenerator {
+    pub fn generate(&self) -> String {
+        let millis = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as u64)
+            .unwrap_or(0);
+        let mut last = self.last.lock().unwrap();
+        let (millis, random) = if millis <= last.0 {
+            // Same millisecond, or the clock went back: count up from the
+            // last one instead. 80 random bits don't run out in practice.
+            (last.0, last.1 + 1)
+        } else {
+            let random = uuid::Uuid::new_v4().as_u128() & ((1 << 80) - 1);
+            (millis, random)
+        };
+        *last = (millis, random);
+        encode_ulid(millis, random)
+    }
+}
+
+fn encode_ulid(millis: u64, random: u128) -> String {
+    let value = (u128::from(millis) << 80) | random;
+    // 26 characters of 5 bits cover the 128
+    (0..26)
+        .rev()
+        .map(|i| ULID_ALPHABET[((value >> (i * 5)) & 0x1f) as usize] as char)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generated_ids_sort_in_creation_order() {
+        let generator = UlidGenerator::default();
+        let ids: Vec<String> = (0..1000).map(|_| generator.generate()).collect();
+        assert!(ids.iter().all(|id| id.len() == 26));
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(encode_ulid(0, 0), "0".repeat(26));
+        // The timestamp takes the first 10 characters
+        assert_eq!(
+            encode_ulid(1, 0),
+            format!("{}1{}", "0".repeat(9), "0".repeat(16))
+        );
+
+        assert_eq!(sequence_id(42), "00000000000000000042");
+        assert!(sequence_id(9) < sequence_id(10));
+        assert_eq!("ulid".parse::<IdScheme>().unwrap(), IdScheme::Ulid);
+        assert!("serial".parse::<IdScheme>().is_err());
+    }
+}
@@ -0,0 +1,263 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+use tracing::{info, warn};
+
+// Finished jobs kept for polling; the oldest are dropped past this
+const MAX_FINISHED_JOBS: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    pub id: String,
+    // What the job does, e.g. "reindex"
+    pub kind: String,
+    pub namespace: String,
+    pub status: JobStatus,
+    // Units of work done so far, out of `total` when it's known up front
+    pub processed: u64,
+    pub total: Option<u64>,
+    pub error: Option<String>,
+    // Set once cancellation was asked for, until the job notices
+    #[serde(default)]
+    pub cancel_requested: bool,
+    pub started_at: u64,
+    pub finished_at: Option<u64>,
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+struct Entry {
+    job: Job,
+    cancelled: Arc<AtomicBool>,
+}
+
+// Runs long operations on their own tasks and keeps their status in memory
+// until restart, so requests can start them and poll for the outcome
+#[derive(Default)]
+pub struct JobManager {
+    jobs: RwLock<HashMap<String, Entry>>,
+}
+
+// Handed to a running job to report progress and notice cancellation
+pub struct JobContext {
+    jobs: Arc<JobManager>,
+    id: String,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl JobContext {
+    pub fn set_progress(&self, processed: u64) {
+        self.jobs.update(&self.id, |job| job.processed = processed);
+    }
+
+    pub fn set_total(&self, total: u64) {
+        self.jobs.update(&self.id, |job| job.total = Some(total));
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Acquire)
+    }
+
+    // For jobs to call between steps they can safely stop after
+    pub fn check_cancelled(&self) -> Result<()> {
+        if self.is_cancelled() {
+            return Err(anyhow!("Job {} was cancelled", self.id));
+        }
+        Ok(())
+    }
+}
+
+impl JobManager {
+    fn update(&self, id: &str, change: impl FnOnce(&mut Job)) {
+        if let Some(entry) = self.jobs.write().unwrap().get_mut(id) {
+            change(&mut entry.job);
+        }
+    }
+
+    // Drops the oldest finished jobs past MAX_FINISHED_JOBS
+    fn prune(jobs: &mut HashMap<String, Entry>) {
+        let mut finished: Vec<(u64, String)> = jobs
+            .values()
+            .filter_map(|entry| Some((entry.job.finished_at?, entry.job.id.clone())))
+            .collect();
+        if finished.len() <= MAX_FINISHED_JOBS {
+            return;
+        }
+        finished.sort();
+        for (_, id) in &finished[..finished.len() - MAX_FINISHED_JOBS] {
+            jobs.remove(id);
+        }
+    }
+
+    // Runs `run` on its own task and returns the job as it starts. A job
+    // that fails after being cancelled ends up Cancelled rather than Failed.
+    pub fn start<F, Fut>(
+        self: &Arc<Self>,
+        kind: &str,
+        namespace: &str,
+        total: Option<u64>,
+        run: F,
+    ) -> Job
+    where
+        F: FnOnce(JobContext) -> Fut,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let job = Job {
+            id: uuid::Uuid::new_v4().to_string(),
+            kind: kind.to_string(),
+            namespace: namespace.to_string(),
+            status: JobStatus::Running,
+            processed: 0,
+            total,
+            error: None,
+            cancel_requested: false,
+            started_at: unix_now(),
+            finished_at: None,
+        };
+        let cancelled = Arc::new(AtomicBool::new(false));
+        self.jobs.write().unwrap().insert(
+            job.id.clone(),
+            Entry {
+                job: job.clone(),
+                cancelled: Arc::clone(&cancelled),
+            },
+        );
+
+        let future = run(JobContext {
+            jobs: Arc::clone(self),
+            id: job.id.clone(),
+            cancelled: Arc::clone(&cancelled),
+        });
+        let jobs = Arc::clone(self);
+        let id = job.id.clone();
+        tokio::spawn(async move {
+            let result = future.await;
+            let mut entries = jobs.jobs.write().unwrap();
+            if let Some(entry) = entries.get_mut(&id) {
+                let job = &mut entry.job;
+                job.finished_at = Some(unix_now());
+                match result {
+                    Ok(()) => {
+                        info!("Job {} ({}) completed", job.id, job.kind);
+                        job.status = JobStatus::Completed;
+                    }
+                    Err(_) if cancelled.load(Ordering::Acquire) => {
+                        info!("Job {} ({}) cancelled", job.id, job.kind);
+                        job.status = JobStatus::Cancelled;
+                    }
+                    Err(e) => {
+                        warn!("Job {} ({}) failed: {}", job.id, job.kind, e);
+                        job.status = JobStatus::Failed;
+                        job.error = Some(e.to_string());
+                    }
+                }
+            }
+            Self::prune(&mut entries);
+        });
+        job
+    }
+
+    pub fn get(&self, id: &str) -> Option<Job> {
+        self.jobs
+            .read()
+            .unwrap()
+            .get(id)
+            .map(|entry| entry.job.clone())
+    }
+
+    // Newest first
+    pub fn list(&self) -> Vec<Job> {
+        let mut jobs: Vec<Job> = self
+            .jobs
+            .read()
+            .unwrap()
+            .values()
+            .map(|entry| entry.job.clone())
+            .collect();
+        jobs.sort_by(|a, b| b.started_at.cmp(&a.started_at).then(a.id.cmp(&b.id)));
+        jobs
+    }
+
+    // Asks a running job to stop; it does at its next safe point. None if
+    // there's no such job.
+    pub fn cancel(&self, id: &str) -> Option<Job> {
+        let mut jobs = self.jobs.write().unwrap();
+        let entry = jobs.get_mut(id)?;
+        if entry.job.status == JobStatus::Running {
+            entry.cancelled.store(true, Ordering::Release);
+            entry.job.cancel_requested = true;
+        }
+        Some(entry.job.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn finished(jobs: &JobManager, id: &str) -> Job {
+        loop {
+            let job = jobs.get(id).unwrap();
+            if job.status != JobStatus::Running {
+                return job;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_jobs_track_progress_errors_and_cancellation() {
+        let jobs = Arc::new(JobManager::default());
+        let job = jobs.start("count", "default", Some(3), |context| async move {
+            for i in 1..=3 {
+                context.set_progress(i);
+            }
+            Ok(())
+        });
+        assert_eq!(job.status, JobStatus::Running);
+        let done = finished(&jobs, &job.id).await;
+        assert_eq!(done.status, JobStatus::Completed);
+        assert_eq!(done.processed, 3);
+        assert!(done.finished_at.is_some());
+
+        let job = jobs.start("broken", "default", None, |_| async {
+            Err(anyhow!("disk full"))
+        });
+        let failed = finished(&jobs, &job.id).await;
+        assert_eq!(failed.status, JobStatus::Failed);
+        assert_eq!(failed.error.as_deref(), Some("disk full"));
+
+        let job = jobs.start("endless", "default", None, |context| async move {
+            loop {
+                context.check_cancelled()?;
+                tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+            }
+        });
+        assert!(jobs.cancel(&job.id).unwrap().cancel_requested);
+        let cancelled = finished(&jobs, &job.id).await;
+        assert_eq!(cancelled.status, JobStatus::Cancelled);
+        assert!(cancelled.error.is_none());
+
+        assert_eq!(jobs.list().len(), 3);
+        assert!(jobs.get("missing").is_none());
+        assert!(jobs.cancel("missing").is_none());
+    }
+}
@@ -2,17 +2,145 @@ use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+pub mod clustering;
+pub mod conflict;
 pub mod database;
+pub mod filter;
+pub mod hlc;
+pub mod ids;
+#[cfg(feature = "runtime")]
+pub mod jobs;
+pub mod plugin;
+pub mod quota;
+pub mod schema;
+pub mod signing;
 pub mod similarity;
+pub mod validation;
 
+pub use clustering::{ClusterOptions, Clustering};
+pub use conflict::{Conflict, ConflictPolicy, ConflictResolver, ConflictSide, Resolution};
 pub use database::VectorDatabase;
-pub use skypier_storage::Vector;
+pub use filter::SearchFilter;
+pub use hlc::HybridClock;
+pub use ids::IdScheme;
+#[cfg(feature = "runtime")]
+pub use jobs::{Job, JobContext, JobManager, JobStatus};
+pub use plugin::VectorPlugin;
+pub use quota::{Quota, QuotaExceeded, QuotaLimit, Usage};
+pub use schema::{FieldError, FieldSchema, FieldType, MetadataSchema};
+pub use skypier_storage::{
+    AuditEntry, CollectionStats, CorruptRecord, Dtype, RecordSignature, SnapshotInfo, SparseVector,
+    Vector, VerifyReport,
+};
+pub use validation::{ValidationError, ValidationLimits};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SearchResult {
     pub id: String,
     pub score: f32,
     pub metadata: Option<HashMap<String, String>>,
+    // The stored vector the result was scored against, when the search
+    // asked for it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vector: Option<Vec<f32>>,
+}
+
+// Results sharing a value of the field searches were grouped by
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchGroup {
+    pub value: String,
+    pub hits: Vec<SearchResult>,
+}
+
+// A page of vectors in id order. Pass `next` back as `after` for the
+// following page; it's None once there are no more.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ScrollPage {
+    pub vectors: Vec<Vector>,
+    pub next: Option<String>,
+}
+
+// Why a vector in an insert batch would be rejected, by its position in the
+// batch
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RowError {
+    pub index: usize,
+    pub id: String,
+    pub error: String,
+}
+
+// What inserting a batch would do, found without writing it
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BatchCheck {
+    pub checked: usize,
+    pub errors: Vec<RowError>,
+    // Collections that don't exist yet and the batch would create
+    pub new_collections: Vec<String>,
+}
+
+// How many vectors a dense search went through and where its time went,
+// in microseconds, for the slow query log
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SearchProfile {
+    // Vectors the filter allowed; None without a filter
+    pub filter_matches: Option<u64>,
+    // Hits from the index, or the exact scan, before the threshold
+    pub candidates: usize,
+    pub results: usize,
+    pub filter_us: u64,
+    pub index_us: u64,
+    // Loading candidates from storage and rescoring them
+    pub fetch_us: u64,
+}
+
+// Vectors per value of a metadata key, counted from the filter index
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Aggregate {
+    // Vectors matching the filter, and how many of them lack the key
+    pub total: u64,
+    pub missing: u64,
+    // Most common first
+    pub values: Vec<ValueCount>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ValueCount {
+    pub value: String,
+    pub count: u64,
+}
+
+// Up to `group_size` hits per distinct value of a metadata field, so one
+// document's chunks don't crowd out the rest
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Grouping {
+    pub field: String,
+    pub group_size: usize,
+}
+
+impl Grouping {
+    // Takes results best first; ones without the field are left out
+    pub fn apply(&self, results: Vec<SearchResult>, groups: usize) -> Vec<SearchGroup> {
+        let mut grouped: Vec<SearchGroup> = Vec::new();
+        for result in results {
+            let Some(value) = result
+                .metadata
+                .as_ref()
+                .and_then(|metadata| metadata.get(&self.field))
+            else {
+                continue;
+            };
+            match grouped.iter().position(|group| &group.value == value) {
+                Some(i) if grouped[i].hits.len() < self.group_size => grouped[i].hits.push(result),
+                Some(_) => {}
+                None if grouped.len() < groups => grouped.push(SearchGroup {
+                    value: value.clone(),
+                    hits: vec![result],
+                }),
+                None => {}
+            }
+        }
+        grouped
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -20,10 +148,263 @@ pub struct DatabaseStats {
     pub total_vectors: usize,
     pub dimensions: usize,
     pub storage_size_bytes: usize,
+    // Vector records before and after compression
+    pub raw_vector_bytes: usize,
+    pub stored_vector_bytes: usize,
+    // Why the last background build of the main index failed, if it did
+    pub index_build_error: Option<String>,
 }
 
+// Returned for client writes to a follower, which only takes changes
+// replicated from its primary
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("this node is a read-only follower; send writes to its primary")]
+pub struct ReadOnlyError;
+
+// Vectors written and ids deleted between two WAL positions, ending at `seq`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChangeSet {
+    pub seq: u64,
+    pub upserts: Vec<Vector>,
+    pub deletes: Vec<String>,
+}
+
+// Published to subscribers after each write, see `VectorDatabase::subscribe`.
+// `seq` is the change's WAL position, to resume from with `changes_since`.
+// `hlc` orders changes across nodes: replicated writes keep the timestamp
+// they were made with, so merged feeds sort by it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChangeEvent {
+    pub seq: u64,
+    pub kind: ChangeKind,
+    pub id: String,
+    pub collection: Option<String>,
+    pub timestamp: u64,
+    #[serde(default)]
+    pub hlc: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Insert,
+    Update,
+    Delete,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SnapshotDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+// How results with equal scores are ordered. Ties that remain after the
+// primary key (e.g. identical created_at) always fall back to the id.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TieBreak {
+    #[default]
+    Id,
+    CreatedAt,
+}
+
+impl std::str::FromStr for TieBreak {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "id" => Ok(TieBreak::Id),
+            "created_at" => Ok(TieBreak::CreatedAt),
+            other => Err(anyhow!("Unknown tie-break mode: {}", other)),
+        }
+    }
+}
+
+// What's done to each vector as a collection is cloned. `dtype` rounds the
+// copies' values to that precision, e.g. to measure recall at half
+// precision; how they're stored still follows the target's configured
+// dtype.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CloneOptions {
+    #[serde(default)]
+    pub normalize: bool,
+    #[serde(default)]
+    pub dtype: Option<Dtype>,
+}
+
+// How a search is run beyond its query, filter and limits
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SearchOptions {
+    // Search a named vector's index instead of the one for `data`
+    pub vector_name: Option<String>,
+    pub rerank: Option<Rerank>,
+    // Score every stored vector instead of asking the index, for
+    // ground-truth results
+    #[serde(default)]
+    pub exact: bool,
+    // Model that embedded the query. Stored vectors tagged with another one
+    // are left out.
+    #[serde(default)]
+    pub model: Option<String>,
+    // Stops the search early, failing it with `skypier_index::Cancelled`.
+    // A search also stops once its future is dropped, e.g. by a timeout.
+    #[serde(skip)]
+    pub cancel: Option<skypier_index::CancellationToken>,
+    #[serde(default)]
+    pub score_mode: ScoreMode,
+    // Leaves out results further than this from the query, in the metric's
+    // distance whatever the score mode, on top of the threshold
+    #[serde(default)]
+    pub max_distance: Option<f32>,
+    // Return each result's stored vector along with it
+    #[serde(default)]
+    pub include_vector: bool,
+}
+
+// How search results report closeness. `Raw` is the metric's own score:
+// cosine similarity, 1 / (1 + euclidean distance) or the dot product.
+// `Normalized` maps it onto 0-1, higher closer, squashing dot products with
+// a logistic curve; `Distance` is the metric's distance, lower closer. A
+// search's threshold is read in the same mode as its scores, so with
+// `Distance` it's a maximum.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScoreMode {
+    #[default]
+    Raw,
+    Normalized,
+    Distance,
+}
+
+impl ScoreMode {
+    // The threshold when none is given: 0 for scores, as it's always been,
+    // and none at all for distances
+    pub fn default_threshold(self) -> f32 {
+        match self {
+            ScoreMode::Distance => f32::INFINITY,
+            _ => 0.0,
+        }
+    }
+}
+
+// A second search pass: `fetch_factor` times k candidates come from the
+// index and are rescored exactly from their stored vectors, then each boost
+// adds `weight` times a numeric metadata field before the top k are kept
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rerank {
+    pub fetch_factor: usize,
+    #[serde(default)]
+    pub boosts: Vec<MetadataBoost>,
+}
+
+impl Default for Rerank {
+    fn default() -> Self {
+        Self {
+            fetch_factor: 4,
+            boosts: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MetadataBoost {
+    pub field: String,
+    pub weight: f32,
+}
+
+impl MetadataBoost {
+    // Missing or non-numeric fields add nothing
+    pub fn apply(&self, metadata: Option<&HashMap<String, String>>) -> f32 {
+        metadata
+            .and_then(|metadata| metadata.get(&self.field))
+            .and_then(|value| value.parse::<f32>().ok())
+            .map_or(0.0, |value| self.weight * value)
+    }
+}
+
+// How a hybrid search merges its dense and sparse hits. Reciprocal rank
+// fusion scores a hit 1 / (k + rank) in each list it's in, so the two
+// score scales don't matter; a weighted sum adds the raw scores.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum Fusion {
+    Rrf {
+        #[serde(default = "default_rrf_k")]
+        k: f32,
+    },
+    Weighted {
+        dense: f32,
+        sparse: f32,
+    },
+}
+
+fn default_rrf_k() -> f32 {
+    60.0
+}
+
+impl Default for Fusion {
+    fn default() -> Self {
+        Fusion::Rrf { k: default_rrf_k() }
+    }
+}
+
+impl Fusion {
+    // Both lists are (id, score), best first. Returns fused scores by id,
+    // unsorted; an id missing from one list gets nothing from it.
+    pub fn fuse<'a>(
+        &self,
+        dense: impl IntoIterator<Item = (&'a str, f32)>,
+        sparse: impl IntoIterator<Item = (&'a str, f32)>,
+    ) -> Vec<(String, f32)> {
+        let (dense_weight, sparse_weight) = match self {
+            Fusion::Rrf { .. } => (1.0, 1.0),
+            Fusion::Weighted { dense, sparse } => (*dense, *sparse),
+        };
+        let mut fused: HashMap<&str, f32> = HashMap::new();
+        let mut add = |rank: usize, id: &'a str, score: f32, weight: f32| {
+            *fused.entry(id).or_default() += match self {
+                Fusion::Rrf { k } => weight / (k + rank as f32 + 1.0),
+                Fusion::Weighted { .. } => weight * score,
+            };
+        };
+        for (rank, (id, score)) in dense.into_iter().enumerate() {
+            add(rank, id, score, dense_weight);
+        }
+        for (rank, (id, score)) in sparse.into_iter().enumerate() {
+            add(rank, id, score, sparse_weight);
+        }
+        fused
+            .into_iter()
+            .map(|(id, score)| (id.to_string(), score))
+            .collect()
+    }
+}
+
+// What happens to an insert whose nearest neighbour in its collection
+// scores at least `threshold`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Dedup {
+    pub threshold: f32,
+    #[serde(default)]
+    pub action: DedupAction,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DedupAction {
+    // Fails the batch, like other invalid input
+    #[default]
+    Reject,
+    // Adds the new vector's metadata to the neighbour instead of writing it;
+    // keys the neighbour already has keep their values
+    Merge,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum DistanceMetric {
+    #[default]
     Cosine,
     Euclidean,
     DotProduct,
@@ -41,6 +422,66 @@ impl DistanceMetric {
             DistanceMetric::DotProduct => similarity::dot_product(a, b),
         }
     }
+
+    // Like `compute`, but higher is always closer
+    pub fn score(&self, a: &[f32], b: &[f32]) -> Result<f32> {
+        let value = self.compute(a, b)?;
+        Ok(match self {
+            DistanceMetric::Euclidean => 1.0 / (1.0 + value),
+            _ => value,
+        })
+    }
+
+    // A score from `score` as reported in `mode`
+    pub fn convert_score(&self, score: f32, mode: ScoreMode) -> f32 {
+        match (mode, self) {
+            (ScoreMode::Raw, _) | (ScoreMode::Normalized, DistanceMetric::Euclidean) => score,
+            (ScoreMode::Normalized, DistanceMetric::Cosine) => (score + 1.0) / 2.0,
+            (ScoreMode::Normalized, DistanceMetric::DotProduct) => 1.0 / (1.0 + (-score).exp()),
+            (ScoreMode::Distance, DistanceMetric::Cosine) => 1.0 - score,
+            (ScoreMode::Distance, DistanceMetric::Euclidean) => 1.0 / score - 1.0,
+            (ScoreMode::Distance, DistanceMetric::DotProduct) => -score,
+        }
+    }
+
+    // The inverse of `convert_score`, to compare a threshold given in
+    // `mode` with scores from `score`. Values past a mode's range let
+    // everything or nothing through.
+    pub fn raw_score(&self, value: f32, mode: ScoreMode) -> f32 {
+        match (mode, self) {
+            (ScoreMode::Raw, _) | (ScoreMode::Normalized, DistanceMetric::Euclidean) => value,
+            (ScoreMode::Normalized, DistanceMetric::Cosine) => value * 2.0 - 1.0,
+            (ScoreMode::Normalized, DistanceMetric::DotProduct) => {
+                let value = value.clamp(0.0, 1.0);
+                (value / (1.0 - value)).ln()
+            }
+            (ScoreMode::Distance, DistanceMetric::Cosine) => 1.0 - value,
+            (ScoreMode::Distance, DistanceMetric::Euclidean) => 1.0 / (1.0 + value.max(0.0)),
+            (ScoreMode::Distance, DistanceMetric::DotProduct) => -value,
+        }
+    }
+
+    // The index metric scoring the same way as `score`
+    pub fn index_metric(&self) -> skypier_index::Metric {
+        match self {
+            DistanceMetric::Cosine => skypier_index::Metric::Cosine,
+            DistanceMetric::Euclidean => skypier_index::Metric::Euclidean,
+            DistanceMetric::DotProduct => skypier_index::Metric::DotProduct,
+        }
+    }
+}
+
+impl std::str::FromStr for DistanceMetric {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "cosine" => Ok(DistanceMetric::Cosine),
+            "euclidean" => Ok(DistanceMetric::Euclidean),
+            "dot_product" => Ok(DistanceMetric::DotProduct),
+            other => Err(anyhow!("Unknown distance metric: {}", other)),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -48,8 +489,115 @@ mod tests {
     use super::*;
 
     #[test]
-    fn it_works() {
-        let result = add(2, 2);
-        assert_eq!(result, 4);
+    fn test_distance_metric_compute() {
+        let a = vec![1.0, 0.0];
+        let b = vec![0.0, 1.0];
+        assert!(DistanceMetric::Cosine.compute(&a, &b).unwrap().abs() < 1e-6);
+        assert!((DistanceMetric::Euclidean.compute(&a, &b).unwrap() - 2.0_f32.sqrt()).abs() < 1e-6);
+        assert!(DistanceMetric::DotProduct.compute(&a, &[1.0]).is_err());
+        assert!((DistanceMetric::Euclidean.score(&a, &a).unwrap() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_score_modes() {
+        let (a, b) = ([1.0, 0.0], [0.0, -2.0]);
+        let modes = [ScoreMode::Raw, ScoreMode::Normalized, ScoreMode::Distance];
+        for metric in [
+            DistanceMetric::Cosine,
+            DistanceMetric::Euclidean,
+            DistanceMetric::DotProduct,
+        ] {
+            let score = metric.score(&a, &b).unwrap();
+            for mode in modes {
+                let converted = metric.convert_score(score, mode);
+                assert!((metric.raw_score(converted, mode) - score).abs() < 1e-5);
+            }
+            let normalized = metric.convert_score(score, ScoreMode::Normalized);
+            assert!((0.0..=1.0).contains(&normalized));
+            // No default threshold leaves anything out
+            for mode in modes {
+                if mode != ScoreMode::Raw {
+                    assert!(metric.raw_score(mode.default_threshold(), mode) <= score);
+                }
+            }
+        }
+        let distance = DistanceMetric::Euclidean.convert_score(
+            DistanceMetric::Euclidean.score(&a, &b).unwrap(),
+            ScoreMode::Distance,
+        );
+        assert!((distance - 5.0_f32.sqrt()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_grouping() {
+        let result = |id: &str, doc: Option<&str>| SearchResult {
+            id: id.to_string(),
+            score: 1.0,
+            metadata: doc.map(|doc| HashMap::from([("doc".to_string(), doc.to_string())])),
+            vector: None,
+        };
+        let grouping = Grouping {
+            field: "doc".to_string(),
+            group_size: 2,
+        };
+        let groups = grouping.apply(
+            vec![
+                result("a1", Some("a")),
+                result("a2", Some("a")),
+                result("x", None),
+                result("b1", Some("b")),
+                result("a3", Some("a")),
+                result("c1", Some("c")),
+            ],
+            2,
+        );
+        let groups: Vec<(&str, Vec<&str>)> = groups
+            .iter()
+            .map(|group| {
+                let ids = group.hits.iter().map(|hit| hit.id.as_str()).collect();
+                (group.value.as_str(), ids)
+            })
+            .collect();
+        assert_eq!(groups, vec![("a", vec!["a1", "a2"]), ("b", vec!["b1"])]);
+    }
+
+    #[test]
+    fn test_fusion() {
+        let dense = [("a", 0.9), ("b", 0.8)];
+        let sparse = [("b", 12.0), ("c", 3.0)];
+        let ranked = |fusion: Fusion| {
+            let mut fused = fusion.fuse(dense, sparse);
+            fused.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+            fused.into_iter().map(|(id, _)| id).collect::<Vec<_>>()
+        };
+
+        // "b" is in both lists
+        assert_eq!(ranked(Fusion::default()), vec!["b", "a", "c"]);
+        let weighted = Fusion::Weighted {
+            dense: 1.0,
+            sparse: 0.1,
+        };
+        assert_eq!(ranked(weighted), vec!["b", "a", "c"]);
+        let dense_only = Fusion::Weighted {
+            dense: 1.0,
+            sparse: 0.0,
+        };
+        assert_eq!(ranked(dense_only), vec!["a", "b", "c"]);
+
+        let parsed: Fusion = serde_json::from_str(r#"{"method": "rrf"}"#).unwrap();
+        assert_eq!(parsed, Fusion::default());
+    }
+
+    #[test]
+    fn test_metadata_boost() {
+        let boost = MetadataBoost {
+            field: "priority".to_string(),
+            weight: 0.5,
+        };
+        let metadata = HashMap::from([("priority".to_string(), "0.4".to_string())]);
+        assert!((boost.apply(Some(&metadata)) - 0.2).abs() < 1e-6);
+        let metadata = HashMap::from([("priority".to_string(), "high".to_string())]);
+        assert_eq!(boost.apply(Some(&metadata)), 0.0);
+        assert_eq!(boost.apply(None), 0.0);
     }
 }
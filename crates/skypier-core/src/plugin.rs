@@ -0,0 +1,106 @@
+use anyhow::Result;
+
+use crate::{SearchResult, Vector};
+
+// Hooks for embedding applications to add validation, enrichment or audit
+// logic without forking the crate. Every hook defaults to a no-op, and an
+// error returned from any hook aborts the operation that triggered it.
+pub trait VectorPlugin: Send + Sync {
+    fn name(&self) -> &str;
+
+    // Runs before a vector is written; may modify it (e.g. add metadata)
+    fn on_insert(&self, _vector: &mut Vector) -> Result<()> {
+        Ok(())
+    }
+
+    // Runs before a vector is deleted
+    fn on_delete(&self, _id: &str) -> Result<()> {
+        Ok(())
+    }
+
+    // Runs on the final result list of a search; may filter or reorder it
+    fn on_search(&self, _query: &[f32], _results: &mut Vec<SearchResult>) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VectorDatabase;
+    use anyhow::anyhow;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct AuditPlugin {
+        events: Mutex<Vec<String>>,
+    }
+
+    impl VectorPlugin for AuditPlugin {
+        fn name(&self) -> &str {
+            "audit"
+        }
+
+        fn on_insert(&self, vector: &mut Vector) -> Result<()> {
+            if vector.data.iter().all(|x| *x == 0.0) {
+                return Err(anyhow!("zero vectors are not allowed"));
+            }
+            vector
+                .metadata
+                .get_or_insert_with(Default::default)
+                .insert("audited".to_string(), "true".to_string());
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("insert {}", vector.id));
+            Ok(())
+        }
+
+        fn on_delete(&self, id: &str) -> Result<()> {
+            self.events.lock().unwrap().push(format!("delete {}", id));
+            Ok(())
+        }
+
+        fn on_search(&self, _query: &[f32], results: &mut Vec<SearchResult>) -> Result<()> {
+            results.retain(|r| r.id != "hidden");
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_plugin_hooks() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut db = VectorDatabase::new(temp_dir.path().to_str().unwrap())
+            .await
+            .unwrap();
+        let plugin = Arc::new(AuditPlugin::default());
+        db.register_plugin(plugin.clone());
+
+        db.insert_vectors(vec![
+            Vector::with_id("a".to_string(), vec![1.0, 0.0]),
+            Vector::with_id("hidden".to_string(), vec![1.0, 0.0]),
+        ])
+        .await
+        .unwrap();
+        assert!(db
+            .insert_vectors(vec![Vector::with_id("z".to_string(), vec![0.0, 0.0])])
+            .await
+            .is_err());
+
+        let stored = db.get_vector("a").await.unwrap().unwrap();
+        assert_eq!(
+            stored.metadata.unwrap().get("audited").map(String::as_str),
+            Some("true")
+        );
+
+        let results = db.search(&[1.0, 0.0], 10, 0.0).await.unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].id, "a");
+
+        db.delete_vector("a").await.unwrap();
+        assert_eq!(
+            *plugin.events.lock().unwrap(),
+            vec!["insert a", "insert hidden", "delete a"]
+        );
+    }
+}
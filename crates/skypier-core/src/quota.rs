@@ -0,0 +1,111 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// Limits on what a collection or a namespace may hold. Bytes are counted as
+// stored, after compression.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Quota {
+    #[serde(default)]
+    pub max_vectors: Option<u64>,
+    #[serde(default)]
+    pub max_bytes: Option<u64>,
+}
+
+// What a collection or namespace holds, or what a write adds to it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Usage {
+    pub vectors: u64,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaLimit {
+    Vectors,
+    Bytes,
+}
+
+impl fmt::Display for QuotaLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            QuotaLimit::Vectors => "vectors",
+            QuotaLimit::Bytes => "bytes",
+        })
+    }
+}
+
+// A write refused because it would take `scope` past its quota. `usage` is
+// what it holds without the write.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error(
+    "{scope} is limited to {max} {limit} and holds {} vectors in {} bytes",
+    usage.vectors,
+    usage.bytes
+)]
+pub struct QuotaExceeded {
+    pub scope: String,
+    pub limit: QuotaLimit,
+    pub max: u64,
+    pub usage: Usage,
+}
+
+impl Quota {
+    pub fn is_empty(&self) -> bool {
+        self.max_vectors.is_none() && self.max_bytes.is_none()
+    }
+
+    // Whether `scope` can take `added` on top of `usage`
+    pub fn check(&self, scope: &str, usage: Usage, added: Usage) -> Result<(), QuotaExceeded> {
+        let exceeded = |limit, max| QuotaExceeded {
+            scope: scope.to_string(),
+            limit,
+            max,
+            usage,
+        };
+        if let Some(max) = self.max_vectors {
+            if added.vectors > 0 && usage.vectors + added.vectors > max {
+                return Err(exceeded(QuotaLimit::Vectors, max));
+            }
+        }
+        if let Some(max) = self.max_bytes {
+            if added.bytes > 0 && usage.bytes + added.bytes > max {
+                return Err(exceeded(QuotaLimit::Bytes, max));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quota_check() {
+        let quota = Quota {
+            max_vectors: Some(2),
+            max_bytes: Some(100),
+        };
+        let usage = Usage {
+            vectors: 1,
+            bytes: 60,
+        };
+        let add = |vectors, bytes| Usage { vectors, bytes };
+        assert!(quota.check("docs", usage, add(1, 40)).is_ok());
+        let error = quota.check("docs", usage, add(2, 10)).unwrap_err();
+        assert_eq!(error.limit, QuotaLimit::Vectors);
+        assert_eq!(
+            error.to_string(),
+            "docs is limited to 2 vectors and holds 1 vectors in 60 bytes"
+        );
+        let error = quota.check("docs", usage, add(0, 41)).unwrap_err();
+        assert_eq!((error.limit, error.max), (QuotaLimit::Bytes, 100));
+        // Writes that don't grow it pass even when it's over already
+        let over = Usage {
+            vectors: 5,
+            bytes: 500,
+        };
+        assert!(quota.check("docs", over, Usage::default()).is_ok());
+        assert!(Quota::default().check("docs", over, add(9, 900)).is_ok());
+    }
+}
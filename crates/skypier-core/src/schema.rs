@@ -0,0 +1,146 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+
+// What a metadata value must parse as. Metadata is stored as strings, so
+// this only constrains their format.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldType {
+    #[default]
+    String,
+    Integer,
+    Number,
+    Boolean,
+}
+
+impl FieldType {
+    fn name(&self) -> &'static str {
+        match self {
+            FieldType::String => "string",
+            FieldType::Integer => "integer",
+            FieldType::Number => "number",
+            FieldType::Boolean => "boolean",
+        }
+    }
+
+    fn accepts(&self, value: &str) -> bool {
+        match self {
+            FieldType::String => true,
+            FieldType::Integer => value.parse::<i64>().is_ok(),
+            FieldType::Number => value.parse::<f64>().is_ok_and(f64::is_finite),
+            FieldType::Boolean => value == "true" || value == "false",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FieldSchema {
+    #[serde(default, rename = "type")]
+    pub field_type: FieldType,
+    #[serde(default)]
+    pub required: bool,
+    // Whether the key gets a filter index, so searches and aggregations can
+    // use it
+    #[serde(default)]
+    pub indexed: bool,
+}
+
+// The metadata keys a collection declares. Writes must have the required
+// ones and values of the declared types; undeclared keys are stored but,
+// like declared keys that aren't `indexed`, not indexed, which bounds how
+// much memory the filter index takes for collections with free-form
+// metadata.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MetadataSchema {
+    pub fields: BTreeMap<String, FieldSchema>,
+}
+
+// Why one metadata key of a vector doesn't match its collection's schema
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldError {
+    pub field: String,
+    pub reason: String,
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.reason)
+    }
+}
+
+impl MetadataSchema {
+    pub fn indexed_keys(&self) -> HashSet<String> {
+        self.fields
+            .iter()
+            .filter(|(_, field)| field.indexed)
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    // Every way the metadata falls short of the schema, in key order
+    pub fn check(&self, metadata: Option<&HashMap<String, String>>) -> Vec<FieldError> {
+        self.fields
+            .iter()
+            .filter_map(|(key, field)| {
+                let reason = match metadata.and_then(|metadata| metadata.get(key)) {
+                    None if field.required => "is required".to_string(),
+                    Some(value) if !field.field_type.accepts(value) => {
+                        format!("{:?} isn't a valid {}", value, field.field_type.name())
+                    }
+                    _ => return None,
+                };
+                Some(FieldError {
+                    field: key.clone(),
+                    reason,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_metadata() {
+        let schema: MetadataSchema = serde_json::from_str(
+            r#"{"fields": {
+                "lang": {"required": true, "indexed": true},
+                "year": {"type": "integer"},
+                "draft": {"type": "boolean", "required": true}
+            }}"#,
+        )
+        .unwrap();
+        assert_eq!(schema.indexed_keys(), HashSet::from(["lang".to_string()]));
+
+        let metadata = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        };
+        let valid = metadata(&[("lang", "en"), ("year", "2024"), ("draft", "false")]);
+        assert!(schema.check(Some(&valid)).is_empty());
+        // Undeclared keys are fine
+        let extra = metadata(&[("lang", "en"), ("draft", "true"), ("source", "web")]);
+        assert!(schema.check(Some(&extra)).is_empty());
+
+        let invalid = metadata(&[("year", "soon"), ("draft", "yes")]);
+        let fields: Vec<String> = schema
+            .check(Some(&invalid))
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            fields,
+            vec![
+                "draft: \"yes\" isn't a valid boolean",
+                "lang: is required",
+                "year: \"soon\" isn't a valid integer",
+            ]
+        );
+        assert_eq!(schema.check(None).len(), 2);
+    }
+}
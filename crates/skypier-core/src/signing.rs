@@ -0,0 +1,126 @@
+// ed25519 signatures over vector records, so any node holding a record can
+// tell who wrote it. A writer signs the id, a hash of the data, a hash of
+// the metadata and the time; see `payload`.
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+
+use crate::{RecordSignature, Vector};
+
+const PAYLOAD_VERSION: &str = "skypier-record-v1";
+
+// What gets signed, as UTF-8 lines:
+//   skypier-record-v1
+//   <id>
+//   <hex SHA-256 of `data` as little-endian f32s>
+//   <hex SHA-256 of the metadata as a JSON object with sorted keys, `{}` if none>
+//   <signed_at>
+pub fn payload(vector: &Vector, signed_at: u64) -> Vec<u8> {
+    let data: Vec<u8> = vector
+        .data
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect();
+    let metadata: BTreeMap<&String, &String> = vector.metadata.iter().flatten().collect();
+    let metadata = serde_json::to_vec(&metadata).unwrap_or_default();
+    format!(
+        "{}\n{}\n{}\n{}\n{}",
+        PAYLOAD_VERSION,
+        vector.id,
+        hex::encode(Sha256::digest(&data)),
+        hex::encode(Sha256::digest(&metadata)),
+        signed_at
+    )
+    .into_bytes()
+}
+
+pub fn sign(vector: &mut Vector, key: &SigningKey, signed_at: u64) {
+    let signature = key.sign(&payload(vector, signed_at));
+    vector.signature = Some(RecordSignature {
+        public_key: hex::encode(key.verifying_key().as_bytes()),
+        signature: hex::encode(signature.to_bytes()),
+        signed_at,
+    });
+}
+
+// Whether the vector's signature is well-formed and matches the record.
+// False for unsigned vectors.
+pub fn verify(vector: &Vector) -> bool {
+    let Some(record) = &vector.signature else {
+        return false;
+    };
+    let key = hex::decode(&record.public_key)
+        .ok()
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+        .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok());
+    let signature = hex::decode(&record.signature)
+        .ok()
+        .and_then(|bytes| Signature::from_slice(&bytes).ok());
+    match (key, signature) {
+        (Some(key), Some(signature)) => key
+            .verify(&payload(vector, record.signed_at), &signature)
+            .is_ok(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validation::{validate_signature, ValidationError, ValidationLimits};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_signatures_cover_the_record() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let metadata = HashMap::from([
+            ("title".to_string(), "Signed".to_string()),
+            ("lang".to_string(), "en".to_string()),
+        ]);
+        let mut vector = Vector::with_id("a".to_string(), vec![0.5, -1.0]).with_metadata(metadata);
+        let limits = ValidationLimits::default();
+        assert!(validate_signature(&vector, &limits).is_ok());
+
+        sign(&mut vector, &key, 1_717_200_000);
+        assert!(verify(&vector));
+        // Collection, version and the like aren't covered
+        let mut moved = vector.clone();
+        moved.collection = Some("docs".to_string());
+        moved.version = 3;
+        assert!(verify(&moved));
+
+        let mut tampered = vector.clone();
+        tampered.data[0] = 0.25;
+        assert!(!verify(&tampered));
+        let mut tampered = vector.clone();
+        tampered
+            .metadata
+            .as_mut()
+            .unwrap()
+            .insert("title".to_string(), "Forged".to_string());
+        assert!(!verify(&tampered));
+        let mut tampered = vector.clone();
+        tampered.signature.as_mut().unwrap().signed_at += 1;
+        assert_eq!(
+            validate_signature(&tampered, &limits),
+            Err(ValidationError::InvalidSignature {
+                id: "a".to_string()
+            })
+        );
+
+        let strict = ValidationLimits {
+            require_signatures: true,
+            trusted_keys: vec!["00".repeat(32)],
+            ..Default::default()
+        };
+        assert!(matches!(
+            validate_signature(&Vector::with_id("b".to_string(), vec![1.0]), &strict),
+            Err(ValidationError::MissingSignature { .. })
+        ));
+        assert!(matches!(
+            validate_signature(&vector, &strict),
+            Err(ValidationError::UntrustedKey { .. })
+        ));
+    }
+}
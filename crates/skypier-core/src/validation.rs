@@ -0,0 +1,306 @@
+use thiserror::Error;
+
+use crate::schema::FieldError;
+use crate::signing;
+use crate::{SparseVector, Vector};
+
+#[derive(Debug, Clone)]
+pub struct ValidationLimits {
+    pub max_dimensions: usize,
+    pub max_metadata_bytes: usize,
+    // Refuse records without a valid signature
+    pub require_signatures: bool,
+    // Hex public keys signatures are accepted from. Empty accepts any key.
+    pub trusted_keys: Vec<String>,
+}
+
+impl Default for ValidationLimits {
+    fn default() -> Self {
+        Self {
+            max_dimensions: 65_536,
+            max_metadata_bytes: 64 * 1024,
+            require_signatures: false,
+            trusted_keys: Vec::new(),
+        }
+    }
+}
+
+// Errors caused by bad input rather than by the database itself, so callers
+// (e.g. the HTTP API) can report them as client errors.
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum ValidationError {
+    #[error("vector id must not be empty")]
+    EmptyId,
+    #[error("vector {id} has no dimensions")]
+    EmptyVector { id: String },
+    #[error("vector {id} contains a non-finite value at position {position}")]
+    NonFiniteValue { id: String, position: usize },
+    #[error("vector {id} has {actual} dimensions, the maximum is {max}")]
+    TooManyDimensions {
+        id: String,
+        actual: usize,
+        max: usize,
+    },
+    #[error("vector {id} has {actual} dimensions, expected {expected}")]
+    DimensionMismatch {
+        id: String,
+        expected: usize,
+        actual: usize,
+    },
+    #[error("metadata of vector {id} is {size} bytes, the maximum is {max}")]
+    MetadataTooLarge { id: String, size: usize, max: usize },
+    #[error("query vector has no dimensions")]
+    EmptyQuery,
+    #[error("query vector contains a non-finite value at position {position}")]
+    NonFiniteQuery { position: usize },
+    #[error("vector {id} is a near duplicate of {existing} (score {score})")]
+    Duplicate {
+        id: String,
+        existing: String,
+        score: f32,
+    },
+    #[error("vector {id} was embedded with {actual}, collection {collection} expects {expected}")]
+    ModelMismatch {
+        id: String,
+        collection: String,
+        expected: String,
+        actual: String,
+    },
+    #[error("query was embedded with {actual}, collection {collection} expects {expected}")]
+    QueryModelMismatch {
+        collection: String,
+        expected: String,
+        actual: String,
+    },
+    #[error("vector {id} isn't signed")]
+    MissingSignature { id: String },
+    #[error("vector {id} doesn't match its signature")]
+    InvalidSignature { id: String },
+    #[error("vector {id} is signed by {key}, which isn't a trusted key")]
+    UntrustedKey { id: String, key: String },
+    #[error("recommendations need at least one positive example")]
+    NoPositiveExamples,
+    #[error("example {id} isn't stored")]
+    MissingExample { id: String },
+    #[error("{alias} is an alias; write to the collection it points at, {collection}")]
+    WriteToAlias {
+        id: String,
+        alias: String,
+        collection: String,
+    },
+    #[error("{alias} can't be an alias: {reason}")]
+    InvalidAlias { alias: String, reason: String },
+    #[error("can't clone into {target}: {reason}")]
+    InvalidCloneTarget { target: String, reason: String },
+    #[error(
+        "metadata of vector {id} doesn't match the schema of {collection}: {}",
+        errors.iter().map(ToString::to_string).collect::<Vec<_>>().join("; ")
+    )]
+    SchemaViolation {
+        id: String,
+        collection: String,
+        errors: Vec<FieldError>,
+    },
+    #[error("{key} isn't an indexed key of collection {collection}, so it can't be filtered on")]
+    UnindexedFilterKey { collection: String, key: String },
+    #[error("sparse vector {id} has {indices} indices but {values} values")]
+    SparseLengthMismatch {
+        id: String,
+        indices: usize,
+        values: usize,
+    },
+}
+
+pub fn metadata_size(vector: &Vector) -> usize {
+    vector
+        .metadata
+        .as_ref()
+        .map(|metadata| metadata.iter().map(|(k, v)| k.len() + v.len()).sum())
+        .unwrap_or(0)
+}
+
+pub fn validate_vector(vector: &Vector, limits: &ValidationLimits) -> Result<(), ValidationError> {
+    let id = &vector.id;
+    if id.is_empty() {
+        return Err(ValidationError::EmptyId);
+    }
+    validate_data(id, &vector.data, limits)?;
+    for (name, data) in &vector.vectors {
+        validate_data(&format!("{} ({})", id, name), data, limits)?;
+    }
+    if let Some(sparse) = &vector.sparse {
+        validate_sparse(&format!("{} (sparse)", id), sparse)?;
+    }
+
+    let size = metadata_size(vector);
+    if size > limits.max_metadata_bytes {
+        return Err(ValidationError::MetadataTooLarge {
+            id: id.clone(),
+            size,
+            max: limits.max_metadata_bytes,
+        });
+    }
+
+    validate_signature(vector, limits)
+}
+
+// Checks the record as it will be stored, so anything that changes a signed
+// vector on the way in (plugins, dedup merges, rounding to a smaller dtype)
+// fails it
+pub fn validate_signature(
+    vector: &Vector,
+    limits: &ValidationLimits,
+) -> Result<(), ValidationError> {
+    let id = vector.id.clone();
+    let Some(signature) = &vector.signature else {
+        if limits.require_signatures {
+            return Err(ValidationError::MissingSignature { id });
+        }
+        return Ok(());
+    };
+    if !limits.trusted_keys.is_empty() && !limits.trusted_keys.contains(&signature.public_key) {
+        return Err(ValidationError::UntrustedKey {
+            id,
+            key: signature.public_key.clone(),
+        });
+    }
+    if !signing::verify(vector) {
+        return Err(ValidationError::InvalidSignature { id });
+    }
+    Ok(())
+}
+
+fn validate_data(id: &str, data: &[f32], limits: &ValidationLimits) -> Result<(), ValidationError> {
+    if data.is_empty() {
+        return Err(ValidationError::EmptyVector { id: id.to_string() });
+    }
+    if data.len() > limits.max_dimensions {
+        return Err(ValidationError::TooManyDimensions {
+            id: id.to_string(),
+            actual: data.len(),
+            max: limits.max_dimensions,
+        });
+    }
+    if let Some(position) = data.iter().position(|x| !x.is_finite()) {
+        return Err(ValidationError::NonFiniteValue {
+            id: id.to_string(),
+            position,
+        });
+    }
+    Ok(())
+}
+
+fn validate_sparse(id: &str, sparse: &SparseVector) -> Result<(), ValidationError> {
+    if sparse.indices.len() != sparse.values.len() {
+        return Err(ValidationError::SparseLengthMismatch {
+            id: id.to_string(),
+            indices: sparse.indices.len(),
+            values: sparse.values.len(),
+        });
+    }
+    if let Some(position) = sparse.values.iter().position(|x| !x.is_finite()) {
+        return Err(ValidationError::NonFiniteValue {
+            id: id.to_string(),
+            position,
+        });
+    }
+    Ok(())
+}
+
+pub fn validate_sparse_query(query: &SparseVector) -> Result<(), ValidationError> {
+    if query.indices.is_empty() {
+        return Err(ValidationError::EmptyQuery);
+    }
+    if query.indices.len() != query.values.len() {
+        return Err(ValidationError::SparseLengthMismatch {
+            id: "query".to_string(),
+            indices: query.indices.len(),
+            values: query.values.len(),
+        });
+    }
+    validate_query(&query.values)
+}
+
+pub fn validate_query(query: &[f32]) -> Result<(), ValidationError> {
+    if query.is_empty() {
+        return Err(ValidationError::EmptyQuery);
+    }
+    if let Some(position) = query.iter().position(|x| !x.is_finite()) {
+        return Err(ValidationError::NonFiniteQuery { position });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_validate_vector() {
+        let limits = ValidationLimits {
+            max_dimensions: 4,
+            max_metadata_bytes: 8,
+            ..Default::default()
+        };
+
+        assert!(validate_vector(&Vector::new(vec![1.0, 2.0]), &limits).is_ok());
+        assert_eq!(
+            validate_vector(&Vector::with_id("a".to_string(), vec![]), &limits),
+            Err(ValidationError::EmptyVector {
+                id: "a".to_string()
+            })
+        );
+        assert_eq!(
+            validate_vector(
+                &Vector::with_id("a".to_string(), vec![1.0, f32::NAN]),
+                &limits
+            ),
+            Err(ValidationError::NonFiniteValue {
+                id: "a".to_string(),
+                position: 1
+            })
+        );
+        assert!(matches!(
+            validate_vector(&Vector::new(vec![0.0; 5]), &limits),
+            Err(ValidationError::TooManyDimensions { .. })
+        ));
+
+        let metadata = HashMap::from([("source".to_string(), "file.txt".to_string())]);
+        assert!(matches!(
+            validate_vector(&Vector::new(vec![1.0]).with_metadata(metadata), &limits),
+            Err(ValidationError::MetadataTooLarge { size: 14, .. })
+        ));
+
+        let named = Vector::with_id("a".to_string(), vec![1.0]).with_named("title", vec![]);
+        assert_eq!(
+            validate_vector(&named, &limits),
+            Err(ValidationError::EmptyVector {
+                id: "a (title)".to_string()
+            })
+        );
+
+        let sparse = Vector::with_id("a".to_string(), vec![1.0]).with_sparse(SparseVector {
+            indices: vec![3, 8],
+            values: vec![0.5],
+        });
+        assert_eq!(
+            validate_vector(&sparse, &limits),
+            Err(ValidationError::SparseLengthMismatch {
+                id: "a (sparse)".to_string(),
+                indices: 2,
+                values: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_validate_query() {
+        assert!(validate_query(&[0.5]).is_ok());
+        assert_eq!(validate_query(&[]), Err(ValidationError::EmptyQuery));
+        assert_eq!(
+            validate_query(&[f32::INFINITY]),
+            Err(ValidationError::NonFiniteQuery { position: 0 })
+        );
+    }
+}
@@ -0,0 +1,98 @@
+// Random insert/delete/search/compact sequences against a real database,
+// checking that storage and the index never disagree about what is stored.
+
+use proptest::prelude::*;
+use skypier_core::{Vector, VectorDatabase};
+use std::collections::BTreeMap;
+
+const DIMS: usize = 4;
+
+#[derive(Debug, Clone)]
+enum Op {
+    Insert(u8, Vec<f32>),
+    Delete(u8),
+    Search(Vec<f32>, usize),
+    Compact,
+}
+
+fn vector() -> impl Strategy<Value = Vec<f32>> {
+    prop::collection::vec((-4i8..=4).prop_map(f32::from), DIMS)
+        .prop_filter("zero vector", |v| v.iter().any(|x| *x != 0.0))
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        4 => (0u8..24, vector()).prop_map(|(id, v)| Op::Insert(id, v)),
+        2 => (0u8..24).prop_map(Op::Delete),
+        1 => (vector(), 1usize..10).prop_map(|(v, k)| Op::Search(v, k)),
+        1 => Just(Op::Compact),
+    ]
+}
+
+async fn check_invariants(ops: Vec<Op>) -> Result<(), TestCaseError> {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db = VectorDatabase::new(temp_dir.path().to_str().unwrap())
+        .await
+        .unwrap();
+    let mut model: BTreeMap<String, Vec<f32>> = BTreeMap::new();
+
+    for op in ops {
+        match op {
+            Op::Insert(id, data) => {
+                let id = format!("v{}", id);
+                db.insert_vectors(vec![Vector::with_id(id.clone(), data.clone())])
+                    .await
+                    .unwrap();
+                model.insert(id, data);
+            }
+            Op::Delete(id) => {
+                let id = format!("v{}", id);
+                let removed = db.delete_vector(&id).await.unwrap();
+                prop_assert_eq!(removed, model.remove(&id).is_some());
+            }
+            Op::Search(query, k) => {
+                let results = db.search(&query, k, -1.0).await.unwrap();
+                prop_assert!(results.len() <= k);
+                for result in &results {
+                    prop_assert!(model.contains_key(&result.id), "deleted id {}", result.id);
+                }
+            }
+            Op::Compact => db.compact().await.unwrap(),
+        }
+    }
+
+    let stats = db.get_stats().await.unwrap();
+    prop_assert_eq!(stats.total_vectors, model.len());
+
+    // Each stored vector is in storage and findable through the index
+    for (id, data) in &model {
+        let stored = db.get_vector(id).await.unwrap();
+        prop_assert_eq!(stored.map(|v| v.data), Some(data.clone()));
+
+        let results = db.search(data, model.len(), -1.0).await.unwrap();
+        let top = results.first().map(|r| r.score).unwrap_or(f32::MIN);
+        prop_assert!(
+            results
+                .iter()
+                .any(|r| &r.id == id && (r.score - top).abs() < 1e-4),
+            "{} is not its own nearest neighbor",
+            id
+        );
+    }
+
+    Ok(())
+}
+
+proptest! {
+    // Every case opens a fresh on-disk database
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn database_invariants(ops in prop::collection::vec(op(), 0..100)) {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(check_invariants(ops))?;
+    }
+}
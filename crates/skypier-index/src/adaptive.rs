@@ -0,0 +1,418 @@
+use anyhow::{anyhow, Result};
+use parking_lot::{Mutex, RwLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tracing::error;
+
+use crate::{CancellationToken, FlatIndex, Metric, SearchResult, VectorIndex};
+
+type Build = Arc<dyn Fn() -> Result<Box<dyn VectorIndex>> + Send + Sync>;
+
+// Starts as a flat index, which is exact and fastest while small, and moves
+// to the index made by `build` once it holds `threshold` vectors. The large
+// index is built on a background thread from a copy of the flat one; writes
+// keep going to the flat index meanwhile and are replayed onto the large one
+// before it's swapped in. It stays in use until `clear`, however small the
+// index shrinks. A failed build is retried once the flat index reaches the
+// next multiple of `threshold`.
+pub struct AdaptiveIndex {
+    shared: Arc<Shared>,
+    threshold: usize,
+    build: Build,
+}
+
+struct Shared {
+    flat: FlatIndex,
+    large: RwLock<Option<Box<dyn VectorIndex>>>,
+    // Writes made while the large index builds, `None` when it isn't. Held
+    // by every write until the large index is in place.
+    pending: Mutex<Option<Vec<Write>>>,
+    // Bumped by `clear` and `load`, so a build of older contents is dropped
+    generation: AtomicU64,
+    // Set when a build fails, until one succeeds or `clear` or `load`
+    failed: Mutex<Option<Failure>>,
+}
+
+struct Failure {
+    reason: String,
+    // Flat index size when the build started
+    size: usize,
+}
+
+enum Write {
+    Add(String, Vec<f32>),
+    Remove(String),
+}
+
+impl AdaptiveIndex {
+    pub fn new<F>(metric: Metric, threshold: usize, build: F) -> Self
+    where
+        F: Fn() -> Result<Box<dyn VectorIndex>> + Send + Sync + 'static,
+    {
+        Self {
+            shared: Arc::new(Shared {
+                flat: FlatIndex::new().with_metric(metric),
+                large: RwLock::new(None),
+                pending: Mutex::new(None),
+                generation: AtomicU64::new(0),
+                failed: Mutex::new(None),
+            }),
+            threshold,
+            build: Arc::new(build),
+        }
+    }
+
+    // Whether the large index has been swapped in
+    pub fn is_migrated(&self) -> bool {
+        self.shared.large.read().is_some()
+    }
+
+    fn write<R>(
+        &self,
+        apply: impl Fn(&dyn VectorIndex) -> Result<R>,
+        write: impl FnOnce() -> Write,
+    ) -> Result<R> {
+        if let Some(large) = &*self.shared.large.read() {
+            return apply(large.as_ref());
+        }
+        let mut pending = self.shared.pending.lock();
+        // Swapped in while waiting for the lock
+        if let Some(large) = &*self.shared.large.read() {
+            return apply(large.as_ref());
+        }
+        let result = apply(&self.shared.flat)?;
+        let migration = match pending.as_mut() {
+            Some(writes) => {
+                writes.push(write());
+                None
+            }
+            None => self.start_migration(&mut pending),
+        };
+        drop(pending);
+        if let Some(migrate) = migration {
+            run_migration(migrate);
+        }
+        Ok(result)
+    }
+
+    // The build of the large index, once the flat one is big enough, to be
+    // run after `pending` is unlocked
+    fn start_migration(
+        &self,
+        pending: &mut Option<Vec<Write>>,
+    ) -> Option<impl FnOnce() + Send + 'static> {
+        let shared = &self.shared;
+        let threshold = self.threshold.max(1);
+        let size = shared.flat.size();
+        let retry_at = match &*shared.failed.lock() {
+            Some(failure) => (failure.size / threshold + 1) * threshold,
+            None => self.threshold,
+        };
+        if size < retry_at {
+            return None;
+        }
+        *pending = Some(Vec::new());
+        let vectors = shared.flat.vectors();
+        let generation = shared.generation.load(Ordering::SeqCst);
+        let shared = Arc::clone(shared);
+        let build = Arc::clone(&self.build);
+        Some(move || shared.migrate(build.as_ref(), vectors, generation))
+    }
+}
+
+// Runs a migration on a thread of its own, or inline where threads aren't
+// available, e.g. on wasm
+fn run_migration(migrate: impl FnOnce() + Send + 'static) {
+    let migrate = Arc::new(Mutex::new(Some(migrate)));
+    let spawned = {
+        let migrate = Arc::clone(&migrate);
+        std::thread::Builder::new()
+            .name("skypier-index-migrate".to_string())
+            .spawn(move || {
+                if let Some(migrate) = migrate.lock().take() {
+                    migrate();
+                }
+            })
+    };
+    if spawned.is_err() {
+        if let Some(migrate) = migrate.lock().take() {
+            migrate();
+        }
+    }
+}
+
+impl Shared {
+    fn migrate(
+        &self,
+        build: &(dyn Fn() -> Result<Box<dyn VectorIndex>> + Send + Sync),
+        vectors: Vec<(String, Vec<f32>)>,
+        generation: u64,
+    ) {
+        let built = build().and_then(|index| {
+            let ids: Vec<&str> = vectors.iter().map(|(id, _)| id.as_str()).collect();
+            let data: Vec<&[f32]> = vectors
+                .iter()
+                .map(|(_, vector)| vector.as_slice())
+                .collect();
+            index.build_batch(&ids, &data)?;
+            Ok(index)
+        });
+
+        let mut pending = self.pending.lock();
+        if self.generation.load(Ordering::SeqCst) != generation {
+            return;
+        }
+        let writes = pending.take().unwrap_or_default();
+        let replayed = built.and_then(|index| {
+            for write in &writes {
+                match write {
+                    Write::Add(id, vector) => index.add_vector(id, vector)?,
+                    Write::Remove(id) => {
+                        index.remove_vector(id)?;
+                    }
+                }
+            }
+            Ok(index)
+        });
+        match replayed {
+            Ok(index) => {
+                // Searches hold the read lock, so none sees the flat index
+                // emptied before the large one is in
+                let mut large = self.large.write();
+                *large = Some(index);
+                self.flat.clear();
+                *self.failed.lock() = None;
+            }
+            // The flat index still answers, just more slowly
+            Err(e) => {
+                error!(
+                    "Building the index over {} vectors failed, searches stay on the flat index: {}",
+                    vectors.len(),
+                    e
+                );
+                *self.failed.lock() = Some(Failure {
+                    reason: e.to_string(),
+                    size: vectors.len(),
+                });
+            }
+        }
+    }
+
+    // Back to an empty flat index, dropping any build in progress
+    fn reset(&self, pending: &mut Option<Vec<Write>>) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        *self.failed.lock() = None;
+        *pending = None;
+        *self.large.write() = None;
+        self.flat.clear();
+    }
+}
+
+impl VectorIndex for AdaptiveIndex {
+    fn add_vector(&self, id: &str, vector: &[f32]) -> Result<()> {
+        self.write(
+            |index| index.add_vector(id, vector),
+            || Write::Add(id.to_string(), vector.to_vec()),
+        )
+    }
+
+    fn remove_vector(&self, id: &str) -> Result<bool> {
+        self.write(
+            |index| index.remove_vector(id),
+            || Write::Remove(id.to_string()),
+        )
+    }
+
+    fn build_batch(&self, ids: &[&str], vectors: &[&[f32]]) -> Result<()> {
+        crate::check_batch(ids, vectors)?;
+        if let Some(large) = &*self.shared.large.read() {
+            return large.build_batch(ids, vectors);
+        }
+        for (id, vector) in ids.iter().zip(vectors) {
+            self.add_vector(id, vector)?;
+        }
+        Ok(())
+    }
+
+    fn search_filtered(
+        &self,
+        query: &[f32],
+        k: usize,
+        allowed: &dyn Fn(&str) -> bool,
+    ) -> Result<Vec<SearchResult>> {
+        match &*self.shared.large.read() {
+            Some(large) => large.search_filtered(query, k, allowed),
+            None => self.shared.flat.search_filtered(query, k, allowed),
+        }
+    }
+
+    fn search_cancellable(
+        &self,
+        query: &[f32],
+        k: usize,
+        allowed: &dyn Fn(&str) -> bool,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<SearchResult>> {
+        match &*self.shared.large.read() {
+            Some(large) => large.search_cancellable(query, k, allowed, cancel),
+            None => self
+                .shared
+                .flat
+                .search_cancellable(query, k, allowed, cancel),
+        }
+    }
+
+    fn size(&self) -> usize {
+        match &*self.shared.large.read() {
+            Some(large) => large.size(),
+            None => self.shared.flat.size(),
+        }
+    }
+
+    fn clear(&self) {
+        let mut pending = self.shared.pending.lock();
+        self.shared.reset(&mut pending);
+    }
+
+    fn index_type(&self) -> &'static str {
+        match &*self.shared.large.read() {
+            Some(large) => large.index_type(),
+            None => "flat",
+        }
+    }
+
+    fn warm_up(&self) -> usize {
+        match &*self.shared.large.read() {
+            Some(large) => large.warm_up(),
+            None => self.shared.flat.warm_up(),
+        }
+    }
+
+    fn build_error(&self) -> Option<String> {
+        let failed = self.shared.failed.lock();
+        failed.as_ref().map(|failure| failure.reason.clone())
+    }
+
+    // The snapshot of whichever index is in use
+    fn save(&self) -> Result<Vec<u8>> {
+        match &*self.shared.large.read() {
+            Some(large) => large.save(),
+            None => self.shared.flat.save(),
+        }
+    }
+
+    fn load(&self, data: &[u8]) -> Result<()> {
+        let kind: String =
+            bincode::deserialize(data).map_err(|e| anyhow!("Unreadable index snapshot: {}", e))?;
+        let mut pending = self.shared.pending.lock();
+        self.shared.reset(&mut pending);
+        if kind != "flat" {
+            let large = (self.build)()?;
+            large.load(data)?;
+            *self.shared.large.write() = Some(large);
+            return Ok(());
+        }
+        self.shared.flat.load(data)?;
+        let migration = self.start_migration(&mut pending);
+        drop(pending);
+        if let Some(migrate) = migration {
+            run_migration(migrate);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HnswIndex;
+    use std::time::{Duration, Instant};
+
+    fn adaptive(threshold: usize) -> AdaptiveIndex {
+        AdaptiveIndex::new(Metric::Cosine, threshold, || {
+            Ok(Box::new(HnswIndex::new(2)?) as _)
+        })
+    }
+
+    fn wait_for_migration(index: &AdaptiveIndex) {
+        let started = Instant::now();
+        while !index.is_migrated() {
+            assert!(started.elapsed() < Duration::from_secs(30));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_moves_to_large_index_past_threshold() {
+        let index = adaptive(100);
+        let vector = |i: usize| [(i as f32).cos(), (i as f32).sin()];
+        for i in 0..99 {
+            index.add_vector(&i.to_string(), &vector(i)).unwrap();
+        }
+        assert_eq!(index.index_type(), "flat");
+        let flat_snapshot = index.save().unwrap();
+
+        for i in 99..300 {
+            index.add_vector(&i.to_string(), &vector(i)).unwrap();
+        }
+        index.remove_vector("150").unwrap();
+        wait_for_migration(&index);
+        assert_eq!(index.index_type(), "hnsw");
+        assert_eq!(index.size(), 299);
+        assert_eq!(index.search(&vector(42), 1).unwrap()[0].id, "42");
+        assert!(index
+            .search(&vector(150), 5)
+            .unwrap()
+            .iter()
+            .all(|result| result.id != "150"));
+
+        // Snapshots load back into whichever index they came from
+        let restored = adaptive(100);
+        restored.load(&index.save().unwrap()).unwrap();
+        assert_eq!(restored.index_type(), "hnsw");
+        assert_eq!(restored.size(), 299);
+        restored.load(&flat_snapshot).unwrap();
+        assert_eq!(restored.index_type(), "flat");
+        assert_eq!(restored.size(), 99);
+
+        restored.clear();
+        assert_eq!(restored.size(), 0);
+        assert!(!restored.is_migrated());
+    }
+
+    #[test]
+    fn test_failed_build_is_reported_and_retried() {
+        let builds = Arc::new(AtomicU64::new(0));
+        let index = {
+            let builds = Arc::clone(&builds);
+            AdaptiveIndex::new(Metric::Cosine, 10, move || {
+                if builds.fetch_add(1, Ordering::SeqCst) == 0 {
+                    return Err(anyhow!("out of memory"));
+                }
+                Ok(Box::new(HnswIndex::new(2)?) as _)
+            })
+        };
+        let vector = |i: usize| [(i as f32).cos(), (i as f32).sin()];
+        for i in 0..10 {
+            index.add_vector(&i.to_string(), &vector(i)).unwrap();
+        }
+        let started = Instant::now();
+        while index.build_error().is_none() {
+            assert!(started.elapsed() < Duration::from_secs(30));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(index.build_error().as_deref(), Some("out of memory"));
+        assert_eq!(index.search(&vector(3), 1).unwrap()[0].id, "3");
+
+        // Not retried until the next multiple of the threshold
+        for i in 10..19 {
+            index.add_vector(&i.to_string(), &vector(i)).unwrap();
+        }
+        assert_eq!(builds.load(Ordering::SeqCst), 1);
+        index.add_vector("19", &vector(19)).unwrap();
+        wait_for_migration(&index);
+        assert_eq!(builds.load(Ordering::SeqCst), 2);
+        assert_eq!(index.build_error(), None);
+        assert_eq!(index.size(), 20);
+    }
+}
@@ -0,0 +1,216 @@
+use anyhow::{anyhow, Result};
+use parking_lot::RwLock;
+
+use crate::{sort_results, IdMapper, Metric, SearchResult, VectorIndex};
+
+// Brute-force index over binary-quantized vectors: one bit per dimension,
+// set when the value is positive, packed into u64 words. Candidates are
+// ranked by hamming distance, which costs a popcount per word, scored as
+// the fraction of matching bits. With rescoring on, the full-precision
+// vectors are kept as well and the best candidates are rescored exactly by
+// cosine similarity.
+pub struct BinaryIndex {
+    // Hamming candidates fetched per result when rescoring
+    rescore: Option<usize>,
+    entries: RwLock<Entries>,
+}
+
+// A quantized vector, 64 dimensions per word
+type Code = Vec<u64>;
+
+// Kind, ids, codes and full vectors, as saved
+type Snapshot = (String, IdMapper, Vec<Option<Code>>, Vec<Option<Vec<f32>>>);
+
+#[derive(Default)]
+struct Entries {
+    ids: IdMapper,
+    // Indexed by internal id; `None` for freed ids
+    codes: Vec<Option<Code>>,
+    // Only filled in when rescoring
+    vectors: Vec<Option<Vec<f32>>>,
+}
+
+fn quantize(vector: &[f32]) -> Code {
+    let mut code = vec![0u64; vector.len().div_ceil(64)];
+    for (i, &x) in vector.iter().enumerate() {
+        if x > 0.0 {
+            code[i / 64] |= 1 << (i % 64);
+        }
+    }
+    code
+}
+
+// Words only one of the codes has count as all different
+fn hamming(a: &[u64], b: &[u64]) -> u32 {
+    let (short, long) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+    let shared: u32 = short
+        .iter()
+        .zip(long)
+        .map(|(x, y)| (x ^ y).count_ones())
+        .sum();
+    shared + 64 * (long.len() - short.len()) as u32
+}
+
+impl Default for BinaryIndex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BinaryIndex {
+    pub fn new() -> Self {
+        Self {
+            rescore: None,
+            entries: RwLock::new(Entries::default()),
+        }
+    }
+
+    // Keeps full-precision vectors and rescores `fetch_factor` times k
+    // hamming candidates by cosine similarity. Call before adding vectors.
+    pub fn with_rescore(mut self, fetch_factor: usize) -> Self {
+        self.rescore = Some(fetch_factor.max(1));
+        self
+    }
+}
+
+impl VectorIndex for BinaryIndex {
+    fn add_vector(&self, id: &str, vector: &[f32]) -> Result<()> {
+        let mut entries = self.entries.write();
+        let internal = entries.ids.insert(id) as usize;
+        if internal == entries.codes.len() {
+            entries.codes.push(None);
+            entries.vectors.push(None);
+        }
+        entries.codes[internal] = Some(quantize(vector));
+        if self.rescore.is_some() {
+            entries.vectors[internal] = Some(vector.to_vec());
+        }
+        Ok(())
+    }
+
+    fn remove_vector(&self, id: &str) -> Result<bool> {
+        let mut entries = self.entries.write();
+        let Some(internal) = entries.ids.remove(id) else {
+            return Ok(false);
+        };
+        entries.codes[internal as usize] = None;
+        entries.vectors[internal as usize] = None;
+        Ok(true)
+    }
+
+    fn search_filtered(
+        &self,
+        query: &[f32],
+        k: usize,
+        allowed: &dyn Fn(&str) -> bool,
+    ) -> Result<Vec<SearchResult>> {
+        let entries = self.entries.read();
+        let code = quantize(query);
+        let mut candidates: Vec<(u32, &str, u32)> = entries
+            .ids
+            .iter()
+            .filter(|(_, id)| allowed(id))
+            .filter_map(|(internal, id)| {
+                let other = entries.codes[internal as usize].as_ref()?;
+                Some((hamming(&code, other), id, internal))
+            })
+            .collect();
+
+        // Only the closest `fetch` need sorting
+        let fetch = k.saturating_mul(self.rescore.unwrap_or(1));
+        if fetch < candidates.len() {
+            candidates.select_nth_unstable_by_key(fetch, |&(distance, id, _)| (distance, id));
+            candidates.truncate(fetch);
+        }
+
+        let bits = (code.len() * 64).max(1) as f32;
+        let mut results: Vec<_> = candidates
+            .into_iter()
+            .map(|(distance, id, internal)| {
+                let score = match &entries.vectors[internal as usize] {
+                    Some(vector) if self.rescore.is_some() => {
+                        Metric::Cosine.similarity(query, vector)
+                    }
+                    _ => 1.0 - distance as f32 / bits,
+                };
+                SearchResult {
+                    id: id.to_string(),
+                    score,
+                }
+            })
+            .collect();
+        sort_results(&mut results);
+        results.truncate(k);
+        Ok(results)
+    }
+
+    fn index_type(&self) -> &'static str {
+        "binary"
+    }
+
+    fn size(&self) -> usize {
+        self.entries.read().ids.len()
+    }
+
+    fn clear(&self) {
+        *self.entries.write() = Entries::default();
+    }
+
+    fn warm_up(&self) -> usize {
+        let entries = self.entries.read();
+        let codes: usize = entries
+            .codes
+            .iter()
+            .flatten()
+            .map(|c| crate::touch(c))
+            .sum();
+        let vectors: usize = entries
+            .vectors
+            .iter()
+            .flatten()
+            .map(|v| crate::touch(v))
+            .sum();
+        codes + vectors
+    }
+
+    fn save(&self) -> Result<Vec<u8>> {
+        let entries = self.entries.read();
+        Ok(bincode::serialize(&(
+            "binary",
+            &entries.ids,
+            &entries.codes,
+            &entries.vectors,
+        ))?)
+    }
+
+    fn load(&self, data: &[u8]) -> Result<()> {
+        let (kind, ids, codes, vectors): Snapshot = bincode::deserialize(data)?;
+        if kind != "binary" {
+            return Err(anyhow!("Cannot load a {} index into a binary index", kind));
+        }
+        let consistent = codes.len() == ids.capacity()
+            && vectors.len() == codes.len()
+            && codes
+                .iter()
+                .zip(&vectors)
+                .enumerate()
+                .all(|(internal, (code, vector))| {
+                    let live = ids.external(internal as u32).is_some();
+                    // Rescoring needs the full vectors the snapshot may not have
+                    code.is_some() == live && (self.rescore.is_none() || vector.is_some() == live)
+                });
+        if !consistent {
+            return Err(anyhow!("Binary index snapshot ids don't match its vectors"));
+        }
+        let vectors = match self.rescore {
+            Some(_) => vectors,
+            None => vec![None; codes.len()],
+        };
+        *self.entries.write() = Entries {
+            ids,
+            codes,
+            vectors,
+        };
+        Ok(())
+    }
+}
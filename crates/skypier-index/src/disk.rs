@@ -0,0 +1,938 @@
+use anyhow::{anyhow, Result};
+use memmap2::Mmap;
+use parking_lot::RwLock;
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{check_batch, sort_results, Metric, SearchResult, VectorIndex};
+
+// DiskANN-style index for collections bigger than memory. A Vamana graph
+// lives in a file of its own under `dir`, memory-mapped, with each node's
+// full vector next to its neighbor list so expanding a node is one read.
+// Only product-quantized codes, `subspaces` bytes per vector, are kept in
+// RAM: the beam search ranks candidates by those and the nodes it expands
+// by their exact distance, so a query touches about `ef_search` nodes on
+// disk. Writes go to an in-memory delta, searched exactly, with overwritten
+// and removed graph ids hidden; once the delta holds `merge_threshold`
+// vectors it's merged into a new graph file. Building a graph reads all of
+// its vectors into memory, so merges need room for them, but serving one
+// doesn't.
+pub struct DiskIndex {
+    dir: PathBuf,
+    metric: Metric,
+    degree: usize,
+    build_list: usize,
+    search_list: usize,
+    subspaces: usize,
+    merge_threshold: usize,
+    state: RwLock<State>,
+}
+
+#[derive(Default)]
+struct State {
+    graph: Option<Graph>,
+    // Written since the graph was built
+    delta: HashMap<String, Vec<f32>>,
+    // Graph ids removed or overwritten since
+    removed: HashSet<String>,
+}
+
+// Kind, graph file name, delta and removed ids, as saved
+type Snapshot = (String, Option<String>, Vec<(String, Vec<f32>)>, Vec<String>);
+
+const MAGIC: &[u8; 8] = b"SKYDISK1";
+const HEADER_LEN: usize = 48;
+// Centroids per subspace, so codes are one byte each
+const CENTROIDS: usize = 256;
+// Vectors k-means runs over when training the quantizer
+const PQ_SAMPLE: usize = 20_000;
+const PQ_ITERATIONS: usize = 8;
+
+fn metric_id(metric: Metric) -> u32 {
+    match metric {
+        Metric::Cosine => 0,
+        Metric::Euclidean => 1,
+        Metric::DotProduct => 2,
+    }
+}
+
+fn squared_l2(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum()
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+fn normalized(vector: &[f32]) -> Vec<f32> {
+    let norm = dot(vector, vector).sqrt();
+    if norm == 0.0 {
+        return vector.to_vec();
+    }
+    vector.iter().map(|x| x / norm).collect()
+}
+
+// Dimensions split as evenly as they go, the first few one longer
+fn subspace_ranges(dims: usize, subspaces: usize) -> Vec<Range<usize>> {
+    let subspaces = subspaces.clamp(1, dims.max(1));
+    let (base, extra) = (dims / subspaces, dims % subspaces);
+    let mut start = 0;
+    (0..subspaces)
+        .map(|s| {
+            let len = base + usize::from(s < extra);
+            start += len;
+            start - len..start
+        })
+        .collect()
+}
+
+// splitmix64, so graphs build the same way every time
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self, bound: usize) -> usize {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        ((z ^ (z >> 31)) % bound as u64) as usize
+    }
+}
+
+// Product quantizer: a codebook per subspace, and every vector's codes
+struct Quantizer {
+    ranges: Vec<Range<usize>>,
+    // Per subspace, CENTROIDS centroids of the subspace's length
+    centroids: Vec<Vec<f32>>,
+    codes: Vec<u8>,
+}
+
+impl Quantizer {
+    // Cosine vectors are quantized normalized, and ranked by inner product
+    fn train(vectors: &[Vec<f32>], dims: usize, subspaces: usize, metric: Metric) -> Self {
+        let ranges = subspace_ranges(dims, subspaces);
+        let prepared: Vec<Vec<f32>> = match metric {
+            Metric::Cosine => vectors.par_iter().map(|v| normalized(v)).collect(),
+            _ => vectors.to_vec(),
+        };
+        let step = prepared.len().div_ceil(PQ_SAMPLE).max(1);
+        let sample: Vec<&Vec<f32>> = prepared.iter().step_by(step).collect();
+        let centroids: Vec<Vec<f32>> = ranges
+            .par_iter()
+            .map(|range| kmeans(&sample, range.clone()))
+            .collect();
+        let mut quantizer = Self {
+            ranges,
+            centroids,
+            codes: Vec::new(),
+        };
+        quantizer.codes = prepared
+            .par_iter()
+            .flat_map_iter(|vector| quantizer.encode(vector))
+            .collect();
+        quantizer
+    }
+
+    fn encode(&self, vector: &[f32]) -> Vec<u8> {
+        self.ranges
+            .iter()
+            .zip(&self.centroids)
+            .map(|(range, centroids)| {
+                let part = &vector[range.clone()];
+                let mut best = (f32::INFINITY, 0);
+                for (c, centroid) in centroids.chunks(range.len()).enumerate() {
+                    let distance = squared_l2(part, centroid);
+                    if distance < best.0 {
+                        best = (distance, c);
+                    }
+                }
+                best.1 as u8
+            })
+            .collect()
+    }
+
+    // Distance from the query to every centroid, per subspace, so a
+    // vector's approximate distance is a lookup per code
+    fn table(&self, query: &[f32], metric: Metric) -> Vec<f32> {
+        let query = match metric {
+            Metric::Cosine => normalized(query),
+            _ => query.to_vec(),
+        };
+        self.ranges
+            .iter()
+            .zip(&self.centroids)
+            .flat_map(|(range, centroids)| {
+                let part = &query[range.clone()];
+                centroids
+                    .chunks(range.len())
+                    .map(move |centroid| match metric {
+                        Metric::Euclidean => squared_l2(part, centroid),
+                        Metric::Cosine | Metric::DotProduct => -dot(part, centroid),
+                    })
+            })
+            .collect()
+    }
+
+    fn distance(&self, table: &[f32], node: usize) -> f32 {
+        let m = self.ranges.len();
+        self.codes[node * m..(node + 1) * m]
+            .iter()
+            .enumerate()
+            .map(|(s, &code)| table[s * CENTROIDS + code as usize])
+            .sum()
+    }
+}
+
+fn kmeans(sample: &[&Vec<f32>], range: Range<usize>) -> Vec<f32> {
+    let len = range.len();
+    let mut centroids: Vec<f32> = (0..CENTROIDS)
+        .flat_map(|c| sample[c * sample.len() / CENTROIDS][range.clone()].to_vec())
+        .collect();
+    for _ in 0..PQ_ITERATIONS {
+        let mut sums = vec![0.0f32; CENTROIDS * len];
+        let mut counts = vec![0usize; CENTROIDS];
+        for vector in sample {
+            let part = &vector[range.clone()];
+            let nearest = centroids
+                .chunks(len)
+                .enumerate()
+                .map(|(c, centroid)| (squared_l2(part, centroid), c))
+                .min_by(|a, b| a.0.total_cmp(&b.0))
+                .map_or(0, |(_, c)| c);
+            counts[nearest] += 1;
+            for (sum, x) in sums[nearest * len..(nearest + 1) * len]
+                .iter_mut()
+                .zip(part)
+            {
+                *sum += x;
+            }
+        }
+        // Empty clusters keep their centroid
+        for (c, &count) in counts.iter().enumerate().filter(|(_, &n)| n > 0) {
+            for i in 0..len {
+                centroids[c * len + i] = sums[c * len + i] / count as f32;
+            }
+        }
+    }
+    centroids
+}
+
+// A graph file, mapped. Laid out as the header, the codebooks, the codes,
+// fixed-size node records (vector, neighbor count, neighbors padded to the
+// degree), each node's id offset, the nodes sorted by id, and the ids.
+struct Graph {
+    name: String,
+    path: PathBuf,
+    map: Mmap,
+    dims: usize,
+    degree: usize,
+    count: usize,
+    medoid: usize,
+    quantizer: Quantizer,
+    nodes_at: usize,
+    offsets_at: usize,
+    sorted_at: usize,
+    ids_at: usize,
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+fn read_f32s(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+        .collect()
+}
+
+impl Graph {
+    fn record_len(dims: usize, degree: usize) -> usize {
+        dims * 4 + 4 + degree * 4
+    }
+
+    fn open(dir: &Path, name: &str, metric: Metric) -> Result<Self> {
+        let path = dir.join(name);
+        let file = File::open(&path)?;
+        // Graph files are written once, under a new name, and never changed
+        let map = unsafe { Mmap::map(&file)? };
+        if map.len() < HEADER_LEN || &map[..8] != MAGIC {
+            return Err(anyhow!("{} isn't a disk index graph", path.display()));
+        }
+        if read_u32(&map, 12) != metric_id(metric) {
+            return Err(anyhow!(
+                "Disk index graph {} was built for another metric",
+                name
+            ));
+        }
+        let dims = read_u32(&map, 16) as usize;
+        let degree = read_u32(&map, 20) as usize;
+        let subspaces = read_u32(&map, 24) as usize;
+        let count = read_u64(&map, 32) as usize;
+        let medoid = read_u64(&map, 40) as usize;
+
+        let ranges = subspace_ranges(dims, subspaces);
+        let mut at = HEADER_LEN;
+        let centroids = ranges
+            .iter()
+            .map(|range| {
+                let len = CENTROIDS * range.len() * 4;
+                at += len;
+                map.get(at - len..at).map(read_f32s)
+            })
+            .collect::<Option<Vec<_>>>();
+        let codes_len = count * ranges.len();
+        let nodes_at = at + codes_len;
+        let offsets_at = nodes_at + count * Self::record_len(dims, degree);
+        let sorted_at = offsets_at + (count + 1) * 8;
+        let ids_at = sorted_at + count * 4;
+        let (Some(centroids), Some(codes)) = (centroids, map.get(at..nodes_at)) else {
+            return Err(anyhow!("Disk index graph {} is truncated", name));
+        };
+        if map.len() < ids_at || map.len() < ids_at + read_u64(&map, sorted_at - 8) as usize {
+            return Err(anyhow!("Disk index graph {} is truncated", name));
+        }
+        let quantizer = Quantizer {
+            ranges,
+            centroids,
+            codes: codes.to_vec(),
+        };
+        Ok(Self {
+            name: name.to_string(),
+            path,
+            map,
+            dims,
+            degree,
+            count,
+            medoid,
+            quantizer,
+            nodes_at,
+            offsets_at,
+            sorted_at,
+            ids_at,
+        })
+    }
+
+    fn record(&self, node: usize) -> &[u8] {
+        let len = Self::record_len(self.dims, self.degree);
+        let at = self.nodes_at + node * len;
+        &self.map[at..at + len]
+    }
+
+    fn vector(&self, node: usize) -> Vec<f32> {
+        read_f32s(&self.record(node)[..self.dims * 4])
+    }
+
+    fn neighbors(&self, node: usize) -> impl Iterator<Item = usize> + '_ {
+        let record = self.record(node);
+        let at = self.dims * 4;
+        let count = read_u32(record, at) as usize;
+        (0..count).map(move |i| read_u32(record, at + 4 + i * 4) as usize)
+    }
+
+    fn id(&self, node: usize) -> &str {
+        let start = read_u64(&self.map, self.offsets_at + node * 8) as usize;
+        let end = read_u64(&self.map, self.offsets_at + (node + 1) * 8) as usize;
+        std::str::from_utf8(&self.map[self.ids_at + start..self.ids_at + end]).unwrap_or("")
+    }
+
+    fn contains(&self, id: &str) -> bool {
+        let (mut low, mut high) = (0, self.count);
+        while low < high {
+            let mid = (low + high) / 2;
+            let node = read_u32(&self.map, self.sorted_at + mid * 4) as usize;
+            match self.id(node).cmp(id) {
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+                std::cmp::Ordering::Equal => return true,
+            }
+        }
+        false
+    }
+
+    // Every node's id and vector, for merging into a new graph
+    fn entries(&self) -> impl Iterator<Item = (&str, Vec<f32>)> + '_ {
+        (0..self.count).map(|node| (self.id(node), self.vector(node)))
+    }
+
+    // Beam search over `list` candidates ranked by quantized distance; the
+    // expanded nodes come back with exact distances, closest first
+    fn search(&self, query: &[f32], list: usize, metric: Metric) -> Vec<(f32, usize)> {
+        let table = self.quantizer.table(query, metric);
+        // (quantized distance, node, expanded)
+        let mut candidates = vec![(
+            self.quantizer.distance(&table, self.medoid),
+            self.medoid,
+            false,
+        )];
+        let mut seen = HashSet::from([self.medoid]);
+        let mut expanded = Vec::new();
+        while let Some(next) = candidates.iter().position(|c| !c.2) {
+            candidates[next].2 = true;
+            let node = candidates[next].1;
+            expanded.push((metric.distance(query, &self.vector(node)), node));
+            for neighbor in self.neighbors(node) {
+                if neighbor >= self.count || !seen.insert(neighbor) {
+                    continue;
+                }
+                let distance = self.quantizer.distance(&table, neighbor);
+                let at = candidates.partition_point(|c| c.0 <= distance);
+                if at < list {
+                    candidates.insert(at, (distance, neighbor, false));
+                    candidates.truncate(list);
+                }
+            }
+        }
+        expanded.sort_by(|a, b| a.0.total_cmp(&b.0));
+        expanded
+    }
+}
+
+// The graph of `entries`, built in memory and written to `path`
+fn build_graph(
+    path: &Path,
+    entries: &[(String, Vec<f32>)],
+    metric: Metric,
+    degree: usize,
+    build_list: usize,
+    subspaces: usize,
+) -> Result<()> {
+    let count = entries.len();
+    let dims = entries.first().map_or(0, |(_, v)| v.len());
+    if let Some((id, vector)) = entries.iter().find(|(_, v)| v.len() != dims) {
+        return Err(anyhow!(
+            "Vector {} has {} dimensions but the disk index has {}",
+            id,
+            vector.len(),
+            dims
+        ));
+    }
+    let vectors: Vec<Vec<f32>> = entries.iter().map(|(_, v)| v.clone()).collect();
+    let distance = |a: usize, b: usize| metric.distance(&vectors[a], &vectors[b]);
+
+    // The medoid, approximated as the vector nearest the mean, is where
+    // every search starts
+    let mut mean = vec![0.0f32; dims];
+    for vector in &vectors {
+        for (m, x) in mean.iter_mut().zip(vector) {
+            *m += x / count as f32;
+        }
+    }
+    let medoid = (0..count)
+        .min_by(|&a, &b| {
+            metric
+                .distance(&mean, &vectors[a])
+                .total_cmp(&metric.distance(&mean, &vectors[b]))
+        })
+        .unwrap_or(0);
+
+    // Vamana: start from random edges, then for each node search the graph
+    // for it and keep a pruned set of what the search visited, adding the
+    // reverse edges as well. The second pass prunes less eagerly (alpha > 1)
+    // to keep the long edges that make the graph navigable. Inner products
+    // aren't distances, so they only get the first kind of pass.
+    let mut rng = Rng(count as u64);
+    let mut graph: Vec<Vec<usize>> = (0..count)
+        .map(|node| {
+            let mut edges: Vec<usize> = (0..degree.min(count.saturating_sub(1)))
+                .map(|_| rng.next(count))
+                .filter(|&other| other != node)
+                .collect();
+            edges.sort_unstable();
+            edges.dedup();
+            edges
+        })
+        .collect();
+    let prune = |node: usize, mut candidates: Vec<usize>, alpha: f32| -> Vec<usize> {
+        candidates.retain(|&c| c != node);
+        candidates.sort_unstable();
+        candidates.dedup();
+        let mut candidates: Vec<(f32, usize)> = candidates
+            .into_iter()
+            .map(|c| (distance(node, c), c))
+            .collect();
+        candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let mut kept: Vec<usize> = Vec::with_capacity(degree);
+        for (d, c) in candidates {
+            if kept.len() == degree {
+                break;
+            }
+            if kept.iter().all(|&k| alpha * distance(k, c) > d) {
+                kept.push(c);
+            }
+        }
+        kept
+    };
+    let passes: &[f32] = match metric {
+        Metric::DotProduct => &[1.0],
+        _ => &[1.0, 1.2],
+    };
+    for &alpha in passes {
+        let mut order: Vec<usize> = (0..count).collect();
+        for i in (1..count).rev() {
+            order.swap(i, rng.next(i + 1));
+        }
+        for node in order {
+            let visited = greedy_visit(&graph, &vectors, medoid, node, build_list, metric);
+            let mut candidates = visited;
+            candidates.extend(&graph[node]);
+            graph[node] = prune(node, candidates, alpha);
+            for neighbor in graph[node].clone() {
+                if graph[neighbor].contains(&node) {
+                    continue;
+                }
+                graph[neighbor].push(node);
+                if graph[neighbor].len() > degree {
+                    graph[neighbor] = prune(neighbor, graph[neighbor].clone(), alpha);
+                }
+            }
+        }
+    }
+
+    let quantizer = Quantizer::train(&vectors, dims, subspaces, metric);
+    write_graph(path, entries, &graph, &quantizer, metric, degree, medoid)
+}
+
+// The nodes a search for `target` expands, starting from `start`
+fn greedy_visit(
+    graph: &[Vec<usize>],
+    vectors: &[Vec<f32>],
+    start: usize,
+    target: usize,
+    list: usize,
+    metric: Metric,
+) -> Vec<usize> {
+    let query = &vectors[target];
+    let mut candidates = vec![(metric.distance(query, &vectors[start]), start, false)];
+    let mut seen = HashSet::from([start]);
+    let mut visited = Vec::new();
+    while let Some(next) = candidates.iter().position(|c| !c.2) {
+        candidates[next].2 = true;
+        let node = candidates[next].1;
+        visited.push(node);
+        for &neighbor in &graph[node] {
+            if !seen.insert(neighbor) {
+                continue;
+            }
+            let distance = metric.distance(query, &vectors[neighbor]);
+            let at = candidates.partition_point(|c| c.0 <= distance);
+            if at < list {
+                candidates.insert(at, (distance, neighbor, false));
+                candidates.truncate(list);
+            }
+        }
+    }
+    visited
+}
+
+fn write_graph(
+    path: &Path,
+    entries: &[(String, Vec<f32>)],
+    graph: &[Vec<usize>],
+    quantizer: &Quantizer,
+    metric: Metric,
+    degree: usize,
+    medoid: usize,
+) -> Result<()> {
+    let count = entries.len();
+    let dims = entries.first().map_or(0, |(_, v)| v.len());
+    let partial = path.with_extension("partial");
+    let mut out = BufWriter::new(File::create(&partial)?);
+
+    out.write_all(MAGIC)?;
+    for value in [
+        1,
+        metric_id(metric),
+        dims as u32,
+        degree as u32,
+        quantizer.ranges.len() as u32,
+        0,
+    ] {
+        out.write_all(&value.to_le_bytes())?;
+    }
+    out.write_all(&(count as u64).to_le_bytes())?;
+    out.write_all(&(medoid as u64).to_le_bytes())?;
+    for centroids in &quantizer.centroids {
+        for x in centroids {
+            out.write_all(&x.to_le_bytes())?;
+        }
+    }
+    out.write_all(&quantizer.codes)?;
+
+    for ((_, vector), edges) in entries.iter().zip(graph) {
+        for x in vector {
+            out.write_all(&x.to_le_bytes())?;
+        }
+        out.write_all(&(edges.len() as u32).to_le_bytes())?;
+        for i in 0..degree {
+            let edge = edges.get(i).copied().unwrap_or(0) as u32;
+            out.write_all(&edge.to_le_bytes())?;
+        }
+    }
+
+    let mut offset = 0u64;
+    for (id, _) in entries {
+        out.write_all(&offset.to_le_bytes())?;
+        offset += id.len() as u64;
+    }
+    out.write_all(&offset.to_le_bytes())?;
+    let mut sorted: Vec<u32> = (0..count as u32).collect();
+    sorted.sort_by(|&a, &b| entries[a as usize].0.cmp(&entries[b as usize].0));
+    for node in sorted {
+        out.write_all(&node.to_le_bytes())?;
+    }
+    for (id, _) in entries {
+        out.write_all(id.as_bytes())?;
+    }
+
+    out.into_inner()?.sync_all()?;
+    std::fs::rename(&partial, path)?;
+    Ok(())
+}
+
+impl DiskIndex {
+    // Graph files go in `dir`, which is created if need be
+    pub fn new(dir: impl Into<PathBuf>, metric: Metric) -> Self {
+        Self {
+            dir: dir.into(),
+            metric,
+            degree: 32,
+            build_list: 100,
+            search_list: 64,
+            subspaces: 32,
+            merge_threshold: 100_000,
+            state: RwLock::new(State::default()),
+        }
+    }
+
+    // Edges per node; more improve recall at the cost of disk reads
+    pub fn with_degree(mut self, degree: usize) -> Self {
+        self.degree = degree.max(1);
+        self
+    }
+
+    // Candidate list size while building the graph
+    pub fn with_build_list(mut self, build_list: usize) -> Self {
+        self.build_list = build_list.max(1);
+        self
+    }
+
+    // Candidate list size while searching, about the nodes read per query
+    pub fn with_search_list(mut self, search_list: usize) -> Self {
+        self.search_list = search_list.max(1);
+        self
+    }
+
+    // Bytes of quantized code per vector kept in memory
+    pub fn with_subspaces(mut self, subspaces: usize) -> Self {
+        self.subspaces = subspaces.max(1);
+        self
+    }
+
+    // Writes held in memory before they're merged into the graph
+    pub fn with_merge_threshold(mut self, merge_threshold: usize) -> Self {
+        self.merge_threshold = merge_threshold.max(1);
+        self
+    }
+
+    fn new_graph_name() -> String {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos());
+        format!(
+            "graph-{}-{}-{}.disk",
+            nanos,
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        )
+    }
+
+    // Rebuilds the graph from what it holds plus the delta. Searches wait
+    // for it.
+    fn merge(&self, state: &mut State) -> Result<()> {
+        let mut entries: Vec<(String, Vec<f32>)> = match &state.graph {
+            Some(graph) => graph
+                .entries()
+                .filter(|(id, _)| !state.removed.contains(*id))
+                .map(|(id, vector)| (id.to_string(), vector))
+                .collect(),
+            None => Vec::new(),
+        };
+        entries.extend(state.delta.drain());
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let old = state.graph.take();
+        state.removed.clear();
+        if !entries.is_empty() {
+            std::fs::create_dir_all(&self.dir)?;
+            let name = Self::new_graph_name();
+            build_graph(
+                &self.dir.join(&name),
+                &entries,
+                self.metric,
+                self.degree,
+                self.build_list,
+                self.subspaces,
+            )?;
+            state.graph = Some(Graph::open(&self.dir, &name, self.metric)?);
+        }
+        if let Some(old) = old {
+            let path = old.path.clone();
+            drop(old);
+            let _ = std::fs::remove_file(path);
+        }
+        Ok(())
+    }
+
+    fn maybe_merge(&self, state: &mut State) -> Result<()> {
+        if state.delta.len() >= self.merge_threshold {
+            self.merge(state)?;
+        }
+        Ok(())
+    }
+
+    fn insert(&self, state: &mut State, id: &str, vector: &[f32]) {
+        if state.graph.as_ref().is_some_and(|graph| graph.contains(id)) {
+            state.removed.insert(id.to_string());
+        }
+        state.delta.insert(id.to_string(), vector.to_vec());
+    }
+
+    // Merges whatever is in memory into the graph now
+    pub fn flush(&self) -> Result<()> {
+        let mut state = self.state.write();
+        match state.delta.is_empty() && state.removed.is_empty() {
+            true => Ok(()),
+            false => self.merge(&mut state),
+        }
+    }
+}
+
+impl VectorIndex for DiskIndex {
+    fn add_vector(&self, id: &str, vector: &[f32]) -> Result<()> {
+        let mut state = self.state.write();
+        self.insert(&mut state, id, vector);
+        self.maybe_merge(&mut state)
+    }
+
+    fn build_batch(&self, ids: &[&str], vectors: &[&[f32]]) -> Result<()> {
+        check_batch(ids, vectors)?;
+        let mut state = self.state.write();
+        for (id, vector) in ids.iter().zip(vectors) {
+            self.insert(&mut state, id, vector);
+        }
+        self.maybe_merge(&mut state)
+    }
+
+    fn remove_vector(&self, id: &str) -> Result<bool> {
+        let mut state = self.state.write();
+        let in_delta = state.delta.remove(id).is_some();
+        let in_graph = state.graph.as_ref().is_some_and(|graph| graph.contains(id))
+            && state.removed.insert(id.to_string());
+        Ok(in_delta || in_graph)
+    }
+
+    fn search_filtered(
+        &self,
+        query: &[f32],
+        k: usize,
+        allowed: &dyn Fn(&str) -> bool,
+    ) -> Result<Vec<SearchResult>> {
+        let state = self.state.read();
+        let mut results: Vec<SearchResult> = state
+            .delta
+            .iter()
+            .filter(|(id, _)| allowed(id))
+            .map(|(id, vector)| SearchResult {
+                id: id.clone(),
+                score: self.metric.similarity(query, vector),
+            })
+            .collect();
+
+        if let Some(graph) = state.graph.as_ref().filter(|_| k > 0) {
+            if query.len() != graph.dims {
+                return Err(anyhow!(
+                    "Query has {} dimensions but the disk index has {}",
+                    query.len(),
+                    graph.dims
+                ));
+            }
+            // Widen the search until k allowed ones turn up
+            let mut list = self.search_list.max(k);
+            loop {
+                let hits: Vec<SearchResult> = graph
+                    .search(query, list, self.metric)
+                    .into_iter()
+                    .map(|(distance, node)| (distance, graph.id(node)))
+                    .filter(|(_, id)| !state.removed.contains(*id) && allowed(id))
+                    .take(k)
+                    .map(|(distance, id)| SearchResult {
+                        id: id.to_string(),
+                        score: self.metric.score(distance),
+                    })
+                    .collect();
+                if hits.len() >= k || list >= graph.count {
+                    results.extend(hits);
+                    break;
+                }
+                list = list.saturating_mul(4).min(graph.count);
+            }
+        }
+
+        sort_results(&mut results);
+        results.truncate(k);
+        Ok(results)
+    }
+
+    fn size(&self) -> usize {
+        let state = self.state.read();
+        let on_disk = state.graph.as_ref().map_or(0, |graph| graph.count);
+        on_disk - state.removed.len() + state.delta.len()
+    }
+
+    fn clear(&self) {
+        let mut state = self.state.write();
+        if let Some(graph) = state.graph.take() {
+            let path = graph.path.clone();
+            drop(graph);
+            let _ = std::fs::remove_file(path);
+        }
+        *state = State::default();
+    }
+
+    fn index_type(&self) -> &'static str {
+        "disk"
+    }
+
+    // Pages the whole graph file in, so it only makes sense when it fits in
+    // memory
+    fn warm_up(&self) -> usize {
+        let state = self.state.read();
+        let delta: usize = state.delta.values().map(|v| crate::touch(v)).sum();
+        let Some(graph) = &state.graph else {
+            return delta;
+        };
+        #[cfg(unix)]
+        let _ = graph.map.advise(memmap2::Advice::WillNeed);
+        delta + crate::touch(&graph.map[..])
+    }
+
+    // The graph stays in its file; snapshots name it and hold the writes
+    // not merged into it yet
+    fn save(&self) -> Result<Vec<u8>> {
+        let state = self.state.read();
+        Ok(bincode::serialize(&(
+            "disk",
+            state.graph.as_ref().map(|graph| &graph.name),
+            state.delta.iter().collect::<Vec<_>>(),
+            state.removed.iter().collect::<Vec<_>>(),
+        ))?)
+    }
+
+    fn load(&self, data: &[u8]) -> Result<()> {
+        let (kind, name, delta, removed): Snapshot = bincode::deserialize(data)?;
+        if kind != "disk" {
+            return Err(anyhow!("Cannot load a {} index into a disk index", kind));
+        }
+        let graph = name
+            .map(|name| Graph::open(&self.dir, &name, self.metric))
+            .transpose()?;
+        *self.state.write() = State {
+            graph,
+            delta: delta.into_iter().collect(),
+            removed: removed.into_iter().collect(),
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vectors(count: usize, dims: usize) -> Vec<(String, Vec<f32>)> {
+        let mut rng = Rng(7);
+        (0..count)
+            .map(|i| {
+                let vector = (0..dims)
+                    .map(|_| rng.next(1000) as f32 / 500.0 - 1.0)
+                    .collect();
+                (format!("v{:04}", i), vector)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_graph_search_finds_neighbors() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = DiskIndex::new(dir.path(), Metric::Euclidean)
+            .with_degree(12)
+            .with_build_list(40)
+            .with_subspaces(4)
+            .with_merge_threshold(250);
+        let entries = vectors(500, 16);
+        let ids: Vec<&str> = entries.iter().map(|(id, _)| id.as_str()).collect();
+        let data: Vec<&[f32]> = entries.iter().map(|(_, v)| v.as_slice()).collect();
+        index.build_batch(&ids[..300], &data[..300]).unwrap();
+        // The first 300 were merged into a graph, the rest stay in memory
+        index.build_batch(&ids[300..], &data[300..]).unwrap();
+        assert!(index.state.read().graph.is_some());
+        assert_eq!(index.state.read().delta.len(), 200);
+        assert_eq!(index.size(), 500);
+
+        // Recall against an exact scan
+        let mut found = 0;
+        for (_, query) in entries.iter().step_by(25) {
+            let mut exact: Vec<_> = entries
+                .iter()
+                .map(|(id, v)| (Metric::Euclidean.distance(query, v), id))
+                .collect();
+            exact.sort_by(|a, b| a.0.total_cmp(&b.0));
+            let results = index.search(query, 10).unwrap();
+            found += exact[..10]
+                .iter()
+                .filter(|(_, id)| results.iter().any(|r| &&r.id == id))
+                .count();
+        }
+        assert!(found >= 180, "recall {}/200", found);
+
+        // Overwrites and removes hide the graph's copy
+        index.add_vector("v0001", &entries[2].1).unwrap();
+        assert!(index.remove_vector("v0002").unwrap());
+        assert!(!index.remove_vector("v0002").unwrap());
+        let results = index.search(&entries[2].1, 1).unwrap();
+        assert_eq!(results[0].id, "v0001");
+        let filtered = index
+            .search_filtered(&entries[5].1, 3, &|id| id.ends_with('7'))
+            .unwrap();
+        assert_eq!(filtered.len(), 3);
+        assert!(filtered.iter().all(|r| r.id.ends_with('7')));
+        assert_eq!(index.size(), 499);
+
+        // Reopens from the snapshot and the graph file
+        let snapshot = index.save().unwrap();
+        let restored = DiskIndex::new(dir.path(), Metric::Euclidean);
+        restored.load(&snapshot).unwrap();
+        assert_eq!(restored.size(), 499);
+        assert_eq!(restored.search(&entries[2].1, 1).unwrap()[0].id, "v0001");
+        assert!(DiskIndex::new(dir.path(), Metric::Cosine)
+            .load(&snapshot)
+            .is_err());
+
+        // Flushing merges everything and replaces the old file
+        restored.flush().unwrap();
+        assert_eq!(restored.size(), 499);
+        assert!(restored.state.read().delta.is_empty());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
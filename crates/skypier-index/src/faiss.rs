@@ -0,0 +1,468 @@
+use ::faiss::index::IndexImpl;
+use ::faiss::selector::IdSelector;
+use ::faiss::{index_factory, read_index, write_index, Idx, Index, MetricType};
+use anyhow::{anyhow, Result};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{sort_results, Metric, SearchResult, VectorIndex};
+
+// Delegates to a faiss index built from a factory description, e.g.
+// "HNSW32", "IVF1024,Flat" or "IVF4096,PQ64", wrapped in an IDMap so any of
+// them takes our labels. Cosine vectors are normalized and searched by inner
+// product. Faiss only knows i64 labels, so ids get a new label every time
+// they're written; labels of removed vectors are dropped from the faiss
+// index where it supports removal (IVF, flat), and otherwise (HNSW) left
+// behind and skipped in results until the index is rebuilt. Indexes that
+// need training (IVF, PQ) hold vectors back and search them exactly until
+// enough have arrived to train on.
+pub struct FaissIndex {
+    description: String,
+    metric: Metric,
+    train_size: usize,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    // Created on the first vector, once the dimensions are known
+    index: Option<IndexImpl>,
+    labels: HashMap<String, i64>,
+    ids: HashMap<i64, String>,
+    next_label: i64,
+    // Labels still in the faiss index whose vectors were removed
+    stale: usize,
+    // Waiting for the index to be trained
+    pending: Vec<(i64, Vec<f32>)>,
+}
+
+// Kind, description, metric, labelled ids, next label, stale count,
+// pending vectors and the faiss index as written by `write_index`
+type Snapshot = (
+    String,
+    String,
+    String,
+    Vec<(String, i64)>,
+    i64,
+    usize,
+    Vec<(i64, Vec<f32>)>,
+    Option<Vec<u8>>,
+);
+
+fn metric_name(metric: Metric) -> &'static str {
+    match metric {
+        Metric::Cosine => "cosine",
+        Metric::Euclidean => "euclidean",
+        Metric::DotProduct => "dot_product",
+    }
+}
+
+fn normalized(vector: &[f32]) -> Vec<f32> {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm == 0.0 {
+        return vector.to_vec();
+    }
+    vector.iter().map(|x| x / norm).collect()
+}
+
+// faiss-rs only reads and writes indexes by path
+fn scratch_path() -> String {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    std::env::temp_dir()
+        .join(format!(
+            "skypier-faiss-{}-{}.index",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ))
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn index_to_bytes(index: &IndexImpl) -> Result<Vec<u8>> {
+    let path = scratch_path();
+    let written = write_index(index, &path)
+        .map_err(|e| anyhow!("Failed to write faiss index: {}", e))
+        .and_then(|_| Ok(std::fs::read(&path)?));
+    let _ = std::fs::remove_file(&path);
+    written
+}
+
+fn index_from_bytes(bytes: &[u8]) -> Result<IndexImpl> {
+    let path = scratch_path();
+    std::fs::write(&path, bytes)?;
+    let index = read_index(&path).map_err(|e| anyhow!("Failed to read faiss index: {}", e));
+    let _ = std::fs::remove_file(&path);
+    index
+}
+
+impl FaissIndex {
+    pub fn new(description: &str, metric: Metric) -> Self {
+        Self {
+            description: description.to_string(),
+            metric,
+            train_size: 10_000,
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    // Vectors collected before training an index that needs it. IVF wants
+    // at least one per list, and works best with 30 or more.
+    pub fn with_train_size(mut self, train_size: usize) -> Self {
+        self.train_size = train_size.max(1);
+        self
+    }
+
+    fn metric_type(&self) -> MetricType {
+        match self.metric {
+            Metric::Euclidean => MetricType::L2,
+            Metric::Cosine | Metric::DotProduct => MetricType::InnerProduct,
+        }
+    }
+
+    fn prepare(&self, vector: &[f32]) -> Vec<f32> {
+        match self.metric {
+            Metric::Cosine => normalized(vector),
+            _ => vector.to_vec(),
+        }
+    }
+
+    // Faiss reports squared L2 distances and raw inner products
+    fn score(&self, distance: f32) -> f32 {
+        match self.metric {
+            Metric::Euclidean => self.metric.score(distance.max(0.0).sqrt()),
+            Metric::Cosine | Metric::DotProduct => distance,
+        }
+    }
+
+    fn index<'a>(&self, inner: &'a mut Inner, dimensions: usize) -> Result<&'a mut IndexImpl> {
+        if inner.index.is_none() {
+            let factory = match self.description.starts_with("IDMap") {
+                true => self.description.clone(),
+                false => format!("IDMap,{}", self.description),
+            };
+            let index =
+                index_factory(dimensions as u32, factory, self.metric_type()).map_err(|e| {
+                    anyhow!(
+                        "Failed to build faiss index \"{}\": {}",
+                        self.description,
+                        e
+                    )
+                })?;
+            inner.index = Some(index);
+        }
+        let index = inner.index.as_mut().unwrap();
+        if index.d() as usize != dimensions {
+            return Err(anyhow!(
+                "Vector has {} dimensions but the faiss index has {}",
+                dimensions,
+                index.d()
+            ));
+        }
+        Ok(index)
+    }
+
+    // Drops `id`'s current label, if it has one
+    fn unlabel(&self, inner: &mut Inner, id: &str) -> Result<bool> {
+        let Some(label) = inner.labels.remove(id) else {
+            return Ok(false);
+        };
+        inner.ids.remove(&label);
+        if let Some(at) = inner.pending.iter().position(|(l, _)| *l == label) {
+            inner.pending.swap_remove(at);
+            return Ok(true);
+        }
+        let removed = match inner.index.as_mut() {
+            Some(index) => {
+                let selector = IdSelector::batch(&[Idx::new(label as u64)])
+                    .map_err(|e| anyhow!("Failed to select faiss ids: {}", e))?;
+                index.remove_ids(&selector).unwrap_or(0)
+            }
+            None => 0,
+        };
+        if removed == 0 {
+            inner.stale += 1;
+        }
+        Ok(true)
+    }
+
+    fn add_all(&self, inner: &mut Inner, ids: &[&str], vectors: &[Vec<f32>]) -> Result<()> {
+        let Some(dimensions) = vectors.first().map(Vec::len) else {
+            return Ok(());
+        };
+        if let Some(vector) = vectors.iter().find(|v| v.len() != dimensions) {
+            return Err(anyhow!(
+                "Vector has {} dimensions but the faiss index has {}",
+                vector.len(),
+                dimensions
+            ));
+        }
+        self.index(inner, dimensions)?;
+        for id in ids {
+            self.unlabel(inner, id)?;
+        }
+
+        let mut labels = Vec::with_capacity(ids.len());
+        for id in ids {
+            let label = inner.next_label;
+            inner.next_label += 1;
+            // A later duplicate in the batch wins, as when added in order
+            if let Some(previous) = inner.labels.insert(id.to_string(), label) {
+                inner.ids.remove(&previous);
+                inner.stale += 1;
+            }
+            inner.ids.insert(label, id.to_string());
+            labels.push(label);
+        }
+
+        let trained = inner.index.as_ref().is_some_and(|index| index.is_trained());
+        if !trained {
+            inner
+                .pending
+                .extend(labels.into_iter().zip(vectors.iter().cloned()));
+            if inner.pending.len() < self.train_size {
+                return Ok(());
+            }
+            let pending = std::mem::take(&mut inner.pending);
+            let flat: Vec<f32> = pending
+                .iter()
+                .flat_map(|(_, v)| v.iter().copied())
+                .collect();
+            let index = inner.index.as_mut().unwrap();
+            index
+                .train(&flat)
+                .map_err(|e| anyhow!("Failed to train faiss index: {}", e))?;
+            let labels: Vec<Idx> = pending.iter().map(|(l, _)| Idx::new(*l as u64)).collect();
+            return index
+                .add_with_ids(&flat, &labels)
+                .map_err(|e| anyhow!("Failed to add to faiss index: {}", e));
+        }
+
+        let flat: Vec<f32> = vectors.iter().flatten().copied().collect();
+        let labels: Vec<Idx> = labels.iter().map(|l| Idx::new(*l as u64)).collect();
+        inner
+            .index
+            .as_mut()
+            .unwrap()
+            .add_with_ids(&flat, &labels)
+            .map_err(|e| anyhow!("Failed to add to faiss index: {}", e))
+    }
+}
+
+impl VectorIndex for FaissIndex {
+    fn add_vector(&self, id: &str, vector: &[f32]) -> Result<()> {
+        let mut inner = self.inner.lock();
+        self.add_all(&mut inner, &[id], &[self.prepare(vector)])
+    }
+
+    fn build_batch(&self, ids: &[&str], vectors: &[&[f32]]) -> Result<()> {
+        crate::check_batch(ids, vectors)?;
+        let prepared: Vec<Vec<f32>> = vectors.iter().map(|v| self.prepare(v)).collect();
+        let mut inner = self.inner.lock();
+        self.add_all(&mut inner, ids, &prepared)
+    }
+
+    fn remove_vector(&self, id: &str) -> Result<bool> {
+        let mut inner = self.inner.lock();
+        self.unlabel(&mut inner, id)
+    }
+
+    fn search_filtered(
+        &self,
+        query: &[f32],
+        k: usize,
+        allowed: &dyn Fn(&str) -> bool,
+    ) -> Result<Vec<SearchResult>> {
+        let query = self.prepare(query);
+        let mut inner = self.inner.lock();
+        let inner = &mut *inner;
+        if k == 0 {
+            return Ok(Vec::new());
+        }
+
+        // Held back vectors are few enough to compare exactly
+        let mut results: Vec<SearchResult> = inner
+            .pending
+            .iter()
+            .filter_map(|(label, vector)| {
+                let id = inner.ids.get(label)?;
+                allowed(id).then(|| SearchResult {
+                    id: id.clone(),
+                    score: self.metric.similarity(&query, vector),
+                })
+            })
+            .collect();
+
+        if let Some(index) = inner.index.as_mut().filter(|index| index.ntotal() > 0) {
+            if index.d() as usize != query.len() {
+                return Err(anyhow!(
+                    "Query has {} dimensions but the faiss index has {}",
+                    query.len(),
+                    index.d()
+                ));
+            }
+            // Faiss can't filter, so fetch more until k allowed ones are in
+            // or the whole index has been seen
+            let total = index.ntotal() as usize;
+            let mut fetch = (k + inner.stale).min(total);
+            loop {
+                let found = index
+                    .search(&query, fetch)
+                    .map_err(|e| anyhow!("Faiss search failed: {}", e))?;
+                let hits: Vec<SearchResult> = found
+                    .labels
+                    .iter()
+                    .zip(&found.distances)
+                    .filter_map(|(label, &distance)| {
+                        let id = inner.ids.get(&(label.get()? as i64))?;
+                        allowed(id).then(|| SearchResult {
+                            id: id.clone(),
+                            score: self.score(distance),
+                        })
+                    })
+                    .collect();
+                if hits.len() >= k || fetch >= total {
+                    results.extend(hits);
+                    break;
+                }
+                fetch = fetch.saturating_mul(2).min(total);
+            }
+        }
+
+        sort_results(&mut results);
+        results.truncate(k);
+        Ok(results)
+    }
+
+    fn size(&self) -> usize {
+        self.inner.lock().labels.len()
+    }
+
+    fn clear(&self) {
+        *self.inner.lock() = Inner::default();
+    }
+
+    fn index_type(&self) -> &'static str {
+        "faiss"
+    }
+
+    fn save(&self) -> Result<Vec<u8>> {
+        let inner = self.inner.lock();
+        let index = inner.index.as_ref().map(index_to_bytes).transpose()?;
+        Ok(bincode::serialize(&(
+            "faiss",
+            &self.description,
+            metric_name(self.metric),
+            inner
+                .labels
+                .iter()
+                .map(|(id, label)| (id.clone(), *label))
+                .collect::<Vec<_>>(),
+            inner.next_label,
+            inner.stale,
+            &inner.pending,
+            index,
+        ))?)
+    }
+
+    fn load(&self, data: &[u8]) -> Result<()> {
+        let (kind, description, metric, labels, next_label, stale, pending, index): Snapshot =
+            bincode::deserialize(data)?;
+        if kind != "faiss" {
+            return Err(anyhow!("Cannot load a {} index into a faiss index", kind));
+        }
+        // A different description or metric means rebuilding from the vectors
+        if description != self.description || metric != metric_name(self.metric) {
+            return Err(anyhow!(
+                "Faiss index snapshot is \"{}\" by {}, not \"{}\" by {}",
+                description,
+                metric,
+                self.description,
+                metric_name(self.metric)
+            ));
+        }
+        let index = index.as_deref().map(index_from_bytes).transpose()?;
+        let ids = labels
+            .iter()
+            .map(|(id, label)| (*label, id.clone()))
+            .collect();
+        *self.inner.lock() = Inner {
+            index,
+            labels: labels.into_iter().collect(),
+            ids,
+            next_label,
+            stale,
+            pending,
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit(i: usize, dimensions: usize) -> Vec<f32> {
+        let mut vector = vec![0.0; dimensions];
+        vector[i % dimensions] = 1.0;
+        vector[(i + 1) % dimensions] = (i % 7) as f32 / 10.0;
+        vector
+    }
+
+    #[test]
+    fn test_hnsw_add_remove_and_reload() {
+        let index = FaissIndex::new("HNSW16", Metric::Cosine);
+        index.add_vector("a", &[1.0, 0.0, 0.0]).unwrap();
+        index.add_vector("b", &[0.0, 1.0, 0.0]).unwrap();
+        index.add_vector("c", &[0.0, 0.0, 1.0]).unwrap();
+        assert_eq!(index.search(&[0.9, 0.1, 0.0], 1).unwrap()[0].id, "a");
+
+        // HNSW can't remove, so "a" is skipped rather than dropped
+        assert!(index.remove_vector("a").unwrap());
+        assert!(!index.remove_vector("a").unwrap());
+        let results = index.search(&[0.9, 0.1, 0.0], 2).unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|r| r.id != "a"));
+
+        // Re-adding moves "b"
+        index.add_vector("b", &[1.0, 0.0, 0.0]).unwrap();
+        assert_eq!(index.size(), 2);
+        let filtered = index
+            .search_filtered(&[1.0, 0.0, 0.0], 1, &|id| id != "b")
+            .unwrap();
+        assert_eq!(filtered[0].id, "c");
+
+        let restored = FaissIndex::new("HNSW16", Metric::Cosine);
+        restored.load(&index.save().unwrap()).unwrap();
+        assert_eq!(restored.size(), 2);
+        assert_eq!(restored.search(&[1.0, 0.0, 0.0], 1).unwrap()[0].id, "b");
+        assert!(FaissIndex::new("Flat", Metric::Cosine)
+            .load(&index.save().unwrap())
+            .is_err());
+    }
+
+    #[test]
+    fn test_ivf_trains_once_enough_vectors_arrive() {
+        let index = FaissIndex::new("IVF4,Flat", Metric::Euclidean).with_train_size(40);
+        let vectors: Vec<Vec<f32>> = (0..60).map(|i| unit(i, 8)).collect();
+        let ids: Vec<String> = (0..60).map(|i| format!("v{}", i)).collect();
+        for (id, vector) in ids.iter().zip(&vectors).take(10) {
+            index.add_vector(id, vector).unwrap();
+        }
+        // Untrained, searched exactly
+        let results = index.search(&vectors[3], 1).unwrap();
+        assert_eq!(results[0].id, "v3");
+        assert!((results[0].score - 1.0).abs() < 1e-6);
+
+        let rest: Vec<&str> = ids[10..].iter().map(String::as_str).collect();
+        let rest_vectors: Vec<&[f32]> = vectors[10..].iter().map(Vec::as_slice).collect();
+        index.build_batch(&rest, &rest_vectors).unwrap();
+        assert_eq!(index.size(), 60);
+        assert!(index.inner.lock().pending.is_empty());
+
+        // IVF removes for real
+        assert!(index.remove_vector("v3").unwrap());
+        assert_eq!(index.inner.lock().stale, 0);
+        assert!(index.add_vector("x", &[1.0; 4]).is_err());
+    }
+}
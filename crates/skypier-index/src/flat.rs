@@ -1,73 +1,170 @@
-use anyhow::Result;
-use std::collections::HashMap;
+use anyhow::{anyhow, Result};
+use parking_lot::RwLock;
 
-use crate::{SearchResult, VectorIndex};
+use crate::{sort_results, CancellationToken, IdMapper, Metric, SearchResult, VectorIndex};
 
 pub struct FlatIndex {
-    vectors: HashMap<String, Vec<f32>>,
+    entries: RwLock<Entries>,
+    metric: Metric,
+}
+
+#[derive(Default)]
+struct Entries {
+    ids: IdMapper,
+    // Indexed by internal id; `None` for freed ids
+    vectors: Vec<Option<Vec<f32>>>,
+}
+
+impl Default for FlatIndex {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl FlatIndex {
     pub fn new() -> Self {
         Self {
-            vectors: HashMap::new(),
+            entries: RwLock::new(Entries::default()),
+            metric: Metric::Cosine,
         }
     }
 
-    fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
-        let dot_product: f32 = a.iter().zip(b.iter()).map(|(x, y)| x * y).sum();
-        let norm_a: f32 = a.iter().map(|x| x * x).sum::<f32>().sqrt();
-        let norm_b: f32 = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    pub fn with_metric(mut self, metric: Metric) -> Self {
+        self.metric = metric;
+        self
+    }
+
+    // A copy of every vector, e.g. to build another index from
+    pub fn vectors(&self) -> Vec<(String, Vec<f32>)> {
+        let entries = self.entries.read();
+        entries
+            .ids
+            .iter()
+            .filter_map(|(internal, id)| {
+                let vector = entries.vectors[internal as usize].as_ref()?;
+                Some((id.to_string(), vector.clone()))
+            })
+            .collect()
+    }
 
-        if norm_a == 0.0 || norm_b == 0.0 {
-            0.0
-        } else {
-            dot_product / (norm_a * norm_b)
+    fn scan(
+        &self,
+        query: &[f32],
+        k: usize,
+        allowed: &dyn Fn(&str) -> bool,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<Vec<SearchResult>> {
+        let entries = self.entries.read();
+        let mut results = Vec::new();
+        for (row, (internal, id)) in entries.ids.iter().enumerate() {
+            if row % crate::CANCEL_CHECK_INTERVAL == 0 {
+                crate::check_cancelled(cancel)?;
+            }
+            if !allowed(id) {
+                continue;
+            }
+            if let Some(vector) = &entries.vectors[internal as usize] {
+                results.push(SearchResult {
+                    id: id.to_string(),
+                    score: self.metric.similarity(query, vector),
+                });
+            }
         }
+
+        // Sort by score (descending), ties by id
+        sort_results(&mut results);
+
+        // Take top k
+        results.truncate(k);
+
+        Ok(results)
     }
 }
 
 impl VectorIndex for FlatIndex {
-    fn add_vector(&mut self, id: &str, vector: &[f32]) -> Result<()> {
-        self.vectors.insert(id.to_string(), vector.to_vec());
+    fn add_vector(&self, id: &str, vector: &[f32]) -> Result<()> {
+        let mut entries = self.entries.write();
+        let internal = entries.ids.insert(id) as usize;
+        if internal == entries.vectors.len() {
+            entries.vectors.push(None);
+        }
+        entries.vectors[internal] = Some(vector.to_vec());
         Ok(())
     }
 
-    fn remove_vector(&mut self, id: &str) -> Result<bool> {
-        Ok(self.vectors.remove(id).is_some())
+    fn remove_vector(&self, id: &str) -> Result<bool> {
+        let mut entries = self.entries.write();
+        let Some(internal) = entries.ids.remove(id) else {
+            return Ok(false);
+        };
+        entries.vectors[internal as usize] = None;
+        Ok(true)
     }
 
-    fn search(&self, query: &[f32], k: usize) -> Result<Vec<SearchResult>> {
-        let mut results: Vec<_> = self
-            .vectors
-            .iter()
-            .map(|(id, vector)| {
-                let score = Self::cosine_similarity(query, vector);
-                SearchResult {
-                    id: id.clone(),
-                    score,
-                }
-            })
-            .collect();
-
-        // Sort by score (descending)
-        results.sort_by(|a, b| {
-            b.score
-                .partial_cmp(&a.score)
-                .unwrap_or(std::cmp::Ordering::Equal)
-        });
+    fn search_filtered(
+        &self,
+        query: &[f32],
+        k: usize,
+        allowed: &dyn Fn(&str) -> bool,
+    ) -> Result<Vec<SearchResult>> {
+        self.scan(query, k, allowed, None)
+    }
 
-        // Take top k
-        results.truncate(k);
+    fn search_cancellable(
+        &self,
+        query: &[f32],
+        k: usize,
+        allowed: &dyn Fn(&str) -> bool,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<SearchResult>> {
+        self.scan(query, k, allowed, Some(cancel))
+    }
 
-        Ok(results)
+    fn index_type(&self) -> &'static str {
+        "flat"
     }
 
     fn size(&self) -> usize {
-        self.vectors.len()
+        self.entries.read().ids.len()
+    }
+
+    fn clear(&self) {
+        *self.entries.write() = Entries::default();
     }
 
-    fn clear(&mut self) {
-        self.vectors.clear();
+    fn warm_up(&self) -> usize {
+        let entries = self.entries.read();
+        entries
+            .vectors
+            .iter()
+            .flatten()
+            .map(|v| crate::touch(v))
+            .sum()
+    }
+
+    fn save(&self) -> Result<Vec<u8>> {
+        let entries = self.entries.read();
+        Ok(bincode::serialize(&(
+            "flat",
+            &entries.ids,
+            &entries.vectors,
+        ))?)
+    }
+
+    fn load(&self, data: &[u8]) -> Result<()> {
+        let (kind, ids, vectors): (String, IdMapper, Vec<Option<Vec<f32>>>) =
+            bincode::deserialize(data)?;
+        if kind != "flat" {
+            return Err(anyhow!("Cannot load a {} index into a flat index", kind));
+        }
+        let consistent = vectors.len() == ids.capacity()
+            && vectors.iter().enumerate().all(|(internal, vector)| {
+                vector.is_some() == ids.external(internal as u32).is_some()
+            });
+        if !consistent {
+            return Err(anyhow!("Flat index snapshot ids don't match its vectors"));
+        }
+        *self.entries.write() = Entries { ids, vectors };
+        Ok(())
     }
 }
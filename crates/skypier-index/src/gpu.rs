@@ -0,0 +1,275 @@
+use anyhow::{anyhow, Result};
+use std::sync::OnceLock;
+use wgpu::util::DeviceExt;
+
+use crate::Metric;
+
+// Scores a query against every row of a packed vector arena (row-major,
+// `dimensions` floats per row) in a compute shader, one invocation per row.
+// Scores match `Metric::similarity`, up to float rounding. Arenas bigger
+// than the device allows in one buffer are scored a chunk at a time.
+pub struct GpuScorer {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipeline: wgpu::ComputePipeline,
+    // Rows per dispatch, bounded by the storage buffer size and the
+    // workgroup count limit
+    max_floats: u64,
+    max_workgroups: u32,
+}
+
+const WORKGROUP_SIZE: u32 = 256;
+
+const SHADER: &str = r#"
+struct Params {
+    dims: u32,
+    rows: u32,
+    metric: u32,
+    pad: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> query: array<f32>;
+@group(0) @binding(2) var<storage, read> arena: array<f32>;
+@group(0) @binding(3) var<storage, read_write> scores: array<f32>;
+
+@compute @workgroup_size(256)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let row = id.x;
+    if (row >= params.rows) {
+        return;
+    }
+    let base = row * params.dims;
+    var dot = 0.0;
+    var query_norm = 0.0;
+    var row_norm = 0.0;
+    var squared = 0.0;
+    for (var i = 0u; i < params.dims; i++) {
+        let q = query[i];
+        let v = arena[base + i];
+        dot += q * v;
+        query_norm += q * q;
+        row_norm += v * v;
+        squared += (q - v) * (q - v);
+    }
+    var score = dot;
+    if (params.metric == 0u) {
+        score = 0.0;
+        if (query_norm > 0.0 && row_norm > 0.0) {
+            score = dot / (sqrt(query_norm) * sqrt(row_norm));
+        }
+    } else if (params.metric == 1u) {
+        score = 1.0 / (1.0 + sqrt(squared));
+    }
+    scores[row] = score;
+}
+"#;
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct Params {
+    dims: u32,
+    rows: u32,
+    metric: u32,
+    pad: u32,
+}
+
+impl GpuScorer {
+    // The process-wide scorer, or `None` when there's no GPU adapter to run
+    // it on. The adapter is only looked for once.
+    pub fn shared() -> Option<&'static GpuScorer> {
+        static SCORER: OnceLock<Option<GpuScorer>> = OnceLock::new();
+        SCORER
+            .get_or_init(|| pollster::block_on(Self::new()).ok())
+            .as_ref()
+    }
+
+    async fn new() -> Result<Self> {
+        let instance =
+            wgpu::Instance::new(wgpu::InstanceDescriptor::new_without_display_handle_from_env());
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                ..Default::default()
+            })
+            .await?;
+        // As much of the adapter's buffer size as it will give
+        let available = adapter.limits();
+        let limits = wgpu::Limits {
+            max_storage_buffer_binding_size: available.max_storage_buffer_binding_size,
+            max_buffer_size: available.max_buffer_size,
+            ..wgpu::Limits::downlevel_defaults()
+        };
+        let (device, queue) = adapter
+            .request_device(&wgpu::DeviceDescriptor {
+                label: Some("skypier-scorer"),
+                required_limits: limits.clone(),
+                ..Default::default()
+            })
+            .await?;
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("skypier-scorer"),
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("skypier-scorer"),
+            layout: None,
+            module: &module,
+            entry_point: Some("main"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+        let max_bytes = (limits.max_storage_buffer_binding_size as u64).min(limits.max_buffer_size);
+        Ok(Self {
+            device,
+            queue,
+            pipeline,
+            max_floats: max_bytes / 4,
+            max_workgroups: limits.max_compute_workgroups_per_dimension,
+        })
+    }
+
+    // One score per row of `arena`, in row order
+    pub fn scores(&self, query: &[f32], arena: &[f32], metric: Metric) -> Result<Vec<f32>> {
+        let dims = query.len();
+        if dims == 0 || !arena.len().is_multiple_of(dims) {
+            return Err(anyhow!(
+                "Arena of {} floats doesn't hold {}-dimensional rows",
+                arena.len(),
+                dims
+            ));
+        }
+        let rows_per_chunk = (self.max_floats as usize / dims)
+            .min(self.max_workgroups as usize * WORKGROUP_SIZE as usize);
+        if rows_per_chunk == 0 {
+            return Err(anyhow!("A {}-dimensional row doesn't fit on the GPU", dims));
+        }
+        let query_buffer = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("query"),
+                contents: bytemuck::cast_slice(query),
+                usage: wgpu::BufferUsages::STORAGE,
+            });
+        let mut scores = Vec::with_capacity(arena.len() / dims);
+        for chunk in arena.chunks(rows_per_chunk * dims) {
+            scores.extend(self.score_chunk(&query_buffer, chunk, dims, metric)?);
+        }
+        Ok(scores)
+    }
+
+    fn score_chunk(
+        &self,
+        query: &wgpu::Buffer,
+        chunk: &[f32],
+        dims: usize,
+        metric: Metric,
+    ) -> Result<Vec<f32>> {
+        let rows = (chunk.len() / dims) as u32;
+        let params = Params {
+            dims: dims as u32,
+            rows,
+            metric: match metric {
+                Metric::Cosine => 0,
+                Metric::Euclidean => 1,
+                Metric::DotProduct => 2,
+            },
+            pad: 0,
+        };
+        let device = &self.device;
+        let params = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("params"),
+            contents: bytemuck::bytes_of(&params),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let arena = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("arena"),
+            contents: bytemuck::cast_slice(chunk),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let size = rows as u64 * 4;
+        let output = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("scores"),
+            size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("readback"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("skypier-scorer"),
+            layout: &self.pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: query.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: arena.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: output.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut encoder = device.create_command_encoder(&Default::default());
+        {
+            let mut pass = encoder.begin_compute_pass(&Default::default());
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(rows.div_ceil(WORKGROUP_SIZE), 1, 1);
+        }
+        encoder.copy_buffer_to_buffer(&output, 0, &readback, 0, size);
+        self.queue.submit([encoder.finish()]);
+
+        let slice = readback.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |mapped| {
+            let _ = sender.send(mapped);
+        });
+        device.poll(wgpu::PollType::wait_indefinitely())?;
+        receiver
+            .recv()?
+            .map_err(|e| anyhow!("Failed to read GPU scores: {}", e))?;
+        let scores = bytemuck::cast_slice(&slice.get_mapped_range()?).to_vec();
+        readback.unmap();
+        Ok(scores)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scores_match_cpu() {
+        // Nothing to check against without an adapter
+        let Some(scorer) = GpuScorer::shared() else {
+            return;
+        };
+        let dims = 5;
+        let arena: Vec<f32> = (0..1000 * dims)
+            .map(|i| ((i * 37) % 11) as f32 - 5.0)
+            .collect();
+        let query = [0.5, -1.0, 2.0, 0.0, 1.5];
+        for metric in [Metric::Cosine, Metric::Euclidean, Metric::DotProduct] {
+            let scores = scorer.scores(&query, &arena, metric).unwrap();
+            assert_eq!(scores.len(), 1000);
+            for (row, score) in arena.chunks(dims).zip(scores) {
+                assert!((metric.similarity(&query, row) - score).abs() < 1e-4);
+            }
+        }
+        assert!(scorer.scores(&query, &arena[1..], Metric::Cosine).is_err());
+    }
+}
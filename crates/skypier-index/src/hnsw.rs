@@ -1,18 +1,26 @@
 use anyhow::{anyhow, Result};
-use std::cmp::Ordering;
-use std::collections::{BinaryHeap, HashMap};
+use serde::{Deserialize, Serialize};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::sync::Arc;
 
-use crate::{SearchResult, VectorIndex};
+use parking_lot::{Mutex, RwLock};
+use rayon::prelude::*;
+
+use crate::{
+    check_batch, sort_results, CancellationToken, IdMapper, Metric, SearchResult, SharedIds,
+    VectorIndex,
+};
 
 #[derive(Debug, Clone)]
 struct Connection {
-    id: String,
+    id: u32,
     distance: f32,
 }
 
 impl PartialEq for Connection {
     fn eq(&self, other: &Self) -> bool {
-        self.distance == other.distance
+        self.cmp(other) == Ordering::Equal
     }
 }
 
@@ -27,215 +35,570 @@ impl PartialOrd for Connection {
 impl Ord for Connection {
     fn cmp(&self, other: &Self) -> Ordering {
         // Reverse ordering for min-heap behavior
-        other
-            .distance
-            .partial_cmp(&self.distance)
-            .unwrap_or(Ordering::Equal)
+        other.distance.total_cmp(&self.distance)
     }
 }
 
-#[derive(Debug)]
+// Nodes and edges use internal ids from `ids`, as the index's holder. The vector never changes
+// once inserted; edges are behind their own lock so a search only waits for
+// the one node an insert is rewiring.
+#[derive(Debug, Serialize, Deserialize)]
 struct Node {
-    id: String,
     vector: Vec<f32>,
-    connections: Vec<String>,
+    connections: RwLock<Vec<u32>>,
 }
 
+// Searches only take short read locks on single nodes, so they run while
+// vectors are being added or removed, and never hold more than one lock at a
+// time. Writers are serialized by `write_lock`; within it, `build_batch`
+// runs inserts in parallel.
 pub struct HnswIndex {
-    nodes: HashMap<String, Node>,
-    entry_point: Option<String>,
+    // Shared with the filter bitmaps, so a filtered search checks nodes by
+    // their internal id
+    ids: SharedIds,
+    holder: u8,
+    // Indexed by internal id; `None` for freed ids
+    nodes: RwLock<Vec<Option<Arc<Node>>>>,
+    entry_point: RwLock<Option<u32>>,
+    write_lock: Mutex<()>,
     max_connections: usize,
     ef_construction: usize,
+    ef_search: usize,
+    metric: Metric,
 }
 
 impl HnswIndex {
     pub fn new(_dimensions: usize) -> Result<Self> {
+        let ids = SharedIds::new();
+        let holder = ids.register();
         Ok(Self {
-            nodes: HashMap::new(),
-            entry_point: None,
+            ids,
+            holder,
+            nodes: RwLock::new(Vec::new()),
+            entry_point: RwLock::new(None),
+            write_lock: Mutex::new(()),
             max_connections: 16,
             ef_construction: 200,
+            ef_search: 50,
+            metric: Metric::Cosine,
         })
     }
 
+    // M: edges kept per node. More edges improve recall at the cost of
+    // memory and insert time.
+    pub fn with_max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = max_connections.max(1);
+        self
+    }
+
+    // Candidate list size while inserting; higher builds a better graph
+    pub fn with_ef_construction(mut self, ef_construction: usize) -> Self {
+        self.ef_construction = ef_construction.max(1);
+        self
+    }
+
+    // Not saved with the graph, which is only valid for the metric it was
+    // built with
+    pub fn with_metric(mut self, metric: Metric) -> Self {
+        self.metric = metric;
+        self
+    }
+
+    // Candidate list size while searching; trades latency for recall
+    pub fn with_ef_search(mut self, ef_search: usize) -> Self {
+        self.ef_search = ef_search.max(1);
+        self
+    }
+
+    // Searches with an explicit ef instead of the configured one, so
+    // ef_search can be swept without rebuilding the graph
+    pub fn search_with_ef(&self, query: &[f32], k: usize, ef: usize) -> Vec<SearchResult> {
+        // Can't fail without a cancellation token
+        self.search_filtered_with_ef(query, k, ef, &|_| true, None)
+            .unwrap_or_default()
+    }
+
+    // Filters on string ids look each visited node's up; `search_ids` is
+    // the way around that
+    fn search_by_name(
+        &self,
+        query: &[f32],
+        k: usize,
+        allowed: &dyn Fn(&str) -> bool,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<Vec<SearchResult>> {
+        let allowed = |internal: u32| self.ids.read().external(internal).is_some_and(allowed);
+        self.search_filtered_with_ef(query, k, self.ef_search, &allowed, cancel)
+    }
+
+    fn search_filtered_with_ef(
+        &self,
+        query: &[f32],
+        k: usize,
+        ef: usize,
+        allowed: &dyn Fn(u32) -> bool,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<Vec<SearchResult>> {
+        let Some(entry_point) = *self.entry_point.read() else {
+            return Ok(Vec::new());
+        };
+
+        let found = self.search_layer(query, vec![entry_point], k.max(ef), allowed, cancel);
+        // The walk stops early once cancelled, so what it found isn't the
+        // nearest
+        crate::check_cancelled(cancel)?;
+
+        let ids = self.ids.read();
+        let mut results: Vec<SearchResult> = found
+            .into_iter()
+            .filter_map(|conn| {
+                Some(SearchResult {
+                    id: ids.external(conn.id)?.to_string(),
+                    score: self.metric.score(conn.distance),
+                })
+            })
+            .collect();
+
+        // Heap order is arbitrary among equal scores
+        sort_results(&mut results);
+        results.truncate(k);
+        Ok(results)
+    }
+
+    // Greedy best-first search from the entry points, returning up to
+    // `num_closest` allowed nodes ordered by ascending distance to the query.
+    // Disallowed nodes are still walked through, just never returned, so a
+    // selective filter doesn't disconnect the graph.
     fn search_layer(
         &self,
         query: &[f32],
-        entry_points: Vec<String>,
+        entry_points: Vec<u32>,
         num_closest: usize,
+        allowed: &dyn Fn(u32) -> bool,
+        cancel: Option<&CancellationToken>,
     ) -> Vec<Connection> {
-        let mut visited = std::collections::HashSet::new();
+        let mut visited = HashSet::new();
+        // Nearest candidate on top
         let mut candidates = BinaryHeap::new();
+        // Furthest result on top, so it can be evicted
         let mut w = BinaryHeap::new();
 
         // Initialize with entry points
         for ep in entry_points {
-            if let Some(node) = self.nodes.get(&ep) {
-                let distance = cosine_similarity(query, &node.vector);
+            if let Some(node) = self.node(ep) {
                 let conn = Connection {
-                    id: ep.clone(),
-                    distance,
+                    id: ep,
+                    distance: self.metric.distance(query, &node.vector),
                 };
-                candidates.push(conn.clone());
-                w.push(conn);
+                if allowed(ep) {
+                    w.push(Reverse(conn.clone()));
+                }
+                candidates.push(conn);
                 visited.insert(ep);
             }
         }
 
         while let Some(c) = candidates.pop() {
-            if let Some(f) = w.peek() {
-                if c.distance > f.distance {
+            if cancel.is_some_and(CancellationToken::is_cancelled) {
+                break;
+            }
+            if let Some(Reverse(f)) = w.peek() {
+                if w.len() >= num_closest && c.distance > f.distance {
                     break;
                 }
             }
 
-            if let Some(node) = self.nodes.get(&c.id) {
-                for neighbor_id in &node.connections {
-                    if !visited.contains(neighbor_id) {
-                        visited.insert(neighbor_id.clone());
-
-                        if let Some(neighbor) = self.nodes.get(neighbor_id) {
-                            let distance = cosine_similarity(query, &neighbor.vector);
-                            let conn = Connection {
-                                id: neighbor_id.clone(),
-                                distance,
-                            };
-
-                            if w.len() < num_closest {
-                                candidates.push(conn.clone());
-                                w.push(conn);
-                            } else if let Some(f) = w.peek() {
-                                if distance < f.distance {
-                                    candidates.push(conn.clone());
-                                    w.push(conn);
-                                    if w.len() > num_closest {
-                                        w.pop();
-                                    }
-                                }
+            let Some(node) = self.node(c.id) else {
+                continue;
+            };
+            let connections = node.connections.read().clone();
+            for neighbor_id in connections {
+                if !visited.insert(neighbor_id) {
+                    continue;
+                }
+
+                if let Some(neighbor) = self.node(neighbor_id) {
+                    let distance = self.metric.distance(query, &neighbor.vector);
+                    let closer = match w.peek() {
+                        Some(Reverse(f)) => distance < f.distance,
+                        None => true,
+                    };
+
+                    if w.len() < num_closest || closer {
+                        let conn = Connection {
+                            id: neighbor_id,
+                            distance,
+                        };
+                        if allowed(neighbor_id) {
+                            w.push(Reverse(conn.clone()));
+                            if w.len() > num_closest {
+                                w.pop();
                             }
                         }
+                        candidates.push(conn);
                     }
                 }
             }
         }
 
-        w.into_sorted_vec()
+        let mut results: Vec<Connection> = w.into_iter().map(|Reverse(conn)| conn).collect();
+        results.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        results
     }
-}
 
-impl VectorIndex for HnswIndex {
-    fn add_vector(&mut self, id: &str, vector: &[f32]) -> Result<()> {
+    // Picks up to `max_connections` neighbors from candidates sorted by
+    // distance, preferring any that is closer to the base node than to the
+    // neighbors already selected (the HNSW selection heuristic); the rest of
+    // the slots go to the closest of the skipped ones. Keeping only the
+    // closest candidates cuts dense clusters off from the graph.
+    fn select_neighbors(&self, candidates: Vec<Connection>) -> Vec<u32> {
+        let mut selected: Vec<(u32, Arc<Node>)> = Vec::new();
+        let mut skipped = Vec::new();
+
+        for candidate in candidates {
+            if selected.len() >= self.max_connections {
+                break;
+            }
+            let Some(node) = self.node(candidate.id) else {
+                continue;
+            };
+            let diverse = selected.iter().all(|(_, other)| {
+                self.metric.distance(&node.vector, &other.vector) > candidate.distance
+            });
+            if diverse {
+                selected.push((candidate.id, node));
+            } else {
+                skipped.push(candidate.id);
+            }
+        }
+
+        let free = self.max_connections - selected.len();
+        selected
+            .into_iter()
+            .map(|(id, _)| id)
+            .chain(skipped.into_iter().take(free))
+            .collect()
+    }
+
+    // Re-selects a node's neighbors once it has more than `max_connections`.
+    // Holds the node's edge lock throughout, since parallel inserts may be
+    // adding edges to it at the same time.
+    fn prune_connections(&self, id: u32) {
+        let Some(node) = self.node(id) else {
+            return;
+        };
+        let mut connections = node.connections.write();
+        if connections.len() <= self.max_connections {
+            return;
+        }
+
+        let mut candidates: Vec<Connection> = connections
+            .iter()
+            .filter_map(|&conn_id| {
+                let neighbor = self.node(conn_id)?;
+                Some(Connection {
+                    id: conn_id,
+                    distance: self.metric.distance(&node.vector, &neighbor.vector),
+                })
+            })
+            .collect();
+        candidates.sort_by(|a, b| {
+            a.distance
+                .total_cmp(&b.distance)
+                .then_with(|| a.id.cmp(&b.id))
+        });
+        *connections = self.select_neighbors(candidates);
+    }
+
+    fn node(&self, id: u32) -> Option<Arc<Node>> {
+        self.nodes.read().get(id as usize)?.clone()
+    }
+
+    fn set_node(&self, id: u32, node: Option<Arc<Node>>) {
+        let mut nodes = self.nodes.write();
+        if id as usize >= nodes.len() {
+            nodes.resize(id as usize + 1, None);
+        }
+        nodes[id as usize] = node;
+    }
+
+    // Adds a vector whose id isn't in the index yet. Callers hold
+    // `write_lock`; several calls may run in parallel once the index has an
+    // entry point, as `build_batch` does.
+    fn insert_locked(&self, id: &str, vector: &[f32]) {
+        let internal = self.ids.acquire(self.holder, id);
         let node = Node {
-            id: id.to_string(),
             vector: vector.to_vec(),
-            connections: Vec::new(),
+            connections: RwLock::new(Vec::new()),
         };
 
         // If this is the first node, make it the entry point
-        if self.entry_point.is_none() {
-            self.entry_point = Some(id.to_string());
-            self.nodes.insert(id.to_string(), node);
-            return Ok(());
-        }
-
-        // Search for closest nodes
-        let entry_point = self.entry_point.as_ref().unwrap().clone();
-        let candidates = self.search_layer(vector, vec![entry_point], self.ef_construction);
+        let entry_point = *self.entry_point.read();
+        let Some(entry_point) = entry_point else {
+            self.set_node(internal, Some(Arc::new(node)));
+            *self.entry_point.write() = Some(internal);
+            return;
+        };
 
-        // Select M neighbors
-        let mut selected = Vec::new();
-        for candidate in candidates.into_iter().take(self.max_connections) {
-            selected.push(candidate.id);
+        // Search for closest nodes and select M neighbors
+        let candidates = self.search_layer(
+            vector,
+            vec![entry_point],
+            self.ef_construction,
+            &|_| true,
+            None,
+        );
+        let selected = self.select_neighbors(candidates);
+
+        *node.connections.write() = selected.clone();
+        self.set_node(internal, Some(Arc::new(node)));
+
+        // Add bidirectional connections, pruning neighbors that overflow
+        for neighbor_id in selected {
+            if let Some(neighbor) = self.node(neighbor_id) {
+                neighbor.connections.write().push(internal);
+            }
+            self.prune_connections(neighbor_id);
         }
+    }
 
-        // Add bidirectional connections
-        let mut new_node = node;
-        new_node.connections = selected.clone();
-
-        for neighbor_id in &selected {
-            if let Some(neighbor) = self.nodes.get_mut(neighbor_id) {
-                neighbor.connections.push(id.to_string());
-                // Prune connections if needed
-                if neighbor.connections.len() > self.max_connections {
-                    neighbor.connections.truncate(self.max_connections);
+    // `remove_vector` for callers already holding `write_lock`
+    fn remove_locked(&self, id: &str) -> bool {
+        let Some(internal) = self.ids.release(self.holder, id) else {
+            return false;
+        };
+        let Some(node) = self.node(internal) else {
+            return false;
+        };
+        let removed_connections = node.connections.read().clone();
+
+        // Move the entry point first so searches never start from a missing node
+        if *self.entry_point.read() == Some(internal) {
+            let replacement = removed_connections
+                .iter()
+                .copied()
+                .find(|&conn_id| self.node(conn_id).is_some())
+                .or_else(|| {
+                    let nodes = self.nodes.read();
+                    let live = nodes.iter().position(Option::is_some);
+                    live.map(|other_id| other_id as u32)
+                });
+            *self.entry_point.write() = replacement;
+        }
+        self.set_node(internal, None);
+
+        // Pruning makes edges one-directional, so any node may point here
+        let referrers: Vec<(u32, Arc<Node>)> = self
+            .nodes
+            .read()
+            .iter()
+            .enumerate()
+            .filter_map(|(other_id, other)| Some((other_id as u32, other.clone()?)))
+            .collect();
+
+        // Reconnect the nodes that linked through the removed one so the
+        // graph doesn't fall apart around it
+        for (referrer_id, referrer) in referrers {
+            {
+                let mut connections = referrer.connections.write();
+                if !connections.contains(&internal) {
+                    continue;
+                }
+                connections.retain(|&conn_id| conn_id != internal);
+                for &replacement in &removed_connections {
+                    if replacement != referrer_id && !connections.contains(&replacement) {
+                        connections.push(replacement);
+                    }
                 }
             }
+            self.prune_connections(referrer_id);
         }
 
-        self.nodes.insert(id.to_string(), new_node);
+        true
+    }
+}
+
+impl VectorIndex for HnswIndex {
+    fn add_vector(&self, id: &str, vector: &[f32]) -> Result<()> {
+        let _write = self.write_lock.lock();
+
+        // Re-adding an id replaces it; stale edges would point at the old vector
+        self.remove_locked(id);
+        self.insert_locked(id, vector);
         Ok(())
     }
 
-    fn remove_vector(&mut self, id: &str) -> Result<bool> {
-        if let Some(node) = self.nodes.remove(id) {
-            // Remove connections from neighbors
-            for neighbor_id in &node.connections {
-                if let Some(neighbor) = self.nodes.get_mut(neighbor_id) {
-                    neighbor.connections.retain(|conn_id| conn_id != id);
-                }
-            }
+    // Inserts in parallel across the rayon pool. Each insert searches the
+    // graph as built so far, like sequential inserts do, so quality matches
+    // an incremental build.
+    fn build_batch(&self, ids: &[&str], vectors: &[&[f32]]) -> Result<()> {
+        check_batch(ids, vectors)?;
+        let _write = self.write_lock.lock();
+
+        // Later duplicates win, as they would when added one by one
+        let mut latest = HashMap::new();
+        for (position, id) in ids.iter().enumerate() {
+            latest.insert(*id, position);
+        }
+        let mut batch: Vec<usize> = latest.into_values().collect();
+        batch.sort_unstable();
 
-            // Update entry point if needed
-            if self.entry_point.as_ref() == Some(&id.to_string()) {
-                self.entry_point = self.nodes.keys().next().cloned();
+        for &position in &batch {
+            self.remove_locked(ids[position]);
+        }
+
+        // Parallel inserts need an entry point to start from
+        let rest = match batch.split_first() {
+            Some((&first, rest)) if self.entry_point.read().is_none() => {
+                self.insert_locked(ids[first], vectors[first]);
+                rest
             }
+            _ => &batch[..],
+        };
+        rest.par_iter()
+            .for_each(|&position| self.insert_locked(ids[position], vectors[position]));
 
-            Ok(true)
-        } else {
-            Ok(false)
-        }
+        Ok(())
     }
 
-    fn search(&self, query: &[f32], k: usize) -> Result<Vec<SearchResult>> {
-        if let Some(entry_point) = &self.entry_point {
-            let connections = self.search_layer(query, vec![entry_point.clone()], k.max(50));
+    fn remove_vector(&self, id: &str) -> Result<bool> {
+        let _write = self.write_lock.lock();
+        Ok(self.remove_locked(id))
+    }
 
-            let results = connections
-                .into_iter()
-                .take(k)
-                .map(|conn| SearchResult {
-                    id: conn.id,
-                    score: conn.distance, // Using cosine similarity
-                })
-                .collect();
+    fn search_filtered(
+        &self,
+        query: &[f32],
+        k: usize,
+        allowed: &dyn Fn(&str) -> bool,
+    ) -> Result<Vec<SearchResult>> {
+        self.search_by_name(query, k, allowed, None)
+    }
 
-            Ok(results)
-        } else {
-            Ok(Vec::new())
-        }
+    fn search_cancellable(
+        &self,
+        query: &[f32],
+        k: usize,
+        allowed: &dyn Fn(&str) -> bool,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<SearchResult>> {
+        self.search_by_name(query, k, allowed, Some(cancel))
+    }
+
+    fn shared_ids(&self) -> Option<SharedIds> {
+        Some(self.ids.clone())
+    }
+
+    fn search_ids(
+        &self,
+        query: &[f32],
+        k: usize,
+        allowed: &dyn Fn(u32) -> bool,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<SearchResult>> {
+        self.search_filtered_with_ef(query, k, self.ef_search, allowed, Some(cancel))
     }
 
+    fn index_type(&self) -> &'static str {
+        "hnsw"
+    }
+
+    // The shared ids may hold vectors that aren't in the graph
     fn size(&self) -> usize {
-        self.nodes.len()
+        self.nodes.read().iter().flatten().count()
     }
 
-    fn clear(&mut self) {
-        self.nodes.clear();
-        self.entry_point = None;
+    fn clear(&self) {
+        let _write = self.write_lock.lock();
+        *self.entry_point.write() = None;
+        self.nodes.write().clear();
+        self.ids.release_all(self.holder);
     }
-}
 
-// Helper functions for distance calculations
-fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
-    let dot_product: f32 = a.iter().zip(b.iter()).map(|(x, y)| x * y).sum();
-    let norm_a: f32 = a.iter().map(|x| x * x).sum::<f32>().sqrt();
-    let norm_b: f32 = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    fn warm_up(&self) -> usize {
+        let nodes = self.nodes.read();
+        nodes
+            .iter()
+            .flatten()
+            .map(|node| crate::touch(&node.vector) + crate::touch(&node.connections.read()))
+            .sum()
+    }
 
-    if norm_a == 0.0 || norm_b == 0.0 {
-        0.0
-    } else {
-        dot_product / (norm_a * norm_b)
+    // Only the graph is saved, with the ids of its own nodes; M and ef
+    // settings come from the loading index
+    fn save(&self) -> Result<Vec<u8>> {
+        let _write = self.write_lock.lock();
+        let nodes: Vec<Option<Arc<Node>>> = self.nodes.read().clone();
+        let nodes: Vec<Option<&Node>> = nodes.iter().map(Option::as_deref).collect();
+        let ids: IdMapper = {
+            let shared = self.ids.read();
+            let external: Vec<Option<String>> = (0..nodes.len())
+                .map(|internal| match nodes[internal] {
+                    Some(_) => shared.external(internal as u32).map(str::to_string),
+                    None => None,
+                })
+                .collect();
+            external.into()
+        };
+        Ok(bincode::serialize(&(
+            "hnsw",
+            *self.entry_point.read(),
+            &ids,
+            nodes,
+        ))?)
     }
-}
 
-fn euclidean_distance(a: &[f32], b: &[f32]) -> f32 {
-    a.iter()
-        .zip(b.iter())
-        .map(|(x, y)| (x - y).powi(2))
-        .sum::<f32>()
-        .sqrt()
-}
+    fn load(&self, data: &[u8]) -> Result<()> {
+        let (kind, entry_point, ids, nodes): (String, Option<u32>, IdMapper, Vec<Option<Node>>) =
+            bincode::deserialize(data)?;
+        if kind != "hnsw" {
+            return Err(anyhow!("Cannot load a {} index into an HNSW index", kind));
+        }
+
+        // Every id must have a node and every edge a live target, or searches
+        // would silently skip parts of the graph
+        let live = |id: u32| nodes.get(id as usize).is_some_and(Option::is_some);
+        let consistent = nodes.len() == ids.capacity()
+            && nodes.iter().enumerate().all(|(internal, node)| {
+                node.is_some() == ids.external(internal as u32).is_some()
+                    && node
+                        .iter()
+                        .flat_map(|node| node.connections.read().clone())
+                        .all(live)
+            });
+        if !consistent {
+            return Err(anyhow!("HNSW snapshot graph is inconsistent"));
+        }
+        if entry_point.is_some_and(|ep| !live(ep)) {
+            return Err(anyhow!(
+                "HNSW snapshot entry point is missing from the graph"
+            ));
+        }
 
-fn dot_product(a: &[f32], b: &[f32]) -> f32 {
-    a.iter().zip(b.iter()).map(|(x, y)| x * y).sum()
+        // The shared ids may have been handed out differently since, so the
+        // graph is renumbered to whatever they are now
+        let _write = self.write_lock.lock();
+        self.ids.release_all(self.holder);
+        let mut renumbered = vec![0; ids.capacity()];
+        for (saved, id) in ids.iter() {
+            renumbered[saved as usize] = self.ids.acquire(self.holder, id);
+        }
+        let mut graph: Vec<Option<Arc<Node>>> = Vec::new();
+        for (saved, node) in nodes.into_iter().enumerate() {
+            let Some(node) = node else {
+                continue;
+            };
+            let internal = renumbered[saved] as usize;
+            for connection in node.connections.write().iter_mut() {
+                *connection = renumbered[*connection as usize];
+            }
+            if graph.len() <= internal {
+                graph.resize(internal + 1, None);
+            }
+            graph[internal] = Some(Arc::new(node));
+        }
+        *self.entry_point.write() = entry_point.map(|ep| renumbered[ep as usize]);
+        *self.nodes.write() = graph;
+        Ok(())
+    }
 }
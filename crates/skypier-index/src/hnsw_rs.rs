@@ -0,0 +1,337 @@
+use ::hnsw_rs::prelude::{Distance, Hnsw};
+use anyhow::{anyhow, Result};
+use parking_lot::RwLock;
+use std::collections::HashMap;
+
+use crate::{sort_results, Metric, SearchResult, VectorIndex};
+
+// HNSW from the hnsw_rs crate, as an alternative to the in-house graph.
+// hnsw_rs can't delete, so ids get a new label every time they're written
+// and the labels of removed or overwritten vectors are filtered out during
+// the search. Once they outnumber the live ones the graph is rebuilt.
+// hnsw_rs needs distances of zero or more, which rules out dot product.
+pub struct HnswRsIndex {
+    metric: Metric,
+    max_connections: usize,
+    ef_construction: usize,
+    ef_search: usize,
+    inner: RwLock<Inner>,
+}
+
+struct Inner {
+    graph: Hnsw<'static, f32, MetricDistance>,
+    labels: HashMap<String, usize>,
+    // Indexed by label; `None` once removed
+    ids: Vec<Option<String>>,
+}
+
+// Kind, metric and the live ids with their vectors, as saved
+type Snapshot = (String, String, Vec<(String, Vec<f32>)>);
+
+// Rebuilds aren't worth it below this many dead labels
+const MIN_REBUILD_STALE: usize = 1024;
+
+struct MetricDistance(Metric);
+
+impl Distance<f32> for MetricDistance {
+    // Cosine distance can round to just below zero
+    fn eval(&self, a: &[f32], b: &[f32]) -> f32 {
+        self.0.distance(a, b).max(0.0)
+    }
+}
+
+fn metric_name(metric: Metric) -> &'static str {
+    match metric {
+        Metric::Cosine => "cosine",
+        Metric::Euclidean => "euclidean",
+        Metric::DotProduct => "dot_product",
+    }
+}
+
+impl HnswRsIndex {
+    pub fn new(metric: Metric) -> Result<Self> {
+        if metric == Metric::DotProduct {
+            return Err(anyhow!("The hnsw_rs index doesn't support dot product"));
+        }
+        Ok(Self {
+            metric,
+            max_connections: 16,
+            ef_construction: 200,
+            ef_search: 50,
+            inner: RwLock::new(Inner::empty(metric, 16, 200, 0)),
+        })
+    }
+
+    // At most 256, a limit of hnsw_rs
+    pub fn with_max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = max_connections.clamp(1, 256);
+        self.reset();
+        self
+    }
+
+    pub fn with_ef_construction(mut self, ef_construction: usize) -> Self {
+        self.ef_construction = ef_construction.max(1);
+        self.reset();
+        self
+    }
+
+    pub fn with_ef_search(mut self, ef_search: usize) -> Self {
+        self.ef_search = ef_search.max(1);
+        self
+    }
+
+    fn reset(&mut self) {
+        *self.inner.get_mut() = self.empty(0);
+    }
+
+    fn empty(&self, capacity: usize) -> Inner {
+        Inner::empty(
+            self.metric,
+            self.max_connections,
+            self.ef_construction,
+            capacity,
+        )
+    }
+
+    // A fresh graph holding just `entries`
+    fn rebuild(&self, entries: Vec<(String, Vec<f32>)>) -> Inner {
+        let mut inner = self.empty(entries.len());
+        let data: Vec<(&[f32], usize)> = entries
+            .iter()
+            .enumerate()
+            .map(|(label, (_, vector))| (vector.as_slice(), label))
+            .collect();
+        inner.graph.parallel_insert_slice(&data);
+        for (label, (id, _)) in entries.iter().enumerate() {
+            inner.labels.insert(id.clone(), label);
+        }
+        inner.ids = entries.into_iter().map(|(id, _)| Some(id)).collect();
+        inner
+    }
+
+    fn maybe_rebuild(&self, inner: &mut Inner) {
+        let stale = inner.ids.len() - inner.labels.len();
+        if stale >= MIN_REBUILD_STALE && stale > inner.labels.len() {
+            *inner = self.rebuild(inner.live());
+        }
+    }
+}
+
+impl Inner {
+    fn empty(
+        metric: Metric,
+        max_connections: usize,
+        ef_construction: usize,
+        capacity: usize,
+    ) -> Self {
+        Self {
+            // 16 layers is the most hnsw_rs supports
+            graph: Hnsw::new(
+                max_connections,
+                capacity.max(10_000),
+                16,
+                ef_construction,
+                MetricDistance(metric),
+            ),
+            labels: HashMap::new(),
+            ids: Vec::new(),
+        }
+    }
+
+    // Drops `id`'s current label, if it has one
+    fn unlabel(&mut self, id: &str) -> bool {
+        match self.labels.remove(id) {
+            Some(label) => {
+                self.ids[label] = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn label(&mut self, id: &str) -> usize {
+        self.unlabel(id);
+        let label = self.ids.len();
+        self.ids.push(Some(id.to_string()));
+        self.labels.insert(id.to_string(), label);
+        label
+    }
+
+    // Live ids and their vectors, read back out of the graph
+    fn live(&self) -> Vec<(String, Vec<f32>)> {
+        let mut entries: Vec<_> = self
+            .graph
+            .get_point_indexation()
+            .into_iter()
+            .filter_map(|point| {
+                let id = self.ids.get(point.get_origin_id())?.as_ref()?;
+                Some((id.clone(), point.get_v().to_vec()))
+            })
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        entries
+    }
+}
+
+impl VectorIndex for HnswRsIndex {
+    fn add_vector(&self, id: &str, vector: &[f32]) -> Result<()> {
+        let mut inner = self.inner.write();
+        let label = inner.label(id);
+        inner.graph.insert_slice((vector, label));
+        self.maybe_rebuild(&mut inner);
+        Ok(())
+    }
+
+    fn build_batch(&self, ids: &[&str], vectors: &[&[f32]]) -> Result<()> {
+        crate::check_batch(ids, vectors)?;
+        let mut inner = self.inner.write();
+        let labels: Vec<usize> = ids.iter().map(|id| inner.label(id)).collect();
+        // Labels replaced later in the same batch are already dead
+        let data: Vec<(&[f32], usize)> = vectors.iter().copied().zip(labels).collect();
+        inner.graph.parallel_insert_slice(&data);
+        self.maybe_rebuild(&mut inner);
+        Ok(())
+    }
+
+    fn remove_vector(&self, id: &str) -> Result<bool> {
+        let mut inner = self.inner.write();
+        let removed = inner.unlabel(id);
+        self.maybe_rebuild(&mut inner);
+        Ok(removed)
+    }
+
+    fn search_filtered(
+        &self,
+        query: &[f32],
+        k: usize,
+        allowed: &dyn Fn(&str) -> bool,
+    ) -> Result<Vec<SearchResult>> {
+        let inner = self.inner.read();
+        if k == 0 || inner.labels.is_empty() {
+            return Ok(Vec::new());
+        }
+        let live = |label: &usize| {
+            inner
+                .ids
+                .get(*label)
+                .and_then(Option::as_deref)
+                .is_some_and(allowed)
+        };
+        let mut results: Vec<SearchResult> = inner
+            .graph
+            .search_filter(query, k, self.ef_search.max(k), Some(&live))
+            .into_iter()
+            .filter_map(|neighbour| {
+                let id = inner.ids.get(neighbour.d_id)?.as_ref()?;
+                Some(SearchResult {
+                    id: id.clone(),
+                    score: self.metric.score(neighbour.distance),
+                })
+            })
+            .collect();
+        sort_results(&mut results);
+        results.truncate(k);
+        Ok(results)
+    }
+
+    fn size(&self) -> usize {
+        self.inner.read().labels.len()
+    }
+
+    fn clear(&self) {
+        *self.inner.write() = self.empty(0);
+    }
+
+    fn index_type(&self) -> &'static str {
+        "hnsw_rs"
+    }
+
+    // The graph is rebuilt on load rather than saved, as hnsw_rs only dumps
+    // to files of its own
+    fn save(&self) -> Result<Vec<u8>> {
+        let inner = self.inner.read();
+        Ok(bincode::serialize(&(
+            "hnsw_rs",
+            metric_name(self.metric),
+            inner.live(),
+        ))?)
+    }
+
+    fn load(&self, data: &[u8]) -> Result<()> {
+        let (kind, metric, entries): Snapshot = bincode::deserialize(data)?;
+        if kind != "hnsw_rs" {
+            return Err(anyhow!(
+                "Cannot load a {} index into an hnsw_rs index",
+                kind
+            ));
+        }
+        if metric != metric_name(self.metric) {
+            return Err(anyhow!(
+                "hnsw_rs index snapshot is by {}, not {}",
+                metric,
+                metric_name(self.metric)
+            ));
+        }
+        *self.inner.write() = self.rebuild(entries);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_remove_filter_and_reload() {
+        let index = HnswRsIndex::new(Metric::Euclidean).unwrap();
+        index.add_vector("a", &[1.0, 0.0, 0.0]).unwrap();
+        index
+            .build_batch(&["b", "c"], &[&[0.0, 1.0, 0.0], &[0.0, 0.0, 1.0]])
+            .unwrap();
+        let results = index.search(&[0.9, 0.1, 0.0], 1).unwrap();
+        assert_eq!(results[0].id, "a");
+        assert!(
+            (results[0].score - Metric::Euclidean.similarity(&[0.9, 0.1, 0.0], &[1.0, 0.0, 0.0]))
+                .abs()
+                < 1e-6
+        );
+
+        assert!(index.remove_vector("a").unwrap());
+        assert!(!index.remove_vector("a").unwrap());
+        let results = index.search(&[0.9, 0.1, 0.0], 3).unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|r| r.id != "a"));
+
+        // Overwriting moves "b"
+        index.add_vector("b", &[1.0, 0.0, 0.0]).unwrap();
+        assert_eq!(index.size(), 2);
+        assert_eq!(index.search(&[1.0, 0.0, 0.0], 1).unwrap()[0].id, "b");
+        let filtered = index
+            .search_filtered(&[1.0, 0.0, 0.0], 1, &|id| id != "b")
+            .unwrap();
+        assert_eq!(filtered[0].id, "c");
+
+        let restored = HnswRsIndex::new(Metric::Euclidean).unwrap();
+        restored.load(&index.save().unwrap()).unwrap();
+        assert_eq!(restored.size(), 2);
+        assert_eq!(restored.search(&[1.0, 0.0, 0.0], 1).unwrap()[0].id, "b");
+        assert!(HnswRsIndex::new(Metric::Cosine)
+            .unwrap()
+            .load(&index.save().unwrap())
+            .is_err());
+    }
+
+    #[test]
+    fn test_rebuilds_once_mostly_stale() {
+        let index = HnswRsIndex::new(Metric::Cosine).unwrap();
+        assert!(HnswRsIndex::new(Metric::DotProduct).is_err());
+        for i in 0..MIN_REBUILD_STALE * 2 {
+            index
+                .add_vector(&format!("v{}", i % 10), &[1.0, i as f32, 0.5])
+                .unwrap();
+        }
+        let inner = index.inner.read();
+        assert_eq!(inner.labels.len(), 10);
+        assert!(inner.ids.len() < MIN_REBUILD_STALE + 20);
+    }
+}
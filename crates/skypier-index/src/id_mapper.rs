@@ -0,0 +1,242 @@
+use parking_lot::{MappedRwLockReadGuard, RwLock, RwLockReadGuard};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// Assigns compact u32 ids to string ids, so indices can key their internal
+// tables by position and only deal in strings at the API boundary. Ids freed
+// by `remove` are handed out again.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(from = "Vec<Option<String>>", into = "Vec<Option<String>>")]
+pub struct IdMapper {
+    internal: HashMap<String, u32>,
+    external: Vec<Option<String>>,
+    free: Vec<u32>,
+}
+
+impl IdMapper {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Returns the internal id of `id`, assigning one if it has none yet
+    pub fn insert(&mut self, id: &str) -> u32 {
+        if let Some(&internal) = self.internal.get(id) {
+            return internal;
+        }
+
+        let internal = match self.free.pop() {
+            Some(internal) => {
+                self.external[internal as usize] = Some(id.to_string());
+                internal
+            }
+            None => {
+                self.external.push(Some(id.to_string()));
+                (self.external.len() - 1) as u32
+            }
+        };
+        self.internal.insert(id.to_string(), internal);
+        internal
+    }
+
+    pub fn get(&self, id: &str) -> Option<u32> {
+        self.internal.get(id).copied()
+    }
+
+    pub fn external(&self, internal: u32) -> Option<&str> {
+        self.external.get(internal as usize)?.as_deref()
+    }
+
+    pub fn remove(&mut self, id: &str) -> Option<u32> {
+        let internal = self.internal.remove(id)?;
+        self.external[internal as usize] = None;
+        self.free.push(internal);
+        Some(internal)
+    }
+
+    pub fn len(&self) -> usize {
+        self.internal.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.internal.is_empty()
+    }
+
+    // One past the highest internal id handed out, for sizing tables indexed
+    // by internal id
+    pub fn capacity(&self) -> usize {
+        self.external.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &str)> {
+        self.external
+            .iter()
+            .enumerate()
+            .filter_map(|(internal, id)| Some((internal as u32, id.as_deref()?)))
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+// An IdMapper that several tables key vectors by, so their u32 ids agree:
+// an index and the filter bitmaps over the same vectors, say. Each table
+// is a holder, and an id stays assigned while any holder has it. Clones
+// share the ids.
+#[derive(Debug, Clone, Default)]
+pub struct SharedIds(Arc<RwLock<Holders>>);
+
+#[derive(Debug, Default)]
+struct Holders {
+    mapper: IdMapper,
+    // A bit per holder, by internal id
+    held: Vec<u8>,
+    holders: u8,
+}
+
+impl SharedIds {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // A new holder's bit, to pass to `acquire` and `release`
+    pub fn register(&self) -> u8 {
+        let mut state = self.0.write();
+        assert!(state.holders < 8, "ids are shared by at most 8 tables");
+        state.holders += 1;
+        1 << (state.holders - 1)
+    }
+
+    pub fn ptr_eq(&self, other: &SharedIds) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+
+    // The internal id of `id`, assigned if no holder has it yet
+    pub fn acquire(&self, holder: u8, id: &str) -> u32 {
+        let mut state = self.0.write();
+        let internal = state.mapper.insert(id);
+        if state.held.len() <= internal as usize {
+            state.held.resize(internal as usize + 1, 0);
+        }
+        state.held[internal as usize] |= holder;
+        internal
+    }
+
+    // None if `holder` doesn't have `id`
+    pub fn release(&self, holder: u8, id: &str) -> Option<u32> {
+        let mut state = self.0.write();
+        let internal = state.mapper.get(id)?;
+        let held = &mut state.held[internal as usize];
+        if *held & holder == 0 {
+            return None;
+        }
+        *held &= !holder;
+        if *held == 0 {
+            state.mapper.remove(id);
+        }
+        Some(internal)
+    }
+
+    // Releases every id `holder` has
+    pub fn release_all(&self, holder: u8) {
+        let mut state = self.0.write();
+        let Holders { mapper, held, .. } = &mut *state;
+        for (internal, bits) in held.iter_mut().enumerate() {
+            if *bits & holder == 0 {
+                continue;
+            }
+            *bits &= !holder;
+            if *bits == 0 {
+                if let Some(id) = mapper.external(internal as u32).map(str::to_string) {
+                    mapper.remove(&id);
+                }
+            }
+        }
+    }
+
+    // Reads are recursive: a search holding one may call a filter that
+    // takes another, and a queued writer mustn't wedge them
+    pub fn get(&self, id: &str) -> Option<u32> {
+        self.0.read_recursive().mapper.get(id)
+    }
+
+    // Every holder's ids. Don't call `acquire` or `release` while holding it.
+    pub fn read(&self) -> MappedRwLockReadGuard<'_, IdMapper> {
+        RwLockReadGuard::map(self.0.read_recursive(), |state| &state.mapper)
+    }
+}
+
+// Only the internal -> external table is saved; the rest is derived from it
+impl From<Vec<Option<String>>> for IdMapper {
+    fn from(external: Vec<Option<String>>) -> Self {
+        let mut internal = HashMap::new();
+        let mut free = Vec::new();
+        for (i, id) in external.iter().enumerate() {
+            match id {
+                Some(id) => {
+                    internal.insert(id.clone(), i as u32);
+                }
+                None => free.push(i as u32),
+            }
+        }
+        // Reuse the lowest ids first
+        free.reverse();
+
+        Self {
+            internal,
+            external,
+            free,
+        }
+    }
+}
+
+impl From<IdMapper> for Vec<Option<String>> {
+    fn from(mapper: IdMapper) -> Self {
+        mapper.external
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ids_are_reused_and_survive_serialization() {
+        let mut mapper = IdMapper::new();
+        assert_eq!(mapper.insert("a"), 0);
+        assert_eq!(mapper.insert("b"), 1);
+        assert_eq!(mapper.insert("a"), 0);
+
+        assert_eq!(mapper.remove("a"), Some(0));
+        assert_eq!(mapper.external(0), None);
+        assert_eq!(mapper.insert("c"), 0);
+        assert_eq!(mapper.remove("b"), Some(1));
+
+        let restored: IdMapper =
+            bincode::deserialize(&bincode::serialize(&mapper).unwrap()).unwrap();
+        assert_eq!(restored.len(), 1);
+        assert_eq!(restored.get("c"), Some(0));
+        assert_eq!(restored.external(0), Some("c"));
+        assert_eq!(restored.capacity(), 2);
+
+        let mut restored = restored;
+        assert_eq!(restored.insert("d"), 1);
+    }
+
+    #[test]
+    fn test_shared_ids_stay_while_held() {
+        let ids = SharedIds::new();
+        let (index, filter) = (ids.register(), ids.register());
+        assert_eq!(ids.acquire(index, "a"), 0);
+        assert_eq!(ids.acquire(filter, "a"), 0);
+        assert_eq!(ids.acquire(filter, "b"), 1);
+
+        assert_eq!(ids.release(index, "a"), Some(0));
+        assert_eq!(ids.release(index, "a"), None);
+        assert_eq!(ids.get("a"), Some(0));
+        ids.release_all(filter);
+        assert!(ids.read().is_empty());
+        assert_eq!(ids.acquire(index, "c"), 1);
+    }
+}
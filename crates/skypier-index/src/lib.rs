@@ -1,10 +1,37 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 
+pub mod adaptive;
+pub mod binary;
+#[cfg(feature = "disk")]
+pub mod disk;
+#[cfg(feature = "faiss")]
+pub mod faiss;
 pub mod flat;
+#[cfg(feature = "gpu")]
+pub mod gpu;
 pub mod hnsw;
+#[cfg(feature = "hnsw-rs")]
+pub mod hnsw_rs;
+pub mod id_mapper;
+pub mod metric;
+pub mod sparse;
 
+#[cfg(feature = "faiss")]
+pub use crate::faiss::FaissIndex;
+#[cfg(feature = "hnsw-rs")]
+pub use crate::hnsw_rs::HnswRsIndex;
+pub use adaptive::AdaptiveIndex;
+pub use binary::BinaryIndex;
+#[cfg(feature = "disk")]
+pub use disk::DiskIndex;
 pub use flat::FlatIndex;
+#[cfg(feature = "gpu")]
+pub use gpu::GpuScorer;
 pub use hnsw::HnswIndex;
+pub use id_mapper::{IdMapper, SharedIds};
+pub use metric::Metric;
+pub use sparse::SparseIndex;
+pub use tokio_util::sync::CancellationToken;
 
 #[derive(Debug, Clone)]
 pub struct SearchResult {
@@ -12,12 +39,227 @@ pub struct SearchResult {
     pub score: f32,
 }
 
+// Orders results by descending score, breaking ties by ascending id so equal
+// scores always come back in the same order.
+pub fn sort_results(results: &mut [SearchResult]) {
+    results.sort_by(|a, b| {
+        b.score
+            .partial_cmp(&a.score)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| a.id.cmp(&b.id))
+    });
+}
+
+// Reads one value per page of `values`, so they're faulted in, and returns
+// their size in bytes
+fn touch<T>(values: &[T]) -> usize {
+    let step = (4096 / std::mem::size_of::<T>().max(1)).max(1);
+    for value in values.iter().step_by(step) {
+        std::hint::black_box(value);
+    }
+    std::mem::size_of_val(values)
+}
+
+// Returned by `search_cancellable` once its token is cancelled
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Search was cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+// Rows a scan goes through between looks at its cancellation token
+const CANCEL_CHECK_INTERVAL: usize = 1024;
+
+fn check_cancelled(cancel: Option<&CancellationToken>) -> Result<()> {
+    match cancel {
+        Some(cancel) if cancel.is_cancelled() => Err(Cancelled.into()),
+        _ => Ok(()),
+    }
+}
+
+fn check_batch(ids: &[&str], vectors: &[&[f32]]) -> Result<()> {
+    if ids.len() != vectors.len() {
+        return Err(anyhow!(
+            "Batch has {} ids but {} vectors",
+            ids.len(),
+            vectors.len()
+        ));
+    }
+    Ok(())
+}
+
+// Implementations lock internally, so searches can run on a shared index
+// while vectors are being added or removed.
 pub trait VectorIndex: Send + Sync {
-    fn add_vector(&mut self, id: &str, vector: &[f32]) -> Result<()>;
-    fn remove_vector(&mut self, id: &str) -> Result<bool>;
-    fn search(&self, query: &[f32], k: usize) -> Result<Vec<SearchResult>>;
+    fn add_vector(&self, id: &str, vector: &[f32]) -> Result<()>;
+    fn remove_vector(&self, id: &str) -> Result<bool>;
+
+    // Adds many vectors at once, with the same result as adding them one by
+    // one in order. Indices that can build faster in bulk override this.
+    fn build_batch(&self, ids: &[&str], vectors: &[&[f32]]) -> Result<()> {
+        check_batch(ids, vectors)?;
+        for (id, vector) in ids.iter().zip(vectors) {
+            self.add_vector(id, vector)?;
+        }
+        Ok(())
+    }
+
+    fn search(&self, query: &[f32], k: usize) -> Result<Vec<SearchResult>> {
+        self.search_filtered(query, k, &|_| true)
+    }
+
+    // Only ids for which `allowed` returns true are returned. Filtering
+    // during the search, rather than afterwards, keeps k results coming back
+    // even when few vectors match.
+    fn search_filtered(
+        &self,
+        query: &[f32],
+        k: usize,
+        allowed: &dyn Fn(&str) -> bool,
+    ) -> Result<Vec<SearchResult>>;
+
+    // Like `search_filtered`, but gives up with `Cancelled` once `cancel` is
+    // cancelled. Indices whose searches can take long check it as they go;
+    // the rest only check before starting.
+    fn search_cancellable(
+        &self,
+        query: &[f32],
+        k: usize,
+        allowed: &dyn Fn(&str) -> bool,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<SearchResult>> {
+        check_cancelled(Some(cancel))?;
+        self.search_filtered(query, k, allowed)
+    }
+
+    // The ids the index keys vectors by, when other tables can key by them
+    // too, as the filter bitmaps do. None for indices with ids of their own.
+    fn shared_ids(&self) -> Option<SharedIds> {
+        None
+    }
+
+    // `search_cancellable` with `allowed` taking internal ids from
+    // `shared_ids`, so checking a node needs no lookup
+    fn search_ids(
+        &self,
+        _query: &[f32],
+        _k: usize,
+        _allowed: &dyn Fn(u32) -> bool,
+        _cancel: &CancellationToken,
+    ) -> Result<Vec<SearchResult>> {
+        Err(anyhow!(
+            "{} indices don't share their ids",
+            self.index_type()
+        ))
+    }
+
     fn size(&self) -> usize;
-    fn clear(&mut self);
+    fn clear(&self);
+    // Short name reported in stats, e.g. "hnsw"
+    fn index_type(&self) -> &'static str;
+
+    // Serializes the index contents so it can be restored without
+    // re-inserting every vector
+    fn save(&self) -> Result<Vec<u8>>;
+    // Replaces the index contents with ones produced by `save`
+    fn load(&self, data: &[u8]) -> Result<()>;
+
+    // Reads through the index's memory so the first searches after a
+    // restart don't wait on page faults. Returns the bytes read.
+    fn warm_up(&self) -> usize {
+        0
+    }
+
+    // Why the last background build of the index failed, while it's still
+    // answering from the index it had before
+    fn build_error(&self) -> Option<String> {
+        None
+    }
+}
+
+// Lets an index type chosen at runtime go where a concrete one is expected
+impl VectorIndex for Box<dyn VectorIndex> {
+    fn add_vector(&self, id: &str, vector: &[f32]) -> Result<()> {
+        (**self).add_vector(id, vector)
+    }
+
+    fn remove_vector(&self, id: &str) -> Result<bool> {
+        (**self).remove_vector(id)
+    }
+
+    fn build_batch(&self, ids: &[&str], vectors: &[&[f32]]) -> Result<()> {
+        (**self).build_batch(ids, vectors)
+    }
+
+    fn search(&self, query: &[f32], k: usize) -> Result<Vec<SearchResult>> {
+        (**self).search(query, k)
+    }
+
+    fn search_filtered(
+        &self,
+        query: &[f32],
+        k: usize,
+        allowed: &dyn Fn(&str) -> bool,
+    ) -> Result<Vec<SearchResult>> {
+        (**self).search_filtered(query, k, allowed)
+    }
+
+    fn search_cancellable(
+        &self,
+        query: &[f32],
+        k: usize,
+        allowed: &dyn Fn(&str) -> bool,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<SearchResult>> {
+        (**self).search_cancellable(query, k, allowed, cancel)
+    }
+
+    fn shared_ids(&self) -> Option<SharedIds> {
+        (**self).shared_ids()
+    }
+
+    fn search_ids(
+        &self,
+        query: &[f32],
+        k: usize,
+        allowed: &dyn Fn(u32) -> bool,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<SearchResult>> {
+        (**self).search_ids(query, k, allowed, cancel)
+    }
+
+    fn size(&self) -> usize {
+        (**self).size()
+    }
+
+    fn clear(&self) {
+        (**self).clear()
+    }
+
+    fn index_type(&self) -> &'static str {
+        (**self).index_type()
+    }
+
+    fn save(&self) -> Result<Vec<u8>> {
+        (**self).save()
+    }
+
+    fn load(&self, data: &[u8]) -> Result<()> {
+        (**self).load(data)
+    }
+
+    fn warm_up(&self) -> usize {
+        (**self).warm_up()
+    }
+
+    fn build_error(&self) -> Option<String> {
+        (**self).build_error()
+    }
 }
 
 #[cfg(test)]
@@ -25,8 +267,257 @@ mod tests {
     use super::*;
 
     #[test]
-    fn it_works() {
-        let result = add(2, 2);
-        assert_eq!(result, 4);
+    fn test_flat_and_hnsw_agree_on_nearest() {
+        let flat = FlatIndex::new();
+        let hnsw = HnswIndex::new(3).unwrap();
+        for (id, vector) in [
+            ("a", [1.0, 0.0, 0.0]),
+            ("b", [0.0, 1.0, 0.0]),
+            ("c", [0.0, 0.0, 1.0]),
+        ] {
+            flat.add_vector(id, &vector).unwrap();
+            hnsw.add_vector(id, &vector).unwrap();
+        }
+
+        let query = [0.9, 0.1, 0.0];
+        assert_eq!(flat.search(&query, 1).unwrap()[0].id, "a");
+        assert_eq!(hnsw.search(&query, 1).unwrap()[0].id, "a");
+        assert!(hnsw.remove_vector("a").unwrap());
+        assert_eq!(hnsw.size(), 2);
+        assert_eq!(flat.warm_up(), 3 * 3 * 4);
+        assert!(hnsw.warm_up() >= 2 * 3 * 4);
+    }
+
+    #[test]
+    fn test_cancelled_searches_stop() {
+        let indexes: Vec<Box<dyn VectorIndex>> = vec![
+            Box::new(FlatIndex::new()),
+            Box::new(HnswIndex::new(2).unwrap()),
+            Box::new(BinaryIndex::new()),
+        ];
+        let cancel = CancellationToken::new();
+        for index in &indexes {
+            for i in 0..50 {
+                let angle = i as f32 / 10.0;
+                index
+                    .add_vector(&i.to_string(), &[angle.cos(), angle.sin()])
+                    .unwrap();
+            }
+            let results = index
+                .search_cancellable(&[1.0, 0.0], 3, &|_| true, &cancel)
+                .unwrap();
+            assert_eq!(results.len(), 3);
+        }
+
+        cancel.cancel();
+        for index in &indexes {
+            let error = index
+                .search_cancellable(&[1.0, 0.0], 3, &|_| true, &cancel)
+                .unwrap_err();
+            assert!(error.is::<Cancelled>());
+        }
+    }
+
+    #[test]
+    fn test_equal_scores_sorted_by_id() {
+        let flat = FlatIndex::new();
+        for id in ["z", "m", "a"] {
+            flat.add_vector(id, &[1.0, 0.0]).unwrap();
+        }
+        flat.add_vector("best", &[1.0, 0.1]).unwrap();
+
+        let ids: Vec<String> = flat
+            .search(&[1.0, 0.1], 4)
+            .unwrap()
+            .into_iter()
+            .map(|r| r.id)
+            .collect();
+        assert_eq!(ids, vec!["best", "a", "m", "z"]);
+    }
+
+    #[test]
+    fn test_binary_index_rescores() {
+        let vectors = [
+            ("a", [0.9, 0.1, -0.5, 0.2]),
+            ("b", [0.2, 0.9, -0.5, 0.2]),
+            ("c", [-0.3, -0.4, 0.6, -0.1]),
+        ];
+        let binary = BinaryIndex::new();
+        let rescored = BinaryIndex::new().with_rescore(4);
+        for (id, vector) in vectors {
+            binary.add_vector(id, &vector).unwrap();
+            rescored.add_vector(id, &vector).unwrap();
+        }
+
+        // a and b quantize alike, so only rescoring tells them apart
+        let query = [1.0, 0.0, -0.5, 0.2];
+        let results = binary.search(&query, 3).unwrap();
+        assert_eq!(results[0].score, results[1].score);
+        assert_eq!(results[2].id, "c");
+        assert_eq!(rescored.search(&query, 1).unwrap()[0].id, "a");
+
+        // Rescoring needs the full vectors an unrescored snapshot lacks
+        let restored = BinaryIndex::new();
+        restored.load(&rescored.save().unwrap()).unwrap();
+        assert_eq!(restored.size(), 3);
+        assert!(BinaryIndex::new()
+            .with_rescore(4)
+            .load(&binary.save().unwrap())
+            .is_err());
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let hnsw = HnswIndex::new(2).unwrap();
+        for (id, vector) in [("a", [1.0, 0.0]), ("b", [0.0, 1.0]), ("c", [0.7, 0.7])] {
+            hnsw.add_vector(id, &vector).unwrap();
+        }
+
+        let restored = HnswIndex::new(2).unwrap();
+        restored.load(&hnsw.save().unwrap()).unwrap();
+        assert_eq!(restored.size(), 3);
+        assert_eq!(restored.search(&[0.1, 1.0], 1).unwrap()[0].id, "b");
+
+        // A snapshot of one index type can't be loaded into another
+        assert!(FlatIndex::new().load(&hnsw.save().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_hnsw_searches_by_shared_ids() {
+        let hnsw = HnswIndex::new(2).unwrap();
+        let ids = hnsw.shared_ids().unwrap();
+        let filter = ids.register();
+        // Held by the filter only, so the graph's ids get renumbered around
+        // it on load
+        ids.acquire(filter, "x");
+        for (id, vector) in [("a", [1.0, 0.0]), ("b", [0.0, 1.0]), ("c", [0.7, 0.7])] {
+            hnsw.add_vector(id, &vector).unwrap();
+            ids.acquire(filter, id);
+        }
+        let snapshot = hnsw.save().unwrap();
+        hnsw.clear();
+        ids.release_all(filter);
+        ids.acquire(filter, "c");
+        hnsw.load(&snapshot).unwrap();
+        assert_eq!(hnsw.size(), 3);
+
+        let only_b = ids.get("b").unwrap();
+        let cancel = CancellationToken::new();
+        let results = hnsw
+            .search_ids(&[1.0, 0.0], 3, &|internal| internal == only_b, &cancel)
+            .unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].id, "b");
+        assert!(FlatIndex::new()
+            .search_ids(&[1.0, 0.0], 1, &|_| true, &cancel)
+            .is_err());
+    }
+
+    #[test]
+    fn test_hnsw_dense_clusters_stay_reachable() {
+        // Points along a closed curve: every node's nearest neighbors sit in
+        // a tight arc, which used to cut most of the graph off
+        let vector =
+            |i: usize| -> Vec<f32> { (0..8).map(|j| (i as f32 * 0.37 + j as f32).cos()).collect() };
+        let hnsw = HnswIndex::new(8).unwrap();
+        for i in 0..500 {
+            hnsw.add_vector(&i.to_string(), &vector(i)).unwrap();
+        }
+
+        for i in (0..500).step_by(7) {
+            let results = hnsw.search(&vector(i), 1).unwrap();
+            assert_eq!(results[0].id, i.to_string());
+        }
+    }
+
+    #[test]
+    fn test_selective_filter_keeps_recall() {
+        let flat = FlatIndex::new();
+        let hnsw = HnswIndex::new(2).unwrap();
+        for i in 0..300 {
+            let angle = i as f32 * 0.01;
+            let vector = [angle.cos(), angle.sin()];
+            flat.add_vector(&i.to_string(), &vector).unwrap();
+            hnsw.add_vector(&i.to_string(), &vector).unwrap();
+        }
+
+        // Only every 50th vector matches, all far from the query
+        let allowed = |id: &str| id.parse::<usize>().unwrap() % 50 == 0;
+        let query = [1.0, 0.0];
+        let expected: Vec<String> = flat
+            .search_filtered(&query, 5, &allowed)
+            .unwrap()
+            .into_iter()
+            .map(|r| r.id)
+            .collect();
+        let found: Vec<String> = hnsw
+            .search_filtered(&query, 5, &allowed)
+            .unwrap()
+            .into_iter()
+            .map(|r| r.id)
+            .collect();
+        assert_eq!(expected, vec!["0", "50", "100", "150", "200"]);
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn test_hnsw_searches_during_inserts() {
+        let vector =
+            |i: usize| -> Vec<f32> { (0..8).map(|j| (i as f32 * 0.37 + j as f32).cos()).collect() };
+        let hnsw = HnswIndex::new(8).unwrap();
+        for i in 0..200 {
+            hnsw.add_vector(&i.to_string(), &vector(i)).unwrap();
+        }
+
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                for i in 200..600 {
+                    hnsw.add_vector(&i.to_string(), &vector(i)).unwrap();
+                    // Churn some of the new ones so removals race searches too
+                    if i % 3 == 0 {
+                        hnsw.remove_vector(&(i - 1).to_string()).unwrap();
+                    }
+                }
+            });
+            for _ in 0..2 {
+                scope.spawn(|| {
+                    for round in 0..200 {
+                        let i = round % 200;
+                        let results = hnsw.search(&vector(i), 1).unwrap();
+                        assert_eq!(results[0].id, i.to_string());
+                    }
+                });
+            }
+        });
+
+        assert_eq!(hnsw.size(), 600 - 400 / 3);
+        let restored = HnswIndex::new(8).unwrap();
+        restored.load(&hnsw.save().unwrap()).unwrap();
+        assert_eq!(restored.search(&vector(599), 1).unwrap()[0].id, "599");
+    }
+
+    #[test]
+    fn test_build_batch_matches_incremental_adds() {
+        let vector =
+            |i: usize| -> Vec<f32> { (0..8).map(|j| (i as f32 * 0.37 + j as f32).cos()).collect() };
+        let ids: Vec<String> = (0..1000).map(|i| i.to_string()).collect();
+        let vectors: Vec<Vec<f32>> = (0..1000).map(vector).collect();
+        let mut id_refs: Vec<&str> = ids.iter().map(String::as_str).collect();
+        let mut vector_refs: Vec<&[f32]> = vectors.iter().map(Vec::as_slice).collect();
+
+        // A repeated id keeps its last vector
+        let moved = vector(5000);
+        id_refs.push("0");
+        vector_refs.push(&moved);
+
+        let hnsw = HnswIndex::new(8).unwrap();
+        hnsw.build_batch(&id_refs, &vector_refs).unwrap();
+        assert_eq!(hnsw.size(), 1000);
+        assert_eq!(hnsw.search(&moved, 1).unwrap()[0].id, "0");
+        for i in (1..1000).step_by(7) {
+            assert_eq!(hnsw.search(&vector(i), 1).unwrap()[0].id, i.to_string());
+        }
+
+        assert!(hnsw.build_batch(&["a"], &[]).is_err());
     }
 }
@@ -0,0 +1,56 @@
+use serde::{Deserialize, Serialize};
+
+// How an index measures closeness. Distances order candidates while
+// searching, lower is closer; scores are what results report, higher is
+// closer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    #[default]
+    Cosine,
+    Euclidean,
+    DotProduct,
+}
+
+impl Metric {
+    pub fn distance(&self, a: &[f32], b: &[f32]) -> f32 {
+        match self {
+            Metric::Cosine => 1.0 - cosine_similarity(a, b),
+            Metric::Euclidean => a
+                .iter()
+                .zip(b)
+                .map(|(x, y)| (x - y) * (x - y))
+                .sum::<f32>()
+                .sqrt(),
+            Metric::DotProduct => -dot_product(a, b),
+        }
+    }
+
+    // Cosine similarity, 1 / (1 + euclidean distance), or the dot product
+    pub fn score(&self, distance: f32) -> f32 {
+        match self {
+            Metric::Cosine => 1.0 - distance,
+            Metric::Euclidean => 1.0 / (1.0 + distance),
+            Metric::DotProduct => -distance,
+        }
+    }
+
+    pub fn similarity(&self, a: &[f32], b: &[f32]) -> f32 {
+        self.score(self.distance(a, b))
+    }
+}
+
+fn dot_product(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let norm_a: f32 = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b: f32 = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+
+    if norm_a == 0.0 || norm_b == 0.0 {
+        0.0
+    } else {
+        dot_product(a, b) / (norm_a * norm_b)
+    }
+}
@@ -0,0 +1,218 @@
+use anyhow::{anyhow, Result};
+use parking_lot::RwLock;
+use std::collections::HashMap;
+
+use crate::{sort_results, IdMapper, SearchResult};
+
+// Inverted index over sparse vectors, such as SPLADE or BM25 term weights.
+// Each dimension keeps a posting list of the vectors that are non-zero in
+// it, so a query only visits the lists of its own dimensions. Scores are
+// dot products.
+// A vector's non-zero (dimension, value) pairs
+type Pairs = Vec<(u32, f32)>;
+
+pub struct SparseIndex {
+    entries: RwLock<Entries>,
+}
+
+#[derive(Default)]
+struct Entries {
+    ids: IdMapper,
+    // Indexed by internal id; `None` for freed ids
+    vectors: Vec<Option<Pairs>>,
+    // Dimension -> (internal id, value)
+    postings: HashMap<u32, Vec<(u32, f32)>>,
+}
+
+impl Entries {
+    fn unpost(&mut self, internal: u32) {
+        let Some(vector) = self.vectors[internal as usize].take() else {
+            return;
+        };
+        for (dimension, _) in vector {
+            if let Some(list) = self.postings.get_mut(&dimension) {
+                list.retain(|&(posted, _)| posted != internal);
+                if list.is_empty() {
+                    self.postings.remove(&dimension);
+                }
+            }
+        }
+    }
+}
+
+fn pairs(indices: &[u32], values: &[f32]) -> Result<Pairs> {
+    if indices.len() != values.len() {
+        return Err(anyhow!(
+            "Sparse vector has {} indices but {} values",
+            indices.len(),
+            values.len()
+        ));
+    }
+    Ok(indices
+        .iter()
+        .copied()
+        .zip(values.iter().copied())
+        .collect())
+}
+
+impl Default for SparseIndex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SparseIndex {
+    pub fn new() -> Self {
+        Self {
+            entries: RwLock::new(Entries::default()),
+        }
+    }
+
+    // Replaces what's indexed under `id`
+    pub fn add_vector(&self, id: &str, indices: &[u32], values: &[f32]) -> Result<()> {
+        let vector = pairs(indices, values)?;
+        let mut entries = self.entries.write();
+        let internal = entries.ids.insert(id);
+        if internal as usize == entries.vectors.len() {
+            entries.vectors.push(None);
+        }
+        entries.unpost(internal);
+        for &(dimension, value) in &vector {
+            entries
+                .postings
+                .entry(dimension)
+                .or_default()
+                .push((internal, value));
+        }
+        entries.vectors[internal as usize] = Some(vector);
+        Ok(())
+    }
+
+    pub fn remove_vector(&self, id: &str) -> Result<bool> {
+        let mut entries = self.entries.write();
+        let Some(internal) = entries.ids.remove(id) else {
+            return Ok(false);
+        };
+        entries.unpost(internal);
+        Ok(true)
+    }
+
+    pub fn search(&self, indices: &[u32], values: &[f32], k: usize) -> Result<Vec<SearchResult>> {
+        self.search_filtered(indices, values, k, &|_| true)
+    }
+
+    // Vectors sharing no dimension with the query aren't returned
+    pub fn search_filtered(
+        &self,
+        indices: &[u32],
+        values: &[f32],
+        k: usize,
+        allowed: &dyn Fn(&str) -> bool,
+    ) -> Result<Vec<SearchResult>> {
+        let query = pairs(indices, values)?;
+        let entries = self.entries.read();
+        let mut scores: HashMap<u32, f32> = HashMap::new();
+        for (dimension, weight) in query {
+            for &(internal, value) in entries.postings.get(&dimension).into_iter().flatten() {
+                *scores.entry(internal).or_default() += weight * value;
+            }
+        }
+
+        let mut results: Vec<_> = scores
+            .into_iter()
+            .filter_map(|(internal, score)| {
+                let id = entries.ids.external(internal)?;
+                allowed(id).then(|| SearchResult {
+                    id: id.to_string(),
+                    score,
+                })
+            })
+            .collect();
+        sort_results(&mut results);
+        results.truncate(k);
+        Ok(results)
+    }
+
+    pub fn size(&self) -> usize {
+        self.entries.read().ids.len()
+    }
+
+    pub fn clear(&self) {
+        *self.entries.write() = Entries::default();
+    }
+
+    // Posting lists aren't saved; `load` rebuilds them
+    pub fn save(&self) -> Result<Vec<u8>> {
+        let entries = self.entries.read();
+        Ok(bincode::serialize(&(
+            "sparse",
+            &entries.ids,
+            &entries.vectors,
+        ))?)
+    }
+
+    pub fn load(&self, data: &[u8]) -> Result<()> {
+        let (kind, ids, vectors): (String, IdMapper, Vec<Option<Pairs>>) =
+            bincode::deserialize(data)?;
+        if kind != "sparse" {
+            return Err(anyhow!("Cannot load a {} index into a sparse index", kind));
+        }
+        let consistent = vectors.len() == ids.capacity()
+            && vectors.iter().enumerate().all(|(internal, vector)| {
+                vector.is_some() == ids.external(internal as u32).is_some()
+            });
+        if !consistent {
+            return Err(anyhow!("Sparse index snapshot ids don't match its vectors"));
+        }
+
+        let mut postings: HashMap<u32, Vec<(u32, f32)>> = HashMap::new();
+        for (internal, vector) in vectors.iter().enumerate() {
+            for &(dimension, value) in vector.iter().flatten() {
+                postings
+                    .entry(dimension)
+                    .or_default()
+                    .push((internal as u32, value));
+            }
+        }
+        *self.entries.write() = Entries {
+            ids,
+            vectors,
+            postings,
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dot_product_over_shared_dimensions() {
+        let index = SparseIndex::new();
+        index.add_vector("a", &[1, 7], &[1.0, 2.0]).unwrap();
+        index.add_vector("b", &[7, 9], &[0.5, 3.0]).unwrap();
+        index.add_vector("c", &[4], &[5.0]).unwrap();
+
+        let results = index.search(&[7, 9], &[1.0, 1.0], 10).unwrap();
+        let ranked: Vec<_> = results.iter().map(|r| (r.id.as_str(), r.score)).collect();
+        assert_eq!(ranked, vec![("b", 3.5), ("a", 2.0)]);
+
+        // Re-adding replaces the old postings
+        index.add_vector("b", &[4], &[1.0]).unwrap();
+        assert_eq!(index.search(&[9], &[1.0], 10).unwrap().len(), 0);
+        assert!(index.remove_vector("a").unwrap());
+        assert_eq!(index.size(), 2);
+
+        let restored = SparseIndex::new();
+        restored.load(&index.save().unwrap()).unwrap();
+        let ids: Vec<_> = restored
+            .search(&[4], &[1.0], 10)
+            .unwrap()
+            .into_iter()
+            .map(|r| r.id)
+            .collect();
+        assert_eq!(ids, vec!["c", "b"]);
+        assert!(index.add_vector("d", &[1, 2], &[1.0]).is_err());
+    }
+}
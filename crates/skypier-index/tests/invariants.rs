@@ -0,0 +1,115 @@
+// Drives random insert/delete/search sequences against every index type and
+// checks them against a plain map of what should be stored.
+
+use proptest::prelude::*;
+use skypier_index::{BinaryIndex, FlatIndex, HnswIndex, VectorIndex};
+use std::collections::BTreeMap;
+
+const DIMS: usize = 4;
+
+#[derive(Debug, Clone)]
+enum Op {
+    Insert(u8, Vec<f32>),
+    Delete(u8),
+    Search(Vec<f32>, usize),
+}
+
+// Small integer components keep duplicate and parallel vectors (equal
+// scores) common. All-zero vectors have no direction and are left out.
+fn vector() -> impl Strategy<Value = Vec<f32>> {
+    prop::collection::vec((-4i8..=4).prop_map(f32::from), DIMS)
+        .prop_filter("zero vector", |v| v.iter().any(|x| *x != 0.0))
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    // Ids come from a small pool so deletes and re-inserts hit existing ids
+    prop_oneof![
+        4 => (0u8..48, vector()).prop_map(|(id, v)| Op::Insert(id, v)),
+        2 => (0u8..48).prop_map(Op::Delete),
+        1 => (vector(), 0usize..10).prop_map(|(v, k)| Op::Search(v, k)),
+    ]
+}
+
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    dot / (norm_a * norm_b)
+}
+
+fn check_invariants(index: &dyn VectorIndex, ops: &[Op]) -> Result<(), TestCaseError> {
+    let mut model: BTreeMap<String, Vec<f32>> = BTreeMap::new();
+
+    for op in ops {
+        match op {
+            Op::Insert(id, vector) => {
+                index.add_vector(&id.to_string(), vector).unwrap();
+                model.insert(id.to_string(), vector.clone());
+            }
+            Op::Delete(id) => {
+                let removed = index.remove_vector(&id.to_string()).unwrap();
+                prop_assert_eq!(removed, model.remove(&id.to_string()).is_some());
+            }
+            Op::Search(query, k) => {
+                let results = index.search(query, *k).unwrap();
+                prop_assert!(results.len() <= *k);
+                prop_assert!(results.len() <= model.len());
+                for pair in results.windows(2) {
+                    prop_assert!(pair[0].score >= pair[1].score);
+                }
+                for result in &results {
+                    prop_assert!(model.contains_key(&result.id), "deleted id {}", result.id);
+                }
+            }
+        }
+        prop_assert_eq!(index.size(), model.len());
+    }
+
+    // Every stored vector is reachable, and is (one of) its own nearest
+    // neighbors. Only ids in the model ever come back.
+    for (id, vector) in &model {
+        let results = index.search(vector, model.len()).unwrap();
+        for result in &results {
+            prop_assert!(model.contains_key(&result.id), "deleted id {}", result.id);
+        }
+        let top = results.first().map(|r| r.score).unwrap_or(f32::MIN);
+        prop_assert!((top - cosine(vector, vector)).abs() < 1e-4);
+        prop_assert!(
+            results
+                .iter()
+                .any(|r| &r.id == id && (r.score - top).abs() < 1e-4),
+            "{} is not its own nearest neighbor",
+            id
+        );
+
+        // A filter allowing a single id finds it wherever it sits in the graph
+        let only = index
+            .search_filtered(vector, 10, &|other| other == id)
+            .unwrap();
+        prop_assert_eq!(only.len(), 1);
+        prop_assert_eq!(&only[0].id, id);
+    }
+
+    index.clear();
+    prop_assert_eq!(index.size(), 0);
+    prop_assert!(index.search(&[1.0; DIMS], 10).unwrap().is_empty());
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn flat_index_invariants(ops in prop::collection::vec(op(), 0..200)) {
+        check_invariants(&FlatIndex::new(), &ops)?;
+    }
+
+    #[test]
+    fn hnsw_index_invariants(ops in prop::collection::vec(op(), 0..200)) {
+        check_invariants(&HnswIndex::new(DIMS).unwrap(), &ops)?;
+    }
+
+    #[test]
+    fn binary_index_invariants(ops in prop::collection::vec(op(), 0..200)) {
+        check_invariants(&BinaryIndex::new(), &ops)?;
+        check_invariants(&BinaryIndex::new().with_rescore(4), &ops)?;
+    }
+}
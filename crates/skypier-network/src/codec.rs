@@ -0,0 +1,117 @@
+// JSON over request-response streams. libp2p's own JSON codec caps requests
+// at 1MB, less than an insert batch the HTTP API accepts, so this one takes
+// the same limit both ways.
+
+use async_trait::async_trait;
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use libp2p::request_response;
+use libp2p::StreamProtocol;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io;
+use std::marker::PhantomData;
+
+// Largest request or response read off a stream
+pub const MAX_MESSAGE_SIZE: u64 = 64 * 1024 * 1024;
+
+pub struct JsonCodec<Req, Resp> {
+    phantom: PhantomData<fn() -> (Req, Resp)>,
+}
+
+impl<Req, Resp> Default for JsonCodec<Req, Resp> {
+    fn default() -> Self {
+        Self {
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<Req, Resp> Clone for JsonCodec<Req, Resp> {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+pub(crate) async fn read<T, M>(io: &mut T) -> io::Result<M>
+where
+    T: AsyncRead + Unpin + Send,
+    M: DeserializeOwned,
+{
+    let mut buf = Vec::new();
+    io.take(MAX_MESSAGE_SIZE).read_to_end(&mut buf).await?;
+    Ok(serde_json::from_slice(&buf)?)
+}
+
+pub(crate) async fn write<T, M>(io: &mut T, message: M) -> io::Result<()>
+where
+    T: AsyncWrite + Unpin + Send,
+    M: Serialize,
+{
+    let data = serde_json::to_vec(&message)?;
+    io.write_all(&data).await
+}
+
+#[async_trait]
+impl<Req, Resp> request_response::Codec for JsonCodec<Req, Resp>
+where
+    Req: Send + Serialize + DeserializeOwned,
+    Resp: Send + Serialize + DeserializeOwned,
+{
+    type Protocol = StreamProtocol;
+    type Request = Req;
+    type Response = Resp;
+
+    async fn read_request<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<Req>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read(io).await
+    }
+
+    async fn read_response<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<Resp>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read(io).await
+    }
+
+    async fn write_request<T>(&mut self, _: &StreamProtocol, io: &mut T, req: Req) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write(io, req).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+        resp: Resp,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write(io, resp).await
+    }
+}
+
+// A request-response behaviour speaking JSON on `protocol`
+pub type Behaviour<Req, Resp> = request_response::Behaviour<JsonCodec<Req, Resp>>;
+
+pub fn behaviour<Req, Resp>(
+    protocol: &'static str,
+    config: request_response::Config,
+) -> Behaviour<Req, Resp>
+where
+    Req: Send + Serialize + DeserializeOwned,
+    Resp: Send + Serialize + DeserializeOwned,
+{
+    request_response::Behaviour::with_codec(
+        JsonCodec::default(),
+        [(
+            StreamProtocol::new(protocol),
+            request_response::ProtocolSupport::Full,
+        )],
+        config,
+    )
+}
@@ -3,6 +3,7 @@
 
 use anyhow::Result;
 
+#[derive(Default)]
 pub struct ConsensusEngine {
     // Implementation details would go here
 }
@@ -12,7 +13,7 @@ impl ConsensusEngine {
         Self {}
     }
 
-    pub async fn propose_operation(&self, operation: &str) -> Result<bool> {
+    pub async fn propose_operation(&self, _operation: &str) -> Result<bool> {
         // Placeholder implementation
         // In a real implementation, this would:
         // 1. Propose the operation to the cluster
@@ -0,0 +1,110 @@
+// The request-response protocol nodes proxy client requests over to the
+// member that owns the collection they're for
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use skypier_core::{ChangeSet, Vector};
+
+pub const FORWARD_PROTOCOL: &str = "/skypier/forward/1";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ForwardRequest {
+    // The owner answers once `write_concern` is met or `write_timeout_ms`
+    // is up
+    Insert {
+        namespace: String,
+        vectors: Vec<Vector>,
+        write_concern: WriteConcern,
+        write_timeout_ms: u64,
+    },
+    Get {
+        namespace: String,
+        id: String,
+    },
+    Delete {
+        namespace: String,
+        id: String,
+    },
+    // Writes the owner made, for another replica of the collection to
+    // apply as they are
+    Replicate {
+        namespace: String,
+        changes: ChangeSet,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ForwardResponse {
+    Inserted(WriteReport),
+    Vector(Option<Box<Vector>>),
+    // Whether there was a vector to delete
+    Deleted(bool),
+    Replicated,
+    // What the owner would have answered over HTTP
+    Error { status: u16, message: String },
+}
+
+// How many of a collection's replicas must have a write before it's
+// acknowledged
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WriteConcern {
+    // The owner alone; the other replicas get it in the background
+    Local,
+    // A majority of the replicas, the owner included
+    Quorum,
+    #[default]
+    All,
+}
+
+impl WriteConcern {
+    // Acknowledgments needed from the replicas besides the owner, when
+    // there are `others` of them
+    pub fn required(self, others: usize) -> usize {
+        match self {
+            WriteConcern::Local => 0,
+            // A majority of `others + 1`, less the owner
+            WriteConcern::Quorum => others.div_ceil(2),
+            WriteConcern::All => others,
+        }
+    }
+}
+
+// What came of an insert: the ids of the inserted vectors, in request
+// order, and the replicas that failed to take them or didn't answer in
+// time. `acknowledged` is false when fewer replicas of some collection
+// took them than the write concern asks for.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WriteReport {
+    pub ids: Vec<String>,
+    pub acknowledged: bool,
+    pub failures: Vec<ReplicaFailure>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplicaFailure {
+    pub collection: String,
+    // The replica's P2P address
+    pub replica: String,
+    pub error: String,
+}
+
+// Applies requests forwarded by other nodes to this one
+#[async_trait]
+pub trait ForwardHandler: Send + Sync {
+    async fn handle(&self, request: ForwardRequest) -> ForwardResponse;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_concern_acknowledgments() {
+        // Replication factors 1 to 5, so 0 to 4 replicas besides the owner
+        let required = |concern: WriteConcern| (0..5).map(move |others| concern.required(others));
+        assert!(required(WriteConcern::Local).all(|n| n == 0));
+        assert!(required(WriteConcern::Quorum).eq([0, 1, 1, 2, 2]));
+        assert!(required(WriteConcern::All).eq(0..5));
+    }
+}
@@ -1,26 +1,90 @@
-use anyhow::Result;
-
+pub mod codec;
 pub mod consensus;
+pub mod forward;
+pub mod membership;
 pub mod p2p_node;
+pub mod placement;
 pub mod replication;
+pub mod transfer;
 
 pub use consensus::ConsensusEngine;
-pub use p2p_node::P2PNode;
-pub use replication::ReplicationManager;
+pub use forward::{
+    ForwardHandler, ForwardRequest, ForwardResponse, ReplicaFailure, WriteConcern, WriteReport,
+};
+pub use membership::{Member, MemberEvent, MemberEventKind, MemberStatus};
+pub use p2p_node::{NodeHandle, P2PNode};
+pub use placement::PlacementPolicy;
+pub use replication::{ReplicationRequest, ReplicationResponse, RequestHandler};
+pub use transfer::{TransferRequest, TransferResponse};
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+// What the node listens on and dials peers over. `Both` takes connections
+// over either but tells other members its TCP address, so nodes still on
+// TCP only can reach it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransportKind {
+    #[default]
+    Tcp,
+    Quic,
+    Both,
+}
 
 #[derive(Debug, Clone)]
 pub struct NetworkConfig {
+    // TCP and UDP port, for QUIC
     pub port: u16,
+    pub transport: TransportKind,
     pub bootstrap_peers: Vec<String>,
     pub max_peers: usize,
+    // How often a member is probed, and how long it has to answer
+    pub probe_interval: Duration,
+    pub probe_timeout: Duration,
+    // How long a suspected member has to answer before it's declared dead
+    pub suspicion_timeout: Duration,
+    // Whether this node takes a share of the collections in cluster mode
+    pub shard_owner: bool,
+    // Told to other members, which place collections by them
+    pub labels: BTreeMap<String, String>,
+    // Where the node's keypair is kept. None makes a new one, and so a new
+    // peer id, on every start.
+    pub key_file: Option<PathBuf>,
+    // Peer ids connections are accepted from and made to. An empty
+    // allowlist allows every peer that isn't denied.
+    pub allowed_peers: Vec<String>,
+    pub denied_peers: Vec<String>,
+    // Relays to reserve a slot on, as addresses ending in /p2p/<relay id>.
+    // A node behind NAT is reached through them, and hole punching then
+    // tries to replace relayed connections with direct ones.
+    pub relays: Vec<String>,
+    // Whether this node relays connections for others
+    pub relay_server: bool,
+    // Whether to find and join nodes on the local network with mDNS
+    pub mdns: bool,
 }
 
 impl Default for NetworkConfig {
     fn default() -> Self {
         Self {
             port: 8000,
+            transport: TransportKind::Tcp,
             bootstrap_peers: vec![],
             max_peers: 50,
+            probe_interval: Duration::from_secs(1),
+            probe_timeout: Duration::from_millis(500),
+            suspicion_timeout: Duration::from_secs(5),
+            shard_owner: false,
+            labels: BTreeMap::new(),
+            key_file: None,
+            allowed_peers: vec![],
+            denied_peers: vec![],
+            relays: vec![],
+            relay_server: false,
+            mdns: false,
         }
     }
 }
@@ -30,8 +94,388 @@ mod tests {
     use super::*;
 
     #[test]
-    fn it_works() {
-        let result = 2 + 2;
-        assert_eq!(result, 4);
+    fn test_default_network_config() {
+        let config = NetworkConfig::default();
+        assert_eq!(config.port, 8000);
+        assert!(config.bootstrap_peers.is_empty());
+        assert_eq!(config.max_peers, 50);
+    }
+
+    #[tokio::test]
+    async fn test_node_stops_on_shutdown() {
+        let mut node = P2PNode::new(NetworkConfig::default()).await.unwrap();
+        node.run_until(async {}).await.unwrap();
+    }
+
+    async fn listen_address(node: &NodeHandle) -> String {
+        loop {
+            let addresses = node.listen_addresses().await.unwrap();
+            if let Some(addr) = addresses
+                .into_iter()
+                .find(|addr| addr.contains("127.0.0.1"))
+            {
+                return addr;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_members_detect_failures() {
+        let config = NetworkConfig {
+            port: 0,
+            probe_interval: Duration::from_millis(20),
+            probe_timeout: Duration::from_millis(200),
+            suspicion_timeout: Duration::from_millis(200),
+            ..Default::default()
+        };
+        let mut seed = P2PNode::new(config.clone()).await.unwrap();
+        let seed_handle = seed.handle();
+        let mut events = seed_handle.subscribe();
+        tokio::spawn(async move { seed.start().await });
+        let seed_addr = listen_address(&seed_handle).await;
+
+        // Both join through the seed and learn of each other from its gossip
+        let joining = NetworkConfig {
+            bootstrap_peers: vec![seed_addr],
+            ..config
+        };
+        let mut stable = P2PNode::new(joining.clone()).await.unwrap();
+        let stable_handle = stable.handle();
+        tokio::spawn(async move { stable.start().await });
+        let mut leaving = P2PNode::new(joining).await.unwrap();
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        tokio::spawn(async move {
+            leaving
+                .run_until(async {
+                    let _ = stopped.await;
+                })
+                .await
+        });
+
+        let alive = |members: &[Member]| {
+            members
+                .iter()
+                .filter(|member| member.status == MemberStatus::Alive)
+                .count()
+        };
+        // Everyone known, and probed directly at least once
+        for _ in 0..500 {
+            let members = stable_handle.members().await.unwrap();
+            if alive(&members) == 3 && members.iter().all(|member| member.last_seen.is_some()) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let members = stable_handle.members().await.unwrap();
+        assert_eq!(alive(&members), 3);
+        assert!(members.iter().all(|member| member.last_seen.is_some()));
+
+        stop.send(()).unwrap();
+        let failed = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                let event = events.recv().await.unwrap();
+                if event.kind == MemberEventKind::Failed {
+                    return event.member;
+                }
+            }
+        })
+        .await
+        .unwrap();
+        let members = seed_handle.members().await.unwrap();
+        assert_eq!(alive(&members), 2);
+        assert!(members
+            .iter()
+            .any(|member| member.id == failed.id && member.status == MemberStatus::Dead));
+    }
+
+    struct Namespaces;
+
+    #[async_trait::async_trait]
+    impl RequestHandler for Namespaces {
+        async fn handle(&self, request: ReplicationRequest) -> ReplicationResponse {
+            match request {
+                ReplicationRequest::Namespaces => {
+                    ReplicationResponse::Namespaces(vec!["default".to_string()])
+                }
+                _ => ReplicationResponse::Error("unsupported".to_string()),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_nodes_keep_their_key_and_only_talk_to_permitted_peers() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = NetworkConfig {
+            port: 0,
+            key_file: Some(dir.path().join("node.key")),
+            ..Default::default()
+        };
+        let id = P2PNode::new(config.clone())
+            .await
+            .unwrap()
+            .handle()
+            .local_id();
+        let restarted = P2PNode::new(config).await.unwrap().handle().local_id();
+        assert_eq!(id, restarted);
+
+        let mut peers = Vec::new();
+        for _ in 0..3 {
+            let mut node = P2PNode::new(NetworkConfig {
+                port: 0,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+            peers.push(node.handle());
+            tokio::spawn(async move { node.start().await });
+        }
+        let (friend, denied, stranger) = (&peers[0], &peers[1], &peers[2]);
+        let mut server = P2PNode::new(NetworkConfig {
+            port: 0,
+            allowed_peers: vec![friend.local_id(), denied.local_id()],
+            denied_peers: vec![denied.local_id()],
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .with_handler(std::sync::Arc::new(Namespaces));
+        let server_handle = server.handle();
+        tokio::spawn(async move { server.start().await });
+        let addr = listen_address(&server_handle).await;
+
+        assert!(matches!(
+            friend
+                .request(&addr, ReplicationRequest::Namespaces)
+                .await
+                .unwrap(),
+            ReplicationResponse::Namespaces(_)
+        ));
+        for refused in [denied, stranger] {
+            assert!(refused
+                .request(&addr, ReplicationRequest::Namespaces)
+                .await
+                .is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_nodes_connect_over_quic() {
+        let config = |transport, bootstrap_peers| NetworkConfig {
+            port: 0,
+            transport,
+            bootstrap_peers,
+            probe_interval: Duration::from_millis(20),
+            ..Default::default()
+        };
+        let mut server = P2PNode::new(config(TransportKind::Both, vec![]))
+            .await
+            .unwrap()
+            .with_handler(std::sync::Arc::new(Namespaces));
+        let server_handle = server.handle();
+        tokio::spawn(async move { server.start().await });
+        let (tcp_addr, quic_addr) = loop {
+            let addresses = server_handle.listen_addresses().await.unwrap();
+            let local = |quic: bool| {
+                addresses
+                    .iter()
+                    .find(|addr| addr.contains("127.0.0.1") && addr.contains("quic-v1") == quic)
+                    .cloned()
+            };
+            if let (Some(tcp), Some(quic)) = (local(false), local(true)) {
+                break (tcp, quic);
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+
+        let mut quic = P2PNode::new(config(TransportKind::Quic, vec![quic_addr.clone()]))
+            .await
+            .unwrap();
+        let quic_handle = quic.handle();
+        tokio::spawn(async move { quic.start().await });
+        let mut tcp = P2PNode::new(config(TransportKind::Tcp, vec![]))
+            .await
+            .unwrap();
+        let tcp_handle = tcp.handle();
+        tokio::spawn(async move { tcp.start().await });
+        for (client, addr) in [(&quic_handle, &quic_addr), (&tcp_handle, &tcp_addr)] {
+            assert!(matches!(
+                client
+                    .request(addr, ReplicationRequest::Namespaces)
+                    .await
+                    .unwrap(),
+                ReplicationResponse::Namespaces(_)
+            ));
+        }
+
+        // Members learn the QUIC-only node's QUIC address, and the others'
+        // TCP ones
+        let members = loop {
+            let members = quic_handle.members().await.unwrap();
+            let server = members
+                .iter()
+                .find(|member| member.id == server_handle.local_id());
+            if server.is_some_and(|server| !server.addr.is_empty()) {
+                break members;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        for member in members.iter().filter(|member| !member.addr.is_empty()) {
+            let over_quic = member.addr.contains("quic-v1");
+            assert_eq!(over_quic, member.id == quic_handle.local_id());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_nodes_behind_relays_are_reachable() {
+        let mut relay = P2PNode::new(NetworkConfig {
+            port: 0,
+            relay_server: true,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let relay_handle = relay.handle();
+        tokio::spawn(async move { relay.start().await });
+        let relay_addr = format!(
+            "{}/p2p/{}",
+            listen_address(&relay_handle).await,
+            relay_handle.local_id()
+        );
+
+        let mut hidden = P2PNode::new(NetworkConfig {
+            port: 0,
+            relays: vec![relay_addr],
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .with_handler(std::sync::Arc::new(Namespaces));
+        let hidden_handle = hidden.handle();
+        tokio::spawn(async move { hidden.start().await });
+        let relayed = loop {
+            let addresses = hidden_handle.listen_addresses().await.unwrap();
+            if let Some(addr) = addresses
+                .into_iter()
+                .find(|addr| addr.contains("p2p-circuit"))
+            {
+                break addr;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        let members = hidden_handle.members().await.unwrap();
+        let local = members
+            .iter()
+            .find(|member| member.id == hidden_handle.local_id())
+            .unwrap();
+        assert!(local.addr.contains("p2p-circuit"));
+
+        let mut client = P2PNode::new(NetworkConfig {
+            port: 0,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let client_handle = client.handle();
+        tokio::spawn(async move { client.start().await });
+        assert!(matches!(
+            client_handle
+                .request(&relayed, ReplicationRequest::Namespaces)
+                .await
+                .unwrap(),
+            ReplicationResponse::Namespaces(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_nodes_find_each_other_with_mdns() {
+        let config = NetworkConfig {
+            port: 0,
+            mdns: true,
+            probe_interval: Duration::from_millis(20),
+            ..Default::default()
+        };
+        let mut first = P2PNode::new(config.clone()).await.unwrap();
+        let first_handle = first.handle();
+        tokio::spawn(async move { first.start().await });
+        let mut second = P2PNode::new(config).await.unwrap();
+        let second_handle = second.handle();
+        tokio::spawn(async move { second.start().await });
+
+        let found = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                let members = first_handle.members().await.unwrap();
+                if members
+                    .iter()
+                    .any(|member| member.id == second_handle.local_id())
+                {
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        assert!(found.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_nodes_answer_requests() {
+        let config = NetworkConfig {
+            port: 0,
+            ..Default::default()
+        };
+        let mut primary = P2PNode::new(config.clone())
+            .await
+            .unwrap()
+            .with_handler(std::sync::Arc::new(Namespaces));
+        let primary_handle = primary.handle();
+        tokio::spawn(async move { primary.start().await });
+        let mut follower = P2PNode::new(config).await.unwrap();
+        let follower_handle = follower.handle();
+        tokio::spawn(async move { follower.start().await });
+
+        let addr = loop {
+            let addresses = primary_handle.listen_addresses().await.unwrap();
+            if let Some(addr) = addresses
+                .into_iter()
+                .find(|addr| addr.contains("127.0.0.1"))
+            {
+                break addr;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        };
+        for _ in 0..2 {
+            match follower_handle
+                .request(&addr, ReplicationRequest::Namespaces)
+                .await
+                .unwrap()
+            {
+                ReplicationResponse::Namespaces(names) => assert_eq!(names, vec!["default"]),
+                other => panic!("unexpected response {:?}", other),
+            }
+        }
+
+        // Nodes without a handler turn requests down
+        let follower_addr = loop {
+            let addresses = follower_handle.listen_addresses().await.unwrap();
+            if let Some(addr) = addresses
+                .into_iter()
+                .find(|addr| addr.contains("127.0.0.1"))
+            {
+                break addr;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        };
+        assert!(matches!(
+            primary_handle
+                .request(&follower_addr, ReplicationRequest::Namespaces)
+                .await
+                .unwrap(),
+            ReplicationResponse::Error(_)
+        ));
+        assert!(follower_handle
+            .request("not an address", ReplicationRequest::Namespaces)
+            .await
+            .is_err());
     }
 }
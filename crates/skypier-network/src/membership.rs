@@ -0,0 +1,365 @@
+// SWIM-style cluster membership: who's in the cluster and whether they're
+// alive, suspected of having failed, or confirmed dead. Nodes probe one
+// member at a time and gossip the whole member list with every probe.
+
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant, SystemTime};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MemberStatus {
+    Alive,
+    // Missed a probe, direct and indirect; declared dead unless it answers
+    // within the suspicion timeout
+    Suspect,
+    Dead,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Member {
+    // Peer id
+    pub id: String,
+    // P2P address it can be reached on
+    pub addr: String,
+    pub status: MemberStatus,
+    // Bumped by the member itself to refute a suspicion. A newer
+    // incarnation's status wins over an older one's.
+    pub incarnation: u64,
+    // When this node last heard from it directly, in unix seconds. Not
+    // carried over from gossip.
+    #[serde(default)]
+    pub last_seen: Option<u64>,
+    // Whether it holds collections in cluster mode
+    #[serde(default)]
+    pub shard_owner: bool,
+    // Set in its config, e.g. region = "eu-west", for placement constraints
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MemberEventKind {
+    Joined,
+    // Was suspected and answered after all
+    Alive,
+    Suspected,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemberEvent {
+    pub kind: MemberEventKind,
+    pub member: Member,
+}
+
+pub const MEMBERSHIP_PROTOCOL: &str = "/skypier/membership/1";
+
+// Both sides send every member they know of along with each message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum MembershipRequest {
+    Ping {
+        members: Vec<Member>,
+    },
+    // Asks the receiver to probe `target`, which didn't answer the sender
+    PingReq {
+        target: Member,
+        members: Vec<Member>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum MembershipResponse {
+    Ack { members: Vec<Member> },
+    // The target of a PingReq didn't answer either
+    Unreachable { members: Vec<Member> },
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+// Whether `update` carries newer news about a member than `current`
+fn overrides(update: &Member, current: &Member) -> bool {
+    match (update.status, current.status) {
+        // Nothing short of a restart with a newer incarnation brings a dead
+        // member back
+        (MemberStatus::Alive, MemberStatus::Dead) => update.incarnation > current.incarnation,
+        (_, MemberStatus::Dead) => false,
+        (MemberStatus::Dead, _) => true,
+        (MemberStatus::Suspect, MemberStatus::Alive) => update.incarnation >= current.incarnation,
+        _ => update.incarnation > current.incarnation,
+    }
+}
+
+pub struct Membership {
+    local: Member,
+    members: HashMap<String, Member>,
+    // When each suspect was first suspected here
+    suspected: HashMap<String, Instant>,
+    suspicion_timeout: Duration,
+    // Members left to probe this round, in random order
+    probe_order: Vec<String>,
+}
+
+impl Membership {
+    pub fn new(id: &str, shard_owner: bool, suspicion_timeout: Duration) -> Self {
+        Self {
+            local: Member {
+                id: id.to_string(),
+                addr: String::new(),
+                status: MemberStatus::Alive,
+                incarnation: 0,
+                last_seen: None,
+                shard_owner,
+                labels: BTreeMap::new(),
+            },
+            members: HashMap::new(),
+            suspected: HashMap::new(),
+            suspicion_timeout,
+            probe_order: Vec::new(),
+        }
+    }
+
+    pub fn with_labels(mut self, labels: BTreeMap<String, String>) -> Self {
+        self.local.labels = labels;
+        self
+    }
+
+    pub fn local(&self) -> &Member {
+        &self.local
+    }
+
+    pub fn set_local_addr(&mut self, addr: &str) {
+        self.local.addr = addr.to_string();
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Member> {
+        self.members.get(id)
+    }
+
+    // This node and every member it knows of, by id. Also what gets gossiped.
+    pub fn members(&self) -> Vec<Member> {
+        let local = Member {
+            last_seen: Some(unix_now()),
+            ..self.local.clone()
+        };
+        let mut members: Vec<Member> = std::iter::once(local)
+            .chain(self.members.values().cloned())
+            .collect();
+        members.sort_by(|a, b| a.id.cmp(&b.id));
+        members
+    }
+
+    // Takes in what another node knows
+    pub fn merge(&mut self, updates: Vec<Member>) -> Vec<MemberEvent> {
+        let mut events = Vec::new();
+        for update in updates {
+            if update.id == self.local.id {
+                // Someone thinks we're down: outdate that with a newer
+                // incarnation, which spreads with our next messages
+                if update.status != MemberStatus::Alive
+                    && update.incarnation >= self.local.incarnation
+                {
+                    self.local.incarnation = update.incarnation + 1;
+                }
+                continue;
+            }
+            if update.addr.is_empty() {
+                continue;
+            }
+            let last_seen = match self.members.get(&update.id) {
+                Some(current) if !overrides(&update, current) => continue,
+                Some(current) => current.last_seen,
+                // No point learning about members that are gone already
+                None if update.status == MemberStatus::Dead => continue,
+                None => None,
+            };
+            let member = Member {
+                last_seen,
+                ..update
+            };
+            events.extend(self.set(member));
+        }
+        events
+    }
+
+    // Heard from `id` directly, so it's alive whatever was thought before
+    pub fn alive(&mut self, id: &str) -> Vec<MemberEvent> {
+        let Some(current) = self.members.get(id) else {
+            return Vec::new();
+        };
+        let member = Member {
+            status: MemberStatus::Alive,
+            last_seen: Some(unix_now()),
+            ..current.clone()
+        };
+        self.set(member).into_iter().collect()
+    }
+
+    // `id` missed a probe
+    pub fn suspect(&mut self, id: &str) -> Vec<MemberEvent> {
+        match self.members.get(id) {
+            Some(current) if current.status == MemberStatus::Alive => {
+                let member = Member {
+                    status: MemberStatus::Suspect,
+                    ..current.clone()
+                };
+                self.set(member).into_iter().collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    // Declares suspects dead once they've been suspected for the suspicion
+    // timeout
+    pub fn expire(&mut self, now: Instant) -> Vec<MemberEvent> {
+        let expired: Vec<String> = self
+            .suspected
+            .iter()
+            .filter(|(_, since)| now.duration_since(**since) >= self.suspicion_timeout)
+            .map(|(id, _)| id.clone())
+            .collect();
+        let mut events = Vec::new();
+        for id in expired {
+            if let Some(current) = self.members.get(&id) {
+                let member = Member {
+                    status: MemberStatus::Dead,
+                    ..current.clone()
+                };
+                events.extend(self.set(member));
+            }
+        }
+        events
+    }
+
+    // The next member to probe: every live one once per round, in a fresh
+    // random order each round
+    pub fn next_target(&mut self) -> Option<Member> {
+        loop {
+            if self.probe_order.is_empty() {
+                self.probe_order = self.live_ids(None);
+                if self.probe_order.is_empty() {
+                    return None;
+                }
+                self.probe_order.shuffle(&mut rand::thread_rng());
+            }
+            let id = self.probe_order.pop()?;
+            match self.members.get(&id) {
+                Some(member) if member.status != MemberStatus::Dead => {
+                    return Some(member.clone());
+                }
+                _ => continue,
+            }
+        }
+    }
+
+    // Up to `count` random live members other than `target`, to probe it
+    // through
+    pub fn helpers(&self, target: &str, count: usize) -> Vec<Member> {
+        let mut ids = self.live_ids(Some(target));
+        ids.shuffle(&mut rand::thread_rng());
+        ids.iter()
+            .take(count)
+            .filter_map(|id| self.members.get(id).cloned())
+            .collect()
+    }
+
+    fn live_ids(&self, except: Option<&str>) -> Vec<String> {
+        self.members
+            .values()
+            .filter(|member| member.status != MemberStatus::Dead)
+            .filter(|member| Some(member.id.as_str()) != except)
+            .map(|member| member.id.clone())
+            .collect()
+    }
+
+    // Stores `member` and returns the event it amounts to, if any
+    fn set(&mut self, member: Member) -> Option<MemberEvent> {
+        let previous = self
+            .members
+            .insert(member.id.clone(), member.clone())
+            .map(|previous| previous.status);
+        if member.status == MemberStatus::Suspect {
+            self.suspected
+                .entry(member.id.clone())
+                .or_insert_with(Instant::now);
+        } else {
+            self.suspected.remove(&member.id);
+        }
+        let kind = match (previous, member.status) {
+            (None, _) => MemberEventKind::Joined,
+            (Some(before), after) if before == after => return None,
+            (_, MemberStatus::Alive) => MemberEventKind::Alive,
+            (_, MemberStatus::Suspect) => MemberEventKind::Suspected,
+            (_, MemberStatus::Dead) => MemberEventKind::Failed,
+        };
+        Some(MemberEvent { kind, member })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn member(id: &str, status: MemberStatus, incarnation: u64) -> Member {
+        Member {
+            id: id.to_string(),
+            addr: format!("/ip4/127.0.0.1/tcp/{}", id.len()),
+            status,
+            incarnation,
+            last_seen: None,
+            shard_owner: false,
+            labels: BTreeMap::new(),
+        }
+    }
+
+    fn kinds(events: Vec<MemberEvent>) -> Vec<MemberEventKind> {
+        events.into_iter().map(|event| event.kind).collect()
+    }
+
+    #[test]
+    fn test_suspicion_and_refutation() {
+        let mut membership = Membership::new("local", false, Duration::ZERO);
+        let events = membership.merge(vec![
+            member("a", MemberStatus::Alive, 0),
+            member("gone", MemberStatus::Dead, 0),
+        ]);
+        assert_eq!(kinds(events), vec![MemberEventKind::Joined]);
+        assert_eq!(membership.members().len(), 2);
+
+        // Stale gossip doesn't undo a suspicion, a newer incarnation does
+        assert_eq!(
+            kinds(membership.suspect("a")),
+            vec![MemberEventKind::Suspected]
+        );
+        assert!(membership
+            .merge(vec![member("a", MemberStatus::Alive, 0)])
+            .is_empty());
+        assert_eq!(
+            kinds(membership.merge(vec![member("a", MemberStatus::Alive, 1)])),
+            vec![MemberEventKind::Alive]
+        );
+
+        membership.suspect("a");
+        assert_eq!(
+            kinds(membership.expire(Instant::now())),
+            vec![MemberEventKind::Failed]
+        );
+        assert!(membership.next_target().is_none());
+        assert!(membership
+            .merge(vec![member("a", MemberStatus::Suspect, 5)])
+            .is_empty());
+
+        // Gossip suspecting this node gets outdated by a newer incarnation
+        membership.merge(vec![member("local", MemberStatus::Suspect, 0)]);
+        assert_eq!(membership.local().incarnation, 1);
+        membership.merge(vec![member("local", MemberStatus::Alive, 3)]);
+        assert_eq!(membership.local().incarnation, 1);
+    }
+}
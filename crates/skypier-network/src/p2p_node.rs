@@ -1,38 +1,1023 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use futures::StreamExt;
+use libp2p::allow_block_list::{self, AllowedPeers, BlockedPeers};
+use libp2p::core::muxing::StreamMuxerBox;
+use libp2p::core::transport::Boxed;
+use libp2p::core::upgrade;
+use libp2p::multiaddr::Protocol;
+use libp2p::request_response::{self, Message, OutboundRequestId, ResponseChannel};
+use libp2p::swarm::behaviour::toggle::Toggle;
+use libp2p::swarm::dial_opts::DialOpts;
+use libp2p::swarm::{ConnectionId, NetworkBehaviour, SwarmEvent};
+use libp2p::{dcutr, identify, identity, noise, relay, yamux, Multiaddr, PeerId, Swarm, Transport};
 use std::collections::HashMap;
-use tracing::{info, warn};
+use std::future::Future;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tracing::{debug, info, warn};
 
-use crate::NetworkConfig;
+use crate::codec;
+use crate::forward::{ForwardHandler, ForwardRequest, ForwardResponse, FORWARD_PROTOCOL};
+use crate::membership::{
+    Member, MemberEvent, MemberEventKind, MemberStatus, Membership, MembershipRequest,
+    MembershipResponse, MEMBERSHIP_PROTOCOL,
+};
+use crate::replication::{
+    ReplicationRequest, ReplicationResponse, RequestHandler, REPLICATION_PROTOCOL,
+};
+use crate::transfer::{self, TransferRequest, TransferResponse};
+use crate::{NetworkConfig, TransportKind};
+
+// How long a request may wait for its response
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+// How long a transfer's answer, many batches at once, may take
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+// Connections with nothing in flight are closed after this
+const IDLE_CONNECTION_TIMEOUT: Duration = Duration::from_secs(60);
+// Members asked to probe one that missed a direct probe
+const INDIRECT_PROBES: usize = 3;
+// Told to peers by identify
+const AGENT_PROTOCOL: &str = "/skypier/1.0.0";
+// How long a connection relayed through this node may last. Its bytes
+// aren't limited, as replication runs over relayed connections when hole
+// punching fails.
+const MAX_CIRCUIT_DURATION: Duration = Duration::from_secs(60 * 60);
+
+#[derive(NetworkBehaviour)]
+struct Behaviour {
+    // Refuse connections from peers that aren't allowed, when there's an
+    // allowlist, and from denied ones
+    allowed: Toggle<allow_block_list::Behaviour<AllowedPeers>>,
+    denied: allow_block_list::Behaviour<BlockedPeers>,
+    replication: codec::Behaviour<ReplicationRequest, ReplicationResponse>,
+    membership: codec::Behaviour<MembershipRequest, MembershipResponse>,
+    forward: codec::Behaviour<ForwardRequest, ForwardResponse>,
+    transfer: transfer::Behaviour,
+    // Connections through relays, to and from nodes behind NAT, relaying
+    // for others when this node is a relay, and hole punching to replace
+    // relayed connections with direct ones. Hole punching needs the
+    // addresses peers see us on, which identify tells us.
+    relay_client: relay::client::Behaviour,
+    relay: Toggle<relay::Behaviour>,
+    dcutr: dcutr::Behaviour,
+    identify: identify::Behaviour,
+    // Finds nodes on the local network, when enabled
+    mdns: Toggle<libp2p_mdns::tokio::Behaviour>,
+}
+
+// A membership request waiting for its answer
+enum Probe {
+    // Sent to a new connection to swap member lists
+    Join,
+    Direct(String),
+    // Through another member, after `.0` missed a direct probe
+    Indirect(String),
+    // Asked for by another member with a PingReq
+    OnBehalf(ResponseChannel<MembershipResponse>),
+}
+
+type Reply = oneshot::Sender<Result<ReplicationResponse>>;
+type ForwardReply = oneshot::Sender<Result<ForwardResponse>>;
+type TransferReply = oneshot::Sender<Result<TransferResponse>>;
+
+// A request to a node reached by address, waiting for its connection or
+// its answer
+enum Outbound {
+    Replication(ReplicationRequest, Reply),
+    Transfer(TransferRequest, TransferReply),
+}
+
+impl Outbound {
+    fn fail(self, error: anyhow::Error) {
+        match self {
+            Outbound::Replication(_, reply) => {
+                let _ = reply.send(Err(error));
+            }
+            Outbound::Transfer(_, reply) => {
+                let _ = reply.send(Err(error));
+            }
+        }
+    }
+}
+
+// An answer from a handler task, to send back over the swarm
+enum Response {
+    Replication(ResponseChannel<ReplicationResponse>, ReplicationResponse),
+    Forward(ResponseChannel<ForwardResponse>, ForwardResponse),
+    Transfer(ResponseChannel<TransferResponse>, TransferResponse),
+}
+
+enum Command {
+    Request {
+        addr: Multiaddr,
+        request: Outbound,
+    },
+    Forward {
+        peer: PeerId,
+        addr: Multiaddr,
+        request: ForwardRequest,
+        reply: ForwardReply,
+    },
+    ListenAddresses(oneshot::Sender<Vec<Multiaddr>>),
+    Members(oneshot::Sender<Vec<Member>>),
+    PeerId(Multiaddr, oneshot::Sender<Option<PeerId>>),
+}
+
+// Lets other tasks talk to peers through a running node
+#[derive(Clone)]
+pub struct NodeHandle {
+    local_id: PeerId,
+    commands: mpsc::Sender<Command>,
+    events: broadcast::Sender<MemberEvent>,
+}
+
+impl NodeHandle {
+    pub fn local_id(&self) -> String {
+        self.local_id.to_string()
+    }
+
+    // Sends `request` to the node listening at `addr`, e.g.
+    // "/ip4/10.0.0.1/tcp/8000", and waits for its answer
+    pub async fn request(
+        &self,
+        addr: &str,
+        request: ReplicationRequest,
+    ) -> Result<ReplicationResponse> {
+        let addr: Multiaddr = addr
+            .parse()
+            .map_err(|e| anyhow!("Invalid peer address '{}': {}", addr, e))?;
+        let (reply, response) = oneshot::channel();
+        let request = Outbound::Replication(request, reply);
+        self.send(Command::Request { addr, request }).await?;
+        response
+            .await
+            .map_err(|_| anyhow!("The P2P node stopped"))?
+    }
+
+    // Asks the node listening at `addr` for a batch of its vectors over
+    // the transfer protocol
+    pub async fn transfer(&self, addr: &str, request: TransferRequest) -> Result<TransferResponse> {
+        let addr: Multiaddr = addr
+            .parse()
+            .map_err(|e| anyhow!("Invalid peer address '{}': {}", addr, e))?;
+        let (reply, response) = oneshot::channel();
+        let request = Outbound::Transfer(request, reply);
+        self.send(Command::Request { addr, request }).await?;
+        response
+            .await
+            .map_err(|_| anyhow!("The P2P node stopped"))?
+    }
+
+    // Sends `request` to `member`, e.g. the owner of a collection, and
+    // waits for its answer
+    pub async fn forward(
+        &self,
+        member: &Member,
+        request: ForwardRequest,
+    ) -> Result<ForwardResponse> {
+        let peer: PeerId = member
+            .id
+            .parse()
+            .map_err(|e| anyhow!("Invalid peer id '{}': {}", member.id, e))?;
+        let addr: Multiaddr = member
+            .addr
+            .parse()
+            .map_err(|e| anyhow!("Invalid peer address '{}': {}", member.addr, e))?;
+        let (reply, response) = oneshot::channel();
+        self.send(Command::Forward {
+            peer,
+            addr,
+            request,
+            reply,
+        })
+        .await?;
+        response
+            .await
+            .map_err(|_| anyhow!("The P2P node stopped"))?
+    }
+
+    // What the node accepts connections on; empty until it has started
+    pub async fn listen_addresses(&self) -> Result<Vec<String>> {
+        let (reply, addresses) = oneshot::channel();
+        self.send(Command::ListenAddresses(reply)).await?;
+        let addresses = addresses
+            .await
+            .map_err(|_| anyhow!("The P2P node stopped"))?;
+        Ok(addresses.iter().map(Multiaddr::to_string).collect())
+    }
+
+    // This node and every member of the cluster it knows of
+    pub async fn members(&self) -> Result<Vec<Member>> {
+        let (reply, members) = oneshot::channel();
+        self.send(Command::Members(reply)).await?;
+        members.await.map_err(|_| anyhow!("The P2P node stopped"))
+    }
+
+    // Peer id of the node a request was sent to at `addr`, once connected
+    pub async fn peer_id(&self, addr: &str) -> Result<Option<String>> {
+        let addr: Multiaddr = addr
+            .parse()
+            .map_err(|e| anyhow!("Invalid peer address '{}': {}", addr, e))?;
+        let (reply, peer) = oneshot::channel();
+        self.send(Command::PeerId(addr, reply)).await?;
+        let peer = peer.await.map_err(|_| anyhow!("The P2P node stopped"))?;
+        Ok(peer.map(|peer| peer.to_string()))
+    }
+
+    // Members joining, being suspected, failing or coming back from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<MemberEvent> {
+        self.events.subscribe()
+    }
+
+    async fn send(&self, command: Command) -> Result<()> {
+        self.commands
+            .send(command)
+            .await
+            .map_err(|_| anyhow!("The P2P node stopped"))
+    }
+}
 
 pub struct P2PNode {
     config: NetworkConfig,
-    peers: HashMap<String, String>,
+    swarm: Swarm<Behaviour>,
+    handler: Option<Arc<dyn RequestHandler>>,
+    forwarder: Option<Arc<dyn ForwardHandler>>,
+    commands: mpsc::Receiver<Command>,
+    command_sender: mpsc::Sender<Command>,
+    // Answers from handler tasks, to send back over the swarm
+    responses: mpsc::UnboundedReceiver<Response>,
+    response_sender: mpsc::UnboundedSender<Response>,
+    // Peers we dialled, by the address we dialled them on
+    peers: HashMap<Multiaddr, PeerId>,
+    // Requests waiting for their connection to come up
+    dialling: HashMap<ConnectionId, (Multiaddr, Outbound)>,
+    // Requests waiting for their response
+    pending: HashMap<OutboundRequestId, Reply>,
+    forwarded: HashMap<OutboundRequestId, ForwardReply>,
+    transfers: HashMap<OutboundRequestId, TransferReply>,
+    membership: Membership,
+    events: broadcast::Sender<MemberEvent>,
+    probes: HashMap<OutboundRequestId, Probe>,
+    // Indirect probes still out per member, which is suspected once they
+    // all fail
+    indirect: HashMap<String, usize>,
 }
 
 impl P2PNode {
     pub async fn new(config: NetworkConfig) -> Result<Self> {
         info!("Starting P2P node on port {}", config.port);
 
+        let keypair = match &config.key_file {
+            Some(path) => load_or_create_keypair(path)?,
+            None => identity::Keypair::generate_ed25519(),
+        };
+        let mut allowed = allow_block_list::Behaviour::<AllowedPeers>::default();
+        for peer in parse_peers(&config.allowed_peers)? {
+            allowed.allow_peer(peer);
+        }
+        let mut denied = allow_block_list::Behaviour::<BlockedPeers>::default();
+        for peer in parse_peers(&config.denied_peers)? {
+            denied.block_peer(peer);
+        }
+        let peer_id = keypair.public().to_peer_id();
+        let direct = match config.transport {
+            TransportKind::Tcp => tcp_transport(&keypair)?,
+            TransportKind::Quic => quic_transport(&keypair),
+            TransportKind::Both => tcp_transport(&keypair)?
+                .or_transport(quic_transport(&keypair))
+                .map(|either, _| either.into_inner())
+                .boxed(),
+        };
+        // Relayed addresses are dialled through the relay, and the
+        // connection inside the circuit is upgraded like a TCP one
+        let (relayed, relay_client) = relay::client::new(peer_id);
+        let transport = relayed
+            .upgrade(upgrade::Version::V1)
+            .authenticate(noise::Config::new(&keypair)?)
+            .multiplex(yamux::Config::default())
+            .map(|(peer, muxer), _| (peer, StreamMuxerBox::new(muxer)))
+            .or_transport(direct)
+            .map(|either, _| either.into_inner())
+            .boxed();
+        let mdns = if config.mdns {
+            let mdns = libp2p_mdns::tokio::Behaviour::new(libp2p_mdns::Config::default(), peer_id)?;
+            Some(mdns)
+        } else {
+            None
+        };
+        let relay = config.relay_server.then(|| {
+            relay::Behaviour::new(
+                peer_id,
+                relay::Config {
+                    max_circuit_duration: MAX_CIRCUIT_DURATION,
+                    max_circuit_bytes: 0,
+                    ..Default::default()
+                },
+            )
+        });
+        let behaviour = Behaviour {
+            allowed: Toggle::from((!config.allowed_peers.is_empty()).then_some(allowed)),
+            denied,
+            replication: codec::behaviour(
+                REPLICATION_PROTOCOL,
+                request_response::Config::default().with_request_timeout(REQUEST_TIMEOUT),
+            ),
+            membership: codec::behaviour(
+                MEMBERSHIP_PROTOCOL,
+                request_response::Config::default().with_request_timeout(config.probe_timeout),
+            ),
+            forward: codec::behaviour(
+                FORWARD_PROTOCOL,
+                request_response::Config::default().with_request_timeout(REQUEST_TIMEOUT),
+            ),
+            transfer: transfer::behaviour(
+                request_response::Config::default().with_request_timeout(TRANSFER_TIMEOUT),
+            ),
+            relay_client,
+            relay: Toggle::from(relay),
+            dcutr: dcutr::Behaviour::new(peer_id),
+            identify: identify::Behaviour::new(identify::Config::new(
+                AGENT_PROTOCOL.to_string(),
+                keypair.public(),
+            )),
+            mdns: Toggle::from(mdns),
+        };
+        let swarm = Swarm::new(
+            transport,
+            behaviour,
+            peer_id,
+            libp2p_swarm::Config::with_tokio_executor()
+                .with_idle_connection_timeout(IDLE_CONNECTION_TIMEOUT),
+        );
+
+        let (command_sender, commands) = mpsc::channel(64);
+        let (response_sender, responses) = mpsc::unbounded_channel();
+        let membership = Membership::new(
+            &peer_id.to_string(),
+            config.shard_owner,
+            config.suspicion_timeout,
+        )
+        .with_labels(config.labels.clone());
         Ok(Self {
             config,
+            swarm,
+            handler: None,
+            forwarder: None,
+            commands,
+            command_sender,
+            responses,
+            response_sender,
             peers: HashMap::new(),
+            dialling: HashMap::new(),
+            pending: HashMap::new(),
+            forwarded: HashMap::new(),
+            transfers: HashMap::new(),
+            membership,
+            events: broadcast::channel(256).0,
+            probes: HashMap::new(),
+            indirect: HashMap::new(),
         })
     }
 
+    // Serves requests from other nodes with `handler`. Without one they get
+    // an error back.
+    pub fn with_handler(mut self, handler: Arc<dyn RequestHandler>) -> Self {
+        self.handler = Some(handler);
+        self
+    }
+
+    // Applies requests other nodes forward here with `forwarder`. Without
+    // one they get an error back.
+    pub fn with_forwarder(mut self, forwarder: Arc<dyn ForwardHandler>) -> Self {
+        self.forwarder = Some(forwarder);
+        self
+    }
+
+    pub fn handle(&self) -> NodeHandle {
+        NodeHandle {
+            local_id: *self.swarm.local_peer_id(),
+            commands: self.command_sender.clone(),
+            events: self.events.clone(),
+        }
+    }
+
     pub async fn start(&mut self) -> Result<()> {
-        info!("P2P node started successfully");
-        // This is a stub implementation that runs indefinitely
-        // In a real implementation, this would start the libp2p swarm and handle events
+        let port = self.config.port;
+        let listen = match self.config.transport {
+            TransportKind::Tcp => vec![format!("/ip4/0.0.0.0/tcp/{}", port)],
+            TransportKind::Quic => vec![format!("/ip4/0.0.0.0/udp/{}/quic-v1", port)],
+            TransportKind::Both => vec![
+                format!("/ip4/0.0.0.0/tcp/{}", port),
+                format!("/ip4/0.0.0.0/udp/{}/quic-v1", port),
+            ],
+        };
+        for addr in listen {
+            self.swarm.listen_on(addr.parse()?)?;
+        }
+        // Reachable through each relay as well, at <relay>/p2p-circuit
+        for relay in self.config.relays.clone() {
+            let addr = relay
+                .parse::<Multiaddr>()
+                .map_err(|e| anyhow!("Invalid relay address '{}': {}", relay, e))?;
+            if let Err(e) = self.swarm.listen_on(addr.with(Protocol::P2pCircuit)) {
+                warn!("Failed to listen through relay {}: {}", relay, e);
+            }
+        }
+        for peer in self.config.bootstrap_peers.clone() {
+            if let Err(e) = self.connect_to_peer(&peer).await {
+                warn!("Failed to connect to bootstrap peer {}: {}", peer, e);
+            }
+        }
+        info!(
+            "P2P node {} started successfully",
+            self.swarm.local_peer_id()
+        );
+
+        let mut probe_ticker = tokio::time::interval(self.config.probe_interval);
         loop {
-            tokio::time::sleep(tokio::time::Duration::from_secs(60)).await;
+            tokio::select! {
+                event = self.swarm.select_next_some() => self.handle_event(event),
+                Some(command) = self.commands.recv() => self.handle_command(command),
+                Some(response) = self.responses.recv() => {
+                    // Fails when the requester gave up or disconnected
+                    let behaviour = self.swarm.behaviour_mut();
+                    match response {
+                        Response::Replication(channel, response) => {
+                            let _ = behaviour.replication.send_response(channel, response);
+                        }
+                        Response::Forward(channel, response) => {
+                            let _ = behaviour.forward.send_response(channel, response);
+                        }
+                        Response::Transfer(channel, response) => {
+                            let _ = behaviour.transfer.send_response(channel, response);
+                        }
+                    }
+                }
+                _ = probe_ticker.tick() => self.probe(),
+            }
+        }
+    }
+
+    // Runs the node until `shutdown` resolves, then stops it
+    pub async fn run_until<F>(&mut self, shutdown: F) -> Result<()>
+    where
+        F: Future<Output = ()>,
+    {
+        tokio::select! {
+            result = self.start() => result,
+            _ = shutdown => self.stop().await,
         }
     }
 
     pub async fn stop(&mut self) -> Result<()> {
+        for (_, (_, request)) in self.dialling.drain() {
+            request.fail(anyhow!("The P2P node stopped"));
+        }
+        for (_, reply) in self.pending.drain() {
+            let _ = reply.send(Err(anyhow!("The P2P node stopped")));
+        }
+        for (_, reply) in self.forwarded.drain() {
+            let _ = reply.send(Err(anyhow!("The P2P node stopped")));
+        }
+        for (_, reply) in self.transfers.drain() {
+            let _ = reply.send(Err(anyhow!("The P2P node stopped")));
+        }
         info!("P2P node stopped");
         Ok(())
     }
 
+    fn handle_command(&mut self, command: Command) {
+        match command {
+            Command::Request { addr, request } => self.send_request(addr, request),
+            Command::Forward {
+                peer,
+                addr,
+                request,
+                reply,
+            } => {
+                // Lets the behaviour dial it when there's no connection yet
+                self.swarm.add_peer_address(peer, addr);
+                let id = self
+                    .swarm
+                    .behaviour_mut()
+                    .forward
+                    .send_request(&peer, request);
+                self.forwarded.insert(id, reply);
+            }
+            Command::ListenAddresses(reply) => {
+                let _ = reply.send(self.swarm.listeners().cloned().collect());
+            }
+            Command::Members(reply) => {
+                let _ = reply.send(self.membership.members());
+            }
+            Command::PeerId(addr, reply) => {
+                let _ = reply.send(self.peers.get(&addr).copied());
+            }
+        }
+    }
+
+    // Reuses the connection to `addr` when there is one, otherwise dials it
+    // and sends once it's up
+    fn send_request(&mut self, addr: Multiaddr, request: Outbound) {
+        if let Some(peer) = self.peers.get(&addr).copied() {
+            if self.swarm.is_connected(&peer) {
+                self.send_outbound(peer, request);
+                return;
+            }
+        }
+        let opts = DialOpts::unknown_peer_id().address(addr.clone()).build();
+        let connection_id = opts.connection_id();
+        match self.swarm.dial(opts) {
+            Ok(()) => {
+                self.dialling.insert(connection_id, (addr, request));
+            }
+            Err(e) => request.fail(anyhow!("Failed to dial {}: {}", addr, e)),
+        }
+    }
+
+    fn send_outbound(&mut self, peer: PeerId, request: Outbound) {
+        let behaviour = self.swarm.behaviour_mut();
+        match request {
+            Outbound::Replication(request, reply) => {
+                let id = behaviour.replication.send_request(&peer, request);
+                self.pending.insert(id, reply);
+            }
+            Outbound::Transfer(request, reply) => {
+                let id = behaviour.transfer.send_request(&peer, request);
+                self.transfers.insert(id, reply);
+            }
+        }
+    }
+
+    fn handle_event(&mut self, event: SwarmEvent<BehaviourEvent>) {
+        match event {
+            SwarmEvent::NewListenAddr { address, .. } => {
+                info!("P2P node listening on {}", address);
+                // Other members reach us on the first address that isn't
+                // loopback, if there is one, over the transport we tell
+                // them of. Nodes behind NAT, with relays, are reached
+                // through a relay.
+                let local = self.membership.local().addr.clone();
+                let advertised = if self.config.relays.is_empty() {
+                    is_quic(&address) == (self.config.transport == TransportKind::Quic)
+                } else {
+                    is_relayed(&address)
+                };
+                if advertised && (local.is_empty() || is_loopback(&local)) {
+                    self.membership.set_local_addr(&address.to_string());
+                }
+                // Nodes reserving a slot are told how to reach the relay,
+                // which is expected to be reachable on what it listens on
+                if self.config.relay_server && !is_relayed(&address) {
+                    self.swarm.add_external_address(address);
+                }
+            }
+            SwarmEvent::ConnectionEstablished {
+                peer_id,
+                connection_id,
+                ..
+            } => {
+                debug!("Connected to peer {}", peer_id);
+                let known = self
+                    .membership
+                    .get(&peer_id.to_string())
+                    .is_some_and(|member| member.status != MemberStatus::Dead);
+                if !known {
+                    let request = MembershipRequest::Ping {
+                        members: self.membership.members(),
+                    };
+                    let id = self
+                        .swarm
+                        .behaviour_mut()
+                        .membership
+                        .send_request(&peer_id, request);
+                    self.probes.insert(id, Probe::Join);
+                }
+                if let Some((addr, request)) = self.dialling.remove(&connection_id) {
+                    self.peers.insert(addr, peer_id);
+                    self.send_outbound(peer_id, request);
+                }
+            }
+            SwarmEvent::OutgoingConnectionError {
+                connection_id,
+                error,
+                ..
+            } => {
+                if let Some((addr, request)) = self.dialling.remove(&connection_id) {
+                    request.fail(anyhow!("Failed to connect to {}: {}", addr, error));
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Replication(
+                request_response::Event::Message { peer, message, .. },
+            )) => match message {
+                Message::Request {
+                    request, channel, ..
+                } => self.serve(peer, request, channel),
+                Message::Response {
+                    request_id,
+                    response,
+                } => {
+                    if let Some(reply) = self.pending.remove(&request_id) {
+                        let _ = reply.send(Ok(response));
+                    }
+                }
+            },
+            SwarmEvent::Behaviour(BehaviourEvent::Replication(
+                request_response::Event::OutboundFailure {
+                    peer,
+                    request_id,
+                    error,
+                    ..
+                },
+            )) => {
+                if let Some(reply) = self.pending.remove(&request_id) {
+                    let _ = reply.send(Err(anyhow!("Request to peer {} failed: {}", peer, error)));
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Replication(
+                request_response::Event::InboundFailure { peer, error, .. },
+            )) => {
+                warn!("Failed to answer a request from peer {}: {}", peer, error);
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Membership(event)) => {
+                self.handle_membership(event);
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Forward(event)) => self.handle_forward(event),
+            SwarmEvent::Behaviour(BehaviourEvent::Transfer(event)) => self.handle_transfer(event),
+            SwarmEvent::Behaviour(BehaviourEvent::Mdns(libp2p_mdns::Event::Discovered(found))) => {
+                self.discovered(found)
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::RelayClient(
+                relay::client::Event::ReservationReqAccepted {
+                    relay_peer_id,
+                    renewal: false,
+                    ..
+                },
+            )) => info!("Reachable through relay {}", relay_peer_id),
+            SwarmEvent::Behaviour(BehaviourEvent::Dcutr(dcutr::Event {
+                remote_peer_id,
+                result,
+            })) => match result {
+                Ok(_) => info!("Hole punched a direct connection to {}", remote_peer_id),
+                Err(e) => debug!("No direct connection to {}: {}", remote_peer_id, e),
+            },
+            _ => {}
+        }
+    }
+
+    // Connects to nodes mDNS found on the local network that aren't
+    // members yet; they join like bootstrap peers do
+    fn discovered(&mut self, found: Vec<(PeerId, Multiaddr)>) {
+        let mut addresses: HashMap<PeerId, Vec<Multiaddr>> = HashMap::new();
+        for (peer, addr) in found {
+            addresses.entry(peer).or_default().push(addr);
+        }
+        for (peer, addresses) in addresses {
+            let known = self
+                .membership
+                .get(&peer.to_string())
+                .is_some_and(|member| member.status != MemberStatus::Dead);
+            if known || self.swarm.is_connected(&peer) {
+                continue;
+            }
+            info!("Found peer {} on the local network", peer);
+            let dial = DialOpts::peer_id(peer).addresses(addresses).build();
+            if let Err(e) = self.swarm.dial(dial) {
+                debug!("Failed to dial peer {}: {}", peer, e);
+            }
+        }
+    }
+
+    // One SWIM protocol period: declares suspects that didn't answer in
+    // time dead, then probes the next member
+    fn probe(&mut self) {
+        let events = self.membership.expire(Instant::now());
+        self.publish(events);
+        if let Some(target) = self.membership.next_target() {
+            let request = MembershipRequest::Ping {
+                members: self.membership.members(),
+            };
+            if let Some(id) = self.send_membership(&target, request) {
+                self.probes.insert(id, Probe::Direct(target.id));
+            }
+        }
+    }
+
+    fn send_membership(
+        &mut self,
+        member: &Member,
+        request: MembershipRequest,
+    ) -> Option<OutboundRequestId> {
+        let peer: PeerId = member.id.parse().ok()?;
+        let addr: Multiaddr = member.addr.parse().ok()?;
+        // Lets the behaviour dial it when there's no connection yet
+        self.swarm.add_peer_address(peer, addr);
+        Some(
+            self.swarm
+                .behaviour_mut()
+                .membership
+                .send_request(&peer, request),
+        )
+    }
+
+    fn handle_membership(
+        &mut self,
+        event: request_response::Event<MembershipRequest, MembershipResponse>,
+    ) {
+        match event {
+            request_response::Event::Message { peer, message, .. } => match message {
+                Message::Request {
+                    request, channel, ..
+                } => {
+                    let members = match &request {
+                        MembershipRequest::Ping { members } => members.clone(),
+                        MembershipRequest::PingReq { members, .. } => members.clone(),
+                    };
+                    self.heard_from(peer, members);
+                    match request {
+                        MembershipRequest::Ping { .. } => {
+                            let response = MembershipResponse::Ack {
+                                members: self.membership.members(),
+                            };
+                            let _ = self
+                                .swarm
+                                .behaviour_mut()
+                                .membership
+                                .send_response(channel, response);
+                        }
+                        MembershipRequest::PingReq { target, .. } => {
+                            let request = MembershipRequest::Ping {
+                                members: self.membership.members(),
+                            };
+                            match self.send_membership(&target, request) {
+                                Some(id) => {
+                                    self.probes.insert(id, Probe::OnBehalf(channel));
+                                }
+                                None => self.answer_unreachable(channel),
+                            }
+                        }
+                    }
+                }
+                Message::Response {
+                    request_id,
+                    response,
+                } => {
+                    let (reached, members) = match response {
+                        MembershipResponse::Ack { members } => (true, members),
+                        MembershipResponse::Unreachable { members } => (false, members),
+                    };
+                    self.heard_from(peer, members);
+                    match self.probes.remove(&request_id) {
+                        Some(Probe::Indirect(target)) if reached => {
+                            self.indirect.remove(&target);
+                            let events = self.membership.alive(&target);
+                            self.publish(events);
+                        }
+                        Some(Probe::Indirect(target)) => self.indirect_failed(&target),
+                        Some(Probe::OnBehalf(channel)) => {
+                            let response = MembershipResponse::Ack {
+                                members: self.membership.members(),
+                            };
+                            let _ = self
+                                .swarm
+                                .behaviour_mut()
+                                .membership
+                                .send_response(channel, response);
+                        }
+                        _ => {}
+                    }
+                }
+            },
+            request_response::Event::OutboundFailure {
+                peer,
+                request_id,
+                error,
+                ..
+            } => {
+                debug!("Membership probe of peer {} failed: {}", peer, error);
+                match self.probes.remove(&request_id) {
+                    Some(Probe::Direct(target)) => {
+                        let helpers = self.membership.helpers(&target, INDIRECT_PROBES);
+                        let Some(member) = self.membership.get(&target).cloned() else {
+                            return;
+                        };
+                        let mut sent = 0;
+                        for helper in helpers {
+                            let request = MembershipRequest::PingReq {
+                                target: member.clone(),
+                                members: self.membership.members(),
+                            };
+                            if let Some(id) = self.send_membership(&helper, request) {
+                                self.probes.insert(id, Probe::Indirect(target.clone()));
+                                sent += 1;
+                            }
+                        }
+                        if sent == 0 {
+                            let events = self.membership.suspect(&target);
+                            self.publish(events);
+                        } else {
+                            self.indirect.insert(target, sent);
+                        }
+                    }
+                    Some(Probe::Indirect(target)) => self.indirect_failed(&target),
+                    Some(Probe::OnBehalf(channel)) => self.answer_unreachable(channel),
+                    Some(Probe::Join) | None => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_forward(&mut self, event: request_response::Event<ForwardRequest, ForwardResponse>) {
+        match event {
+            request_response::Event::Message { peer, message, .. } => match message {
+                Message::Request {
+                    request, channel, ..
+                } => {
+                    let forwarder = self.forwarder.clone();
+                    let responses = self.response_sender.clone();
+                    tokio::spawn(async move {
+                        let response = match forwarder {
+                            Some(forwarder) => forwarder.handle(request).await,
+                            None => {
+                                debug!("Refused a forwarded request from peer {}", peer);
+                                ForwardResponse::Error {
+                                    status: 503,
+                                    message: "This node doesn't take forwarded requests"
+                                        .to_string(),
+                                }
+                            }
+                        };
+                        let _ = responses.send(Response::Forward(channel, response));
+                    });
+                }
+                Message::Response {
+                    request_id,
+                    response,
+                } => {
+                    if let Some(reply) = self.forwarded.remove(&request_id) {
+                        let _ = reply.send(Ok(response));
+                    }
+                }
+            },
+            request_response::Event::OutboundFailure {
+                peer,
+                request_id,
+                error,
+                ..
+            } => {
+                if let Some(reply) = self.forwarded.remove(&request_id) {
+                    let _ = reply.send(Err(anyhow!(
+                        "Forwarding to peer {} failed: {}",
+                        peer,
+                        error
+                    )));
+                }
+            }
+            request_response::Event::InboundFailure { peer, error, .. } => {
+                warn!(
+                    "Failed to answer a forwarded request from peer {}: {}",
+                    peer, error
+                );
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_transfer(
+        &mut self,
+        event: request_response::Event<TransferRequest, TransferResponse>,
+    ) {
+        match event {
+            request_response::Event::Message { peer, message, .. } => match message {
+                Message::Request {
+                    request, channel, ..
+                } => {
+                    let handler = self.handler.clone();
+                    let responses = self.response_sender.clone();
+                    tokio::spawn(async move {
+                        let response = match handler {
+                            Some(handler) => handler.transfer(request).await,
+                            None => {
+                                debug!("Refused a transfer from peer {}", peer);
+                                TransferResponse::failed("This node doesn't serve replication")
+                            }
+                        };
+                        let _ = responses.send(Response::Transfer(channel, response));
+                    });
+                }
+                Message::Response {
+                    request_id,
+                    response,
+                } => {
+                    if let Some(reply) = self.transfers.remove(&request_id) {
+                        let _ = reply.send(Ok(response));
+                    }
+                }
+            },
+            request_response::Event::OutboundFailure {
+                peer,
+                request_id,
+                error,
+                ..
+            } => {
+                if let Some(reply) = self.transfers.remove(&request_id) {
+                    let _ = reply.send(Err(anyhow!(
+                        "Transfer from peer {} failed: {}",
+                        peer,
+                        error
+                    )));
+                }
+            }
+            request_response::Event::InboundFailure { peer, error, .. } => {
+                warn!("Failed to send a transfer to peer {}: {}", peer, error);
+            }
+            _ => {}
+        }
+    }
+
+    // Any message from `peer` shows it's alive, and carries what it knows.
+    // Members this node may not connect to are left out.
+    fn heard_from(&mut self, peer: PeerId, mut members: Vec<Member>) {
+        members.retain(|member| self.permitted(&member.id));
+        let mut events = self.membership.merge(members);
+        events.extend(self.membership.alive(&peer.to_string()));
+        self.publish(events);
+    }
+
+    fn permitted(&self, id: &str) -> bool {
+        let Ok(peer) = id.parse::<PeerId>() else {
+            return false;
+        };
+        let behaviour = self.swarm.behaviour();
+        peer == *self.swarm.local_peer_id()
+            || (!behaviour.denied.blocked_peers().contains(&peer)
+                && behaviour
+                    .allowed
+                    .as_ref()
+                    .is_none_or(|allowed| allowed.allowed_peers().contains(&peer)))
+    }
+
+    fn indirect_failed(&mut self, target: &str) {
+        let Some(remaining) = self.indirect.get_mut(target) else {
+            return;
+        };
+        *remaining -= 1;
+        if *remaining == 0 {
+            self.indirect.remove(target);
+            let events = self.membership.suspect(target);
+            self.publish(events);
+        }
+    }
+
+    fn answer_unreachable(&mut self, channel: ResponseChannel<MembershipResponse>) {
+        let response = MembershipResponse::Unreachable {
+            members: self.membership.members(),
+        };
+        let _ = self
+            .swarm
+            .behaviour_mut()
+            .membership
+            .send_response(channel, response);
+    }
+
+    fn publish(&self, events: Vec<MemberEvent>) {
+        for event in events {
+            let member = &event.member;
+            match event.kind {
+                MemberEventKind::Joined => info!("Peer {} ({}) joined", member.id, member.addr),
+                MemberEventKind::Alive => info!("Peer {} is alive again", member.id),
+                MemberEventKind::Suspected => warn!("Peer {} is suspected down", member.id),
+                MemberEventKind::Failed => warn!("Peer {} ({}) failed", member.id, member.addr),
+            }
+            // Fails only when nobody is subscribed
+            let _ = self.events.send(event);
+        }
+    }
+
+    // Answers on its own task, so a slow request doesn't hold up the swarm
+    fn serve(
+        &self,
+        peer: PeerId,
+        request: ReplicationRequest,
+        channel: ResponseChannel<ReplicationResponse>,
+    ) {
+        let handler = self.handler.clone();
+        let responses = self.response_sender.clone();
+        tokio::spawn(async move {
+            let response = match handler {
+                Some(handler) => handler.handle(request).await,
+                None => {
+                    debug!("Refused a replication request from peer {}", peer);
+                    ReplicationResponse::Error("This node doesn't serve replication".to_string())
+                }
+            };
+            let _ = responses.send(Response::Replication(channel, response));
+        });
+    }
+
     pub async fn publish_message(&mut self, topic: &str, _message: &[u8]) -> Result<()> {
         info!("Publishing message to topic: {}", topic);
         // Stub implementation - would publish to gossipsub
@@ -41,12 +1026,87 @@ impl P2PNode {
 
     pub async fn connect_to_peer(&mut self, peer_addr: &str) -> Result<()> {
         info!("Connecting to peer: {}", peer_addr);
-        self.peers
-            .insert(peer_addr.to_string(), "connected".to_string());
+        let addr: Multiaddr = peer_addr
+            .parse()
+            .map_err(|e| anyhow!("Invalid peer address '{}': {}", peer_addr, e))?;
+        self.swarm.dial(addr)?;
         Ok(())
     }
 
+    pub fn members(&self) -> Vec<Member> {
+        self.membership.members()
+    }
+
     pub fn get_connected_peers(&self) -> Vec<String> {
-        self.peers.keys().cloned().collect()
+        self.swarm
+            .connected_peers()
+            .map(PeerId::to_string)
+            .collect()
+    }
+}
+
+fn is_loopback(addr: &str) -> bool {
+    addr.parse::<Multiaddr>().is_ok_and(|addr| {
+        addr.iter()
+            .any(|protocol| matches!(protocol, Protocol::Ip4(ip) if ip.is_loopback()))
+    })
+}
+
+fn is_relayed(addr: &Multiaddr) -> bool {
+    addr.iter()
+        .any(|protocol| matches!(protocol, Protocol::P2pCircuit))
+}
+
+fn is_quic(addr: &Multiaddr) -> bool {
+    addr.iter()
+        .any(|protocol| matches!(protocol, Protocol::QuicV1))
+}
+
+// Noise-encrypted, yamux-multiplexed TCP
+fn tcp_transport(keypair: &identity::Keypair) -> Result<Boxed<(PeerId, StreamMuxerBox)>> {
+    Ok(
+        libp2p_tcp::tokio::Transport::new(libp2p_tcp::Config::default())
+            .upgrade(upgrade::Version::V1)
+            .authenticate(noise::Config::new(keypair)?)
+            .multiplex(yamux::Config::default())
+            .map(|(peer, muxer), _| (peer, StreamMuxerBox::new(muxer)))
+            .boxed(),
+    )
+}
+
+// QUIC brings its own TLS encryption and stream multiplexing, and sets up
+// a connection in one round trip
+fn quic_transport(keypair: &identity::Keypair) -> Boxed<(PeerId, StreamMuxerBox)> {
+    libp2p_quic::tokio::Transport::new(libp2p_quic::Config::new(keypair))
+        .map(|(peer, connection), _| (peer, StreamMuxerBox::new(connection)))
+        .boxed()
+}
+
+// The node's identity is kept in `path`, so its peer id survives restarts
+fn load_or_create_keypair(path: &Path) -> Result<identity::Keypair> {
+    if path.exists() {
+        let bytes = std::fs::read(path)?;
+        return identity::Keypair::from_protobuf_encoding(&bytes)
+            .map_err(|e| anyhow!("Invalid node key in {}: {}", path.display(), e));
+    }
+    let keypair = identity::Keypair::generate_ed25519();
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
     }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    std::io::Write::write_all(&mut options.open(path)?, &keypair.to_protobuf_encoding()?)?;
+    info!("Created node key {}", path.display());
+    Ok(keypair)
+}
+
+fn parse_peers(ids: &[String]) -> Result<Vec<PeerId>> {
+    ids.iter()
+        .map(|id| {
+            id.parse()
+                .map_err(|e| anyhow!("Invalid peer id '{}': {}", id, e))
+        })
+        .collect()
 }
@@ -0,0 +1,174 @@
+// Which members hold a collection. Rendezvous hashing: every member gets a
+// score per collection and the highest ones hold it, so members joining or
+// failing only move the collections they win or held.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::membership::{Member, MemberStatus};
+
+// How many members hold a collection, and which may
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlacementPolicy {
+    pub replication_factor: usize,
+    // Labels a member needs, all of them, to hold the collection
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+}
+
+impl Default for PlacementPolicy {
+    fn default() -> Self {
+        Self {
+            replication_factor: 1,
+            labels: BTreeMap::new(),
+        }
+    }
+}
+
+impl PlacementPolicy {
+    pub fn admits(&self, member: &Member) -> bool {
+        self.labels
+            .iter()
+            .all(|(key, value)| member.labels.get(key) == Some(value))
+    }
+}
+
+// FNV-1a, so every node and build scores the same
+fn fnv1a(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    bytes.into_iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+// splitmix64's finaliser, to spread scores of similar inputs apart
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+// Members are scored by peer id, which stays the same across restarts
+// and address changes as long as the node keeps its key
+fn score(member: &Member, collection: &str) -> u64 {
+    let bytes = member
+        .id
+        .bytes()
+        .chain(std::iter::once(0xff))
+        .chain(collection.bytes());
+    mix(fnv1a(bytes))
+}
+
+// The live shard owners holding `collection` under `policy`, up to its
+// replication factor. The first one owns it and takes its writes.
+pub fn replicas<'a>(
+    members: &'a [Member],
+    collection: &str,
+    policy: &PlacementPolicy,
+) -> Vec<&'a Member> {
+    let mut eligible: Vec<&Member> = members
+        .iter()
+        .filter(|member| member.shard_owner && member.status != MemberStatus::Dead)
+        .filter(|member| policy.admits(member))
+        .collect();
+    eligible
+        .sort_by_cached_key(|member| std::cmp::Reverse((score(member, collection), &member.id)));
+    eligible.truncate(policy.replication_factor.max(1));
+    eligible
+}
+
+// The live shard owner that owns `collection`, None when there isn't one
+pub fn owner<'a>(
+    members: &'a [Member],
+    collection: &str,
+    policy: &PlacementPolicy,
+) -> Option<&'a Member> {
+    replicas(members, collection, policy).into_iter().next()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn member(port: u16, shard_owner: bool) -> Member {
+        Member {
+            id: format!("peer-{}", port),
+            addr: format!("/ip4/10.0.0.1/tcp/{}", port),
+            status: MemberStatus::Alive,
+            incarnation: 0,
+            last_seen: None,
+            shard_owner,
+            labels: BTreeMap::from([("zone".to_string(), format!("z{}", port % 2))]),
+        }
+    }
+
+    #[test]
+    fn test_owners_move_only_when_needed() {
+        let mut members: Vec<Member> = (1..=3).map(|port| member(port, true)).collect();
+        members.push(member(4, false));
+        let collections: Vec<String> = (0..300).map(|i| format!("collection-{}", i)).collect();
+        let owners: Vec<String> = collections
+            .iter()
+            .map(|name| {
+                owner(&members, name, &PlacementPolicy::default())
+                    .unwrap()
+                    .addr
+                    .clone()
+            })
+            .collect();
+        // Spread over every shard owner, and never the node that isn't one
+        for port in 1..=3 {
+            let addr = format!("/ip4/10.0.0.1/tcp/{}", port);
+            assert!(owners.iter().filter(|owner| **owner == addr).count() > 50);
+        }
+        assert!(!owners.iter().any(|owner| owner.ends_with("/4")));
+
+        // A member failing hands over only the collections it owned
+        members[0].status = MemberStatus::Dead;
+        for (name, before) in collections.iter().zip(&owners) {
+            let after = &owner(&members, name, &PlacementPolicy::default())
+                .unwrap()
+                .addr;
+            if *before != members[0].addr {
+                assert_eq!(after, before);
+            } else {
+                assert_ne!(after, before);
+            }
+        }
+        members
+            .iter_mut()
+            .for_each(|member| member.shard_owner = false);
+        assert!(owner(&members, "collection-0", &PlacementPolicy::default()).is_none());
+    }
+
+    #[test]
+    fn test_replicas_follow_the_policy() {
+        let members: Vec<Member> = (1..=5).map(|port| member(port, true)).collect();
+        let policy = |replication_factor: usize, zone: Option<&str>| PlacementPolicy {
+            replication_factor,
+            labels: zone
+                .map(|zone| BTreeMap::from([("zone".to_string(), zone.to_string())]))
+                .unwrap_or_default(),
+        };
+        for i in 0..50 {
+            let name = format!("collection-{}", i);
+            let three = replicas(&members, &name, &policy(3, None));
+            assert_eq!(three.len(), 3);
+            // Growing the factor adds replicas without moving the owner
+            assert_eq!(
+                three[0].id,
+                owner(&members, &name, &PlacementPolicy::default())
+                    .unwrap()
+                    .id
+            );
+            assert_eq!(
+                replicas(&members, &name, &policy(2, None)),
+                three[..2].to_vec()
+            );
+
+            let zoned = replicas(&members, &name, &policy(3, Some("z0")));
+            assert_eq!(zoned.len(), 2);
+            assert!(zoned.iter().all(|member| member.labels["zone"] == "z0"));
+        }
+        assert!(replicas(&members, "a", &policy(1, Some("z9"))).is_empty());
+    }
+}
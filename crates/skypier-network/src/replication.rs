@@ -1,29 +1,55 @@
-// Placeholder for data replication logic
-// This would handle replicating vector data across the network
+// The request-response protocol followers use to copy a primary's data
 
-use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use skypier_core::{ChangeSet, Vector};
 
-pub struct ReplicationManager {
-    // Implementation details would go here
+use crate::transfer::{TransferRequest, TransferResponse};
+
+pub const REPLICATION_PROTOCOL: &str = "/skypier/replication/1";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ReplicationRequest {
+    // Names of the namespaces the primary serves
+    Namespaces,
+    // Up to `limit` vectors of `namespace` in id order after `after`, for a
+    // full sync
+    Snapshot {
+        namespace: String,
+        after: Option<String>,
+        limit: usize,
+    },
+    // What changed in `namespace` after WAL seq `since`
+    Changes {
+        namespace: String,
+        since: u64,
+        limit: usize,
+    },
 }
 
-impl ReplicationManager {
-    pub fn new() -> Self {
-        Self {}
-    }
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ReplicationResponse {
+    Namespaces(Vec<String>),
+    // `seq` is the primary's WAL head when the page was read. `next` is the
+    // id to ask for the following page after, None on the last one.
+    Snapshot {
+        seq: u64,
+        vectors: Vec<Vector>,
+        next: Option<String>,
+    },
+    // None when the primary can't resume from `since`, e.g. the changes
+    // after it were truncated; the follower then has to sync in full again
+    Changes(Option<ChangeSet>),
+    Error(String),
+}
 
-    pub async fn replicate_vector(&self, vector_id: &str, data: &[u8]) -> Result<()> {
-        // Placeholder implementation
-        // In a real implementation, this would:
-        // 1. Determine replica nodes
-        // 2. Send data to replica nodes
-        // 3. Wait for acknowledgments
-        // 4. Handle failures
-        Ok(())
-    }
+// Answers replication requests from other nodes
+#[async_trait]
+pub trait RequestHandler: Send + Sync {
+    async fn handle(&self, request: ReplicationRequest) -> ReplicationResponse;
 
-    pub async fn sync_with_peers(&self) -> Result<()> {
-        // Placeholder for syncing data with other nodes
-        Ok(())
+    // Answers a bulk copy over the transfer protocol
+    async fn transfer(&self, _request: TransferRequest) -> TransferResponse {
+        TransferResponse::failed("This node doesn't serve transfers")
     }
 }
@@ -0,0 +1,293 @@
+// The protocol followers copy a namespace in bulk over. A request asks for
+// the vectors after a cursor, and the answer streams back as
+// length-prefixed batches, each with a checksum. A stream that breaks off
+// or carries a corrupt batch still yields the batches before it, so the
+// transfer resumes after the last vector that arrived whole.
+
+use async_trait::async_trait;
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use libp2p::request_response;
+use libp2p::StreamProtocol;
+use serde::{Deserialize, Serialize};
+use skypier_core::Vector;
+use std::io;
+
+use crate::codec::{self, MAX_MESSAGE_SIZE};
+
+pub const TRANSFER_PROTOCOL: &str = "/skypier/transfer/1";
+
+// Frame header: the payload's length, then its BLAKE3 hash
+const LENGTH_BYTES: usize = 4;
+const CHECKSUM_BYTES: usize = 32;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferRequest {
+    pub namespace: String,
+    // Id to start after, None for the first vector
+    pub after: Option<String>,
+    // Vectors per batch, and batches in one answer
+    pub batch_size: usize,
+    pub batches: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TransferResponse {
+    // The source's WAL head when it started reading
+    pub seq: u64,
+    // In id order
+    pub batches: Vec<Vec<Vector>>,
+    // Id to ask for the next answer after, None once every vector was sent.
+    // Only meaningful without an error.
+    pub next: Option<String>,
+    // Set when the source failed, or the stream broke off or had a corrupt
+    // batch. The batches before it are whole.
+    pub error: Option<String>,
+}
+
+impl TransferResponse {
+    pub fn failed(error: impl Into<String>) -> Self {
+        Self {
+            error: Some(error.into()),
+            ..Default::default()
+        }
+    }
+
+    // Id of the last vector that arrived, to resume an interrupted
+    // transfer after
+    pub fn last_id(&self) -> Option<&str> {
+        self.batches
+            .iter()
+            .rev()
+            .find_map(|batch| batch.last())
+            .map(|vector| vector.id.as_str())
+    }
+}
+
+// What goes over the stream: a start frame, the batches, then an end frame
+#[derive(Serialize, Deserialize)]
+enum Frame {
+    Start {
+        seq: u64,
+    },
+    Batch(Vec<Vector>),
+    End {
+        next: Option<String>,
+        error: Option<String>,
+    },
+}
+
+async fn read_frame<T>(io: &mut T) -> io::Result<Frame>
+where
+    T: AsyncRead + Unpin + Send,
+{
+    let mut header = [0; LENGTH_BYTES + CHECKSUM_BYTES];
+    io.read_exact(&mut header).await?;
+    let (length, checksum) = header.split_at(LENGTH_BYTES);
+    let length = u32::from_be_bytes(length.try_into().unwrap()) as u64;
+    if length > MAX_MESSAGE_SIZE {
+        return Err(invalid(format!("A frame of {} bytes is too large", length)));
+    }
+    let mut payload = vec![0; length as usize];
+    io.read_exact(&mut payload).await?;
+    if blake3::hash(&payload).as_bytes() != checksum {
+        return Err(invalid("A batch doesn't match its checksum".to_string()));
+    }
+    Ok(serde_json::from_slice(&payload)?)
+}
+
+async fn write_frame<T>(io: &mut T, frame: &Frame) -> io::Result<()>
+where
+    T: AsyncWrite + Unpin + Send,
+{
+    let payload = serde_json::to_vec(frame)?;
+    if payload.len() as u64 > MAX_MESSAGE_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("A batch of {} bytes is too large to send", payload.len()),
+        ));
+    }
+    io.write_all(&(payload.len() as u32).to_be_bytes()).await?;
+    io.write_all(blake3::hash(&payload).as_bytes()).await?;
+    io.write_all(&payload).await
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// JSON requests, framed batches back
+#[derive(Debug, Clone, Default)]
+pub struct TransferCodec;
+
+#[async_trait]
+impl request_response::Codec for TransferCodec {
+    type Protocol = StreamProtocol;
+    type Request = TransferRequest;
+    type Response = TransferResponse;
+
+    async fn read_request<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+    ) -> io::Result<TransferRequest>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        codec::read(io).await
+    }
+
+    // Nothing arriving before the start frame fails the request; after it,
+    // the batches read so far are kept
+    async fn read_response<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+    ) -> io::Result<TransferResponse>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let Frame::Start { seq } = read_frame(io).await? else {
+            return Err(invalid(
+                "The transfer didn't start with a start frame".to_string(),
+            ));
+        };
+        let mut response = TransferResponse {
+            seq,
+            ..Default::default()
+        };
+        loop {
+            match read_frame(io).await {
+                Ok(Frame::Batch(vectors)) => response.batches.push(vectors),
+                Ok(Frame::End { next, error }) => {
+                    response.next = next;
+                    response.error = error;
+                    return Ok(response);
+                }
+                Ok(Frame::Start { .. }) => {
+                    response.error = Some("The transfer started twice".to_string());
+                    return Ok(response);
+                }
+                Err(e) => {
+                    response.error = Some(format!("The transfer broke off: {}", e));
+                    return Ok(response);
+                }
+            }
+        }
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+        request: TransferRequest,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        codec::write(io, request).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+        response: TransferResponse,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_frame(io, &Frame::Start { seq: response.seq }).await?;
+        for batch in response.batches {
+            write_frame(io, &Frame::Batch(batch)).await?;
+        }
+        let end = Frame::End {
+            next: response.next,
+            error: response.error,
+        };
+        write_frame(io, &end).await
+    }
+}
+
+pub type Behaviour = request_response::Behaviour<TransferCodec>;
+
+pub fn behaviour(config: request_response::Config) -> Behaviour {
+    request_response::Behaviour::with_codec(
+        TransferCodec,
+        [(
+            StreamProtocol::new(TRANSFER_PROTOCOL),
+            request_response::ProtocolSupport::Full,
+        )],
+        config,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::io::Cursor;
+    use request_response::Codec;
+
+    fn response() -> TransferResponse {
+        let batch = |ids: &[&str]| {
+            ids.iter()
+                .map(|id| Vector::with_id(id.to_string(), vec![1.0, 0.0]))
+                .collect()
+        };
+        TransferResponse {
+            seq: 7,
+            batches: vec![batch(&["a", "b"]), batch(&["c", "d"])],
+            next: Some("d".to_string()),
+            error: None,
+        }
+    }
+
+    async fn encode(response: TransferResponse) -> Vec<u8> {
+        let protocol = StreamProtocol::new(TRANSFER_PROTOCOL);
+        let mut stream = Cursor::new(Vec::new());
+        TransferCodec
+            .write_response(&protocol, &mut stream, response)
+            .await
+            .unwrap();
+        stream.into_inner()
+    }
+
+    async fn decode(bytes: Vec<u8>) -> io::Result<TransferResponse> {
+        let protocol = StreamProtocol::new(TRANSFER_PROTOCOL);
+        TransferCodec
+            .read_response(&protocol, &mut Cursor::new(bytes))
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_transfer_round_trip() {
+        let decoded = decode(encode(response()).await).await.unwrap();
+        assert_eq!(decoded.seq, 7);
+        assert_eq!(decoded.batches.len(), 2);
+        assert_eq!(decoded.next.as_deref(), Some("d"));
+        assert!(decoded.error.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_interrupted_transfer_keeps_whole_batches() {
+        let bytes = encode(response()).await;
+        // Where the second batch starts, after the start frame and the first
+        let mut second = 0;
+        for _ in 0..2 {
+            let length =
+                u32::from_be_bytes(bytes[second..second + LENGTH_BYTES].try_into().unwrap());
+            second += LENGTH_BYTES + CHECKSUM_BYTES + length as usize;
+        }
+        let cut_off = bytes[..second + 10].to_vec();
+        let mut corrupt = bytes.clone();
+        corrupt[second + 50] ^= 1;
+        for broken in [cut_off, corrupt] {
+            let decoded = decode(broken).await.unwrap();
+            assert_eq!(decoded.batches.len(), 1);
+            assert_eq!(decoded.last_id(), Some("b"));
+            assert!(decoded.error.is_some());
+        }
+
+        // Nothing arrived at all
+        assert!(decode(bytes[..10].to_vec()).await.is_err());
+    }
+}
@@ -0,0 +1,74 @@
+use anyhow::{anyhow, Result};
+use half::{bf16, f16};
+use serde::{Deserialize, Serialize};
+
+// Precision a vector's `data` is stored at. The half-precision types take
+// half the space of f32: f16 keeps more of the mantissa, bf16 all of f32's
+// range.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Dtype {
+    #[default]
+    F32,
+    F16,
+    Bf16,
+}
+
+impl Dtype {
+    pub fn is_f32(&self) -> bool {
+        *self == Dtype::F32
+    }
+
+    pub fn bytes_per_value(&self) -> usize {
+        match self {
+            Dtype::F32 => 4,
+            Dtype::F16 | Dtype::Bf16 => 2,
+        }
+    }
+
+    // Rounds values to what they'll read back as once stored. Values beyond
+    // f16's range become infinite.
+    pub fn round(&self, data: &mut [f32]) {
+        match self {
+            Dtype::F32 => {}
+            Dtype::F16 => data
+                .iter_mut()
+                .for_each(|x| *x = f16::from_f32(*x).to_f32()),
+            Dtype::Bf16 => data
+                .iter_mut()
+                .for_each(|x| *x = bf16::from_f32(*x).to_f32()),
+        }
+    }
+
+    // Little-endian values at this precision
+    pub fn encode(&self, data: &[f32]) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(data.len() * self.bytes_per_value());
+        for &x in data {
+            match self {
+                Dtype::F32 => bytes.extend_from_slice(&x.to_le_bytes()),
+                Dtype::F16 => bytes.extend_from_slice(&f16::from_f32(x).to_le_bytes()),
+                Dtype::Bf16 => bytes.extend_from_slice(&bf16::from_f32(x).to_le_bytes()),
+            }
+        }
+        bytes
+    }
+
+    pub fn decode(&self, bytes: &[u8]) -> Result<Vec<f32>> {
+        let width = self.bytes_per_value();
+        if !bytes.len().is_multiple_of(width) {
+            return Err(anyhow!(
+                "{} bytes don't hold a whole number of {:?} values",
+                bytes.len(),
+                self
+            ));
+        }
+        Ok(bytes
+            .chunks_exact(width)
+            .map(|chunk| match self {
+                Dtype::F32 => f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]),
+                Dtype::F16 => f16::from_le_bytes([chunk[0], chunk[1]]).to_f32(),
+                Dtype::Bf16 => bf16::from_le_bytes([chunk[0], chunk[1]]).to_f32(),
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,195 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::Aes256Gcm;
+use anyhow::{anyhow, Result};
+use chacha20poly1305::XChaCha20Poly1305;
+use serde::{Deserialize, Serialize};
+
+// Encrypted records start with this instead of a format byte, then the
+// cipher, the key's id and the nonce. The ciphertext holds the record as it
+// would be stored unencrypted, and the header is authenticated with it.
+pub(crate) const FORMAT_ENCRYPTED: u8 = 0x40;
+const KEY_ID_LEN: usize = 4;
+pub const KEY_LEN: usize = 32;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Cipher {
+    #[default]
+    #[serde(rename = "aes-256-gcm")]
+    Aes256Gcm,
+    #[serde(rename = "xchacha20-poly1305")]
+    XChaCha20Poly1305,
+}
+
+impl Cipher {
+    fn id(self) -> u8 {
+        match self {
+            Cipher::Aes256Gcm => 1,
+            Cipher::XChaCha20Poly1305 => 2,
+        }
+    }
+
+    fn from_id(id: u8) -> Result<Self> {
+        match id {
+            1 => Ok(Cipher::Aes256Gcm),
+            2 => Ok(Cipher::XChaCha20Poly1305),
+            other => Err(anyhow!("Unknown record cipher {}", other)),
+        }
+    }
+
+    fn nonce_len(self) -> usize {
+        match self {
+            Cipher::Aes256Gcm => 12,
+            Cipher::XChaCha20Poly1305 => 24,
+        }
+    }
+}
+
+struct Key {
+    // The first bytes of the key's BLAKE3 hash, to find it again by
+    id: [u8; KEY_ID_LEN],
+    bytes: [u8; KEY_LEN],
+}
+
+impl Key {
+    fn new(bytes: [u8; KEY_LEN]) -> Self {
+        let mut id = [0; KEY_ID_LEN];
+        id.copy_from_slice(&blake3::hash(&bytes).as_bytes()[..KEY_ID_LEN]);
+        Self { id, bytes }
+    }
+}
+
+// The key records are encrypted with, and the retired ones still read
+pub struct EncryptionKeys {
+    cipher: Cipher,
+    current: Key,
+    previous: Vec<Key>,
+}
+
+impl EncryptionKeys {
+    pub fn new(cipher: Cipher, key: [u8; KEY_LEN]) -> Self {
+        Self {
+            cipher,
+            current: Key::new(key),
+            previous: Vec::new(),
+        }
+    }
+
+    // A retired key, for records that compaction hasn't re-encrypted yet
+    pub fn with_previous(mut self, key: [u8; KEY_LEN]) -> Self {
+        self.previous.push(Key::new(key));
+        self
+    }
+
+    // A key written as 64 hex digits
+    pub fn parse_key(hex_key: &str) -> Result<[u8; KEY_LEN]> {
+        let bytes = hex::decode(hex_key.trim())
+            .map_err(|e| anyhow!("Encryption key isn't valid hex: {}", e))?;
+        bytes.try_into().map_err(|bytes: Vec<u8>| {
+            anyhow!("Encryption keys are {} bytes, got {}", KEY_LEN, bytes.len())
+        })
+    }
+
+    pub(crate) fn encrypt(&self, record: &[u8]) -> Result<Vec<u8>> {
+        let nonce = match self.cipher {
+            Cipher::Aes256Gcm => Aes256Gcm::generate_nonce(&mut OsRng).to_vec(),
+            Cipher::XChaCha20Poly1305 => XChaCha20Poly1305::generate_nonce(&mut OsRng).to_vec(),
+        };
+        let mut sealed = vec![FORMAT_ENCRYPTED, self.cipher.id()];
+        sealed.extend_from_slice(&self.current.id);
+        sealed.extend_from_slice(&nonce);
+        let payload = Payload {
+            msg: record,
+            aad: &sealed,
+        };
+        let key = &self.current.bytes;
+        let ciphertext = match self.cipher {
+            Cipher::Aes256Gcm => {
+                Aes256Gcm::new(key.into()).encrypt(nonce.as_slice().into(), payload)
+            }
+            Cipher::XChaCha20Poly1305 => {
+                XChaCha20Poly1305::new(key.into()).encrypt(nonce.as_slice().into(), payload)
+            }
+        }
+        .map_err(|_| anyhow!("Failed to encrypt a vector record"))?;
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    pub(crate) fn decrypt(&self, sealed: &[u8]) -> Result<Vec<u8>> {
+        let (cipher, key_id, nonce_len) = self.header(sealed)?;
+        let key = std::iter::once(&self.current)
+            .chain(&self.previous)
+            .find(|key| key.id == key_id)
+            .ok_or_else(|| {
+                anyhow!("Vector record is encrypted with a key that isn't configured")
+            })?;
+        let (header, ciphertext) = sealed.split_at(2 + KEY_ID_LEN + nonce_len);
+        let nonce = &header[2 + KEY_ID_LEN..];
+        let payload = Payload {
+            msg: ciphertext,
+            aad: header,
+        };
+        match cipher {
+            Cipher::Aes256Gcm => Aes256Gcm::new((&key.bytes).into()).decrypt(nonce.into(), payload),
+            Cipher::XChaCha20Poly1305 => {
+                XChaCha20Poly1305::new((&key.bytes).into()).decrypt(nonce.into(), payload)
+            }
+        }
+        .map_err(|_| anyhow!("Vector record failed to decrypt"))
+    }
+
+    // Whether a record is encrypted the way new ones are, so compaction can
+    // leave it be
+    #[cfg(any(feature = "redb", test))]
+    pub(crate) fn is_current(&self, record: &[u8]) -> bool {
+        match self.header(record) {
+            Ok((cipher, key_id, _)) => cipher == self.cipher && key_id == self.current.id,
+            Err(_) => false,
+        }
+    }
+
+    fn header(&self, sealed: &[u8]) -> Result<(Cipher, [u8; KEY_ID_LEN], usize)> {
+        let [FORMAT_ENCRYPTED, cipher, rest @ ..] = sealed else {
+            return Err(anyhow!("Vector record isn't encrypted"));
+        };
+        let cipher = Cipher::from_id(*cipher)?;
+        let (key_id, rest) = rest
+            .split_first_chunk::<KEY_ID_LEN>()
+            .ok_or_else(|| anyhow!("Encrypted vector record is truncated"))?;
+        if rest.len() < cipher.nonce_len() {
+            return Err(anyhow!("Encrypted vector record is truncated"));
+        }
+        Ok((cipher, *key_id, cipher.nonce_len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys_and_rotation() {
+        let (old, new) = ([1; KEY_LEN], [2; KEY_LEN]);
+        for cipher in [Cipher::Aes256Gcm, Cipher::XChaCha20Poly1305] {
+            let before = EncryptionKeys::new(cipher, old);
+            let sealed = before.encrypt(b"record").unwrap();
+            assert_eq!(before.decrypt(&sealed).unwrap(), b"record");
+            assert!(before.is_current(&sealed));
+
+            // Retired keys still read, but aren't current
+            let after = EncryptionKeys::new(cipher, new).with_previous(old);
+            assert_eq!(after.decrypt(&sealed).unwrap(), b"record");
+            assert!(!after.is_current(&sealed));
+            assert!(EncryptionKeys::new(cipher, new).decrypt(&sealed).is_err());
+
+            let mut tampered = sealed.clone();
+            let last = tampered.len() - 1;
+            tampered[last] ^= 1;
+            assert!(before.decrypt(&tampered).is_err());
+        }
+
+        assert!(EncryptionKeys::parse_key(&"ab".repeat(KEY_LEN)).is_ok());
+        assert!(EncryptionKeys::parse_key("abcd").is_err());
+        assert!(EncryptionKeys::parse_key("not hex").is_err());
+    }
+}
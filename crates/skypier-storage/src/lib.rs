@@ -1,18 +1,90 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
+pub mod dtype;
+#[cfg(feature = "records")]
+pub mod encryption;
+pub mod memory;
+#[cfg(feature = "records")]
+mod record;
+#[cfg(feature = "redb")]
 pub mod redb_storage;
+#[cfg(feature = "sled")]
+pub mod sled_storage;
+pub mod time;
 
+pub use dtype::Dtype;
+#[cfg(feature = "records")]
+pub use encryption::{Cipher, EncryptionKeys};
+pub use memory::InMemoryStorage;
+#[cfg(feature = "redb")]
 pub use redb_storage::RedbStorage;
+#[cfg(feature = "sled")]
+pub use sled_storage::SledStorage;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Vector {
+    // Left out or empty, the database makes one up on insert
+    #[serde(default)]
     pub id: String,
     pub data: Vec<f32>,
     pub metadata: Option<HashMap<String, String>>,
     pub collection: Option<String>,
     pub created_at: u64,
+    // Named embeddings besides `data`, e.g. "title" and "body", each
+    // searched through an index of its own
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub vectors: HashMap<String, Vec<f32>>,
+    // Term weights from a sparse model such as SPLADE or BM25
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sparse: Option<SparseVector>,
+    // Precision `data` is stored at, set from the collection's config
+    #[serde(default, skip_serializing_if = "Dtype::is_f32")]
+    pub dtype: Dtype,
+    // Counts up from 1 each time the id is written. 0 for records from
+    // before versions were counted.
+    #[serde(default)]
+    pub version: u64,
+    // Name of the embedding model that produced the vector, e.g.
+    // "text-embedding-3-small"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    // The writer's ed25519 signature over the record, checked on every node
+    // that stores it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<RecordSignature>,
+    // Hybrid logical clock timestamp of the write, and the replica that
+    // made it. Set by the database; replicas use them to settle concurrent
+    // writes.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub hlc: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    // Writes of the id seen by each replica, this one included
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub clock: BTreeMap<String, u64>,
+}
+
+fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordSignature {
+    // Hex-encoded ed25519 public key of the writer
+    pub public_key: String,
+    // Hex-encoded signature over the record's signing payload
+    pub signature: String,
+    // When the writer signed it, in unix seconds
+    pub signed_at: u64,
+}
+
+// Only the non-zero dimensions of a vector, as parallel lists
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct SparseVector {
+    pub indices: Vec<u32>,
+    pub values: Vec<f32>,
 }
 
 impl Vector {
@@ -22,10 +94,19 @@ impl Vector {
             data,
             metadata: None,
             collection: None,
-            created_at: std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
+            created_at: time::SystemTime::now()
+                .duration_since(time::UNIX_EPOCH)
                 .unwrap()
                 .as_secs(),
+            vectors: HashMap::new(),
+            sparse: None,
+            dtype: Dtype::F32,
+            version: 0,
+            model: None,
+            signature: None,
+            hlc: 0,
+            origin: None,
+            clock: BTreeMap::new(),
         }
     }
 
@@ -35,10 +116,19 @@ impl Vector {
             data,
             metadata: None,
             collection: None,
-            created_at: std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
+            created_at: time::SystemTime::now()
+                .duration_since(time::UNIX_EPOCH)
                 .unwrap()
                 .as_secs(),
+            vectors: HashMap::new(),
+            sparse: None,
+            dtype: Dtype::F32,
+            version: 0,
+            model: None,
+            signature: None,
+            hlc: 0,
+            origin: None,
+            clock: BTreeMap::new(),
         }
     }
 
@@ -52,6 +142,21 @@ impl Vector {
         self
     }
 
+    pub fn with_named(mut self, name: &str, data: Vec<f32>) -> Self {
+        self.vectors.insert(name.to_string(), data);
+        self
+    }
+
+    pub fn with_sparse(mut self, sparse: SparseVector) -> Self {
+        self.sparse = Some(sparse);
+        self
+    }
+
+    pub fn with_model(mut self, model: String) -> Self {
+        self.model = Some(model);
+        self
+    }
+
     pub fn dimensions(&self) -> usize {
         self.data.len()
     }
@@ -71,16 +176,470 @@ impl Vector {
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotInfo {
+    pub name: String,
+    pub collection: String,
+    pub vector_count: usize,
+    pub created_at: u64,
+}
+
+// Summed size of the stored vector records, before and after compression
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VectorBytes {
+    pub raw: u64,
+    pub stored: u64,
+}
+
+// Per-collection figures, kept up to date by every write so reading them
+// doesn't scan the collection
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CollectionStats {
+    pub vector_count: u64,
+    // Of the most recently written vector
+    pub dimensions: usize,
+    // Size of the stored records, after compression
+    pub stored_bytes: u64,
+    // Unix seconds of the last write or delete
+    pub last_modified: u64,
+}
+
+impl CollectionStats {
+    pub(crate) fn added(&mut self, vector: &Vector, record_len: usize, now: u64) {
+        self.vector_count += 1;
+        self.dimensions = vector.dimensions();
+        self.stored_bytes += record_len as u64;
+        self.last_modified = now;
+    }
+
+    pub(crate) fn removed(&mut self, record_len: usize, now: u64) {
+        self.vector_count = self.vector_count.saturating_sub(1);
+        self.stored_bytes = self.stored_bytes.saturating_sub(record_len as u64);
+        self.last_modified = now;
+    }
+}
+
+// A stored vector record that can't be read back
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CorruptRecord {
+    pub id: String,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VerifyReport {
+    // Records read
+    pub checked: u64,
+    pub corrupt: Vec<CorruptRecord>,
+}
+
+#[cfg(feature = "records")]
+pub(crate) fn setting_key(key: &str) -> String {
+    format!("setting:{}", key)
+}
+
+pub(crate) fn unix_now() -> u64 {
+    time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+// Every vector write and delete is appended to a write-ahead log, so the
+// in-memory index can be brought up to date from an older snapshot.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WalOp {
+    Upsert,
+    Delete,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WalEntry {
+    pub seq: u64,
+    pub id: String,
+    pub op: WalOp,
+    // Of the vector written or deleted. Entries logged before these were
+    // recorded have neither.
+    #[serde(default)]
+    pub collection: Option<String>,
+    #[serde(default)]
+    pub timestamp: u64,
+    // Whether an upsert overwrote an existing vector
+    #[serde(default)]
+    pub replaced: bool,
+    // Hybrid logical clock timestamp of the write, 0 for entries logged
+    // before writes were stamped
+    #[serde(default)]
+    pub hlc: u64,
+}
+
+impl WalEntry {
+    pub(crate) fn new(
+        seq: u64,
+        id: &str,
+        op: WalOp,
+        collection: Option<&str>,
+        replaced: bool,
+        hlc: u64,
+    ) -> Self {
+        Self {
+            seq,
+            id: id.to_string(),
+            op,
+            collection: collection.map(str::to_string),
+            timestamp: unix_now(),
+            replaced,
+            hlc,
+        }
+    }
+}
+
+// A write or admin action made through the API. Storage numbers and
+// timestamps each as it's appended to the audit log.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub seq: u64,
+    pub timestamp: u64,
+    pub namespace: String,
+    // The route taken, e.g. "DELETE /vectors/:id"
+    pub action: String,
+    // The path as requested, naming what was acted on
+    pub path: String,
+    // Id of the API key used, when keys are required
+    pub key_id: Option<String>,
+    pub client_ip: Option<String>,
+    // The response status, so refused attempts are recorded too
+    pub status: u16,
+}
+
 #[async_trait::async_trait]
 pub trait Storage: Send + Sync {
+    // Whether data survives a restart. Index snapshots are only kept for
+    // storage that does, or they would outlive the vectors they describe.
+    fn persistent(&self) -> bool {
+        true
+    }
+
     async fn store_vector(&self, vector: &Vector) -> Result<()>;
+    // Writes many vectors in one transaction without logging them to the
+    // WAL. Only for offline loads that snapshot the index afterwards.
+    async fn bulk_load(&self, vectors: &[Vector]) -> Result<()>;
+    // Applies the upserts and then the deletes in one transaction, logging
+    // each to the WAL. Either all of it lands or none of it does.
+    async fn write_batch(
+        &self,
+        upserts: &[Vector],
+        deletes: &[String],
+        deleted_at: u64,
+    ) -> Result<()>;
     async fn get_vector(&self, id: &str) -> Result<Option<Vector>>;
-    async fn delete_vector(&self, id: &str) -> Result<bool>;
+    async fn delete_vector(&self, id: &str, deleted_at: u64) -> Result<bool>;
     async fn count_vectors(&self) -> Result<usize>;
+    // Of the most recently written vector, 0 when there are none
+    async fn dimensions(&self) -> Result<usize>;
     async fn size_bytes(&self) -> Result<usize>;
+    async fn vector_bytes(&self) -> Result<VectorBytes>;
+    // What the vector's record would add to `VectorBytes::stored` and the
+    // collection's `stored_bytes`, for checking byte quotas before a write
+    fn record_len(&self, vector: &Vector) -> Result<u64>;
+    // None if no stored vector is in the collection
+    async fn collection_stats(&self, collection: &str) -> Result<Option<CollectionStats>>;
     async fn compact(&self) -> Result<()>;
+    // Makes every completed write durable; called before shutdown
+    async fn flush(&self) -> Result<()>;
     async fn backup(&self, backup_path: &str) -> Result<()>;
     async fn list_collections(&self) -> Result<Vec<String>>;
     async fn get_vectors_in_collection(&self, collection: &str) -> Result<Vec<Vector>>;
+    // Up to `limit` vectors in id order, starting after the id `after`, from
+    // `collection` or every collection when None. Pages through large
+    // collections without loading them whole.
+    async fn scan_collection(
+        &self,
+        collection: Option<&str>,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<Vector>>;
+    // Ids of the vectors whose metadata has `key` set to `value`
+    async fn ids_with_metadata(&self, key: &str, value: &str) -> Result<Vec<String>> {
+        Ok(self
+            .list_vectors()
+            .await?
+            .into_iter()
+            .filter(|vector| {
+                vector
+                    .metadata
+                    .as_ref()
+                    .and_then(|metadata| metadata.get(key))
+                    .is_some_and(|found| found == value)
+            })
+            .map(|vector| vector.id)
+            .collect())
+    }
     async fn get_first_vector(&self) -> Result<Option<Vector>>;
+    async fn list_vectors(&self) -> Result<Vec<Vector>>;
+
+    // Reads every vector record back and reports those that fail to decode
+    // or don't match their checksum. Backends that keep vectors in memory
+    // have nothing that can rot.
+    async fn verify(&self) -> Result<VerifyReport> {
+        Ok(VerifyReport {
+            checked: self.count_vectors().await? as u64,
+            corrupt: Vec::new(),
+        })
+    }
+    // Replaces the record stored under the vector's id, corrupt or not,
+    // with a good copy, logging it to the WAL
+    async fn repair_vector(&self, vector: &Vector) -> Result<()> {
+        self.write_batch(std::slice::from_ref(vector), &[], 0).await
+    }
+
+    // Sequence number of the latest WAL entry, 0 if nothing was ever logged
+    async fn wal_head(&self) -> Result<u64>;
+    async fn wal_since(&self, seq: u64) -> Result<Vec<WalEntry>>;
+    // The first `limit` entries after `seq`
+    async fn wal_page(&self, seq: u64, limit: usize) -> Result<Vec<WalEntry>> {
+        let mut entries = self.wal_since(seq).await?;
+        entries.truncate(limit);
+        Ok(entries)
+    }
+    // Drops entries up to and including `seq`
+    async fn truncate_wal(&self, seq: u64) -> Result<()>;
+    // Keeps entries after `seq` through later truncations, so an incremental
+    // backup taken at `seq` can be followed by another one
+    async fn retain_wal_after(&self, seq: u64) -> Result<()>;
+
+    // The audit log, which only ever grows at the end. Returns the entry's
+    // seq, one past the last entry ever appended.
+    async fn append_audit(&self, entry: &AuditEntry) -> Result<u64>;
+    // The first `limit` entries after `seq`
+    async fn audit_page(&self, seq: u64, limit: usize) -> Result<Vec<AuditEntry>>;
+    // Drops entries appended before the unix time `before`, returning how
+    // many went
+    async fn prune_audit(&self, before: u64) -> Result<usize>;
+
+    // Earlier versions of a vector, newest first, for collections that
+    // keep them. Deleting the vector drops them too.
+    async fn get_versions(&self, id: &str) -> Result<Vec<Vector>>;
+    // Replaces the earlier versions kept for `id`; empty drops them
+    async fn put_versions(&self, id: &str, versions: &[Vector]) -> Result<()>;
+
+    // Small named values kept in the metadata table, apart from the keys
+    // storage uses itself
+    async fn get_setting(&self, key: &str) -> Result<Option<String>>;
+    async fn put_setting(&self, key: &str, value: &str) -> Result<()>;
+
+    // Snapshots are immutable, named copies of a collection's vectors
+    async fn create_snapshot(&self, collection: &str, name: &str) -> Result<SnapshotInfo>;
+    async fn get_snapshot(&self, collection: &str, name: &str) -> Result<Option<SnapshotInfo>>;
+    async fn list_snapshots(&self, collection: &str) -> Result<Vec<SnapshotInfo>>;
+    async fn get_snapshot_vectors(&self, collection: &str, name: &str) -> Result<Vec<Vector>>;
+    async fn delete_snapshot(&self, collection: &str, name: &str) -> Result<bool>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The behaviour every backend has to agree on
+    async fn check_backend(storage: &dyn Storage) {
+        let a = Vector::with_id("a".to_string(), vec![1.0, 0.0]).with_collection("docs".into());
+        let b = Vector::with_id("b".to_string(), vec![0.0, 1.0]);
+
+        storage.store_vector(&a).await.unwrap();
+        storage.store_vector(&b).await.unwrap();
+        assert_eq!(storage.count_vectors().await.unwrap(), 2);
+        assert_eq!(storage.dimensions().await.unwrap(), 2);
+        assert_eq!(storage.get_vector("a").await.unwrap().unwrap().data, a.data);
+        assert_eq!(storage.list_collections().await.unwrap(), vec!["docs"]);
+        assert!(storage.vector_bytes().await.unwrap().raw > 0);
+        let stats = storage.collection_stats("docs").await.unwrap().unwrap();
+        assert_eq!((stats.vector_count, stats.dimensions), (1, 2));
+        assert!(stats.stored_bytes > 0 && stats.last_modified > 0);
+
+        let info = storage.create_snapshot("docs", "s1").await.unwrap();
+        assert_eq!(info.vector_count, 1);
+        assert!(storage.create_snapshot("docs", "s1").await.is_err());
+
+        assert!(storage.delete_vector("a", 0).await.unwrap());
+        assert!(!storage.delete_vector("a", 0).await.unwrap());
+        assert!(storage.get_vector("a").await.unwrap().is_none());
+        assert_eq!(storage.count_vectors().await.unwrap(), 1);
+        assert!(storage.collection_stats("docs").await.unwrap().is_none());
+        let snapshot = storage.get_snapshot_vectors("docs", "s1").await.unwrap();
+        assert_eq!(snapshot.len(), 1);
+
+        assert_eq!(storage.wal_head().await.unwrap(), 3);
+        let ops: Vec<WalOp> = storage
+            .wal_since(1)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.op)
+            .collect();
+        assert_eq!(ops, vec![WalOp::Upsert, WalOp::Delete]);
+        storage.truncate_wal(2).await.unwrap();
+        assert_eq!(storage.wal_since(0).await.unwrap().len(), 1);
+        assert_eq!(storage.wal_head().await.unwrap(), 3);
+
+        storage.retain_wal_after(2).await.unwrap();
+        storage.store_vector(&b).await.unwrap();
+        storage.truncate_wal(4).await.unwrap();
+        let retained: Vec<u64> = storage
+            .wal_since(0)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.seq)
+            .collect();
+        assert_eq!(retained, vec![3, 4]);
+
+        let entry = AuditEntry {
+            action: "DELETE /vectors/:id".to_string(),
+            path: "/vectors/a".to_string(),
+            status: 204,
+            ..Default::default()
+        };
+        assert_eq!(storage.append_audit(&entry).await.unwrap(), 1);
+        assert_eq!(storage.append_audit(&entry).await.unwrap(), 2);
+        let page = storage.audit_page(1, 10).await.unwrap();
+        assert_eq!((page.len(), page[0].seq), (1, 2));
+        assert!(page[0].timestamp > 0 && page[0].path == "/vectors/a");
+        assert_eq!(storage.prune_audit(0).await.unwrap(), 0);
+        assert_eq!(storage.prune_audit(u64::MAX).await.unwrap(), 2);
+        assert!(storage.audit_page(0, 10).await.unwrap().is_empty());
+        // Numbering carries on past pruned entries
+        assert_eq!(storage.append_audit(&entry).await.unwrap(), 3);
+
+        assert!(storage.delete_snapshot("docs", "s1").await.unwrap());
+        assert!(storage.list_snapshots("docs").await.unwrap().is_empty());
+
+        let mut c =
+            Vector::with_id("c".to_string(), vec![0.5, 0.5, 0.5]).with_collection("docs".into());
+        c.hlc = 5;
+        storage
+            .write_batch(
+                &[a.clone(), c],
+                &["b".to_string(), "missing".to_string()],
+                6,
+            )
+            .await
+            .unwrap();
+        assert_eq!(storage.count_vectors().await.unwrap(), 2);
+        assert_eq!(storage.dimensions().await.unwrap(), 3);
+        assert!(storage.get_vector("b").await.unwrap().is_none());
+        let stats = storage.collection_stats("docs").await.unwrap().unwrap();
+        assert_eq!((stats.vector_count, stats.dimensions), (2, 3));
+        let ops: Vec<(String, WalOp, Option<String>, u64)> = storage
+            .wal_since(4)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.id, entry.op, entry.collection, entry.hlc))
+            .collect();
+        assert_eq!(
+            ops,
+            vec![
+                ("a".to_string(), WalOp::Upsert, Some("docs".to_string()), 0),
+                ("c".to_string(), WalOp::Upsert, Some("docs".to_string()), 5),
+                ("b".to_string(), WalOp::Delete, None, 6),
+            ]
+        );
+        let scanned = |vectors: Vec<Vector>| -> Vec<String> {
+            vectors.into_iter().map(|vector| vector.id).collect()
+        };
+        let page = storage.scan_collection(None, None, 1).await.unwrap();
+        assert_eq!(scanned(page), vec!["a"]);
+        let page = storage.scan_collection(None, Some("a"), 5).await.unwrap();
+        assert_eq!(scanned(page), vec!["c"]);
+        let page = storage
+            .scan_collection(Some("docs"), Some("0"), 5)
+            .await
+            .unwrap();
+        assert_eq!(scanned(page), vec!["a", "c"]);
+        assert!(storage
+            .scan_collection(Some("notes"), None, 5)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let page = storage.wal_page(3, 2).await.unwrap();
+        assert_eq!((page.len(), page[0].seq), (2, 4));
+        assert!(page[0].replaced && !page[1].replaced);
+        assert!(page[0].timestamp > 0);
+
+        let tagged = Vector::with_id("t".to_string(), vec![1.0, 1.0])
+            .with_metadata(HashMap::from([("lang".to_string(), "en".to_string())]));
+        storage.store_vector(&tagged).await.unwrap();
+        assert_eq!(
+            storage.ids_with_metadata("lang", "en").await.unwrap(),
+            vec!["t"]
+        );
+        assert!(storage
+            .ids_with_metadata("lang", "fr")
+            .await
+            .unwrap()
+            .is_empty());
+        storage
+            .write_batch(&[], &["t".to_string()], 0)
+            .await
+            .unwrap();
+        assert!(storage
+            .ids_with_metadata("lang", "en")
+            .await
+            .unwrap()
+            .is_empty());
+
+        let mut older = c_version(1);
+        older.dtype = Dtype::F16;
+        storage
+            .put_versions("c", &[c_version(2), older])
+            .await
+            .unwrap();
+        let versions = storage.get_versions("c").await.unwrap();
+        assert_eq!(
+            versions.iter().map(|v| v.version).collect::<Vec<_>>(),
+            [2, 1]
+        );
+        assert_eq!(versions[1].dtype, Dtype::F16);
+        assert!(storage.get_versions("a").await.unwrap().is_empty());
+        storage
+            .write_batch(&[], &["c".to_string()], 0)
+            .await
+            .unwrap();
+        assert!(storage.get_versions("c").await.unwrap().is_empty());
+        storage.put_versions("a", &[c_version(1)]).await.unwrap();
+        storage.delete_vector("a", 0).await.unwrap();
+        assert!(storage.get_versions("a").await.unwrap().is_empty());
+    }
+
+    fn c_version(version: u64) -> Vector {
+        Vector {
+            version,
+            ..Vector::with_id("c".to_string(), vec![0.25, 0.5, 0.75])
+        }
+    }
+
+    #[tokio::test]
+    async fn test_backends_agree() {
+        check_backend(&InMemoryStorage::new()).await;
+
+        #[cfg(feature = "redb")]
+        {
+            let temp_dir = tempfile::tempdir().unwrap();
+            let redb = RedbStorage::new(temp_dir.path().to_str().unwrap())
+                .await
+                .unwrap();
+            check_backend(&redb).await;
+        }
+
+        #[cfg(feature = "sled")]
+        {
+            let temp_dir = tempfile::tempdir().unwrap();
+            let sled = SledStorage::new(temp_dir.path().to_str().unwrap())
+                .await
+                .unwrap();
+            check_backend(&sled).await;
+        }
+    }
 }
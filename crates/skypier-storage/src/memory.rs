@@ -0,0 +1,421 @@
+use anyhow::{anyhow, Result};
+use std::collections::{BTreeMap, HashSet};
+use std::ops::Bound;
+use tokio::sync::RwLock;
+
+use crate::{
+    unix_now, AuditEntry, CollectionStats, SnapshotInfo, Storage, Vector, VectorBytes, WalEntry,
+    WalOp,
+};
+
+// Keeps everything in memory and loses it on exit. For tests and throwaway
+// instances.
+#[derive(Default)]
+pub struct InMemoryStorage {
+    state: RwLock<MemoryState>,
+}
+
+#[derive(Default)]
+struct MemoryState {
+    vectors: BTreeMap<String, Vector>,
+    collections: BTreeMap<String, CollectionStats>,
+    dimensions: usize,
+    wal: BTreeMap<u64, WalEntry>,
+    wal_head: u64,
+    wal_retain_after: Option<u64>,
+    audit: BTreeMap<u64, AuditEntry>,
+    audit_head: u64,
+    settings: BTreeMap<String, String>,
+    snapshots: BTreeMap<(String, String), SnapshotInfo>,
+    snapshot_vectors: BTreeMap<(String, String), Vec<Vector>>,
+    versions: BTreeMap<String, Vec<Vector>>,
+}
+
+impl InMemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl MemoryState {
+    // Stats count the JSON size, since nothing is compressed
+    // True if it replaced a vector with the same id
+    fn insert(&mut self, vector: Vector) -> bool {
+        let replaced = self.remove(&vector.id).is_some();
+        if let Some(collection) = &vector.collection {
+            let len = serde_json::to_vec(&vector).map_or(0, |json| json.len());
+            self.collections
+                .entry(collection.clone())
+                .or_default()
+                .added(&vector, len, unix_now());
+        }
+        self.dimensions = vector.dimensions();
+        self.vectors.insert(vector.id.clone(), vector);
+        replaced
+    }
+
+    fn remove(&mut self, id: &str) -> Option<Vector> {
+        let vector = self.vectors.remove(id)?;
+        if self.vectors.is_empty() {
+            self.dimensions = 0;
+        }
+        if let Some(collection) = &vector.collection {
+            let len = serde_json::to_vec(&vector).map_or(0, |json| json.len());
+            if let Some(stats) = self.collections.get_mut(collection) {
+                stats.removed(len, unix_now());
+                if stats.vector_count == 0 {
+                    self.collections.remove(collection);
+                }
+            }
+        }
+        Some(vector)
+    }
+
+    fn append_wal(
+        &mut self,
+        id: &str,
+        op: WalOp,
+        collection: Option<&str>,
+        replaced: bool,
+        hlc: u64,
+    ) {
+        self.wal_head += 1;
+        let entry = WalEntry::new(self.wal_head, id, op, collection, replaced, hlc);
+        self.wal.insert(self.wal_head, entry);
+    }
+}
+
+#[async_trait::async_trait]
+impl Storage for InMemoryStorage {
+    fn persistent(&self) -> bool {
+        false
+    }
+
+    async fn store_vector(&self, vector: &Vector) -> Result<()> {
+        let mut state = self.state.write().await;
+        let replaced = state.insert(vector.clone());
+        state.append_wal(
+            &vector.id,
+            WalOp::Upsert,
+            vector.collection.as_deref(),
+            replaced,
+            vector.hlc,
+        );
+        Ok(())
+    }
+
+    async fn bulk_load(&self, vectors: &[Vector]) -> Result<()> {
+        let mut state = self.state.write().await;
+        for vector in vectors {
+            state.insert(vector.clone());
+        }
+        Ok(())
+    }
+
+    async fn write_batch(
+        &self,
+        upserts: &[Vector],
+        deletes: &[String],
+        deleted_at: u64,
+    ) -> Result<()> {
+        let mut state = self.state.write().await;
+        for vector in upserts {
+            let replaced = state.insert(vector.clone());
+            state.append_wal(
+                &vector.id,
+                WalOp::Upsert,
+                vector.collection.as_deref(),
+                replaced,
+                vector.hlc,
+            );
+        }
+        for id in deletes {
+            if let Some(removed) = state.remove(id) {
+                state.versions.remove(id);
+                state.append_wal(
+                    id,
+                    WalOp::Delete,
+                    removed.collection.as_deref(),
+                    false,
+                    deleted_at,
+                );
+            }
+        }
+        Ok(())
+    }
+
+    async fn get_vector(&self, id: &str) -> Result<Option<Vector>> {
+        Ok(self.state.read().await.vectors.get(id).cloned())
+    }
+
+    async fn delete_vector(&self, id: &str, deleted_at: u64) -> Result<bool> {
+        let mut state = self.state.write().await;
+        let Some(removed) = state.remove(id) else {
+            return Ok(false);
+        };
+        state.versions.remove(id);
+        state.append_wal(
+            id,
+            WalOp::Delete,
+            removed.collection.as_deref(),
+            false,
+            deleted_at,
+        );
+        Ok(true)
+    }
+
+    async fn count_vectors(&self) -> Result<usize> {
+        Ok(self.state.read().await.vectors.len())
+    }
+
+    async fn dimensions(&self) -> Result<usize> {
+        Ok(self.state.read().await.dimensions)
+    }
+
+    async fn size_bytes(&self) -> Result<usize> {
+        Ok(0)
+    }
+
+    // Nothing is compressed, so both are the JSON size
+    fn record_len(&self, vector: &Vector) -> Result<u64> {
+        Ok(serde_json::to_vec(vector)?.len() as u64)
+    }
+
+    async fn vector_bytes(&self) -> Result<VectorBytes> {
+        let state = self.state.read().await;
+        let mut bytes = 0;
+        for vector in state.vectors.values() {
+            bytes += serde_json::to_vec(vector)?.len() as u64;
+        }
+        Ok(VectorBytes {
+            raw: bytes,
+            stored: bytes,
+        })
+    }
+
+    async fn collection_stats(&self, collection: &str) -> Result<Option<CollectionStats>> {
+        Ok(self.state.read().await.collections.get(collection).cloned())
+    }
+
+    async fn compact(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn backup(&self, _backup_path: &str) -> Result<()> {
+        Err(anyhow!("In-memory storage can't be backed up"))
+    }
+
+    async fn list_collections(&self) -> Result<Vec<String>> {
+        let state = self.state.read().await;
+        let collections: HashSet<String> = state
+            .vectors
+            .values()
+            .filter_map(|vector| vector.collection.clone())
+            .collect();
+        Ok(collections.into_iter().collect())
+    }
+
+    async fn get_vectors_in_collection(&self, collection: &str) -> Result<Vec<Vector>> {
+        let state = self.state.read().await;
+        Ok(state
+            .vectors
+            .values()
+            .filter(|vector| vector.collection.as_deref() == Some(collection))
+            .cloned()
+            .collect())
+    }
+
+    async fn scan_collection(
+        &self,
+        collection: Option<&str>,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<Vector>> {
+        let state = self.state.read().await;
+        let start = match after {
+            Some(after) => Bound::Excluded(after),
+            None => Bound::Unbounded,
+        };
+        Ok(state
+            .vectors
+            .range::<str, _>((start, Bound::Unbounded))
+            .map(|(_, vector)| vector)
+            .filter(|vector| collection.is_none() || vector.collection.as_deref() == collection)
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    async fn get_first_vector(&self) -> Result<Option<Vector>> {
+        Ok(self.state.read().await.vectors.values().next().cloned())
+    }
+
+    async fn list_vectors(&self) -> Result<Vec<Vector>> {
+        Ok(self.state.read().await.vectors.values().cloned().collect())
+    }
+
+    async fn wal_head(&self) -> Result<u64> {
+        Ok(self.state.read().await.wal_head)
+    }
+
+    async fn wal_since(&self, seq: u64) -> Result<Vec<WalEntry>> {
+        let state = self.state.read().await;
+        Ok(state
+            .wal
+            .range(seq.saturating_add(1)..)
+            .map(|(_, entry)| entry.clone())
+            .collect())
+    }
+
+    async fn wal_page(&self, seq: u64, limit: usize) -> Result<Vec<WalEntry>> {
+        let state = self.state.read().await;
+        Ok(state
+            .wal
+            .range(seq.saturating_add(1)..)
+            .take(limit)
+            .map(|(_, entry)| entry.clone())
+            .collect())
+    }
+
+    async fn truncate_wal(&self, seq: u64) -> Result<()> {
+        let mut state = self.state.write().await;
+        let seq = state
+            .wal_retain_after
+            .map_or(seq, |retained| seq.min(retained));
+        state.wal = state.wal.split_off(&seq.saturating_add(1));
+        Ok(())
+    }
+
+    async fn retain_wal_after(&self, seq: u64) -> Result<()> {
+        self.state.write().await.wal_retain_after = Some(seq);
+        Ok(())
+    }
+
+    async fn append_audit(&self, entry: &AuditEntry) -> Result<u64> {
+        let mut state = self.state.write().await;
+        state.audit_head += 1;
+        let seq = state.audit_head;
+        let entry = AuditEntry {
+            seq,
+            timestamp: unix_now(),
+            ..entry.clone()
+        };
+        state.audit.insert(seq, entry);
+        Ok(seq)
+    }
+
+    async fn audit_page(&self, seq: u64, limit: usize) -> Result<Vec<AuditEntry>> {
+        let state = self.state.read().await;
+        Ok(state
+            .audit
+            .range(seq.saturating_add(1)..)
+            .take(limit)
+            .map(|(_, entry)| entry.clone())
+            .collect())
+    }
+
+    async fn prune_audit(&self, before: u64) -> Result<usize> {
+        let mut state = self.state.write().await;
+        let before_len = state.audit.len();
+        state.audit.retain(|_, entry| entry.timestamp >= before);
+        Ok(before_len - state.audit.len())
+    }
+
+    async fn get_versions(&self, id: &str) -> Result<Vec<Vector>> {
+        Ok(self
+            .state
+            .read()
+            .await
+            .versions
+            .get(id)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn put_versions(&self, id: &str, versions: &[Vector]) -> Result<()> {
+        let mut state = self.state.write().await;
+        if versions.is_empty() {
+            state.versions.remove(id);
+        } else {
+            state.versions.insert(id.to_string(), versions.to_vec());
+        }
+        Ok(())
+    }
+
+    async fn get_setting(&self, key: &str) -> Result<Option<String>> {
+        Ok(self.state.read().await.settings.get(key).cloned())
+    }
+
+    async fn put_setting(&self, key: &str, value: &str) -> Result<()> {
+        let mut state = self.state.write().await;
+        state.settings.insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+
+    async fn create_snapshot(&self, collection: &str, name: &str) -> Result<SnapshotInfo> {
+        let mut state = self.state.write().await;
+        let key = (collection.to_string(), name.to_string());
+        if state.snapshots.contains_key(&key) {
+            return Err(anyhow!(
+                "Snapshot '{}' already exists for collection '{}'",
+                name,
+                collection
+            ));
+        }
+
+        let vectors: Vec<Vector> = state
+            .vectors
+            .values()
+            .filter(|vector| vector.collection.as_deref() == Some(collection))
+            .cloned()
+            .collect();
+        let info = SnapshotInfo {
+            name: name.to_string(),
+            collection: collection.to_string(),
+            vector_count: vectors.len(),
+            created_at: crate::time::SystemTime::now()
+                .duration_since(crate::time::UNIX_EPOCH)?
+                .as_secs(),
+        };
+        state.snapshots.insert(key.clone(), info.clone());
+        state.snapshot_vectors.insert(key, vectors);
+        Ok(info)
+    }
+
+    async fn get_snapshot(&self, collection: &str, name: &str) -> Result<Option<SnapshotInfo>> {
+        let state = self.state.read().await;
+        let key = (collection.to_string(), name.to_string());
+        Ok(state.snapshots.get(&key).cloned())
+    }
+
+    async fn list_snapshots(&self, collection: &str) -> Result<Vec<SnapshotInfo>> {
+        let state = self.state.read().await;
+        Ok(state
+            .snapshots
+            .values()
+            .filter(|info| info.collection == collection)
+            .cloned()
+            .collect())
+    }
+
+    async fn get_snapshot_vectors(&self, collection: &str, name: &str) -> Result<Vec<Vector>> {
+        let state = self.state.read().await;
+        let key = (collection.to_string(), name.to_string());
+        Ok(state
+            .snapshot_vectors
+            .get(&key)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn delete_snapshot(&self, collection: &str, name: &str) -> Result<bool> {
+        let mut state = self.state.write().await;
+        let key = (collection.to_string(), name.to_string());
+        state.snapshot_vectors.remove(&key);
+        Ok(state.snapshots.remove(&key).is_some())
+    }
+}
@@ -0,0 +1,291 @@
+use anyhow::{anyhow, Result};
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use crate::encryption::{EncryptionKeys, FORMAT_ENCRYPTED};
+use crate::Vector;
+
+// Vector records start with a format byte. Records written before there was
+// one are bare JSON, which always starts with `{`.
+const FORMAT_JSON: u8 = 0;
+const FORMAT_ZSTD: u8 = 1;
+// Vectors with a half-precision `dtype`: the packed `data`, length prefixed,
+// then the rest of the vector as JSON. Optionally zstd-compressed as a whole.
+const FORMAT_PACKED: u8 = 2;
+const FORMAT_PACKED_ZSTD: u8 = 3;
+const ZSTD_LEVEL: i32 = 3;
+// Set on the format byte of records followed by a checksum of the rest:
+// the first bytes of its BLAKE3 hash. Every record is written with one;
+// those from before are read without checking.
+const CHECKSUMMED: u8 = 0x80;
+const CHECKSUM_LEN: usize = 8;
+
+pub(crate) fn encode_vector(vector: &Vector, compression: bool) -> Result<Vec<u8>> {
+    let (format, payload) = if vector.dtype.is_f32() {
+        (FORMAT_JSON, serde_json::to_vec(vector)?)
+    } else {
+        let packed = vector.dtype.encode(&vector.data);
+        let rest = Vector {
+            data: Vec::new(),
+            ..vector.clone()
+        };
+        let mut payload = (packed.len() as u32).to_le_bytes().to_vec();
+        payload.extend_from_slice(&packed);
+        serde_json::to_writer(&mut payload, &rest)?;
+        (FORMAT_PACKED, payload)
+    };
+    let (format, payload) = if compression {
+        // The compressed formats directly follow the uncompressed ones
+        (format + 1, zstd::bulk::compress(&payload, ZSTD_LEVEL)?)
+    } else {
+        (format, payload)
+    };
+
+    let mut record = Vec::with_capacity(payload.len() + CHECKSUM_LEN + 1);
+    record.push(format | CHECKSUMMED);
+    record.extend_from_slice(&checksum(&payload));
+    record.extend_from_slice(&payload);
+    Ok(record)
+}
+
+fn checksum(payload: &[u8]) -> [u8; CHECKSUM_LEN] {
+    let mut checksum = [0; CHECKSUM_LEN];
+    checksum.copy_from_slice(&blake3::hash(payload).as_bytes()[..CHECKSUM_LEN]);
+    checksum
+}
+
+// The format and payload of a record, once its checksum matches
+fn split_record(record: &[u8]) -> Result<Option<(u8, &[u8])>> {
+    let Some((&format, rest)) = record.split_first() else {
+        return Ok(None);
+    };
+    if format & CHECKSUMMED == 0 {
+        return Ok(Some((format, rest)));
+    }
+    let (expected, payload) = rest
+        .split_first_chunk::<CHECKSUM_LEN>()
+        .ok_or_else(|| anyhow!("Vector record is truncated"))?;
+    if checksum(payload) != *expected {
+        return Err(anyhow!("Vector record doesn't match its checksum"));
+    }
+    Ok(Some((format & !CHECKSUMMED, payload)))
+}
+
+pub(crate) fn decode_vector(record: &[u8]) -> Result<Vector> {
+    match split_record(record)? {
+        Some((FORMAT_JSON, json)) => Ok(serde_json::from_slice(json)?),
+        Some((FORMAT_ZSTD, payload)) => Ok(serde_json::from_slice(&zstd::decode_all(payload)?)?),
+        Some((FORMAT_PACKED, payload)) => decode_packed(payload),
+        Some((FORMAT_PACKED_ZSTD, payload)) => decode_packed(&zstd::decode_all(payload)?),
+        Some((b'{', _)) => Ok(serde_json::from_slice(record)?),
+        Some((FORMAT_ENCRYPTED, _)) => Err(anyhow!(
+            "Vector record is encrypted and no encryption key is configured"
+        )),
+        Some((format, _)) => Err(anyhow!("Unknown vector record format {}", format)),
+        None => Err(anyhow!("Empty vector record")),
+    }
+}
+
+fn decode_packed(payload: &[u8]) -> Result<Vector> {
+    let (len, rest) = payload
+        .split_first_chunk::<4>()
+        .ok_or_else(|| anyhow!("Packed vector record is truncated"))?;
+    let len = u32::from_le_bytes(*len) as usize;
+    if rest.len() < len {
+        return Err(anyhow!("Packed vector record is truncated"));
+    }
+    let (packed, json) = rest.split_at(len);
+    let mut vector: Vector = serde_json::from_slice(json)?;
+    vector.data = vector.dtype.decode(packed)?;
+    Ok(vector)
+}
+
+// Size of a record before compression, without the header
+pub(crate) fn raw_len(record: &[u8]) -> Result<u64> {
+    match split_record(record)? {
+        Some((FORMAT_ZSTD | FORMAT_PACKED_ZSTD, payload)) => {
+            zstd::zstd_safe::get_frame_content_size(payload)
+                .ok()
+                .flatten()
+                .ok_or_else(|| anyhow!("Compressed vector record has no content size"))
+        }
+        Some((FORMAT_JSON | FORMAT_PACKED, payload)) => Ok(payload.len() as u64),
+        _ => Ok(record.len() as u64),
+    }
+}
+
+// What a record that may be corrupt counts for in the raw byte counter.
+// A corrupt one is taken to be uncompressed, the best guess there is.
+pub(crate) fn removed_raw_len(record: &[u8]) -> u64 {
+    raw_len(record).unwrap_or_else(|_| match record.first() {
+        Some(format) if format & CHECKSUMMED != 0 => {
+            record.len().saturating_sub(CHECKSUM_LEN + 1) as u64
+        }
+        _ => record.len() as u64,
+    })
+}
+
+// A vector's earlier versions are kept together under its id, as
+// length-prefixed vector records
+fn join_versions(records: &[Vec<u8>]) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    for record in records {
+        encoded.extend_from_slice(&(record.len() as u32).to_le_bytes());
+        encoded.extend_from_slice(record);
+    }
+    encoded
+}
+
+fn split_versions(mut encoded: &[u8]) -> Result<Vec<&[u8]>> {
+    let mut records = Vec::new();
+    while let Some((len, rest)) = encoded.split_first_chunk::<4>() {
+        let len = u32::from_le_bytes(*len) as usize;
+        if rest.len() < len {
+            return Err(anyhow!("Version record is truncated"));
+        }
+        let (record, rest) = rest.split_at(len);
+        records.push(record);
+        encoded = rest;
+    }
+    if !encoded.is_empty() {
+        return Err(anyhow!("Version record is truncated"));
+    }
+    Ok(records)
+}
+
+// How records are written, and the keys encrypted ones are read with
+#[derive(Clone, Default)]
+pub(crate) struct Records {
+    pub(crate) compression: bool,
+    pub(crate) keys: Option<Arc<EncryptionKeys>>,
+}
+
+impl Records {
+    pub(crate) fn new(compression: bool) -> Self {
+        Self {
+            compression,
+            keys: None,
+        }
+    }
+
+    pub(crate) fn encode(&self, vector: &Vector) -> Result<Vec<u8>> {
+        let record = encode_vector(vector, self.compression)?;
+        match &self.keys {
+            Some(keys) => keys.encrypt(&record),
+            None => Ok(record),
+        }
+    }
+
+    pub(crate) fn decode(&self, record: &[u8]) -> Result<Vector> {
+        decode_vector(&self.open(record)?)
+    }
+
+    // The record as it would be stored unencrypted
+    fn open<'a>(&self, record: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+        match (record.first(), &self.keys) {
+            (Some(&FORMAT_ENCRYPTED), Some(keys)) => Ok(Cow::Owned(keys.decrypt(record)?)),
+            _ => Ok(Cow::Borrowed(record)),
+        }
+    }
+
+    #[cfg(feature = "redb")]
+    pub(crate) fn raw_len(&self, record: &[u8]) -> Result<u64> {
+        raw_len(&self.open(record)?)
+    }
+
+    #[cfg(feature = "redb")]
+    pub(crate) fn removed_raw_len(&self, record: &[u8]) -> u64 {
+        match self.open(record) {
+            Ok(record) => removed_raw_len(&record),
+            Err(_) => record.len() as u64,
+        }
+    }
+
+    pub(crate) fn encode_versions(&self, versions: &[Vector]) -> Result<Vec<u8>> {
+        let records = versions
+            .iter()
+            .map(|version| self.encode(version))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(join_versions(&records))
+    }
+
+    pub(crate) fn decode_versions(&self, encoded: &[u8]) -> Result<Vec<Vector>> {
+        split_versions(encoded)?
+            .into_iter()
+            .map(|record| self.decode(record))
+            .collect()
+    }
+
+    // The record encrypted with the current key, None if it already is or
+    // there's no key
+    #[cfg(feature = "redb")]
+    pub(crate) fn reseal(&self, record: &[u8]) -> Result<Option<Vec<u8>>> {
+        match &self.keys {
+            Some(keys) if !keys.is_current(record) => Ok(Some(keys.encrypt(&self.open(record)?)?)),
+            _ => Ok(None),
+        }
+    }
+
+    // Like `reseal`, for a vector's earlier versions
+    #[cfg(feature = "redb")]
+    pub(crate) fn reseal_versions(&self, encoded: &[u8]) -> Result<Option<Vec<u8>>> {
+        let records = split_versions(encoded)?;
+        let mut resealed = Vec::with_capacity(records.len());
+        let mut changed = false;
+        for record in records {
+            match self.reseal(record)? {
+                Some(record) => {
+                    resealed.push(record);
+                    changed = true;
+                }
+                None => resealed.push(record.to_vec()),
+            }
+        }
+        Ok(changed.then(|| join_versions(&resealed)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Dtype;
+
+    #[test]
+    fn test_packed_records_round_trip() {
+        let data: Vec<f32> = (0..64).map(|i| (i as f32 * 0.37).sin()).collect();
+        let mut vector = Vector::with_id("a".to_string(), data.clone());
+        vector.dtype = Dtype::F16;
+        let mut rounded = data;
+        Dtype::F16.round(&mut rounded);
+
+        for compression in [false, true] {
+            let record = encode_vector(&vector, compression).unwrap();
+            let decoded = decode_vector(&record).unwrap();
+            assert_eq!(decoded.dtype, Dtype::F16);
+            assert_eq!(decoded.data, rounded);
+
+            let full = Vector {
+                dtype: Dtype::F32,
+                ..vector.clone()
+            };
+            assert!(record.len() < encode_vector(&full, compression).unwrap().len());
+        }
+    }
+
+    #[test]
+    fn test_corrupt_records_fail_their_checksum() {
+        let vector = Vector::with_id("a".to_string(), vec![0.25; 16]);
+        for compression in [false, true] {
+            let mut record = encode_vector(&vector, compression).unwrap();
+            assert_eq!(decode_vector(&record).unwrap().data, vector.data);
+            let last = record.len() - 1;
+            record[last] ^= 1;
+            let error = decode_vector(&record).unwrap_err().to_string();
+            assert!(error.contains("checksum"), "{}", error);
+        }
+        // Records from before checksums are still read
+        let mut unchecked = vec![FORMAT_JSON];
+        unchecked.extend(serde_json::to_vec(&vector).unwrap());
+        assert_eq!(decode_vector(&unchecked).unwrap().id, "a");
+    }
+}
@@ -1,19 +1,98 @@
-use anyhow::Result;
-use redb::{Database, ReadableTable, ReadableTableMetadata, TableDefinition};
+use anyhow::{anyhow, Result};
+use redb::{
+    Database, Durability, ReadTransaction, ReadableTable, ReadableTableMetadata, Table,
+    TableDefinition, WriteTransaction,
+};
 use serde_json;
 use std::fs;
+use std::ops::Bound;
 use std::path::Path;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use tokio::task;
 
-use crate::{Storage, Vector};
+use crate::record::{decode_vector, Records};
+use crate::{
+    setting_key, unix_now, AuditEntry, CollectionStats, CorruptRecord, EncryptionKeys,
+    SnapshotInfo, Storage, Vector, VectorBytes, VerifyReport, WalEntry, WalOp,
+};
 
 const VECTORS_TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("vectors");
 const METADATA_TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("metadata");
+// (collection, snapshot) -> SnapshotInfo
+const SNAPSHOTS_TABLE: TableDefinition<(&str, &str), &[u8]> = TableDefinition::new("snapshots");
+// (collection, snapshot, vector id) -> Vector
+const SNAPSHOT_VECTORS_TABLE: TableDefinition<(&str, &str, &str), &[u8]> =
+    TableDefinition::new("snapshot_vectors");
+// vector id -> its earlier versions, newest first
+const VERSIONS_TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("versions");
+// seq -> WalEntry
+const WAL_TABLE: TableDefinition<u64, &[u8]> = TableDefinition::new("wal");
+// Audit log entries by seq, apart from the WAL so snapshots don't
+// truncate them
+const AUDIT_TABLE: TableDefinition<u64, &[u8]> = TableDefinition::new("audit");
+// Kept in METADATA_TABLE, since truncation can leave the WAL table empty
+const WAL_HEAD_KEY: &str = "wal_head";
+// Truncation never goes past this seq once it's set; see `retain_wal_after`
+const WAL_RETAIN_KEY: &str = "wal_retain_after";
+// Seq of the last audit entry ever appended
+const AUDIT_HEAD_KEY: &str = "audit_head";
+// Summed size of the records in VECTORS_TABLE before and after compression,
+// kept in METADATA_TABLE
+const RAW_BYTES_KEY: &str = "vector_raw_bytes";
+const STORED_BYTES_KEY: &str = "vector_stored_bytes";
+// Number of records in VECTORS_TABLE and dimensions of the last one written,
+// kept in METADATA_TABLE so stats don't scan the table
+const VECTOR_COUNT_KEY: &str = "vector_count";
+const DIMENSIONS_KEY: &str = "dimensions";
+// collection -> CollectionStats, for collections with at least one vector
+const COLLECTIONS_TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("collections");
+// Set in METADATA_TABLE once COLLECTIONS_TABLE is maintained
+const COLLECTION_STATS_KEY: &str = "collection_stats";
+// (collection, vector id) and (metadata key, value, vector id) for every
+// record in VECTORS_TABLE, so lookups by either are range scans
+const COLLECTION_IDS_TABLE: TableDefinition<(&str, &str), ()> =
+    TableDefinition::new("collection_ids");
+const METADATA_IDS_TABLE: TableDefinition<(&str, &str, &str), ()> =
+    TableDefinition::new("metadata_ids");
+// Set in METADATA_TABLE once both are maintained
+const SECONDARY_INDEXES_KEY: &str = "secondary_indexes";
+
+// Transactions share the database; compaction takes it exclusively, since
+// redb needs `&mut Database` for it
+struct SharedDatabase(RwLock<Database>);
+
+impl SharedDatabase {
+    fn begin_read(&self) -> Result<ReadTransaction> {
+        let db = self
+            .0
+            .read()
+            .map_err(|_| anyhow!("Database lock poisoned"))?;
+        Ok(db.begin_read()?)
+    }
+
+    fn begin_write(&self) -> Result<WriteTransaction> {
+        let db = self
+            .0
+            .read()
+            .map_err(|_| anyhow!("Database lock poisoned"))?;
+        Ok(db.begin_write()?)
+    }
+
+    // Fails while any read transaction is still open
+    fn compact(&self) -> Result<bool> {
+        let mut db = self
+            .0
+            .write()
+            .map_err(|_| anyhow!("Database lock poisoned"))?;
+        db.compact()
+            .map_err(|e| anyhow!("Compaction failed: {}", e))
+    }
+}
 
 pub struct RedbStorage {
-    db: Arc<Database>,
+    db: Arc<SharedDatabase>,
     data_dir: String,
+    records: Records,
 }
 
 impl RedbStorage {
@@ -32,15 +111,523 @@ impl RedbStorage {
             {
                 let _vectors_table = write_txn.open_table(VECTORS_TABLE)?;
                 let _metadata_table = write_txn.open_table(METADATA_TABLE)?;
+                let _snapshots_table = write_txn.open_table(SNAPSHOTS_TABLE)?;
+                let _snapshot_vectors_table = write_txn.open_table(SNAPSHOT_VECTORS_TABLE)?;
+                let _versions_table = write_txn.open_table(VERSIONS_TABLE)?;
+                let _wal_table = write_txn.open_table(WAL_TABLE)?;
+                let _audit_table = write_txn.open_table(AUDIT_TABLE)?;
+                let _collections_table = write_txn.open_table(COLLECTIONS_TABLE)?;
+                let _collection_ids_table = write_txn.open_table(COLLECTION_IDS_TABLE)?;
+                let _metadata_ids_table = write_txn.open_table(METADATA_IDS_TABLE)?;
             }
             write_txn.commit()?;
         }
+        count_vector_bytes(&db)?;
+        count_vectors(&db)?;
+        count_collection_stats(&db)?;
+        build_secondary_indexes(&db)?;
 
         Ok(Self {
-            db: Arc::new(db),
+            db: Arc::new(SharedDatabase(RwLock::new(db))),
             data_dir: data_dir.to_string(),
+            records: Records::new(false),
         })
     }
+
+    // Compresses vector records written from now on with zstd. Existing
+    // records are read either way.
+    pub fn with_compression(mut self, compression: bool) -> Self {
+        self.records.compression = compression;
+        self
+    }
+
+    // Encrypts vector records written from now on. Existing records are
+    // encrypted with the current key the next time the store is compacted.
+    pub fn with_encryption(mut self, keys: EncryptionKeys) -> Self {
+        self.records.keys = Some(Arc::new(keys));
+        self
+    }
+}
+
+fn read_u64(table: &impl ReadableTable<&'static str, &'static [u8]>, key: &str) -> Result<u64> {
+    match table.get(key)? {
+        Some(data) => Ok(serde_json::from_slice(data.value())?),
+        None => Ok(0),
+    }
+}
+
+fn read_wal_head(table: &impl ReadableTable<&'static str, &'static [u8]>) -> Result<u64> {
+    read_u64(table, WAL_HEAD_KEY)
+}
+
+// Adjusts the byte counters for records added to or removed from
+// VECTORS_TABLE, in the transaction that does it
+fn update_vector_bytes(
+    write_txn: &WriteTransaction,
+    records: &Records,
+    added: &[&[u8]],
+    removed: &[&[u8]],
+) -> Result<()> {
+    let mut metadata = write_txn.open_table(METADATA_TABLE)?;
+    let mut raw = read_u64(&metadata, RAW_BYTES_KEY)?;
+    let mut stored = read_u64(&metadata, STORED_BYTES_KEY)?;
+    for record in added {
+        raw += records.raw_len(record)?;
+        stored += record.len() as u64;
+    }
+    for record in removed {
+        raw = raw.saturating_sub(records.removed_raw_len(record));
+        stored = stored.saturating_sub(record.len() as u64);
+    }
+    metadata.insert(RAW_BYTES_KEY, serde_json::to_vec(&raw)?.as_slice())?;
+    metadata.insert(STORED_BYTES_KEY, serde_json::to_vec(&stored)?.as_slice())?;
+    Ok(())
+}
+
+// Adjusts the vector count for records added to or removed from
+// VECTORS_TABLE, in the transaction that does it. `dimensions` is that of
+// the last vector written, if any was.
+fn update_vector_count(
+    write_txn: &WriteTransaction,
+    added: usize,
+    removed: usize,
+    dimensions: Option<usize>,
+) -> Result<()> {
+    let mut metadata = write_txn.open_table(METADATA_TABLE)?;
+    let count =
+        (read_u64(&metadata, VECTOR_COUNT_KEY)? + added as u64).saturating_sub(removed as u64);
+    let dimensions = match (count, dimensions) {
+        (0, _) => 0,
+        (_, Some(dimensions)) => dimensions as u64,
+        (_, None) => read_u64(&metadata, DIMENSIONS_KEY)?,
+    };
+    metadata.insert(VECTOR_COUNT_KEY, serde_json::to_vec(&count)?.as_slice())?;
+    metadata.insert(DIMENSIONS_KEY, serde_json::to_vec(&dimensions)?.as_slice())?;
+    Ok(())
+}
+
+// Data dirs from before the vector count existed get it computed once
+fn count_vectors(db: &Database) -> Result<()> {
+    let write_txn = db.begin_write()?;
+    {
+        if write_txn
+            .open_table(METADATA_TABLE)?
+            .get(VECTOR_COUNT_KEY)?
+            .is_some()
+        {
+            return Ok(());
+        }
+        let vectors = write_txn.open_table(VECTORS_TABLE)?;
+        let count = vectors.len()? as usize;
+        let dimensions = match vectors.first()? {
+            Some((_, data)) => Some(decode_vector(data.value())?.dimensions()),
+            None => None,
+        };
+        update_vector_count(&write_txn, count, 0, dimensions)?;
+    }
+    write_txn.commit()?;
+    Ok(())
+}
+
+// Data dirs from before the byte counters existed get them computed once
+fn count_vector_bytes(db: &Database) -> Result<()> {
+    let write_txn = db.begin_write()?;
+    {
+        if write_txn
+            .open_table(METADATA_TABLE)?
+            .get(STORED_BYTES_KEY)?
+            .is_some()
+        {
+            return Ok(());
+        }
+        let vectors = write_txn.open_table(VECTORS_TABLE)?;
+        let mut added = Vec::new();
+        for item in vectors.iter()? {
+            let (_, data) = item?;
+            added.push(data.value().to_vec());
+        }
+        let added: Vec<&[u8]> = added.iter().map(Vec::as_slice).collect();
+        update_vector_bytes(&write_txn, &Records::default(), &added, &[])?;
+    }
+    write_txn.commit()?;
+    Ok(())
+}
+
+fn read_collection_stats(
+    table: &impl ReadableTable<&'static str, &'static [u8]>,
+    collection: &str,
+) -> Result<CollectionStats> {
+    match table.get(collection)? {
+        Some(data) => Ok(serde_json::from_slice(data.value())?),
+        None => Ok(CollectionStats::default()),
+    }
+}
+
+fn write_collection_stats(
+    table: &mut Table<&'static str, &'static [u8]>,
+    collection: &str,
+    stats: &CollectionStats,
+) -> Result<()> {
+    if stats.vector_count == 0 {
+        table.remove(collection)?;
+    } else {
+        table.insert(collection, serde_json::to_vec(stats)?.as_slice())?;
+    }
+    Ok(())
+}
+
+// Adjusts COLLECTIONS_TABLE for a record added to and/or one removed from
+// VECTORS_TABLE, in the transaction that does it
+fn update_collection_stats(
+    table: &mut Table<&'static str, &'static [u8]>,
+    records: &Records,
+    added: Option<(&Vector, &[u8])>,
+    removed: Option<&[u8]>,
+) -> Result<()> {
+    let now = unix_now();
+    if let Some(record) = removed {
+        if let Some(collection) = records.decode(record)?.collection {
+            let mut stats = read_collection_stats(table, &collection)?;
+            stats.removed(record.len(), now);
+            write_collection_stats(table, &collection, &stats)?;
+        }
+    }
+    if let Some((vector, record)) = added {
+        if let Some(collection) = &vector.collection {
+            let mut stats = read_collection_stats(table, collection)?;
+            stats.added(vector, record.len(), now);
+            write_collection_stats(table, collection, &stats)?;
+        }
+    }
+    Ok(())
+}
+
+// Data dirs from before the collection stats existed get them computed once
+fn count_collection_stats(db: &Database) -> Result<()> {
+    let write_txn = db.begin_write()?;
+    {
+        let mut metadata = write_txn.open_table(METADATA_TABLE)?;
+        if metadata.get(COLLECTION_STATS_KEY)?.is_some() {
+            return Ok(());
+        }
+        metadata.insert(COLLECTION_STATS_KEY, serde_json::to_vec(&true)?.as_slice())?;
+
+        let vectors = write_txn.open_table(VECTORS_TABLE)?;
+        let mut collections = write_txn.open_table(COLLECTIONS_TABLE)?;
+        for item in vectors.iter()? {
+            let (_, data) = item?;
+            let vector = decode_vector(data.value())?;
+            if let Some(collection) = &vector.collection {
+                let mut stats = read_collection_stats(&collections, collection)?;
+                let last_modified = stats.last_modified.max(vector.created_at);
+                stats.added(&vector, data.value().len(), last_modified);
+                write_collection_stats(&mut collections, collection, &stats)?;
+            }
+        }
+    }
+    write_txn.commit()?;
+    Ok(())
+}
+
+// Adds or removes a vector's entries in the secondary index tables, in the
+// transaction that writes it
+fn update_secondary_indexes(
+    write_txn: &WriteTransaction,
+    vector: &Vector,
+    add: bool,
+) -> Result<()> {
+    let mut collection_ids = write_txn.open_table(COLLECTION_IDS_TABLE)?;
+    let mut metadata_ids = write_txn.open_table(METADATA_IDS_TABLE)?;
+    let id = vector.id.as_str();
+    if let Some(collection) = &vector.collection {
+        match add {
+            true => collection_ids.insert((collection.as_str(), id), ())?,
+            false => collection_ids.remove((collection.as_str(), id))?,
+        };
+    }
+    for (key, value) in vector.metadata.iter().flatten() {
+        match add {
+            true => metadata_ids.insert((key.as_str(), value.as_str(), id), ())?,
+            false => metadata_ids.remove((key.as_str(), value.as_str(), id))?,
+        };
+    }
+    Ok(())
+}
+
+// Swaps the entries of the record being replaced or deleted, if any, for
+// those of the vector being written, if any
+fn reindex(
+    write_txn: &WriteTransaction,
+    records: &Records,
+    added: Option<&Vector>,
+    removed: Option<&[u8]>,
+) -> Result<()> {
+    if let Some(record) = removed {
+        update_secondary_indexes(write_txn, &records.decode(record)?, false)?;
+    }
+    if let Some(vector) = added {
+        update_secondary_indexes(write_txn, vector, true)?;
+    }
+    Ok(())
+}
+
+// Data dirs from before the secondary indexes existed get them built once
+fn build_secondary_indexes(db: &Database) -> Result<()> {
+    let write_txn = db.begin_write()?;
+    {
+        let mut metadata = write_txn.open_table(METADATA_TABLE)?;
+        if metadata.get(SECONDARY_INDEXES_KEY)?.is_some() {
+            return Ok(());
+        }
+        metadata.insert(SECONDARY_INDEXES_KEY, serde_json::to_vec(&true)?.as_slice())?;
+
+        let vectors = write_txn.open_table(VECTORS_TABLE)?;
+        for item in vectors.iter()? {
+            let (_, data) = item?;
+            update_secondary_indexes(&write_txn, &decode_vector(data.value())?, true)?;
+        }
+    }
+    write_txn.commit()?;
+    Ok(())
+}
+
+// Ids in `collection` after `after`, in order, from COLLECTION_IDS_TABLE
+fn collection_ids(
+    table: &impl ReadableTable<(&'static str, &'static str), ()>,
+    collection: &str,
+    after: Option<&str>,
+) -> Result<Vec<String>> {
+    let start = match after {
+        Some(after) => Bound::Excluded((collection, after)),
+        None => Bound::Included((collection, "")),
+    };
+    let mut ids = Vec::new();
+    for item in table.range::<(&str, &str)>((start, Bound::Unbounded))? {
+        let (key, _) = item?;
+        let (entry_collection, id) = key.value();
+        if entry_collection != collection {
+            break;
+        }
+        ids.push(id.to_string());
+    }
+    Ok(ids)
+}
+
+// The vectors stored under `ids`, skipping any that are gone
+fn read_records(
+    table: &impl ReadableTable<&'static str, &'static [u8]>,
+    records: &Records,
+    ids: &[String],
+) -> Result<Vec<Vector>> {
+    let mut vectors = Vec::with_capacity(ids.len());
+    for id in ids {
+        if let Some(data) = table.get(id.as_str())? {
+            vectors.push(records.decode(data.value())?);
+        }
+    }
+    Ok(vectors)
+}
+
+// Drops a record that can't be decoded, with its secondary index entries
+// and its share of the counters. The index tables say which collection
+// and metadata it had.
+fn remove_corrupt(write_txn: &WriteTransaction, records: &Records, id: &str) -> Result<()> {
+    let Some(record) = write_txn
+        .open_table(VECTORS_TABLE)?
+        .remove(id)?
+        .map(|old| old.value().to_vec())
+    else {
+        return Ok(());
+    };
+    update_vector_bytes(write_txn, records, &[], &[&record])?;
+    update_vector_count(write_txn, 0, 1, None)?;
+
+    let mut collection_ids = write_txn.open_table(COLLECTION_IDS_TABLE)?;
+    let mut collections = Vec::new();
+    for item in collection_ids.iter()? {
+        let (key, _) = item?;
+        let (collection, entry_id) = key.value();
+        if entry_id == id {
+            collections.push(collection.to_string());
+        }
+    }
+    let mut stats_table = write_txn.open_table(COLLECTIONS_TABLE)?;
+    let now = unix_now();
+    for collection in &collections {
+        collection_ids.remove((collection.as_str(), id))?;
+        let mut stats = read_collection_stats(&stats_table, collection)?;
+        stats.removed(record.len(), now);
+        write_collection_stats(&mut stats_table, collection, &stats)?;
+    }
+
+    let mut metadata_ids = write_txn.open_table(METADATA_IDS_TABLE)?;
+    let mut entries = Vec::new();
+    for item in metadata_ids.iter()? {
+        let (key, _) = item?;
+        let (field, value, entry_id) = key.value();
+        if entry_id == id {
+            entries.push((field.to_string(), value.to_string()));
+        }
+    }
+    for (field, value) in &entries {
+        metadata_ids.remove((field.as_str(), value.as_str(), id))?;
+    }
+    Ok(())
+}
+
+// Encrypts the records that aren't under the current key with it: vectors,
+// their earlier versions and snapshot copies. Records that fail to decrypt
+// are left for `verify` to report.
+fn reencrypt(db: &SharedDatabase, records: &Records) -> Result<()> {
+    if records.keys.is_none() {
+        return Ok(());
+    }
+    let write_txn = db.begin_write()?;
+    {
+        let mut vectors = write_txn.open_table(VECTORS_TABLE)?;
+        let mut resealed = Vec::new();
+        for item in vectors.iter()? {
+            let (id, data) = item?;
+            if let Ok(Some(record)) = records.reseal(data.value()) {
+                resealed.push((id.value().to_string(), data.value().len(), record));
+            }
+        }
+        let mut collections = write_txn.open_table(COLLECTIONS_TABLE)?;
+        let (mut old_bytes, mut new_bytes) = (0, 0);
+        for (id, old_len, record) in &resealed {
+            vectors.insert(id.as_str(), record.as_slice())?;
+            old_bytes += *old_len as u64;
+            new_bytes += record.len() as u64;
+            if let Some(collection) = records.decode(record)?.collection {
+                let mut stats = read_collection_stats(&collections, &collection)?;
+                stats.stored_bytes =
+                    (stats.stored_bytes + record.len() as u64).saturating_sub(*old_len as u64);
+                write_collection_stats(&mut collections, &collection, &stats)?;
+            }
+        }
+        let mut metadata = write_txn.open_table(METADATA_TABLE)?;
+        let stored = (read_u64(&metadata, STORED_BYTES_KEY)? + new_bytes).saturating_sub(old_bytes);
+        metadata.insert(STORED_BYTES_KEY, serde_json::to_vec(&stored)?.as_slice())?;
+
+        let mut versions = write_txn.open_table(VERSIONS_TABLE)?;
+        let mut resealed = Vec::new();
+        for item in versions.iter()? {
+            let (id, data) = item?;
+            if let Ok(Some(encoded)) = records.reseal_versions(data.value()) {
+                resealed.push((id.value().to_string(), encoded));
+            }
+        }
+        for (id, encoded) in &resealed {
+            versions.insert(id.as_str(), encoded.as_slice())?;
+        }
+
+        let mut snapshot_vectors = write_txn.open_table(SNAPSHOT_VECTORS_TABLE)?;
+        let mut resealed = Vec::new();
+        for item in snapshot_vectors.iter()? {
+            let (key, data) = item?;
+            if let Ok(Some(record)) = records.reseal(data.value()) {
+                let (collection, name, id) = key.value();
+                let key = (collection.to_string(), name.to_string(), id.to_string());
+                resealed.push((key, record));
+            }
+        }
+        for ((collection, name, id), record) in &resealed {
+            snapshot_vectors.insert(
+                (collection.as_str(), name.as_str(), id.as_str()),
+                record.as_slice(),
+            )?;
+        }
+    }
+    write_txn.commit()?;
+    Ok(())
+}
+
+// Logs a change inside the transaction that makes it, so the WAL can't miss
+// or invent writes
+fn append_wal(
+    write_txn: &WriteTransaction,
+    id: &str,
+    op: WalOp,
+    collection: Option<&str>,
+    replaced: bool,
+    hlc: u64,
+) -> Result<()> {
+    let mut metadata = write_txn.open_table(METADATA_TABLE)?;
+    let seq = read_wal_head(&metadata)? + 1;
+    metadata.insert(WAL_HEAD_KEY, serde_json::to_vec(&seq)?.as_slice())?;
+
+    let entry = WalEntry::new(seq, id, op, collection, replaced, hlc);
+    let mut wal = write_txn.open_table(WAL_TABLE)?;
+    wal.insert(seq, serde_json::to_vec(&entry)?.as_slice())?;
+    Ok(())
+}
+
+// Applies the upserts and then the deletes, keeping the counters in step.
+// Only `log`ged writes reach the WAL.
+fn write_vectors(
+    write_txn: &WriteTransaction,
+    records: &Records,
+    upserts: &[Vector],
+    deletes: &[String],
+    deleted_at: u64,
+    log: bool,
+) -> Result<()> {
+    let mut table = write_txn.open_table(VECTORS_TABLE)?;
+    let mut collections = write_txn.open_table(COLLECTIONS_TABLE)?;
+    let mut added = Vec::with_capacity(upserts.len());
+    let mut removed = Vec::new();
+    let mut logged = Vec::new();
+    for vector in upserts {
+        let record = records.encode(vector)?;
+        let previous = table
+            .insert(vector.id.as_str(), record.as_slice())?
+            .map(|old| old.value().to_vec());
+        update_collection_stats(
+            &mut collections,
+            records,
+            Some((vector, &record)),
+            previous.as_deref(),
+        )?;
+        reindex(write_txn, records, Some(vector), previous.as_deref())?;
+        logged.push((
+            vector.id.as_str(),
+            WalOp::Upsert,
+            vector.collection.clone(),
+            previous.is_some(),
+            vector.hlc,
+        ));
+        removed.extend(previous);
+        added.push(record);
+    }
+    let mut versions = write_txn.open_table(VERSIONS_TABLE)?;
+    for id in deletes {
+        let previous = table.remove(id.as_str())?.map(|old| old.value().to_vec());
+        if let Some(previous) = previous {
+            versions.remove(id.as_str())?;
+            update_collection_stats(&mut collections, records, None, Some(&previous))?;
+            reindex(write_txn, records, None, Some(&previous))?;
+            let collection = records.decode(&previous)?.collection;
+            removed.push(previous);
+            logged.push((id.as_str(), WalOp::Delete, collection, false, deleted_at));
+        }
+    }
+    drop((table, collections, versions));
+
+    let added: Vec<&[u8]> = added.iter().map(Vec::as_slice).collect();
+    let removed: Vec<&[u8]> = removed.iter().map(Vec::as_slice).collect();
+    update_vector_bytes(write_txn, records, &added, &removed)?;
+    update_vector_count(
+        write_txn,
+        added.len(),
+        removed.len(),
+        upserts.last().map(Vector::dimensions),
+    )?;
+    if log {
+        for (id, op, collection, replaced, hlc) in logged {
+            append_wal(write_txn, id, op, collection.as_deref(), replaced, hlc)?;
+        }
+    }
+    Ok(())
 }
 
 #[async_trait::async_trait]
@@ -48,14 +635,83 @@ impl Storage for RedbStorage {
     async fn store_vector(&self, vector: &Vector) -> Result<()> {
         let db = Arc::clone(&self.db);
         let vector = vector.clone();
+        let records = self.records.clone();
 
         task::spawn_blocking(move || {
             let write_txn = db.begin_write()?;
-            {
+            let replaced = {
                 let mut table = write_txn.open_table(VECTORS_TABLE)?;
-                let serialized = serde_json::to_vec(&vector)?;
-                table.insert(vector.id.as_str(), serialized.as_slice())?;
-            }
+                let record = records.encode(&vector)?;
+                let previous = table
+                    .insert(vector.id.as_str(), record.as_slice())?
+                    .map(|old| old.value().to_vec());
+                update_vector_bytes(
+                    &write_txn,
+                    &records,
+                    &[&record],
+                    previous.as_deref().as_slice(),
+                )?;
+                update_vector_count(
+                    &write_txn,
+                    1,
+                    usize::from(previous.is_some()),
+                    Some(vector.dimensions()),
+                )?;
+                update_collection_stats(
+                    &mut write_txn.open_table(COLLECTIONS_TABLE)?,
+                    &records,
+                    Some((&vector, &record)),
+                    previous.as_deref(),
+                )?;
+                reindex(&write_txn, &records, Some(&vector), previous.as_deref())?;
+                previous.is_some()
+            };
+            append_wal(
+                &write_txn,
+                &vector.id,
+                WalOp::Upsert,
+                vector.collection.as_deref(),
+                replaced,
+                vector.hlc,
+            )?;
+            write_txn.commit()?;
+            Ok::<(), anyhow::Error>(())
+        })
+        .await??;
+
+        Ok(())
+    }
+
+    async fn bulk_load(&self, vectors: &[Vector]) -> Result<()> {
+        let db = Arc::clone(&self.db);
+        let vectors = vectors.to_vec();
+        let records = self.records.clone();
+
+        task::spawn_blocking(move || {
+            let write_txn = db.begin_write()?;
+            write_vectors(&write_txn, &records, &vectors, &[], 0, false)?;
+            write_txn.commit()?;
+            Ok::<(), anyhow::Error>(())
+        })
+        .await??;
+
+        Ok(())
+    }
+
+    async fn write_batch(
+        &self,
+        upserts: &[Vector],
+        deletes: &[String],
+        deleted_at: u64,
+    ) -> Result<()> {
+        let db = Arc::clone(&self.db);
+        let upserts = upserts.to_vec();
+        let deletes = deletes.to_vec();
+        let records = self.records.clone();
+
+        task::spawn_blocking(move || {
+            let write_txn = db.begin_write()?;
+            write_vectors(&write_txn, &records, &upserts, &deletes, deleted_at, true)?;
             write_txn.commit()?;
             Ok::<(), anyhow::Error>(())
         })
@@ -67,6 +723,7 @@ impl Storage for RedbStorage {
     async fn get_vector(&self, id: &str) -> Result<Option<Vector>> {
         let db = Arc::clone(&self.db);
         let id = id.to_string();
+        let records = self.records.clone();
 
         let result = task::spawn_blocking(move || {
             let read_txn = db.begin_read()?;
@@ -74,7 +731,7 @@ impl Storage for RedbStorage {
 
             match table.get(id.as_str())? {
                 Some(data) => {
-                    let vector: Vector = serde_json::from_slice(data.value())?;
+                    let vector = records.decode(data.value())?;
                     Ok::<Option<Vector>, anyhow::Error>(Some(vector))
                 }
                 None => Ok::<Option<Vector>, anyhow::Error>(None),
@@ -85,17 +742,40 @@ impl Storage for RedbStorage {
         Ok(result)
     }
 
-    async fn delete_vector(&self, id: &str) -> Result<bool> {
+    async fn delete_vector(&self, id: &str, deleted_at: u64) -> Result<bool> {
         let db = Arc::clone(&self.db);
         let id = id.to_string();
+        let records = self.records.clone();
 
         let result = task::spawn_blocking(move || {
             let write_txn = db.begin_write()?;
-            let existed = {
+            let removed = {
                 let mut table = write_txn.open_table(VECTORS_TABLE)?;
-                let removal_result = table.remove(id.as_str())?;
-                removal_result.is_some()
+                let removed = table.remove(id.as_str())?.map(|old| old.value().to_vec());
+                update_vector_bytes(&write_txn, &records, &[], removed.as_deref().as_slice())?;
+                update_vector_count(&write_txn, 0, usize::from(removed.is_some()), None)?;
+                update_collection_stats(
+                    &mut write_txn.open_table(COLLECTIONS_TABLE)?,
+                    &records,
+                    None,
+                    removed.as_deref(),
+                )?;
+                reindex(&write_txn, &records, None, removed.as_deref())?;
+                removed
             };
+            let existed = removed.is_some();
+            if let Some(removed) = removed {
+                write_txn.open_table(VERSIONS_TABLE)?.remove(id.as_str())?;
+                let collection = records.decode(&removed)?.collection;
+                append_wal(
+                    &write_txn,
+                    &id,
+                    WalOp::Delete,
+                    collection.as_deref(),
+                    false,
+                    deleted_at,
+                )?;
+            }
             write_txn.commit()?;
             Ok::<bool, anyhow::Error>(existed)
         })
@@ -109,12 +789,25 @@ impl Storage for RedbStorage {
 
         let count = task::spawn_blocking(move || {
             let read_txn = db.begin_read()?;
-            let table = read_txn.open_table(VECTORS_TABLE)?;
-            Ok::<usize, anyhow::Error>(table.len()? as usize)
+            let metadata = read_txn.open_table(METADATA_TABLE)?;
+            read_u64(&metadata, VECTOR_COUNT_KEY)
+        })
+        .await??;
+
+        Ok(count as usize)
+    }
+
+    async fn dimensions(&self) -> Result<usize> {
+        let db = Arc::clone(&self.db);
+
+        let dimensions = task::spawn_blocking(move || {
+            let read_txn = db.begin_read()?;
+            let metadata = read_txn.open_table(METADATA_TABLE)?;
+            read_u64(&metadata, DIMENSIONS_KEY)
         })
         .await??;
 
-        Ok(count)
+        Ok(dimensions as usize)
     }
 
     async fn size_bytes(&self) -> Result<usize> {
@@ -123,11 +816,67 @@ impl Storage for RedbStorage {
         Ok(metadata.len() as usize)
     }
 
+    fn record_len(&self, vector: &Vector) -> Result<u64> {
+        Ok(self.records.encode(vector)?.len() as u64)
+    }
+
+    async fn vector_bytes(&self) -> Result<VectorBytes> {
+        let db = Arc::clone(&self.db);
+
+        task::spawn_blocking(move || {
+            let read_txn = db.begin_read()?;
+            let metadata = read_txn.open_table(METADATA_TABLE)?;
+            Ok(VectorBytes {
+                raw: read_u64(&metadata, RAW_BYTES_KEY)?,
+                stored: read_u64(&metadata, STORED_BYTES_KEY)?,
+            })
+        })
+        .await?
+    }
+
+    async fn collection_stats(&self, collection: &str) -> Result<Option<CollectionStats>> {
+        let db = Arc::clone(&self.db);
+        let collection = collection.to_string();
+
+        task::spawn_blocking(move || {
+            let read_txn = db.begin_read()?;
+            let table = read_txn.open_table(COLLECTIONS_TABLE)?;
+            match table.get(collection.as_str())? {
+                Some(data) => Ok(Some(serde_json::from_slice(data.value())?)),
+                None => Ok(None),
+            }
+        })
+        .await?
+    }
+
     async fn compact(&self) -> Result<()> {
-        // Note: redb Database doesn't need explicit compaction in the same way
-        // The database automatically compacts during normal operations
-        // For now, we'll just return Ok(()) as a no-op
-        // In a real implementation, you might want to trigger a checkpoint or similar operation
+        let db = Arc::clone(&self.db);
+        let records = self.records.clone();
+
+        // Waits for in-flight writes, then rewrites the file to release
+        // pages freed by deletes and overwrites, and by records encrypted
+        // with a retired key or none
+        task::spawn_blocking(move || {
+            reencrypt(&db, &records)?;
+            db.compact()
+        })
+        .await??;
+
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<()> {
+        let db = Arc::clone(&self.db);
+
+        // An immediate-durability commit persists all commits before it
+        task::spawn_blocking(move || {
+            let mut write_txn = db.begin_write()?;
+            write_txn.set_durability(Durability::Immediate);
+            write_txn.commit()?;
+            Ok::<(), anyhow::Error>(())
+        })
+        .await??;
+
         Ok(())
     }
 
@@ -149,20 +898,13 @@ impl Storage for RedbStorage {
         let db = self.db.clone();
 
         let collections = task::spawn_blocking(move || {
+            // COLLECTIONS_TABLE only has collections with vectors in them
             let read_txn = db.begin_read()?;
-            let table = read_txn.open_table(VECTORS_TABLE)?;
-
-            let mut collections = std::collections::HashSet::new();
-
-            for item in table.iter()? {
-                let (_, data) = item?;
-                let vector: Vector = serde_json::from_slice(data.value())?;
-                if let Some(collection) = vector.collection {
-                    collections.insert(collection);
-                }
-            }
-
-            Ok::<Vec<String>, anyhow::Error>(collections.into_iter().collect())
+            let table = read_txn.open_table(COLLECTIONS_TABLE)?;
+            table
+                .iter()?
+                .map(|item| Ok(item?.0.value().to_string()))
+                .collect::<Result<Vec<String>>>()
         })
         .await??;
 
@@ -172,30 +914,86 @@ impl Storage for RedbStorage {
     async fn get_vectors_in_collection(&self, collection: &str) -> Result<Vec<Vector>> {
         let db = self.db.clone();
         let collection = collection.to_string();
+        let records = self.records.clone();
 
         let vectors = task::spawn_blocking(move || {
             let read_txn = db.begin_read()?;
             let table = read_txn.open_table(VECTORS_TABLE)?;
+            let ids = collection_ids(
+                &read_txn.open_table(COLLECTION_IDS_TABLE)?,
+                &collection,
+                None,
+            )?;
+            read_records(&table, &records, &ids)
+        })
+        .await??;
 
-            let mut vectors = Vec::new();
+        Ok(vectors)
+    }
 
-            for item in table.iter()? {
-                let (_, data) = item?;
-                let vector: Vector = serde_json::from_slice(data.value())?;
-                if vector.collection.as_ref() == Some(&collection) {
-                    vectors.push(vector);
-                }
+    async fn scan_collection(
+        &self,
+        collection: Option<&str>,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<Vector>> {
+        let db = Arc::clone(&self.db);
+        let collection = collection.map(str::to_string);
+        let after = after.map(str::to_string);
+        let records = self.records.clone();
+
+        task::spawn_blocking(move || {
+            let read_txn = db.begin_read()?;
+            let table = read_txn.open_table(VECTORS_TABLE)?;
+            if let Some(collection) = &collection {
+                let mut ids = collection_ids(
+                    &read_txn.open_table(COLLECTION_IDS_TABLE)?,
+                    collection,
+                    after.as_deref(),
+                )?;
+                ids.truncate(limit);
+                return read_records(&table, &records, &ids);
             }
 
-            Ok::<Vec<Vector>, anyhow::Error>(vectors)
+            let start = match &after {
+                Some(after) => Bound::Excluded(after.as_str()),
+                None => Bound::Unbounded,
+            };
+            table
+                .range::<&str>((start, Bound::Unbounded))?
+                .take(limit)
+                .map(|item| records.decode(item?.1.value()))
+                .collect()
         })
-        .await??;
+        .await?
+    }
 
-        Ok(vectors)
+    async fn ids_with_metadata(&self, key: &str, value: &str) -> Result<Vec<String>> {
+        let db = Arc::clone(&self.db);
+        let key = key.to_string();
+        let value = value.to_string();
+
+        task::spawn_blocking(move || {
+            let read_txn = db.begin_read()?;
+            let table = read_txn.open_table(METADATA_IDS_TABLE)?;
+            let start = (key.as_str(), value.as_str(), "");
+            let mut ids = Vec::new();
+            for item in table.range(start..)? {
+                let (entry, _) = item?;
+                let (entry_key, entry_value, id) = entry.value();
+                if entry_key != key || entry_value != value {
+                    break;
+                }
+                ids.push(id.to_string());
+            }
+            Ok(ids)
+        })
+        .await?
     }
 
     async fn get_first_vector(&self) -> Result<Option<Vector>> {
         let db = Arc::clone(&self.db);
+        let records = self.records.clone();
 
         let first_vector = task::spawn_blocking(move || {
             let read_txn = db.begin_read()?;
@@ -204,9 +1002,7 @@ impl Storage for RedbStorage {
             let mut iter = table.iter()?;
             let result = if let Some(first) = iter.next() {
                 let (_, value) = first?;
-                let vector_data = value.value();
-                let vector: Vector = serde_json::from_slice(vector_data)?;
-                Some(vector)
+                Some(records.decode(value.value())?)
             } else {
                 None
             };
@@ -216,4 +1012,686 @@ impl Storage for RedbStorage {
 
         Ok(first_vector)
     }
+
+    async fn list_vectors(&self) -> Result<Vec<Vector>> {
+        let db = Arc::clone(&self.db);
+        let records = self.records.clone();
+
+        let vectors = task::spawn_blocking(move || {
+            let read_txn = db.begin_read()?;
+            let table = read_txn.open_table(VECTORS_TABLE)?;
+
+            let mut vectors = Vec::new();
+            for item in table.iter()? {
+                let (_, value) = item?;
+                vectors.push(records.decode(value.value())?);
+            }
+            Ok::<Vec<Vector>, anyhow::Error>(vectors)
+        })
+        .await??;
+
+        Ok(vectors)
+    }
+
+    async fn verify(&self) -> Result<VerifyReport> {
+        let db = Arc::clone(&self.db);
+        let records = self.records.clone();
+
+        let report = task::spawn_blocking(move || {
+            let read_txn = db.begin_read()?;
+            let table = read_txn.open_table(VECTORS_TABLE)?;
+
+            let mut report = VerifyReport::default();
+            for item in table.iter()? {
+                let (id, value) = item?;
+                report.checked += 1;
+                if let Err(e) = records.decode(value.value()) {
+                    report.corrupt.push(CorruptRecord {
+                        id: id.value().to_string(),
+                        error: e.to_string(),
+                    });
+                }
+            }
+            Ok::<VerifyReport, anyhow::Error>(report)
+        })
+        .await??;
+
+        Ok(report)
+    }
+
+    async fn repair_vector(&self, vector: &Vector) -> Result<()> {
+        let db = Arc::clone(&self.db);
+        let vector = vector.clone();
+        let records = self.records.clone();
+
+        task::spawn_blocking(move || {
+            let write_txn = db.begin_write()?;
+            let corrupt = match write_txn
+                .open_table(VECTORS_TABLE)?
+                .get(vector.id.as_str())?
+            {
+                Some(data) => records.decode(data.value()).is_err(),
+                None => false,
+            };
+            // A corrupt record can't be swapped out the usual way, which
+            // decodes it to find its index entries
+            if corrupt {
+                remove_corrupt(&write_txn, &records, &vector.id)?;
+            }
+            write_vectors(&write_txn, &records, &[vector], &[], 0, true)?;
+            write_txn.commit()?;
+            Ok::<(), anyhow::Error>(())
+        })
+        .await??;
+
+        Ok(())
+    }
+
+    async fn wal_head(&self) -> Result<u64> {
+        let db = Arc::clone(&self.db);
+
+        task::spawn_blocking(move || {
+            let read_txn = db.begin_read()?;
+            let metadata = read_txn.open_table(METADATA_TABLE)?;
+            read_wal_head(&metadata)
+        })
+        .await?
+    }
+
+    async fn wal_since(&self, seq: u64) -> Result<Vec<WalEntry>> {
+        let db = Arc::clone(&self.db);
+
+        let entries = task::spawn_blocking(move || {
+            let read_txn = db.begin_read()?;
+            let table = read_txn.open_table(WAL_TABLE)?;
+
+            let mut entries = Vec::new();
+            for item in table.range(seq.saturating_add(1)..)? {
+                let (_, value) = item?;
+                entries.push(serde_json::from_slice(value.value())?);
+            }
+            Ok::<Vec<WalEntry>, anyhow::Error>(entries)
+        })
+        .await??;
+
+        Ok(entries)
+    }
+
+    async fn wal_page(&self, seq: u64, limit: usize) -> Result<Vec<WalEntry>> {
+        let db = Arc::clone(&self.db);
+
+        task::spawn_blocking(move || {
+            let read_txn = db.begin_read()?;
+            let table = read_txn.open_table(WAL_TABLE)?;
+            table
+                .range(seq.saturating_add(1)..)?
+                .take(limit)
+                .map(|item| Ok(serde_json::from_slice(item?.1.value())?))
+                .collect()
+        })
+        .await?
+    }
+
+    async fn truncate_wal(&self, seq: u64) -> Result<()> {
+        let db = Arc::clone(&self.db);
+
+        task::spawn_blocking(move || {
+            let write_txn = db.begin_write()?;
+            {
+                let retained = write_txn
+                    .open_table(METADATA_TABLE)?
+                    .get(WAL_RETAIN_KEY)?
+                    .map(|data| serde_json::from_slice::<u64>(data.value()))
+                    .transpose()?;
+                let seq = retained.map_or(seq, |retained| seq.min(retained));
+                let mut table = write_txn.open_table(WAL_TABLE)?;
+                table.retain_in(..=seq, |_, _| false)?;
+            }
+            write_txn.commit()?;
+            Ok::<(), anyhow::Error>(())
+        })
+        .await??;
+
+        Ok(())
+    }
+
+    async fn retain_wal_after(&self, seq: u64) -> Result<()> {
+        let db = Arc::clone(&self.db);
+
+        task::spawn_blocking(move || {
+            let write_txn = db.begin_write()?;
+            {
+                let mut metadata = write_txn.open_table(METADATA_TABLE)?;
+                metadata.insert(WAL_RETAIN_KEY, serde_json::to_vec(&seq)?.as_slice())?;
+            }
+            write_txn.commit()?;
+            Ok::<(), anyhow::Error>(())
+        })
+        .await??;
+
+        Ok(())
+    }
+
+    async fn append_audit(&self, entry: &AuditEntry) -> Result<u64> {
+        let db = Arc::clone(&self.db);
+        let entry = entry.clone();
+
+        task::spawn_blocking(move || {
+            let write_txn = db.begin_write()?;
+            let seq = {
+                let mut metadata = write_txn.open_table(METADATA_TABLE)?;
+                let seq = read_u64(&metadata, AUDIT_HEAD_KEY)? + 1;
+                metadata.insert(AUDIT_HEAD_KEY, serde_json::to_vec(&seq)?.as_slice())?;
+                let entry = AuditEntry {
+                    seq,
+                    timestamp: unix_now(),
+                    ..entry
+                };
+                let mut audit = write_txn.open_table(AUDIT_TABLE)?;
+                audit.insert(seq, serde_json::to_vec(&entry)?.as_slice())?;
+                seq
+            };
+            write_txn.commit()?;
+            Ok(seq)
+        })
+        .await?
+    }
+
+    async fn audit_page(&self, seq: u64, limit: usize) -> Result<Vec<AuditEntry>> {
+        let db = Arc::clone(&self.db);
+
+        task::spawn_blocking(move || {
+            let read_txn = db.begin_read()?;
+            let table = read_txn.open_table(AUDIT_TABLE)?;
+            table
+                .range(seq.saturating_add(1)..)?
+                .take(limit)
+                .map(|item| Ok(serde_json::from_slice(item?.1.value())?))
+                .collect()
+        })
+        .await?
+    }
+
+    async fn prune_audit(&self, before: u64) -> Result<usize> {
+        let db = Arc::clone(&self.db);
+
+        task::spawn_blocking(move || {
+            let write_txn = db.begin_write()?;
+            let pruned = {
+                let mut table = write_txn.open_table(AUDIT_TABLE)?;
+                // Entries are appended in time order, so the old ones come
+                // first
+                let mut last = None;
+                let mut pruned = 0;
+                for item in table.iter()? {
+                    let (seq, data) = item?;
+                    let entry: AuditEntry = serde_json::from_slice(data.value())?;
+                    if entry.timestamp >= before {
+                        break;
+                    }
+                    last = Some(seq.value());
+                    pruned += 1;
+                }
+                if let Some(last) = last {
+                    table.retain_in(..=last, |_, _| false)?;
+                }
+                pruned
+            };
+            write_txn.commit()?;
+            Ok::<usize, anyhow::Error>(pruned)
+        })
+        .await?
+    }
+
+    async fn get_versions(&self, id: &str) -> Result<Vec<Vector>> {
+        let db = Arc::clone(&self.db);
+        let id = id.to_string();
+        let records = self.records.clone();
+
+        task::spawn_blocking(move || {
+            let read_txn = db.begin_read()?;
+            let table = read_txn.open_table(VERSIONS_TABLE)?;
+            match table.get(id.as_str())? {
+                Some(data) => records.decode_versions(data.value()),
+                None => Ok(Vec::new()),
+            }
+        })
+        .await?
+    }
+
+    async fn put_versions(&self, id: &str, versions: &[Vector]) -> Result<()> {
+        let db = Arc::clone(&self.db);
+        let id = id.to_string();
+        let encoded = self.records.encode_versions(versions)?;
+
+        task::spawn_blocking(move || {
+            let write_txn = db.begin_write()?;
+            {
+                let mut table = write_txn.open_table(VERSIONS_TABLE)?;
+                if encoded.is_empty() {
+                    table.remove(id.as_str())?;
+                } else {
+                    table.insert(id.as_str(), encoded.as_slice())?;
+                }
+            }
+            write_txn.commit()?;
+            Ok::<(), anyhow::Error>(())
+        })
+        .await??;
+
+        Ok(())
+    }
+
+    async fn get_setting(&self, key: &str) -> Result<Option<String>> {
+        let db = Arc::clone(&self.db);
+        let key = setting_key(key);
+
+        task::spawn_blocking(move || {
+            let read_txn = db.begin_read()?;
+            let metadata = read_txn.open_table(METADATA_TABLE)?;
+            let value = match metadata.get(key.as_str())? {
+                Some(data) => Some(String::from_utf8(data.value().to_vec())?),
+                None => None,
+            };
+            Ok(value)
+        })
+        .await?
+    }
+
+    async fn put_setting(&self, key: &str, value: &str) -> Result<()> {
+        let db = Arc::clone(&self.db);
+        let key = setting_key(key);
+        let value = value.to_string();
+
+        task::spawn_blocking(move || {
+            let write_txn = db.begin_write()?;
+            {
+                let mut metadata = write_txn.open_table(METADATA_TABLE)?;
+                metadata.insert(key.as_str(), value.as_bytes())?;
+            }
+            write_txn.commit()?;
+            Ok::<(), anyhow::Error>(())
+        })
+        .await??;
+
+        Ok(())
+    }
+
+    async fn create_snapshot(&self, collection: &str, name: &str) -> Result<SnapshotInfo> {
+        let db = Arc::clone(&self.db);
+        let collection = collection.to_string();
+        let name = name.to_string();
+
+        let info = task::spawn_blocking(move || {
+            let write_txn = db.begin_write()?;
+            let info = {
+                let mut snapshots = write_txn.open_table(SNAPSHOTS_TABLE)?;
+                if snapshots
+                    .get((collection.as_str(), name.as_str()))?
+                    .is_some()
+                {
+                    return Err(anyhow!(
+                        "Snapshot '{}' already exists for collection '{}'",
+                        name,
+                        collection
+                    ));
+                }
+
+                let vectors = write_txn.open_table(VECTORS_TABLE)?;
+                let mut snapshot_vectors = write_txn.open_table(SNAPSHOT_VECTORS_TABLE)?;
+                let mut vector_count = 0;
+
+                let ids = collection_ids(
+                    &write_txn.open_table(COLLECTION_IDS_TABLE)?,
+                    &collection,
+                    None,
+                )?;
+                for id in &ids {
+                    if let Some(data) = vectors.get(id.as_str())? {
+                        snapshot_vectors.insert(
+                            (collection.as_str(), name.as_str(), id.as_str()),
+                            data.value(),
+                        )?;
+                        vector_count += 1;
+                    }
+                }
+
+                let info = SnapshotInfo {
+                    name: name.clone(),
+                    collection: collection.clone(),
+                    vector_count,
+                    created_at: std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)?
+                        .as_secs(),
+                };
+                let serialized = serde_json::to_vec(&info)?;
+                snapshots.insert((collection.as_str(), name.as_str()), serialized.as_slice())?;
+                info
+            };
+            write_txn.commit()?;
+            Ok::<SnapshotInfo, anyhow::Error>(info)
+        })
+        .await??;
+
+        Ok(info)
+    }
+
+    async fn get_snapshot(&self, collection: &str, name: &str) -> Result<Option<SnapshotInfo>> {
+        let db = Arc::clone(&self.db);
+        let collection = collection.to_string();
+        let name = name.to_string();
+
+        let info = task::spawn_blocking(move || {
+            let read_txn = db.begin_read()?;
+            let table = read_txn.open_table(SNAPSHOTS_TABLE)?;
+
+            match table.get((collection.as_str(), name.as_str()))? {
+                Some(data) => {
+                    let info: SnapshotInfo = serde_json::from_slice(data.value())?;
+                    Ok::<Option<SnapshotInfo>, anyhow::Error>(Some(info))
+                }
+                None => Ok::<Option<SnapshotInfo>, anyhow::Error>(None),
+            }
+        })
+        .await??;
+
+        Ok(info)
+    }
+
+    async fn list_snapshots(&self, collection: &str) -> Result<Vec<SnapshotInfo>> {
+        let db = Arc::clone(&self.db);
+        let collection = collection.to_string();
+
+        let snapshots = task::spawn_blocking(move || {
+            let read_txn = db.begin_read()?;
+            let table = read_txn.open_table(SNAPSHOTS_TABLE)?;
+
+            let mut snapshots = Vec::new();
+            for item in table.range((collection.as_str(), "")..)? {
+                let (key, data) = item?;
+                if key.value().0 != collection {
+                    break;
+                }
+                snapshots.push(serde_json::from_slice::<SnapshotInfo>(data.value())?);
+            }
+
+            Ok::<Vec<SnapshotInfo>, anyhow::Error>(snapshots)
+        })
+        .await??;
+
+        Ok(snapshots)
+    }
+
+    async fn get_snapshot_vectors(&self, collection: &str, name: &str) -> Result<Vec<Vector>> {
+        let db = Arc::clone(&self.db);
+        let records = self.records.clone();
+        let collection = collection.to_string();
+        let name = name.to_string();
+
+        let vectors = task::spawn_blocking(move || {
+            let read_txn = db.begin_read()?;
+            let table = read_txn.open_table(SNAPSHOT_VECTORS_TABLE)?;
+
+            let mut vectors = Vec::new();
+            for item in table.range((collection.as_str(), name.as_str(), "")..)? {
+                let (key, data) = item?;
+                let (key_collection, key_name, _) = key.value();
+                if key_collection != collection || key_name != name {
+                    break;
+                }
+                vectors.push(records.decode(data.value())?);
+            }
+
+            Ok::<Vec<Vector>, anyhow::Error>(vectors)
+        })
+        .await??;
+
+        Ok(vectors)
+    }
+
+    async fn delete_snapshot(&self, collection: &str, name: &str) -> Result<bool> {
+        let db = Arc::clone(&self.db);
+        let collection = collection.to_string();
+        let name = name.to_string();
+
+        let existed = task::spawn_blocking(move || {
+            let write_txn = db.begin_write()?;
+            let existed = {
+                let mut snapshots = write_txn.open_table(SNAPSHOTS_TABLE)?;
+                let existed = snapshots
+                    .remove((collection.as_str(), name.as_str()))?
+                    .is_some();
+
+                let mut snapshot_vectors = write_txn.open_table(SNAPSHOT_VECTORS_TABLE)?;
+                let mut ids = Vec::new();
+                for item in snapshot_vectors.range((collection.as_str(), name.as_str(), "")..)? {
+                    let (key, _) = item?;
+                    let (key_collection, key_name, id) = key.value();
+                    if key_collection != collection || key_name != name {
+                        break;
+                    }
+                    ids.push(id.to_string());
+                }
+                for id in &ids {
+                    snapshot_vectors.remove((collection.as_str(), name.as_str(), id.as_str()))?;
+                }
+
+                existed
+            };
+            write_txn.commit()?;
+            Ok::<bool, anyhow::Error>(existed)
+        })
+        .await??;
+
+        Ok(existed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Cipher;
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn test_compressed_and_legacy_records() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let data_dir = temp_dir.path().to_str().unwrap();
+
+        let metadata = HashMap::from([("text".to_string(), "lorem ipsum ".repeat(100))]);
+        let compressed = Vector::with_id("compressed".to_string(), vec![0.5; 64])
+            .with_metadata(metadata.clone());
+        let legacy = Vector::with_id("legacy".to_string(), vec![1.0, 2.0])
+            .with_collection("old".to_string())
+            .with_metadata(HashMap::from([("lang".to_string(), "la".to_string())]));
+
+        {
+            let storage = RedbStorage::new(data_dir)
+                .await
+                .unwrap()
+                .with_compression(true);
+            storage.store_vector(&compressed).await.unwrap();
+            let bytes = storage.vector_bytes().await.unwrap();
+            assert!(bytes.stored < bytes.raw / 4);
+
+            // A record from before the format byte, and counters that were
+            // never kept
+            let write_txn = storage.db.begin_write().unwrap();
+            {
+                let mut table = write_txn.open_table(VECTORS_TABLE).unwrap();
+                let json = serde_json::to_vec(&legacy).unwrap();
+                table.insert("legacy", json.as_slice()).unwrap();
+                let mut metadata = write_txn.open_table(METADATA_TABLE).unwrap();
+                metadata.remove(RAW_BYTES_KEY).unwrap();
+                metadata.remove(STORED_BYTES_KEY).unwrap();
+                metadata.remove(VECTOR_COUNT_KEY).unwrap();
+                metadata.remove(COLLECTION_STATS_KEY).unwrap();
+                metadata.remove(SECONDARY_INDEXES_KEY).unwrap();
+            }
+            write_txn.commit().unwrap();
+        }
+
+        let storage = RedbStorage::new(data_dir).await.unwrap();
+        let read = storage.get_vector("compressed").await.unwrap().unwrap();
+        assert_eq!(read.metadata, Some(metadata));
+        let read = storage.get_vector("legacy").await.unwrap().unwrap();
+        assert_eq!(read.data, legacy.data);
+        assert_eq!(storage.count_vectors().await.unwrap(), 2);
+        assert_eq!(storage.list_collections().await.unwrap(), vec!["old"]);
+        let in_old = storage.get_vectors_in_collection("old").await.unwrap();
+        assert_eq!(in_old.len(), 1);
+        assert_eq!(
+            storage.ids_with_metadata("lang", "la").await.unwrap(),
+            vec!["legacy"]
+        );
+
+        let legacy_len = serde_json::to_vec(&legacy).unwrap().len() as u64;
+        let bytes = storage.vector_bytes().await.unwrap();
+        storage.delete_vector("compressed", 0).await.unwrap();
+        assert!(bytes.raw > legacy_len);
+        assert_eq!(
+            storage.vector_bytes().await.unwrap(),
+            VectorBytes {
+                raw: legacy_len,
+                stored: legacy_len
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verify_and_repair_corrupt_records() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let storage = RedbStorage::new(temp_dir.path().to_str().unwrap())
+            .await
+            .unwrap();
+        let vector = Vector::with_id("a".to_string(), vec![1.0, 2.0])
+            .with_collection("docs".to_string())
+            .with_metadata(HashMap::from([("lang".to_string(), "en".to_string())]));
+        storage.store_vector(&vector).await.unwrap();
+        storage
+            .store_vector(&Vector::with_id("b".to_string(), vec![3.0, 4.0]))
+            .await
+            .unwrap();
+        let bytes = storage.vector_bytes().await.unwrap();
+
+        // Flip a bit in the stored record
+        let write_txn = storage.db.begin_write().unwrap();
+        {
+            let mut table = write_txn.open_table(VECTORS_TABLE).unwrap();
+            let mut record = table.get("a").unwrap().unwrap().value().to_vec();
+            let last = record.len() - 1;
+            record[last] ^= 1;
+            table.insert("a", record.as_slice()).unwrap();
+        }
+        write_txn.commit().unwrap();
+
+        assert!(storage.get_vector("a").await.is_err());
+        let report = storage.verify().await.unwrap();
+        assert_eq!(report.checked, 2);
+        assert_eq!(report.corrupt.len(), 1);
+        assert_eq!(report.corrupt[0].id, "a");
+
+        storage.repair_vector(&vector).await.unwrap();
+        assert!(storage.verify().await.unwrap().corrupt.is_empty());
+        assert_eq!(
+            storage.get_vector("a").await.unwrap().unwrap().data,
+            vector.data
+        );
+        assert_eq!(storage.count_vectors().await.unwrap(), 2);
+        assert_eq!(storage.vector_bytes().await.unwrap(), bytes);
+        let stats = storage.collection_stats("docs").await.unwrap().unwrap();
+        assert_eq!(stats.vector_count, 1);
+        assert_eq!(
+            storage.ids_with_metadata("lang", "en").await.unwrap(),
+            vec!["a"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_records_and_key_rotation() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let data_dir = temp_dir.path().to_str().unwrap();
+        let (old, new) = ([7; 32], [9; 32]);
+        let open = |keys: Option<EncryptionKeys>| async move {
+            let storage = RedbStorage::new(data_dir).await.unwrap();
+            match keys {
+                Some(keys) => storage.with_encryption(keys),
+                None => storage,
+            }
+        };
+        let vector =
+            Vector::with_id("a".to_string(), vec![1.0, 2.0]).with_collection("docs".to_string());
+
+        {
+            let storage = open(Some(EncryptionKeys::new(Cipher::Aes256Gcm, old))).await;
+            storage.store_vector(&vector).await.unwrap();
+            storage.put_versions("a", std::slice::from_ref(&vector)).await.unwrap();
+            storage.create_snapshot("docs", "s1").await.unwrap();
+            // Nothing readable is left in the file
+            let read_txn = storage.db.begin_read().unwrap();
+            let table = read_txn.open_table(VECTORS_TABLE).unwrap();
+            let record = table.get("a").unwrap().unwrap().value().to_vec();
+            assert!(!record.windows(4).any(|window| window == b"docs"));
+        }
+        let unkeyed = open(None).await;
+        assert!(unkeyed.get_vector("a").await.is_err());
+        drop(unkeyed);
+
+        // Rotate: the old key stays readable until compaction re-encrypts
+        {
+            let keys = EncryptionKeys::new(Cipher::XChaCha20Poly1305, new).with_previous(old);
+            let storage = open(Some(keys)).await;
+            assert_eq!(
+                storage.get_vector("a").await.unwrap().unwrap().data,
+                vector.data
+            );
+            storage.compact().await.unwrap();
+            let stats = storage.collection_stats("docs").await.unwrap().unwrap();
+            assert_eq!(
+                stats.stored_bytes,
+                storage.vector_bytes().await.unwrap().stored
+            );
+        }
+        let storage = open(Some(EncryptionKeys::new(Cipher::XChaCha20Poly1305, new))).await;
+        assert_eq!(
+            storage.get_vector("a").await.unwrap().unwrap().data,
+            vector.data
+        );
+        assert_eq!(storage.get_versions("a").await.unwrap().len(), 1);
+        assert_eq!(
+            storage
+                .get_snapshot_vectors("docs", "s1")
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(storage.verify().await.unwrap().corrupt.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_compact_releases_deleted_pages() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let storage = RedbStorage::new(temp_dir.path().to_str().unwrap())
+            .await
+            .unwrap();
+
+        let vectors: Vec<Vector> = (0..2000)
+            .map(|i| Vector::with_id(i.to_string(), vec![i as f32; 128]))
+            .collect();
+        storage.bulk_load(&vectors).await.unwrap();
+        let ids: Vec<String> = vectors.iter().skip(10).map(|v| v.id.clone()).collect();
+        storage.write_batch(&[], &ids, 0).await.unwrap();
+        let before = storage.size_bytes().await.unwrap();
+
+        // Blocked by an open read transaction
+        let read_txn = storage.db.begin_read().unwrap();
+        assert!(storage.compact().await.is_err());
+        drop(read_txn);
+
+        storage.compact().await.unwrap();
+        assert!(storage.size_bytes().await.unwrap() < before);
+        assert_eq!(storage.count_vectors().await.unwrap(), 10);
+        assert!(storage.get_vector("5").await.unwrap().is_some());
+    }
 }
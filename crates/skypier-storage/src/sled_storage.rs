@@ -0,0 +1,774 @@
+use anyhow::{anyhow, Result};
+use sled::transaction::{
+    ConflictableTransactionError, TransactionError, Transactional, TransactionalTree,
+};
+use sled::{Db, Tree};
+use std::collections::{HashMap, HashSet};
+use std::ops::Bound;
+use tokio::task;
+
+use crate::record::{decode_vector, encode_vector, raw_len, removed_raw_len, Records};
+use crate::{
+    setting_key, unix_now, AuditEntry, CollectionStats, CorruptRecord, SnapshotInfo, Storage,
+    Vector, VectorBytes, VerifyReport, WalEntry, WalOp,
+};
+
+// Same keys as RedbStorage keeps in its metadata table
+const WAL_HEAD_KEY: &str = "wal_head";
+const WAL_RETAIN_KEY: &str = "wal_retain_after";
+const AUDIT_HEAD_KEY: &str = "audit_head";
+const RAW_BYTES_KEY: &str = "vector_raw_bytes";
+const STORED_BYTES_KEY: &str = "vector_stored_bytes";
+const VECTOR_COUNT_KEY: &str = "vector_count";
+const DIMENSIONS_KEY: &str = "dimensions";
+
+// sled's log-structured storage keeps up with heavy write loads better than
+// redb's copy-on-write B-trees. Same data model: vectors by id, metadata,
+// a WAL and an audit log keyed by big-endian seq, collection stats, earlier
+// versions by id, and snapshots keyed by `collection \0 name [\0 id]`.
+pub struct SledStorage {
+    db: Db,
+    vectors: Tree,
+    metadata: Tree,
+    wal: Tree,
+    audit: Tree,
+    collections: Tree,
+    snapshots: Tree,
+    snapshot_vectors: Tree,
+    versions: Tree,
+    compression: bool,
+}
+
+impl SledStorage {
+    pub async fn new(data_dir: &str) -> Result<Self> {
+        let path = std::path::Path::new(data_dir).join("vectors.sled");
+        let db = task::spawn_blocking(move || sled::open(path)).await??;
+
+        Ok(Self {
+            vectors: db.open_tree("vectors")?,
+            metadata: db.open_tree("metadata")?,
+            wal: db.open_tree("wal")?,
+            audit: db.open_tree("audit")?,
+            collections: db.open_tree("collections")?,
+            snapshots: db.open_tree("snapshots")?,
+            snapshot_vectors: db.open_tree("snapshot_vectors")?,
+            versions: db.open_tree("versions")?,
+            db,
+            compression: false,
+        })
+    }
+
+    // Compresses vector records written from now on with zstd
+    pub fn with_compression(mut self, compression: bool) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    fn read_u64(&self, key: &str) -> Result<u64> {
+        match self.metadata.get(key)? {
+            Some(data) => Ok(serde_json::from_slice(&data)?),
+            None => Ok(0),
+        }
+    }
+}
+
+type TxResult<T> = std::result::Result<T, ConflictableTransactionError<String>>;
+
+fn abort<T>(error: impl std::fmt::Display) -> TxResult<T> {
+    Err(ConflictableTransactionError::Abort(error.to_string()))
+}
+
+fn tx_error(error: TransactionError<String>) -> anyhow::Error {
+    anyhow!("sled transaction failed: {}", error)
+}
+
+fn read_u64_tx(metadata: &TransactionalTree, key: &str) -> TxResult<u64> {
+    match metadata.get(key)? {
+        Some(data) => serde_json::from_slice(&data).or_else(abort),
+        None => Ok(0),
+    }
+}
+
+fn write_u64_tx(metadata: &TransactionalTree, key: &str, value: u64) -> TxResult<()> {
+    let data = serde_json::to_vec(&value).or_else(abort)?;
+    metadata.insert(key, data)?;
+    Ok(())
+}
+
+// Logs a change inside the transaction that makes it
+fn append_wal_tx(
+    metadata: &TransactionalTree,
+    wal: &TransactionalTree,
+    id: &str,
+    op: WalOp,
+    collection: Option<&str>,
+    replaced: bool,
+    hlc: u64,
+) -> TxResult<()> {
+    let seq = read_u64_tx(metadata, WAL_HEAD_KEY)? + 1;
+    write_u64_tx(metadata, WAL_HEAD_KEY, seq)?;
+
+    let entry = WalEntry::new(seq, id, op, collection, replaced, hlc);
+    let data = serde_json::to_vec(&entry).or_else(abort)?;
+    wal.insert(&seq.to_be_bytes(), data)?;
+    Ok(())
+}
+
+fn update_vector_bytes_tx(
+    metadata: &TransactionalTree,
+    added: Option<&[u8]>,
+    removed: Option<&[u8]>,
+) -> TxResult<()> {
+    let mut raw = read_u64_tx(metadata, RAW_BYTES_KEY)?;
+    let mut stored = read_u64_tx(metadata, STORED_BYTES_KEY)?;
+    if let Some(record) = added {
+        raw += raw_len(record).or_else(abort)?;
+        stored += record.len() as u64;
+    }
+    if let Some(record) = removed {
+        raw = raw.saturating_sub(removed_raw_len(record));
+        stored = stored.saturating_sub(record.len() as u64);
+    }
+    write_u64_tx(metadata, RAW_BYTES_KEY, raw)?;
+    write_u64_tx(metadata, STORED_BYTES_KEY, stored)
+}
+
+// `dimensions` is that of the vector written, if one was
+fn update_vector_count_tx(
+    metadata: &TransactionalTree,
+    added: bool,
+    removed: bool,
+    dimensions: Option<usize>,
+) -> TxResult<()> {
+    let count = (read_u64_tx(metadata, VECTOR_COUNT_KEY)? + u64::from(added))
+        .saturating_sub(u64::from(removed));
+    let dimensions = match (count, dimensions) {
+        (0, _) => 0,
+        (_, Some(dimensions)) => dimensions as u64,
+        (_, None) => read_u64_tx(metadata, DIMENSIONS_KEY)?,
+    };
+    write_u64_tx(metadata, VECTOR_COUNT_KEY, count)?;
+    write_u64_tx(metadata, DIMENSIONS_KEY, dimensions)
+}
+
+fn update_collection_tx(
+    collections: &TransactionalTree,
+    collection: &str,
+    update: impl FnOnce(&mut CollectionStats),
+) -> TxResult<()> {
+    let mut stats: CollectionStats = match collections.get(collection)? {
+        Some(data) => serde_json::from_slice(&data).or_else(abort)?,
+        None => CollectionStats::default(),
+    };
+    update(&mut stats);
+    if stats.vector_count == 0 {
+        collections.remove(collection)?;
+    } else {
+        collections.insert(collection, serde_json::to_vec(&stats).or_else(abort)?)?;
+    }
+    Ok(())
+}
+
+fn update_collection_stats_tx(
+    collections: &TransactionalTree,
+    added: Option<(&Vector, &[u8])>,
+    removed: Option<&[u8]>,
+) -> TxResult<()> {
+    let now = unix_now();
+    if let Some(record) = removed {
+        if let Some(collection) = decode_vector(record).or_else(abort)?.collection {
+            update_collection_tx(collections, &collection, |stats| {
+                stats.removed(record.len(), now)
+            })?;
+        }
+    }
+    if let Some((vector, record)) = added {
+        if let Some(collection) = &vector.collection {
+            update_collection_tx(collections, collection, |stats| {
+                stats.added(vector, record.len(), now)
+            })?;
+        }
+    }
+    Ok(())
+}
+
+fn snapshot_key(collection: &str, name: &str) -> Vec<u8> {
+    let mut key = collection.as_bytes().to_vec();
+    key.push(0);
+    key.extend_from_slice(name.as_bytes());
+    key
+}
+
+fn snapshot_vector_prefix(collection: &str, name: &str) -> Vec<u8> {
+    let mut prefix = snapshot_key(collection, name);
+    prefix.push(0);
+    prefix
+}
+
+#[async_trait::async_trait]
+impl Storage for SledStorage {
+    async fn store_vector(&self, vector: &Vector) -> Result<()> {
+        let record = encode_vector(vector, self.compression)?;
+
+        (&self.vectors, &self.metadata, &self.wal, &self.collections)
+            .transaction(|(vectors, metadata, wal, collections)| {
+                let previous = vectors.insert(vector.id.as_bytes(), record.as_slice())?;
+                update_vector_bytes_tx(metadata, Some(&record), previous.as_deref())?;
+                update_vector_count_tx(
+                    metadata,
+                    true,
+                    previous.is_some(),
+                    Some(vector.dimensions()),
+                )?;
+                update_collection_stats_tx(
+                    collections,
+                    Some((vector, &record)),
+                    previous.as_deref(),
+                )?;
+                append_wal_tx(
+                    metadata,
+                    wal,
+                    &vector.id,
+                    WalOp::Upsert,
+                    vector.collection.as_deref(),
+                    previous.is_some(),
+                    vector.hlc,
+                )
+            })
+            .map_err(tx_error)
+    }
+
+    async fn bulk_load(&self, vectors: &[Vector]) -> Result<()> {
+        let mut batch = sled::Batch::default();
+        let mut raw = self.read_u64(RAW_BYTES_KEY)?;
+        let mut stored = self.read_u64(STORED_BYTES_KEY)?;
+        let mut collections: HashMap<&str, CollectionStats> = HashMap::new();
+        let now = unix_now();
+        for vector in vectors {
+            let record = encode_vector(vector, self.compression)?;
+            raw += raw_len(&record)?;
+            stored += record.len() as u64;
+            if let Some(collection) = &vector.collection {
+                collections
+                    .entry(collection)
+                    .or_default()
+                    .added(vector, record.len(), now);
+            }
+            batch.insert(vector.id.as_bytes(), record);
+        }
+
+        // Offline into an empty store only, so the counters don't need to be
+        // in the same batch and start from zero
+        self.vectors.apply_batch(batch)?;
+        for (collection, stats) in collections {
+            self.collections
+                .insert(collection, serde_json::to_vec(&stats)?)?;
+        }
+        self.metadata
+            .insert(RAW_BYTES_KEY, serde_json::to_vec(&raw)?)?;
+        self.metadata
+            .insert(STORED_BYTES_KEY, serde_json::to_vec(&stored)?)?;
+        let count = self.vectors.len() as u64;
+        let dimensions = vectors.last().map_or(0, Vector::dimensions) as u64;
+        self.metadata
+            .insert(VECTOR_COUNT_KEY, serde_json::to_vec(&count)?)?;
+        self.metadata
+            .insert(DIMENSIONS_KEY, serde_json::to_vec(&dimensions)?)?;
+        Ok(())
+    }
+
+    async fn write_batch(
+        &self,
+        upserts: &[Vector],
+        deletes: &[String],
+        deleted_at: u64,
+    ) -> Result<()> {
+        let records = upserts
+            .iter()
+            .map(|vector| encode_vector(vector, self.compression))
+            .collect::<Result<Vec<_>>>()?;
+
+        let trees = (
+            &self.vectors,
+            &self.metadata,
+            &self.wal,
+            &self.collections,
+            &self.versions,
+        );
+        trees
+            .transaction(|(vectors, metadata, wal, collections, versions)| {
+                for (vector, record) in upserts.iter().zip(&records) {
+                    let previous = vectors.insert(vector.id.as_bytes(), record.as_slice())?;
+                    update_vector_bytes_tx(metadata, Some(record), previous.as_deref())?;
+                    update_vector_count_tx(
+                        metadata,
+                        true,
+                        previous.is_some(),
+                        Some(vector.dimensions()),
+                    )?;
+                    update_collection_stats_tx(
+                        collections,
+                        Some((vector, record)),
+                        previous.as_deref(),
+                    )?;
+                    append_wal_tx(
+                        metadata,
+                        wal,
+                        &vector.id,
+                        WalOp::Upsert,
+                        vector.collection.as_deref(),
+                        previous.is_some(),
+                        vector.hlc,
+                    )?;
+                }
+                for id in deletes {
+                    let Some(previous) = vectors.remove(id.as_bytes())? else {
+                        continue;
+                    };
+                    versions.remove(id.as_bytes())?;
+                    update_vector_bytes_tx(metadata, None, Some(&previous))?;
+                    update_vector_count_tx(metadata, false, true, None)?;
+                    update_collection_stats_tx(collections, None, Some(&previous))?;
+                    let collection = decode_vector(&previous).or_else(abort)?.collection;
+                    append_wal_tx(
+                        metadata,
+                        wal,
+                        id,
+                        WalOp::Delete,
+                        collection.as_deref(),
+                        false,
+                        deleted_at,
+                    )?;
+                }
+                Ok(())
+            })
+            .map_err(tx_error)
+    }
+
+    async fn get_vector(&self, id: &str) -> Result<Option<Vector>> {
+        match self.vectors.get(id)? {
+            Some(data) => Ok(Some(decode_vector(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn delete_vector(&self, id: &str, deleted_at: u64) -> Result<bool> {
+        let trees = (
+            &self.vectors,
+            &self.metadata,
+            &self.wal,
+            &self.collections,
+            &self.versions,
+        );
+        trees
+            .transaction(|(vectors, metadata, wal, collections, versions)| {
+                let Some(previous) = vectors.remove(id.as_bytes())? else {
+                    return Ok(false);
+                };
+                versions.remove(id.as_bytes())?;
+                update_vector_bytes_tx(metadata, None, Some(&previous))?;
+                update_vector_count_tx(metadata, false, true, None)?;
+                update_collection_stats_tx(collections, None, Some(&previous))?;
+                let collection = decode_vector(&previous).or_else(abort)?.collection;
+                append_wal_tx(
+                    metadata,
+                    wal,
+                    id,
+                    WalOp::Delete,
+                    collection.as_deref(),
+                    false,
+                    deleted_at,
+                )?;
+                Ok(true)
+            })
+            .map_err(tx_error)
+    }
+
+    async fn count_vectors(&self) -> Result<usize> {
+        Ok(self.read_u64(VECTOR_COUNT_KEY)? as usize)
+    }
+
+    async fn dimensions(&self) -> Result<usize> {
+        Ok(self.read_u64(DIMENSIONS_KEY)? as usize)
+    }
+
+    async fn size_bytes(&self) -> Result<usize> {
+        Ok(self.db.size_on_disk()? as usize)
+    }
+
+    fn record_len(&self, vector: &Vector) -> Result<u64> {
+        Ok(encode_vector(vector, self.compression)?.len() as u64)
+    }
+
+    async fn vector_bytes(&self) -> Result<VectorBytes> {
+        Ok(VectorBytes {
+            raw: self.read_u64(RAW_BYTES_KEY)?,
+            stored: self.read_u64(STORED_BYTES_KEY)?,
+        })
+    }
+
+    async fn collection_stats(&self, collection: &str) -> Result<Option<CollectionStats>> {
+        match self.collections.get(collection)? {
+            Some(data) => Ok(Some(serde_json::from_slice(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn compact(&self) -> Result<()> {
+        // sled reclaims space from its log in the background
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.db.flush_async().await?;
+        Ok(())
+    }
+
+    async fn backup(&self, backup_path: &str) -> Result<()> {
+        let db = self.db.clone();
+        let path = std::path::Path::new(backup_path).join("vectors.sled");
+
+        task::spawn_blocking(move || {
+            let backup = sled::open(path)?;
+            backup.import(db.export());
+            backup.flush()?;
+            Ok::<(), anyhow::Error>(())
+        })
+        .await??;
+
+        Ok(())
+    }
+
+    async fn list_collections(&self) -> Result<Vec<String>> {
+        let mut collections = HashSet::new();
+        for item in self.vectors.iter() {
+            let (_, data) = item?;
+            if let Some(collection) = decode_vector(&data)?.collection {
+                collections.insert(collection);
+            }
+        }
+        Ok(collections.into_iter().collect())
+    }
+
+    async fn get_vectors_in_collection(&self, collection: &str) -> Result<Vec<Vector>> {
+        let mut vectors = Vec::new();
+        for item in self.vectors.iter() {
+            let (_, data) = item?;
+            let vector = decode_vector(&data)?;
+            if vector.collection.as_deref() == Some(collection) {
+                vectors.push(vector);
+            }
+        }
+        Ok(vectors)
+    }
+
+    async fn scan_collection(
+        &self,
+        collection: Option<&str>,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<Vector>> {
+        let start = match after {
+            Some(after) => Bound::Excluded(after.as_bytes()),
+            None => Bound::Unbounded,
+        };
+        let mut vectors = Vec::new();
+        for item in self.vectors.range::<&[u8], _>((start, Bound::Unbounded)) {
+            if vectors.len() == limit {
+                break;
+            }
+            let (_, data) = item?;
+            let vector = decode_vector(&data)?;
+            if collection.is_none() || vector.collection.as_deref() == collection {
+                vectors.push(vector);
+            }
+        }
+        Ok(vectors)
+    }
+
+    async fn get_first_vector(&self) -> Result<Option<Vector>> {
+        match self.vectors.first()? {
+            Some((_, data)) => Ok(Some(decode_vector(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn list_vectors(&self) -> Result<Vec<Vector>> {
+        let mut vectors = Vec::new();
+        for item in self.vectors.iter() {
+            let (_, data) = item?;
+            vectors.push(decode_vector(&data)?);
+        }
+        Ok(vectors)
+    }
+
+    async fn verify(&self) -> Result<VerifyReport> {
+        let mut report = VerifyReport::default();
+        for item in self.vectors.iter() {
+            let (id, data) = item?;
+            report.checked += 1;
+            if let Err(e) = decode_vector(&data) {
+                report.corrupt.push(CorruptRecord {
+                    id: String::from_utf8_lossy(&id).into_owned(),
+                    error: e.to_string(),
+                });
+            }
+        }
+        Ok(report)
+    }
+
+    async fn repair_vector(&self, vector: &Vector) -> Result<()> {
+        let record = encode_vector(vector, self.compression)?;
+
+        (&self.vectors, &self.metadata, &self.wal, &self.collections)
+            .transaction(|(vectors, metadata, wal, collections)| {
+                let previous = vectors.insert(vector.id.as_bytes(), record.as_slice())?;
+                update_vector_bytes_tx(metadata, Some(&record), previous.as_deref())?;
+                update_vector_count_tx(
+                    metadata,
+                    true,
+                    previous.is_some(),
+                    Some(vector.dimensions()),
+                )?;
+                let now = unix_now();
+                match previous.as_deref().map(decode_vector) {
+                    Some(Ok(_)) | None => update_collection_stats_tx(
+                        collections,
+                        Some((vector, &record)),
+                        previous.as_deref(),
+                    )?,
+                    // There's no telling which collection a corrupt record
+                    // was in, so it's taken to be the good copy's
+                    Some(Err(_)) => {
+                        if let Some(collection) = &vector.collection {
+                            let removed = previous.as_deref().map_or(0, <[u8]>::len);
+                            update_collection_tx(collections, collection, |stats| {
+                                stats.removed(removed, now);
+                                stats.added(vector, record.len(), now);
+                            })?;
+                        }
+                    }
+                }
+                append_wal_tx(
+                    metadata,
+                    wal,
+                    &vector.id,
+                    WalOp::Upsert,
+                    vector.collection.as_deref(),
+                    previous.is_some(),
+                    vector.hlc,
+                )
+            })
+            .map_err(tx_error)
+    }
+
+    async fn wal_head(&self) -> Result<u64> {
+        self.read_u64(WAL_HEAD_KEY)
+    }
+
+    async fn wal_since(&self, seq: u64) -> Result<Vec<WalEntry>> {
+        let mut entries = Vec::new();
+        for item in self.wal.range(seq.saturating_add(1).to_be_bytes()..) {
+            let (_, data) = item?;
+            entries.push(serde_json::from_slice(&data)?);
+        }
+        Ok(entries)
+    }
+
+    async fn wal_page(&self, seq: u64, limit: usize) -> Result<Vec<WalEntry>> {
+        self.wal
+            .range(seq.saturating_add(1).to_be_bytes()..)
+            .take(limit)
+            .map(|item| Ok(serde_json::from_slice(&item?.1)?))
+            .collect()
+    }
+
+    async fn truncate_wal(&self, seq: u64) -> Result<()> {
+        let seq = match self.metadata.get(WAL_RETAIN_KEY)? {
+            Some(data) => seq.min(serde_json::from_slice(&data)?),
+            None => seq,
+        };
+        let mut batch = sled::Batch::default();
+        for item in self.wal.range(..=seq.to_be_bytes()) {
+            let (key, _) = item?;
+            batch.remove(key);
+        }
+        self.wal.apply_batch(batch)?;
+        Ok(())
+    }
+
+    async fn retain_wal_after(&self, seq: u64) -> Result<()> {
+        self.metadata
+            .insert(WAL_RETAIN_KEY, serde_json::to_vec(&seq)?)?;
+        Ok(())
+    }
+
+    async fn append_audit(&self, entry: &AuditEntry) -> Result<u64> {
+        (&self.metadata, &self.audit)
+            .transaction(|(metadata, audit)| {
+                let seq = read_u64_tx(metadata, AUDIT_HEAD_KEY)? + 1;
+                write_u64_tx(metadata, AUDIT_HEAD_KEY, seq)?;
+                let entry = AuditEntry {
+                    seq,
+                    timestamp: unix_now(),
+                    ..entry.clone()
+                };
+                let data = serde_json::to_vec(&entry).or_else(abort)?;
+                audit.insert(&seq.to_be_bytes(), data)?;
+                Ok(seq)
+            })
+            .map_err(tx_error)
+    }
+
+    async fn audit_page(&self, seq: u64, limit: usize) -> Result<Vec<AuditEntry>> {
+        self.audit
+            .range(seq.saturating_add(1).to_be_bytes()..)
+            .take(limit)
+            .map(|item| Ok(serde_json::from_slice(&item?.1)?))
+            .collect()
+    }
+
+    async fn prune_audit(&self, before: u64) -> Result<usize> {
+        // Entries are appended in time order, so the old ones come first
+        let mut batch = sled::Batch::default();
+        let mut pruned = 0;
+        for item in self.audit.iter() {
+            let (key, data) = item?;
+            let entry: AuditEntry = serde_json::from_slice(&data)?;
+            if entry.timestamp >= before {
+                break;
+            }
+            batch.remove(key);
+            pruned += 1;
+        }
+        self.audit.apply_batch(batch)?;
+        Ok(pruned)
+    }
+
+    async fn get_versions(&self, id: &str) -> Result<Vec<Vector>> {
+        match self.versions.get(id)? {
+            Some(data) => Records::default().decode_versions(&data),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    async fn put_versions(&self, id: &str, versions: &[Vector]) -> Result<()> {
+        if versions.is_empty() {
+            self.versions.remove(id)?;
+        } else {
+            self.versions.insert(
+                id,
+                Records::new(self.compression).encode_versions(versions)?,
+            )?;
+        }
+        Ok(())
+    }
+
+    async fn get_setting(&self, key: &str) -> Result<Option<String>> {
+        match self.metadata.get(setting_key(key))? {
+            Some(data) => Ok(Some(String::from_utf8(data.to_vec())?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn put_setting(&self, key: &str, value: &str) -> Result<()> {
+        self.metadata.insert(setting_key(key), value.as_bytes())?;
+        Ok(())
+    }
+
+    // sled transactions can't scan, so the collection is read before the
+    // transaction that writes the snapshot. Writes racing the snapshot may or
+    // may not make it in.
+    async fn create_snapshot(&self, collection: &str, name: &str) -> Result<SnapshotInfo> {
+        let mut records = Vec::new();
+        for item in self.vectors.iter() {
+            let (_, data) = item?;
+            let vector = decode_vector(&data)?;
+            if vector.collection.as_deref() == Some(collection) {
+                records.push((vector.id, data));
+            }
+        }
+
+        let info = SnapshotInfo {
+            name: name.to_string(),
+            collection: collection.to_string(),
+            vector_count: records.len(),
+            created_at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)?
+                .as_secs(),
+        };
+        let serialized = serde_json::to_vec(&info)?;
+        let key = snapshot_key(collection, name);
+        let prefix = snapshot_vector_prefix(collection, name);
+
+        (&self.snapshots, &self.snapshot_vectors)
+            .transaction(|(snapshots, snapshot_vectors)| {
+                if snapshots.get(&key)?.is_some() {
+                    return abort(format!(
+                        "Snapshot '{}' already exists for collection '{}'",
+                        name, collection
+                    ));
+                }
+                for (id, data) in &records {
+                    let mut vector_key = prefix.clone();
+                    vector_key.extend_from_slice(id.as_bytes());
+                    snapshot_vectors.insert(vector_key, data.clone())?;
+                }
+                snapshots.insert(key.as_slice(), serialized.as_slice())?;
+                Ok(())
+            })
+            .map_err(tx_error)?;
+
+        Ok(info)
+    }
+
+    async fn get_snapshot(&self, collection: &str, name: &str) -> Result<Option<SnapshotInfo>> {
+        match self.snapshots.get(snapshot_key(collection, name))? {
+            Some(data) => Ok(Some(serde_json::from_slice(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn list_snapshots(&self, collection: &str) -> Result<Vec<SnapshotInfo>> {
+        let mut prefix = collection.as_bytes().to_vec();
+        prefix.push(0);
+
+        let mut snapshots = Vec::new();
+        for item in self.snapshots.scan_prefix(prefix) {
+            let (_, data) = item?;
+            snapshots.push(serde_json::from_slice(&data)?);
+        }
+        Ok(snapshots)
+    }
+
+    async fn get_snapshot_vectors(&self, collection: &str, name: &str) -> Result<Vec<Vector>> {
+        let mut vectors = Vec::new();
+        for item in self
+            .snapshot_vectors
+            .scan_prefix(snapshot_vector_prefix(collection, name))
+        {
+            let (_, data) = item?;
+            vectors.push(decode_vector(&data)?);
+        }
+        Ok(vectors)
+    }
+
+    async fn delete_snapshot(&self, collection: &str, name: &str) -> Result<bool> {
+        let existed = self
+            .snapshots
+            .remove(snapshot_key(collection, name))?
+            .is_some();
+
+        let mut batch = sled::Batch::default();
+        for item in self
+            .snapshot_vectors
+            .scan_prefix(snapshot_vector_prefix(collection, name))
+        {
+            let (key, _) = item?;
+            batch.remove(key);
+        }
+        self.snapshot_vectors.apply_batch(batch)?;
+
+        Ok(existed)
+    }
+}
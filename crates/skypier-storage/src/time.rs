@@ -0,0 +1,6 @@
+// std's clock panics in the browser (wasm32-unknown-unknown), so the crates
+// read time through here, which uses the browser's clock there instead
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub use std::time::{Instant, SystemTime, UNIX_EPOCH};
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub use web_time::{Instant, SystemTime, UNIX_EPOCH};
@@ -0,0 +1,46 @@
+#![no_main]
+
+// Feeds arbitrary insert/delete/search sequences (including NaN, infinite
+// and mismatched-length vectors) to the HNSW index, checking it never panics
+// and stays consistent with the set of ids it was given.
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use skypier_index::{HnswIndex, VectorIndex};
+use std::collections::HashSet;
+
+#[derive(Debug, Arbitrary)]
+enum Op {
+    Insert { id: u8, vector: Vec<f32> },
+    Delete { id: u8 },
+    Search { query: Vec<f32>, k: u8 },
+    Clear,
+}
+
+fuzz_target!(|ops: Vec<Op>| {
+    let index = HnswIndex::new(8).unwrap();
+    let mut ids = HashSet::new();
+
+    for op in ops {
+        match op {
+            Op::Insert { id, vector } => {
+                index.add_vector(&id.to_string(), &vector).unwrap();
+                ids.insert(id.to_string());
+            }
+            Op::Delete { id } => {
+                let removed = index.remove_vector(&id.to_string()).unwrap();
+                assert_eq!(removed, ids.remove(&id.to_string()));
+            }
+            Op::Search { query, k } => {
+                let results = index.search(&query, k as usize).unwrap();
+                assert!(results.len() <= k as usize);
+                assert!(results.iter().all(|r| ids.contains(&r.id)));
+            }
+            Op::Clear => {
+                index.clear();
+                ids.clear();
+            }
+        }
+        assert_eq!(index.size(), ids.len());
+    }
+});
@@ -1,34 +1,658 @@
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    response::Json,
-    routing::{get, post},
+    async_trait,
+    body::Bytes,
+    error_handling::HandleErrorLayer,
+    extract::{
+        ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        ConnectInfo, DefaultBodyLimit, FromRequestParts, MatchedPath, Path, Query, Request, State,
+    },
+    http::{header, request::Parts, Extensions, HeaderMap, Method, StatusCode, Uri},
+    middleware::{self, Next},
+    response::{IntoResponse, Json, Response},
+    routing::{delete, get, post},
     Router,
 };
+use axum_server::tls_rustls::RustlsConfig;
 use serde::{Deserialize, Serialize};
-use skypier_core::{Vector, VectorDatabase};
-use std::collections::HashMap;
+use skypier_core::{
+    Aggregate, AuditEntry, BatchCheck, ChangeEvent, CloneOptions, ClusterOptions, Clustering,
+    Conflict, ConflictSide, Dedup, DistanceMetric, Dtype, FieldError, Fusion, Grouping, IdScheme,
+    Job, JobManager, MetadataBoost, MetadataSchema, Quota, QuotaExceeded, QuotaLimit,
+    ReadOnlyError, Rerank, ScoreMode, ScrollPage, SearchFilter, SearchGroup, SearchOptions,
+    SearchProfile, SnapshotDiff, SnapshotInfo, SparseVector, Usage, ValidationError, Vector,
+    VectorDatabase,
+};
+use skypier_network::{
+    ForwardRequest, ForwardResponse, Member, NodeHandle, WriteConcern, WriteReport,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use tower::limit::GlobalConcurrencyLimitLayer;
+use tower::timeout::TimeoutLayer;
+use tower::{BoxError, ServiceBuilder};
+use tower_http::compression::CompressionLayer;
 use tower_http::cors::CorsLayer;
-use tracing::info;
+use tracing::{error, info, warn};
+
+use crate::audit::{AuditLog, AuditPage, AuditQuery};
+use crate::auth::{self, ApiKeyInfo, ApiKeys, Role};
+use crate::backup;
+use crate::cluster::{self, Placement};
+use crate::codec::Encoded;
+use crate::dataset::{self, ColumnMapping, DatasetFormat};
+#[cfg(feature = "embeddings")]
+use crate::embeddings::Embedder;
+use crate::export::{self, ExportFormat};
+use crate::knn_graph::{self, GraphFormat};
+use crate::namespace::Namespaces;
+use crate::rate_limit::{Client, RateLimiter};
+use crate::raw;
+use crate::replication::{Follower, FollowerStatus};
+use crate::replicator::{ReplicationStatus, Replicator};
+use crate::slow_queries::{SlowQuery, SlowQueryLog};
+
+#[derive(Clone)]
+pub struct AppState {
+    pub namespaces: Arc<Namespaces>,
+    pub max_body_bytes: usize,
+    // Past these, requests are answered 408 or 503 instead of piling up
+    pub request_timeout: Option<Duration>,
+    pub max_concurrent_requests: Option<usize>,
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    pub auth: Option<Arc<ApiKeys>>,
+    #[cfg(feature = "embeddings")]
+    pub embedder: Option<Arc<dyn Embedder>>,
+    // Metadata key a vector's source text is stored under
+    pub text_field: String,
+    pub jobs: Arc<JobManager>,
+    pub slow_queries: Option<Arc<SlowQueryLog>>,
+    // Records writes and admin actions when set
+    pub audit: Option<Arc<AuditLog>>,
+    // Set when this node follows a primary
+    pub follower: Option<Arc<Follower>>,
+    // The P2P node, for cluster membership
+    pub cluster: Option<NodeHandle>,
+    // Whether requests for collections other members own go to them
+    pub cluster_mode: bool,
+    // Which members hold each collection in cluster mode
+    pub placement: Arc<Placement>,
+    // Sends owners' writes to the other replicas, throttled
+    pub replicator: Arc<Replicator>,
+}
+
+impl AppState {
+    pub fn new(db: Arc<VectorDatabase>) -> Self {
+        Self {
+            namespaces: Arc::new(Namespaces::single(db)),
+            max_body_bytes: 16 * 1024 * 1024,
+            request_timeout: None,
+            max_concurrent_requests: None,
+            rate_limiter: None,
+            auth: None,
+            #[cfg(feature = "embeddings")]
+            embedder: None,
+            text_field: "text".to_string(),
+            jobs: Arc::new(JobManager::default()),
+            slow_queries: None,
+            audit: None,
+            follower: None,
+            cluster: None,
+            cluster_mode: false,
+            placement: Arc::new(Placement::default()),
+            replicator: Arc::new(Replicator::default()),
+        }
+    }
+
+    pub fn with_namespaces(mut self, namespaces: Arc<Namespaces>) -> Self {
+        self.namespaces = namespaces;
+        self
+    }
+
+    pub fn with_rate_limiter(mut self, limiter: Arc<RateLimiter>) -> Self {
+        self.rate_limiter = Some(limiter);
+        self
+    }
+
+    pub fn with_auth(mut self, keys: Arc<ApiKeys>) -> Self {
+        self.auth = Some(keys);
+        self
+    }
+
+    pub fn with_max_body_bytes(mut self, max_body_bytes: usize) -> Self {
+        self.max_body_bytes = max_body_bytes;
+        self
+    }
+
+    pub fn with_request_limits(
+        mut self,
+        timeout: Option<Duration>,
+        max_concurrent: Option<usize>,
+    ) -> Self {
+        self.request_timeout = timeout;
+        self.max_concurrent_requests = max_concurrent;
+        self
+    }
+
+    pub fn with_slow_queries(mut self, log: Arc<SlowQueryLog>) -> Self {
+        self.slow_queries = Some(log);
+        self
+    }
+
+    pub fn with_audit(mut self, log: Arc<AuditLog>) -> Self {
+        self.audit = Some(log);
+        self
+    }
+
+    pub fn with_follower(mut self, follower: Arc<Follower>) -> Self {
+        self.follower = Some(follower);
+        self
+    }
+
+    pub fn with_cluster(mut self, cluster: NodeHandle) -> Self {
+        self.cluster = Some(cluster);
+        self
+    }
+
+    pub fn with_cluster_mode(mut self, cluster_mode: bool) -> Self {
+        self.cluster_mode = cluster_mode;
+        self
+    }
+
+    pub fn with_placement(mut self, placement: Arc<Placement>) -> Self {
+        self.placement = placement;
+        self
+    }
+
+    pub fn with_replicator(mut self, replicator: Arc<Replicator>) -> Self {
+        self.replicator = replicator;
+        self
+    }
+
+    // The P2P node to forward requests through, in cluster mode
+    fn sharded(&self) -> Option<&NodeHandle> {
+        self.cluster.as_ref().filter(|_| self.cluster_mode)
+    }
+
+    pub fn with_text_field(mut self, text_field: &str) -> Self {
+        self.text_field = text_field.to_string();
+        self
+    }
+
+    #[cfg(feature = "embeddings")]
+    pub fn with_embedder(mut self, embedder: Arc<dyn Embedder>) -> Self {
+        self.embedder = Some(embedder);
+        self
+    }
+}
+
+// The namespace a request is for: the `/namespaces/{name}` path prefix,
+// else the namespace header, else the default one
+pub struct Tenant {
+    pub namespace: String,
+    pub db: Arc<VectorDatabase>,
+    pub max_vectors: Option<usize>,
+    pub max_bytes: Option<u64>,
+}
+
+// Left by `strip_namespace_prefix` for `Tenant`
+#[derive(Clone)]
+struct NamespacePrefix(String);
+
+// The id of the API key `authorize` accepted for a request
+#[derive(Clone)]
+struct AuthenticatedKey(String);
+
+#[async_trait]
+impl FromRequestParts<AppState> for Tenant {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, ApiError> {
+        let namespace = requested_namespace(&parts.extensions, &parts.headers, &state.namespaces);
+        Self::open(&state.namespaces, namespace).await
+    }
+}
+
+impl Tenant {
+    pub(crate) async fn open(namespaces: &Namespaces, namespace: String) -> Result<Self, ApiError> {
+        if !crate::namespace::valid_name(&namespace) {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                format!("Invalid namespace name '{}'", namespace),
+            ));
+        }
+
+        let db = namespaces.get(&namespace).await?.ok_or_else(|| {
+            ApiError::new(
+                StatusCode::NOT_FOUND,
+                format!("Namespace '{}' not found", namespace),
+            )
+        })?;
+        let quota = namespaces.quota(&namespace).cloned().unwrap_or_default();
+        Ok(Self {
+            namespace,
+            db,
+            max_vectors: quota.max_vectors,
+            max_bytes: quota.max_bytes,
+        })
+    }
+}
+
+fn requested_namespace(
+    extensions: &Extensions,
+    headers: &HeaderMap,
+    namespaces: &Namespaces,
+) -> String {
+    match extensions.get::<NamespacePrefix>() {
+        Some(NamespacePrefix(name)) => name.clone(),
+        None => match headers.get(namespaces.header()) {
+            Some(value) => String::from_utf8_lossy(value.as_bytes()).into_owned(),
+            None => namespaces.default_name().to_string(),
+        },
+    }
+}
+
+// Takes a token from the client's bucket: the API key if the request has
+// one, its namespace otherwise
+async fn rate_limit(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let Some(limiter) = &state.rate_limiter else {
+        return next.run(request).await;
+    };
+    if request.uri().path().starts_with("/health") {
+        return next.run(request).await;
+    }
+
+    // Runs after `authorize`, so only keys it accepted get a bucket of
+    // their own; made-up ones would otherwise each get a fresh one
+    let namespace = requested_namespace(request.extensions(), request.headers(), &state.namespaces);
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(peer)| peer.ip());
+    let client = match (request.extensions().get::<AuthenticatedKey>(), peer) {
+        (Some(AuthenticatedKey(key_id)), _) => Client::Key(key_id),
+        (None, Some(ip)) => Client::Peer(ip, &namespace),
+        (None, None) => Client::Namespace(&namespace),
+    };
+    match limiter.check(client, Instant::now()) {
+        Ok(()) => next.run(request).await,
+        Err(wait) => {
+            let retry_after = wait.as_secs_f64().ceil().clamp(1.0, u32::MAX as f64) as u64;
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, retry_after.to_string())],
+                Json(ErrorResponse {
+                    error: format!("Rate limit exceeded, retry in {}s", retry_after),
+                    fields: Vec::new(),
+                    usage: None,
+                }),
+            )
+                .into_response()
+        }
+    }
+}
+
+// The least a key needs for a route. Searches and recommendations are
+// reads even though they are POSTs.
+fn required_role(method: &Method, route: &str) -> Role {
+    match (method, route) {
+        (_, route) if route.starts_with("/admin/") => Role::Admin,
+        // Every tenant's stats and quotas
+        (_, "/namespaces") => Role::Admin,
+        (&Method::POST, "/collections/:collection/snapshots")
+        | (&Method::DELETE, "/collections/:collection/snapshots/:name")
+        | (&Method::PUT, "/collections/:collection/config")
+        | (&Method::PUT, "/aliases/:alias")
+        | (&Method::DELETE, "/aliases/:alias") => Role::Admin,
+        (&Method::GET, _) => Role::Read,
+        (&Method::POST, route)
+            if route.ends_with("/search")
+                || route.starts_with("/search/")
+                || route.ends_with("/recommend") =>
+        {
+            Role::Read
+        }
+        // Reads the compatibility APIs make with POST
+        (&Method::POST, route)
+            if route.ends_with("/query")
+                || route.ends_with("/get")
+                || route == "/qdrant/collections/:collection/points" =>
+        {
+            Role::Read
+        }
+        _ => Role::Write,
+    }
+}
+
+async fn authorize(State(state): State<AppState>, mut request: Request, next: Next) -> Response {
+    let Some(keys) = &state.auth else {
+        return next.run(request).await;
+    };
+    let route = match request.extensions().get::<MatchedPath>() {
+        Some(route) => route.as_str().to_string(),
+        None => request.uri().path().to_string(),
+    };
+    if route.starts_with("/health") {
+        return next.run(request).await;
+    }
+
+    let role = match request.headers().get(keys.header()) {
+        Some(key) => keys.role(&String::from_utf8_lossy(key.as_bytes())).await,
+        None => None,
+    };
+    let Some(role) = role else {
+        return ApiError::new(StatusCode::UNAUTHORIZED, "A valid API key is required")
+            .into_response();
+    };
+    let required = required_role(request.method(), &route);
+    if role < required {
+        return ApiError::new(
+            StatusCode::FORBIDDEN,
+            format!("This needs a {:?} key", required).to_lowercase(),
+        )
+        .into_response();
+    }
+    let key_id = request
+        .headers()
+        .get(keys.header())
+        .map(|key| auth::key_id(&String::from_utf8_lossy(key.as_bytes())));
+    if let Some(key_id) = key_id {
+        request.extensions_mut().insert(AuthenticatedKey(key_id));
+    }
+    next.run(request).await
+}
+
+// Records requests that write or administer, once they're answered, so
+// refused attempts are recorded with their status too. Reads, including
+// admin ones, aren't recorded.
+async fn audit(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let Some(log) = &state.audit else {
+        return next.run(request).await;
+    };
+    let route = match request.extensions().get::<MatchedPath>() {
+        Some(route) => route.as_str().to_string(),
+        None => request.uri().path().to_string(),
+    };
+    let method = request.method().clone();
+    if method == Method::GET
+        || route.starts_with("/health")
+        || required_role(&method, &route) == Role::Read
+    {
+        return next.run(request).await;
+    }
+
+    let key_id = state
+        .auth
+        .as_ref()
+        .and_then(|keys| request.headers().get(keys.header()))
+        .map(|key| auth::key_id(&String::from_utf8_lossy(key.as_bytes())));
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(peer)| *peer);
+    let entry = AuditEntry {
+        namespace: requested_namespace(request.extensions(), request.headers(), &state.namespaces),
+        action: format!("{} {}", method, route),
+        path: request.uri().path().to_string(),
+        key_id,
+        client_ip: log.client_ip(request.headers(), peer),
+        ..Default::default()
+    };
+    let response = next.run(request).await;
+    log.record(&AuditEntry {
+        status: response.status().as_u16(),
+        ..entry
+    })
+    .await;
+    response
+}
 
-pub type AppState = Arc<VectorDatabase>;
+// Turns `/namespaces/{name}/rest` into `/rest` before routing, so every
+// route is also served under a namespace prefix
+fn strip_namespace_prefix(mut request: Request) -> Request {
+    let path = request.uri().path();
+    let Some((name, rest)) = path
+        .strip_prefix("/namespaces/")
+        .and_then(|path| path.split_once('/'))
+    else {
+        return request;
+    };
+    let (name, rest) = (name.to_string(), format!("/{}", rest));
+
+    let path_and_query = match request.uri().query() {
+        Some(query) => format!("{}?{}", rest, query),
+        None => rest,
+    };
+    let mut parts = request.uri().clone().into_parts();
+    parts.path_and_query = path_and_query.parse().ok();
+    if let Ok(uri) = Uri::from_parts(parts) {
+        *request.uri_mut() = uri;
+        request.extensions_mut().insert(NamespacePrefix(name));
+    }
+    request
+}
 
 #[derive(Debug, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    pub error: String,
+    // Each metadata key that didn't match the collection's schema
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<FieldError>,
+    // What the collection or namespace holds, when a write would take it
+    // past its quota
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+}
+
+// Error type for handlers that need to explain a failure to the client.
+// Validation failures become 400s and writes to a follower 403s; anything
+// else is logged and reported as an opaque 500.
+#[derive(Debug)]
+pub struct ApiError {
+    pub(crate) status: StatusCode,
+    pub(crate) message: String,
+    pub(crate) fields: Vec<FieldError>,
+    pub(crate) usage: Option<Usage>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+            fields: Vec::new(),
+            usage: None,
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (
+            self.status,
+            Json(ErrorResponse {
+                error: self.message,
+                fields: self.fields,
+                usage: self.usage,
+            }),
+        )
+            .into_response()
+    }
+}
+
+impl From<StatusCode> for ApiError {
+    fn from(status: StatusCode) -> Self {
+        Self::new(status, status.canonical_reason().unwrap_or("error"))
+    }
+}
+
+// 403 for too many vectors, as before there were byte quotas, and 413 for
+// too many bytes
+impl From<QuotaExceeded> for ApiError {
+    fn from(err: QuotaExceeded) -> Self {
+        let status = match err.limit {
+            QuotaLimit::Vectors => StatusCode::FORBIDDEN,
+            QuotaLimit::Bytes => StatusCode::PAYLOAD_TOO_LARGE,
+        };
+        let mut error = Self::new(status, err.to_string());
+        error.usage = Some(err.usage);
+        error
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(err: anyhow::Error) -> Self {
+        if let Some(read_only) = err.downcast_ref::<ReadOnlyError>() {
+            return Self::new(StatusCode::FORBIDDEN, read_only.to_string());
+        }
+        if let Some(exceeded) = err.downcast_ref::<QuotaExceeded>() {
+            return Self::from(exceeded.clone());
+        }
+        match err.downcast_ref::<ValidationError>() {
+            Some(validation_error) => {
+                let mut error = Self::new(StatusCode::BAD_REQUEST, validation_error.to_string());
+                if let ValidationError::SchemaViolation { errors, .. } = validation_error {
+                    error.fields = errors.clone();
+                }
+                error
+            }
+            None => {
+                error!("Request failed: {:#}", err);
+                Self::from(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct InsertRequest {
     pub vectors: Vec<Vector>,
+    // In cluster mode, how many replicas must have the vectors before the
+    // insert answers, and for how long to wait for them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub write_concern: Option<WriteConcern>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub write_timeout_ms: Option<u64>,
 }
 
+// The ids of the inserted vectors, or with a write concern, how far they
+// were replicated as well
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum InsertResponse {
+    Ids(Vec<String>),
+    Report(WriteReport),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ValidateResponse {
+    // Whether POST /vectors would accept the batch as it stands
+    pub valid: bool,
+    #[serde(flatten)]
+    pub check: BatchCheck,
+    // Set when the batch would take the namespace over its vector quota
+    pub quota_error: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct SearchRequest {
+    // May be left out when `sparse` is given
+    #[serde(default)]
     pub vector: Vec<f32>,
     pub k: Option<usize>,
     pub threshold: Option<f32>,
+    // Exact metadata values the results must all have
+    #[serde(default)]
+    pub filter: Option<HashMap<String, String>>,
+    // Rescore `fetch_factor * k` candidates exactly, plus any boosts
+    #[serde(default)]
+    pub rerank: bool,
+    pub fetch_factor: Option<usize>,
+    #[serde(default)]
+    pub boost: Option<Vec<MetadataBoost>>,
+    // Return `k` groups of up to `group_size` hits per value of this field
+    pub group_by: Option<String>,
+    pub group_size: Option<usize>,
+    // Search this named vector instead of `data`
+    pub vector_name: Option<String>,
+    // Searched alone, or fused with `vector`'s hits when both are given
+    pub sparse: Option<SparseVector>,
+    pub fusion: Option<Fusion>,
+    // Scan every stored vector instead of the index
+    #[serde(default)]
+    pub exact: bool,
+    // Embedding model that produced `vector`
+    pub model: Option<String>,
+    // Report scores as the metric gives them, normalized to 0-1, or as
+    // distances; `threshold` is read the same way
+    #[serde(default)]
+    pub score_mode: ScoreMode,
+    // Leave out results further than this from `vector`, in the metric's
+    // distance whatever `score_mode` is
+    pub max_distance: Option<f32>,
+    // Return each hit's stored vector, the named one when `vector_name` is
+    // given, and its metadata (on by default)
+    #[serde(default)]
+    pub include_vector: bool,
+    pub include_metadata: Option<bool>,
+    // Only these metadata keys are returned, to keep large ones such as
+    // document text out of the response
+    pub fields: Option<Vec<String>>,
+}
+
+impl SearchRequest {
+    fn options(&self) -> SearchOptions {
+        SearchOptions {
+            vector_name: self.vector_name.clone(),
+            rerank: self.rerank.then(|| Rerank {
+                fetch_factor: self
+                    .fetch_factor
+                    .unwrap_or_else(|| Rerank::default().fetch_factor),
+                boosts: self.boost.clone().unwrap_or_default(),
+            }),
+            exact: self.exact,
+            model: self.model.clone(),
+            cancel: None,
+            score_mode: self.score_mode,
+            max_distance: self.max_distance,
+            include_vector: self.include_vector,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SearchResponse {
     pub results: Vec<SearchResult>,
+    // Grouped searches fill this in instead of `results`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub groups: Option<Vec<SearchGroup>>,
+}
+
+impl SearchResponse {
+    fn for_each_metadata(&mut self, f: impl Fn(&mut Option<HashMap<String, String>>)) {
+        for result in &mut self.results {
+            f(&mut result.metadata);
+        }
+        for hit in self
+            .groups
+            .iter_mut()
+            .flatten()
+            .flat_map(|group| &mut group.hits)
+        {
+            f(&mut hit.metadata);
+        }
+    }
+}
+
+// Keeps only the metadata keys asked for
+fn select_fields(metadata: &mut Option<HashMap<String, String>>, fields: &[String]) {
+    if let Some(metadata) = metadata {
+        metadata.retain(|key, _| fields.contains(key));
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -36,6 +660,62 @@ pub struct SearchResult {
     pub id: String,
     pub score: f32,
     pub metadata: Option<HashMap<String, String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vector: Option<Vec<f32>>,
+}
+
+impl From<skypier_core::SearchResult> for SearchResult {
+    fn from(result: skypier_core::SearchResult) -> Self {
+        Self {
+            id: result.id,
+            score: result.score,
+            metadata: result.metadata,
+            vector: result.vector,
+        }
+    }
+}
+
+// A search for vectors like the `positive` examples and unlike the
+// `negative` ones, which are stored vector ids. Everything else is as for
+// /search, except that there's no `vector` and no sparse or grouped search.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RecommendRequest {
+    pub positive: Vec<String>,
+    #[serde(default)]
+    pub negative: Vec<String>,
+    // How far the query is pushed from the negatives, 1 unless given
+    pub negative_weight: Option<f32>,
+    // Scopes the search, like the collection of /collections/:c/search
+    pub collection: Option<String>,
+    #[serde(flatten)]
+    pub search: SearchRequest,
+}
+
+// Results shaped like the documents RAG frameworks work with: the text
+// from `content_field` (the server's text field unless given) as
+// `page_content`, the rest of the metadata alongside it
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DocumentSearchRequest {
+    pub vector: Vec<f32>,
+    pub k: Option<usize>,
+    pub threshold: Option<f32>,
+    pub collection: Option<String>,
+    #[serde(default)]
+    pub filter: Option<HashMap<String, String>>,
+    pub content_field: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DocumentSearchResponse {
+    pub documents: Vec<ScoredDocument>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScoredDocument {
+    pub id: String,
+    pub page_content: String,
+    pub metadata: HashMap<String, String>,
+    pub score: f32,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -43,444 +723,5415 @@ pub struct StatsResponse {
     pub total_vectors: usize,
     pub dimensions: usize,
     pub storage_size_bytes: usize,
+    pub raw_vector_bytes: usize,
+    pub stored_vector_bytes: usize,
+    // Set while a background index build has failed
+    #[serde(default)]
+    pub index_build_error: Option<String>,
+    // The namespace's quota
+    #[serde(default)]
+    pub max_vectors: Option<usize>,
+    #[serde(default)]
+    pub max_bytes: Option<u64>,
 }
 
-pub async fn start_server(db: Arc<VectorDatabase>, port: u16) -> anyhow::Result<()> {
-    let app = Router::new()
-        .route("/health", get(health_check))
-        .route("/stats", get(get_stats))
-        .route("/vectors", post(insert_vectors))
-        .route("/vectors/:id", get(get_vector))
-        .route("/search", post(search_vectors))
-        .route(
-            "/collections/:collection/search",
-            post(search_in_collection),
-        )
-        .layer(CorsLayer::permissive())
-        .with_state(db);
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiKeyRequest {
+    pub role: Role,
+}
 
-    let addr = format!("0.0.0.0:{}", port);
-    info!("Starting HTTP server on {}", addr);
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateApiKeyResponse {
+    pub id: String,
+    pub role: Role,
+    // Only ever returned here
+    pub key: String,
+}
 
-    let listener = tokio::net::TcpListener::bind(&addr).await?;
-    axum::serve(listener, app).await?;
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NamespaceStatsResponse {
+    pub namespace: String,
+    pub total_vectors: usize,
+    pub dimensions: usize,
+    #[serde(default)]
+    pub stored_vector_bytes: usize,
+    pub max_vectors: Option<usize>,
+    #[serde(default)]
+    pub max_bytes: Option<u64>,
+}
 
-    Ok(())
+// Settings kept per collection. PUT replaces them all, so leaving out
+// `dedup`, `expected_model`, `metadata_schema` or `quota` turns it off, and
+// leaving out `id_scheme` or `keep_versions` goes back to the server's.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CollectionConfig {
+    pub distance_metric: DistanceMetric,
+    #[serde(default)]
+    pub dedup: Option<Dedup>,
+    #[serde(default)]
+    pub id_scheme: Option<IdScheme>,
+    #[serde(default)]
+    pub keep_versions: Option<usize>,
+    #[serde(default)]
+    pub expected_model: Option<String>,
+    #[serde(default)]
+    pub metadata_schema: Option<MetadataSchema>,
+    #[serde(default)]
+    pub quota: Option<Quota>,
 }
 
-async fn health_check() -> &'static str {
-    "OK"
+// A name that reads resolve to `collection`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Alias {
+    pub alias: String,
+    pub collection: String,
 }
 
-async fn get_stats(State(db): State<AppState>) -> Result<Json<StatsResponse>, StatusCode> {
-    match db.get_stats().await {
-        Ok(stats) => Ok(Json(StatsResponse {
-            total_vectors: stats.total_vectors,
-            dimensions: stats.dimensions,
-            storage_size_bytes: stats.storage_size_bytes,
-        })),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-    }
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SetAliasRequest {
+    pub collection: String,
 }
 
-async fn insert_vectors(
-    State(db): State<AppState>,
-    Json(payload): Json<InsertRequest>,
-) -> Result<Json<Vec<String>>, StatusCode> {
-    match db.insert_vectors(payload.vectors).await {
-        Ok(ids) => Ok(Json(ids)),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-    }
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CollectionStatsResponse {
+    pub collection: String,
+    pub vector_count: u64,
+    pub dimensions: usize,
+    pub storage_bytes: u64,
+    pub index_type: String,
+    #[serde(default)]
+    pub index_build_error: Option<String>,
+    // Unix seconds of the last write or delete
+    pub last_modified: u64,
+    // The collection's quota
+    #[serde(default)]
+    pub max_vectors: Option<u64>,
+    #[serde(default)]
+    pub max_bytes: Option<u64>,
 }
 
-async fn get_vector(
-    State(db): State<AppState>,
-    Path(id): Path<String>,
-) -> Result<Json<Vector>, StatusCode> {
-    match db.get_vector(&id).await {
-        Ok(Some(vector)) => Ok(Json(vector)),
-        Ok(None) => Err(StatusCode::NOT_FOUND),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-    }
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateSnapshotRequest {
+    pub name: String,
 }
 
-async fn search_vectors(
-    State(db): State<AppState>,
-    Json(payload): Json<SearchRequest>,
-) -> Result<Json<SearchResponse>, StatusCode> {
-    let k = payload.k.unwrap_or(10);
-    let threshold = payload.threshold.unwrap_or(0.0);
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CloneSnapshotRequest {
+    pub target: String,
+}
 
-    match db.search(&payload.vector, k, threshold).await {
-        Ok(results) => {
-            let search_results = results
-                .into_iter()
-                .map(|r| SearchResult {
-                    id: r.id,
-                    score: r.score,
-                    metadata: r.metadata,
-                })
-                .collect();
-            Ok(Json(SearchResponse {
-                results: search_results,
-            }))
-        }
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-    }
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReadinessResponse {
+    pub ready: bool,
+    pub checks: Vec<ReadinessCheck>,
 }
 
-async fn search_in_collection(
-    State(db): State<AppState>,
-    Path(collection): Path<String>,
-    Json(payload): Json<SearchRequest>,
-) -> Result<Json<SearchResponse>, StatusCode> {
-    let k = payload.k.unwrap_or(10);
-    let threshold = payload.threshold.unwrap_or(0.0);
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReadinessCheck {
+    pub name: String,
+    pub namespace: String,
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
 
-    match db
-        .search_in_collection(&collection, &payload.vector, k, threshold)
-        .await
-    {
-        Ok(results) => {
-            let search_results = results
-                .into_iter()
-                .map(|r| SearchResult {
-                    id: r.id,
-                    score: r.score,
-                    metadata: r.metadata,
-                })
-                .collect();
-            Ok(Json(SearchResponse {
-                results: search_results,
-            }))
-        }
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-    }
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AggregateQuery {
+    // Metadata key to count the values of
+    pub group_by: String,
+    // Keeps only the most common values
+    pub limit: Option<usize>,
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use axum::http::StatusCode;
-    use axum_test::TestServer;
-    use skypier_core::VectorDatabase;
-    use std::collections::HashMap;
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AggregateResponse {
+    pub collection: String,
+    pub group_by: String,
+    #[serde(flatten)]
+    pub aggregate: Aggregate,
+}
 
-    async fn create_test_db() -> Arc<VectorDatabase> {
-        let temp_dir = tempfile::tempdir().unwrap();
-        let db_path = temp_dir.path().to_str().unwrap();
-        let db = Arc::new(VectorDatabase::new(db_path).await.unwrap());
-        // Keep temp_dir alive by not dropping it
-        std::mem::forget(temp_dir);
-        db
-    }
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DiffQuery {
+    pub against: Option<String>,
+}
 
-    async fn create_test_app() -> TestServer {
-        let db = create_test_db().await;
-        let app = Router::new()
-            .route("/health", get(health_check))
-            .route("/stats", get(get_stats))
-            .route("/vectors", post(insert_vectors))
-            .route("/vectors/:id", get(get_vector))
-            .route("/search", post(search_vectors))
-            .route(
-                "/collections/:collection/search",
-                post(search_in_collection),
-            )
-            .layer(CorsLayer::permissive())
-            .with_state(db);
+// Changes returned per /changes page unless `limit` says otherwise, and the
+// most a page may hold
+const DEFAULT_CHANGES_LIMIT: usize = 1000;
+const MAX_CHANGES_LIMIT: usize = 10_000;
 
-        TestServer::new(app).unwrap()
-    }
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChangesQuery {
+    #[serde(default)]
+    pub since: u64,
+    pub limit: Option<usize>,
+}
 
-    #[tokio::test]
-    async fn test_health_check() {
-        let server = create_test_app().await;
+// Vectors per /scroll page unless `limit` says otherwise, and the most a
+// page may hold
+const DEFAULT_SCROLL_LIMIT: usize = 100;
+const MAX_SCROLL_LIMIT: usize = 10_000;
 
-        let response = server.get("/health").await;
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SampleQuery {
+    // How many vectors to pick, 100 unless given, up to `MAX_SCROLL_LIMIT`
+    pub n: Option<usize>,
+    // Picks the same sample again, as long as the collection is unchanged
+    pub seed: Option<u64>,
+}
 
-        assert_eq!(response.status_code(), StatusCode::OK);
-        assert_eq!(response.text(), "OK");
-    }
+const MAX_CLUSTERS: usize = 4096;
+
+// Options for POST /collections/:collection/cluster, see `ClusterOptions`.
+// `key` is the metadata key assignments go under, "cluster" by default.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClusterQuery {
+    pub k: usize,
+    pub iterations: Option<usize>,
+    pub batch_size: Option<usize>,
+    pub seed: Option<u64>,
+    pub key: Option<String>,
+}
+
+// Options for POST /collections/:collection/clone, see `CloneOptions`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CloneQuery {
+    pub target: String,
+    pub normalize: Option<bool>,
+    pub dtype: Option<Dtype>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScrollQuery {
+    // The `next` of the previous page
+    pub after: Option<String>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChangesResponse {
+    pub changes: Vec<ChangeEvent>,
+    // Pass as `since` to get the changes after these
+    pub next: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportQuery {
+    pub format: Option<String>, // "parquet" (the default) or "arrow"
+    pub collection: Option<String>,
+}
+
+// Column mapping for the upload, see `ColumnMapping`. `metadata_columns`
+// is comma-separated.
+// Neighbors per vector in GET /admin/knn-graph
+const DEFAULT_GRAPH_NEIGHBORS: usize = 10;
+const MAX_GRAPH_NEIGHBORS: usize = 100;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KnnGraphQuery {
+    pub n: Option<usize>,
+    pub format: Option<String>, // "jsonl" (the default) or "parquet"
+    pub collection: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImportQuery {
+    pub format: String, // "parquet" or "csv"
+    pub id_column: Option<String>,
+    pub vector_column: Option<String>,
+    pub dimension_prefix: Option<String>,
+    pub collection_column: Option<String>,
+    pub collection: Option<String>,
+    pub metadata_columns: Option<String>,
+}
+
+impl ImportQuery {
+    fn mapping(&self) -> ColumnMapping {
+        let defaults = ColumnMapping::default();
+        ColumnMapping {
+            id_column: self.id_column.clone().unwrap_or(defaults.id_column),
+            vector_column: self.vector_column.clone().unwrap_or(defaults.vector_column),
+            dimension_prefix: self.dimension_prefix.clone(),
+            collection_column: self
+                .collection_column
+                .clone()
+                .unwrap_or(defaults.collection_column),
+            collection: self.collection.clone(),
+            metadata_columns: self
+                .metadata_columns
+                .as_ref()
+                .map(|columns| columns.split(',').map(|c| c.trim().to_string()).collect()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImportResponse {
+    pub imported: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupRequest {
+    // A local directory, or an s3://, gs:// or az:// URL
+    pub destination: String,
+    // Adds to the incremental backup set at the destination instead of
+    // copying the whole store
+    #[serde(default)]
+    pub incremental: bool,
+    // Runs it as a job and answers with that instead of waiting
+    #[serde(default)]
+    pub background: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupResponse {
+    pub destination: String,
+    // WAL seq of an incremental backup, to restore to later
+    pub seq: Option<u64>,
+}
+
+#[cfg(feature = "embeddings")]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TextItem {
+    pub id: Option<String>,
+    pub text: String,
+    pub metadata: Option<HashMap<String, String>>,
+    pub collection: Option<String>,
+}
+
+#[cfg(feature = "embeddings")]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EmbedAndInsertRequest {
+    pub items: Vec<TextItem>,
+}
+
+#[cfg(feature = "embeddings")]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TextSearchRequest {
+    pub text: String,
+    pub collection: Option<String>,
+    pub k: Option<usize>,
+    pub threshold: Option<f32>,
+}
+
+pub fn create_router(state: AppState) -> Router {
+    let router = Router::new()
+        .route("/health", get(health_check))
+        .route("/health/live", get(health_live))
+        .route("/health/ready", get(health_ready))
+        .route("/stats", get(get_stats))
+        .route("/namespaces", get(list_namespaces))
+        .route("/vectors", post(insert_vectors))
+        .route("/vectors/validate", post(validate_vectors))
+        .route("/vectors/raw", post(insert_raw_vectors))
+        .route("/vectors/:id", get(get_vector).delete(delete_vector))
+        .route("/vectors/:id/versions", get(get_vector_versions))
+        .route("/similarity", get(get_similarity))
+        .route("/search", post(search_vectors))
+        .route("/search/documents", post(search_documents))
+        .route("/recommend", post(recommend_vectors))
+        .route("/aliases", get(list_aliases))
+        .route(
+            "/aliases/:alias",
+            axum::routing::put(set_alias).delete(delete_alias),
+        )
+        .route(
+            "/collections/:collection/search",
+            post(search_in_collection),
+        )
+        .route("/collections/:collection/stats", get(get_collection_stats))
+        .route(
+            "/collections/:collection/aggregate",
+            get(aggregate_collection),
+        )
+        .route("/collections/:collection/scroll", get(scroll_collection))
+        .route("/collections/:collection/sample", get(sample_collection))
+        .route("/collections/:collection/cluster", post(start_clustering))
+        .route("/collections/:collection/clusters", get(get_clustering))
+        .route("/collections/:collection/clone", post(start_clone))
+        .route(
+            "/collections/:collection/config",
+            get(get_collection_config).put(update_collection_config),
+        )
+        .route(
+            "/collections/:collection/snapshots",
+            post(create_snapshot).get(list_snapshots),
+        )
+        .route(
+            "/collections/:collection/snapshots/:name",
+            delete(delete_snapshot),
+        )
+        .route(
+            "/collections/:collection/snapshots/:name/search",
+            post(search_snapshot),
+        )
+        .route(
+            "/collections/:collection/snapshots/:name/export",
+            get(export_snapshot),
+        )
+        .route(
+            "/collections/:collection/snapshots/:name/clone",
+            post(clone_snapshot),
+        )
+        .route(
+            "/collections/:collection/snapshots/:name/diff",
+            get(diff_snapshot),
+        )
+        .route("/import", post(import_vectors))
+        .route("/export", get(export_vectors))
+        .route("/changes", get(list_changes))
+        .route("/ws/changes", get(subscribe_changes))
+        .route("/admin/backup", post(create_backup))
+        .route("/admin/compact", post(start_compaction))
+        .route("/admin/reindex", post(start_reindex))
+        .route("/admin/verify", post(verify_storage))
+        .route("/admin/jobs", get(list_jobs))
+        .route("/admin/jobs/:id", get(get_job))
+        .route("/admin/jobs/:id/cancel", post(cancel_job))
+        .route("/admin/slow-queries", get(list_slow_queries))
+        .route("/admin/audit", get(list_audit_entries))
+        .route("/admin/knn-graph", get(export_knn_graph))
+        .route("/cluster/members", get(list_cluster_members))
+        .route("/collections/:collection/placement", get(get_placement))
+        .route("/cluster/replication", get(cluster_replication_status))
+        .route("/admin/replication", get(replication_status))
+        .route("/admin/replication/promote", post(promote_follower))
+        .route("/admin/conflicts", get(list_conflicts))
+        .route("/admin/conflicts/:id/resolve", post(resolve_conflict))
+        .route(
+            "/admin/namespaces/:name",
+            axum::routing::put(create_namespace),
+        )
+        .route("/admin/keys", get(list_api_keys).post(create_api_key))
+        .route(
+            "/admin/keys/:id",
+            axum::routing::put(update_api_key).delete(revoke_api_key),
+        )
+        .nest("/qdrant", crate::compat::qdrant_routes())
+        .nest("/api/v1", crate::compat::chroma_routes());
+
+    #[cfg(feature = "embeddings")]
+    let router = router
+        .route("/embed-and-insert", post(embed_and_insert))
+        .route("/search/text", post(search_text));
+
+    let max_body_bytes = state.max_body_bytes;
+    let limits = ServiceBuilder::new()
+        .layer(HandleErrorLayer::new(request_limit_error))
+        .option_layer(state.max_concurrent_requests.map(|max| {
+            ServiceBuilder::new()
+                .load_shed()
+                .layer(GlobalConcurrencyLimitLayer::new(max))
+        }))
+        .option_layer(state.request_timeout.map(TimeoutLayer::new));
+    let router = router
+        .layer(middleware::from_fn_with_state(state.clone(), rate_limit))
+        .layer(middleware::from_fn_with_state(state.clone(), authorize))
+        .layer(middleware::from_fn_with_state(state.clone(), audit))
+        .layer(DefaultBodyLimit::max(max_body_bytes))
+        .layer(CorsLayer::permissive())
+        .with_state(state);
+
+    // The prefix has to come off before the router matches the path.
+    // Responses are gzip or zstd compressed for clients that accept it.
+    Router::new()
+        .fallback_service(
+            ServiceBuilder::new()
+                .map_request(strip_namespace_prefix)
+                .service(router),
+        )
+        .layer(CompressionLayer::new())
+        .layer(limits)
+}
+
+// A request turned away by the concurrency limit or the timeout. A timed
+// out handler is dropped at its next await, so an index search already
+// underway still runs to the end.
+async fn request_limit_error(error: BoxError) -> ApiError {
+    if error.is::<tower::load_shed::error::Overloaded>() {
+        ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Too many requests in progress, try again shortly",
+        )
+    } else if error.is::<tower::timeout::error::Elapsed>() {
+        ApiError::new(StatusCode::REQUEST_TIMEOUT, "The request took too long")
+    } else {
+        ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, error.to_string())
+    }
+}
+
+// Certificate chain and private key the server presents, as PEM files
+#[derive(Debug, Clone)]
+pub struct TlsFiles {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+// Serves until `shutdown` resolves, then stops accepting connections and
+// waits for in-flight requests to finish. With `tls` it serves HTTPS and
+// reloads the certificate on SIGHUP. HTTP/2 is spoken over TLS when ALPN
+// picks it, and without TLS to clients that start with it (h2c).
+pub async fn start_server<F>(
+    state: AppState,
+    host: &str,
+    port: u16,
+    tls: Option<TlsFiles>,
+    shutdown: F,
+) -> anyhow::Result<()>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let app = create_router(state);
+
+    let addr = format!("{}:{}", host, port);
+    let Some(tls) = tls else {
+        info!("Starting HTTP server on {}", addr);
+        let listener = tokio::net::TcpListener::bind(&addr).await?;
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .with_graceful_shutdown(shutdown)
+        .await?;
+        info!("HTTP server stopped");
+        return Ok(());
+    };
+
+    info!("Starting HTTPS server on {}", addr);
+    let rustls = RustlsConfig::from_pem_file(&tls.cert, &tls.key).await?;
+    let listener = std::net::TcpListener::bind(&addr)?;
+    listener.set_nonblocking(true)?;
+
+    let handle = axum_server::Handle::new();
+    let stop = handle.clone();
+    tokio::spawn(async move {
+        shutdown.await;
+        stop.graceful_shutdown(None);
+    });
+    let reload = tokio::spawn(reload_tls_on_sighup(rustls.clone(), tls));
+    let served = axum_server::from_tcp_rustls(listener, rustls)
+        .handle(handle)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .await;
+    reload.abort();
+    served?;
+    info!("HTTPS server stopped");
+
+    Ok(())
+}
+
+// New connections get the reloaded certificate; open ones keep theirs
+async fn reload_tls_on_sighup(rustls: RustlsConfig, tls: TlsFiles) {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                warn!("Failed to listen for SIGHUP: {}", e);
+                return;
+            }
+        };
+        while hangup.recv().await.is_some() {
+            match rustls.reload_from_pem_file(&tls.cert, &tls.key).await {
+                Ok(()) => info!("Reloaded TLS certificate"),
+                Err(e) => warn!("TLS certificate reload failed, keeping the old one: {}", e),
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (rustls, tls);
+}
+
+async fn health_check() -> &'static str {
+    "OK"
+}
+
+async fn health_live() -> Json<serde_json::Value> {
+    Json(serde_json::json!({"status": "live"}))
+}
+
+// Every open namespace needs readable storage and a loaded index. Answers
+// 503 until they all have them.
+async fn health_ready(State(state): State<AppState>) -> (StatusCode, Json<ReadinessResponse>) {
+    let mut checks = Vec::new();
+    for (namespace, db) in state.namespaces.open().await {
+        let storage = db.check_storage().await.err().map(|e| e.to_string());
+        checks.push(ReadinessCheck {
+            name: "storage".to_string(),
+            namespace: namespace.clone(),
+            ok: storage.is_none(),
+            error: storage,
+        });
+        let loaded = db.index_loaded();
+        checks.push(ReadinessCheck {
+            name: "index".to_string(),
+            namespace,
+            ok: loaded,
+            error: (!loaded).then(|| "The index hasn't been loaded".to_string()),
+        });
+    }
+    let ready = checks.iter().all(|check| check.ok);
+    let status = match ready {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    (status, Json(ReadinessResponse { ready, checks }))
+}
+
+async fn get_stats(
+    Tenant {
+        db,
+        max_vectors,
+        max_bytes,
+        ..
+    }: Tenant,
+) -> Result<Json<StatsResponse>, StatusCode> {
+    match db.get_stats().await {
+        Ok(stats) => Ok(Json(StatsResponse {
+            total_vectors: stats.total_vectors,
+            dimensions: stats.dimensions,
+            storage_size_bytes: stats.storage_size_bytes,
+            raw_vector_bytes: stats.raw_vector_bytes,
+            stored_vector_bytes: stats.stored_vector_bytes,
+            index_build_error: stats.index_build_error,
+            max_vectors,
+            max_bytes,
+        })),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+async fn list_namespaces(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<NamespaceStatsResponse>>, ApiError> {
+    let namespaces = &state.namespaces;
+    let mut response = Vec::new();
+    for namespace in namespaces.names().await? {
+        let Some(db) = namespaces.get(&namespace).await? else {
+            continue;
+        };
+        response.push(namespace_stats(namespaces, namespace, &db).await?);
+    }
+    Ok(Json(response))
+}
+
+// Makes the namespace, or leaves an existing one as it is
+async fn create_namespace(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<NamespaceStatsResponse>, ApiError> {
+    if !crate::namespace::valid_name(&name) {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("Invalid namespace name '{}'", name),
+        ));
+    }
+    let namespaces = &state.namespaces;
+    let Some(db) = namespaces.create(&name).await? else {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "Namespaces aren't enabled",
+        ));
+    };
+    Ok(Json(namespace_stats(namespaces, name, &db).await?))
+}
+
+async fn namespace_stats(
+    namespaces: &Namespaces,
+    namespace: String,
+    db: &VectorDatabase,
+) -> Result<NamespaceStatsResponse, ApiError> {
+    let stats = db.get_stats().await?;
+    let quota = namespaces.quota(&namespace).cloned().unwrap_or_default();
+    Ok(NamespaceStatsResponse {
+        namespace,
+        total_vectors: stats.total_vectors,
+        dimensions: stats.dimensions,
+        stored_vector_bytes: stats.stored_vector_bytes,
+        max_vectors: quota.max_vectors,
+        max_bytes: quota.max_bytes,
+    })
+}
+
+async fn get_collection_stats(
+    Tenant { db, .. }: Tenant,
+    Path(collection): Path<String>,
+) -> Result<Json<CollectionStatsResponse>, StatusCode> {
+    match db.collection_stats(&collection).await {
+        Ok(Some(stats)) => {
+            let quota = db.collection_quota(&collection).await.unwrap_or_default();
+            Ok(Json(CollectionStatsResponse {
+                index_type: db.collection_index_type(&collection).await.to_string(),
+                index_build_error: db.collection_index_build_error(&collection).await,
+                collection,
+                vector_count: stats.vector_count,
+                dimensions: stats.dimensions,
+                storage_bytes: stats.stored_bytes,
+                last_modified: stats.last_modified,
+                max_vectors: quota.max_vectors,
+                max_bytes: quota.max_bytes,
+            }))
+        }
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+async fn aggregate_collection(
+    Tenant { db, .. }: Tenant,
+    Path(collection): Path<String>,
+    Query(query): Query<AggregateQuery>,
+) -> Result<Json<AggregateResponse>, ApiError> {
+    let filter = SearchFilter {
+        collection: Some(collection.clone()),
+        ..Default::default()
+    };
+    let mut aggregate = db.aggregate(&filter, &query.group_by).await;
+    if aggregate.total == 0 {
+        return Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            format!("Collection {} has no vectors", collection),
+        ));
+    }
+    if let Some(limit) = query.limit {
+        aggregate.values.truncate(limit);
+    }
+    Ok(Json(AggregateResponse {
+        collection,
+        group_by: query.group_by,
+        aggregate,
+    }))
+}
+
+async fn scroll_collection(
+    Tenant { db, .. }: Tenant,
+    Path(collection): Path<String>,
+    Query(query): Query<ScrollQuery>,
+) -> Result<Json<ScrollPage>, ApiError> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_SCROLL_LIMIT)
+        .clamp(1, MAX_SCROLL_LIMIT);
+    let page = db
+        .scroll(Some(&collection), query.after.as_deref(), limit)
+        .await?;
+    Ok(Json(page))
+}
+
+async fn sample_collection(
+    Tenant { db, .. }: Tenant,
+    Path(collection): Path<String>,
+    Query(query): Query<SampleQuery>,
+) -> Result<Json<Vec<Vector>>, ApiError> {
+    let n = query
+        .n
+        .unwrap_or(DEFAULT_SCROLL_LIMIT)
+        .min(MAX_SCROLL_LIMIT);
+    Ok(Json(db.sample(Some(&collection), n, query.seed).await?))
+}
+
+// Runs mini-batch k-means over the collection as a job, which writes each
+// vector's cluster into its metadata
+async fn start_clustering(
+    State(state): State<AppState>,
+    Tenant { namespace, db, .. }: Tenant,
+    Path(collection): Path<String>,
+    Query(query): Query<ClusterQuery>,
+) -> Result<(StatusCode, Json<Job>), ApiError> {
+    let Some(stats) = db.collection_stats(&collection).await? else {
+        return Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            format!("Collection {} has no vectors", collection),
+        ));
+    };
+    if !(1..=MAX_CLUSTERS).contains(&query.k) || query.k as u64 > stats.vector_count {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "k must be between 1 and {}, and at most the collection's {} vectors",
+                MAX_CLUSTERS, stats.vector_count
+            ),
+        ));
+    }
+    let defaults = ClusterOptions::default();
+    let options = ClusterOptions {
+        k: query.k,
+        iterations: query.iterations.unwrap_or(defaults.iterations),
+        batch_size: query.batch_size.unwrap_or(defaults.batch_size).max(1),
+        seed: query.seed,
+        metadata_key: query.key.unwrap_or(defaults.metadata_key),
+    };
+    let job = state.jobs.start(
+        "cluster",
+        &namespace,
+        Some(stats.vector_count),
+        |context| async move {
+            db.cluster(&collection, &options, |count| {
+                context.set_progress(count as u64);
+                context.check_cancelled()
+            })
+            .await?;
+            Ok(())
+        },
+    );
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+// Copies the collection's vectors into a new one as a job
+async fn start_clone(
+    State(state): State<AppState>,
+    Tenant { namespace, db, .. }: Tenant,
+    Path(collection): Path<String>,
+    Query(query): Query<CloneQuery>,
+) -> Result<(StatusCode, Json<Job>), ApiError> {
+    let Some(stats) = db.collection_stats(&collection).await? else {
+        return Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            format!("Collection {} has no vectors", collection),
+        ));
+    };
+    db.check_clone(&collection, &query.target).await?;
+    let options = CloneOptions {
+        normalize: query.normalize.unwrap_or(false),
+        dtype: query.dtype,
+    };
+    let job = state.jobs.start(
+        "clone",
+        &namespace,
+        Some(stats.vector_count),
+        |context| async move {
+            db.clone_collection(&collection, &query.target, &options, |count| {
+                context.set_progress(count as u64);
+                context.check_cancelled()
+            })
+            .await?;
+            Ok(())
+        },
+    );
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+async fn get_clustering(
+    Tenant { db, .. }: Tenant,
+    Path(collection): Path<String>,
+) -> Result<Json<Clustering>, ApiError> {
+    match db.clustering(&collection).await? {
+        Some(clustering) => Ok(Json(clustering)),
+        None => Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            format!("Collection {} hasn't been clustered", collection),
+        )),
+    }
+}
+
+async fn get_collection_config(
+    Tenant { db, .. }: Tenant,
+    Path(collection): Path<String>,
+) -> Json<CollectionConfig> {
+    Json(CollectionConfig {
+        distance_metric: db.collection_metric(&collection).await,
+        dedup: db.collection_dedup(&collection).await,
+        id_scheme: Some(db.id_scheme(Some(&collection)).await),
+        keep_versions: Some(db.versions_kept(Some(&collection)).await),
+        expected_model: db.expected_model(&collection).await,
+        metadata_schema: db.metadata_schema(&collection).await,
+        quota: db.collection_quota(&collection).await,
+    })
+}
+
+async fn list_aliases(Tenant { db, .. }: Tenant) -> Json<Vec<Alias>> {
+    Json(
+        db.aliases()
+            .await
+            .into_iter()
+            .map(|(alias, collection)| Alias { alias, collection })
+            .collect(),
+    )
+}
+
+// Creates the alias or repoints it in one step, e.g. to cut "docs" over to
+// a re-embedded "docs_v2", or back
+async fn set_alias(
+    Tenant { db, .. }: Tenant,
+    Path(alias): Path<String>,
+    Json(payload): Json<SetAliasRequest>,
+) -> Result<Json<Alias>, ApiError> {
+    db.set_alias(&alias, &payload.collection).await?;
+    Ok(Json(Alias {
+        alias,
+        collection: payload.collection,
+    }))
+}
+
+async fn delete_alias(
+    Tenant { db, .. }: Tenant,
+    Path(alias): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    match db.delete_alias(&alias).await? {
+        true => Ok(StatusCode::NO_CONTENT),
+        false => Err(ApiError::from(StatusCode::NOT_FOUND)),
+    }
+}
+
+// Rebuilds the collection's index before returning when the metric changes
+async fn update_collection_config(
+    Tenant { db, .. }: Tenant,
+    Path(collection): Path<String>,
+    Json(config): Json<CollectionConfig>,
+) -> Result<Json<CollectionConfig>, ApiError> {
+    if let Some(dedup) = &config.dedup {
+        if !dedup.threshold.is_finite() {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "Dedup threshold must be a finite number",
+            ));
+        }
+    }
+    if db.collection_metric(&collection).await != config.distance_metric {
+        db.set_collection_metric(&collection, config.distance_metric)
+            .await?;
+    }
+    db.set_collection_dedup(&collection, config.dedup).await?;
+    db.set_collection_id_scheme(&collection, config.id_scheme)
+        .await?;
+    db.set_collection_versions(&collection, config.keep_versions)
+        .await?;
+    db.set_expected_model(&collection, config.expected_model.clone())
+        .await?;
+    db.set_metadata_schema(&collection, config.metadata_schema.clone())
+        .await?;
+    db.set_collection_quota(&collection, config.quota).await?;
+    Ok(Json(config))
+}
+
+async fn insert_vectors(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Encoded(payload, encoding): Encoded<InsertRequest>,
+) -> Result<Encoded<InsertResponse>, ApiError> {
+    Ok(Encoded(insert(&state, &tenant, payload).await?, encoding))
+}
+
+// POST /vectors with the vectors packed in the binary layout `raw`
+// describes, which skips parsing floats out of text
+async fn insert_raw_vectors(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    body: Bytes,
+) -> Result<Json<InsertResponse>, ApiError> {
+    let vectors =
+        raw::decode(&body).map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, e.to_string()))?;
+    let payload = InsertRequest {
+        vectors,
+        ..Default::default()
+    };
+    Ok(Json(insert(&state, &tenant, payload).await?))
+}
+
+async fn insert(
+    state: &AppState,
+    tenant: &Tenant,
+    payload: InsertRequest,
+) -> Result<InsertResponse, ApiError> {
+    let Some(node) = state.sharded() else {
+        let ids = tenant.db.insert_vectors(payload.vectors).await?;
+        return Ok(match payload.write_concern {
+            // There are no other replicas to wait for
+            Some(_) => InsertResponse::Report(WriteReport {
+                ids,
+                acknowledged: true,
+                failures: Vec::new(),
+            }),
+            None => InsertResponse::Ids(ids),
+        });
+    };
+    let concern = payload.write_concern.unwrap_or_default();
+    let timeout = payload
+        .write_timeout_ms
+        .map(Duration::from_millis)
+        .unwrap_or(cluster::WRITE_TIMEOUT);
+
+    // Split the batch by owner, remembering where each vector came from.
+    // Each owner's share is applied on its own, so one failing leaves the
+    // others' written.
+    let members = node.members().await?;
+    let local_id = node.local_id();
+    let mut local = (Vec::new(), Vec::new());
+    let mut remote: HashMap<String, (Member, Vec<usize>, Vec<Vector>)> = HashMap::new();
+    for (position, vector) in payload.vectors.into_iter().enumerate() {
+        let collection = vector.collection.as_deref().unwrap_or_default();
+        match cluster::remote_owner(&members, &local_id, &state.placement, collection)? {
+            Some(owner) => {
+                let share = remote
+                    .entry(owner.id.clone())
+                    .or_insert_with(|| (owner, Vec::new(), Vec::new()));
+                share.1.push(position);
+                share.2.push(vector);
+            }
+            None => {
+                local.0.push(position);
+                local.1.push(vector);
+            }
+        }
+    }
+
+    let mut report = WriteReport {
+        ids: vec![
+            String::new();
+            local.0.len() + remote.values().map(|share| share.1.len()).sum::<usize>()
+        ],
+        acknowledged: true,
+        failures: Vec::new(),
+    };
+    let mut merge = |positions: Vec<usize>, share: WriteReport| {
+        for (position, id) in positions.into_iter().zip(share.ids) {
+            report.ids[position] = id;
+        }
+        report.acknowledged &= share.acknowledged;
+        report.failures.extend(share.failures);
+    };
+    if !local.1.is_empty() {
+        let share = cluster::insert(
+            node,
+            &state.placement,
+            &state.replicator,
+            tenant,
+            local.1,
+            concern,
+            timeout,
+        )
+        .await?;
+        merge(local.0, share);
+    }
+    for (owner, positions, vectors) in remote.into_values() {
+        let request = ForwardRequest::Insert {
+            namespace: tenant.namespace.clone(),
+            vectors,
+            write_concern: concern,
+            write_timeout_ms: timeout.as_millis() as u64,
+        };
+        let ForwardResponse::Inserted(share) = cluster::forward(node, &owner, request).await?
+        else {
+            return Err(cluster::unexpected(&owner));
+        };
+        merge(positions, share);
+    }
+    Ok(match payload.write_concern {
+        Some(_) => InsertResponse::Report(report),
+        None => InsertResponse::Ids(report.ids),
+    })
+}
+
+// Dry run of POST /vectors: reports every row that would be rejected, and
+// the collections it would create, without writing anything
+async fn validate_vectors(
+    tenant: Tenant,
+    Json(payload): Json<InsertRequest>,
+) -> Result<Json<ValidateResponse>, ApiError> {
+    let check = tenant.db.check_vectors(&payload.vectors).await?;
+    let quota_error = match tenant
+        .db
+        .check_quota(&payload.vectors)
+        .await
+        .map_err(ApiError::from)
+    {
+        Ok(()) => None,
+        Err(e) if e.usage.is_some() => Some(e.message),
+        Err(e) => return Err(e),
+    };
+    Ok(Json(ValidateResponse {
+        valid: check.errors.is_empty() && quota_error.is_none(),
+        check,
+        quota_error,
+    }))
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct LocateQuery {
+    // In cluster mode, the collection the vector is in, so the request goes
+    // straight to its replicas instead of asking every member
+    pub collection: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct GetVectorQuery {
+    #[serde(flatten)]
+    pub locate: LocateQuery,
+    // Comma-separated metadata keys to return, leaving out the rest
+    pub fields: Option<String>,
+}
+
+async fn get_vector(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Path(id): Path<String>,
+    Query(query): Query<GetVectorQuery>,
+) -> Result<Json<Vector>, ApiError> {
+    let mut vector = find_vector(&state, &tenant, &id, &query.locate).await?;
+    if let Some(fields) = &query.fields {
+        let fields: Vec<String> = fields.split(',').map(str::to_string).collect();
+        select_fields(&mut vector.metadata, &fields);
+    }
+    Ok(Json(vector))
+}
+
+async fn find_vector(
+    state: &AppState,
+    tenant: &Tenant,
+    id: &str,
+    query: &LocateQuery,
+) -> Result<Vector, ApiError> {
+    if let (Some(node), Some(collection)) = (state.sharded(), &query.collection) {
+        let replicas = cluster::replicas(node, &state.placement, Some(collection)).await?;
+        if replicas.len() > 1 {
+            return match cluster::get_from_replicas(node, replicas, tenant, id).await? {
+                Some(vector) => Ok(vector),
+                None => Err(ApiError::from(StatusCode::NOT_FOUND)),
+            };
+        }
+    }
+    let request = ForwardRequest::Get {
+        namespace: tenant.namespace.clone(),
+        id: id.to_string(),
+    };
+    // Any replica will do, so one that can't be reached is skipped
+    let mut unreachable = None;
+    for owner in replicas_to_read(state, query).await? {
+        let (Some(owner), Some(node)) = (owner, state.sharded()) else {
+            match tenant.db.get_vector(id).await? {
+                Some(vector) => return Ok(vector),
+                None => continue,
+            }
+        };
+        match cluster::forward(node, &owner, request.clone()).await {
+            Ok(ForwardResponse::Vector(Some(vector))) => return Ok(*vector),
+            Ok(ForwardResponse::Vector(None)) => continue,
+            Ok(_) => return Err(cluster::unexpected(&owner)),
+            Err(e) if e.status == StatusCode::BAD_GATEWAY => unreachable = Some(e),
+            Err(e) => return Err(e),
+        }
+    }
+    Err(unreachable.unwrap_or_else(|| ApiError::from(StatusCode::NOT_FOUND)))
+}
+
+async fn delete_vector(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Path(id): Path<String>,
+    Query(query): Query<LocateQuery>,
+) -> Result<StatusCode, ApiError> {
+    let request = ForwardRequest::Delete {
+        namespace: tenant.namespace.clone(),
+        id: id.clone(),
+    };
+    for owner in owners_to_ask(&state, &query).await? {
+        let (Some(owner), Some(node)) = (owner, state.sharded()) else {
+            let deleted = match state.sharded() {
+                Some(node) => {
+                    cluster::delete(node, &state.placement, &state.replicator, &tenant, &id).await?
+                }
+                None => tenant.db.delete_vector(&id).await?,
+            };
+            match deleted {
+                true => return Ok(StatusCode::NO_CONTENT),
+                false => continue,
+            }
+        };
+        match cluster::forward(node, &owner, request.clone()).await? {
+            ForwardResponse::Deleted(true) => return Ok(StatusCode::NO_CONTENT),
+            ForwardResponse::Deleted(false) => continue,
+            _ => return Err(cluster::unexpected(&owner)),
+        }
+    }
+    Err(ApiError::from(StatusCode::NOT_FOUND))
+}
+
+// Where a single vector may be, in the order to look: None for this node.
+// Without a collection to place it by, that's this node and then every
+// other member.
+async fn owners_to_ask(
+    state: &AppState,
+    query: &LocateQuery,
+) -> Result<Vec<Option<Member>>, ApiError> {
+    let Some(node) = state.sharded() else {
+        return Ok(vec![None]);
+    };
+    if let Some(collection) = &query.collection {
+        let members = node.members().await?;
+        let local_id = node.local_id();
+        return Ok(vec![cluster::remote_owner(
+            &members,
+            &local_id,
+            &state.placement,
+            collection,
+        )?]);
+    }
+    let others = cluster::other_owners(node).await?;
+    Ok(std::iter::once(None)
+        .chain(others.into_iter().map(Some))
+        .collect())
+}
+
+// Like `owners_to_ask`, but with every replica of the collection, this
+// node first when it's one of them
+async fn replicas_to_read(
+    state: &AppState,
+    query: &LocateQuery,
+) -> Result<Vec<Option<Member>>, ApiError> {
+    let (Some(node), Some(collection)) = (state.sharded(), &query.collection) else {
+        return owners_to_ask(state, query).await;
+    };
+    let replicas = cluster::replicas(node, &state.placement, Some(collection)).await?;
+    if replicas.is_empty() {
+        return owners_to_ask(state, query).await;
+    }
+    let local_id = node.local_id();
+    let (local, remote): (Vec<Member>, Vec<Member>) = replicas
+        .into_iter()
+        .partition(|replica| replica.id == local_id);
+    Ok(local
+        .into_iter()
+        .map(|_| None)
+        .chain(remote.into_iter().map(Some))
+        .collect())
+}
+
+// Newest first, starting with the stored vector
+#[derive(Debug, Deserialize)]
+pub struct SimilarityQuery {
+    pub id_a: String,
+    pub id_b: String,
+    // The collection's metric, or the server's, unless given
+    pub metric: Option<DistanceMetric>,
+    // Compare this named vector of each instead of `data`
+    pub vector_name: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SimilarityResponse {
+    pub metric: DistanceMetric,
+    // Higher is closer, as search results score
+    pub score: f32,
+    // The metric's own measure, lower is closer
+    pub distance: f32,
+}
+
+// How close two stored vectors are, without downloading them
+async fn get_similarity(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Query(query): Query<SimilarityQuery>,
+) -> Result<Json<SimilarityResponse>, ApiError> {
+    let locate = LocateQuery::default();
+    let a = find_vector(&state, &tenant, &query.id_a, &locate).await?;
+    let b = find_vector(&state, &tenant, &query.id_b, &locate).await?;
+    let metric = match (query.metric, &a.collection) {
+        (Some(metric), _) => metric,
+        (None, Some(collection)) => tenant.db.collection_metric(collection).await,
+        (None, None) => *tenant.db.distance_metric(),
+    };
+    let data = |vector: &Vector| -> Result<Vec<f32>, ApiError> {
+        match &query.vector_name {
+            Some(name) => vector.vectors.get(name).cloned().ok_or_else(|| {
+                ApiError::new(
+                    StatusCode::NOT_FOUND,
+                    format!("Vector {} has no {} vector", vector.id, name),
+                )
+            }),
+            None => Ok(vector.data.clone()),
+        }
+    };
+    let score = metric
+        .score(&data(&a)?, &data(&b)?)
+        .map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, e.to_string()))?;
+    Ok(Json(SimilarityResponse {
+        metric,
+        score,
+        distance: metric.convert_score(score, ScoreMode::Distance),
+    }))
+}
+
+async fn get_vector_versions(
+    Tenant { db, .. }: Tenant,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<Vector>>, StatusCode> {
+    match db.get_versions(&id).await {
+        Ok(Some(versions)) => Ok(Json(versions)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+async fn search_vectors(
+    State(state): State<AppState>,
+    Tenant { namespace, db, .. }: Tenant,
+    Encoded(payload, encoding): Encoded<SearchRequest>,
+) -> Result<Encoded<SearchResponse>, ApiError> {
+    let response = run_logged_search(&state, &namespace, &db, payload, None).await?;
+    Ok(Encoded(response, encoding))
+}
+
+// `run_search`, recorded in the slow query log if it took too long
+async fn run_logged_search(
+    state: &AppState,
+    namespace: &str,
+    db: &VectorDatabase,
+    payload: SearchRequest,
+    collection: Option<String>,
+) -> Result<SearchResponse, ApiError> {
+    let Some(slow_queries) = &state.slow_queries else {
+        return Ok(run_search(db, payload, collection).await?.0);
+    };
+    let mut query = SlowQuery::new(
+        namespace,
+        &payload.vector,
+        payload.sparse.as_ref(),
+        payload.k.unwrap_or(10),
+        collection.as_deref(),
+        payload.filter.as_ref(),
+    );
+    let started = Instant::now();
+    let (response, profile) = run_search(db, payload, collection).await?;
+    query.profile = profile;
+    slow_queries.record(query, started.elapsed());
+    Ok(response)
+}
+
+// Also returns how a plain dense search went
+async fn run_search(
+    db: &VectorDatabase,
+    payload: SearchRequest,
+    collection: Option<String>,
+) -> Result<(SearchResponse, Option<SearchProfile>), ApiError> {
+    let include_metadata = payload.include_metadata.unwrap_or(true);
+    let fields = payload.fields.clone();
+    let (mut response, profile) = search_results(db, payload, collection).await?;
+    if !include_metadata {
+        response.for_each_metadata(|metadata| *metadata = None);
+    } else if let Some(fields) = &fields {
+        response.for_each_metadata(|metadata| select_fields(metadata, fields));
+    }
+    Ok((response, profile))
+}
+
+async fn search_results(
+    db: &VectorDatabase,
+    payload: SearchRequest,
+    collection: Option<String>,
+) -> Result<(SearchResponse, Option<SearchProfile>), ApiError> {
+    let k = payload.k.unwrap_or(10);
+    let options = payload.options();
+    let threshold = payload
+        .threshold
+        .unwrap_or(options.score_mode.default_threshold());
+    // NaN would compare false with every score; MessagePack and CBOR can
+    // carry one where JSON can't
+    if threshold.is_nan()
+        || options
+            .max_distance
+            .is_some_and(|max| max.is_nan() || max < 0.0)
+    {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "threshold must be a number and max_distance one that isn't negative",
+        ));
+    }
+    let filter = SearchFilter {
+        collection,
+        metadata: payload.filter.unwrap_or_default(),
+    };
+
+    if let Some(sparse) = &payload.sparse {
+        if payload.group_by.is_some()
+            || options.rerank.is_some()
+            || options.vector_name.is_some()
+            || options.exact
+            || options.score_mode != ScoreMode::Raw
+            || options.max_distance.is_some()
+            || options.include_vector
+        {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "Sparse searches can't be grouped, reranked, exact, run on a named vector, take a score_mode or max_distance or include vectors",
+            ));
+        }
+        let results = if payload.vector.is_empty() {
+            db.search_sparse(sparse, k, threshold, &filter).await?
+        } else {
+            let fusion = payload.fusion.unwrap_or_default();
+            db.search_hybrid(&payload.vector, sparse, k, threshold, &filter, &fusion)
+                .await?
+        };
+        let response = SearchResponse {
+            results: results.into_iter().map(SearchResult::from).collect(),
+            groups: None,
+        };
+        return Ok((response, None));
+    }
+
+    if let Some(field) = payload.group_by {
+        let grouping = Grouping {
+            field,
+            group_size: payload.group_size.unwrap_or(1),
+        };
+        let groups = db
+            .search_grouped(&payload.vector, k, threshold, &filter, &grouping, &options)
+            .await?;
+        let response = SearchResponse {
+            results: Vec::new(),
+            groups: Some(groups),
+        };
+        return Ok((response, None));
+    }
+
+    let (results, profile) = db
+        .search_profiled(&payload.vector, k, threshold, &filter, &options)
+        .await?;
+    let response = SearchResponse {
+        results: results.into_iter().map(SearchResult::from).collect(),
+        groups: None,
+    };
+    Ok((response, Some(profile)))
+}
+
+async fn recommend_vectors(
+    State(state): State<AppState>,
+    Tenant { namespace, db, .. }: Tenant,
+    Json(payload): Json<RecommendRequest>,
+) -> Result<Json<SearchResponse>, ApiError> {
+    let RecommendRequest {
+        positive,
+        negative,
+        negative_weight,
+        collection,
+        mut search,
+    } = payload;
+    if !search.vector.is_empty() || search.sparse.is_some() || search.group_by.is_some() {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "Recommendations take example ids instead of a vector and can't be sparse or grouped",
+        ));
+    }
+    search.vector = db
+        .recommendation_query(
+            &positive,
+            &negative,
+            negative_weight.unwrap_or(1.0),
+            search.vector_name.as_deref(),
+        )
+        .await?;
+    // Room for the examples, which are left out
+    let k = search.k.unwrap_or(10);
+    search.k = Some(k + positive.len() + negative.len());
+    let mut response = run_logged_search(&state, &namespace, &db, search, collection).await?;
+    response
+        .results
+        .retain(|result| !positive.contains(&result.id) && !negative.contains(&result.id));
+    response.results.truncate(k);
+    Ok(Json(response))
+}
+
+async fn search_documents(
+    State(state): State<AppState>,
+    Tenant { db, .. }: Tenant,
+    Json(payload): Json<DocumentSearchRequest>,
+) -> Result<Json<DocumentSearchResponse>, ApiError> {
+    let k = payload.k.unwrap_or(10);
+    let threshold = payload.threshold.unwrap_or(0.0);
+    let content_field = payload.content_field.unwrap_or(state.text_field);
+    let filter = SearchFilter {
+        collection: payload.collection,
+        metadata: payload.filter.unwrap_or_default(),
+    };
+
+    let results = db
+        .search_filtered(&payload.vector, k, threshold, &filter)
+        .await?;
+    let documents = results
+        .into_iter()
+        .map(|r| {
+            let mut metadata = r.metadata.unwrap_or_default();
+            ScoredDocument {
+                id: r.id,
+                page_content: metadata.remove(&content_field).unwrap_or_default(),
+                metadata,
+                score: r.score,
+            }
+        })
+        .collect();
+    Ok(Json(DocumentSearchResponse { documents }))
+}
+
+async fn search_in_collection(
+    State(state): State<AppState>,
+    Tenant { namespace, db, .. }: Tenant,
+    Path(collection): Path<String>,
+    Encoded(payload, encoding): Encoded<SearchRequest>,
+) -> Result<Encoded<SearchResponse>, ApiError> {
+    let response = run_logged_search(&state, &namespace, &db, payload, Some(collection)).await?;
+    Ok(Encoded(response, encoding))
+}
+
+async fn create_snapshot(
+    Tenant { db, .. }: Tenant,
+    Path(collection): Path<String>,
+    Json(payload): Json<CreateSnapshotRequest>,
+) -> Result<(StatusCode, Json<SnapshotInfo>), StatusCode> {
+    if payload.name.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    match db.get_snapshot(&collection, &payload.name).await {
+        Ok(Some(_)) => return Err(StatusCode::CONFLICT),
+        Ok(None) => {}
+        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+
+    match db.create_snapshot(&collection, &payload.name).await {
+        Ok(info) => Ok((StatusCode::CREATED, Json(info))),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+async fn list_snapshots(
+    Tenant { db, .. }: Tenant,
+    Path(collection): Path<String>,
+) -> Result<Json<Vec<SnapshotInfo>>, StatusCode> {
+    match db.list_snapshots(&collection).await {
+        Ok(snapshots) => Ok(Json(snapshots)),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+async fn delete_snapshot(
+    Tenant { db, .. }: Tenant,
+    Path((collection, name)): Path<(String, String)>,
+) -> StatusCode {
+    match db.delete_snapshot(&collection, &name).await {
+        Ok(true) => StatusCode::NO_CONTENT,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+// Resolves to NOT_FOUND when the snapshot does not exist so that handlers
+// don't silently operate on an empty snapshot.
+async fn require_snapshot(
+    db: &VectorDatabase,
+    collection: &str,
+    name: &str,
+) -> Result<(), StatusCode> {
+    match db.get_snapshot(collection, name).await {
+        Ok(Some(_)) => Ok(()),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+async fn search_snapshot(
+    Tenant { db, .. }: Tenant,
+    Path((collection, name)): Path<(String, String)>,
+    Json(payload): Json<SearchRequest>,
+) -> Result<Json<SearchResponse>, ApiError> {
+    require_snapshot(&db, &collection, &name).await?;
+
+    let k = payload.k.unwrap_or(10);
+    let threshold = payload.threshold.unwrap_or(0.0);
+
+    match db
+        .search_snapshot(&collection, &name, &payload.vector, k, threshold)
+        .await
+    {
+        Ok(results) => {
+            let search_results = results.into_iter().map(SearchResult::from).collect();
+            Ok(Json(SearchResponse {
+                results: search_results,
+                groups: None,
+            }))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn export_snapshot(
+    Tenant { db, .. }: Tenant,
+    Path((collection, name)): Path<(String, String)>,
+) -> Result<Json<Vec<Vector>>, StatusCode> {
+    require_snapshot(&db, &collection, &name).await?;
+
+    match db.export_snapshot(&collection, &name).await {
+        Ok(vectors) => Ok(Json(vectors)),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+async fn clone_snapshot(
+    Tenant { db, .. }: Tenant,
+    Path((collection, name)): Path<(String, String)>,
+    Json(payload): Json<CloneSnapshotRequest>,
+) -> Result<Json<Vec<String>>, ApiError> {
+    require_snapshot(&db, &collection, &name).await?;
+
+    match db.clone_snapshot(&collection, &name, &payload.target).await {
+        Ok(ids) => Ok(Json(ids)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn diff_snapshot(
+    Tenant { db, .. }: Tenant,
+    Path((collection, name)): Path<(String, String)>,
+    Query(query): Query<DiffQuery>,
+) -> Result<Json<SnapshotDiff>, StatusCode> {
+    require_snapshot(&db, &collection, &name).await?;
+    if let Some(against) = &query.against {
+        require_snapshot(&db, &collection, against).await?;
+    }
+
+    match db
+        .diff_snapshot(&collection, &name, query.against.as_deref())
+        .await
+    {
+        Ok(diff) => Ok(Json(diff)),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+async fn create_backup(
+    State(state): State<AppState>,
+    Tenant { namespace, db, .. }: Tenant,
+    Json(payload): Json<BackupRequest>,
+) -> Result<Response, ApiError> {
+    if payload.destination.is_empty() {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "Backup destination must not be empty",
+        ));
+    }
+    if backup::is_remote(&payload.destination) && !cfg!(feature = "object-store") {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "Remote backups require building with --features object-store",
+        ));
+    }
+
+    if payload.background {
+        let job = state
+            .jobs
+            .start("backup", &namespace, None, |_| async move {
+                if payload.incremental {
+                    backup::backup_incremental(&db, &payload.destination).await?;
+                } else {
+                    backup::backup(&db, &payload.destination).await?;
+                }
+                Ok(())
+            });
+        return Ok((StatusCode::ACCEPTED, Json(job)).into_response());
+    }
+
+    let seq = if payload.incremental {
+        Some(backup::backup_incremental(&db, &payload.destination).await?)
+    } else {
+        backup::backup(&db, &payload.destination).await?;
+        None
+    };
+    Ok(Json(BackupResponse {
+        destination: payload.destination,
+        seq,
+    })
+    .into_response())
+}
+
+// Compaction needs storage to itself, so it fails if reads are in flight
+// when it starts; the job reports that
+async fn start_compaction(
+    State(state): State<AppState>,
+    Tenant { namespace, db, .. }: Tenant,
+) -> (StatusCode, Json<Job>) {
+    let job = state
+        .jobs
+        .start("compact", &namespace, None, |_| async move {
+            db.compact().await
+        });
+    (StatusCode::ACCEPTED, Json(job))
+}
+
+async fn start_reindex(
+    State(state): State<AppState>,
+    Tenant { namespace, db, .. }: Tenant,
+) -> Result<(StatusCode, Json<Job>), ApiError> {
+    let total = db.get_stats().await?.total_vectors as u64;
+    let job = state
+        .jobs
+        .start("reindex", &namespace, Some(total), |context| async move {
+            db.reindex(|count| {
+                context.set_progress(count as u64);
+                context.check_cancelled()
+            })
+            .await?;
+            Ok(())
+        });
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+async fn list_jobs(State(state): State<AppState>) -> Json<Vec<Job>> {
+    Json(state.jobs.list())
+}
+
+// Empty when the slow query log is off
+async fn list_audit_entries(
+    State(state): State<AppState>,
+    Query(query): Query<AuditQuery>,
+) -> Result<Json<AuditPage>, ApiError> {
+    let Some(log) = &state.audit else {
+        return Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            "The audit log is off; enable it with [audit] enabled = true",
+        ));
+    };
+    Ok(Json(log.page(&query).await?))
+}
+
+async fn list_slow_queries(State(state): State<AppState>) -> Json<Vec<SlowQuery>> {
+    Json(
+        state
+            .slow_queries
+            .as_ref()
+            .map(|log| log.recent())
+            .unwrap_or_default(),
+    )
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClusterMembersResponse {
+    // Id of the node that answered
+    pub local: String,
+    pub members: Vec<Member>,
+}
+
+async fn list_cluster_members(
+    State(state): State<AppState>,
+) -> Result<Json<ClusterMembersResponse>, ApiError> {
+    let cluster = state.cluster.as_ref().ok_or_else(|| {
+        ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "The P2P node isn't running",
+        )
+    })?;
+    let members = cluster.members().await?;
+    let local = cluster.local_id();
+    Ok(Json(ClusterMembersResponse { local, members }))
+}
+
+// What owners have queued for other replicas and sent so far
+async fn cluster_replication_status(State(state): State<AppState>) -> Json<ReplicationStatus> {
+    Json(state.replicator.status())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PlacementResponse {
+    pub collection: String,
+    pub replication_factor: usize,
+    pub labels: BTreeMap<String, String>,
+    // Members holding the collection, the one taking its writes first
+    pub replicas: Vec<Member>,
+    // Whether there are as many as the replication factor asks for
+    pub satisfied: bool,
+}
+
+async fn get_placement(
+    State(state): State<AppState>,
+    Path(collection): Path<String>,
+) -> Result<Json<PlacementResponse>, ApiError> {
+    let node = state.sharded().ok_or_else(|| {
+        ApiError::new(
+            StatusCode::NOT_FOUND,
+            "Cluster mode isn't enabled on this node",
+        )
+    })?;
+    let policy = state.placement.policy(&collection).clone();
+    let replicas = cluster::replicas(node, &state.placement, Some(&collection)).await?;
+    Ok(Json(PlacementResponse {
+        collection,
+        satisfied: replicas.len() >= policy.replication_factor,
+        replication_factor: policy.replication_factor,
+        labels: policy.labels,
+        replicas,
+    }))
+}
+
+fn not_a_follower() -> ApiError {
+    ApiError::new(StatusCode::NOT_FOUND, "This node isn't following a primary")
+}
+
+async fn replication_status(
+    State(state): State<AppState>,
+) -> Result<Json<FollowerStatus>, ApiError> {
+    let follower = state.follower.as_ref().ok_or_else(not_a_follower)?;
+    Ok(Json(follower.status()))
+}
+
+// Stops following and takes writes from then on
+async fn promote_follower(State(state): State<AppState>) -> Result<Json<FollowerStatus>, ApiError> {
+    let follower = state.follower.as_ref().ok_or_else(not_a_follower)?;
+    follower.promote().await;
+    Ok(Json(follower.status()))
+}
+
+// Replicated writes that conflicted with local ones, left for an operator
+async fn list_conflicts(Tenant { db, .. }: Tenant) -> Result<Json<Vec<Conflict>>, ApiError> {
+    Ok(Json(db.conflicts().await?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResolveConflictRequest {
+    pub keep: ConflictSide,
+}
+
+// Writes the side kept as a new version that replicates over both
+async fn resolve_conflict(
+    Tenant { db, .. }: Tenant,
+    Path(id): Path<String>,
+    Json(request): Json<ResolveConflictRequest>,
+) -> Result<StatusCode, ApiError> {
+    if db.resolve_conflict(&id, request.keep).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            format!("No conflict on vector '{}'", id),
+        ))
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct VerifyQuery {
+    // Replace corrupt vectors with copies from other members
+    #[serde(default)]
+    pub repair: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VerifyResponse {
+    pub checked: u64,
+    pub corrupt: Vec<CorruptVector>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CorruptVector {
+    pub id: String,
+    pub error: String,
+    pub repaired: bool,
+}
+
+// Reads every stored vector back and reports the corrupt ones. With
+// `repair`, those a member of the cluster holds a good copy of are
+// replaced by it.
+async fn verify_storage(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Query(query): Query<VerifyQuery>,
+) -> Result<Json<VerifyResponse>, ApiError> {
+    let report = tenant.db.verify().await?;
+    let mut corrupt = Vec::with_capacity(report.corrupt.len());
+    for record in report.corrupt {
+        let mut repaired = false;
+        if let (true, Some(node)) = (query.repair, state.sharded()) {
+            match cluster::fetch_copy(node, &tenant.namespace, &record.id).await? {
+                Some(copy) => {
+                    tenant.db.repair_vector(&copy).await?;
+                    info!("Repaired corrupt vector {}", record.id);
+                    repaired = true;
+                }
+                None => warn!("No member has a copy of corrupt vector {}", record.id),
+            }
+        }
+        corrupt.push(CorruptVector {
+            id: record.id,
+            error: record.error,
+            repaired,
+        });
+    }
+    Ok(Json(VerifyResponse {
+        checked: report.checked,
+        corrupt,
+    }))
+}
+
+fn job_not_found(id: &str) -> ApiError {
+    ApiError::new(StatusCode::NOT_FOUND, format!("Job '{}' not found", id))
+}
+
+async fn get_job(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<Job>, ApiError> {
+    state
+        .jobs
+        .get(&id)
+        .map(Json)
+        .ok_or_else(|| job_not_found(&id))
+}
+
+// Jobs stop at their next safe point, so the job is returned still running
+// with `cancel_requested` set. Compaction can't be stopped once it starts.
+async fn cancel_job(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, Json<Job>), ApiError> {
+    let job = state.jobs.cancel(&id).ok_or_else(|| job_not_found(&id))?;
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+// The whole file comes in the body, so it's subject to `max_body_bytes`;
+// larger files are better loaded offline with the `import` command
+async fn import_vectors(
+    tenant: Tenant,
+    Query(query): Query<ImportQuery>,
+    body: Bytes,
+) -> Result<Json<ImportResponse>, ApiError> {
+    let bad_request = |e: anyhow::Error| ApiError::new(StatusCode::BAD_REQUEST, e.to_string());
+    let format: DatasetFormat = query.format.parse().map_err(bad_request)?;
+    if format == DatasetFormat::Json {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "Send JSON vectors to POST /vectors",
+        ));
+    }
+    let mapping = query.mapping();
+    let vectors =
+        tokio::task::spawn_blocking(move || dataset::read_vectors(body, format, &mapping))
+            .await
+            .map_err(anyhow::Error::from)?
+            .map_err(bad_request)?;
+
+    let imported = tenant.db.import_vectors(vectors).await?.len();
+    Ok(Json(ImportResponse { imported }))
+}
+
+async fn export_vectors(
+    Tenant { db, .. }: Tenant,
+    Query(query): Query<ExportQuery>,
+) -> Result<Response, ApiError> {
+    let format: ExportFormat = query
+        .format
+        .as_deref()
+        .unwrap_or("parquet")
+        .parse()
+        .map_err(|e: anyhow::Error| ApiError::new(StatusCode::BAD_REQUEST, e.to_string()))?;
+    if !cfg!(feature = "parquet") {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "Exports require building with --features parquet",
+        ));
+    }
+
+    // Only the first page is checked up front; a later mismatch cuts the
+    // download short
+    let first = db
+        .scroll(query.collection.as_deref(), None, export::BATCH_ROWS)
+        .await?;
+    export::dimensions(&first.vectors)
+        .map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, e.to_string()))?;
+    let disposition = format!("attachment; filename=\"vectors.{}\"", format.extension());
+    Ok((
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        export::stream(export::pages(db, query.collection), format),
+    )
+        .into_response())
+}
+
+// Each vector's nearest neighbors, computed page by page as the body is
+// sent, for offline clustering and visualization
+async fn export_knn_graph(
+    Tenant { db, .. }: Tenant,
+    Query(query): Query<KnnGraphQuery>,
+) -> Result<Response, ApiError> {
+    let format: GraphFormat = query
+        .format
+        .as_deref()
+        .unwrap_or("jsonl")
+        .parse()
+        .map_err(|e: anyhow::Error| ApiError::new(StatusCode::BAD_REQUEST, e.to_string()))?;
+    if format == GraphFormat::Parquet && !cfg!(feature = "parquet") {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "Parquet graphs require building with --features parquet",
+        ));
+    }
+    let neighbors = query.n.unwrap_or(DEFAULT_GRAPH_NEIGHBORS);
+    if !(1..=MAX_GRAPH_NEIGHBORS).contains(&neighbors) {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("n must be between 1 and {}", MAX_GRAPH_NEIGHBORS),
+        ));
+    }
+
+    let disposition = format!("attachment; filename=\"knn-graph.{}\"", format.extension());
+    Ok((
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        knn_graph::stream(knn_graph::pages(db, query.collection, neighbors), format),
+    )
+        .into_response())
+}
+
+async fn list_changes(
+    Tenant { db, .. }: Tenant,
+    Query(query): Query<ChangesQuery>,
+) -> Result<Json<ChangesResponse>, ApiError> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_CHANGES_LIMIT)
+        .min(MAX_CHANGES_LIMIT);
+    let Some(changes) = db.changes_since(query.since, limit).await? else {
+        return Err(ApiError::new(
+            StatusCode::GONE,
+            format!(
+                "Changes after seq {} are no longer available; start over from a full export",
+                query.since
+            ),
+        ));
+    };
+    let next = changes.last().map_or(query.since, |change| change.seq);
+    Ok(Json(ChangesResponse { changes, next }))
+}
+
+async fn subscribe_changes(Tenant { db, .. }: Tenant, ws: WebSocketUpgrade) -> Response {
+    let changes = db.subscribe();
+    ws.on_upgrade(move |socket| send_changes(socket, changes))
+}
+
+// One JSON text message per change. A subscriber too slow to keep up is
+// disconnected rather than silently missing events.
+async fn send_changes(mut socket: WebSocket, mut changes: broadcast::Receiver<ChangeEvent>) {
+    loop {
+        tokio::select! {
+            change = changes.recv() => {
+                let event = match change {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        let reason = format!("Fell behind by {} events", missed);
+                        let _ = socket
+                            .send(Message::Close(Some(CloseFrame {
+                                code: axum::extract::ws::close_code::AGAIN,
+                                reason: reason.into(),
+                            })))
+                            .await;
+                        return;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                };
+                let Ok(json) = serde_json::to_string(&event) else {
+                    continue;
+                };
+                if socket.send(Message::Text(json)).await.is_err() {
+                    return;
+                }
+            }
+            // Anything from the client other than a close is ignored
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+fn api_keys(state: &AppState) -> Result<&ApiKeys, ApiError> {
+    state
+        .auth
+        .as_deref()
+        .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, "API keys are not enabled"))
+}
+
+async fn list_api_keys(State(state): State<AppState>) -> Result<Json<Vec<ApiKeyInfo>>, ApiError> {
+    Ok(Json(api_keys(&state)?.list().await))
+}
+
+async fn create_api_key(
+    State(state): State<AppState>,
+    Json(payload): Json<ApiKeyRequest>,
+) -> Result<(StatusCode, Json<CreateApiKeyResponse>), ApiError> {
+    let (info, key) = api_keys(&state)?.create(payload.role).await?;
+    Ok((
+        StatusCode::CREATED,
+        Json(CreateApiKeyResponse {
+            id: info.id,
+            role: info.role,
+            key,
+        }),
+    ))
+}
+
+async fn update_api_key(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(payload): Json<ApiKeyRequest>,
+) -> Result<StatusCode, ApiError> {
+    match api_keys(&state)?.set_role(&id, payload.role).await? {
+        true => Ok(StatusCode::NO_CONTENT),
+        false => Err(StatusCode::NOT_FOUND.into()),
+    }
+}
+
+async fn revoke_api_key(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    match api_keys(&state)?.revoke(&id).await? {
+        true => Ok(StatusCode::NO_CONTENT),
+        false => Err(StatusCode::NOT_FOUND.into()),
+    }
+}
+
+#[cfg(feature = "embeddings")]
+async fn embed_and_insert(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    Json(payload): Json<EmbedAndInsertRequest>,
+) -> Result<Json<Vec<String>>, ApiError> {
+    let embedder = state
+        .embedder
+        .as_ref()
+        .ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+
+    let texts: Vec<String> = payload.items.iter().map(|item| item.text.clone()).collect();
+    let embeddings = embedder
+        .embed(&texts)
+        .await
+        .map_err(|_| StatusCode::BAD_GATEWAY)?;
+
+    let vectors = payload
+        .items
+        .into_iter()
+        .zip(embeddings)
+        .map(|(item, data)| {
+            let mut vector = match item.id {
+                Some(id) => Vector::with_id(id, data),
+                None => Vector::new(data),
+            };
+            let mut metadata = item.metadata.unwrap_or_default();
+            metadata.insert(state.text_field.clone(), item.text);
+            vector.metadata = Some(metadata);
+            vector.collection = item.collection;
+            vector.model = embedder.model().map(str::to_string);
+            vector
+        })
+        .collect::<Vec<_>>();
+
+    match tenant.db.insert_vectors(vectors).await {
+        Ok(ids) => Ok(Json(ids)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(feature = "embeddings")]
+async fn search_text(
+    State(state): State<AppState>,
+    Tenant { db, .. }: Tenant,
+    Json(payload): Json<TextSearchRequest>,
+) -> Result<Json<SearchResponse>, ApiError> {
+    let embedder = state
+        .embedder
+        .as_ref()
+        .ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+
+    let query = embedder
+        .embed(std::slice::from_ref(&payload.text))
+        .await
+        .map_err(|_| StatusCode::BAD_GATEWAY)?
+        .pop()
+        .ok_or(StatusCode::BAD_GATEWAY)?;
+
+    let k = payload.k.unwrap_or(10);
+    let threshold = payload.threshold.unwrap_or(0.0);
+
+    let filter = SearchFilter {
+        collection: payload.collection,
+        ..Default::default()
+    };
+    let options = SearchOptions {
+        model: embedder.model().map(str::to_string),
+        ..Default::default()
+    };
+    let results = db
+        .search_with(&query, k, threshold, &filter, &options)
+        .await;
+
+    match results {
+        Ok(results) => {
+            let search_results = results.into_iter().map(SearchResult::from).collect();
+            Ok(Json(SearchResponse {
+                results: search_results,
+                groups: None,
+            }))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use axum_test::TestServer;
+    use skypier_core::{ChangeKind, JobStatus, VectorDatabase};
+    use std::collections::HashMap;
+
+    async fn create_test_db() -> Arc<VectorDatabase> {
+        Arc::new(VectorDatabase::in_memory().unwrap())
+    }
+
+    // For what only storage on disk does: backups, compaction, its size and
+    // the WAL truncation that index snapshots bring. The database lives as
+    // long as the directory does.
+    async fn create_disk_db() -> (Arc<VectorDatabase>, tempfile::TempDir) {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = VectorDatabase::new(temp_dir.path().to_str().unwrap())
+            .await
+            .unwrap();
+        (Arc::new(db), temp_dir)
+    }
+
+    async fn create_test_app() -> TestServer {
+        let db = create_test_db().await;
+        let app = create_router(AppState::new(db));
+
+        TestServer::new(app).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_health_check() {
+        let server = create_test_app().await;
+
+        let response = server.get("/health").await;
+
+        assert_eq!(response.status_code(), StatusCode::OK);
+        assert_eq!(response.text(), "OK");
+    }
+
+    #[tokio::test]
+    async fn test_responses_compressed_when_accepted() {
+        let server = create_test_app().await;
+        let insert_request = InsertRequest {
+            vectors: (0..50)
+                .map(|i| Vector::new(vec![1.0, i as f32, 0.0]))
+                .collect(),
+            ..Default::default()
+        };
+        server.post("/vectors").json(&insert_request).await;
+        let search_request = SearchRequest {
+            vector: vec![1.0, 0.1, 0.1],
+            k: Some(50),
+            threshold: Some(0.0),
+            ..Default::default()
+        };
+
+        for encoding in ["gzip", "zstd"] {
+            let response = server
+                .post("/search")
+                .add_header("accept-encoding", encoding)
+                .json(&search_request)
+                .await;
+            assert_eq!(response.status_code(), StatusCode::OK);
+            assert_eq!(response.header("content-encoding"), encoding);
+        }
+        let response = server.post("/search").json(&search_request).await;
+        assert!(response.maybe_header("content-encoding").is_none());
+        assert_eq!(response.json::<SearchResponse>().results.len(), 50);
+    }
+
+    #[tokio::test]
+    async fn test_binary_encoded_insert_and_search() {
+        use crate::codec::Encoding;
+
+        let server = create_test_app().await;
+        for encoding in [Encoding::MsgPack, Encoding::Cbor] {
+            let collection = format!("{:?}", encoding);
+            let insert_request = InsertRequest {
+                vectors: vec![
+                    Vector::with_id(format!("{}-a", collection), vec![1.0, 0.0])
+                        .with_collection(collection.clone()),
+                    Vector::with_id(format!("{}-b", collection), vec![0.0, 1.0])
+                        .with_collection(collection.clone()),
+                ],
+                ..Default::default()
+            };
+            let response = server
+                .post("/vectors")
+                .content_type(encoding.content_type())
+                .bytes(encoding.encode(&insert_request).unwrap().into())
+                .await;
+            assert_eq!(response.status_code(), StatusCode::OK);
+            assert_eq!(response.header("content-type"), encoding.content_type());
+            let ids: Vec<String> = encoding.decode(response.as_bytes()).unwrap();
+            assert_eq!(ids.len(), 2);
+
+            let search_request = SearchRequest {
+                vector: vec![0.1, 1.0],
+                k: Some(1),
+                ..Default::default()
+            };
+            let response = server
+                .post(&format!("/collections/{}/search", collection))
+                .content_type(encoding.content_type())
+                .bytes(encoding.encode(&search_request).unwrap().into())
+                .await;
+            let results: SearchResponse = encoding.decode(response.as_bytes()).unwrap();
+            assert_eq!(results.results[0].id, format!("{}-b", collection));
+
+            // Accept picks the response encoding
+            let response = server
+                .post("/search")
+                .add_header("accept", encoding.content_type())
+                .json(&search_request)
+                .await;
+            let results: SearchResponse = encoding.decode(response.as_bytes()).unwrap();
+            assert_eq!(results.results.len(), 1);
+        }
+
+        let response = server
+            .post("/search")
+            .content_type("application/msgpack")
+            .bytes(b"not msgpack".to_vec().into())
+            .await;
+        assert_eq!(response.status_code(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_raw_vector_upload() {
+        use skypier_core::Dtype;
+
+        let server = create_test_app().await;
+        let side = raw::SideChannel {
+            ids: Some(vec![Some("a".to_string()), None]),
+            collection: Some("docs".to_string()),
+            ..Default::default()
+        };
+        let body = raw::encode(&[vec![1.0, 0.0], vec![0.0, 1.0]], Dtype::F16, &side).unwrap();
+        let response = server.post("/vectors/raw").bytes(body.into()).await;
+        assert_eq!(response.status_code(), StatusCode::OK);
+        let ids: Vec<String> = response.json();
+        assert_eq!(ids[0], "a");
+        assert!(!ids[1].is_empty());
+
+        let stored: Vector = server.get(&format!("/vectors/{}", ids[1])).await.json();
+        assert_eq!(stored.data, vec![0.0, 1.0]);
+        assert_eq!(stored.collection.as_deref(), Some("docs"));
+
+        let response = server
+            .post("/vectors/raw")
+            .bytes(b"not raw vectors".to_vec().into())
+            .await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_health_live_and_ready() {
+        let db = create_test_db().await;
+        let keys = ApiKeys::load(Arc::clone(&db), "x-api-key", Some("admin"))
+            .await
+            .unwrap();
+        let state = AppState::new(Arc::clone(&db)).with_auth(Arc::new(keys));
+        let server = TestServer::new(create_router(state)).unwrap();
+
+        let response = server.get("/health/live").await;
+        assert_eq!(response.status_code(), StatusCode::OK);
+
+        let response = server.get("/health/ready").await;
+        assert_eq!(response.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+        let readiness: ReadinessResponse = response.json();
+        assert!(!readiness.ready);
+        let failed: Vec<&str> = readiness
+            .checks
+            .iter()
+            .filter(|check| !check.ok)
+            .map(|check| check.name.as_str())
+            .collect();
+        assert_eq!(failed, vec!["index"]);
+
+        db.load_index().await.unwrap();
+        let response = server.get("/health/ready").await;
+        assert_eq!(response.status_code(), StatusCode::OK);
+        let readiness: ReadinessResponse = response.json();
+        assert!(readiness.ready && readiness.checks.len() == 2);
+    }
+
+    #[tokio::test]
+    async fn test_get_stats_empty_db() {
+        let server = create_test_app().await;
+
+        let response = server.get("/stats").await;
+
+        assert_eq!(response.status_code(), StatusCode::OK);
+        let stats: StatsResponse = response.json();
+        assert_eq!(stats.total_vectors, 0);
+        assert_eq!(stats.dimensions, 0);
+    }
+
+    #[tokio::test]
+    async fn test_insert_single_vector() {
+        let server = create_test_app().await;
+
+        let vector = Vector::new(vec![1.0, 2.0, 3.0]);
+        let insert_request = InsertRequest {
+            vectors: vec![vector],
+            ..Default::default()
+        };
+
+        let response = server.post("/vectors").json(&insert_request).await;
+
+        assert_eq!(response.status_code(), StatusCode::OK);
+        let ids: Vec<String> = response.json();
+        assert_eq!(ids.len(), 1);
+        assert!(!ids[0].is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_insert_multiple_vectors() {
+        let server = create_test_app().await;
+
+        let vectors = vec![
+            Vector::new(vec![1.0, 2.0, 3.0]),
+            Vector::new(vec![4.0, 5.0, 6.0]),
+            Vector::new(vec![7.0, 8.0, 9.0]),
+        ];
+        let insert_request = InsertRequest {
+            vectors,
+            ..Default::default()
+        };
+
+        let response = server.post("/vectors").json(&insert_request).await;
+
+        assert_eq!(response.status_code(), StatusCode::OK);
+        let ids: Vec<String> = response.json();
+        assert_eq!(ids.len(), 3);
+        assert!(ids.iter().all(|id| !id.is_empty()));
+    }
+
+    #[tokio::test]
+    async fn test_insert_vector_with_metadata() {
+        let server = create_test_app().await;
+
+        let mut metadata = HashMap::new();
+        metadata.insert("type".to_string(), "test".to_string());
+        metadata.insert("category".to_string(), "unit_test".to_string());
+
+        let vector = Vector::new(vec![1.0, 2.0, 3.0]).with_metadata(metadata);
+        let insert_request = InsertRequest {
+            vectors: vec![vector],
+            ..Default::default()
+        };
+
+        let response = server.post("/vectors").json(&insert_request).await;
+
+        assert_eq!(response.status_code(), StatusCode::OK);
+        let ids: Vec<String> = response.json();
+        assert_eq!(ids.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_get_vector_success() {
+        let server = create_test_app().await;
+
+        // First insert a vector
+        let vector = Vector::new(vec![1.0, 2.0, 3.0]);
+        let insert_request = InsertRequest {
+            vectors: vec![vector.clone()],
+            ..Default::default()
+        };
+
+        let insert_response = server.post("/vectors").json(&insert_request).await;
+
+        let ids: Vec<String> = insert_response.json();
+        let vector_id = &ids[0];
+
+        // Now get the vector
+        let get_response = server.get(&format!("/vectors/{}", vector_id)).await;
+
+        assert_eq!(get_response.status_code(), StatusCode::OK);
+        let retrieved_vector: Vector = get_response.json();
+        assert_eq!(retrieved_vector.id, *vector_id);
+        assert_eq!(retrieved_vector.data, vec![1.0, 2.0, 3.0]);
+    }
+
+    #[tokio::test]
+    async fn test_get_vector_not_found() {
+        let server = create_test_app().await;
+
+        let response = server.get("/vectors/nonexistent-id").await;
+
+        assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_search_vectors() {
+        let server = create_test_app().await;
+
+        // Insert test vectors
+        let vectors = vec![
+            Vector::new(vec![1.0, 0.0, 0.0]),
+            Vector::new(vec![0.0, 1.0, 0.0]),
+            Vector::new(vec![0.0, 0.0, 1.0]),
+        ];
+        let insert_request = InsertRequest {
+            vectors,
+            ..Default::default()
+        };
+
+        let insert_response = server.post("/vectors").json(&insert_request).await;
+        assert_eq!(insert_response.status_code(), StatusCode::OK);
+
+        // Search for similar vectors
+        let search_request = SearchRequest {
+            vector: vec![1.0, 0.1, 0.1],
+            k: Some(2),
+            threshold: Some(0.0),
+            filter: None,
+            ..Default::default()
+        };
+
+        let search_response = server.post("/search").json(&search_request).await;
+
+        assert_eq!(search_response.status_code(), StatusCode::OK);
+        let search_result: SearchResponse = search_response.json();
+        assert!(search_result.results.len() <= 2);
+        assert!(!search_result.results.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_search_vectors_with_defaults() {
+        let server = create_test_app().await;
+
+        // Insert test vectors
+        let vector = Vector::new(vec![1.0, 2.0, 3.0]);
+        let insert_request = InsertRequest {
+            vectors: vec![vector],
+            ..Default::default()
+        };
+
+        let insert_response = server.post("/vectors").json(&insert_request).await;
+        assert_eq!(insert_response.status_code(), StatusCode::OK);
+
+        // Search with minimal parameters (using defaults)
+        let search_request = SearchRequest {
+            vector: vec![1.0, 2.0, 3.0],
+            k: None,         // Should default to 10
+            threshold: None, // Should default to 0.0
+            filter: None,
+            ..Default::default()
+        };
+
+        let search_response = server.post("/search").json(&search_request).await;
+
+        assert_eq!(search_response.status_code(), StatusCode::OK);
+        let search_result: SearchResponse = search_response.json();
+        assert_eq!(search_result.results.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_search_in_collection() {
+        let server = create_test_app().await;
+
+        // Insert vectors in different collections
+        let vectors = vec![
+            Vector::new(vec![1.0, 0.0, 0.0]).with_collection("collection1".to_string()),
+            Vector::new(vec![0.0, 1.0, 0.0]).with_collection("collection2".to_string()),
+            Vector::new(vec![0.0, 0.0, 1.0]).with_collection("collection1".to_string()),
+        ];
+        let insert_request = InsertRequest {
+            vectors,
+            ..Default::default()
+        };
+
+        let insert_response = server.post("/vectors").json(&insert_request).await;
+        assert_eq!(insert_response.status_code(), StatusCode::OK);
+
+        // Search in specific collection
+        let search_request = SearchRequest {
+            vector: vec![1.0, 0.0, 0.0],
+            k: Some(10),
+            threshold: Some(0.0),
+            filter: None,
+            ..Default::default()
+        };
+
+        let search_response = server
+            .post("/collections/collection1/search")
+            .json(&search_request)
+            .await;
+
+        assert_eq!(search_response.status_code(), StatusCode::OK);
+        let search_result: SearchResponse = search_response.json();
+        // Should find vectors only from collection1
+        assert!(search_result.results.len() <= 2);
+    }
+
+    #[tokio::test]
+    async fn test_collection_stats() {
+        let server = create_test_app().await;
+
+        let vectors = vec![
+            Vector::new(vec![1.0, 0.0, 0.0]).with_collection("docs".to_string()),
+            Vector::new(vec![0.0, 1.0, 0.0]).with_collection("docs".to_string()),
+            Vector::new(vec![0.0, 0.0, 1.0]).with_collection("notes".to_string()),
+        ];
+        let response = server
+            .post("/vectors")
+            .json(&InsertRequest {
+                vectors,
+                ..Default::default()
+            })
+            .await;
+        assert_eq!(response.status_code(), StatusCode::OK);
+
+        let response = server.get("/collections/docs/stats").await;
+        assert_eq!(response.status_code(), StatusCode::OK);
+        let stats: CollectionStatsResponse = response.json();
+        assert_eq!(stats.vector_count, 2);
+        assert_eq!(stats.dimensions, 3);
+        assert_eq!(stats.index_type, "hnsw");
+        assert!(stats.storage_bytes > 0);
+        assert!(stats.last_modified > 0);
+
+        let response = server.get("/collections/missing/stats").await;
+        assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_collection() {
+        use serde_json::{json, Value};
+        let server = create_test_app().await;
+
+        let source = |value: &str| HashMap::from([("source".to_string(), value.to_string())]);
+        let vectors = vec![
+            Vector::new(vec![1.0, 0.0]).with_metadata(source("web")),
+            Vector::new(vec![0.0, 1.0]).with_metadata(source("pdf")),
+            Vector::new(vec![1.0, 1.0]).with_metadata(source("web")),
+            Vector::new(vec![1.0, 2.0]),
+        ];
+        let vectors = vectors
+            .into_iter()
+            .map(|vector| vector.with_collection("docs".to_string()))
+            .chain([Vector::new(vec![2.0, 1.0]).with_metadata(source("pdf"))])
+            .collect();
+        server
+            .post("/vectors")
+            .json(&InsertRequest {
+                vectors,
+                ..Default::default()
+            })
+            .await
+            .assert_status_ok();
+
+        let result: Value = server
+            .get("/collections/docs/aggregate?group_by=source")
+            .await
+            .json();
+        assert_eq!(
+            result,
+            json!({
+                "collection": "docs",
+                "group_by": "source",
+                "total": 4,
+                "missing": 1,
+                "values": [{"value": "web", "count": 2}, {"value": "pdf", "count": 1}],
+            })
+        );
+        let result: Value = server
+            .get("/collections/docs/aggregate?group_by=source&limit=1")
+            .await
+            .json();
+        assert_eq!(result["values"].as_array().unwrap().len(), 1);
+
+        let response = server
+            .get("/collections/missing/aggregate?group_by=source")
+            .await;
+        assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+        let response = server.get("/collections/docs/aggregate").await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_scroll_collection() {
+        let server = create_test_app().await;
+
+        let vectors = (0..5)
+            .map(|i| Vector::with_id(format!("v{}", i), vec![1.0, i as f32]))
+            .map(|vector| vector.with_collection("docs".to_string()))
+            .chain([Vector::with_id("other".to_string(), vec![1.0, 1.0])])
+            .collect();
+        server
+            .post("/vectors")
+            .json(&InsertRequest {
+                vectors,
+                ..Default::default()
+            })
+            .await
+            .assert_status_ok();
+
+        let mut ids = Vec::new();
+        let mut url = "/collections/docs/scroll?limit=2".to_string();
+        loop {
+            let page: ScrollPage = server.get(&url).await.json();
+            assert!(page.vectors.len() <= 2);
+            ids.extend(page.vectors.into_iter().map(|vector| vector.id));
+            match page.next {
+                Some(next) => url = format!("/collections/docs/scroll?limit=2&after={}", next),
+                None => break,
+            }
+        }
+        assert_eq!(ids, vec!["v0", "v1", "v2", "v3", "v4"]);
+
+        let page: ScrollPage = server.get("/collections/missing/scroll").await.json();
+        assert!(page.vectors.is_empty() && page.next.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_similarity_between_stored_vectors() {
+        let server = create_test_app().await;
+        server
+            .post("/vectors")
+            .json(&serde_json::json!({"vectors": [
+                {"id": "a", "data": [1.0, 0.0], "created_at": 0},
+                {"id": "b", "data": [0.0, 2.0], "created_at": 0, "vectors": {"title": [1.0, 0.0]}},
+                {"id": "c", "data": [1.0, 0.0, 0.0], "created_at": 0},
+            ]}))
+            .await
+            .assert_status_ok();
+
+        let response: SimilarityResponse = server.get("/similarity?id_a=a&id_b=b").await.json();
+        assert_eq!(response.metric, DistanceMetric::Cosine);
+        assert!(response.score.abs() < 1e-6);
+        assert!((response.distance - 1.0).abs() < 1e-6);
+        let response: SimilarityResponse = server
+            .get("/similarity?id_a=a&id_b=b&metric=euclidean")
+            .await
+            .json();
+        assert!((response.distance - 5.0_f32.sqrt()).abs() < 1e-5);
+
+        let response = server.get("/similarity?id_a=a&id_b=missing").await;
+        assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+        let response = server.get("/similarity?id_a=a&id_b=c").await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+        let response = server
+            .get("/similarity?id_a=b&id_b=a&vector_name=title")
+            .await;
+        assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_recommend() {
+        let server = create_test_app().await;
+        let vectors = [
+            ("liked", [1.0, 0.1]),
+            ("also_liked", [1.0, -0.1]),
+            ("disliked", [0.0, 1.0]),
+            ("similar", [0.9, 0.0]),
+            ("like_disliked", [0.1, 1.0]),
+            ("in_between", [0.7, 0.7]),
+        ]
+        .into_iter()
+        .map(|(id, data)| Vector::with_id(id.to_string(), data.to_vec()))
+        .collect();
+        server
+            .post("/vectors")
+            .json(&InsertRequest {
+                vectors,
+                ..Default::default()
+            })
+            .await
+            .assert_status_ok();
+
+        let ids = |response: SearchResponse| -> Vec<String> {
+            response.results.into_iter().map(|r| r.id).collect()
+        };
+        let response: SearchResponse = server
+            .post("/recommend")
+            .json(&serde_json::json!({
+                "positive": ["liked", "also_liked"],
+                "negative": ["disliked"],
+                "k": 3,
+            }))
+            .await
+            .json();
+        // The examples themselves aren't recommended, and the query is
+        // pushed far enough from the negative example that what's like it
+        // scores below the threshold
+        assert_eq!(ids(response), vec!["similar", "in_between"]);
+
+        let response = server
+            .post("/recommend")
+            .json(&serde_json::json!({"positive": ["missing"]}))
+            .await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+        let response = server
+            .post("/recommend")
+            .json(&serde_json::json!({"positive": [], "negative": ["liked"]}))
+            .await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_sample_collection() {
+        let server = create_test_app().await;
+        let vectors = (0..20)
+            .map(|i| {
+                Vector::with_id(i.to_string(), vec![1.0, i as f32])
+                    .with_collection("docs".to_string())
+            })
+            .collect();
+        server
+            .post("/vectors")
+            .json(&InsertRequest {
+                vectors,
+                ..Default::default()
+            })
+            .await
+            .assert_status_ok();
+
+        let sample: Vec<Vector> = server
+            .get("/collections/docs/sample?n=5&seed=3")
+            .await
+            .json();
+        assert_eq!(sample.len(), 5);
+        let again: Vec<Vector> = server
+            .get("/collections/docs/sample?n=5&seed=3")
+            .await
+            .json();
+        let ids = |sample: &[Vector]| sample.iter().map(|v| v.id.clone()).collect::<Vec<_>>();
+        assert_eq!(ids(&again), ids(&sample));
+        let all: Vec<Vector> = server.get("/collections/docs/sample").await.json();
+        assert_eq!(all.len(), 20);
+    }
+
+    #[tokio::test]
+    async fn test_collection_aliases() {
+        use serde_json::json;
+        let server = create_test_app().await;
+        server
+            .post("/vectors")
+            .json(&InsertRequest {
+                vectors: vec![
+                    Vector::with_id("old".to_string(), vec![1.0, 0.0])
+                        .with_collection("docs_v1".to_string()),
+                    Vector::with_id("new".to_string(), vec![1.0, 0.0])
+                        .with_collection("docs_v2".to_string()),
+                ],
+                ..Default::default()
+            })
+            .await
+            .assert_status_ok();
+
+        let search = || async {
+            let response: SearchResponse = server
+                .post("/collections/docs/search")
+                .json(&json!({"vector": [1.0, 0.0]}))
+                .await
+                .json();
+            response
+                .results
+                .into_iter()
+                .map(|r| r.id)
+                .collect::<Vec<_>>()
+        };
+        for (collection, expected) in [("docs_v1", "old"), ("docs_v2", "new")] {
+            server
+                .put("/aliases/docs")
+                .json(&json!({ "collection": collection }))
+                .await
+                .assert_status_ok();
+            assert_eq!(search().await, vec![expected]);
+        }
+        let aliases: Vec<Alias> = server.get("/aliases").await.json();
+        assert_eq!(
+            aliases,
+            vec![Alias {
+                alias: "docs".to_string(),
+                collection: "docs_v2".to_string(),
+            }]
+        );
+
+        let response = server
+            .post("/vectors")
+            .json(&InsertRequest {
+                vectors: vec![Vector::with_id("x".to_string(), vec![0.0, 1.0])
+                    .with_collection("docs".to_string())],
+                ..Default::default()
+            })
+            .await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+        let response = server
+            .put("/aliases/docs_v1")
+            .json(&json!({"collection": "docs_v2"}))
+            .await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+
+        let response = server.delete("/aliases/docs").await;
+        assert_eq!(response.status_code(), StatusCode::NO_CONTENT);
+        assert!(search().await.is_empty());
+        let response = server.delete("/aliases/docs").await;
+        assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_insert_without_ids() {
+        use serde_json::json;
+        let server = create_test_app().await;
+        server
+            .put("/collections/tickets/config")
+            .json(&json!({"distance_metric": "cosine", "id_scheme": "auto_increment"}))
+            .await
+            .assert_status_ok();
+        let ids: Vec<String> = server
+            .post("/vectors")
+            .json(&json!({"vectors": [
+                {"data": [1.0, 0.0], "metadata": null, "collection": "tickets", "created_at": 0},
+                {"id": "", "data": [0.0, 1.0], "metadata": null, "collection": "tickets", "created_at": 0}
+            ]}))
+            .await
+            .json();
+        assert_eq!(ids, vec!["00000000000000000001", "00000000000000000002"]);
+        let stored: Vector = server.get("/vectors/00000000000000000002").await.json();
+        assert_eq!(stored.data, vec![0.0, 1.0]);
+    }
+
+    #[tokio::test]
+    async fn test_vector_versions() {
+        use serde_json::json;
+        let server = create_test_app().await;
+        server
+            .put("/collections/docs/config")
+            .json(&json!({"distance_metric": "cosine", "keep_versions": 5}))
+            .await
+            .assert_status_ok();
+        for data in [vec![1.0, 0.0], vec![0.0, 1.0]] {
+            let vector = Vector::with_id("doc".to_string(), data).with_collection("docs".into());
+            server
+                .post("/vectors")
+                .json(&InsertRequest {
+                    vectors: vec![vector],
+                    ..Default::default()
+                })
+                .await
+                .assert_status_ok();
+        }
+
+        let versions: Vec<Vector> = server.get("/vectors/doc/versions").await.json();
+        assert_eq!(versions.len(), 2);
+        assert_eq!((versions[0].version, versions[1].version), (2, 1));
+        assert_eq!(versions[1].data, vec![1.0, 0.0]);
+        let response = server.get("/vectors/missing/versions").await;
+        assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_metadata_schema() {
+        use serde_json::{json, Value};
+        let server = create_test_app().await;
+        let schema = json!({"fields": {
+            "lang": {"type": "string", "required": true, "indexed": true},
+            "year": {"type": "integer", "required": false, "indexed": false}
+        }});
+        server
+            .put("/collections/docs/config")
+            .json(&json!({"distance_metric": "cosine", "metadata_schema": schema}))
+            .await
+            .assert_status_ok();
+        let config: Value = server.get("/collections/docs/config").await.json();
+        assert_eq!(config["metadata_schema"], schema);
+
+        let doc = |pairs: &[(&str, &str)]| InsertRequest {
+            vectors: vec![Vector::with_id("a".to_string(), vec![1.0, 0.0])
+                .with_collection("docs".to_string())
+                .with_metadata(
+                    pairs
+                        .iter()
+                        .map(|(key, value)| (key.to_string(), value.to_string()))
+                        .collect(),
+                )],
+            ..Default::default()
+        };
+        let response = server
+            .post("/vectors")
+            .json(&doc(&[("year", "soon")]))
+            .await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+        let error: ErrorResponse = response.json();
+        let fields: Vec<&str> = error.fields.iter().map(|f| f.field.as_str()).collect();
+        assert_eq!(fields, vec!["lang", "year"]);
+        server
+            .post("/vectors")
+            .json(&doc(&[("lang", "en"), ("year", "2024")]))
+            .await
+            .assert_status_ok();
+
+        let search = |key: &str| json!({"vector": [1.0, 0.0], "k": 1, "filter": {key: "2024"}});
+        let response = server
+            .post("/collections/docs/search")
+            .json(&search("year"))
+            .await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+        let response = server
+            .post("/collections/docs/search")
+            .json(&search("lang"))
+            .await;
+        response.assert_status_ok();
+    }
+
+    #[tokio::test]
+    async fn test_expected_model() {
+        use serde_json::json;
+        let server = create_test_app().await;
+        server
+            .put("/collections/docs/config")
+            .json(&json!({"distance_metric": "cosine", "expected_model": "small"}))
+            .await
+            .assert_status_ok();
+        let vector = Vector::with_id("a".to_string(), vec![1.0, 0.0])
+            .with_collection("docs".into())
+            .with_model("large".into());
+        let response = server
+            .post("/vectors")
+            .json(&InsertRequest {
+                vectors: vec![vector.clone().with_model("small".into())],
+                ..Default::default()
+            })
+            .await;
+        response.assert_status_ok();
+        let response = server
+            .post("/vectors")
+            .json(&InsertRequest {
+                vectors: vec![vector],
+                ..Default::default()
+            })
+            .await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+
+        let response = server
+            .post("/collections/docs/search")
+            .json(&json!({"vector": [1.0, 0.0], "k": 1, "model": "large"}))
+            .await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+        let error: ErrorResponse = response.json();
+        assert!(error.error.contains("expects small"));
+        let result: SearchResponse = server
+            .post("/collections/docs/search")
+            .json(&json!({"vector": [1.0, 0.0], "k": 1, "model": "small"}))
+            .await
+            .json();
+        assert_eq!(result.results.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_collection_dedup() {
+        use serde_json::json;
+        let server = create_test_app().await;
+        let response = server
+            .put("/collections/chunks/config")
+            .json(&json!({"distance_metric": "cosine", "dedup": {"threshold": 0.98}}))
+            .await;
+        assert_eq!(response.status_code(), StatusCode::OK);
+        let config: CollectionConfig = server.get("/collections/chunks/config").await.json();
+        assert_eq!(
+            config.dedup.unwrap().action,
+            skypier_core::DedupAction::Reject
+        );
+
+        let chunk = |id: &str, data: Vec<f32>| {
+            Vector::with_id(id.to_string(), data).with_collection("chunks".to_string())
+        };
+        server
+            .post("/vectors")
+            .json(&InsertRequest {
+                vectors: vec![chunk("a", vec![1.0, 0.0])],
+                ..Default::default()
+            })
+            .await
+            .assert_status_ok();
+        let response = server
+            .post("/vectors")
+            .json(&InsertRequest {
+                vectors: vec![chunk("b", vec![0.99, 0.01])],
+                ..Default::default()
+            })
+            .await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+        let error: ErrorResponse = response.json();
+        assert!(error.error.contains("near duplicate of a"));
+
+        // Leaving dedup out of the config turns it off
+        server
+            .put("/collections/chunks/config")
+            .json(&json!({"distance_metric": "cosine"}))
+            .await
+            .assert_status_ok();
+        server
+            .post("/vectors")
+            .json(&InsertRequest {
+                vectors: vec![chunk("b", vec![0.99, 0.01])],
+                ..Default::default()
+            })
+            .await
+            .assert_status_ok();
+    }
+
+    #[tokio::test]
+    async fn test_collection_distance_metric() {
+        use serde_json::{json, Value};
+        let server = create_test_app().await;
+
+        let vectors = vec![
+            Vector::with_id("near".to_string(), vec![1.0, 0.0, 0.0])
+                .with_collection("places".to_string()),
+            Vector::with_id("far".to_string(), vec![9.0, 1.0, 0.0])
+                .with_collection("places".to_string()),
+        ];
+        server
+            .post("/vectors")
+            .json(&InsertRequest {
+                vectors,
+                ..Default::default()
+            })
+            .await
+            .assert_status_ok();
+        let config: Value = server.get("/collections/places/config").await.json();
+        assert_eq!(
+            config,
+            json!({"distance_metric": "cosine", "dedup": null, "id_scheme": "uuid", "keep_versions": 0, "expected_model": null, "metadata_schema": null, "quota": null})
+        );
+
+        let response = server
+            .put("/collections/places/config")
+            .json(&json!({"distance_metric": "euclidean"}))
+            .await;
+        assert_eq!(response.status_code(), StatusCode::OK);
+        let config: Value = server.get("/collections/places/config").await.json();
+        assert_eq!(
+            config,
+            json!({"distance_metric": "euclidean", "dedup": null, "id_scheme": "uuid", "keep_versions": 0, "expected_model": null, "metadata_schema": null, "quota": null})
+        );
+
+        let result: SearchResponse = server
+            .post("/collections/places/search")
+            .json(&json!({"vector": [8.0, 0.0, 0.0], "k": 1, "threshold": 0.0}))
+            .await
+            .json();
+        assert_eq!(result.results[0].id, "far");
+
+        // As distances, the threshold is the furthest a result may be
+        let result: SearchResponse = server
+            .post("/collections/places/search")
+            .json(&json!({"vector": [8.0, 0.0, 0.0], "score_mode": "distance", "threshold": 2.0}))
+            .await
+            .json();
+        assert_eq!(result.results.len(), 1);
+        assert!((result.results[0].score - 2.0_f32.sqrt()).abs() < 1e-4);
+        let result: SearchResponse = server
+            .post("/collections/places/search")
+            .json(&json!({"vector": [8.0, 0.0, 0.0], "score_mode": "distance"}))
+            .await
+            .json();
+        assert_eq!(result.results[1].id, "near");
+        assert!((result.results[1].score - 7.0).abs() < 1e-4);
+        let result: SearchResponse = server
+            .post("/collections/places/search")
+            .json(&json!({"vector": [8.0, 0.0, 0.0], "max_distance": 2.0}))
+            .await
+            .json();
+        assert_eq!(result.results.len(), 1);
+        assert!((result.results[0].score - 1.0 / (1.0 + 2.0_f32.sqrt())).abs() < 1e-4);
+        let response = server
+            .post("/collections/places/search")
+            .json(&json!({"vector": [8.0, 0.0, 0.0], "max_distance": -1.0}))
+            .await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+
+        let response = server
+            .put("/collections/places/config")
+            .json(&json!({"distance_metric": "manhattan"}))
+            .await;
+        assert_eq!(response.status_code(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_storage_quotas() {
+        use serde_json::json;
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut config = crate::config::Config::default();
+        config.storage.data_dir = temp_dir.path().to_str().unwrap().to_string();
+        config.namespaces.enabled = true;
+        config.namespaces.names = vec!["tiny".to_string()];
+        config.namespaces.quotas.insert(
+            "tiny".to_string(),
+            crate::config::NamespaceQuota {
+                max_bytes: Some(8),
+                ..Default::default()
+            },
+        );
+        let db = create_test_db().await;
+        let namespaces = Arc::new(Namespaces::from_config(Arc::clone(&db), &config).await);
+        let server =
+            TestServer::new(create_router(AppState::new(db).with_namespaces(namespaces))).unwrap();
+        let insert = |id: &str| InsertRequest {
+            vectors: vec![Vector::with_id(id.to_string(), vec![1.0, 0.0, 0.0])
+                .with_collection("docs".to_string())],
+            ..Default::default()
+        };
+
+        server
+            .put("/collections/docs/config")
+            .json(&json!({"distance_metric": "cosine", "quota": {"max_vectors": 1}}))
+            .await
+            .assert_status_ok();
+        server
+            .post("/vectors")
+            .json(&insert("a"))
+            .await
+            .assert_status_ok();
+        let response = server.post("/vectors").json(&insert("b")).await;
+        assert_eq!(response.status_code(), StatusCode::FORBIDDEN);
+        let error: ErrorResponse = response.json();
+        assert_eq!(error.usage.unwrap().vectors, 1);
+        // Rewriting a vector it holds is fine
+        server
+            .post("/vectors")
+            .json(&insert("a"))
+            .await
+            .assert_status_ok();
+        let stats: CollectionStatsResponse = server.get("/collections/docs/stats").await.json();
+        assert_eq!((stats.vector_count, stats.max_vectors), (1, Some(1)));
+
+        let response = server
+            .post("/vectors")
+            .add_header("x-namespace", "tiny")
+            .json(&insert("a"))
+            .await;
+        assert_eq!(response.status_code(), StatusCode::PAYLOAD_TOO_LARGE);
+        let error: ErrorResponse = response.json();
+        assert_eq!(error.usage.unwrap().bytes, 0);
+        let stats: StatsResponse = server
+            .get("/stats")
+            .add_header("x-namespace", "tiny")
+            .await
+            .json();
+        assert_eq!(stats.max_bytes, Some(8));
+    }
+
+    #[tokio::test]
+    async fn test_namespaces_are_isolated() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut config = crate::config::Config::default();
+        config.storage.data_dir = temp_dir.path().to_str().unwrap().to_string();
+        config.namespaces.enabled = true;
+        config.namespaces.names = vec!["small".to_string()];
+        config.namespaces.quotas.insert(
+            "small".to_string(),
+            crate::config::NamespaceQuota {
+                max_vectors: Some(1),
+                ..Default::default()
+            },
+        );
+        let db = create_test_db().await;
+        let namespaces = Arc::new(Namespaces::from_config(Arc::clone(&db), &config).await);
+        let server =
+            TestServer::new(create_router(AppState::new(db).with_namespaces(namespaces))).unwrap();
+
+        let vector = Vector::with_id("a".to_string(), vec![1.0, 0.0, 0.0]);
+        let insert = InsertRequest {
+            vectors: vec![vector.clone()],
+            ..Default::default()
+        };
+        // Naming a namespace doesn't make it
+        let response = server.post("/namespaces/acme/vectors").json(&insert).await;
+        assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+        assert!(!temp_dir.path().join("namespaces/acme").exists());
+        let created: NamespaceStatsResponse = server.put("/admin/namespaces/acme").await.json();
+        assert_eq!(
+            (created.namespace.as_str(), created.total_vectors),
+            ("acme", 0)
+        );
+        let response = server.post("/namespaces/acme/vectors").json(&insert).await;
+        assert_eq!(response.status_code(), StatusCode::OK);
+
+        // Same namespace through the header, nothing in the default one
+        let response = server
+            .get("/vectors/a")
+            .add_header("x-namespace", "acme")
+            .await;
+        assert_eq!(response.status_code(), StatusCode::OK);
+        let response = server.get("/vectors/a").await;
+        assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+
+        let response = server.get("/namespaces/acme/stats").await;
+        let stats: StatsResponse = response.json();
+        assert_eq!(stats.total_vectors, 1);
+
+        let response = server
+            .post("/vectors")
+            .add_header("x-namespace", "small")
+            .json(&InsertRequest {
+                vectors: vec![vector.clone()],
+                ..Default::default()
+            })
+            .await;
+        assert_eq!(response.status_code(), StatusCode::OK);
+        let response = server
+            .post("/vectors")
+            .add_header("x-namespace", "small")
+            .json(&InsertRequest {
+                vectors: vec![Vector::new(vec![0.0, 1.0, 0.0])],
+                ..Default::default()
+            })
+            .await;
+        assert_eq!(response.status_code(), StatusCode::FORBIDDEN);
+        let response = server
+            .post("/namespaces/small/vectors/validate")
+            .json(&InsertRequest {
+                vectors: vec![Vector::new(vec![0.0, 1.0, 0.0])],
+                ..Default::default()
+            })
+            .await;
+        let validated: ValidateResponse = response.json();
+        assert!(!validated.valid);
+        assert!(validated.check.errors.is_empty());
+        assert!(validated.quota_error.unwrap().contains("limited to 1"));
+
+        let response = server.get("/namespaces").await;
+        let listed: Vec<NamespaceStatsResponse> = response.json();
+        let listed: Vec<(String, usize, Option<usize>)> = listed
+            .into_iter()
+            .map(|ns| (ns.namespace, ns.total_vectors, ns.max_vectors))
+            .collect();
+        assert_eq!(
+            listed,
+            vec![
+                ("acme".to_string(), 1, None),
+                ("default".to_string(), 0, None),
+                ("small".to_string(), 1, Some(1)),
+            ]
+        );
+
+        let response = server.get("/namespaces/bad.name/stats").await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_per_client() {
+        let config = crate::config::RateLimitConfig {
+            default: crate::config::RateLimit {
+                requests_per_second: 0.5,
+                burst: 1,
+            },
+            ..Default::default()
+        };
+        let limiter = Arc::new(RateLimiter::from_config(&config));
+        let db = create_test_db().await;
+        let keys = ApiKeys::load(Arc::clone(&db), "x-api-key", Some("admin"))
+            .await
+            .unwrap();
+        let (_, key) = keys.create(Role::Read).await.unwrap();
+        let state = AppState::new(Arc::clone(&db)).with_rate_limiter(Arc::clone(&limiter));
+        let server = TestServer::new(create_router(state)).unwrap();
+
+        assert_eq!(server.get("/stats").await.status_code(), StatusCode::OK);
+        let response = server.get("/stats").await;
+        assert_eq!(response.status_code(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.header("retry-after"), "2");
+        // Without auth a made-up key is no way around the limit
+        let response = server.get("/stats").add_header("x-api-key", "other").await;
+        assert_eq!(response.status_code(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(server.get("/health").await.status_code(), StatusCode::OK);
+
+        // Accepted keys have their own bucket
+        let state = AppState::new(db)
+            .with_auth(Arc::new(keys))
+            .with_rate_limiter(limiter);
+        let server = TestServer::new(create_router(state)).unwrap();
+        let response = server.get("/stats").add_header("x-api-key", &key).await;
+        assert_eq!(response.status_code(), StatusCode::OK);
+        let response = server.get("/stats").add_header("x-api-key", &key).await;
+        assert_eq!(response.status_code(), StatusCode::TOO_MANY_REQUESTS);
+        let response = server.get("/stats").add_header("x-api-key", "admin").await;
+        assert_eq!(response.status_code(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_read_keys_recommend() {
+        let db = create_test_db().await;
+        db.insert_vectors(vec![
+            Vector::with_id("a".to_string(), vec![1.0, 0.0, 0.0]),
+            Vector::with_id("b".to_string(), vec![0.9, 0.1, 0.0]),
+        ])
+        .await
+        .unwrap();
+        let keys = ApiKeys::load(Arc::clone(&db), "x-api-key", None)
+            .await
+            .unwrap();
+        let (_, key) = keys.create(Role::Read).await.unwrap();
+        let state = AppState::new(db).with_auth(Arc::new(keys));
+        let server = TestServer::new(create_router(state)).unwrap();
+
+        let response = server
+            .post("/recommend")
+            .add_header("x-api-key", &key)
+            .json(&serde_json::json!({"positive": ["a"]}))
+            .await;
+        assert_eq!(response.status_code(), StatusCode::OK);
+        let response = server
+            .post("/vectors")
+            .add_header("x-api-key", &key)
+            .json(&InsertRequest {
+                vectors: vec![Vector::with_id("c".to_string(), vec![0.0, 1.0, 0.0])],
+                ..Default::default()
+            })
+            .await;
+        assert_eq!(response.status_code(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_audit_log() {
+        let db = create_test_db().await;
+        let keys = ApiKeys::load(Arc::clone(&db), "x-api-key", Some("admin"))
+            .await
+            .unwrap();
+        let state = AppState::new(Arc::clone(&db))
+            .with_auth(Arc::new(keys))
+            .with_audit(Arc::new(AuditLog::new(db)));
+        let server = TestServer::new(create_router(state)).unwrap();
+
+        let response = server
+            .post("/admin/keys")
+            .add_header("x-api-key", "admin")
+            .json(&ApiKeyRequest { role: Role::Write })
+            .await;
+        let created: CreateApiKeyResponse = response.json();
+        let key = created.key.as_str();
+        let insert = InsertRequest {
+            vectors: vec![Vector::with_id("a".to_string(), vec![1.0, 0.0, 0.0])],
+            ..Default::default()
+        };
+        let inserted = server
+            .post("/vectors")
+            .add_header("x-api-key", key)
+            .json(&insert)
+            .await;
+        let search = SearchRequest {
+            vector: vec![1.0, 0.0, 0.0],
+            ..Default::default()
+        };
+        let searched = server
+            .post("/search")
+            .add_header("x-api-key", key)
+            .json(&search)
+            .await;
+        assert_eq!(searched.status_code(), StatusCode::OK);
+        let deleted = server
+            .delete("/namespaces/default/vectors/a")
+            .add_header("x-api-key", key)
+            .await;
+        let refused = server
+            .post("/admin/compact")
+            .add_header("x-api-key", key)
+            .await;
+        assert_eq!(refused.status_code(), StatusCode::FORBIDDEN);
+
+        // Reads aren't recorded, refused writes are
+        let page: AuditPage = server
+            .get("/admin/audit")
+            .add_header("x-api-key", "admin")
+            .await
+            .json();
+        let entries: Vec<(&str, &str, u16)> = page
+            .entries
+            .iter()
+            .map(|entry| (entry.action.as_str(), entry.path.as_str(), entry.status))
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                ("POST /admin/keys", "/admin/keys", 201),
+                ("POST /vectors", "/vectors", inserted.status_code().as_u16()),
+                (
+                    "DELETE /vectors/:id",
+                    "/vectors/a",
+                    deleted.status_code().as_u16()
+                ),
+                ("POST /admin/compact", "/admin/compact", 403),
+            ]
+        );
+        assert_eq!(page.next, 4);
+        assert_eq!(
+            page.entries[0].key_id.as_deref(),
+            Some(auth::key_id("admin").as_str())
+        );
+        assert!(page
+            .entries
+            .iter()
+            .all(|entry| entry.namespace == "default"));
+
+        let page: AuditPage = server
+            .get("/admin/audit")
+            .add_query_param("key_id", &created.id)
+            .add_query_param("action", "POST")
+            .add_header("x-api-key", "admin")
+            .await
+            .json();
+        let seqs: Vec<u64> = page.entries.iter().map(|entry| entry.seq).collect();
+        assert_eq!(seqs, vec![2, 4]);
+        let response = server
+            .get("/admin/audit")
+            .add_header("x-api-key", key)
+            .await;
+        assert_eq!(response.status_code(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_api_key_roles() {
+        let db = create_test_db().await;
+        let keys = ApiKeys::load(Arc::clone(&db), "x-api-key", Some("admin"))
+            .await
+            .unwrap();
+        let state = AppState::new(db).with_auth(Arc::new(keys));
+        let server = TestServer::new(create_router(state)).unwrap();
+
+        assert_eq!(server.get("/health").await.status_code(), StatusCode::OK);
+        let response = server.get("/stats").await;
+        assert_eq!(response.status_code(), StatusCode::UNAUTHORIZED);
+
+        let response = server
+            .post("/admin/keys")
+            .add_header("x-api-key", "admin")
+            .json(&ApiKeyRequest { role: Role::Read })
+            .await;
+        assert_eq!(response.status_code(), StatusCode::CREATED);
+        let created: CreateApiKeyResponse = response.json();
+        let key = created.key.as_str();
+
+        let search = SearchRequest {
+            vector: vec![1.0, 0.0, 0.0],
+            k: None,
+            threshold: None,
+            filter: None,
+            ..Default::default()
+        };
+        let response = server
+            .post("/search")
+            .add_header("x-api-key", key)
+            .json(&search)
+            .await;
+        assert_eq!(response.status_code(), StatusCode::OK);
+        // Other tenants' stats are for admins
+        let response = server.get("/namespaces").add_header("x-api-key", key).await;
+        assert_eq!(response.status_code(), StatusCode::FORBIDDEN);
+        let insert = InsertRequest {
+            vectors: vec![Vector::new(vec![1.0, 0.0, 0.0])],
+            ..Default::default()
+        };
+        let response = server
+            .post("/vectors")
+            .add_header("x-api-key", key)
+            .json(&insert)
+            .await;
+        assert_eq!(response.status_code(), StatusCode::FORBIDDEN);
+
+        let response = server
+            .put(&format!("/admin/keys/{}", created.id))
+            .add_header("x-api-key", "admin")
+            .json(&ApiKeyRequest { role: Role::Write })
+            .await;
+        assert_eq!(response.status_code(), StatusCode::NO_CONTENT);
+        let response = server
+            .post("/vectors")
+            .add_header("x-api-key", key)
+            .json(&insert)
+            .await;
+        assert_eq!(response.status_code(), StatusCode::OK);
+        let response = server
+            .post("/collections/docs/snapshots")
+            .add_header("x-api-key", key)
+            .json(&CreateSnapshotRequest {
+                name: "s1".to_string(),
+            })
+            .await;
+        assert_eq!(response.status_code(), StatusCode::FORBIDDEN);
+
+        let response = server
+            .delete(&format!("/admin/keys/{}", created.id))
+            .add_header("x-api-key", "admin")
+            .await;
+        assert_eq!(response.status_code(), StatusCode::NO_CONTENT);
+        let response = server.get("/stats").add_header("x-api-key", key).await;
+        assert_eq!(response.status_code(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_namespace_without_namespaces_enabled() {
+        let server = create_test_app().await;
+
+        let response = server.get("/namespaces/acme/stats").await;
+        assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+        let response = server
+            .get("/stats")
+            .add_header("x-namespace", "default")
+            .await;
+        assert_eq!(response.status_code(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_search_with_metadata_filter() {
+        let server = create_test_app().await;
+
+        let lang = |value: &str| HashMap::from([("lang".to_string(), value.to_string())]);
+        let mut vectors: Vec<Vector> = (0..50)
+            .map(|i| {
+                Vector::with_id(format!("en-{}", i), vec![1.0, i as f32 * 0.01, 0.0])
+                    .with_metadata(lang("en"))
+            })
+            .collect();
+        vectors
+            .push(Vector::with_id("fr".to_string(), vec![0.1, 0.0, 1.0]).with_metadata(lang("fr")));
+        let insert_response = server
+            .post("/vectors")
+            .json(&InsertRequest {
+                vectors,
+                ..Default::default()
+            })
+            .await;
+        assert_eq!(insert_response.status_code(), StatusCode::OK);
+
+        // The only French vector is far from the query, but still the only match
+        let search_response = server
+            .post("/search")
+            .json(&SearchRequest {
+                vector: vec![1.0, 0.0, 0.0],
+                k: Some(5),
+                threshold: None,
+                filter: Some(lang("fr")),
+                ..Default::default()
+            })
+            .await;
+        assert_eq!(search_response.status_code(), StatusCode::OK);
+        let search_result: SearchResponse = search_response.json();
+        let ids: Vec<&str> = search_result
+            .results
+            .iter()
+            .map(|r| r.id.as_str())
+            .collect();
+        assert_eq!(ids, vec!["fr"]);
+    }
+
+    #[tokio::test]
+    async fn test_stats_after_insertions() {
+        let (db, _dir) = create_disk_db().await;
+        let server = TestServer::new(create_router(AppState::new(db))).unwrap();
+
+        // Insert some vectors
+        let vectors = vec![
+            Vector::new(vec![1.0, 2.0, 3.0]),
+            Vector::new(vec![4.0, 5.0, 6.0]),
+        ];
+        let insert_request = InsertRequest {
+            vectors,
+            ..Default::default()
+        };
+
+        let insert_response = server.post("/vectors").json(&insert_request).await;
+        assert_eq!(insert_response.status_code(), StatusCode::OK);
+
+        // Check stats
+        let stats_response = server.get("/stats").await;
+        assert_eq!(stats_response.status_code(), StatusCode::OK);
+
+        let stats: StatsResponse = stats_response.json();
+        assert_eq!(stats.total_vectors, 2);
+        assert_eq!(stats.dimensions, 3);
+        assert!(stats.storage_size_bytes > 0);
+    }
+
+    #[tokio::test]
+    async fn test_insert_empty_vectors_list() {
+        let server = create_test_app().await;
+
+        let insert_request = InsertRequest::default();
+
+        let response = server.post("/vectors").json(&insert_request).await;
+
+        assert_eq!(response.status_code(), StatusCode::OK);
+        let ids: Vec<String> = response.json();
+        assert_eq!(ids.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_search_empty_database() {
+        let server = create_test_app().await;
+
+        let search_request = SearchRequest {
+            vector: vec![1.0, 2.0, 3.0],
+            k: Some(5),
+            threshold: Some(0.0),
+            filter: None,
+            ..Default::default()
+        };
+
+        let response = server.post("/search").json(&search_request).await;
+
+        assert_eq!(response.status_code(), StatusCode::OK);
+        let search_result: SearchResponse = response.json();
+        assert_eq!(search_result.results.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_vector_metadata_preserved() {
+        let server = create_test_app().await;
+
+        let mut metadata = HashMap::new();
+        metadata.insert("type".to_string(), "document".to_string());
+        metadata.insert("source".to_string(), "test_file.txt".to_string());
+
+        let vector = Vector::new(vec![1.0, 2.0, 3.0]).with_metadata(metadata.clone());
+        let insert_request = InsertRequest {
+            vectors: vec![vector],
+            ..Default::default()
+        };
+
+        let insert_response = server.post("/vectors").json(&insert_request).await;
+        let ids: Vec<String> = insert_response.json();
+
+        // Retrieve the vector and check metadata
+        let get_response = server.get(&format!("/vectors/{}", ids[0])).await;
+
+        let retrieved_vector: Vector = get_response.json();
+        assert_eq!(retrieved_vector.metadata, Some(metadata));
+    }
 
     #[tokio::test]
-    async fn test_get_stats_empty_db() {
+    async fn test_invalid_json_request() {
         let server = create_test_app().await;
 
-        let response = server.get("/stats").await;
+        let response = server
+            .post("/vectors")
+            .json(&serde_json::json!({"invalid": "structure"}))
+            .await;
+
+        // Should return bad request for invalid JSON structure
+        assert_eq!(response.status_code(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_lifecycle() {
+        let server = create_test_app().await;
+
+        let vectors = vec![
+            Vector::with_id("a".to_string(), vec![1.0, 0.0, 0.0])
+                .with_collection("docs".to_string()),
+            Vector::with_id("b".to_string(), vec![0.0, 1.0, 0.0])
+                .with_collection("docs".to_string()),
+        ];
+        server
+            .post("/vectors")
+            .json(&InsertRequest {
+                vectors,
+                ..Default::default()
+            })
+            .await
+            .assert_status_ok();
+
+        let create_response = server
+            .post("/collections/docs/snapshots")
+            .json(&CreateSnapshotRequest {
+                name: "v1".to_string(),
+            })
+            .await;
+        assert_eq!(create_response.status_code(), StatusCode::CREATED);
+        let info: SnapshotInfo = create_response.json();
+        assert_eq!(info.vector_count, 2);
+
+        // Snapshot names are unique per collection
+        let duplicate_response = server
+            .post("/collections/docs/snapshots")
+            .json(&CreateSnapshotRequest {
+                name: "v1".to_string(),
+            })
+            .await;
+        assert_eq!(duplicate_response.status_code(), StatusCode::CONFLICT);
+
+        // Mutating the live collection leaves the snapshot untouched
+        let vectors = vec![Vector::with_id("c".to_string(), vec![0.0, 0.0, 1.0])
+            .with_collection("docs".to_string())];
+        server
+            .post("/vectors")
+            .json(&InsertRequest {
+                vectors,
+                ..Default::default()
+            })
+            .await
+            .assert_status_ok();
+
+        let exported: Vec<Vector> = server
+            .get("/collections/docs/snapshots/v1/export")
+            .await
+            .json();
+        assert_eq!(exported.len(), 2);
+
+        let search_result: SearchResponse = server
+            .post("/collections/docs/snapshots/v1/search")
+            .json(&SearchRequest {
+                vector: vec![0.0, 0.0, 1.0],
+                k: Some(10),
+                threshold: Some(0.5),
+                filter: None,
+                ..Default::default()
+            })
+            .await
+            .json();
+        assert!(search_result.results.is_empty());
+
+        let diff: SnapshotDiff = server
+            .get("/collections/docs/snapshots/v1/diff")
+            .await
+            .json();
+        assert_eq!(diff.added, vec!["c".to_string()]);
+        assert!(diff.removed.is_empty());
+        assert!(diff.changed.is_empty());
+
+        let listed: Vec<SnapshotInfo> = server.get("/collections/docs/snapshots").await.json();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].name, "v1");
+
+        let delete_response = server.delete("/collections/docs/snapshots/v1").await;
+        assert_eq!(delete_response.status_code(), StatusCode::NO_CONTENT);
+        let missing_response = server.get("/collections/docs/snapshots/v1/export").await;
+        assert_eq!(missing_response.status_code(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_clone_snapshot_into_new_collection() {
+        let server = create_test_app().await;
+
+        let vectors = vec![Vector::new(vec![1.0, 2.0, 3.0])
+            .with_collection("src".to_string())
+            .with_model("small".to_string())];
+        server
+            .post("/vectors")
+            .json(&InsertRequest {
+                vectors,
+                ..Default::default()
+            })
+            .await
+            .assert_status_ok();
+        server
+            .post("/collections/src/snapshots")
+            .json(&CreateSnapshotRequest {
+                name: "base".to_string(),
+            })
+            .await;
+
+        let clone_response = server
+            .post("/collections/src/snapshots/base/clone")
+            .json(&CloneSnapshotRequest {
+                target: "dst".to_string(),
+            })
+            .await;
+        assert_eq!(clone_response.status_code(), StatusCode::OK);
+        let ids: Vec<String> = clone_response.json();
+        assert_eq!(ids.len(), 1);
+
+        let cloned: Vector = server.get(&format!("/vectors/{}", ids[0])).await.json();
+        assert_eq!(cloned.collection.as_deref(), Some("dst"));
+        assert_eq!(cloned.data, vec![1.0, 2.0, 3.0]);
+        assert_eq!(cloned.model.as_deref(), Some("small"));
+    }
+
+    #[tokio::test]
+    async fn test_equal_scores_are_ordered_by_id() {
+        let server = create_test_app().await;
+
+        let vectors = ["d", "b", "a", "c"]
+            .iter()
+            .map(|id| Vector::with_id(id.to_string(), vec![1.0, 1.0, 0.0]))
+            .collect();
+        server
+            .post("/vectors")
+            .json(&InsertRequest {
+                vectors,
+                ..Default::default()
+            })
+            .await
+            .assert_status_ok();
+
+        let search_request = SearchRequest {
+            vector: vec![1.0, 1.0, 0.0],
+            k: Some(3),
+            threshold: None,
+            filter: None,
+            ..Default::default()
+        };
+        for _ in 0..3 {
+            let search_result: SearchResponse =
+                server.post("/search").json(&search_request).await.json();
+            let ids: Vec<&str> = search_result
+                .results
+                .iter()
+                .map(|r| r.id.as_str())
+                .collect();
+            assert_eq!(ids, vec!["a", "b", "c"]);
+        }
+    }
+
+    #[cfg(feature = "embeddings")]
+    struct KeywordEmbedder;
+
+    // Embeds text by counting a few keywords, enough to make similarity meaningful
+    #[cfg(feature = "embeddings")]
+    #[async_trait::async_trait]
+    impl Embedder for KeywordEmbedder {
+        async fn embed(&self, texts: &[String]) -> anyhow::Result<Vec<Vec<f32>>> {
+            Ok(texts
+                .iter()
+                .map(|text| {
+                    ["cat", "dog", "fish"]
+                        .iter()
+                        .map(|word| text.matches(word).count() as f32)
+                        .collect()
+                })
+                .collect())
+        }
+    }
+
+    #[cfg(feature = "embeddings")]
+    #[tokio::test]
+    async fn test_embed_and_insert_then_search_text() {
+        let db = create_test_db().await;
+        let state = AppState::new(db).with_embedder(Arc::new(KeywordEmbedder));
+        let server = TestServer::new(create_router(state)).unwrap();
+
+        let insert_request = EmbedAndInsertRequest {
+            items: vec![
+                TextItem {
+                    id: Some("cats".to_string()),
+                    text: "a cat sat next to another cat".to_string(),
+                    metadata: None,
+                    collection: Some("pets".to_string()),
+                },
+                TextItem {
+                    id: Some("dogs".to_string()),
+                    text: "the dog chased the dog".to_string(),
+                    metadata: None,
+                    collection: Some("pets".to_string()),
+                },
+            ],
+        };
+        let insert_response = server.post("/embed-and-insert").json(&insert_request).await;
+        assert_eq!(insert_response.status_code(), StatusCode::OK);
+
+        let stored: Vector = server.get("/vectors/cats").await.json();
+        assert_eq!(stored.data, vec![2.0, 0.0, 0.0]);
+        assert_eq!(
+            stored.metadata.unwrap().get("text").map(String::as_str),
+            Some("a cat sat next to another cat")
+        );
+
+        let search_response: SearchResponse = server
+            .post("/search/text")
+            .json(&TextSearchRequest {
+                text: "my dog".to_string(),
+                collection: Some("pets".to_string()),
+                k: Some(1),
+                threshold: None,
+            })
+            .await
+            .json();
+        assert_eq!(search_response.results[0].id, "dogs");
+    }
+
+    #[cfg(feature = "embeddings")]
+    #[tokio::test]
+    async fn test_text_endpoints_without_embedder() {
+        let server = create_test_app().await;
+
+        let response = server
+            .post("/search/text")
+            .json(&TextSearchRequest {
+                text: "hello".to_string(),
+                collection: None,
+                k: None,
+                threshold: None,
+            })
+            .await;
+        assert_eq!(response.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_insert_rejects_invalid_vectors() {
+        let server = create_test_app().await;
+
+        let response = server
+            .post("/vectors")
+            .json(&InsertRequest {
+                vectors: vec![Vector::with_id("empty".to_string(), vec![])],
+                ..Default::default()
+            })
+            .await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+        let error: ErrorResponse = response.json();
+        assert_eq!(error.error, "vector empty has no dimensions");
+
+        // 1e39 overflows f32 and deserializes to infinity
+        let response = server
+            .post("/vectors")
+            .json(&serde_json::json!({
+                "vectors": [{"id": "inf", "data": [1.0, 1e39], "metadata": null, "collection": null, "created_at": 0}]
+            }))
+            .await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+
+        // Nothing from a rejected batch is written
+        let response = server
+            .post("/vectors")
+            .json(&InsertRequest {
+                vectors: vec![
+                    Vector::with_id("ok".to_string(), vec![1.0]),
+                    Vector::with_id("bad".to_string(), vec![]),
+                ],
+                ..Default::default()
+            })
+            .await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+        let response = server.get("/vectors/ok").await;
+        assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+    }
 
+    #[tokio::test]
+    async fn test_validate_reports_every_bad_row() {
+        let server = create_test_app().await;
+        server
+            .post("/vectors")
+            .json(&InsertRequest {
+                vectors: vec![Vector::new(vec![1.0, 0.0]).with_collection("docs".to_string())],
+                ..Default::default()
+            })
+            .await;
+
+        let response = server
+            .post("/vectors/validate")
+            .json(&serde_json::json!({"vectors": [
+                {"id": "ok", "data": [1.0, 0.0], "collection": "docs", "created_at": 0},
+                {"id": "empty", "data": [], "created_at": 0},
+                {"id": "new", "data": [0.0, 1.0], "collection": "papers", "created_at": 0},
+                // Overflows to infinity; its collection isn't reported
+                {"id": "inf", "data": [1e39], "collection": "drafts", "created_at": 0},
+            ]}))
+            .await;
         assert_eq!(response.status_code(), StatusCode::OK);
-        let stats: StatsResponse = response.json();
-        assert_eq!(stats.total_vectors, 0);
-        assert_eq!(stats.dimensions, 0);
+        let validated: ValidateResponse = response.json();
+        assert!(!validated.valid);
+        assert_eq!(validated.check.checked, 4);
+        let rows: Vec<(usize, &str)> = validated
+            .check
+            .errors
+            .iter()
+            .map(|row| (row.index, row.id.as_str()))
+            .collect();
+        assert_eq!(rows, vec![(1, "empty"), (3, "inf")]);
+        assert_eq!(validated.check.new_collections, vec!["papers"]);
+
+        // Nothing was written
+        let response = server.get("/vectors/ok").await;
+        assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_search_rejects_empty_query() {
+        let server = create_test_app().await;
+
+        let response = server
+            .post("/search")
+            .json(&SearchRequest {
+                vector: vec![],
+                k: None,
+                threshold: None,
+                filter: None,
+                ..Default::default()
+            })
+            .await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_body_rejected() {
+        let db = create_test_db().await;
+        let state = AppState::new(db).with_max_body_bytes(1024);
+        let server = TestServer::new(create_router(state)).unwrap();
+
+        let response = server
+            .post("/vectors")
+            .json(&InsertRequest {
+                vectors: vec![Vector::new(vec![0.5; 1024])],
+                ..Default::default()
+            })
+            .await;
+        assert_eq!(response.status_code(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_backup_endpoint() {
+        let (db, _dir) = create_disk_db().await;
+        let server = TestServer::new(create_router(AppState::new(db))).unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let destination = backup_dir.path().join("backup");
+
+        let response = server
+            .post("/admin/backup")
+            .json(&BackupRequest {
+                destination: destination.to_str().unwrap().to_string(),
+                incremental: false,
+                background: false,
+            })
+            .await;
+        assert_eq!(response.status_code(), StatusCode::OK);
+        assert!(destination.join("vectors.redb").exists());
+
+        let response = server
+            .post("/admin/backup")
+            .json(&BackupRequest {
+                destination: destination.join("set").to_str().unwrap().to_string(),
+                incremental: true,
+                background: false,
+            })
+            .await;
+        assert_eq!(response.status_code(), StatusCode::OK);
+        assert!(destination.join("set/manifest.json").exists());
+
+        let response = server
+            .post("/admin/backup")
+            .json(&BackupRequest {
+                destination: String::new(),
+                incremental: false,
+                background: false,
+            })
+            .await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_slow_query_log() {
+        let db = create_test_db().await;
+        let log = Arc::new(SlowQueryLog::new(std::time::Duration::ZERO, 10));
+        let state = AppState::new(Arc::clone(&db)).with_slow_queries(log);
+        let server = TestServer::new(create_router(state)).unwrap();
+        server
+            .post("/vectors")
+            .json(&InsertRequest {
+                vectors: vec![
+                    Vector::with_id("a".to_string(), vec![1.0, 0.0])
+                        .with_collection("docs".to_string()),
+                    Vector::with_id("b".to_string(), vec![0.0, 1.0]),
+                ],
+                ..Default::default()
+            })
+            .await;
+
+        server
+            .post("/collections/docs/search")
+            .json(&SearchRequest {
+                vector: vec![1.0, 0.0],
+                k: Some(3),
+                ..Default::default()
+            })
+            .await;
+        server
+            .post("/search")
+            .json(&SearchRequest {
+                sparse: Some(SparseVector {
+                    indices: vec![1],
+                    values: vec![1.0],
+                }),
+                ..Default::default()
+            })
+            .await;
+
+        let queries: Vec<SlowQuery> = server.get("/admin/slow-queries").await.json();
+        assert_eq!(queries.len(), 2);
+        assert!(queries[0].profile.is_none());
+        let dense = &queries[1];
+        assert_eq!((dense.dimensions, dense.k), (2, 3));
+        assert_eq!(dense.filter.as_deref(), Some("collection=docs"));
+        let profile = dense.profile.as_ref().unwrap();
+        assert_eq!(profile.filter_matches, Some(1));
+        assert_eq!(profile.results, 1);
+    }
+
+    #[tokio::test]
+    async fn test_cluster_members() {
+        let db = create_test_db().await;
+        let server = TestServer::new(create_router(AppState::new(Arc::clone(&db)))).unwrap();
+        server
+            .get("/cluster/members")
+            .await
+            .assert_status(StatusCode::SERVICE_UNAVAILABLE);
+
+        let mut node = skypier_network::P2PNode::new(skypier_network::NetworkConfig {
+            port: 0,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let state = AppState::new(db).with_cluster(node.handle());
+        tokio::spawn(async move { node.start().await });
+        let server = TestServer::new(create_router(state)).unwrap();
+        let response: ClusterMembersResponse = server.get("/cluster/members").await.json();
+        assert_eq!(response.members.len(), 1);
+        assert_eq!(response.members[0].id, response.local);
+        assert_eq!(
+            response.members[0].status,
+            skypier_network::MemberStatus::Alive
+        );
+    }
+
+    // A P2P node that owns collections and applies requests forwarded to
+    // it, with a database of its own, and its address once it listens
+    async fn start_cluster_node(
+        config: skypier_network::NetworkConfig,
+        placement: &Arc<Placement>,
+        replicator: &Arc<Replicator>,
+    ) -> (NodeHandle, Arc<VectorDatabase>, String) {
+        let config = skypier_network::NetworkConfig {
+            port: 0,
+            probe_interval: std::time::Duration::from_millis(20),
+            shard_owner: true,
+            ..config
+        };
+        let mut node = skypier_network::P2PNode::new(config).await.unwrap();
+        let handle = node.handle();
+        let db = create_test_db().await;
+        let namespaces = Arc::new(Namespaces::single(Arc::clone(&db)));
+        node = node.with_forwarder(Arc::new(cluster::ForwardTarget::new(
+            namespaces,
+            handle.clone(),
+            Arc::clone(placement),
+            Arc::clone(replicator),
+        )));
+        tokio::spawn(async move { node.start().await });
+        let addr = loop {
+            let addresses = handle.listen_addresses().await.unwrap();
+            if let Some(addr) = addresses
+                .into_iter()
+                .find(|addr| addr.contains("127.0.0.1"))
+            {
+                break addr;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        };
+        (handle, db, addr)
+    }
+
+    async fn wait_for_members(node: &NodeHandle, count: usize) -> Vec<Member> {
+        loop {
+            let members = node.members().await.unwrap();
+            if members.len() == count {
+                return members;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_requests_reach_collection_owners() {
+        let placement = Arc::new(Placement::default());
+        let replicator = Arc::new(Replicator::default());
+        let (first_handle, first_db, first_addr) =
+            start_cluster_node(Default::default(), &placement, &replicator).await;
+        let (_, second_db, _) = start_cluster_node(
+            skypier_network::NetworkConfig {
+                bootstrap_peers: vec![first_addr],
+                ..Default::default()
+            },
+            &placement,
+            &replicator,
+        )
+        .await;
+        let members = wait_for_members(&first_handle, 2).await;
+
+        // One collection owned by each node
+        let local_id = first_handle.local_id();
+        let collection = |remote: bool| {
+            (0..)
+                .map(|i| format!("collection-{}", i))
+                .find(|name| {
+                    let owner = cluster::remote_owner(&members, &local_id, &placement, name);
+                    owner.unwrap().is_some() == remote
+                })
+                .unwrap()
+        };
+        let (local, remote) = (collection(false), collection(true));
+        let state = AppState::new(Arc::clone(&first_db))
+            .with_cluster(first_handle)
+            .with_cluster_mode(true);
+        let server = TestServer::new(create_router(state)).unwrap();
+        let ids: Vec<String> = server
+            .post("/vectors")
+            .json(&InsertRequest {
+                vectors: vec![
+                    Vector::with_id("theirs".to_string(), vec![1.0, 0.0])
+                        .with_collection(remote.clone()),
+                    Vector::with_id("ours".to_string(), vec![0.0, 1.0])
+                        .with_collection(local.clone()),
+                ],
+                ..Default::default()
+            })
+            .await
+            .json();
+        assert_eq!(ids, vec!["theirs", "ours"]);
+        assert!(first_db.get_vector("ours").await.unwrap().is_some());
+        assert!(first_db.get_vector("theirs").await.unwrap().is_none());
+        assert!(second_db.get_vector("theirs").await.unwrap().is_some());
+
+        // Found on the owner with or without the collection to place it by
+        let vector: Vector = server.get("/vectors/theirs").await.json();
+        assert_eq!(vector.collection.as_deref(), Some(remote.as_str()));
+        server
+            .get(&format!("/vectors/theirs?collection={}", remote))
+            .await
+            .assert_status_ok();
+        server
+            .delete(&format!("/vectors/theirs?collection={}", remote))
+            .await
+            .assert_status(StatusCode::NO_CONTENT);
+        assert!(second_db.get_vector("theirs").await.unwrap().is_none());
+        server
+            .delete("/vectors/theirs")
+            .await
+            .assert_status(StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_collections_replicate_to_their_placement() {
+        let eu = BTreeMap::from([("region".to_string(), "eu".to_string())]);
+        let policy = |replication_factor: usize, labels: &BTreeMap<String, String>| {
+            skypier_network::PlacementPolicy {
+                replication_factor,
+                labels: labels.clone(),
+            }
+        };
+        let placement = Arc::new(Placement::new(
+            policy(2, &BTreeMap::new()),
+            HashMap::from([
+                ("eu-only".to_string(), policy(2, &eu)),
+                (
+                    "us-only".to_string(),
+                    policy(
+                        1,
+                        &BTreeMap::from([("region".to_string(), "us".to_string())]),
+                    ),
+                ),
+            ]),
+        ));
+        let replicator = Arc::new(Replicator::default());
+        let (first_handle, first_db, first_addr) =
+            start_cluster_node(Default::default(), &placement, &replicator).await;
+        let (second_handle, second_db, _) = start_cluster_node(
+            skypier_network::NetworkConfig {
+                bootstrap_peers: vec![first_addr],
+                labels: eu.clone(),
+                ..Default::default()
+            },
+            &placement,
+            &replicator,
+        )
+        .await;
+        // Both place by the same member list, so owners copy writes to
+        // every replica
+        wait_for_members(&first_handle, 2).await;
+        wait_for_members(&second_handle, 2).await;
+        let state = AppState::new(Arc::clone(&first_db))
+            .with_cluster(first_handle)
+            .with_cluster_mode(true)
+            .with_placement(Arc::clone(&placement))
+            .with_replicator(replicator);
+        let server = TestServer::new(create_router(state)).unwrap();
+
+        let insert = |id: &str, collection: &str| InsertRequest {
+            vectors: vec![
+                Vector::with_id(id.to_string(), vec![1.0, 0.0]).with_collection(collection.into())
+            ],
+            ..Default::default()
+        };
+        server
+            .post("/vectors")
+            .json(&insert("a", "docs"))
+            .await
+            .assert_status_ok();
+        assert!(first_db.get_vector("a").await.unwrap().is_some());
+        assert!(second_db.get_vector("a").await.unwrap().is_some());
+        server
+            .delete("/vectors/a?collection=docs")
+            .await
+            .assert_status(StatusCode::NO_CONTENT);
+        assert!(first_db.get_vector("a").await.unwrap().is_none());
+        assert!(second_db.get_vector("a").await.unwrap().is_none());
+        let replication: ReplicationStatus = server.get("/cluster/replication").await.json();
+        assert_eq!((replication.batches_sent, replication.ops_sent), (2, 2));
+        assert_eq!(replication.backlog_ops, 0);
+
+        // With a write concern the insert reports how far it got
+        let report: WriteReport = server
+            .post("/vectors")
+            .json(&InsertRequest {
+                write_concern: Some(WriteConcern::Quorum),
+                write_timeout_ms: Some(5000),
+                ..insert("a", "docs")
+            })
+            .await
+            .json();
+        assert_eq!(report.ids, vec!["a"]);
+        assert!(report.acknowledged);
+        assert!(report.failures.is_empty());
+        assert!(second_db.get_vector("a").await.unwrap().is_some());
+
+        server
+            .post("/vectors")
+            .json(&insert("b", "eu-only"))
+            .await
+            .assert_status_ok();
+        assert!(first_db.get_vector("b").await.unwrap().is_none());
+        assert!(second_db.get_vector("b").await.unwrap().is_some());
+        server
+            .post("/vectors")
+            .json(&insert("c", "us-only"))
+            .await
+            .assert_status(StatusCode::SERVICE_UNAVAILABLE);
+
+        let docs: PlacementResponse = server.get("/collections/docs/placement").await.json();
+        assert_eq!(docs.replicas.len(), 2);
+        assert!(docs.satisfied);
+        let eu_only: PlacementResponse = server.get("/collections/eu-only/placement").await.json();
+        assert_eq!(eu_only.replication_factor, 2);
+        assert_eq!(eu_only.labels, eu);
+        assert_eq!(eu_only.replicas.len(), 1);
+        assert_eq!(eu_only.replicas[0].id, second_handle.local_id());
+        assert!(!eu_only.satisfied);
+    }
+
+    #[tokio::test]
+    async fn test_gets_repair_stale_replicas() {
+        let placement = Arc::new(Placement::new(
+            skypier_network::PlacementPolicy {
+                replication_factor: 2,
+                labels: BTreeMap::new(),
+            },
+            HashMap::new(),
+        ));
+        let replicator = Arc::new(Replicator::default());
+        let (first_handle, first_db, first_addr) =
+            start_cluster_node(Default::default(), &placement, &replicator).await;
+        let (second_handle, second_db, _) = start_cluster_node(
+            skypier_network::NetworkConfig {
+                bootstrap_peers: vec![first_addr],
+                ..Default::default()
+            },
+            &placement,
+            &replicator,
+        )
+        .await;
+        wait_for_members(&first_handle, 2).await;
+        wait_for_members(&second_handle, 2).await;
+        let state = AppState::new(Arc::clone(&first_db))
+            .with_cluster(first_handle)
+            .with_cluster_mode(true)
+            .with_placement(placement);
+        let server = TestServer::new(create_router(state)).unwrap();
+
+        let vector =
+            |data: Vec<f32>| Vector::with_id("a".to_string(), data).with_collection("docs".into());
+        server
+            .post("/vectors")
+            .json(&InsertRequest {
+                vectors: vec![vector(vec![1.0, 0.0])],
+                ..Default::default()
+            })
+            .await
+            .assert_status_ok();
+        // A write the first node missed leaves it holding an older copy
+        second_db
+            .insert_vectors(vec![vector(vec![0.0, 1.0])])
+            .await
+            .unwrap();
+
+        let read: Vector = server.get("/vectors/a?collection=docs").await.json();
+        assert_eq!(read.data, vec![0.0, 1.0]);
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while first_db.get_vector("a").await.unwrap().unwrap().data != vec![0.0, 1.0] {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_writes_to_read_only_node() {
+        let db = create_test_db().await;
+        db.set_read_only(true);
+        let server = TestServer::new(create_router(AppState::new(Arc::clone(&db)))).unwrap();
+
+        let response = server
+            .post("/vectors")
+            .json(&InsertRequest {
+                vectors: vec![Vector::with_id("a".to_string(), vec![1.0, 0.0])],
+                ..Default::default()
+            })
+            .await;
+        response.assert_status(StatusCode::FORBIDDEN);
+        assert!(response
+            .json::<ErrorResponse>()
+            .error
+            .contains("read-only follower"));
+        server
+            .post("/search")
+            .json(&SearchRequest {
+                vector: vec![1.0, 0.0],
+                ..Default::default()
+            })
+            .await
+            .assert_status_ok();
+        server
+            .get("/admin/replication")
+            .await
+            .assert_status(StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_replication_conflicts() {
+        let open = || async {
+            let db = VectorDatabase::in_memory()
+                .unwrap()
+                .with_conflict_resolver(skypier_core::ConflictPolicy::VectorClock.resolver());
+            Arc::new(db)
+        };
+        let (local, remote) = (open().await, open().await);
+        for (db, data) in [(&local, vec![1.0, 0.0]), (&remote, vec![0.0, 1.0])] {
+            db.insert_vectors(vec![Vector::with_id("a".to_string(), data)])
+                .await
+                .unwrap();
+        }
+        local
+            .apply_changes(&remote.changes_page(0, 10).await.unwrap().unwrap())
+            .await
+            .unwrap();
+        let server = TestServer::new(create_router(AppState::new(Arc::clone(&local)))).unwrap();
+
+        let conflicts: Vec<Conflict> = server.get("/admin/conflicts").await.json();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].remote.data, vec![0.0, 1.0]);
+        server
+            .post("/admin/conflicts/b/resolve")
+            .json(&serde_json::json!({"keep": "remote"}))
+            .await
+            .assert_status(StatusCode::NOT_FOUND);
+        server
+            .post("/admin/conflicts/a/resolve")
+            .json(&serde_json::json!({"keep": "remote"}))
+            .await
+            .assert_status(StatusCode::NO_CONTENT);
+        assert_eq!(
+            local.get_vector("a").await.unwrap().unwrap().data,
+            vec![0.0, 1.0]
+        );
+        let conflicts: Vec<Conflict> = server.get("/admin/conflicts").await.json();
+        assert!(conflicts.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_verify_storage() {
+        let server = create_test_app().await;
+        server
+            .post("/vectors")
+            .json(&InsertRequest {
+                vectors: vec![
+                    Vector::with_id("a".to_string(), vec![1.0, 0.0]),
+                    Vector::with_id("b".to_string(), vec![0.0, 1.0]),
+                ],
+                ..Default::default()
+            })
+            .await
+            .assert_status_ok();
+
+        let response: VerifyResponse = server.post("/admin/verify?repair=true").await.json();
+        assert_eq!(response.checked, 2);
+        assert!(response.corrupt.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_cluster_job() {
+        let db = create_test_db().await;
+        let server = TestServer::new(create_router(AppState::new(Arc::clone(&db)))).unwrap();
+        db.insert_vectors(
+            [
+                ("a", [1.0, 0.0]),
+                ("b", [0.9, 0.1]),
+                ("c", [0.0, 1.0]),
+                ("d", [0.1, 0.9]),
+            ]
+            .into_iter()
+            .map(|(id, data)| {
+                Vector::with_id(id.to_string(), data.to_vec()).with_collection("docs".to_string())
+            })
+            .collect(),
+        )
+        .await
+        .unwrap();
+
+        let response = server.get("/collections/docs/clusters").await;
+        assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+        let response = server.post("/collections/docs/cluster?k=5").await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+        let response = server.post("/collections/none/cluster?k=2").await;
+        assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+
+        let response = server
+            .post("/collections/docs/cluster?k=2&seed=1&key=topic")
+            .await;
+        assert_eq!(response.status_code(), StatusCode::ACCEPTED);
+        let job: Job = response.json();
+        assert_eq!((job.kind.as_str(), job.total), ("cluster", Some(4)));
+        let job = loop {
+            let job: Job = server.get(&format!("/admin/jobs/{}", job.id)).await.json();
+            if job.status != JobStatus::Running {
+                break job;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        };
+        assert_eq!(job.status, JobStatus::Completed);
+        assert_eq!(job.processed, 4);
+
+        let clustering: Clustering = server.get("/collections/docs/clusters").await.json();
+        assert_eq!(clustering.centroids.len(), 2);
+        assert_eq!(clustering.sizes, vec![2, 2]);
+        assert_eq!(clustering.metadata_key, "topic");
+        let topic = |id: &str| {
+            let db = Arc::clone(&db);
+            let id = id.to_string();
+            async move { db.get_vector(&id).await.unwrap().unwrap().metadata.unwrap()["topic"].clone() }
+        };
+        assert_eq!(topic("a").await, topic("b").await);
+        assert_eq!(topic("c").await, topic("d").await);
+        assert_ne!(topic("a").await, topic("c").await);
+    }
+
+    #[tokio::test]
+    async fn test_clone_job() {
+        let db = create_test_db().await;
+        let server = TestServer::new(create_router(AppState::new(Arc::clone(&db)))).unwrap();
+        db.insert_vectors(vec![
+            Vector::with_id("a".to_string(), vec![3.0, 4.0]).with_collection("docs".to_string()),
+            Vector::with_id("b".to_string(), vec![0.0, 1.0]).with_collection("docs".to_string()),
+        ])
+        .await
+        .unwrap();
+
+        let response = server.post("/collections/none/clone?target=copy").await;
+        assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+        let response = server.post("/collections/docs/clone?target=docs").await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+
+        let response = server
+            .post("/collections/docs/clone?target=copy&normalize=true&dtype=f16")
+            .await;
+        assert_eq!(response.status_code(), StatusCode::ACCEPTED);
+        let job: Job = response.json();
+        assert_eq!((job.kind.as_str(), job.total), ("clone", Some(2)));
+        let job = loop {
+            let job: Job = server.get(&format!("/admin/jobs/{}", job.id)).await.json();
+            if job.status != JobStatus::Running {
+                break job;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        };
+        assert_eq!(job.status, JobStatus::Completed);
+        assert_eq!(job.processed, 2);
+
+        let copies = db.scroll(Some("copy"), None, 10).await.unwrap().vectors;
+        assert_eq!(copies.len(), 2);
+        assert!(copies.iter().all(|copy| {
+            let norm: f32 = copy.data.iter().map(|x| x * x).sum();
+            (norm - 1.0).abs() < 1e-3
+        }));
+        // The target is taken now
+        let response = server.post("/collections/docs/clone?target=copy").await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_jobs() {
+        let (db, _dir) = create_disk_db().await;
+        let server = TestServer::new(create_router(AppState::new(db))).unwrap();
+        server
+            .post("/vectors")
+            .json(&InsertRequest {
+                vectors: vec![
+                    Vector::with_id("a".to_string(), vec![1.0, 0.0]),
+                    Vector::with_id("b".to_string(), vec![0.0, 1.0]),
+                ],
+                ..Default::default()
+            })
+            .await;
+
+        let finished = |id: String| {
+            let server = &server;
+            async move {
+                loop {
+                    let job: Job = server.get(&format!("/admin/jobs/{}", id)).await.json();
+                    if job.status != JobStatus::Running {
+                        return job;
+                    }
+                    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                }
+            }
+        };
+
+        let response = server.post("/admin/reindex").await;
+        assert_eq!(response.status_code(), StatusCode::ACCEPTED);
+        let job: Job = response.json();
+        assert_eq!(job.total, Some(2));
+        let job = finished(job.id).await;
+        assert_eq!(job.status, JobStatus::Completed);
+        assert_eq!(job.processed, 2);
+
+        let response = server.post("/admin/compact").await;
+        assert_eq!(response.status_code(), StatusCode::ACCEPTED);
+        let job = finished(response.json::<Job>().id).await;
+        assert_eq!(job.kind, "compact");
+        assert_eq!(job.status, JobStatus::Completed);
+
+        let response = server
+            .post("/search")
+            .json(&SearchRequest {
+                vector: vec![1.0, 0.1],
+                k: Some(1),
+                ..Default::default()
+            })
+            .await;
+        let response: SearchResponse = response.json();
+        assert_eq!(response.results[0].id, "a");
+
+        let backup_dir = tempfile::tempdir().unwrap();
+        let response = server
+            .post("/admin/backup")
+            .json(&BackupRequest {
+                destination: backup_dir.path().to_str().unwrap().to_string(),
+                incremental: false,
+                background: true,
+            })
+            .await;
+        assert_eq!(response.status_code(), StatusCode::ACCEPTED);
+        let job = finished(response.json::<Job>().id).await;
+        assert_eq!(job.status, JobStatus::Completed);
+        assert!(backup_dir.path().join("vectors.redb").exists());
+
+        // Newest first; finished jobs are left alone by cancel
+        let jobs: Vec<Job> = server.get("/admin/jobs").await.json();
+        assert_eq!(jobs.len(), 3);
+        let response = server.post(&format!("/admin/jobs/{}/cancel", job.id)).await;
+        assert_eq!(response.status_code(), StatusCode::ACCEPTED);
+        let job: Job = response.json();
+        assert_eq!(job.status, JobStatus::Completed);
+        assert!(!job.cancel_requested);
+
+        let response = server.get("/admin/jobs/missing").await;
+        assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+        let response = server.post("/admin/jobs/missing/cancel").await;
+        assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
     }
 
     #[tokio::test]
-    async fn test_insert_single_vector() {
-        let server = create_test_app().await;
-
-        let vector = Vector::new(vec![1.0, 2.0, 3.0]);
-        let insert_request = InsertRequest {
-            vectors: vec![vector],
-        };
+    async fn test_server_stops_on_shutdown_signal() {
+        let db = create_test_db().await;
+        let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(start_server(
+            AppState::new(db),
+            "127.0.0.1",
+            0,
+            None,
+            async {
+                let _ = stop_rx.await;
+            },
+        ));
 
-        let response = server.post("/vectors").json(&insert_request).await;
+        stop_tx.send(()).unwrap();
+        let result = tokio::time::timeout(std::time::Duration::from_secs(5), server)
+            .await
+            .expect("server did not shut down");
+        assert!(result.unwrap().is_ok());
+    }
 
-        assert_eq!(response.status_code(), StatusCode::OK);
-        let ids: Vec<String> = response.json();
-        assert_eq!(ids.len(), 1);
-        assert!(!ids[0].is_empty());
+    fn fixture_tls() -> TlsFiles {
+        let fixtures = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        TlsFiles {
+            cert: fixtures.join("tls-cert.pem"),
+            key: fixtures.join("tls-key.pem"),
+        }
     }
 
     #[tokio::test]
-    async fn test_insert_multiple_vectors() {
-        let server = create_test_app().await;
+    async fn test_missing_certificate_fails_start() {
+        let db = create_test_db().await;
+        let mut tls = fixture_tls();
+        tls.cert.set_file_name("missing.pem");
+        let result = start_server(AppState::new(db), "127.0.0.1", 0, Some(tls), async {}).await;
+        assert!(result.is_err());
+    }
 
-        let vectors = vec![
-            Vector::new(vec![1.0, 2.0, 3.0]),
-            Vector::new(vec![4.0, 5.0, 6.0]),
-            Vector::new(vec![7.0, 8.0, 9.0]),
-        ];
-        let insert_request = InsertRequest { vectors };
+    #[cfg(feature = "embeddings")]
+    #[tokio::test]
+    async fn test_https_server() {
+        let db = create_test_db().await;
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(start_server(
+            AppState::new(db),
+            "127.0.0.1",
+            port,
+            Some(fixture_tls()),
+            async {
+                let _ = stop_rx.await;
+            },
+        ));
 
-        let response = server.post("/vectors").json(&insert_request).await;
+        let client = reqwest::Client::builder()
+            .add_root_certificate(
+                reqwest::Certificate::from_pem(&std::fs::read(fixture_tls().cert).unwrap())
+                    .unwrap(),
+            )
+            .build()
+            .unwrap();
+        let url = format!("https://localhost:{}/health", port);
+        let mut body = None;
+        for _ in 0..50 {
+            if let Ok(response) = client.get(&url).send().await {
+                body = Some(response.text().await.unwrap());
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert_eq!(body.as_deref(), Some("OK"));
 
-        assert_eq!(response.status_code(), StatusCode::OK);
-        let ids: Vec<String> = response.json();
-        assert_eq!(ids.len(), 3);
-        assert!(ids.iter().all(|id| !id.is_empty()));
+        stop_tx.send(()).unwrap();
+        let result = tokio::time::timeout(std::time::Duration::from_secs(5), server)
+            .await
+            .expect("server did not shut down");
+        assert!(result.unwrap().is_ok());
     }
 
     #[tokio::test]
-    async fn test_insert_vector_with_metadata() {
-        let server = create_test_app().await;
+    async fn test_request_timeout_and_concurrency_limit() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
-        let mut metadata = HashMap::new();
-        metadata.insert("type".to_string(), "test".to_string());
-        metadata.insert("category".to_string(), "unit_test".to_string());
+        let db = create_test_db().await;
+        let state =
+            AppState::new(db).with_request_limits(Some(Duration::from_millis(300)), Some(1));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, create_router(state)).await });
 
-        let vector = Vector::new(vec![1.0, 2.0, 3.0]).with_metadata(metadata);
-        let insert_request = InsertRequest {
-            vectors: vec![vector],
-        };
+        async fn status_line(stream: &mut tokio::net::TcpStream) -> String {
+            let mut response = vec![0; 1024];
+            let read = stream.read(&mut response).await.unwrap();
+            let response = String::from_utf8_lossy(&response[..read]).to_string();
+            response.lines().next().unwrap_or_default().to_string()
+        }
 
-        let response = server.post("/vectors").json(&insert_request).await;
+        // Holds the only slot while it waits for a body that never comes
+        let mut stalled = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stalled
+            .write_all(b"POST /vectors HTTP/1.1\r\nHost: test\r\nContent-Type: application/json\r\nContent-Length: 100\r\n\r\n")
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
 
-        assert_eq!(response.status_code(), StatusCode::OK);
-        let ids: Vec<String> = response.json();
-        assert_eq!(ids.len(), 1);
+        let mut other = tokio::net::TcpStream::connect(addr).await.unwrap();
+        other
+            .write_all(b"GET /health HTTP/1.1\r\nHost: test\r\n\r\n")
+            .await
+            .unwrap();
+        assert!(status_line(&mut other).await.contains("503"));
+
+        assert!(status_line(&mut stalled).await.contains("408"));
+        other
+            .write_all(b"GET /health HTTP/1.1\r\nHost: test\r\n\r\n")
+            .await
+            .unwrap();
+        assert!(status_line(&mut other).await.contains("200"));
     }
 
     #[tokio::test]
-    async fn test_get_vector_success() {
-        let server = create_test_app().await;
-
-        // First insert a vector
-        let vector = Vector::new(vec![1.0, 2.0, 3.0]);
-        let insert_request = InsertRequest {
-            vectors: vec![vector.clone()],
-        };
+    async fn test_change_events_over_websocket() {
+        use futures::StreamExt;
 
-        let insert_response = server.post("/vectors").json(&insert_request).await;
-
-        let ids: Vec<String> = insert_response.json();
-        let vector_id = &ids[0];
+        let db = create_test_db().await;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = create_router(AppState::new(Arc::clone(&db)));
+        tokio::spawn(async move { axum::serve(listener, app).await });
 
-        // Now get the vector
-        let get_response = server.get(&format!("/vectors/{}", vector_id)).await;
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws/changes", addr))
+            .await
+            .unwrap();
+        db.insert_vectors(vec![
+            Vector::with_id("a".to_string(), vec![1.0, 0.0]).with_collection("docs".to_string())
+        ])
+        .await
+        .unwrap();
+        db.delete_vector("a").await.unwrap();
 
-        assert_eq!(get_response.status_code(), StatusCode::OK);
-        let retrieved_vector: Vector = get_response.json();
-        assert_eq!(retrieved_vector.id, *vector_id);
-        assert_eq!(retrieved_vector.data, vec![1.0, 2.0, 3.0]);
+        let mut kinds = Vec::new();
+        for _ in 0..2 {
+            let message = tokio::time::timeout(std::time::Duration::from_secs(5), socket.next())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            let event: ChangeEvent = serde_json::from_str(message.to_text().unwrap()).unwrap();
+            assert_eq!(event.collection.as_deref(), Some("docs"));
+            kinds.push(event.kind);
+        }
+        assert_eq!(kinds, vec![ChangeKind::Insert, ChangeKind::Delete]);
     }
 
     #[tokio::test]
-    async fn test_get_vector_not_found() {
-        let server = create_test_app().await;
+    async fn test_changes_resume_from_seq() {
+        let (db, _dir) = create_disk_db().await;
+        let server = TestServer::new(create_router(AppState::new(Arc::clone(&db)))).unwrap();
+        db.insert_vectors(
+            ["a", "b", "c"]
+                .into_iter()
+                .map(|id| Vector::with_id(id.to_string(), vec![1.0, 0.0]))
+                .collect(),
+        )
+        .await
+        .unwrap();
+        db.delete_vector("a").await.unwrap();
 
-        let response = server.get("/vectors/nonexistent-id").await;
+        let page: ChangesResponse = server.get("/changes?since=0&limit=2").await.json();
+        let ids: Vec<&str> = page
+            .changes
+            .iter()
+            .map(|change| change.id.as_str())
+            .collect();
+        assert_eq!((ids, page.next), (vec!["a", "b"], 2));
+        let page: ChangesResponse = server.get("/changes?since=2").await.json();
+        let changes: Vec<(&str, ChangeKind)> = page
+            .changes
+            .iter()
+            .map(|change| (change.id.as_str(), change.kind))
+            .collect();
+        assert_eq!(
+            (changes, page.next),
+            (
+                vec![("c", ChangeKind::Insert), ("a", ChangeKind::Delete)],
+                4
+            )
+        );
 
-        assert_eq!(response.status_code(), StatusCode::NOT_FOUND);
+        // Without retention a snapshot drops what the index no longer needs
+        db.snapshot_index().await.unwrap();
+        let response = server.get("/changes?since=0").await;
+        assert_eq!(response.status_code(), StatusCode::GONE);
+        let page: ChangesResponse = server.get("/changes?since=4").await.json();
+        assert!(page.changes.is_empty());
+        assert_eq!(page.next, 4);
     }
 
+    #[cfg(feature = "parquet")]
     #[tokio::test]
-    async fn test_search_vectors() {
-        let server = create_test_app().await;
+    async fn test_export_parquet() {
+        let db = create_test_db().await;
+        let server = TestServer::new(create_router(AppState::new(Arc::clone(&db)))).unwrap();
+        db.insert_vectors(vec![
+            Vector::with_id("a".to_string(), vec![1.0, 0.0]).with_collection("docs".to_string()),
+            Vector::with_id("b".to_string(), vec![0.0, 1.0]),
+        ])
+        .await
+        .unwrap();
 
-        // Insert test vectors
-        let vectors = vec![
-            Vector::new(vec![1.0, 0.0, 0.0]),
-            Vector::new(vec![0.0, 1.0, 0.0]),
-            Vector::new(vec![0.0, 0.0, 1.0]),
-        ];
-        let insert_request = InsertRequest { vectors };
+        let response = server.get("/export?format=parquet&collection=docs").await;
+        assert_eq!(response.status_code(), StatusCode::OK);
+        assert_eq!(
+            response.header(header::CONTENT_TYPE),
+            "application/vnd.apache.parquet"
+        );
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("export.parquet");
+        std::fs::write(&path, response.as_bytes()).unwrap();
+        let vectors = crate::dataset::load_vectors(path.to_str().unwrap()).unwrap();
+        let ids: Vec<&str> = vectors.iter().map(|vector| vector.id.as_str()).collect();
+        assert_eq!(ids, vec!["a"]);
 
-        let insert_response = server.post("/vectors").json(&insert_request).await;
-        assert_eq!(insert_response.status_code(), StatusCode::OK);
+        let response = server.get("/export?format=csv").await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    }
 
-        // Search for similar vectors
-        let search_request = SearchRequest {
-            vector: vec![1.0, 0.1, 0.1],
-            k: Some(2),
-            threshold: Some(0.0),
-        };
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_export_knn_graph() {
+        let db = create_test_db().await;
+        let server = TestServer::new(create_router(AppState::new(Arc::clone(&db)))).unwrap();
+        db.insert_vectors(vec![
+            Vector::with_id("a".to_string(), vec![1.0, 0.0]).with_collection("docs".to_string()),
+            Vector::with_id("b".to_string(), vec![0.8, 0.2]).with_collection("docs".to_string()),
+            Vector::with_id("c".to_string(), vec![0.0, 1.0]).with_collection("docs".to_string()),
+            Vector::with_id("d".to_string(), vec![0.9, 0.1]),
+        ])
+        .await
+        .unwrap();
 
-        let search_response = server.post("/search").json(&search_request).await;
+        let response = server.get("/admin/knn-graph?n=1&collection=docs").await;
+        assert_eq!(response.status_code(), StatusCode::OK);
+        assert_eq!(
+            response.header(header::CONTENT_TYPE),
+            "application/x-ndjson"
+        );
+        let rows: Vec<knn_graph::GraphRow> = response
+            .text()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let edges: Vec<(&str, &str)> = rows
+            .iter()
+            .map(|row| (row.id.as_str(), row.neighbors[0].id.as_str()))
+            .collect();
+        assert_eq!(edges, vec![("a", "b"), ("b", "a"), ("c", "b")]);
 
-        assert_eq!(search_response.status_code(), StatusCode::OK);
-        let search_result: SearchResponse = search_response.json();
-        assert!(search_result.results.len() <= 2);
-        assert!(!search_result.results.is_empty());
+        let response = server.get("/admin/knn-graph?n=0").await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+        let response = server.get("/admin/knn-graph?format=csv").await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
     }
 
     #[tokio::test]
-    async fn test_search_vectors_with_defaults() {
+    async fn test_import_csv() {
         let server = create_test_app().await;
 
-        // Insert test vectors
-        let vector = Vector::new(vec![1.0, 2.0, 3.0]);
-        let insert_request = InsertRequest {
-            vectors: vec![vector],
-        };
-
-        let insert_response = server.post("/vectors").json(&insert_request).await;
-        assert_eq!(insert_response.status_code(), StatusCode::OK);
-
-        // Search with minimal parameters (using defaults)
-        let search_request = SearchRequest {
-            vector: vec![1.0, 2.0, 3.0],
-            k: None,         // Should default to 10
-            threshold: None, // Should default to 0.0
-        };
+        let response = server
+            .post("/import?format=csv&id_column=key&dimension_prefix=dim_&collection=docs")
+            .bytes(Bytes::from(
+                "key,dim_0,dim_1,lang\na,1.0,0.0,en\nb,0.0,1.0,fr\n",
+            ))
+            .await;
+        assert_eq!(response.status_code(), StatusCode::OK);
+        assert_eq!(response.json::<ImportResponse>().imported, 2);
 
-        let search_response = server.post("/search").json(&search_request).await;
+        let vector: Vector = server.get("/vectors/b").await.json();
+        assert_eq!(vector.data, vec![0.0, 1.0]);
+        assert_eq!(vector.collection.as_deref(), Some("docs"));
+        assert_eq!(vector.metadata.unwrap().get("lang").unwrap(), "fr");
 
-        assert_eq!(search_response.status_code(), StatusCode::OK);
-        let search_result: SearchResponse = search_response.json();
-        assert_eq!(search_result.results.len(), 1);
+        let response = server
+            .post("/import?format=csv")
+            .bytes(Bytes::from("id,lang\na,en\n"))
+            .await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
     }
 
     #[tokio::test]
-    async fn test_search_in_collection() {
+    async fn test_qdrant_compat() {
+        use serde_json::{json, Value};
         let server = create_test_app().await;
 
-        // Insert vectors in different collections
-        let vectors = vec![
-            Vector::new(vec![1.0, 0.0, 0.0]).with_collection("collection1".to_string()),
-            Vector::new(vec![0.0, 1.0, 0.0]).with_collection("collection2".to_string()),
-            Vector::new(vec![0.0, 0.0, 1.0]).with_collection("collection1".to_string()),
-        ];
-        let insert_request = InsertRequest { vectors };
+        let response = server
+            .put("/qdrant/collections/docs")
+            .json(&json!({"vectors": {"size": 2, "distance": "Cosine"}}))
+            .await;
+        assert_eq!(response.status_code(), StatusCode::OK);
+        let response = server
+            .put("/qdrant/collections/docs")
+            .json(&json!({"vectors": {"size": 2, "distance": "Cosine"}}))
+            .await;
+        assert_eq!(response.status_code(), StatusCode::CONFLICT);
+        let response = server
+            .put("/qdrant/collections/places")
+            .json(&json!({"vectors": {"size": 2, "distance": "Manhattan"}}))
+            .await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+        let response = server
+            .put("/qdrant/collections/places")
+            .json(&json!({"vectors": {"size": 2, "distance": "Dot"}}))
+            .await;
+        assert_eq!(response.status_code(), StatusCode::OK);
+        let config: Value = server.get("/collections/places/config").await.json();
+        assert_eq!(config["distance_metric"], json!("dot_product"));
 
-        let insert_response = server.post("/vectors").json(&insert_request).await;
-        assert_eq!(insert_response.status_code(), StatusCode::OK);
+        let response = server
+            .put("/qdrant/collections/docs/points")
+            .json(&json!({"points": [
+                {"id": 1, "vector": [1.0, 0.0], "payload": {"page_content": "one", "page": 1}},
+                {"id": 2, "vector": [0.0, 1.0], "payload": {"page_content": "two", "page": 2}},
+            ]}))
+            .await;
+        assert_eq!(response.status_code(), StatusCode::OK);
 
-        // Search in specific collection
-        let search_request = SearchRequest {
-            vector: vec![1.0, 0.0, 0.0],
-            k: Some(10),
-            threshold: Some(0.0),
-        };
+        let result: Value = server
+            .post("/qdrant/collections/docs/points/search")
+            .json(&json!({"vector": [0.9, 0.1], "limit": 1, "with_payload": true}))
+            .await
+            .json();
+        let hit = &result["result"][0];
+        assert_eq!(
+            (&hit["id"], &hit["payload"]["page"]),
+            (&json!(1), &json!(1))
+        );
 
-        let search_response = server
-            .post("/collections/collection1/search")
-            .json(&search_request)
-            .await;
+        let result: Value = server
+            .post("/qdrant/collections/docs/points/search")
+            .json(&json!({
+                "vector": [0.9, 0.1],
+                "filter": {"must": [{"key": "page", "match": {"value": 2}}]},
+            }))
+            .await
+            .json();
+        assert_eq!(result["result"][0]["id"], json!(2));
 
-        assert_eq!(search_response.status_code(), StatusCode::OK);
-        let search_result: SearchResponse = search_response.json();
-        // Should find vectors only from collection1
-        assert!(search_result.results.len() <= 2);
+        let result: Value = server.get("/qdrant/collections/docs").await.json();
+        assert_eq!(result["result"]["points_count"], json!(2));
     }
 
     #[tokio::test]
-    async fn test_stats_after_insertions() {
+    async fn test_chroma_compat() {
+        use serde_json::{json, Value};
         let server = create_test_app().await;
 
-        // Insert some vectors
-        let vectors = vec![
-            Vector::new(vec![1.0, 2.0, 3.0]),
-            Vector::new(vec![4.0, 5.0, 6.0]),
-        ];
-        let insert_request = InsertRequest { vectors };
+        let collection: Value = server
+            .post("/api/v1/collections")
+            .json(&json!({"name": "docs", "get_or_create": true}))
+            .await
+            .json();
+        let id = collection["id"].as_str().unwrap();
+        let response = server
+            .post("/api/v1/collections")
+            .json(&json!({"name": "docs"}))
+            .await;
+        assert_eq!(response.status_code(), StatusCode::CONFLICT);
 
-        let insert_response = server.post("/vectors").json(&insert_request).await;
-        assert_eq!(insert_response.status_code(), StatusCode::OK);
+        let response = server
+            .post(&format!("/api/v1/collections/{}/upsert", id))
+            .json(&json!({
+                "ids": ["a", "b"],
+                "embeddings": [[1.0, 0.0], [0.0, 1.0]],
+                "metadatas": [{"lang": "en"}, {"lang": "fr"}],
+                "documents": ["hello", "bonjour"],
+            }))
+            .await;
+        assert_eq!(response.status_code(), StatusCode::OK);
 
-        // Check stats
-        let stats_response = server.get("/stats").await;
-        assert_eq!(stats_response.status_code(), StatusCode::OK);
+        let result: Value = server
+            .post(&format!("/api/v1/collections/{}/query", id))
+            .json(&json!({"query_embeddings": [[0.0, 1.0]], "n_results": 2}))
+            .await
+            .json();
+        assert_eq!(result["ids"], json!([["b", "a"]]));
+        assert_eq!(result["documents"][0][0], json!("bonjour"));
+        assert_eq!(result["metadatas"][0][0], json!({"lang": "fr"}));
+        assert!(result["distances"][0][0].as_f64().unwrap() < 1e-6);
+        assert!(result["embeddings"].is_null());
 
-        let stats: StatsResponse = stats_response.json();
-        assert_eq!(stats.total_vectors, 2);
-        assert_eq!(stats.dimensions, 3);
-        assert!(stats.storage_size_bytes > 0);
+        let result: Value = server
+            .post(&format!("/api/v1/collections/{}/get", id))
+            .json(&json!({"where": {"lang": {"$eq": "en"}}}))
+            .await
+            .json();
+        assert_eq!(result["ids"], json!(["a"]));
+        let count: u64 = server
+            .get(&format!("/api/v1/collections/{}/count", id))
+            .await
+            .json();
+        assert_eq!(count, 2);
     }
 
     #[tokio::test]
-    async fn test_insert_empty_vectors_list() {
-        let server = create_test_app().await;
-
-        let insert_request = InsertRequest { vectors: vec![] };
+    async fn test_search_documents() {
+        let db = create_test_db().await;
+        let server = TestServer::new(create_router(
+            AppState::new(Arc::clone(&db)).with_text_field("body"),
+        ))
+        .unwrap();
+        db.insert_vectors(vec![
+            Vector::with_id("a".to_string(), vec![1.0, 0.0]).with_metadata(HashMap::from([
+                ("body".to_string(), "hello".to_string()),
+                ("source".to_string(), "a.txt".to_string()),
+            ])),
+            Vector::with_id("b".to_string(), vec![0.0, 1.0])
+                .with_metadata(HashMap::from([("title".to_string(), "bye".to_string())])),
+        ])
+        .await
+        .unwrap();
 
-        let response = server.post("/vectors").json(&insert_request).await;
+        let response: DocumentSearchResponse = server
+            .post("/search/documents")
+            .json(&DocumentSearchRequest {
+                vector: vec![1.0, 0.0],
+                k: Some(1),
+                threshold: None,
+                collection: None,
+                filter: None,
+                content_field: None,
+            })
+            .await
+            .json();
+        let document = &response.documents[0];
+        assert_eq!(
+            (document.id.as_str(), document.page_content.as_str()),
+            ("a", "hello")
+        );
+        assert_eq!(
+            document.metadata,
+            HashMap::from([("source".to_string(), "a.txt".to_string())])
+        );
 
-        assert_eq!(response.status_code(), StatusCode::OK);
-        let ids: Vec<String> = response.json();
-        assert_eq!(ids.len(), 0);
+        let response: DocumentSearchResponse = server
+            .post("/search/documents")
+            .json(&DocumentSearchRequest {
+                vector: vec![0.0, 1.0],
+                k: Some(1),
+                threshold: None,
+                collection: None,
+                filter: None,
+                content_field: Some("title".to_string()),
+            })
+            .await
+            .json();
+        assert_eq!(response.documents[0].page_content, "bye");
+        assert!(response.documents[0].metadata.is_empty());
     }
 
     #[tokio::test]
-    async fn test_search_empty_database() {
-        let server = create_test_app().await;
-
-        let search_request = SearchRequest {
-            vector: vec![1.0, 2.0, 3.0],
-            k: Some(5),
-            threshold: Some(0.0),
+    async fn test_grouped_search() {
+        let db = create_test_db().await;
+        let server = TestServer::new(create_router(AppState::new(Arc::clone(&db)))).unwrap();
+        let chunk = |id: &str, data: Vec<f32>, doc: &str| {
+            Vector::with_id(id.to_string(), data)
+                .with_metadata(HashMap::from([("doc".to_string(), doc.to_string())]))
         };
+        db.insert_vectors(vec![
+            chunk("a1", vec![1.0, 0.0], "a"),
+            chunk("a2", vec![0.99, 0.01], "a"),
+            chunk("a3", vec![0.98, 0.02], "a"),
+            chunk("b1", vec![0.9, 0.1], "b"),
+        ])
+        .await
+        .unwrap();
 
-        let response = server.post("/search").json(&search_request).await;
-
-        assert_eq!(response.status_code(), StatusCode::OK);
-        let search_result: SearchResponse = response.json();
-        assert_eq!(search_result.results.len(), 0);
+        let response: SearchResponse = server
+            .post("/search")
+            .json(&SearchRequest {
+                vector: vec![1.0, 0.0],
+                k: Some(2),
+                group_by: Some("doc".to_string()),
+                group_size: Some(2),
+                ..Default::default()
+            })
+            .await
+            .json();
+        let groups: Vec<(String, Vec<String>)> = response
+            .groups
+            .unwrap()
+            .into_iter()
+            .map(|group| {
+                (
+                    group.value,
+                    group.hits.into_iter().map(|hit| hit.id).collect(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            groups,
+            vec![
+                ("a".to_string(), vec!["a1".to_string(), "a2".to_string()]),
+                ("b".to_string(), vec!["b1".to_string()]),
+            ]
+        );
+        assert!(response.results.is_empty());
     }
 
     #[tokio::test]
-    async fn test_vector_metadata_preserved() {
+    async fn test_search_named_vector() {
         let server = create_test_app().await;
+        let response = server
+            .post("/vectors")
+            .json(&serde_json::json!({"vectors": [
+                {"id": "a", "data": [1.0, 0.0], "created_at": 0, "vectors": {"title": [0.0, 1.0]}, "metadata": {"lang": "en", "text": "..."}},
+                {"id": "b", "data": [0.0, 1.0], "created_at": 0, "vectors": {"title": [1.0, 0.0]}},
+            ]}))
+            .await;
+        assert_eq!(response.status_code(), StatusCode::OK);
 
-        let mut metadata = HashMap::new();
-        metadata.insert("type".to_string(), "document".to_string());
-        metadata.insert("source".to_string(), "test_file.txt".to_string());
-
-        let vector = Vector::new(vec![1.0, 2.0, 3.0]).with_metadata(metadata.clone());
-        let insert_request = InsertRequest {
-            vectors: vec![vector],
-        };
+        let response: SearchResponse = server
+            .post("/search")
+            .json(&SearchRequest {
+                vector: vec![1.0, 0.0],
+                k: Some(1),
+                vector_name: Some("title".to_string()),
+                ..Default::default()
+            })
+            .await
+            .json();
+        assert_eq!(response.results[0].id, "b");
+        assert!(response.results[0].vector.is_none());
 
-        let insert_response = server.post("/vectors").json(&insert_request).await;
-        let ids: Vec<String> = insert_response.json();
+        let vector: Vector = server.get("/vectors/b").await.json();
+        assert_eq!(vector.vectors.get("title").unwrap(), &vec![1.0, 0.0]);
 
-        // Retrieve the vector and check metadata
-        let get_response = server.get(&format!("/vectors/{}", ids[0])).await;
+        // Hits can carry the vector they were scored against, with or
+        // without their metadata
+        let request = |vector_name: Option<&str>, include_metadata| SearchRequest {
+            vector: vec![1.0, 0.0],
+            k: Some(1),
+            vector_name: vector_name.map(str::to_string),
+            include_vector: true,
+            include_metadata,
+            ..Default::default()
+        };
+        let response: SearchResponse = server
+            .post("/search")
+            .json(&request(Some("title"), None))
+            .await
+            .json();
+        assert_eq!(response.results[0].vector.as_deref(), Some(&[1.0, 0.0][..]));
+        let response: SearchResponse = server
+            .post("/search")
+            .json(&request(None, None))
+            .await
+            .json();
+        assert!(response.results[0].metadata.is_some());
+        let response: SearchResponse = server
+            .post("/search")
+            .json(&request(None, Some(false)))
+            .await
+            .json();
+        assert_eq!(response.results[0].id, "a");
+        assert_eq!(response.results[0].vector.as_deref(), Some(&[1.0, 0.0][..]));
+        assert!(response.results[0].metadata.is_none());
 
-        let retrieved_vector: Vector = get_response.json();
-        assert_eq!(retrieved_vector.metadata, Some(metadata));
+        // Or just some of their metadata
+        let response: SearchResponse = server
+            .post("/search")
+            .json(&SearchRequest {
+                fields: Some(vec!["lang".to_string()]),
+                ..request(None, None)
+            })
+            .await
+            .json();
+        let metadata = response.results[0].metadata.as_ref().unwrap();
+        assert_eq!(metadata.keys().collect::<Vec<_>>(), vec!["lang"]);
+        let vector: Vector = server.get("/vectors/a?fields=lang,missing").await.json();
+        assert_eq!(vector.metadata.unwrap().len(), 1);
+        let vector: Vector = server.get("/vectors/a").await.json();
+        assert_eq!(vector.metadata.unwrap().len(), 2);
     }
 
     #[tokio::test]
-    async fn test_invalid_json_request() {
+    async fn test_sparse_and_hybrid_search() {
         let server = create_test_app().await;
-
         let response = server
             .post("/vectors")
-            .json(&serde_json::json!({"invalid": "structure"}))
+            .json(&serde_json::json!({"vectors": [
+                {"id": "a", "data": [1.0, 0.0], "created_at": 0,
+                 "sparse": {"indices": [4], "values": [1.0]}},
+                {"id": "b", "data": [0.0, 1.0], "created_at": 0,
+                 "sparse": {"indices": [4, 7], "values": [3.0, 1.0]}},
+            ]}))
             .await;
+        assert_eq!(response.status_code(), StatusCode::OK);
 
-        // Should return bad request for invalid JSON structure
-        assert_eq!(response.status_code(), StatusCode::UNPROCESSABLE_ENTITY);
+        let response: SearchResponse = server
+            .post("/search")
+            .json(&serde_json::json!({"sparse": {"indices": [4], "values": [1.0]}}))
+            .await
+            .json();
+        let ids: Vec<_> = response.results.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(ids, vec!["b", "a"]);
+
+        // The dense side alone would rank a first
+        let response: SearchResponse = server
+            .post("/search")
+            .json(&serde_json::json!({
+                "vector": [1.0, 0.1],
+                "sparse": {"indices": [4], "values": [1.0]},
+                "fusion": {"method": "weighted", "dense": 1.0, "sparse": 1.0},
+                "k": 1,
+            }))
+            .await
+            .json();
+        assert_eq!(response.results[0].id, "b");
+
+        let response = server
+            .post("/search")
+            .json(&serde_json::json!({
+                "sparse": {"indices": [4], "values": [1.0]},
+                "group_by": "doc",
+            }))
+            .await;
+        assert_eq!(response.status_code(), StatusCode::BAD_REQUEST);
+    }
+
+    // The TypeScript client's types are generated from openapi.json, so
+    // every operation there has to reach a handler
+    #[tokio::test]
+    async fn test_openapi_spec_matches_routes() {
+        let spec: serde_json::Value =
+            serde_json::from_str(include_str!("../openapi.json")).unwrap();
+
+        let schemas = spec["components"]["schemas"].as_object().unwrap();
+        for reference in spec.to_string().split("#/components/schemas/").skip(1) {
+            let name = &reference[..reference.find('"').unwrap()];
+            assert!(schemas.contains_key(name), "No schema named {}", name);
+        }
+
+        for (path, operations) in spec["paths"].as_object().unwrap() {
+            let path = path
+                .replace("{collection}", "docs")
+                .replace("{name}", "snap")
+                .replace("{id}", "a");
+            for (method, operation) in operations.as_object().unwrap() {
+                let tags = operation["tags"].as_array().unwrap();
+                if tags.contains(&"embeddings".into()) && !cfg!(feature = "embeddings") {
+                    continue;
+                }
+
+                // A fresh database each time, so deletes don't take away
+                // what later operations need
+                let db = create_test_db().await;
+                db.insert_vectors(vec![
+                    Vector::with_id("a".to_string(), vec![1.0, 0.0]).with_collection("docs".into())
+                ])
+                .await
+                .unwrap();
+                db.create_snapshot("docs", "snap").await.unwrap();
+                let server = TestServer::new(create_router(AppState::new(db))).unwrap();
+
+                let method: Method = method.to_uppercase().parse().unwrap();
+                let response = server.method(method.clone(), &path).await;
+                // Handlers' own 404s come with an error, except where the
+                // seeded vector and snapshot are found
+                let status = response.status_code();
+                assert!(
+                    status != StatusCode::METHOD_NOT_ALLOWED
+                        && !(status == StatusCode::NOT_FOUND && response.text().is_empty()),
+                    "{} {} has no route",
+                    method,
+                    path
+                );
+            }
+        }
     }
 }
@@ -0,0 +1,229 @@
+use anyhow::Result;
+use axum::http::HeaderMap;
+use serde::{Deserialize, Serialize};
+use skypier_core::{AuditEntry, VectorDatabase};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tracing::warn;
+
+use crate::config::AuditConfig;
+
+// Entries per page of GET /admin/audit unless `limit` says otherwise, and
+// the most a page may hold
+const DEFAULT_AUDIT_LIMIT: usize = 100;
+const MAX_AUDIT_LIMIT: usize = 1000;
+// Entries read from storage at a time while filtering a page
+const SCAN_BATCH: usize = 1000;
+
+// Filters for GET /admin/audit. Entries come oldest first, after `since`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuditQuery {
+    #[serde(default)]
+    pub since: u64,
+    pub limit: Option<usize>,
+    pub namespace: Option<String>,
+    pub key_id: Option<String>,
+    // Matches the start of the action, so "DELETE" or "POST /admin/" work
+    pub action: Option<String>,
+    // Unix seconds, both inclusive
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+}
+
+impl AuditQuery {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        self.namespace
+            .as_ref()
+            .is_none_or(|namespace| &entry.namespace == namespace)
+            && self
+                .key_id
+                .as_ref()
+                .is_none_or(|key_id| entry.key_id.as_ref() == Some(key_id))
+            && self
+                .action
+                .as_ref()
+                .is_none_or(|action| entry.action.starts_with(action.as_str()))
+            && self.from.is_none_or(|from| entry.timestamp >= from)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditPage {
+    pub entries: Vec<AuditEntry>,
+    // Pass as `since` for the entries after these
+    pub next: u64,
+}
+
+// The audit log of every namespace, kept in the default one's storage
+pub struct AuditLog {
+    db: Arc<VectorDatabase>,
+    retain: Option<Duration>,
+    client_ip_header: Option<String>,
+}
+
+impl AuditLog {
+    pub fn new(db: Arc<VectorDatabase>) -> Self {
+        Self {
+            db,
+            retain: None,
+            client_ip_header: None,
+        }
+    }
+
+    pub fn from_config(db: Arc<VectorDatabase>, config: &AuditConfig) -> Self {
+        Self {
+            db,
+            retain: (config.retain_days > 0)
+                .then(|| Duration::from_secs(config.retain_days * 24 * 60 * 60)),
+            client_ip_header: config.client_ip_header.clone(),
+        }
+    }
+
+    // The first address in the proxy's header, which is the client's, else
+    // the peer's
+    pub fn client_ip(&self, headers: &HeaderMap, peer: Option<SocketAddr>) -> Option<String> {
+        self.client_ip_header
+            .as_ref()
+            .and_then(|header| headers.get(header))
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(',').next())
+            .map(str::trim)
+            .filter(|ip| !ip.is_empty())
+            .map(str::to_string)
+            .or_else(|| peer.map(|peer| peer.ip().to_string()))
+    }
+
+    // Failing to record doesn't undo the action, so it's only logged
+    pub async fn record(&self, entry: &AuditEntry) {
+        if let Err(e) = self.db.append_audit(entry).await {
+            warn!(
+                "Failed to record '{}' on {} in the audit log: {}",
+                entry.action, entry.path, e
+            );
+        }
+    }
+
+    // Up to `limit` matching entries. `next` is the last entry looked at,
+    // so paging through a sparse filter doesn't rescan.
+    pub async fn page(&self, query: &AuditQuery) -> Result<AuditPage> {
+        let limit = query
+            .limit
+            .unwrap_or(DEFAULT_AUDIT_LIMIT)
+            .min(MAX_AUDIT_LIMIT);
+        let mut entries = Vec::new();
+        let mut next = query.since;
+        'scan: while entries.len() < limit {
+            let batch = self.db.audit_page(next, SCAN_BATCH).await?;
+            let done = batch.len() < SCAN_BATCH;
+            for entry in batch {
+                // Entries are in time order, so none after this match
+                if query.to.is_some_and(|to| entry.timestamp > to) {
+                    break 'scan;
+                }
+                next = entry.seq;
+                if query.matches(&entry) {
+                    entries.push(entry);
+                    if entries.len() == limit {
+                        break 'scan;
+                    }
+                }
+            }
+            if done {
+                break;
+            }
+        }
+        Ok(AuditPage { entries, next })
+    }
+
+    // Drops the entries past the retention period, returning how many
+    pub async fn prune(&self) -> Result<usize> {
+        let Some(retain) = self.retain else {
+            return Ok(0);
+        };
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default();
+        self.db
+            .prune_audit(now.saturating_sub(retain.as_secs()))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_audit_page_filters() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Arc::new(
+            VectorDatabase::new(dir.path().to_str().unwrap())
+                .await
+                .unwrap(),
+        );
+        let config = AuditConfig {
+            enabled: true,
+            retain_days: 30,
+            client_ip_header: Some("x-forwarded-for".to_string()),
+        };
+        let log = AuditLog::from_config(Arc::clone(&db), &config);
+        for (action, key_id) in [
+            ("POST /vectors", "a"),
+            ("DELETE /vectors/:id", "b"),
+            ("POST /vectors", "b"),
+        ] {
+            log.record(&AuditEntry {
+                namespace: "default".to_string(),
+                action: action.to_string(),
+                key_id: Some(key_id.to_string()),
+                ..Default::default()
+            })
+            .await;
+        }
+
+        let all = log.page(&AuditQuery::default()).await.unwrap();
+        assert_eq!((all.entries.len(), all.next), (3, 3));
+        let posts = log
+            .page(&AuditQuery {
+                action: Some("POST".to_string()),
+                limit: Some(1),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!((posts.entries[0].seq, posts.next), (1, 1));
+        let posts = log
+            .page(&AuditQuery {
+                action: Some("POST".to_string()),
+                since: posts.next,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let seqs: Vec<u64> = posts.entries.iter().map(|entry| entry.seq).collect();
+        assert_eq!(seqs, vec![3]);
+        let by_key = log
+            .page(&AuditQuery {
+                key_id: Some("b".to_string()),
+                to: Some(0),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert!(by_key.entries.is_empty());
+
+        // Everything was just recorded, so nothing is old enough to go
+        assert_eq!(log.prune().await.unwrap(), 0);
+
+        let mut headers = HeaderMap::new();
+        let peer = Some("10.0.0.9:5000".parse().unwrap());
+        assert_eq!(log.client_ip(&headers, peer).as_deref(), Some("10.0.0.9"));
+        headers.insert("x-forwarded-for", "203.0.113.7, 10.0.0.1".parse().unwrap());
+        assert_eq!(
+            log.client_ip(&headers, peer).as_deref(),
+            Some("203.0.113.7")
+        );
+    }
+}
@@ -0,0 +1,177 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use skypier_core::VectorDatabase;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+// Setting the assignments are kept under, as JSON key hash -> role
+const API_KEYS_SETTING: &str = "api_keys";
+
+// Each role may do everything the ones before it can
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Read,  // search and get
+    Write, // insert and delete
+    Admin, // snapshots, backups, key management
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKeyInfo {
+    pub id: String,
+    pub role: Role,
+}
+
+// API keys and their roles. Only SHA-256 hashes are stored; a key's id is
+// the start of its hash, so keys can be managed without knowing them.
+pub struct ApiKeys {
+    db: Arc<VectorDatabase>,
+    header: String,
+    // From the environment, always admin, never stored
+    bootstrap_hash: Option<String>,
+    keys: RwLock<BTreeMap<String, Role>>,
+}
+
+fn hash(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+fn id_of(hash: &str) -> String {
+    hash[..16].to_string()
+}
+
+// The id a key is listed under, or would be if it were valid
+pub fn key_id(key: &str) -> String {
+    id_of(&hash(key))
+}
+
+impl ApiKeys {
+    pub async fn load(
+        db: Arc<VectorDatabase>,
+        header: &str,
+        bootstrap_key: Option<&str>,
+    ) -> Result<Self> {
+        let keys = match db.get_setting(API_KEYS_SETTING).await? {
+            Some(json) => serde_json::from_str(&json)?,
+            None => BTreeMap::new(),
+        };
+        Ok(Self {
+            db,
+            header: header.to_string(),
+            bootstrap_hash: bootstrap_key.map(hash),
+            keys: RwLock::new(keys),
+        })
+    }
+
+    // Request header the key is sent in
+    pub fn header(&self) -> &str {
+        &self.header
+    }
+
+    pub async fn role(&self, key: &str) -> Option<Role> {
+        let hash = hash(key);
+        if self.bootstrap_hash.as_ref() == Some(&hash) {
+            return Some(Role::Admin);
+        }
+        self.keys.read().await.get(&hash).copied()
+    }
+
+    // Returns the new key; it can't be recovered later
+    pub async fn create(&self, role: Role) -> Result<(ApiKeyInfo, String)> {
+        let key = format!("sk_{}", uuid::Uuid::new_v4().simple());
+        let hash = hash(&key);
+        let mut keys = self.keys.write().await;
+        keys.insert(hash.clone(), role);
+        self.save(&keys).await?;
+        Ok((
+            ApiKeyInfo {
+                id: id_of(&hash),
+                role,
+            },
+            key,
+        ))
+    }
+
+    // False if no key has that id
+    pub async fn set_role(&self, id: &str, role: Role) -> Result<bool> {
+        let mut keys = self.keys.write().await;
+        let Some(assigned) = keys
+            .iter_mut()
+            .find(|(hash, _)| id_of(hash) == id)
+            .map(|(_, assigned)| assigned)
+        else {
+            return Ok(false);
+        };
+        *assigned = role;
+        self.save(&keys).await?;
+        Ok(true)
+    }
+
+    pub async fn revoke(&self, id: &str) -> Result<bool> {
+        let mut keys = self.keys.write().await;
+        let before = keys.len();
+        keys.retain(|hash, _| id_of(hash) != id);
+        if keys.len() == before {
+            return Ok(false);
+        }
+        self.save(&keys).await?;
+        Ok(true)
+    }
+
+    pub async fn list(&self) -> Vec<ApiKeyInfo> {
+        self.keys
+            .read()
+            .await
+            .iter()
+            .map(|(hash, role)| ApiKeyInfo {
+                id: id_of(hash),
+                role: *role,
+            })
+            .collect()
+    }
+
+    async fn save(&self, keys: &BTreeMap<String, Role>) -> Result<()> {
+        self.db
+            .put_setting(API_KEYS_SETTING, &serde_json::to_string(keys)?)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_keys_persist_as_hashes() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().to_str().unwrap();
+        let key = {
+            let db = Arc::new(VectorDatabase::new(path).await.unwrap());
+            let keys = ApiKeys::load(db, "x-api-key", Some("boot")).await.unwrap();
+            assert_eq!(keys.role("boot").await, Some(Role::Admin));
+
+            let (info, key) = keys.create(Role::Read).await.unwrap();
+            assert_eq!(keys.role(&key).await, Some(Role::Read));
+            assert!(keys.set_role(&info.id, Role::Write).await.unwrap());
+            assert!(!keys.set_role("missing", Role::Write).await.unwrap());
+            key
+        };
+
+        let db = Arc::new(VectorDatabase::new(path).await.unwrap());
+        assert!(!db
+            .get_setting(API_KEYS_SETTING)
+            .await
+            .unwrap()
+            .unwrap()
+            .contains(&key));
+        let keys = ApiKeys::load(db, "x-api-key", None).await.unwrap();
+        assert_eq!(keys.role(&key).await, Some(Role::Write));
+        assert_eq!(keys.role("boot").await, None);
+
+        let id = keys.list().await[0].id.clone();
+        assert!(keys.revoke(&id).await.unwrap());
+        assert_eq!(keys.role(&key).await, None);
+    }
+}
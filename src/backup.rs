@@ -0,0 +1,487 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use skypier_core::{ChangeSet, Vector, VectorDatabase};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::config::Config;
+
+// Backups go to a local directory or, with the `object-store` feature, to an
+// s3://, gs:// or az:// URL. Remote backups are staged in a temporary dir
+// and uploaded file by file; restores download them the same way.
+pub fn is_remote(location: &str) -> bool {
+    location.contains("://")
+}
+
+pub async fn backup(db: &VectorDatabase, destination: &str) -> Result<()> {
+    if !is_remote(destination) {
+        return db.backup(destination).await;
+    }
+
+    let staging = tempfile::tempdir()?;
+    db.backup(path_str(staging.path())?).await?;
+    upload(staging.path(), destination).await?;
+    info!("Uploaded backup to {}", destination);
+    Ok(())
+}
+
+const MANIFEST_FILE: &str = "manifest.json";
+
+// An incremental backup set: a full export followed by the changes since
+// each previous backup. Any backup in it can be restored.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Manifest {
+    backups: Vec<BackupPoint>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BackupPoint {
+    // WAL seq the backup was taken at
+    seq: u64,
+    // Seq of the backup this one builds on; None for the full export
+    base_seq: Option<u64>,
+    file: String,
+    upserts: usize,
+    deletes: usize,
+    created_at: u64,
+}
+
+// Adds a backup to the set at `destination`, starting the set with a full
+// export if there is none yet. Returns the seq it was taken at.
+pub async fn backup_incremental(db: &VectorDatabase, destination: &str) -> Result<u64> {
+    let location = Location::open(destination)?;
+    let mut manifest: Manifest = match location.read(MANIFEST_FILE).await? {
+        Some(contents) => serde_json::from_slice(&contents)?,
+        None => Manifest::default(),
+    };
+
+    let base_seq = manifest.backups.last().map(|point| point.seq);
+    let changes = db.export_changes(base_seq).await?;
+    if base_seq == Some(changes.seq) {
+        info!("Nothing changed since the backup at seq {}", changes.seq);
+        return Ok(changes.seq);
+    }
+
+    // The manifest goes last, so a failed backup leaves the set as it was
+    let file = format!("{:020}.json", changes.seq);
+    location.write(&file, serde_json::to_vec(&changes)?).await?;
+    manifest.backups.push(BackupPoint {
+        seq: changes.seq,
+        base_seq,
+        file,
+        upserts: changes.upserts.len(),
+        deletes: changes.deletes.len(),
+        created_at: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs(),
+    });
+    location
+        .write(MANIFEST_FILE, serde_json::to_vec_pretty(&manifest)?)
+        .await?;
+
+    info!(
+        "Backed up {} upserts and {} deletes to {} at seq {}",
+        changes.upserts.len(),
+        changes.deletes.len(),
+        destination,
+        changes.seq
+    );
+    Ok(changes.seq)
+}
+
+// Fills `data_dir` from a backup. Refuses to overwrite existing data, so it
+// is meant to run before the server starts. Incremental backup sets are
+// restored as of the latest backup at or before `to_seq`, or the latest one.
+pub async fn restore(
+    config: &Config,
+    source: &str,
+    data_dir: &str,
+    to_seq: Option<u64>,
+) -> Result<()> {
+    let target = Path::new(data_dir);
+    if target.exists() && std::fs::read_dir(target)?.next().is_some() {
+        return Err(anyhow!(
+            "Refusing to restore into {}, which is not empty",
+            data_dir
+        ));
+    }
+
+    let location = Location::open(source)?;
+    if let Some(manifest) = location.read(MANIFEST_FILE).await? {
+        let manifest: Manifest = serde_json::from_slice(&manifest)?;
+        return restore_set(config, &location, manifest, data_dir, to_seq).await;
+    }
+    if to_seq.is_some() {
+        return Err(anyhow!(
+            "{} is not an incremental backup set, so it can't be restored to a seq",
+            source
+        ));
+    }
+
+    std::fs::create_dir_all(target)?;
+    if is_remote(source) {
+        download(source, target).await?;
+    } else {
+        for file in files_under(Path::new(source))? {
+            let destination = target.join(&file);
+            if let Some(parent) = destination.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::copy(Path::new(source).join(&file), destination)?;
+        }
+    }
+    info!("Restored {} into {}", source, data_dir);
+    Ok(())
+}
+
+async fn restore_set(
+    config: &Config,
+    location: &Location,
+    manifest: Manifest,
+    data_dir: &str,
+    to_seq: Option<u64>,
+) -> Result<()> {
+    let points: Vec<&BackupPoint> = manifest
+        .backups
+        .iter()
+        .take_while(|point| to_seq.is_none_or(|to_seq| point.seq <= to_seq))
+        .collect();
+    let Some(last) = points.last() else {
+        return Err(anyhow!(
+            "The backup set has no backup at or before seq {}",
+            to_seq.unwrap_or_default()
+        ));
+    };
+
+    let mut vectors: HashMap<String, Vector> = HashMap::new();
+    for point in &points {
+        let contents = location
+            .read(&point.file)
+            .await?
+            .ok_or_else(|| anyhow!("Backup file {} is missing", point.file))?;
+        let changes: ChangeSet = serde_json::from_slice(&contents)?;
+        for id in &changes.deletes {
+            vectors.remove(id);
+        }
+        for vector in changes.upserts {
+            vectors.insert(vector.id.clone(), vector);
+        }
+    }
+
+    let db = VectorDatabase::from_storage(config.storage.open(data_dir).await?, data_dir)?
+        .with_index(config.index.in_data_dir(data_dir).build()?)
+        .with_validation_limits(config.validation.limits());
+    let count = db.bulk_load(vectors.into_values().collect()).await?;
+    info!(
+        "Restored {} vectors into {} as of seq {}",
+        count, data_dir, last.seq
+    );
+    Ok(())
+}
+
+// Where a backup set lives
+enum Location {
+    Local(PathBuf),
+    #[cfg(feature = "object-store")]
+    Remote(ObjectStoreBackend),
+}
+
+impl Location {
+    fn open(location: &str) -> Result<Self> {
+        if !is_remote(location) {
+            return Ok(Self::Local(PathBuf::from(location)));
+        }
+        #[cfg(feature = "object-store")]
+        return Ok(Self::Remote(ObjectStoreBackend::from_url(location)?));
+        #[cfg(not(feature = "object-store"))]
+        Err(anyhow!(
+            "Remote backups require building with --features object-store"
+        ))
+    }
+
+    async fn read(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        match self {
+            Self::Local(dir) => match tokio::fs::read(dir.join(name)).await {
+                Ok(contents) => Ok(Some(contents)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e.into()),
+            },
+            #[cfg(feature = "object-store")]
+            Self::Remote(backend) => backend.read(Path::new(name)).await,
+        }
+    }
+
+    async fn write(&self, name: &str, contents: Vec<u8>) -> Result<()> {
+        match self {
+            Self::Local(dir) => {
+                tokio::fs::create_dir_all(dir).await?;
+                tokio::fs::write(dir.join(name), contents).await?;
+                Ok(())
+            }
+            #[cfg(feature = "object-store")]
+            Self::Remote(backend) => backend.write(Path::new(name), contents).await,
+        }
+    }
+}
+
+fn path_str(path: &Path) -> Result<&str> {
+    path.to_str()
+        .ok_or_else(|| anyhow!("{} is not valid UTF-8", path.display()))
+}
+
+// Paths of every file below `dir`, relative to it
+fn files_under(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(relative) = pending.pop() {
+        for entry in std::fs::read_dir(dir.join(&relative))? {
+            let entry = entry?;
+            let path = relative.join(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                pending.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(not(feature = "object-store"))]
+async fn upload(_dir: &Path, _destination: &str) -> Result<()> {
+    Err(anyhow!(
+        "Remote backups require building with --features object-store"
+    ))
+}
+
+#[cfg(not(feature = "object-store"))]
+async fn download(_source: &str, _dir: &Path) -> Result<()> {
+    Err(anyhow!(
+        "Remote backups require building with --features object-store"
+    ))
+}
+
+#[cfg(feature = "object-store")]
+async fn upload(dir: &Path, destination: &str) -> Result<()> {
+    let backend = ObjectStoreBackend::from_url(destination)?;
+    for file in files_under(dir)? {
+        backend.upload_file(&dir.join(&file), &file).await?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "object-store")]
+async fn download(source: &str, dir: &Path) -> Result<()> {
+    ObjectStoreBackend::from_url(source)?
+        .download_all(dir)
+        .await
+}
+
+// A bucket prefix on S3, GCS or Azure. Credentials and regions come from the
+// usual AWS_*, GOOGLE_* and AZURE_* environment variables.
+#[cfg(feature = "object-store")]
+pub struct ObjectStoreBackend {
+    store: Box<dyn object_store::ObjectStore>,
+    prefix: object_store::path::Path,
+}
+
+#[cfg(feature = "object-store")]
+impl ObjectStoreBackend {
+    // Files at least this large are sent as multipart uploads, in parts of
+    // this size
+    const PART_SIZE: usize = 8 * 1024 * 1024;
+    const MAX_CONCURRENT_PARTS: usize = 4;
+
+    pub fn from_url(url: &str) -> Result<Self> {
+        let url = url::Url::parse(url).map_err(|e| anyhow!("Invalid URL {}: {}", url, e))?;
+        let options = std::env::vars().map(|(key, value)| (key.to_ascii_lowercase(), value));
+        let (store, prefix) = object_store::parse_url_opts(&url, options)?;
+        Ok(Self { store, prefix })
+    }
+
+    fn location(&self, relative: &Path) -> object_store::path::Path {
+        relative
+            .components()
+            .fold(self.prefix.clone(), |location, component| {
+                location.child(component.as_os_str().to_string_lossy().as_ref())
+            })
+    }
+
+    pub async fn read(&self, relative: &Path) -> Result<Option<Vec<u8>>> {
+        match self.store.get(&self.location(relative)).await {
+            Ok(result) => Ok(Some(result.bytes().await?.to_vec())),
+            Err(object_store::Error::NotFound { .. }) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn write(&self, relative: &Path, contents: Vec<u8>) -> Result<()> {
+        let location = self.location(relative);
+        if contents.len() < Self::PART_SIZE {
+            self.store.put(&location, contents.into()).await?;
+            return Ok(());
+        }
+
+        let upload = self.store.put_multipart(&location).await?;
+        let mut writer = object_store::WriteMultipart::new_with_chunk_size(upload, Self::PART_SIZE);
+        for part in contents.chunks(Self::PART_SIZE) {
+            writer.wait_for_capacity(Self::MAX_CONCURRENT_PARTS).await?;
+            writer.write(part);
+        }
+        writer.finish().await?;
+        Ok(())
+    }
+
+    pub async fn upload_file(&self, file: &Path, relative: &Path) -> Result<()> {
+        use tokio::io::AsyncReadExt;
+
+        let mut reader = tokio::fs::File::open(file).await?;
+        if reader.metadata().await?.len() < Self::PART_SIZE as u64 {
+            let mut contents = Vec::new();
+            reader.read_to_end(&mut contents).await?;
+            return self.write(relative, contents).await;
+        }
+
+        let upload = self.store.put_multipart(&self.location(relative)).await?;
+        let mut writer = object_store::WriteMultipart::new_with_chunk_size(upload, Self::PART_SIZE);
+        let mut buffer = vec![0; Self::PART_SIZE];
+        loop {
+            let read = reader.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            writer.wait_for_capacity(Self::MAX_CONCURRENT_PARTS).await?;
+            writer.write(&buffer[..read]);
+        }
+        writer.finish().await?;
+        Ok(())
+    }
+
+    // Downloads every object under the prefix into `dir`, keeping their
+    // paths relative to the prefix
+    pub async fn download_all(&self, dir: &Path) -> Result<()> {
+        use futures::TryStreamExt;
+        use tokio::io::AsyncWriteExt;
+
+        let objects: Vec<_> = self.store.list(Some(&self.prefix)).try_collect().await?;
+        if objects.is_empty() {
+            return Err(anyhow!("No backup found at {}", self.prefix));
+        }
+
+        for object in objects {
+            let relative: PathBuf = object
+                .location
+                .prefix_match(&self.prefix)
+                .ok_or_else(|| anyhow!("{} is outside the backup", object.location))?
+                .map(|part| part.as_ref().to_string())
+                .collect();
+            let file = dir.join(relative);
+            if let Some(parent) = file.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+
+            let mut writer = tokio::fs::File::create(&file).await?;
+            let mut chunks = self.store.get(&object.location).await?.into_stream();
+            while let Some(chunk) = chunks.try_next().await? {
+                writer.write_all(&chunk).await?;
+            }
+            writer.flush().await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vector(id: &str, data: Vec<f32>) -> Vector {
+        Vector::with_id(id.to_string(), data)
+    }
+
+    async fn restored(location: &str, to_seq: Option<u64>) -> (tempfile::TempDir, VectorDatabase) {
+        let restored_dir = tempfile::tempdir().unwrap();
+        let data_dir = restored_dir.path().join("data");
+        let data_dir = data_dir.to_str().unwrap();
+        let config = Config::default();
+        restore(&config, location, data_dir, to_seq).await.unwrap();
+        assert!(restore(&config, location, data_dir, to_seq).await.is_err());
+
+        let db = VectorDatabase::new(data_dir).await.unwrap();
+        db.load_index().await.unwrap();
+        (restored_dir, db)
+    }
+
+    async fn check_round_trip(location: &str) {
+        let source_dir = tempfile::tempdir().unwrap();
+        let db = VectorDatabase::new(source_dir.path().to_str().unwrap())
+            .await
+            .unwrap();
+        db.insert_vectors(vec![vector("a", vec![1.0, 0.0])])
+            .await
+            .unwrap();
+        backup(&db, location).await.unwrap();
+        drop(db);
+
+        let (_dir, db) = restored(location, None).await;
+        assert!(db.get_vector("a").await.unwrap().is_some());
+        assert_eq!(db.search(&[1.0, 0.0], 1, 0.0).await.unwrap()[0].id, "a");
+    }
+
+    async fn check_incremental_round_trip(location: &str) {
+        let source_dir = tempfile::tempdir().unwrap();
+        let db = VectorDatabase::new(source_dir.path().to_str().unwrap())
+            .await
+            .unwrap();
+        db.insert_vectors(vec![
+            vector("a", vec![1.0, 0.0]),
+            vector("b", vec![0.0, 1.0]),
+        ])
+        .await
+        .unwrap();
+        let full = backup_incremental(&db, location).await.unwrap();
+
+        // Index snapshots truncate the WAL, but not past the last backup
+        db.delete_vector("a").await.unwrap();
+        db.insert_vectors(vec![vector("c", vec![0.5, 0.5])])
+            .await
+            .unwrap();
+        db.snapshot_index().await.unwrap();
+        let incremental = backup_incremental(&db, location).await.unwrap();
+        assert!(incremental > full);
+        assert_eq!(
+            backup_incremental(&db, location).await.unwrap(),
+            incremental
+        );
+        drop(db);
+
+        let (_dir, db) = restored(location, None).await;
+        assert!(db.get_vector("a").await.unwrap().is_none());
+        assert!(db.get_vector("c").await.unwrap().is_some());
+
+        let (_dir, db) = restored(location, Some(incremental - 1)).await;
+        assert!(db.get_vector("a").await.unwrap().is_some());
+        assert!(db.get_vector("c").await.unwrap().is_none());
+        assert_eq!(db.search(&[1.0, 0.0], 1, 0.0).await.unwrap()[0].id, "a");
+    }
+
+    #[tokio::test]
+    async fn test_local_backup_round_trip() {
+        let backup_dir = tempfile::tempdir().unwrap();
+        check_round_trip(backup_dir.path().join("backup").to_str().unwrap()).await;
+        check_incremental_round_trip(backup_dir.path().join("set").to_str().unwrap()).await;
+    }
+
+    // file:// goes through the same object store code as S3
+    #[cfg(feature = "object-store")]
+    #[tokio::test]
+    async fn test_object_store_backup_round_trip() {
+        let backup_dir = tempfile::tempdir().unwrap();
+        let url = format!("file://{}/backup", backup_dir.path().display());
+        check_round_trip(&url).await;
+        let url = format!("file://{}/set", backup_dir.path().display());
+        check_incremental_round_trip(&url).await;
+    }
+}
@@ -0,0 +1,199 @@
+use anyhow::{anyhow, Result};
+use skypier_core::{DistanceMetric, Vector};
+use skypier_index::{FlatIndex, SearchResult, VectorIndex};
+use std::collections::HashSet;
+use std::time::Instant;
+use tracing::info;
+
+use crate::config::Config;
+use crate::tune::percentile;
+
+#[derive(Debug, Clone)]
+pub struct RecallRow {
+    pub ef_search: usize,
+    pub recall: f32,
+    pub p50_latency_ms: f64,
+    pub p95_latency_ms: f64,
+}
+
+// Evenly spaced positions of `count` queries among `len` vectors
+fn sample(len: usize, count: usize) -> Vec<usize> {
+    let count = count.clamp(1, len);
+    (0..count).map(|i| i * len / count).collect()
+}
+
+// Ids of the k nearest hits, leaving out the query's own record
+fn top_ids(results: Vec<SearchResult>, query_id: &str, k: usize) -> Vec<String> {
+    results
+        .into_iter()
+        .map(|result| result.id)
+        .filter(|id| id != query_id)
+        .take(k)
+        .collect()
+}
+
+// Runs `num_queries` stored vectors through `search(query, n, ef_search)` at
+// each ef_search and measures recall@k against an exact scan, plus query
+// latency. The queries stay in the index, so their own records are left out
+// of both sides.
+pub fn measure(
+    vectors: &[Vector],
+    metric: DistanceMetric,
+    num_queries: usize,
+    k: usize,
+    ef_searches: &[usize],
+    search: impl Fn(&[f32], usize, usize) -> Result<Vec<SearchResult>>,
+) -> Result<Vec<RecallRow>> {
+    if vectors.len() < 2 {
+        return Err(anyhow!(
+            "Measuring recall needs at least two stored vectors"
+        ));
+    }
+    let ids: Vec<&str> = vectors.iter().map(|vector| vector.id.as_str()).collect();
+    let data: Vec<&[f32]> = vectors
+        .iter()
+        .map(|vector| vector.data.as_slice())
+        .collect();
+    let exact = FlatIndex::new().with_metric(metric.index_metric());
+    exact.build_batch(&ids, &data)?;
+
+    let queries: Vec<&Vector> = sample(vectors.len(), num_queries)
+        .into_iter()
+        .map(|i| &vectors[i])
+        .collect();
+    let ground_truth: Vec<HashSet<String>> = queries
+        .iter()
+        .map(|query| {
+            let results = exact.search(&query.data, k + 1)?;
+            Ok(top_ids(results, &query.id, k).into_iter().collect())
+        })
+        .collect::<Result<_>>()?;
+
+    let mut rows = Vec::new();
+    for &ef_search in ef_searches {
+        let mut latencies = Vec::with_capacity(queries.len());
+        let mut hits = 0;
+        let mut expected = 0;
+
+        for (query, truth) in queries.iter().zip(&ground_truth) {
+            let started = Instant::now();
+            let results = search(&query.data, k + 1, ef_search.max(k + 1))?;
+            latencies.push(started.elapsed().as_secs_f64() * 1000.0);
+
+            let found = top_ids(results, &query.id, k);
+            hits += found.iter().filter(|id| truth.contains(*id)).count();
+            expected += truth.len();
+        }
+
+        rows.push(RecallRow {
+            ef_search,
+            recall: hits as f32 / expected.max(1) as f32,
+            p50_latency_ms: percentile(&mut latencies, 0.5),
+            p95_latency_ms: percentile(&mut latencies, 0.95),
+        });
+    }
+    Ok(rows)
+}
+
+pub fn render_table(rows: &[RecallRow], k: usize) -> String {
+    let recall = format!("recall@{}", k);
+    let mut table = format!(
+        "{:>9}  {:>9}  {:>8}  {:>8}\n",
+        "ef_search", recall, "p50 ms", "p95 ms"
+    );
+    for row in rows {
+        table.push_str(&format!(
+            "{:>9}  {:>9.3}  {:>8.3}  {:>8.3}\n",
+            row.ef_search, row.recall, row.p50_latency_ms, row.p95_latency_ms
+        ));
+    }
+    table
+}
+
+// `skypier-vecdb bench recall`: builds an HNSW graph with the configured
+// [index] settings over a stopped instance's vectors and prints a recall and
+// latency table per ef_search
+pub async fn run(
+    config: &Config,
+    data_dir: &str,
+    num_queries: usize,
+    k: usize,
+    ef_searches: &[usize],
+) -> Result<()> {
+    let storage = config.storage.open(data_dir).await?;
+    let vectors = storage.list_vectors().await?;
+    let Some(first) = vectors.first() else {
+        return Err(anyhow!("{} has no stored vectors", data_dir));
+    };
+
+    let mut index_config = config.index.clone();
+    index_config.dimensions = first.dimensions();
+    let metric: DistanceMetric = index_config.distance_metric.parse()?;
+    let index = index_config.hnsw()?;
+    let started = Instant::now();
+    let ids: Vec<&str> = vectors.iter().map(|vector| vector.id.as_str()).collect();
+    let data: Vec<&[f32]> = vectors
+        .iter()
+        .map(|vector| vector.data.as_slice())
+        .collect();
+    index.build_batch(&ids, &data)?;
+    info!(
+        "Built M={} ef_construction={} over {} vectors in {:?}",
+        index_config.max_connections,
+        index_config.ef_construction,
+        vectors.len(),
+        started.elapsed()
+    );
+
+    let rows = measure(
+        &vectors,
+        metric,
+        num_queries,
+        k,
+        ef_searches,
+        |query, n, ef| Ok(index.search_with_ef(query, n, ef)),
+    )?;
+    print!("{}", render_table(&rows, k));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_measure_recall_per_ef_search() {
+        // Deterministic spread of 2D directions
+        let vectors: Vec<Vector> = (0..80)
+            .map(|i| {
+                let angle = i as f32 * 0.37;
+                Vector::with_id(i.to_string(), vec![angle.cos(), angle.sin()])
+            })
+            .collect();
+        let index = skypier_index::HnswIndex::new(2).unwrap();
+        let ids: Vec<&str> = vectors.iter().map(|vector| vector.id.as_str()).collect();
+        let data: Vec<&[f32]> = vectors
+            .iter()
+            .map(|vector| vector.data.as_slice())
+            .collect();
+        index.build_batch(&ids, &data).unwrap();
+
+        let rows = measure(
+            &vectors,
+            DistanceMetric::Cosine,
+            10,
+            5,
+            &[8, 200],
+            |query, n, ef| Ok(index.search_with_ef(query, n, ef)),
+        )
+        .unwrap();
+        assert_eq!(rows.len(), 2);
+        assert!(rows.iter().all(|row| (0.0..=1.0).contains(&row.recall)));
+        assert_eq!(rows[1].recall, 1.0);
+
+        let table = render_table(&rows, 5);
+        assert!(table.lines().next().unwrap().contains("recall@5"));
+        assert_eq!(table.lines().count(), 3);
+        assert_eq!(sample(10, 3), vec![0, 3, 6]);
+    }
+}
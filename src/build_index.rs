@@ -0,0 +1,34 @@
+use anyhow::Result;
+use skypier_core::VectorDatabase;
+use std::time::Instant;
+use tracing::info;
+
+use crate::config::Config;
+use crate::dataset;
+
+// `skypier-vecdb build-index`: loads a dataset straight into a fresh data
+// dir, bypassing the HTTP API, so a server can boot from it.
+pub async fn run(config: &Config, input: &str, output: &str) -> Result<()> {
+    let started = Instant::now();
+    let vectors = dataset::load_vectors(input)?;
+    info!(
+        "Read {} vectors from {} in {:?}",
+        vectors.len(),
+        input,
+        started.elapsed()
+    );
+
+    let started = Instant::now();
+    let db = VectorDatabase::from_storage(config.storage.open(output).await?, output)?
+        .with_index(config.index.in_data_dir(output).build()?)
+        .with_validation_limits(config.validation.limits());
+    let count = db.bulk_load(vectors).await?;
+    info!(
+        "Stored and indexed {} vectors in {} in {:?}",
+        count,
+        output,
+        started.elapsed()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,499 @@
+// Cluster mode: every collection belongs to one member, picked from the
+// membership list, and whichever node a client reaches proxies inserts,
+// gets and deletes for other members' collections to their owner. The
+// owner copies its writes to the collection's other replicas.
+
+use anyhow::Result;
+use async_trait::async_trait;
+use axum::http::StatusCode;
+use skypier_core::{ChangeSet, Vector};
+use skypier_network::placement;
+use skypier_network::{
+    ForwardHandler, ForwardRequest, ForwardResponse, Member, MemberStatus, NodeHandle,
+    PlacementPolicy, ReplicaFailure, WriteConcern, WriteReport,
+};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+use crate::api::{ApiError, Tenant};
+use crate::namespace::Namespaces;
+use crate::replicator::Replicator;
+
+// Vectors inserted without a collection are placed under this name
+const NO_COLLECTION: &str = "";
+// How long writes wait for the other replicas, unless an insert asks for
+// another limit
+pub const WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Replication factor and placement constraints per collection, and the
+// cluster-wide ones for every other collection
+#[derive(Debug, Clone, Default)]
+pub struct Placement {
+    default: PlacementPolicy,
+    collections: HashMap<String, PlacementPolicy>,
+}
+
+impl Placement {
+    pub fn new(default: PlacementPolicy, collections: HashMap<String, PlacementPolicy>) -> Self {
+        Self {
+            default,
+            collections,
+        }
+    }
+
+    pub fn policy(&self, collection: &str) -> &PlacementPolicy {
+        self.collections.get(collection).unwrap_or(&self.default)
+    }
+}
+
+// The members holding `collection`, its owner first
+pub async fn replicas(
+    node: &NodeHandle,
+    placement: &Placement,
+    collection: Option<&str>,
+) -> Result<Vec<Member>> {
+    let collection = collection.unwrap_or(NO_COLLECTION);
+    let members = node.members().await?;
+    Ok(
+        placement::replicas(&members, collection, placement.policy(collection))
+            .into_iter()
+            .cloned()
+            .collect(),
+    )
+}
+
+// The member owning `collection`, None when that's this node or when no
+// member owns anything yet. 503 when members own collections but none
+// meets this one's placement constraints.
+pub fn remote_owner(
+    members: &[Member],
+    local_id: &str,
+    placement: &Placement,
+    collection: &str,
+) -> Result<Option<Member>, ApiError> {
+    let policy = placement.policy(collection);
+    match placement::owner(members, collection, policy) {
+        Some(owner) => Ok(Some(owner).filter(|owner| owner.id != local_id).cloned()),
+        None if placement::owner(members, collection, &PlacementPolicy::default()).is_some() => {
+            Err(ApiError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                format!(
+                    "No live member meets the placement constraints of collection '{}'",
+                    collection
+                ),
+            ))
+        }
+        None => Ok(None),
+    }
+}
+
+// Live members other than this node that may hold vectors
+pub async fn other_owners(node: &NodeHandle) -> Result<Vec<Member>> {
+    let local_id = node.local_id();
+    Ok(node
+        .members()
+        .await?
+        .into_iter()
+        .filter(|member| member.shard_owner && member.id != local_id)
+        .filter(|member| member.status != MemberStatus::Dead)
+        .collect())
+}
+
+// Sends `request` to `owner` and turns its answer back into what this node
+// would have answered
+pub async fn forward(
+    node: &NodeHandle,
+    owner: &Member,
+    request: ForwardRequest,
+) -> Result<ForwardResponse, ApiError> {
+    match node.forward(owner, request).await {
+        Ok(ForwardResponse::Error { status, message }) => Err(ApiError::new(
+            StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
+            message,
+        )),
+        Ok(response) => Ok(response),
+        Err(e) => {
+            warn!("Failed to forward a request to {}: {:#}", owner.addr, e);
+            Err(unexpected(owner))
+        }
+    }
+}
+
+// For an owner that can't be reached or answers with the wrong kind of
+// response
+pub fn unexpected(owner: &Member) -> ApiError {
+    ApiError::new(
+        StatusCode::BAD_GATEWAY,
+        format!(
+            "Couldn't get an answer from {}, which owns the collection",
+            owner.addr
+        ),
+    )
+}
+
+// Inserts `vectors` here, as their collections' owner, and copies them to
+// the collections' other replicas, waiting as long as `concern` asks for
+// but no longer than `timeout`
+pub async fn insert(
+    node: &NodeHandle,
+    placement: &Placement,
+    replicator: &Arc<Replicator>,
+    tenant: &Tenant,
+    vectors: Vec<Vector>,
+    concern: WriteConcern,
+    timeout: Duration,
+) -> Result<WriteReport, ApiError> {
+    let deadline = Instant::now() + timeout;
+    let ids = tenant.db.insert_vectors(vectors).await?;
+    let mut written: HashMap<String, Vec<Vector>> = HashMap::new();
+    let unique: HashSet<&String> = ids.iter().collect();
+    for id in unique {
+        if let Some(vector) = tenant.db.get_vector(id).await? {
+            let collection = vector.collection.clone().unwrap_or_default();
+            written.entry(collection).or_default().push(vector);
+        }
+    }
+    let mut report = WriteReport {
+        ids,
+        acknowledged: true,
+        failures: Vec::new(),
+    };
+    for (collection, upserts) in written {
+        let changes = ChangeSet {
+            upserts,
+            ..Default::default()
+        };
+        let (acknowledged, failures) = replicate(
+            node,
+            placement,
+            replicator,
+            &tenant.namespace,
+            &collection,
+            changes,
+            concern,
+            deadline,
+        )
+        .await?;
+        report.acknowledged &= acknowledged;
+        report.failures.extend(failures);
+    }
+    Ok(report)
+}
+
+// Deletes `id` here, as its collection's owner, and on the collection's
+// other replicas
+pub async fn delete(
+    node: &NodeHandle,
+    placement: &Placement,
+    replicator: &Arc<Replicator>,
+    tenant: &Tenant,
+    id: &str,
+) -> Result<bool, ApiError> {
+    let Some(vector) = tenant.db.get_vector(id).await? else {
+        return Ok(false);
+    };
+    if !tenant.db.delete_vector(id).await? {
+        return Ok(false);
+    }
+    let changes = ChangeSet {
+        deletes: vec![id.to_string()],
+        ..Default::default()
+    };
+    // Replicas that miss the delete are only warned about
+    let collection = vector.collection.unwrap_or_default();
+    replicate(
+        node,
+        placement,
+        replicator,
+        &tenant.namespace,
+        &collection,
+        changes,
+        WriteConcern::All,
+        Instant::now() + WRITE_TIMEOUT,
+    )
+    .await?;
+    Ok(true)
+}
+
+// A copy of `id` from the first other member that has one, to repair a
+// corrupt record with. Members that fail to answer are skipped.
+pub async fn fetch_copy(node: &NodeHandle, namespace: &str, id: &str) -> Result<Option<Vector>> {
+    for owner in other_owners(node).await? {
+        let request = ForwardRequest::Get {
+            namespace: namespace.to_string(),
+            id: id.to_string(),
+        };
+        if let Ok(ForwardResponse::Vector(Some(vector))) = forward(node, &owner, request).await {
+            return Ok(Some(*vector));
+        }
+    }
+    Ok(None)
+}
+
+// Asks every replica of `collection` for `id` and answers with the newest
+// copy. Replicas holding an older copy are sent the newest in the
+// background. Ones without any are left alone: they may have missed the
+// insert, or taken a delete the others missed.
+pub async fn get_from_replicas(
+    node: &NodeHandle,
+    replicas: Vec<Member>,
+    tenant: &Tenant,
+    id: &str,
+) -> Result<Option<Vector>, ApiError> {
+    let local_id = node.local_id();
+    let mut asked = tokio::task::JoinSet::new();
+    for replica in replicas {
+        let (node, db) = (node.clone(), Arc::clone(&tenant.db));
+        let (namespace, id) = (tenant.namespace.clone(), id.to_string());
+        let local = replica.id == local_id;
+        asked.spawn(async move {
+            if local {
+                let copy = db.get_vector(&id).await.map_err(ApiError::from);
+                return (replica, copy);
+            }
+            let copy = match forward(&node, &replica, ForwardRequest::Get { namespace, id }).await {
+                Ok(ForwardResponse::Vector(vector)) => Ok(vector.map(|vector| *vector)),
+                Ok(_) => Err(unexpected(&replica)),
+                Err(e) => Err(e),
+            };
+            (replica, copy)
+        });
+    }
+
+    let mut copies = Vec::new();
+    let mut error = None;
+    let mut answered = false;
+    while let Some(result) = asked.join_next().await {
+        let (replica, copy) = result.map_err(anyhow::Error::from)?;
+        match copy {
+            Ok(copy) => {
+                answered = true;
+                copies.extend(copy.map(|copy| (replica, copy)));
+            }
+            Err(e) => error = Some(e),
+        }
+    }
+    if !answered {
+        return Err(error.unwrap_or_else(|| ApiError::from(StatusCode::NOT_FOUND)));
+    }
+
+    let stamp = |vector: &Vector| (vector.hlc, vector.origin.clone(), vector.version);
+    let Some(newest) = copies
+        .iter()
+        .map(|(_, copy)| copy)
+        .max_by_key(|copy| stamp(copy))
+    else {
+        return Ok(None);
+    };
+    let newest = newest.clone();
+    let stale: Vec<Member> = copies
+        .into_iter()
+        .filter(|(_, copy)| stamp(copy) < stamp(&newest))
+        .map(|(replica, _)| replica)
+        .collect();
+    if !stale.is_empty() {
+        let changes = ChangeSet {
+            upserts: vec![newest.clone()],
+            ..Default::default()
+        };
+        tokio::spawn(repair(
+            node.clone(),
+            Arc::clone(&tenant.db),
+            tenant.namespace.clone(),
+            stale,
+            changes,
+        ));
+    }
+    Ok(Some(newest))
+}
+
+async fn repair(
+    node: NodeHandle,
+    db: Arc<skypier_core::VectorDatabase>,
+    namespace: String,
+    stale: Vec<Member>,
+    changes: ChangeSet,
+) {
+    let local_id = node.local_id();
+    let id = &changes.upserts[0].id;
+    for replica in stale {
+        let result = if replica.id == local_id {
+            db.apply_changes(&changes).await.map_err(ApiError::from)
+        } else {
+            let request = ForwardRequest::Replicate {
+                namespace: namespace.clone(),
+                changes: changes.clone(),
+            };
+            forward(&node, &replica, request).await.map(|_| ())
+        };
+        match result {
+            Ok(()) => info!(
+                "Read repair sent {} the newest copy of {}",
+                replica.addr, id
+            ),
+            Err(e) => warn!(
+                "Read repair of {} on {} failed: {}",
+                id, replica.addr, e.message
+            ),
+        }
+    }
+}
+
+// Sends writes this node made to the other replicas of `collection` and
+// waits until as many have them as `concern` asks for, or `deadline`. The
+// others still get them in the background. Returns whether enough did,
+// and the replicas that failed or hadn't answered by then.
+#[allow(clippy::too_many_arguments)]
+async fn replicate(
+    node: &NodeHandle,
+    placement: &Placement,
+    replicator: &Arc<Replicator>,
+    namespace: &str,
+    collection: &str,
+    changes: ChangeSet,
+    concern: WriteConcern,
+    deadline: Instant,
+) -> Result<(bool, Vec<ReplicaFailure>), ApiError> {
+    let local_id = node.local_id();
+    let others: Vec<Member> = replicas(node, placement, Some(collection))
+        .await?
+        .into_iter()
+        .filter(|replica| replica.id != local_id)
+        .collect();
+    let required = concern.required(others.len());
+    let (sent_tx, mut sent) = mpsc::unbounded_channel();
+    for replica in others.clone() {
+        let (node, replicator, sent_tx) = (node.clone(), Arc::clone(replicator), sent_tx.clone());
+        let (namespace, collection, changes) = (
+            namespace.to_string(),
+            collection.to_string(),
+            changes.clone(),
+        );
+        tokio::spawn(async move {
+            let result = replicator.send(&node, &replica, &namespace, changes).await;
+            if let Err(e) = &result {
+                warn!(
+                    "Replica {} of '{}' missed writes: {}",
+                    replica.addr, collection, e
+                );
+            }
+            let _ = sent_tx.send((replica, result));
+        });
+    }
+    drop(sent_tx);
+
+    let mut acknowledged = 0;
+    let mut answered = HashSet::new();
+    let mut failures = Vec::new();
+    let failure = |replica: &Member, error: String| ReplicaFailure {
+        collection: collection.to_string(),
+        replica: replica.addr.clone(),
+        error,
+    };
+    while acknowledged < required {
+        match tokio::time::timeout_at(deadline, sent.recv()).await {
+            Ok(Some((replica, result))) => {
+                answered.insert(replica.id.clone());
+                match result {
+                    Ok(()) => acknowledged += 1,
+                    Err(e) => failures.push(failure(&replica, e)),
+                }
+            }
+            Ok(None) => break,
+            Err(_) => {
+                for replica in others
+                    .iter()
+                    .filter(|replica| !answered.contains(&replica.id))
+                {
+                    let error = "No acknowledgment within the write timeout".to_string();
+                    failures.push(failure(replica, error));
+                }
+                break;
+            }
+        }
+    }
+    Ok((acknowledged >= required, failures))
+}
+
+// Applies requests other nodes forward to this one. They're never
+// forwarded again, so members disagreeing about an owner can't bounce a
+// request around.
+pub struct ForwardTarget {
+    namespaces: Arc<Namespaces>,
+    node: NodeHandle,
+    placement: Arc<Placement>,
+    replicator: Arc<Replicator>,
+}
+
+impl ForwardTarget {
+    pub fn new(
+        namespaces: Arc<Namespaces>,
+        node: NodeHandle,
+        placement: Arc<Placement>,
+        replicator: Arc<Replicator>,
+    ) -> Self {
+        Self {
+            namespaces,
+            node,
+            placement,
+            replicator,
+        }
+    }
+
+    async fn apply(&self, request: ForwardRequest) -> Result<ForwardResponse, ApiError> {
+        match request {
+            ForwardRequest::Insert {
+                namespace,
+                vectors,
+                write_concern,
+                write_timeout_ms,
+            } => {
+                let tenant = Tenant::open(&self.namespaces, namespace).await?;
+                let report = insert(
+                    &self.node,
+                    &self.placement,
+                    &self.replicator,
+                    &tenant,
+                    vectors,
+                    write_concern,
+                    Duration::from_millis(write_timeout_ms),
+                )
+                .await?;
+                Ok(ForwardResponse::Inserted(report))
+            }
+            ForwardRequest::Get { namespace, id } => {
+                let tenant = Tenant::open(&self.namespaces, namespace).await?;
+                let vector = tenant.db.get_vector(&id).await?;
+                Ok(ForwardResponse::Vector(vector.map(Box::new)))
+            }
+            ForwardRequest::Delete { namespace, id } => {
+                let tenant = Tenant::open(&self.namespaces, namespace).await?;
+                let deleted =
+                    delete(&self.node, &self.placement, &self.replicator, &tenant, &id).await?;
+                Ok(ForwardResponse::Deleted(deleted))
+            }
+            ForwardRequest::Replicate { namespace, changes } => {
+                let tenant = Tenant::open(&self.namespaces, namespace).await?;
+                tenant.db.apply_changes(&changes).await?;
+                Ok(ForwardResponse::Replicated)
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl ForwardHandler for ForwardTarget {
+    async fn handle(&self, request: ForwardRequest) -> ForwardResponse {
+        match self.apply(request).await {
+            Ok(response) => response,
+            Err(e) => ForwardResponse::Error {
+                status: e.status.as_u16(),
+                message: e.message,
+            },
+        }
+    }
+}
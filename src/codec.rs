@@ -0,0 +1,176 @@
+use axum::{
+    async_trait,
+    body::Bytes,
+    extract::{FromRequest, Request},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::api::ApiError;
+
+// Body encodings the insert and search endpoints speak besides JSON, which
+// spare high-throughput clients formatting and parsing floats as text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Json,
+    MsgPack,
+    Cbor,
+}
+
+impl Encoding {
+    // From a Content-Type or one entry of an Accept header
+    fn from_mime(mime: &str) -> Option<Self> {
+        let essence = mime.split(';').next()?.trim().to_ascii_lowercase();
+        match essence.as_str() {
+            "application/json" => Some(Self::Json),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                Some(Self::MsgPack)
+            }
+            "application/cbor" => Some(Self::Cbor),
+            _ if essence.ends_with("+json") => Some(Self::Json),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::MsgPack => "application/msgpack",
+            Self::Cbor => "application/cbor",
+        }
+    }
+
+    // The first encoding the Accept header names that's supported, if any
+    fn accepted(headers: &HeaderMap) -> Option<Self> {
+        headers
+            .get_all(header::ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .find_map(Self::from_mime)
+    }
+
+    pub fn encode<T: Serialize>(self, value: &T) -> anyhow::Result<Vec<u8>> {
+        Ok(match self {
+            Self::Json => serde_json::to_vec(value)?,
+            // As maps, so fields skipped when empty don't shift the rest
+            Self::MsgPack => rmp_serde::to_vec_named(value)?,
+            Self::Cbor => {
+                let mut bytes = Vec::new();
+                ciborium::into_writer(value, &mut bytes)?;
+                bytes
+            }
+        })
+    }
+
+    pub fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> anyhow::Result<T> {
+        Ok(match self {
+            Self::Json => serde_json::from_slice(bytes)?,
+            Self::MsgPack => rmp_serde::from_slice(bytes)?,
+            Self::Cbor => ciborium::from_reader(bytes)?,
+        })
+    }
+}
+
+// Like `Json`, for a body in any `Encoding`, picked by its Content-Type.
+// Extracted, the second field is the encoding to answer in: the first one
+// the Accept header names, else the request's own.
+pub struct Encoded<T>(pub T, pub Encoding);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for Encoded<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Response> {
+        let content_type = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(Encoding::from_mime);
+        let accepted = Encoding::accepted(req.headers());
+        match content_type {
+            Some(Encoding::Json) | None => {
+                // JSON bodies get the same checks and rejections as `Json`
+                let Json(value) = Json::<T>::from_request(req, state)
+                    .await
+                    .map_err(IntoResponse::into_response)?;
+                Ok(Self(value, accepted.unwrap_or(Encoding::Json)))
+            }
+            Some(encoding) => {
+                let bytes = Bytes::from_request(req, state)
+                    .await
+                    .map_err(IntoResponse::into_response)?;
+                let value = encoding.decode(&bytes).map_err(|e| {
+                    ApiError::new(
+                        StatusCode::UNPROCESSABLE_ENTITY,
+                        format!("Failed to parse the request body: {}", e),
+                    )
+                    .into_response()
+                })?;
+                Ok(Self(value, accepted.unwrap_or(encoding)))
+            }
+        }
+    }
+}
+
+impl<T: Serialize> IntoResponse for Encoded<T> {
+    fn into_response(self) -> Response {
+        let Self(value, encoding) = self;
+        match encoding.encode(&value) {
+            Ok(bytes) => (
+                [(
+                    header::CONTENT_TYPE,
+                    HeaderValue::from_static(encoding.content_type()),
+                )],
+                bytes,
+            )
+                .into_response(),
+            Err(e) => ApiError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to encode the response: {}", e),
+            )
+            .into_response(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encodings_round_trip() {
+        let value = serde_json::json!({"vector": [0.5, -1.25, 3.0], "k": 2});
+        for encoding in [Encoding::Json, Encoding::MsgPack, Encoding::Cbor] {
+            let bytes = encoding.encode(&value).unwrap();
+            let decoded: serde_json::Value = encoding.decode(&bytes).unwrap();
+            assert_eq!(decoded, value);
+        }
+    }
+
+    #[test]
+    fn test_encoding_from_headers() {
+        assert_eq!(
+            Encoding::from_mime("application/msgpack; charset=binary"),
+            Some(Encoding::MsgPack)
+        );
+        assert_eq!(
+            Encoding::from_mime("application/problem+json"),
+            Some(Encoding::Json)
+        );
+        assert_eq!(Encoding::from_mime("text/plain"), None);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::ACCEPT,
+            HeaderValue::from_static("text/html, application/cbor;q=0.9, */*"),
+        );
+        assert_eq!(Encoding::accepted(&headers), Some(Encoding::Cbor));
+        assert_eq!(Encoding::accepted(&HeaderMap::new()), None);
+    }
+}
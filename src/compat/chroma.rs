@@ -0,0 +1,547 @@
+use axum::{
+    extract::Path,
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use skypier_core::{SearchFilter, Vector, VectorDatabase};
+use std::collections::HashMap;
+
+use super::{bad_request, load_setting, save_setting, stored_value, to_fields, to_metadata};
+use crate::api::{ApiError, AppState, Tenant};
+
+// Where a record's document is kept in its metadata
+const DOCUMENT_KEY: &str = "_document";
+
+const MAX_BATCH_SIZE: usize = 1000;
+
+// Chroma's v1 REST API, served under /api/v1 with the single default tenant
+// and database. Embeddings have to be computed by the client, which is what
+// its embedding functions do. Distances are cosine distances.
+pub fn chroma_routes() -> Router<AppState> {
+    Router::new()
+        .route("/heartbeat", get(heartbeat))
+        .route("/version", get(version))
+        .route("/pre-flight-checks", get(pre_flight_checks))
+        .route("/tenants/:tenant", get(tenant))
+        .route("/databases/:database", get(database))
+        .route(
+            "/collections",
+            get(list_collections).post(create_collection),
+        )
+        .route(
+            "/collections/:collection",
+            get(get_collection).delete(delete_collection),
+        )
+        .route("/collections/:collection/add", post(add))
+        .route("/collections/:collection/upsert", post(upsert))
+        .route("/collections/:collection/get", post(get_records))
+        .route("/collections/:collection/query", post(query))
+        .route("/collections/:collection/delete", post(delete_records))
+        .route("/collections/:collection/count", get(count))
+}
+
+// What a client created a collection with, under its name. Record routes
+// address collections by id, which maps back to the name.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CollectionRecord {
+    id: String,
+    #[serde(default)]
+    metadata: Option<Map<String, Value>>,
+}
+
+fn record_key(name: &str) -> String {
+    format!("chroma:collection:{}", name)
+}
+
+fn id_key(id: &str) -> String {
+    format!("chroma:collection_id:{}", id)
+}
+
+fn collection_json(name: &str, record: &CollectionRecord) -> Value {
+    json!({
+        "id": record.id,
+        "name": name,
+        "metadata": record.metadata,
+        "tenant": "default_tenant",
+        "database": "default_database",
+    })
+}
+
+fn not_found(collection: &str) -> ApiError {
+    ApiError::new(
+        StatusCode::NOT_FOUND,
+        format!("Collection {} does not exist.", collection),
+    )
+}
+
+async fn register(
+    db: &VectorDatabase,
+    name: &str,
+    metadata: Option<Map<String, Value>>,
+) -> Result<CollectionRecord, ApiError> {
+    let record = CollectionRecord {
+        id: uuid::Uuid::new_v4().to_string(),
+        metadata,
+    };
+    save_setting(db, &id_key(&record.id), Some(&name)).await?;
+    save_setting(db, &record_key(name), Some(&record)).await?;
+    Ok(record)
+}
+
+// Collections holding vectors from the native API are registered the first
+// time they're asked for
+async fn find_collection(
+    db: &VectorDatabase,
+    name: &str,
+) -> Result<Option<CollectionRecord>, ApiError> {
+    if let Some(record) = load_setting(db, &record_key(name)).await? {
+        return Ok(Some(record));
+    }
+    match db.collection_stats(name).await? {
+        Some(_) => Ok(Some(register(db, name, None).await?)),
+        None => Ok(None),
+    }
+}
+
+async fn collection_name(db: &VectorDatabase, id: &str) -> Result<String, ApiError> {
+    load_setting(db, &id_key(id))
+        .await?
+        .ok_or_else(|| not_found(id))
+}
+
+async fn heartbeat() -> Json<Value> {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    Json(json!({ "nanosecond heartbeat": nanos }))
+}
+
+// The server version the clients are written against
+async fn version() -> Json<&'static str> {
+    Json("0.4.24")
+}
+
+// Clients split larger writes into batches of this size, which keeps them
+// under the default body limit at typical dimensions
+async fn pre_flight_checks() -> Json<Value> {
+    Json(json!({ "max_batch_size": MAX_BATCH_SIZE }))
+}
+
+async fn tenant(Path(tenant): Path<String>) -> Json<Value> {
+    Json(json!({ "name": tenant }))
+}
+
+async fn database(Path(database): Path<String>) -> Json<Value> {
+    Json(json!({
+        "id": uuid::Uuid::nil(),
+        "name": database,
+        "tenant": "default_tenant",
+    }))
+}
+
+async fn list_collections(Tenant { db, .. }: Tenant) -> Result<Json<Vec<Value>>, ApiError> {
+    let mut collections = Vec::new();
+    for name in db.list_collections().await? {
+        if let Some(record) = find_collection(&db, &name).await? {
+            collections.push(collection_json(&name, &record));
+        }
+    }
+    Ok(Json(collections))
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateCollection {
+    name: String,
+    #[serde(default)]
+    metadata: Option<Map<String, Value>>,
+    #[serde(default)]
+    get_or_create: bool,
+}
+
+async fn create_collection(
+    Tenant { db, .. }: Tenant,
+    Json(payload): Json<CreateCollection>,
+) -> Result<Json<Value>, ApiError> {
+    if let Some(record) = find_collection(&db, &payload.name).await? {
+        if !payload.get_or_create {
+            return Err(ApiError::new(
+                StatusCode::CONFLICT,
+                format!("Collection {} already exists.", payload.name),
+            ));
+        }
+        return Ok(Json(collection_json(&payload.name, &record)));
+    }
+    let record = register(&db, &payload.name, payload.metadata).await?;
+    Ok(Json(collection_json(&payload.name, &record)))
+}
+
+async fn get_collection(
+    Tenant { db, .. }: Tenant,
+    Path(name): Path<String>,
+) -> Result<Json<Value>, ApiError> {
+    let record = find_collection(&db, &name)
+        .await?
+        .ok_or_else(|| not_found(&name))?;
+    Ok(Json(collection_json(&name, &record)))
+}
+
+async fn delete_collection(
+    Tenant { db, .. }: Tenant,
+    Path(name): Path<String>,
+) -> Result<Json<Value>, ApiError> {
+    let record = find_collection(&db, &name)
+        .await?
+        .ok_or_else(|| not_found(&name))?;
+    db.delete_collection(&name).await?;
+    save_setting::<String>(&db, &id_key(&record.id), None).await?;
+    save_setting::<CollectionRecord>(&db, &record_key(&name), None).await?;
+    Ok(Json(Value::Null))
+}
+
+#[derive(Debug, Deserialize)]
+struct Records {
+    ids: Vec<String>,
+    #[serde(default)]
+    embeddings: Option<Vec<Vec<f32>>>,
+    #[serde(default)]
+    metadatas: Option<Vec<Option<Map<String, Value>>>>,
+    #[serde(default)]
+    documents: Option<Vec<Option<String>>>,
+}
+
+fn to_vectors(collection: &str, records: Records) -> Result<Vec<Vector>, ApiError> {
+    let embeddings = records.embeddings.ok_or_else(|| {
+        bad_request("Embeddings are required; compute them with an embedding function")
+    })?;
+    if embeddings.len() != records.ids.len() {
+        return Err(bad_request(format!(
+            "Got {} embeddings for {} ids",
+            embeddings.len(),
+            records.ids.len()
+        )));
+    }
+    let metadatas = records.metadatas.unwrap_or_default();
+    let documents = records.documents.unwrap_or_default();
+    Ok(records
+        .ids
+        .into_iter()
+        .zip(embeddings)
+        .enumerate()
+        .map(|(i, (id, embedding))| {
+            let mut metadata = metadatas
+                .get(i)
+                .cloned()
+                .flatten()
+                .and_then(to_metadata)
+                .unwrap_or_default();
+            if let Some(document) = documents.get(i).cloned().flatten() {
+                metadata.insert(DOCUMENT_KEY.to_string(), document);
+            }
+            let mut vector = Vector::with_id(id, embedding).with_collection(collection.to_string());
+            vector.metadata = (!metadata.is_empty()).then_some(metadata);
+            vector
+        })
+        .collect())
+}
+
+// Adding leaves records that already exist alone; upserting replaces them
+async fn add(
+    tenant: Tenant,
+    Path(id): Path<String>,
+    Json(payload): Json<Records>,
+) -> Result<Json<bool>, ApiError> {
+    let collection = collection_name(&tenant.db, &id).await?;
+    let mut vectors = Vec::new();
+    for vector in to_vectors(&collection, payload)? {
+        if tenant.db.get_vector(&vector.id).await?.is_none() {
+            vectors.push(vector);
+        }
+    }
+    tenant.db.insert_vectors(vectors).await?;
+    Ok(Json(true))
+}
+
+async fn upsert(
+    tenant: Tenant,
+    Path(id): Path<String>,
+    Json(payload): Json<Records>,
+) -> Result<Json<bool>, ApiError> {
+    let collection = collection_name(&tenant.db, &id).await?;
+    let vectors = to_vectors(&collection, payload)?;
+    tenant.db.insert_vectors(vectors).await?;
+    Ok(Json(true))
+}
+
+// Only exact matches on metadata fields, `{"k": v}` or `{"k": {"$eq": v}}`,
+// combined with `$and`
+fn where_filter(clause: Option<Value>) -> Result<HashMap<String, String>, ApiError> {
+    fn add(clause: Value, filter: &mut HashMap<String, String>) -> Result<(), ApiError> {
+        let unsupported = || bad_request("Only $eq and $and where clauses are supported");
+        let Value::Object(clause) = clause else {
+            return Err(unsupported());
+        };
+        for (key, value) in clause {
+            match (key.as_str(), value) {
+                ("$and", Value::Array(clauses)) => {
+                    for clause in clauses {
+                        add(clause, filter)?;
+                    }
+                }
+                (key, _) if key.starts_with('$') => return Err(unsupported()),
+                (key, Value::Object(operator)) => {
+                    let value = match operator.get("$eq") {
+                        Some(value) if operator.len() == 1 => value,
+                        _ => return Err(unsupported()),
+                    };
+                    filter.insert(key.to_string(), stored_value(value));
+                }
+                (key, value) => {
+                    filter.insert(key.to_string(), stored_value(&value));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    let mut filter = HashMap::new();
+    if let Some(clause) = clause.filter(|clause| !clause.is_null()) {
+        add(clause, &mut filter)?;
+    }
+    Ok(filter)
+}
+
+fn check_where_document(clause: &Option<Value>) -> Result<(), ApiError> {
+    match clause {
+        Some(Value::Object(clause)) if !clause.is_empty() => {
+            Err(bad_request("where_document is not supported"))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn matches(vector: &Vector, filter: &HashMap<String, String>) -> bool {
+    filter.iter().all(|(key, value)| {
+        vector
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.get(key))
+            == Some(value)
+    })
+}
+
+// A response's columns, one entry per record
+#[derive(Default)]
+struct Columns {
+    ids: Vec<String>,
+    embeddings: Vec<Vec<f32>>,
+    metadatas: Vec<Value>,
+    documents: Vec<Option<String>>,
+}
+
+impl Columns {
+    fn push(&mut self, vector: Vector) {
+        let metadata = vector.metadata.unwrap_or_default();
+        let fields = to_fields(&metadata);
+        self.metadatas
+            .push((!fields.is_empty()).then_some(Value::Object(fields)).into());
+        self.documents.push(metadata.get(DOCUMENT_KEY).cloned());
+        self.embeddings.push(vector.data);
+        self.ids.push(vector.id);
+    }
+}
+
+// Columns that weren't asked for are null
+fn response(include: &[String], columns: [(&str, Value); 5]) -> Json<Value> {
+    let mut body = Map::new();
+    for (name, values) in columns {
+        let wanted = name == "ids" || include.iter().any(|included| included == name);
+        body.insert(name.to_string(), if wanted { values } else { Value::Null });
+    }
+    body.insert("uris".to_string(), Value::Null);
+    body.insert("data".to_string(), Value::Null);
+    body.insert("included".to_string(), json!(include));
+    Json(Value::Object(body))
+}
+
+#[derive(Debug, Deserialize)]
+struct GetRecords {
+    #[serde(default)]
+    ids: Option<Vec<String>>,
+    #[serde(rename = "where", default)]
+    where_clause: Option<Value>,
+    #[serde(default)]
+    where_document: Option<Value>,
+    #[serde(default)]
+    limit: Option<usize>,
+    #[serde(default)]
+    offset: Option<usize>,
+    #[serde(default = "default_get_include")]
+    include: Vec<String>,
+}
+
+fn default_get_include() -> Vec<String> {
+    vec!["metadatas".to_string(), "documents".to_string()]
+}
+
+// The collection's records, or the ones asked for, that match the filter
+async fn select(
+    db: &VectorDatabase,
+    collection: &str,
+    ids: Option<Vec<String>>,
+    filter: &HashMap<String, String>,
+) -> Result<Vec<Vector>, ApiError> {
+    let vectors = match ids {
+        Some(ids) => {
+            let mut vectors = Vec::with_capacity(ids.len());
+            for id in ids {
+                if let Some(vector) = db.get_vector(&id).await? {
+                    vectors.push(vector);
+                }
+            }
+            vectors
+        }
+        None => db.list_vectors(Some(collection)).await?,
+    };
+    Ok(vectors
+        .into_iter()
+        .filter(|vector| vector.collection.as_deref() == Some(collection))
+        .filter(|vector| matches(vector, filter))
+        .collect())
+}
+
+async fn get_records(
+    Tenant { db, .. }: Tenant,
+    Path(id): Path<String>,
+    Json(payload): Json<GetRecords>,
+) -> Result<Json<Value>, ApiError> {
+    let collection = collection_name(&db, &id).await?;
+    check_where_document(&payload.where_document)?;
+    let filter = where_filter(payload.where_clause)?;
+    let vectors = select(&db, &collection, payload.ids, &filter).await?;
+
+    let mut columns = Columns::default();
+    for vector in vectors
+        .into_iter()
+        .skip(payload.offset.unwrap_or(0))
+        .take(payload.limit.unwrap_or(usize::MAX))
+    {
+        columns.push(vector);
+    }
+    Ok(response(
+        &payload.include,
+        [
+            ("ids", json!(columns.ids)),
+            ("embeddings", json!(columns.embeddings)),
+            ("metadatas", json!(columns.metadatas)),
+            ("documents", json!(columns.documents)),
+            ("distances", Value::Null),
+        ],
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+struct Query {
+    query_embeddings: Vec<Vec<f32>>,
+    #[serde(default = "default_n_results")]
+    n_results: usize,
+    #[serde(rename = "where", default)]
+    where_clause: Option<Value>,
+    #[serde(default)]
+    where_document: Option<Value>,
+    #[serde(default = "default_query_include")]
+    include: Vec<String>,
+}
+
+fn default_n_results() -> usize {
+    10
+}
+
+fn default_query_include() -> Vec<String> {
+    vec![
+        "metadatas".to_string(),
+        "documents".to_string(),
+        "distances".to_string(),
+    ]
+}
+
+// One list of results per query embedding
+async fn query(
+    Tenant { db, .. }: Tenant,
+    Path(id): Path<String>,
+    Json(payload): Json<Query>,
+) -> Result<Json<Value>, ApiError> {
+    let collection = collection_name(&db, &id).await?;
+    check_where_document(&payload.where_document)?;
+    let filter = SearchFilter {
+        collection: Some(collection),
+        metadata: where_filter(payload.where_clause)?,
+    };
+
+    let (mut ids, mut embeddings, mut metadatas, mut documents, mut distances) =
+        (Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    for embedding in &payload.query_embeddings {
+        let results = db
+            .search_filtered(embedding, payload.n_results, f32::MIN, &filter)
+            .await?;
+        let mut columns = Columns::default();
+        let mut scores = Vec::with_capacity(results.len());
+        for result in results {
+            if let Some(vector) = db.get_vector(&result.id).await? {
+                scores.push(1.0 - result.score);
+                columns.push(vector);
+            }
+        }
+        ids.push(columns.ids);
+        embeddings.push(columns.embeddings);
+        metadatas.push(columns.metadatas);
+        documents.push(columns.documents);
+        distances.push(scores);
+    }
+    Ok(response(
+        &payload.include,
+        [
+            ("ids", json!(ids)),
+            ("embeddings", json!(embeddings)),
+            ("metadatas", json!(metadatas)),
+            ("documents", json!(documents)),
+            ("distances", json!(distances)),
+        ],
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+struct DeleteRecords {
+    #[serde(default)]
+    ids: Option<Vec<String>>,
+    #[serde(rename = "where", default)]
+    where_clause: Option<Value>,
+    #[serde(default)]
+    where_document: Option<Value>,
+}
+
+async fn delete_records(
+    Tenant { db, .. }: Tenant,
+    Path(id): Path<String>,
+    Json(payload): Json<DeleteRecords>,
+) -> Result<Json<Vec<String>>, ApiError> {
+    let collection = collection_name(&db, &id).await?;
+    check_where_document(&payload.where_document)?;
+    let filter = where_filter(payload.where_clause)?;
+    let mut deleted = Vec::new();
+    for vector in select(&db, &collection, payload.ids, &filter).await? {
+        if db.delete_vector(&vector.id).await? {
+            deleted.push(vector.id);
+        }
+    }
+    Ok(Json(deleted))
+}
+
+async fn count(Tenant { db, .. }: Tenant, Path(id): Path<String>) -> Result<Json<u64>, ApiError> {
+    let collection = collection_name(&db, &id).await?;
+    let stats = db.collection_stats(&collection).await?;
+    Ok(Json(stats.map_or(0, |stats| stats.vector_count)))
+}
@@ -0,0 +1,119 @@
+use axum::http::StatusCode;
+use serde_json::{Map, Value};
+use skypier_core::VectorDatabase;
+use std::collections::HashMap;
+
+use crate::api::ApiError;
+
+pub mod chroma;
+pub mod qdrant;
+
+pub use chroma::chroma_routes;
+pub use qdrant::qdrant_routes;
+
+// Routes speaking other databases' client protocols, so apps built on them
+// only need a new URL. Their collections are skypier collections; what the
+// clients create them with is kept in the database's settings.
+
+// Metadata only holds strings. Other JSON values are stored as their JSON
+// text, and this key lists which ones so they come back as they went in.
+const JSON_FIELDS_KEY: &str = "_json_fields";
+
+fn to_metadata(fields: Map<String, Value>) -> Option<HashMap<String, String>> {
+    let mut metadata = HashMap::new();
+    let mut json_fields = Vec::new();
+    for (key, value) in fields {
+        match value {
+            Value::Null => {}
+            Value::String(value) => {
+                metadata.insert(key, value);
+            }
+            other => {
+                metadata.insert(key.clone(), other.to_string());
+                json_fields.push(key);
+            }
+        }
+    }
+    if !json_fields.is_empty() {
+        json_fields.sort();
+        metadata.insert(
+            JSON_FIELDS_KEY.to_string(),
+            Value::from(json_fields).to_string(),
+        );
+    }
+    (!metadata.is_empty()).then_some(metadata)
+}
+
+// The reverse of `to_metadata`, leaving out keys starting with `_`
+fn to_fields(metadata: &HashMap<String, String>) -> Map<String, Value> {
+    let json_fields: Vec<String> = metadata
+        .get(JSON_FIELDS_KEY)
+        .and_then(|fields| serde_json::from_str(fields).ok())
+        .unwrap_or_default();
+    metadata
+        .iter()
+        .filter(|(key, _)| !key.starts_with('_'))
+        .map(|(key, value)| {
+            let value = match json_fields.contains(key) {
+                true => serde_json::from_str(value).unwrap_or_else(|_| Value::from(value.as_str())),
+                false => Value::from(value.as_str()),
+            };
+            (key.clone(), value)
+        })
+        .collect()
+}
+
+// A value as `to_metadata` stores it, for exact-match filters
+fn stored_value(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        other => other.to_string(),
+    }
+}
+
+// Settings hold JSON; `null` is how a deleted entry is left
+async fn load_setting<T: serde::de::DeserializeOwned>(
+    db: &VectorDatabase,
+    key: &str,
+) -> Result<Option<T>, ApiError> {
+    match db.get_setting(key).await? {
+        Some(json) => Ok(serde_json::from_str(&json).map_err(anyhow::Error::from)?),
+        None => Ok(None),
+    }
+}
+
+async fn save_setting<T: serde::Serialize>(
+    db: &VectorDatabase,
+    key: &str,
+    value: Option<&T>,
+) -> Result<(), ApiError> {
+    let json = serde_json::to_string(&value).map_err(anyhow::Error::from)?;
+    Ok(db.put_setting(key, &json).await?)
+}
+
+fn bad_request(message: impl Into<String>) -> ApiError {
+    ApiError::new(StatusCode::BAD_REQUEST, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_metadata_round_trips_json_values() {
+        let fields = json!({"source": "a.txt", "page": 3, "nested": {"tags": ["x"]}, "gone": null});
+        let Value::Object(fields) = fields else {
+            unreachable!()
+        };
+        let metadata = to_metadata(fields).unwrap();
+        assert_eq!(metadata.get("page").unwrap(), "3");
+        assert!(!metadata.contains_key("gone"));
+        assert_eq!(
+            Value::Object(to_fields(&metadata)),
+            json!({"source": "a.txt", "page": 3, "nested": {"tags": ["x"]}})
+        );
+        assert_eq!(stored_value(&json!(3)), "3");
+        assert!(to_metadata(Map::new()).is_none());
+    }
+}
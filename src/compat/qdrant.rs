@@ -0,0 +1,519 @@
+use axum::{
+    extract::Path,
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use skypier_core::{DistanceMetric, SearchFilter, Vector, VectorDatabase};
+use std::collections::HashMap;
+use std::time::Instant;
+
+use super::{bad_request, load_setting, save_setting, stored_value, to_fields, to_metadata};
+use crate::api::{ApiError, AppState, Tenant};
+
+// Qdrant's REST API, served under /qdrant: enough of it for the official
+// clients and LangChain to create collections and upsert, fetch, delete
+// and search points. Point ids are unsigned integers or UUIDs; payloads
+// are stored as metadata, and only top-level `match` filters are
+// supported.
+pub fn qdrant_routes() -> Router<AppState> {
+    Router::new()
+        .route("/collections", get(list_collections))
+        .route(
+            "/collections/:collection",
+            get(collection_info)
+                .put(create_collection)
+                .delete(delete_collection),
+        )
+        .route("/collections/:collection/exists", get(collection_exists))
+        .route(
+            "/collections/:collection/points",
+            axum::routing::put(upsert_points).post(retrieve_points),
+        )
+        .route(
+            "/collections/:collection/points/delete",
+            post(delete_points),
+        )
+        .route(
+            "/collections/:collection/points/search",
+            post(search_points),
+        )
+        .route("/collections/:collection/points/query", post(query_points))
+}
+
+// How a client created a collection
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CollectionConfig {
+    size: usize,
+    distance: String,
+}
+
+fn config_key(collection: &str) -> String {
+    format!("qdrant:collection:{}", collection)
+}
+
+// Qdrant wraps every response the same way
+fn respond(result: impl Serialize, started: Instant) -> Json<Value> {
+    Json(json!({
+        "result": result,
+        "status": "ok",
+        "time": started.elapsed().as_secs_f64(),
+    }))
+}
+
+fn not_found(collection: &str) -> ApiError {
+    ApiError::new(
+        StatusCode::NOT_FOUND,
+        format!("Not found: Collection `{}` doesn't exist!", collection),
+    )
+}
+
+// Numeric ids go back out as numbers
+fn point_id(id: &str) -> Value {
+    match id.parse::<u64>() {
+        Ok(id) => Value::from(id),
+        Err(_) => Value::from(id),
+    }
+}
+
+fn stored_id(id: &Value) -> Result<String, ApiError> {
+    match id {
+        Value::Number(number) if number.is_u64() => Ok(number.to_string()),
+        Value::String(id) if uuid::Uuid::parse_str(id).is_ok() => Ok(id.clone()),
+        other => Err(bad_request(format!(
+            "Point ids must be unsigned integers or UUIDs, not {}",
+            other
+        ))),
+    }
+}
+
+fn dense_vector(vector: Value) -> Result<Vec<f32>, ApiError> {
+    serde_json::from_value(vector)
+        .map_err(|_| bad_request("Only unnamed dense vectors are supported"))
+}
+
+async fn collection_config(
+    db: &VectorDatabase,
+    collection: &str,
+) -> Result<Option<CollectionConfig>, ApiError> {
+    load_setting(db, &config_key(collection)).await
+}
+
+// Created through this API, or holding vectors from any other
+async fn exists(db: &VectorDatabase, collection: &str) -> Result<bool, ApiError> {
+    Ok(collection_config(db, collection).await?.is_some()
+        || db.collection_stats(collection).await?.is_some())
+}
+
+async fn list_collections(Tenant { db, .. }: Tenant) -> Result<Json<Value>, ApiError> {
+    let started = Instant::now();
+    let collections: Vec<Value> = db
+        .list_collections()
+        .await?
+        .into_iter()
+        .map(|name| json!({ "name": name }))
+        .collect();
+    Ok(respond(json!({ "collections": collections }), started))
+}
+
+async fn collection_exists(
+    Tenant { db, .. }: Tenant,
+    Path(collection): Path<String>,
+) -> Result<Json<Value>, ApiError> {
+    let started = Instant::now();
+    let exists = exists(&db, &collection).await?;
+    Ok(respond(json!({ "exists": exists }), started))
+}
+
+async fn collection_info(
+    Tenant { db, .. }: Tenant,
+    Path(collection): Path<String>,
+) -> Result<Json<Value>, ApiError> {
+    let started = Instant::now();
+    let config = collection_config(&db, &collection).await?;
+    let stats = db.collection_stats(&collection).await?;
+    if config.is_none() && stats.is_none() {
+        return Err(not_found(&collection));
+    }
+    let points = stats.as_ref().map_or(0, |stats| stats.vector_count);
+    let config = config.unwrap_or_else(|| CollectionConfig {
+        size: stats.as_ref().map_or(0, |stats| stats.dimensions),
+        distance: "Cosine".to_string(),
+    });
+
+    // The fields the clients' models require
+    Ok(respond(
+        json!({
+            "status": "green",
+            "optimizer_status": "ok",
+            "vectors_count": points,
+            "indexed_vectors_count": points,
+            "points_count": points,
+            "segments_count": 1,
+            "config": {
+                "params": {
+                    "vectors": { "size": config.size, "distance": config.distance },
+                    "shard_number": 1,
+                    "replication_factor": 1,
+                    "write_consistency_factor": 1,
+                    "on_disk_payload": true,
+                },
+                "hnsw_config": { "m": 16, "ef_construct": 200, "full_scan_threshold": 10000 },
+                "optimizer_config": {
+                    "deleted_threshold": 0.2,
+                    "vacuum_min_vector_number": 1000,
+                    "default_segment_number": 0,
+                    "flush_interval_sec": 5,
+                },
+                "wal_config": { "wal_capacity_mb": 32, "wal_segments_ahead": 0 },
+            },
+            "payload_schema": {},
+        }),
+        started,
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateCollection {
+    vectors: Value,
+}
+
+async fn create_collection(
+    Tenant { db, .. }: Tenant,
+    Path(collection): Path<String>,
+    Json(payload): Json<CreateCollection>,
+) -> Result<Json<Value>, ApiError> {
+    let started = Instant::now();
+    let config: CollectionConfig = serde_json::from_value(payload.vectors)
+        .map_err(|_| bad_request("Only a single unnamed vector config is supported"))?;
+    let metric = match config.distance.as_str() {
+        "Cosine" => DistanceMetric::Cosine,
+        "Euclid" => DistanceMetric::Euclidean,
+        "Dot" => DistanceMetric::DotProduct,
+        other => {
+            return Err(bad_request(format!(
+                "Distance {} is not supported, only Cosine, Euclid and Dot",
+                other
+            )))
+        }
+    };
+    if exists(&db, &collection).await? {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            format!("Wrong input: Collection `{}` already exists!", collection),
+        ));
+    }
+    save_setting(&db, &config_key(&collection), Some(&config)).await?;
+    // Euclid scores come back as 1 / (1 + distance) rather than the distance
+    db.set_collection_metric(&collection, metric).await?;
+    Ok(respond(true, started))
+}
+
+async fn delete_collection(
+    Tenant { db, .. }: Tenant,
+    Path(collection): Path<String>,
+) -> Result<Json<Value>, ApiError> {
+    let started = Instant::now();
+    let existed = exists(&db, &collection).await?;
+    db.delete_collection(&collection).await?;
+    save_setting::<CollectionConfig>(&db, &config_key(&collection), None).await?;
+    Ok(respond(existed, started))
+}
+
+#[derive(Debug, Deserialize)]
+struct Point {
+    id: Value,
+    vector: Value,
+    #[serde(default)]
+    payload: Option<Map<String, Value>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Batch {
+    ids: Vec<Value>,
+    vectors: Vec<Value>,
+    #[serde(default)]
+    payloads: Option<Vec<Option<Map<String, Value>>>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Upsert {
+    Points { points: Vec<Point> },
+    Batch { batch: Batch },
+}
+
+async fn upsert_points(
+    tenant: Tenant,
+    Path(collection): Path<String>,
+    Json(payload): Json<Upsert>,
+) -> Result<Json<Value>, ApiError> {
+    let started = Instant::now();
+    let points = match payload {
+        Upsert::Points { points } => points,
+        Upsert::Batch { batch } => {
+            let payloads = batch.payloads.unwrap_or_default();
+            batch
+                .ids
+                .into_iter()
+                .zip(batch.vectors)
+                .enumerate()
+                .map(|(i, (id, vector))| Point {
+                    id,
+                    vector,
+                    payload: payloads.get(i).cloned().flatten(),
+                })
+                .collect()
+        }
+    };
+
+    let size = collection_config(&tenant.db, &collection)
+        .await?
+        .map(|config| config.size);
+    let mut vectors = Vec::with_capacity(points.len());
+    for point in points {
+        let data = dense_vector(point.vector)?;
+        if size.is_some_and(|size| size != data.len()) {
+            return Err(bad_request(format!(
+                "Wrong input: Vector dimension error: expected dim: {}, got {}",
+                size.unwrap_or_default(),
+                data.len()
+            )));
+        }
+        let mut vector =
+            Vector::with_id(stored_id(&point.id)?, data).with_collection(collection.clone());
+        vector.metadata = point.payload.and_then(to_metadata);
+        vectors.push(vector);
+    }
+
+    tenant.db.insert_vectors(vectors).await?;
+    Ok(respond(
+        json!({ "operation_id": null, "status": "completed" }),
+        started,
+    ))
+}
+
+// `with_payload` and `with_vector` take a flag or a list of fields
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum Selector {
+    All(bool),
+    Fields(Vec<String>),
+}
+
+impl Selector {
+    fn wants_any(&self) -> bool {
+        !matches!(self, Self::All(false))
+    }
+
+    fn payload(&self, vector: &Vector) -> Value {
+        let Some(metadata) = vector.metadata.as_ref().filter(|_| self.wants_any()) else {
+            return Value::Null;
+        };
+        let mut fields = to_fields(metadata);
+        if let Self::Fields(wanted) = self {
+            fields.retain(|key, _| wanted.contains(key));
+        }
+        Value::Object(fields)
+    }
+}
+
+fn record(vector: &Vector, with_payload: &Selector, with_vector: &Selector) -> Value {
+    json!({
+        "id": point_id(&vector.id),
+        "payload": with_payload.payload(vector),
+        "vector": with_vector.wants_any().then_some(&vector.data),
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct Retrieve {
+    ids: Vec<Value>,
+    #[serde(default = "payload_by_default")]
+    with_payload: Selector,
+    #[serde(default = "no_vectors")]
+    with_vector: Selector,
+}
+
+fn payload_by_default() -> Selector {
+    Selector::All(true)
+}
+
+fn no_vectors() -> Selector {
+    Selector::All(false)
+}
+
+async fn retrieve_points(
+    Tenant { db, .. }: Tenant,
+    Path(collection): Path<String>,
+    Json(payload): Json<Retrieve>,
+) -> Result<Json<Value>, ApiError> {
+    let started = Instant::now();
+    let mut records = Vec::new();
+    for id in &payload.ids {
+        let Some(vector) = db.get_vector(&stored_id(id)?).await? else {
+            continue;
+        };
+        if vector.collection.as_deref() == Some(collection.as_str()) {
+            records.push(record(&vector, &payload.with_payload, &payload.with_vector));
+        }
+    }
+    Ok(respond(records, started))
+}
+
+#[derive(Debug, Deserialize)]
+struct DeletePoints {
+    points: Vec<Value>,
+}
+
+async fn delete_points(
+    Tenant { db, .. }: Tenant,
+    Path(collection): Path<String>,
+    Json(payload): Json<DeletePoints>,
+) -> Result<Json<Value>, ApiError> {
+    let started = Instant::now();
+    for id in &payload.points {
+        let id = stored_id(id)?;
+        let in_collection = db
+            .get_vector(&id)
+            .await?
+            .is_some_and(|vector| vector.collection.as_deref() == Some(collection.as_str()));
+        if in_collection {
+            db.delete_vector(&id).await?;
+        }
+    }
+    Ok(respond(
+        json!({ "operation_id": null, "status": "completed" }),
+        started,
+    ))
+}
+
+// Only `must` conditions matching a top-level payload field exactly
+fn search_filter(collection: &str, filter: Option<Value>) -> Result<SearchFilter, ApiError> {
+    let mut metadata = HashMap::new();
+    if let Some(filter) = filter.filter(|filter| !filter.is_null()) {
+        let unsupported = || bad_request("Only `must` filters with `match.value` are supported");
+        let Value::Object(filter) = filter else {
+            return Err(unsupported());
+        };
+        for (clause, conditions) in filter {
+            if clause != "must" {
+                return Err(unsupported());
+            }
+            let conditions = match conditions {
+                Value::Array(conditions) => conditions,
+                condition => vec![condition],
+            };
+            for condition in conditions {
+                let key = condition["key"].as_str().ok_or_else(unsupported)?;
+                let value = condition["match"].get("value").ok_or_else(unsupported)?;
+                metadata.insert(key.to_string(), stored_value(value));
+            }
+        }
+    }
+    Ok(SearchFilter {
+        collection: Some(collection.to_string()),
+        metadata,
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct Search {
+    vector: Value,
+    #[serde(default = "default_limit")]
+    limit: usize,
+    #[serde(default)]
+    offset: usize,
+    #[serde(default)]
+    filter: Option<Value>,
+    #[serde(default = "no_vectors")]
+    with_payload: Selector,
+    #[serde(default = "no_vectors")]
+    with_vector: Selector,
+    score_threshold: Option<f32>,
+}
+
+fn default_limit() -> usize {
+    10
+}
+
+async fn scored_points(
+    db: &VectorDatabase,
+    collection: &str,
+    search: Search,
+) -> Result<Vec<Value>, ApiError> {
+    let query = dense_vector(search.vector)?;
+    let filter = search_filter(collection, search.filter)?;
+    let results = db
+        .search_filtered(
+            &query,
+            search.limit + search.offset,
+            search.score_threshold.unwrap_or(f32::MIN),
+            &filter,
+        )
+        .await?;
+
+    let mut points = Vec::with_capacity(results.len());
+    for result in results.into_iter().skip(search.offset) {
+        let Some(vector) = db.get_vector(&result.id).await? else {
+            continue;
+        };
+        let mut point = record(&vector, &search.with_payload, &search.with_vector);
+        point["score"] = json!(result.score);
+        point["version"] = json!(0);
+        points.push(point);
+    }
+    Ok(points)
+}
+
+async fn search_points(
+    Tenant { db, .. }: Tenant,
+    Path(collection): Path<String>,
+    Json(payload): Json<Search>,
+) -> Result<Json<Value>, ApiError> {
+    let started = Instant::now();
+    let points = scored_points(&db, &collection, payload).await?;
+    Ok(respond(points, started))
+}
+
+// The universal query endpoint, for nearest-neighbour queries only
+#[derive(Debug, Deserialize)]
+struct Query {
+    query: Value,
+    #[serde(default = "default_limit")]
+    limit: usize,
+    #[serde(default)]
+    offset: usize,
+    #[serde(default)]
+    filter: Option<Value>,
+    #[serde(default = "no_vectors")]
+    with_payload: Selector,
+    #[serde(default = "no_vectors")]
+    with_vector: Selector,
+    score_threshold: Option<f32>,
+}
+
+async fn query_points(
+    Tenant { db, .. }: Tenant,
+    Path(collection): Path<String>,
+    Json(payload): Json<Query>,
+) -> Result<Json<Value>, ApiError> {
+    let started = Instant::now();
+    let vector = match payload.query {
+        Value::Object(mut query) => query.remove("nearest").unwrap_or(Value::Null),
+        vector => vector,
+    };
+    let search = Search {
+        vector,
+        limit: payload.limit,
+        offset: payload.offset,
+        filter: payload.filter,
+        with_payload: payload.with_payload,
+        with_vector: payload.with_vector,
+        score_threshold: payload.score_threshold,
+    };
+    let points = scored_points(&db, &collection, search).await?;
+    Ok(respond(json!({ "points": points }), started))
+}
@@ -1,41 +1,369 @@
 use serde::{Deserialize, Serialize};
+use skypier_core::VectorDatabase;
+use skypier_core::{ChangeKind, ConflictPolicy, DistanceMetric, Dtype, IdScheme, ValidationLimits};
+use skypier_index::{AdaptiveIndex, BinaryIndex, HnswIndex, VectorIndex};
+use skypier_network::{PlacementPolicy, TransportKind};
+use skypier_storage::{Cipher, EncryptionKeys, InMemoryStorage, RedbStorage, Storage};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::Arc;
 
-#[derive(Debug, Deserialize, Serialize)]
+use crate::api::TlsFiles;
+use crate::cluster::Placement;
+use crate::dataset::ColumnMapping;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Config {
     pub server: ServerConfig,
     pub p2p: P2PConfig,
     pub storage: StorageConfig,
     pub index: IndexConfig,
+    pub embeddings: EmbeddingsConfig,
+    pub validation: ValidationConfig,
+    pub namespaces: NamespacesConfig,
+    pub rate_limit: RateLimitConfig,
+    pub auth: AuthConfig,
+    pub changes: ChangesConfig,
+    pub slow_queries: SlowQueriesConfig,
+    pub audit: AuditConfig,
+    pub replication: ReplicationConfig,
+    pub cluster: ClusterConfig,
+    pub import: ColumnMapping,
+    #[serde(default)]
+    pub webhooks: Vec<WebhookConfig>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ServerConfig {
     pub host: String,
     pub port: u16,
+    pub max_body_bytes: usize,
+    pub shutdown_timeout_secs: u64, // how long to wait for in-flight requests
+    // Requests still unanswered after this long get a 408; 0 = no limit
+    pub request_timeout_ms: u64,
+    // Requests handled at once, past which more get a 503; 0 = no limit
+    pub max_concurrent_requests: usize,
+    // PEM files; with both set the server speaks HTTPS, and SIGHUP reloads them
+    pub tls_cert_path: Option<String>,
+    pub tls_key_path: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct P2PConfig {
     pub port: u16,
+    // "tcp", "quic" or "both"
+    #[serde(default)]
+    pub transport: TransportKind,
+    // The config crate drops empty arrays from the layered defaults
+    #[serde(default)]
     pub bootstrap_peers: Vec<String>,
     pub max_peers: usize,
+    // Cluster membership: a member is probed every `probe_interval_ms`,
+    // suspected when it doesn't answer within `probe_timeout_ms`, directly
+    // or through others, and declared failed after `suspicion_timeout_ms`
+    pub probe_interval_ms: u64,
+    pub probe_timeout_ms: u64,
+    pub suspicion_timeout_ms: u64,
+    // Peer ids this node connects with. With an allowlist only those
+    // peers may connect; denied ones never may.
+    #[serde(default)]
+    pub allowed_peers: Vec<String>,
+    #[serde(default)]
+    pub denied_peers: Vec<String>,
+    // This node's labels, e.g. region = "eu-west", for collections'
+    // placement constraints
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    // Relays to be reached through from behind NAT, and whether this node
+    // is one
+    #[serde(default)]
+    pub relays: Vec<String>,
+    #[serde(default)]
+    pub relay_server: bool,
+    // Find and join nodes on the local network without bootstrap peers
+    #[serde(default)]
+    pub mdns: bool,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct StorageConfig {
+    pub backend: String, // "redb", "sled" or "memory"
     pub data_dir: String,
     pub max_file_size: usize,
     pub compression: bool,
+    // Precision vectors are stored at, unless their collection is listed in
+    // `dtypes`
+    pub dtype: Dtype,
+    #[serde(default)]
+    pub dtypes: HashMap<String, Dtype>,
+    // How ids are made for vectors inserted without one, in collections
+    // that don't set their own
+    #[serde(default)]
+    pub id_scheme: IdScheme,
+    // Earlier versions kept when an id is re-inserted, in collections that
+    // don't set their own number
+    #[serde(default)]
+    pub keep_versions: usize,
+    // Encrypts vector records at rest; redb only
+    #[serde(default)]
+    pub encryption: Option<EncryptionConfig>,
+}
+
+// Keys are 64 hex digits, given inline as `key`, in the variable named by
+// `key_env`, or in `key_file`. A key file holds the current key on its
+// first line and retired ones on the lines after; `previous_keys` adds
+// more. Retired keys are only read, until compaction re-encrypts their
+// records with the current one.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct EncryptionConfig {
+    #[serde(default)]
+    pub cipher: Cipher,
+    pub key: Option<String>,
+    pub key_env: Option<String>,
+    pub key_file: Option<String>,
+    #[serde(default)]
+    pub previous_keys: Vec<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct IndexConfig {
-    pub index_type: String, // "faiss" or "embedded"
+    pub index_type: String, // "embedded" (HNSW), "auto", "binary", "faiss", "hnsw_rs" or "disk"
     pub dimensions: usize,
     pub distance_metric: String, // "cosine", "euclidean", "dot_product"
     pub ef_construction: usize,
     pub ef_search: usize,
     pub max_connections: usize,
+    pub tie_break: String,              // "id" or "created_at"
+    pub snapshot_interval_minutes: u64, // 0 = only on shutdown
+    // Hamming candidates per result the binary index rescores; 0 = off
+    pub rescore_factor: usize,
+    // Faiss index factory description, e.g. "HNSW32" or "IVF1024,Flat"
+    pub faiss_factory: String,
+    // Vectors the faiss index waits for before training, for IVF and PQ
+    pub faiss_train_size: usize,
+    // Where the disk index keeps its graph files; empty for `disk_index`
+    // in the data directory
+    pub disk_dir: String,
+    // Bytes of quantized code per vector the disk index keeps in memory
+    pub disk_pq_subspaces: usize,
+    // Writes the disk index holds in memory before merging them to disk
+    pub disk_merge_threshold: usize,
+    // Vectors an "auto" index holds before moving from a flat scan to HNSW
+    pub flat_threshold: usize,
+    // Read through the indexes once loaded, so first queries don't fault
+    // them in
+    pub warmup: bool,
+    // mlockall the process, keeping index memory out of swap; needs a
+    // memlock limit to match
+    pub lock_memory: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct EmbeddingsConfig {
+    pub enabled: bool,
+    pub backend: String, // "openai" or "onnx"
+    pub url: String,
+    pub model: String,
+    pub api_key_env: Option<String>,
+    pub text_field: String, // metadata key the source text is stored under
+    // Local ONNX model (backend = "onnx")
+    pub model_path: Option<String>,
+    pub tokenizer_path: Option<String>,
+    pub batch_size: usize,
+    pub max_length: usize,
+    pub warmup: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ValidationConfig {
+    pub max_dimensions: usize,
+    pub max_metadata_bytes: usize,
+    // Refuse vectors that aren't signed. Signed ones are always checked.
+    pub require_signatures: bool,
+    // Hex ed25519 public keys signatures are accepted from; empty trusts any
+    #[serde(default)]
+    pub trusted_keys: Vec<String>,
+}
+
+// Tenants above collections. With `enabled` off every request goes to the
+// default namespace.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct NamespacesConfig {
+    pub enabled: bool,
+    pub header: String, // alternative to a /namespaces/{name} path prefix
+    pub default: String,
+    // Namespaces besides the default; others are made with
+    // PUT /admin/namespaces/{name}
+    #[serde(default)]
+    pub names: Vec<String>,
+    #[serde(default)]
+    pub quotas: HashMap<String, NamespaceQuota>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct NamespaceQuota {
+    pub max_vectors: Option<usize>,
+    // Stored bytes of the namespace's vectors, after compression
+    pub max_bytes: Option<u64>,
+}
+
+// Token buckets per client: the API key a request was authorized with, by
+// id, or else its address. `keys` overrides the default per key, given as
+// the key or its id; `namespaces` per namespace, for requests without a key.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    pub default: RateLimit,
+    #[serde(default)]
+    pub keys: HashMap<String, RateLimit>,
+    #[serde(default)]
+    pub namespaces: HashMap<String, RateLimit>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RateLimit {
+    pub requests_per_second: f64,
+    pub burst: u32,
+}
+
+// With `enabled` on, every request but /health needs an API key. The key in
+// `admin_key_env` is always an admin and can create the others.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AuthConfig {
+    pub enabled: bool,
+    pub key_header: String,
+    pub admin_key_env: Option<String>,
+}
+
+// WAL entries kept past each index snapshot, so /changes consumers can
+// resume from up to this many writes back. 0 keeps only what the index
+// still needs.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ChangesConfig {
+    pub retain_entries: u64,
+}
+
+// Searches taking at least `threshold_ms` are logged, and the last
+// `buffer_size` of them kept for GET /admin/slow-queries. 0 turns it off.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SlowQueriesConfig {
+    pub threshold_ms: u64,
+    pub buffer_size: usize,
+}
+
+// With `enabled`, inserts, deletes and admin actions made through the API
+// are appended to the default namespace's audit log, and entries older than
+// `retain_days` are pruned hourly (0 keeps them all). `client_ip_header`,
+// e.g. "x-forwarded-for", names the header a trusted proxy puts the
+// client's address in; without it the peer address is recorded.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AuditConfig {
+    pub enabled: bool,
+    pub retain_days: u64,
+    pub client_ip_header: Option<String>,
+}
+
+// With `primary` set, e.g. "/ip4/10.0.0.1/tcp/7777", this node follows that
+// node's P2P address, read-only unless `writable`. With `serve`, followers
+// may copy this node's data over the P2P port. `conflicts` settles
+// replicated writes to ids that were written here too.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ReplicationConfig {
+    pub serve: bool,
+    pub primary: Option<String>,
+    pub batch_size: usize, // changes or vectors per request
+    pub poll_interval_ms: u64,
+    pub writable: bool,
+    pub conflicts: ConflictPolicy,
+}
+
+// With `enabled`, each collection lives on `replication_factor` members of
+// the cluster, or as many as its entry in `collections` says, and requests
+// for it are passed on to them, whichever node they reach
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ClusterConfig {
+    pub enabled: bool,
+    pub replication_factor: usize,
+    #[serde(default)]
+    pub collections: HashMap<String, PlacementPolicy>,
+    #[serde(default)]
+    pub throttle: ThrottleConfig,
+}
+
+// Each webhook gets a namespace's committed changes POSTed to `url` in
+// batches, signed with the secret in `secret_env` if set. Empty `events` or
+// `collections` means all of them; no `namespace` means the default one.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct WebhookConfig {
+    pub url: String,
+    pub namespace: Option<String>,
+    pub secret_env: Option<String>,
+    pub events: Vec<ChangeKind>,
+    pub collections: Vec<String>,
+    pub batch_size: usize,
+    pub flush_interval_ms: u64,
+    pub max_retries: u32,
+    pub timeout_ms: u64,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            namespace: None,
+            secret_env: None,
+            events: vec![],
+            collections: vec![],
+            batch_size: 100,
+            flush_interval_ms: 1000,
+            max_retries: 5,
+            timeout_ms: 10_000,
+        }
+    }
+}
+
+// Limits on the writes owners copy to other replicas, 0 for none. Writes
+// waiting for the same replica go out together, up to `max_batch` a message.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ThrottleConfig {
+    pub ops_per_second: u64,
+    pub bytes_per_second: u64,
+    pub max_batch: usize,
+}
+
+impl Default for ThrottleConfig {
+    fn default() -> Self {
+        Self {
+            ops_per_second: 0,
+            bytes_per_second: 0,
+            max_batch: 1000,
+        }
+    }
+}
+
+impl ClusterConfig {
+    pub fn placement(&self) -> Placement {
+        let default = PlacementPolicy {
+            replication_factor: self.replication_factor,
+            ..Default::default()
+        };
+        Placement::new(default, self.collections.clone())
+    }
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            default: RateLimit {
+                requests_per_second: 100.0,
+                burst: 200,
+            },
+            keys: HashMap::new(),
+            namespaces: HashMap::new(),
+        }
+    }
 }
 
 impl Default for Config {
@@ -44,16 +372,38 @@ impl Default for Config {
             server: ServerConfig {
                 host: "0.0.0.0".to_string(),
                 port: 8080,
+                max_body_bytes: 16 * 1024 * 1024, // 16MB
+                shutdown_timeout_secs: 30,
+                request_timeout_ms: 30_000,
+                max_concurrent_requests: 0,
+                tls_cert_path: None,
+                tls_key_path: None,
             },
             p2p: P2PConfig {
                 port: 7777,
+                transport: TransportKind::Tcp,
                 bootstrap_peers: vec![],
                 max_peers: 50,
+                probe_interval_ms: 1000,
+                probe_timeout_ms: 500,
+                suspicion_timeout_ms: 5000,
+                allowed_peers: vec![],
+                denied_peers: vec![],
+                labels: BTreeMap::new(),
+                relays: vec![],
+                relay_server: false,
+                mdns: false,
             },
             storage: StorageConfig {
+                backend: "redb".to_string(),
                 data_dir: "./data".to_string(),
                 max_file_size: 1024 * 1024 * 1024, // 1GB
                 compression: true,
+                dtype: Dtype::F32,
+                dtypes: HashMap::new(),
+                id_scheme: IdScheme::Uuid,
+                keep_versions: 0,
+                encryption: None,
             },
             index: IndexConfig {
                 index_type: "embedded".to_string(),
@@ -62,7 +412,466 @@ impl Default for Config {
                 ef_construction: 200,
                 ef_search: 50,
                 max_connections: 16,
+                tie_break: "id".to_string(),
+                snapshot_interval_minutes: 10,
+                rescore_factor: 4,
+                faiss_factory: "HNSW32".to_string(),
+                faiss_train_size: 10_000,
+                disk_dir: String::new(),
+                disk_pq_subspaces: 32,
+                disk_merge_threshold: 100_000,
+                flat_threshold: 10_000,
+                warmup: false,
+                lock_memory: false,
+            },
+            embeddings: EmbeddingsConfig {
+                enabled: false,
+                backend: "openai".to_string(),
+                url: "https://api.openai.com/v1".to_string(),
+                model: "text-embedding-3-small".to_string(),
+                api_key_env: Some("OPENAI_API_KEY".to_string()),
+                text_field: "text".to_string(),
+                model_path: None,
+                tokenizer_path: None,
+                batch_size: 32,
+                max_length: 256,
+                warmup: true,
+            },
+            validation: ValidationConfig {
+                max_dimensions: 65_536,
+                max_metadata_bytes: 64 * 1024, // 64KB
+                require_signatures: false,
+                trusted_keys: vec![],
+            },
+            namespaces: NamespacesConfig {
+                enabled: false,
+                header: "x-namespace".to_string(),
+                default: "default".to_string(),
+                names: Vec::new(),
+                quotas: HashMap::new(),
+            },
+            rate_limit: RateLimitConfig::default(),
+            auth: AuthConfig {
+                enabled: false,
+                key_header: "x-api-key".to_string(),
+                admin_key_env: Some("SKYPIER_ADMIN_KEY".to_string()),
+            },
+            changes: ChangesConfig {
+                retain_entries: 100_000,
+            },
+            slow_queries: SlowQueriesConfig {
+                threshold_ms: 500,
+                buffer_size: 100,
+            },
+            audit: AuditConfig {
+                enabled: false,
+                retain_days: 90,
+                client_ip_header: None,
             },
+            replication: ReplicationConfig {
+                serve: false,
+                primary: None,
+                batch_size: 256,
+                poll_interval_ms: 1000,
+                writable: false,
+                conflicts: ConflictPolicy::default(),
+            },
+            cluster: ClusterConfig {
+                enabled: false,
+                replication_factor: 1,
+                collections: HashMap::new(),
+                throttle: ThrottleConfig::default(),
+            },
+            import: ColumnMapping::default(),
+            webhooks: vec![],
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn tls(&self) -> anyhow::Result<Option<TlsFiles>> {
+        match (&self.tls_cert_path, &self.tls_key_path) {
+            (Some(cert), Some(key)) => Ok(Some(TlsFiles {
+                cert: cert.into(),
+                key: key.into(),
+            })),
+            (None, None) => Ok(None),
+            _ => Err(anyhow::anyhow!(
+                "TLS needs both server.tls_cert_path and server.tls_key_path"
+            )),
+        }
+    }
+}
+
+impl StorageConfig {
+    // Opens storage in `data_dir`, which may differ from the configured one
+    // (e.g. `build-index --output`)
+    pub async fn open(&self, data_dir: &str) -> anyhow::Result<Arc<dyn Storage>> {
+        if self.encryption.is_some() && self.backend != "redb" {
+            return Err(anyhow::anyhow!(
+                "Encryption at rest is only supported by the redb backend"
+            ));
+        }
+        match self.backend.as_str() {
+            "redb" => {
+                let storage = RedbStorage::new(data_dir)
+                    .await?
+                    .with_compression(self.compression);
+                Ok(Arc::new(match &self.encryption {
+                    Some(encryption) => storage.with_encryption(encryption.keys()?),
+                    None => storage,
+                }))
+            }
+            #[cfg(feature = "sled-backend")]
+            "sled" => Ok(Arc::new(
+                skypier_storage::SledStorage::new(data_dir)
+                    .await?
+                    .with_compression(self.compression),
+            )),
+            #[cfg(not(feature = "sled-backend"))]
+            "sled" => Err(anyhow::anyhow!(
+                "The sled backend requires building with --features sled-backend"
+            )),
+            "memory" => Ok(Arc::new(InMemoryStorage::new())),
+            other => Err(anyhow::anyhow!("Unknown storage backend '{}'", other)),
+        }
+    }
+}
+
+impl EncryptionConfig {
+    pub fn keys(&self) -> anyhow::Result<EncryptionKeys> {
+        let mut lines = Vec::new();
+        if let Some(key) = &self.key {
+            lines.push(key.clone());
+        } else if let Some(var) = &self.key_env {
+            lines.push(std::env::var(var).map_err(|_| {
+                anyhow::anyhow!("The storage encryption key variable {} isn't set", var)
+            })?);
+        } else if let Some(path) = &self.key_file {
+            let contents = std::fs::read_to_string(path).map_err(|e| {
+                anyhow::anyhow!("Failed to read storage encryption key file {}: {}", path, e)
+            })?;
+            lines.extend(
+                contents
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                    .map(str::to_string),
+            );
+        }
+        if lines.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Storage encryption needs one of key, key_env or key_file"
+            ));
+        }
+        let mut keys = lines.iter().chain(&self.previous_keys);
+        let current = keys.next().unwrap();
+        let mut encryption_keys =
+            EncryptionKeys::new(self.cipher, EncryptionKeys::parse_key(current)?);
+        for key in keys {
+            encryption_keys = encryption_keys.with_previous(EncryptionKeys::parse_key(key)?);
+        }
+        Ok(encryption_keys)
+    }
+}
+
+impl IndexConfig {
+    // The configured index type; anything but "auto", "binary", "faiss",
+    // "hnsw_rs" and "disk" gets the embedded HNSW index
+    pub fn build(&self) -> anyhow::Result<Box<dyn VectorIndex>> {
+        Ok(match self.index_type.as_str() {
+            "auto" => Box::new(self.adaptive_for(self.distance_metric.parse()?)),
+            "binary" if self.rescore_factor > 0 => {
+                Box::new(BinaryIndex::new().with_rescore(self.rescore_factor))
+            }
+            "binary" => Box::new(BinaryIndex::new()),
+            #[cfg(feature = "faiss-backend")]
+            "faiss" => {
+                let metric: DistanceMetric = self.distance_metric.parse()?;
+                Box::new(
+                    skypier_index::FaissIndex::new(&self.faiss_factory, metric.index_metric())
+                        .with_train_size(self.faiss_train_size),
+                )
+            }
+            #[cfg(not(feature = "faiss-backend"))]
+            "faiss" => {
+                return Err(anyhow::anyhow!(
+                    "The faiss index requires building with --features faiss-backend"
+                ))
+            }
+            #[cfg(feature = "hnsw-rs")]
+            "hnsw_rs" => {
+                let metric: DistanceMetric = self.distance_metric.parse()?;
+                Box::new(
+                    skypier_index::HnswRsIndex::new(metric.index_metric())?
+                        .with_max_connections(self.max_connections)
+                        .with_ef_construction(self.ef_construction)
+                        .with_ef_search(self.ef_search),
+                )
+            }
+            #[cfg(not(feature = "hnsw-rs"))]
+            "hnsw_rs" => {
+                return Err(anyhow::anyhow!(
+                    "The hnsw_rs index requires building with --features hnsw-rs"
+                ))
+            }
+            #[cfg(feature = "disk-index")]
+            "disk" => {
+                let metric: DistanceMetric = self.distance_metric.parse()?;
+                Box::new(
+                    skypier_index::DiskIndex::new(&self.disk_dir, metric.index_metric())
+                        .with_degree(self.max_connections)
+                        .with_build_list(self.ef_construction)
+                        .with_search_list(self.ef_search)
+                        .with_subspaces(self.disk_pq_subspaces)
+                        .with_merge_threshold(self.disk_merge_threshold),
+                )
+            }
+            #[cfg(not(feature = "disk-index"))]
+            "disk" => {
+                return Err(anyhow::anyhow!(
+                    "The disk index requires building with --features disk-index"
+                ))
+            }
+            _ => Box::new(self.hnsw()?),
+        })
+    }
+
+    // With paths left empty resolved inside the database's data directory
+    pub fn in_data_dir(&self, data_dir: &str) -> Self {
+        let mut config = self.clone();
+        if config.disk_dir.is_empty() {
+            config.disk_dir = Path::new(data_dir)
+                .join("disk_index")
+                .to_string_lossy()
+                .into_owned();
+        }
+        config
+    }
+
+    pub fn hnsw(&self) -> anyhow::Result<HnswIndex> {
+        self.hnsw_for(self.distance_metric.parse()?)
+    }
+
+    // The index of a collection with a metric of its own: HNSW, or with
+    // "auto" a flat index until the collection grows past `flat_threshold`
+    pub fn collection_index(&self, metric: DistanceMetric) -> anyhow::Result<Box<dyn VectorIndex>> {
+        Ok(match self.index_type.as_str() {
+            "auto" => Box::new(self.adaptive_for(metric)),
+            _ => Box::new(self.hnsw_for(metric)?),
+        })
+    }
+
+    fn adaptive_for(&self, metric: DistanceMetric) -> AdaptiveIndex {
+        let config = self.clone();
+        AdaptiveIndex::new(metric.index_metric(), self.flat_threshold, move || {
+            Ok(Box::new(config.hnsw_for(metric)?) as _)
+        })
+    }
+
+    // Like `hnsw`, for a collection with a metric of its own
+    pub fn hnsw_for(&self, metric: DistanceMetric) -> anyhow::Result<HnswIndex> {
+        Ok(HnswIndex::new(self.dimensions)?
+            .with_max_connections(self.max_connections)
+            .with_ef_construction(self.ef_construction)
+            .with_ef_search(self.ef_search)
+            .with_metric(metric.index_metric()))
+    }
+}
+
+impl ValidationConfig {
+    pub fn limits(&self) -> ValidationLimits {
+        ValidationLimits {
+            max_dimensions: self.max_dimensions,
+            max_metadata_bytes: self.max_metadata_bytes,
+            require_signatures: self.require_signatures,
+            trusted_keys: self.trusted_keys.clone(),
         }
     }
 }
+
+impl Config {
+    // A database in `data_dir` set up with the configured storage, index and
+    // limits. The index still has to be loaded.
+    pub async fn open_database(&self, data_dir: &str) -> anyhow::Result<VectorDatabase> {
+        let index = self.index.in_data_dir(data_dir);
+        Ok(
+            VectorDatabase::from_storage(self.storage.open(data_dir).await?, data_dir)?
+                .with_index(index.build()?)
+                .with_named_index({
+                    let index = index.clone();
+                    move || index.build()
+                })
+                .with_collection_index({
+                    let index = index.clone();
+                    move |metric| index.collection_index(metric)
+                })
+                .with_distance_metric(index.distance_metric.parse()?)
+                .with_tie_break(self.index.tie_break.parse()?)
+                .with_dtypes(self.storage.dtype, self.storage.dtypes.clone())
+                .with_id_scheme(self.storage.id_scheme)
+                .with_versions(self.storage.keep_versions)
+                .with_validation_limits(self.validation.limits())
+                .with_changefeed_retention(self.changes.retain_entries)
+                .with_conflict_resolver(self.replication.conflicts.resolver()),
+        )
+    }
+
+    // Loads `path` layered over the defaults, so a config file only needs the
+    // options it wants to override. A missing file yields the defaults.
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let config = config::Config::builder()
+            .add_source(config::Config::try_from(&Config::default())?)
+            .add_source(
+                config::File::from(Path::new(path))
+                    .format(config::FileFormat::Toml)
+                    .required(false),
+            )
+            .build()?;
+
+        Ok(config.try_deserialize()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_missing_file_uses_defaults() {
+        let config = Config::load("does-not-exist.toml").unwrap();
+        assert_eq!(config.server.port, 8080);
+        assert!(config.p2p.bootstrap_peers.is_empty());
+        assert_eq!(config.embeddings.model_path, None);
+        assert!(!config.namespaces.enabled);
+        assert!(!config.audit.enabled);
+        assert_eq!(config.audit.retain_days, 90);
+    }
+
+    #[test]
+    fn test_load_namespace_quotas() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            "[namespaces]\nenabled = true\n\n[namespaces.quotas.acme]\nmax_vectors = 10\n",
+        )
+        .unwrap();
+
+        let config = Config::load(path.to_str().unwrap()).unwrap();
+        assert!(config.namespaces.enabled);
+        assert_eq!(config.namespaces.header, "x-namespace");
+        assert_eq!(config.namespaces.quotas["acme"].max_vectors, Some(10));
+    }
+
+    #[test]
+    fn test_build_configured_index() {
+        let mut config = Config::default();
+        assert_eq!(config.index.build().unwrap().index_type(), "hnsw");
+        config.index.index_type = "binary".to_string();
+        assert_eq!(config.index.build().unwrap().index_type(), "binary");
+        config.index.index_type = "faiss".to_string();
+        #[cfg(feature = "faiss-backend")]
+        assert_eq!(config.index.build().unwrap().index_type(), "faiss");
+        #[cfg(not(feature = "faiss-backend"))]
+        assert!(config.index.build().is_err());
+        config.index.index_type = "hnsw_rs".to_string();
+        #[cfg(feature = "hnsw-rs")]
+        assert_eq!(config.index.build().unwrap().index_type(), "hnsw_rs");
+        #[cfg(not(feature = "hnsw-rs"))]
+        assert!(config.index.build().is_err());
+        config.index.index_type = "auto".to_string();
+        assert_eq!(config.index.build().unwrap().index_type(), "flat");
+        assert_eq!(
+            config
+                .index
+                .collection_index(DistanceMetric::Euclidean)
+                .unwrap()
+                .index_type(),
+            "flat"
+        );
+        config.index.index_type = "disk".to_string();
+        #[cfg(feature = "disk-index")]
+        assert_eq!(config.index.build().unwrap().index_type(), "disk");
+        #[cfg(not(feature = "disk-index"))]
+        assert!(config.index.build().is_err());
+        assert!(config
+            .index
+            .in_data_dir("./data")
+            .disk_dir
+            .ends_with("disk_index"));
+    }
+
+    #[test]
+    fn test_load_webhooks() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            "[[webhooks]]\nurl = \"http://localhost:9000/hook\"\nevents = [\"insert\", \"update\"]\n",
+        )
+        .unwrap();
+
+        let config = Config::load(path.to_str().unwrap()).unwrap();
+        assert_eq!(config.webhooks.len(), 1);
+        let webhook = &config.webhooks[0];
+        assert_eq!(webhook.events, vec![ChangeKind::Insert, ChangeKind::Update]);
+        assert_eq!(webhook.batch_size, 100);
+        assert!(webhook.collections.is_empty());
+        assert!(Config::default().webhooks.is_empty());
+    }
+
+    #[test]
+    fn test_load_collection_dtypes() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("config.toml");
+        std::fs::write(&path, "[storage.dtypes]\ndocuments = \"f16\"\n").unwrap();
+
+        let config = Config::load(path.to_str().unwrap()).unwrap();
+        assert_eq!(config.storage.dtype, Dtype::F32);
+        assert_eq!(config.storage.dtypes["documents"], Dtype::F16);
+    }
+
+    #[tokio::test]
+    async fn test_load_storage_encryption() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let key_file = temp_dir.path().join("storage.key");
+        std::fs::write(
+            &key_file,
+            format!("{}\n{}\n", "01".repeat(32), "02".repeat(32)),
+        )
+        .unwrap();
+        let path = temp_dir.path().join("config.toml");
+        std::fs::write(
+            &path,
+            format!(
+                "[storage.encryption]\ncipher = \"xchacha20-poly1305\"\nkey_file = {:?}\n",
+                key_file.to_str().unwrap()
+            ),
+        )
+        .unwrap();
+
+        let config = Config::load(path.to_str().unwrap()).unwrap();
+        let encryption = config.storage.encryption.as_ref().unwrap();
+        assert_eq!(encryption.cipher, Cipher::XChaCha20Poly1305);
+        assert!(encryption.keys().is_ok());
+        let data_dir = temp_dir.path().join("data");
+        let storage = config
+            .storage
+            .open(data_dir.to_str().unwrap())
+            .await
+            .unwrap();
+        storage
+            .store_vector(&skypier_core::Vector::with_id("a".to_string(), vec![1.0]))
+            .await
+            .unwrap();
+        assert!(storage.get_vector("a").await.unwrap().is_some());
+
+        let missing = EncryptionConfig {
+            key_env: Some("SKYPIER_TEST_UNSET_STORAGE_KEY".to_string()),
+            ..Default::default()
+        };
+        assert!(missing.keys().is_err());
+        assert!(EncryptionConfig::default().keys().is_err());
+    }
+}
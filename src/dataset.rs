@@ -0,0 +1,585 @@
+use anyhow::{anyhow, Result};
+use axum::body::Bytes;
+use serde::{Deserialize, Serialize};
+use skypier_core::Vector;
+use std::collections::HashMap;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DatasetFormat {
+    Json,
+    Parquet, // needs the `parquet` feature
+    Csv,
+}
+
+impl std::str::FromStr for DatasetFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(Self::Json),
+            "parquet" => Ok(Self::Parquet),
+            "csv" => Ok(Self::Csv),
+            other => Err(anyhow!(
+                "Unknown dataset format '{}', expected 'parquet', 'csv' or 'json'",
+                other
+            )),
+        }
+    }
+}
+
+impl DatasetFormat {
+    // JSON unless the extension says otherwise
+    pub fn for_path(path: &str) -> Self {
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("parquet") => Self::Parquet,
+            Some("csv") => Self::Csv,
+            _ => Self::Json,
+        }
+    }
+}
+
+// Which Parquet or CSV columns hold what. JSON datasets have fixed fields
+// and ignore it.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ColumnMapping {
+    pub id_column: String, // ids are generated where it's missing or empty
+    // A list of floats per row (in CSV, a cell like "[0.1, 0.2]"). `data`
+    // is tried when there's no such column.
+    pub vector_column: String,
+    // One column per dimension instead: `<prefix>0`, `<prefix>1`, ...
+    pub dimension_prefix: Option<String>,
+    pub collection_column: String,
+    // For rows that don't name a collection
+    pub collection: Option<String>,
+    // Defaults to every other string column (and, in Parquet, the entries
+    // of a `metadata` map column)
+    pub metadata_columns: Option<Vec<String>>,
+}
+
+impl Default for ColumnMapping {
+    fn default() -> Self {
+        Self {
+            id_column: "id".to_string(),
+            vector_column: "vector".to_string(),
+            dimension_prefix: None,
+            collection_column: "collection".to_string(),
+            collection: None,
+            metadata_columns: None,
+        }
+    }
+}
+
+impl ColumnMapping {
+    // Index of each dimension column in `names`, in dimension order
+    fn dimension_columns<'a>(&self, names: impl Iterator<Item = &'a str>) -> Vec<usize> {
+        let Some(prefix) = &self.dimension_prefix else {
+            return Vec::new();
+        };
+        let mut columns: Vec<(usize, usize)> = names
+            .enumerate()
+            .filter_map(|(position, name)| {
+                let dimension = name.strip_prefix(prefix.as_str())?.parse().ok()?;
+                Some((dimension, position))
+            })
+            .collect();
+        columns.sort_unstable();
+        columns.into_iter().map(|(_, position)| position).collect()
+    }
+
+    // Whether `name` is a metadata column, given it isn't mapped to
+    // anything else
+    fn is_metadata(&self, name: &str) -> bool {
+        match &self.metadata_columns {
+            Some(columns) => columns.iter().any(|column| column == name),
+            None => ![
+                self.id_column.as_str(),
+                self.vector_column.as_str(),
+                "data",
+                self.collection_column.as_str(),
+                "created_at",
+            ]
+            .contains(&name),
+        }
+    }
+
+    fn vector(&self, id: Option<String>, data: Vec<f32>, collection: Option<String>) -> Vector {
+        let mut vector = match id.filter(|id| !id.is_empty()) {
+            Some(id) => Vector::with_id(id, data),
+            None => Vector::new(data),
+        };
+        vector.collection = collection
+            .filter(|collection| !collection.is_empty())
+            .or_else(|| self.collection.clone());
+        vector
+    }
+}
+
+// Reads vectors from a file for the offline tools (`tune`, `build-index`,
+// `import`), picking the format from the extension.
+pub fn load_vectors(path: &str) -> Result<Vec<Vector>> {
+    load_vectors_with(
+        path,
+        DatasetFormat::for_path(path),
+        &ColumnMapping::default(),
+    )
+}
+
+pub fn load_vectors_with(
+    path: &str,
+    format: DatasetFormat,
+    mapping: &ColumnMapping,
+) -> Result<Vec<Vector>> {
+    let data =
+        std::fs::read(path).map_err(|e| anyhow!("Failed to read dataset {}: {}", path, e))?;
+    read_vectors(Bytes::from(data), format, mapping)
+        .map_err(|e| anyhow!("Failed to parse dataset {}: {}", path, e))
+}
+
+// Same as `load_vectors_with`, for a file that was uploaded
+pub fn read_vectors(
+    data: Bytes,
+    format: DatasetFormat,
+    mapping: &ColumnMapping,
+) -> Result<Vec<Vector>> {
+    match format {
+        DatasetFormat::Json => read_json(&data),
+        DatasetFormat::Parquet => read_parquet(data, mapping),
+        DatasetFormat::Csv => read_csv(&data, mapping),
+    }
+}
+
+// Accepts the body of a `POST /vectors` request, a bare array of vector
+// objects, or an array of raw float arrays.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonDataset {
+    Request { vectors: Vec<JsonVector> },
+    Vectors(Vec<JsonVector>),
+    Raw(Vec<Vec<f32>>),
+}
+
+// Like `Vector`, but everything except the data is optional
+#[derive(Deserialize)]
+struct JsonVector {
+    id: Option<String>,
+    data: Vec<f32>,
+    metadata: Option<HashMap<String, String>>,
+    collection: Option<String>,
+    created_at: Option<u64>,
+}
+
+impl From<JsonVector> for Vector {
+    fn from(item: JsonVector) -> Self {
+        let mut vector = match item.id {
+            Some(id) => Vector::with_id(id, item.data),
+            None => Vector::new(item.data),
+        };
+        vector.metadata = item.metadata;
+        vector.collection = item.collection;
+        if let Some(created_at) = item.created_at {
+            vector.created_at = created_at;
+        }
+        vector
+    }
+}
+
+fn read_json(data: &[u8]) -> Result<Vec<Vector>> {
+    let dataset = serde_json::from_slice(data)?;
+    Ok(match dataset {
+        JsonDataset::Request { vectors } | JsonDataset::Vectors(vectors) => {
+            vectors.into_iter().map(Vector::from).collect()
+        }
+        JsonDataset::Raw(vectors) => vectors.into_iter().map(Vector::new).collect(),
+    })
+}
+
+// Floats in a CSV cell, separated by commas or whitespace and optionally
+// in brackets
+fn parse_floats(cell: &str) -> Result<Vec<f32>> {
+    cell.trim()
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|value| !value.is_empty())
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| anyhow!("'{}' is not a number", value))
+        })
+        .collect()
+}
+
+// The first row holds the column names
+fn read_csv(data: &[u8], mapping: &ColumnMapping) -> Result<Vec<Vector>> {
+    let mut reader = csv::Reader::from_reader(data);
+    let headers = reader.headers()?.clone();
+    let position = |name: &str| headers.iter().position(|header| header == name);
+
+    let dimensions = mapping.dimension_columns(headers.iter());
+    let vector_column = position(&mapping.vector_column).or_else(|| position("data"));
+    if dimensions.is_empty() && vector_column.is_none() {
+        return Err(anyhow!(
+            "No `{}` column and no columns with the dimension prefix",
+            mapping.vector_column
+        ));
+    }
+    let id_column = position(&mapping.id_column);
+    let collection_column = position(&mapping.collection_column);
+    let metadata_columns: Vec<usize> = (0..headers.len())
+        .filter(|&column| {
+            Some(column) != id_column
+                && Some(column) != vector_column
+                && Some(column) != collection_column
+                && !dimensions.contains(&column)
+                && mapping.is_metadata(&headers[column])
+        })
+        .collect();
+
+    let mut vectors = Vec::new();
+    for (row, record) in reader.records().enumerate() {
+        let record = record?;
+        let cell = |column: Option<usize>| column.and_then(|c| record.get(c)).map(str::to_string);
+        let data = match vector_column {
+            Some(column) if dimensions.is_empty() => parse_floats(&record[column]),
+            _ => dimensions
+                .iter()
+                .map(|&column| {
+                    let value = record[column].trim();
+                    value
+                        .parse()
+                        .map_err(|_| anyhow!("'{}' in {} is not a number", value, &headers[column]))
+                })
+                .collect(),
+        }
+        .map_err(|e| anyhow!("Row {}: {}", row + 1, e))?;
+
+        let mut vector = mapping.vector(cell(id_column), data, cell(collection_column));
+        let metadata: HashMap<String, String> = metadata_columns
+            .iter()
+            .filter(|&&column| !record[column].is_empty())
+            .map(|&column| (headers[column].to_string(), record[column].to_string()))
+            .collect();
+        if !metadata.is_empty() {
+            vector.metadata = Some(metadata);
+        }
+        vectors.push(vector);
+    }
+    Ok(vectors)
+}
+
+#[cfg(not(feature = "parquet"))]
+fn read_parquet(_data: Bytes, _mapping: &ColumnMapping) -> Result<Vec<Vector>> {
+    Err(anyhow!(
+        "Reading Parquet files requires building with --features parquet"
+    ))
+}
+
+// Vectors come from a list column or float columns per dimension, as
+// mapped. A `created_at` integer column is used as such. Reads what
+// `export` writes.
+#[cfg(feature = "parquet")]
+fn read_parquet(data: Bytes, mapping: &ColumnMapping) -> Result<Vec<Vector>> {
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{Float32Type, Float64Type, Int64Type, UInt64Type};
+    use arrow_array::{
+        Array, ArrayRef, FixedSizeListArray, Float32Array, Float64Array, LargeListArray,
+        LargeStringArray, ListArray, MapArray, StringArray,
+    };
+    use arrow_schema::DataType;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    fn floats(values: &dyn Array) -> Result<Vec<f32>> {
+        if let Some(values) = values.as_any().downcast_ref::<Float32Array>() {
+            Ok(values.values().to_vec())
+        } else if let Some(values) = values.as_any().downcast_ref::<Float64Array>() {
+            Ok(values.values().iter().map(|&x| x as f32).collect())
+        } else {
+            Err(anyhow!(
+                "Vector values must be float32 or float64, not {}",
+                values.data_type()
+            ))
+        }
+    }
+
+    fn vector_at(column: &dyn Array, row: usize) -> Result<Vec<f32>> {
+        if column.is_null(row) {
+            return Err(anyhow!("Row {} has no vector", row));
+        }
+        let any = column.as_any();
+        let values = if let Some(list) = any.downcast_ref::<ListArray>() {
+            list.value(row)
+        } else if let Some(list) = any.downcast_ref::<LargeListArray>() {
+            list.value(row)
+        } else if let Some(list) = any.downcast_ref::<FixedSizeListArray>() {
+            list.value(row)
+        } else {
+            return Err(anyhow!(
+                "The vector column must be a list of floats, not {}",
+                column.data_type()
+            ));
+        };
+        floats(values.as_ref())
+    }
+
+    fn string_at(column: &dyn Array, row: usize) -> Option<String> {
+        if column.is_null(row) {
+            return None;
+        }
+        let any = column.as_any();
+        if let Some(strings) = any.downcast_ref::<StringArray>() {
+            Some(strings.value(row).to_string())
+        } else {
+            any.downcast_ref::<LargeStringArray>()
+                .map(|strings| strings.value(row).to_string())
+        }
+    }
+
+    fn map_at(column: &dyn Array, row: usize) -> Vec<(String, String)> {
+        let Some(map) = column.as_any().downcast_ref::<MapArray>() else {
+            return Vec::new();
+        };
+        if map.is_null(row) {
+            return Vec::new();
+        }
+        let entries = map.value(row);
+        let (keys, values) = (entries.column(0), entries.column(1));
+        (0..entries.len())
+            .filter_map(|i| Some((string_at(keys.as_ref(), i)?, string_at(values.as_ref(), i)?)))
+            .collect()
+    }
+
+    fn timestamp_at(column: &dyn Array, row: usize) -> Option<u64> {
+        if column.is_null(row) {
+            return None;
+        }
+        match column.data_type() {
+            DataType::UInt64 => Some(column.as_primitive::<UInt64Type>().value(row)),
+            DataType::Int64 => u64::try_from(column.as_primitive::<Int64Type>().value(row)).ok(),
+            _ => None,
+        }
+    }
+
+    fn float_at(column: &dyn Array, row: usize) -> Result<f32> {
+        if column.is_null(row) {
+            return Err(anyhow!("Row {} is missing a dimension", row));
+        }
+        match column.data_type() {
+            DataType::Float32 => Ok(column.as_primitive::<Float32Type>().value(row)),
+            DataType::Float64 => Ok(column.as_primitive::<Float64Type>().value(row) as f32),
+            other => Err(anyhow!("Dimension columns must be floats, not {}", other)),
+        }
+    }
+
+    let reader = ParquetRecordBatchReaderBuilder::try_new(data)?.build()?;
+
+    let mut vectors = Vec::new();
+    for batch in reader {
+        let batch = batch?;
+        let schema = batch.schema();
+        let names = schema.fields().iter().map(|field| field.name().as_str());
+        let dimensions: Vec<&ArrayRef> = mapping
+            .dimension_columns(names)
+            .into_iter()
+            .map(|position| batch.column(position))
+            .collect();
+        let data = batch
+            .column_by_name(&mapping.vector_column)
+            .or_else(|| batch.column_by_name("data"));
+        if dimensions.is_empty() && data.is_none() {
+            return Err(anyhow!(
+                "No `{}` column and no columns with the dimension prefix",
+                mapping.vector_column
+            ));
+        }
+        let ids = batch.column_by_name(&mapping.id_column);
+        let collections = batch.column_by_name(&mapping.collection_column);
+        let maps = batch
+            .column_by_name("metadata")
+            .filter(|_| mapping.metadata_columns.is_none());
+        let created_at = batch.column_by_name("created_at");
+        let metadata_columns: Vec<_> = schema
+            .fields()
+            .iter()
+            .zip(batch.columns())
+            .filter(|(field, _)| {
+                matches!(field.data_type(), DataType::Utf8 | DataType::LargeUtf8)
+                    && field.name() != &mapping.id_column
+                    && field.name() != &mapping.collection_column
+                    && mapping.is_metadata(field.name())
+            })
+            .collect();
+
+        for row in 0..batch.num_rows() {
+            let data = match data {
+                Some(data) if dimensions.is_empty() => vector_at(data.as_ref(), row)?,
+                _ => dimensions
+                    .iter()
+                    .map(|column| float_at(column.as_ref(), row))
+                    .collect::<Result<_>>()?,
+            };
+            let mut vector = mapping.vector(
+                ids.and_then(|ids| string_at(ids.as_ref(), row)),
+                data,
+                collections.and_then(|c| string_at(c.as_ref(), row)),
+            );
+            if let Some(created_at) = created_at.and_then(|c| timestamp_at(c.as_ref(), row)) {
+                vector.created_at = created_at;
+            }
+
+            let mut metadata: HashMap<String, String> = metadata_columns
+                .iter()
+                .filter_map(|(field, column)| {
+                    Some((field.name().clone(), string_at(column.as_ref(), row)?))
+                })
+                .collect();
+            if let Some(maps) = maps {
+                metadata.extend(map_at(maps.as_ref(), row));
+            }
+            if !metadata.is_empty() {
+                vector.metadata = Some(metadata);
+            }
+
+            vectors.push(vector);
+        }
+    }
+
+    Ok(vectors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_json_formats() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let raw = dir.path().join("raw.json");
+        std::fs::write(&raw, "[[1.0, 0.0], [0.0, 1.0]]").unwrap();
+        let vectors = load_vectors(raw.to_str().unwrap()).unwrap();
+        assert_eq!(vectors.len(), 2);
+        assert_eq!(vectors[1].data, vec![0.0, 1.0]);
+
+        let request = dir.path().join("request.json");
+        std::fs::write(
+            &request,
+            r#"{"vectors": [{"id": "a", "data": [0.5], "collection": "docs"}]}"#,
+        )
+        .unwrap();
+        let vectors = load_vectors(request.to_str().unwrap()).unwrap();
+        assert_eq!(vectors[0].id, "a");
+        assert_eq!(vectors[0].collection.as_deref(), Some("docs"));
+    }
+
+    #[test]
+    fn test_read_csv_with_mapping() {
+        let csv = "key,embedding,lang,title\na,\"[1.0, 0.0]\",en,First\n,0.0 1.0,fr,\n";
+        let mapping = ColumnMapping {
+            id_column: "key".to_string(),
+            vector_column: "embedding".to_string(),
+            collection: Some("docs".to_string()),
+            metadata_columns: Some(vec!["lang".to_string()]),
+            ..Default::default()
+        };
+        let vectors = read_vectors(Bytes::from(csv), DatasetFormat::Csv, &mapping).unwrap();
+        assert_eq!(vectors.len(), 2);
+        assert_eq!(
+            (vectors[0].id.as_str(), &vectors[0].data),
+            ("a", &vec![1.0, 0.0])
+        );
+        assert_eq!(vectors[1].data, vec![0.0, 1.0]);
+        assert_eq!(vectors[1].collection.as_deref(), Some("docs"));
+        let metadata = vectors[0].metadata.as_ref().unwrap();
+        assert_eq!(
+            (metadata.get("lang").unwrap().as_str(), metadata.len()),
+            ("en", 1)
+        );
+
+        // One column per dimension, in numeric rather than column order
+        let csv = "id,d1,d0,d10,note\na,0.5,0.25,1,x\n";
+        let mapping = ColumnMapping {
+            dimension_prefix: Some("d".to_string()),
+            ..Default::default()
+        };
+        let vectors = read_vectors(Bytes::from(csv), DatasetFormat::Csv, &mapping).unwrap();
+        assert_eq!(vectors[0].data, vec![0.25, 0.5, 1.0]);
+        assert_eq!(vectors[0].metadata.as_ref().unwrap().len(), 1);
+
+        let csv = "id,d0\na,oops\n";
+        assert!(read_vectors(Bytes::from(csv), DatasetFormat::Csv, &mapping).is_err());
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_load_parquet() {
+        use arrow_array::types::Float32Type;
+        use arrow_array::{ArrayRef, ListArray, RecordBatch, StringArray};
+        use parquet::arrow::ArrowWriter;
+        use std::sync::Arc;
+
+        let batch = RecordBatch::try_from_iter([
+            (
+                "id",
+                Arc::new(StringArray::from(vec![Some("a"), None])) as ArrayRef,
+            ),
+            (
+                "vector",
+                Arc::new(ListArray::from_iter_primitive::<Float32Type, _, _>(vec![
+                    Some(vec![Some(1.0), Some(0.0)]),
+                    Some(vec![Some(0.0), Some(1.0)]),
+                ])) as ArrayRef,
+            ),
+            (
+                "source",
+                Arc::new(StringArray::from(vec!["x.txt", "y.txt"])) as ArrayRef,
+            ),
+        ])
+        .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.parquet");
+        let mut writer =
+            ArrowWriter::try_new(std::fs::File::create(&path).unwrap(), batch.schema(), None)
+                .unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+
+        let vectors = load_vectors(path.to_str().unwrap()).unwrap();
+        assert_eq!(vectors.len(), 2);
+        assert_eq!(vectors[0].id, "a");
+        assert_eq!(vectors[1].data, vec![0.0, 1.0]);
+        assert_eq!(
+            vectors[1].metadata.as_ref().unwrap().get("source").unwrap(),
+            "y.txt"
+        );
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_read_parquet_dimension_columns() {
+        use arrow_array::{ArrayRef, Float32Array, Float64Array, RecordBatch};
+        use parquet::arrow::ArrowWriter;
+        use std::sync::Arc;
+
+        let batch = RecordBatch::try_from_iter([
+            ("x1", Arc::new(Float64Array::from(vec![0.5])) as ArrayRef),
+            ("x0", Arc::new(Float32Array::from(vec![0.25])) as ArrayRef),
+        ])
+        .unwrap();
+        let mut file = Vec::new();
+        let mut writer = ArrowWriter::try_new(&mut file, batch.schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+
+        let mapping = ColumnMapping {
+            dimension_prefix: Some("x".to_string()),
+            collection: Some("docs".to_string()),
+            ..Default::default()
+        };
+        let vectors = read_vectors(Bytes::from(file), DatasetFormat::Parquet, &mapping).unwrap();
+        assert_eq!(vectors[0].data, vec![0.25, 0.5]);
+        assert_eq!(vectors[0].collection.as_deref(), Some("docs"));
+    }
+}
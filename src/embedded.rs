@@ -0,0 +1,128 @@
+use crate::api::{self, AppState};
+use crate::config::Config;
+use anyhow::Result;
+use skypier_core::{DistanceMetric, VectorDatabase};
+use std::future::Future;
+use std::sync::Arc;
+
+// Opens a database in-process, configured like the server would be:
+//
+//     let db = skypier_vecdb::Builder::new()
+//         .with_storage("redb")
+//         .with_data_dir("./vectors")
+//         .with_dimensions(384)
+//         .open()
+//         .await?;
+//
+// Options left unset keep the server's defaults.
+#[derive(Debug, Clone, Default)]
+pub struct Builder {
+    config: Config,
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Starts from a whole config, e.g. one read with `Config::load`
+    pub fn from_config(config: Config) -> Self {
+        Self { config }
+    }
+
+    // "redb", "sled" or "memory"
+    pub fn with_storage(mut self, backend: &str) -> Self {
+        self.config.storage.backend = backend.to_string();
+        self
+    }
+
+    pub fn with_data_dir(mut self, data_dir: &str) -> Self {
+        self.config.storage.data_dir = data_dir.to_string();
+        self
+    }
+
+    pub fn with_compression(mut self, compression: bool) -> Self {
+        self.config.storage.compression = compression;
+        self
+    }
+
+    // "embedded" (HNSW), "binary", "faiss", "hnsw_rs" or "disk"
+    pub fn with_index_type(mut self, index_type: &str) -> Self {
+        self.config.index.index_type = index_type.to_string();
+        self
+    }
+
+    pub fn with_dimensions(mut self, dimensions: usize) -> Self {
+        self.config.index.dimensions = dimensions;
+        self
+    }
+
+    pub fn with_distance_metric(mut self, metric: DistanceMetric) -> Self {
+        self.config.index.distance_metric = match metric {
+            DistanceMetric::Cosine => "cosine",
+            DistanceMetric::Euclidean => "euclidean",
+            DistanceMetric::DotProduct => "dot_product",
+        }
+        .to_string();
+        self
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    // Opens the storage and loads the index. Call `shutdown` on the
+    // database before dropping it, to snapshot the index.
+    pub async fn open(&self) -> Result<Arc<VectorDatabase>> {
+        // Nothing of an in-memory database goes to disk
+        let data_dir = match self.config.storage.backend.as_str() {
+            "memory" => "",
+            _ => &self.config.storage.data_dir,
+        };
+        let db = Arc::new(self.config.open_database(data_dir).await?);
+        db.load_index().await?;
+        Ok(db)
+    }
+}
+
+// Serves the HTTP API for an embedded database until `shutdown` resolves
+pub async fn serve<F>(db: Arc<VectorDatabase>, host: &str, port: u16, shutdown: F) -> Result<()>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    api::start_server(AppState::new(db), host, port, None, shutdown).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use skypier_core::Vector;
+
+    #[tokio::test]
+    async fn test_builder_opens_database() {
+        let db = Builder::new()
+            .with_storage("memory")
+            .with_dimensions(3)
+            .with_distance_metric(DistanceMetric::Euclidean)
+            .open()
+            .await
+            .unwrap();
+        let ids = db
+            .insert_vectors(vec![
+                Vector::new(vec![1.0, 0.0, 0.0]),
+                Vector::new(vec![0.0, 1.0, 0.0]),
+            ])
+            .await
+            .unwrap();
+        let results = db.search(&[0.9, 0.1, 0.0], 1, 0.0).await.unwrap();
+        assert_eq!(results[0].id, ids[0]);
+
+        assert!(Builder::new().with_storage("nope").open().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_serve_stops_on_shutdown() {
+        let db = Builder::new().with_storage("memory").open().await.unwrap();
+        serve(db, "127.0.0.1", 0, async {}).await.unwrap();
+    }
+}
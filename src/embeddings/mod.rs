@@ -0,0 +1,41 @@
+use anyhow::{anyhow, Result};
+use std::sync::Arc;
+
+use crate::config::EmbeddingsConfig;
+
+#[cfg(feature = "onnx")]
+pub mod onnx;
+pub mod openai;
+
+#[cfg(feature = "onnx")]
+pub use onnx::OnnxEmbedder;
+pub use openai::OpenAiEmbedder;
+
+// Turns raw text into vectors so clients can insert and search without their
+// own embedding step.
+#[async_trait::async_trait]
+pub trait Embedder: Send + Sync {
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>>;
+
+    // Tagged onto the vectors and queries it embeds, when known
+    fn model(&self) -> Option<&str> {
+        None
+    }
+}
+
+pub fn from_config(config: &EmbeddingsConfig) -> Result<Option<Arc<dyn Embedder>>> {
+    if !config.enabled {
+        return Ok(None);
+    }
+
+    match config.backend.as_str() {
+        "openai" => Ok(Some(Arc::new(OpenAiEmbedder::from_config(config)?))),
+        #[cfg(feature = "onnx")]
+        "onnx" => Ok(Some(Arc::new(OnnxEmbedder::from_config(config)?))),
+        #[cfg(not(feature = "onnx"))]
+        "onnx" => Err(anyhow!(
+            "The onnx embeddings backend requires building with --features onnx"
+        )),
+        other => Err(anyhow!("Unknown embeddings backend: {}", other)),
+    }
+}
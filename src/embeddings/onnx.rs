@@ -0,0 +1,187 @@
+use anyhow::{anyhow, Result};
+use ort::session::{builder::GraphOptimizationLevel, Session};
+use ort::value::Tensor;
+use std::sync::{Arc, Mutex};
+use tokenizers::{PaddingParams, Tokenizer, TruncationParams};
+use tracing::info;
+
+use super::Embedder;
+use crate::config::EmbeddingsConfig;
+
+// Local sentence-transformer model run through ONNX Runtime, for deployments
+// that can't call out to an embeddings API.
+pub struct OnnxEmbedder {
+    inner: Arc<OnnxModel>,
+}
+
+struct OnnxModel {
+    session: Mutex<Session>,
+    tokenizer: Tokenizer,
+    batch_size: usize,
+    uses_token_type_ids: bool,
+}
+
+impl OnnxEmbedder {
+    pub fn new(
+        model_path: &str,
+        tokenizer_path: &str,
+        batch_size: usize,
+        max_length: usize,
+    ) -> Result<Self> {
+        let session = Session::builder()?
+            .with_optimization_level(GraphOptimizationLevel::Level3)?
+            .commit_from_file(model_path)?;
+        let uses_token_type_ids = session
+            .inputs
+            .iter()
+            .any(|input| input.name == "token_type_ids");
+
+        let mut tokenizer = Tokenizer::from_file(tokenizer_path)
+            .map_err(|e| anyhow!("Failed to load tokenizer {}: {}", tokenizer_path, e))?;
+        tokenizer.with_padding(Some(PaddingParams::default()));
+        tokenizer
+            .with_truncation(Some(TruncationParams {
+                max_length,
+                ..Default::default()
+            }))
+            .map_err(|e| anyhow!("Invalid truncation settings: {}", e))?;
+
+        Ok(Self {
+            inner: Arc::new(OnnxModel {
+                session: Mutex::new(session),
+                tokenizer,
+                batch_size: batch_size.max(1),
+                uses_token_type_ids,
+            }),
+        })
+    }
+
+    pub fn from_config(config: &EmbeddingsConfig) -> Result<Self> {
+        let model_path = config
+            .model_path
+            .as_deref()
+            .ok_or_else(|| anyhow!("embeddings.model_path is required for the onnx backend"))?;
+        let tokenizer_path = config
+            .tokenizer_path
+            .as_deref()
+            .ok_or_else(|| anyhow!("embeddings.tokenizer_path is required for the onnx backend"))?;
+
+        let embedder = Self::new(
+            model_path,
+            tokenizer_path,
+            config.batch_size,
+            config.max_length,
+        )?;
+
+        if config.warmup {
+            // The first inference pays for graph initialization; do it before serving
+            let started = std::time::Instant::now();
+            embedder.inner.embed_batch(&["warmup".to_string()])?;
+            info!("ONNX embedding model warmed up in {:?}", started.elapsed());
+        }
+
+        Ok(embedder)
+    }
+}
+
+impl OnnxModel {
+    fn embed_all(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let mut embeddings = Vec::with_capacity(texts.len());
+        for batch in texts.chunks(self.batch_size) {
+            embeddings.extend(self.embed_batch(batch)?);
+        }
+        Ok(embeddings)
+    }
+
+    fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let encodings = self
+            .tokenizer
+            .encode_batch(texts.to_vec(), true)
+            .map_err(|e| anyhow!("Tokenization failed: {}", e))?;
+
+        // Padding makes every encoding in the batch the same length
+        let batch = encodings.len();
+        let seq_len = encodings.first().map(|e| e.len()).unwrap_or(0);
+        let flatten = |f: fn(&tokenizers::Encoding) -> &[u32]| -> Vec<i64> {
+            encodings
+                .iter()
+                .flat_map(|e| f(e).iter().map(|&x| x as i64))
+                .collect()
+        };
+        let input_ids = flatten(|e| e.get_ids());
+        let attention_mask = flatten(|e| e.get_attention_mask());
+        let token_type_ids = flatten(|e| e.get_type_ids());
+        let shape = vec![batch as i64, seq_len as i64];
+
+        let mut inputs = ort::inputs![
+            "input_ids" => Tensor::from_array((shape.clone(), input_ids))?,
+            "attention_mask" => Tensor::from_array((shape.clone(), attention_mask.clone()))?,
+        ];
+        if self.uses_token_type_ids {
+            inputs.push((
+                "token_type_ids".into(),
+                Tensor::from_array((shape, token_type_ids))?.into(),
+            ));
+        }
+
+        let mut session = self
+            .session
+            .lock()
+            .map_err(|_| anyhow!("ONNX session lock poisoned"))?;
+        let outputs = session.run(inputs)?;
+        let (output_shape, output) = outputs[0].try_extract_tensor::<f32>()?;
+
+        let embeddings: Vec<Vec<f32>> = match output_shape.len() {
+            // Already pooled: [batch, hidden]
+            2 => {
+                let hidden = output_shape[1] as usize;
+                output.chunks(hidden).map(|row| row.to_vec()).collect()
+            }
+            // Token embeddings: [batch, seq_len, hidden], mean-pooled over the mask
+            3 => {
+                let hidden = output_shape[2] as usize;
+                (0..batch)
+                    .map(|b| {
+                        let mut pooled = vec![0.0f32; hidden];
+                        let mut count = 0.0f32;
+                        for t in 0..seq_len {
+                            if attention_mask[b * seq_len + t] == 0 {
+                                continue;
+                            }
+                            let offset = (b * seq_len + t) * hidden;
+                            for (acc, x) in pooled.iter_mut().zip(&output[offset..offset + hidden])
+                            {
+                                *acc += x;
+                            }
+                            count += 1.0;
+                        }
+                        pooled.iter_mut().for_each(|x| *x /= count.max(1.0));
+                        pooled
+                    })
+                    .collect()
+            }
+            dims => return Err(anyhow!("Unexpected ONNX output rank {}", dims)),
+        };
+
+        Ok(embeddings.into_iter().map(normalize).collect())
+    }
+}
+
+fn normalize(mut vector: Vec<f32>) -> Vec<f32> {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|x| *x /= norm);
+    }
+    vector
+}
+
+#[async_trait::async_trait]
+impl Embedder for OnnxEmbedder {
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let inner = Arc::clone(&self.inner);
+        let texts = texts.to_vec();
+
+        // Inference is CPU-bound; keep it off the async workers
+        tokio::task::spawn_blocking(move || inner.embed_all(&texts)).await?
+    }
+}
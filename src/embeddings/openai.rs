@@ -0,0 +1,91 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use super::Embedder;
+use crate::config::EmbeddingsConfig;
+
+// Client for any server implementing the OpenAI `/embeddings` endpoint
+// (OpenAI itself, vLLM, Ollama, text-embeddings-inference, ...).
+pub struct OpenAiEmbedder {
+    client: reqwest::Client,
+    url: String,
+    model: String,
+    api_key: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct EmbeddingRequest<'a> {
+    model: &'a str,
+    input: &'a [String],
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingData {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+impl OpenAiEmbedder {
+    pub fn new(url: &str, model: &str, api_key: Option<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url: format!("{}/embeddings", url.trim_end_matches('/')),
+            model: model.to_string(),
+            api_key,
+        }
+    }
+
+    pub fn from_config(config: &EmbeddingsConfig) -> Result<Self> {
+        // The key itself is read from the environment so it never lands in config.toml
+        let api_key = match &config.api_key_env {
+            Some(var) => Some(
+                std::env::var(var)
+                    .map_err(|_| anyhow!("Embeddings API key variable {} is not set", var))?,
+            ),
+            None => None,
+        };
+
+        Ok(Self::new(&config.url, &config.model, api_key))
+    }
+}
+
+#[async_trait::async_trait]
+impl Embedder for OpenAiEmbedder {
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        if texts.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut request = self.client.post(&self.url).json(&EmbeddingRequest {
+            model: &self.model,
+            input: texts,
+        });
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+
+        let response = request.send().await?.error_for_status()?;
+        let mut body: EmbeddingResponse = response.json().await?;
+
+        if body.data.len() != texts.len() {
+            return Err(anyhow!(
+                "Embeddings backend returned {} embeddings for {} inputs",
+                body.data.len(),
+                texts.len()
+            ));
+        }
+
+        // Responses are not guaranteed to preserve input order
+        body.data.sort_by_key(|item| item.index);
+        Ok(body.data.into_iter().map(|item| item.embedding).collect())
+    }
+
+    fn model(&self) -> Option<&str> {
+        Some(&self.model)
+    }
+}
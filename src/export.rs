@@ -0,0 +1,344 @@
+use anyhow::{anyhow, Result};
+use axum::body::{Body, Bytes};
+use skypier_core::{Vector, VectorDatabase};
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::mpsc;
+use tracing::info;
+
+use crate::config::Config;
+
+// Rows read from storage per page, and per record batch / row group
+pub const BATCH_ROWS: usize = 8192;
+
+// Columnar exports for pandas, polars and the like. Both formats have the
+// same columns: `id`, `collection`, `data` (FixedSizeList<f32>), `metadata`
+// (map of strings) and `created_at`. Needs the `parquet` feature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Parquet,
+    Arrow, // Arrow IPC file
+}
+
+impl FromStr for ExportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "parquet" => Ok(Self::Parquet),
+            "arrow" | "ipc" => Ok(Self::Arrow),
+            other => Err(anyhow!(
+                "Unknown export format '{}', expected 'parquet' or 'arrow'",
+                other
+            )),
+        }
+    }
+}
+
+impl ExportFormat {
+    // From a file name, Parquet unless it ends in .arrow or .ipc
+    pub fn for_path(path: &str) -> Self {
+        match std::path::Path::new(path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+        {
+            Some("arrow" | "ipc") => Self::Arrow,
+            _ => Self::Parquet,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Parquet => "application/vnd.apache.parquet",
+            Self::Arrow => "application/vnd.apache.arrow.file",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Parquet => "parquet",
+            Self::Arrow => "arrow",
+        }
+    }
+}
+
+// The width of the `data` column. Every vector has to have it.
+pub fn dimensions(vectors: &[Vector]) -> Result<usize> {
+    let dimensions = vectors.first().map_or(0, Vector::dimensions);
+    check_dimensions(vectors, dimensions)?;
+    Ok(dimensions)
+}
+
+fn check_dimensions(vectors: &[Vector], dimensions: usize) -> Result<()> {
+    match vectors
+        .iter()
+        .find(|vector| vector.dimensions() != dimensions)
+    {
+        Some(vector) => Err(anyhow!(
+            "Vector {} has {} dimensions, not {}; export one collection at a time",
+            vector.id,
+            vector.dimensions(),
+            dimensions
+        )),
+        None => Ok(()),
+    }
+}
+
+// Reads every vector, or a collection's, a page at a time. Meant to be
+// drained on a blocking thread, since each page blocks on storage.
+pub fn pages(
+    db: Arc<VectorDatabase>,
+    collection: Option<String>,
+) -> impl Iterator<Item = Result<Vec<Vector>>> + Send {
+    let runtime = tokio::runtime::Handle::current();
+    let mut after = None;
+    let mut done = false;
+    std::iter::from_fn(move || {
+        if done {
+            return None;
+        }
+        let page = runtime.block_on(db.scroll(collection.as_deref(), after.as_deref(), BATCH_ROWS));
+        match page {
+            Ok(page) => {
+                done = page.next.is_none();
+                after = page.next;
+                Some(Ok(page.vectors))
+            }
+            Err(e) => {
+                done = true;
+                Some(Err(e))
+            }
+        }
+    })
+}
+
+#[cfg(not(feature = "parquet"))]
+pub fn write_pages<W: Write + Send>(
+    _pages: impl Iterator<Item = Result<Vec<Vector>>>,
+    _format: ExportFormat,
+    _writer: W,
+) -> Result<u64> {
+    Err(anyhow!("Exports require building with --features parquet"))
+}
+
+// Writes the pages out as they come, returning how many vectors there were.
+// The first page sets the width of `data`.
+#[cfg(feature = "parquet")]
+pub fn write_pages<W: Write + Send>(
+    mut pages: impl Iterator<Item = Result<Vec<Vector>>>,
+    format: ExportFormat,
+    writer: W,
+) -> Result<u64> {
+    use arrow_array::builder::{
+        FixedSizeListBuilder, Float32Builder, MapBuilder, StringBuilder, UInt64Builder,
+    };
+    use arrow_array::{ArrayRef, RecordBatch};
+    use arrow_schema::{DataType, Field, Schema};
+    use std::sync::Arc;
+
+    let first = pages.next().transpose()?.unwrap_or_default();
+    let width = dimensions(&first)?;
+    let dimensions = i32::try_from(width)?;
+    let batch = |vectors: &[Vector]| -> Result<RecordBatch> {
+        let mut ids = StringBuilder::new();
+        let mut collections = StringBuilder::new();
+        let mut data = FixedSizeListBuilder::new(Float32Builder::new(), dimensions)
+            .with_field(Field::new("item", DataType::Float32, false));
+        let mut metadata = MapBuilder::new(None, StringBuilder::new(), StringBuilder::new());
+        let mut created_at = UInt64Builder::new();
+        for vector in vectors {
+            ids.append_value(&vector.id);
+            collections.append_option(vector.collection.as_deref());
+            data.values().append_slice(&vector.data);
+            data.append(true);
+            match &vector.metadata {
+                Some(entries) => {
+                    let mut entries: Vec<_> = entries.iter().collect();
+                    entries.sort();
+                    for (key, value) in entries {
+                        metadata.keys().append_value(key);
+                        metadata.values().append_value(value);
+                    }
+                    metadata.append(true)?;
+                }
+                None => metadata.append(false)?,
+            }
+            created_at.append_value(vector.created_at);
+        }
+        Ok(RecordBatch::try_from_iter_with_nullable([
+            ("id", Arc::new(ids.finish()) as ArrayRef, false),
+            (
+                "collection",
+                Arc::new(collections.finish()) as ArrayRef,
+                true,
+            ),
+            ("data", Arc::new(data.finish()) as ArrayRef, false),
+            ("metadata", Arc::new(metadata.finish()) as ArrayRef, true),
+            (
+                "created_at",
+                Arc::new(created_at.finish()) as ArrayRef,
+                false,
+            ),
+        ])?)
+    };
+
+    // The schema comes from an empty batch so it matches when there are no
+    // vectors at all
+    let schema: Arc<Schema> = batch(&[])?.schema();
+    let mut count = 0;
+    let mut pages = std::iter::once(Ok(first)).chain(pages);
+    let mut next_batch = || -> Result<Option<RecordBatch>> {
+        let Some(page) = pages.next().transpose()? else {
+            return Ok(None);
+        };
+        check_dimensions(&page, width)?;
+        count += page.len() as u64;
+        batch(&page).map(Some)
+    };
+    match format {
+        ExportFormat::Parquet => {
+            let mut writer = parquet::arrow::ArrowWriter::try_new(writer, schema, None)?;
+            while let Some(batch) = next_batch()? {
+                writer.write(&batch)?;
+            }
+            writer.into_inner()?.flush()?;
+        }
+        ExportFormat::Arrow => {
+            let mut writer = arrow_ipc::writer::FileWriter::try_new(writer, &schema)?;
+            while let Some(batch) = next_batch()? {
+                writer.write(&batch)?;
+            }
+            writer.into_inner()?.flush()?;
+        }
+    }
+    Ok(count)
+}
+
+// Sends what's written as body chunks. Fails once the client has gone, so
+// an abandoned download stops being encoded.
+pub struct ChannelWriter(mpsc::Sender<std::io::Result<Bytes>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0
+            .blocking_send(Ok(Bytes::copy_from_slice(buf)))
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// Encodes on a blocking thread while the body is being sent. An error part
+// way through cuts the body short.
+pub fn stream(
+    pages: impl Iterator<Item = Result<Vec<Vector>>> + Send + 'static,
+    format: ExportFormat,
+) -> Body {
+    stream_with(move |writer| write_pages(pages, format, writer))
+}
+
+// The body `write` writes out, run on a blocking thread as it's sent
+pub fn stream_with<F>(write: F) -> Body
+where
+    F: FnOnce(std::io::BufWriter<ChannelWriter>) -> Result<u64> + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(16);
+    let errors = tx.clone();
+    tokio::task::spawn_blocking(move || {
+        let writer = std::io::BufWriter::with_capacity(64 * 1024, ChannelWriter(tx));
+        if let Err(e) = write(writer) {
+            let _ = errors.blocking_send(Err(std::io::Error::other(e.to_string())));
+        }
+    });
+    Body::from_stream(tokio_stream::wrappers::ReceiverStream::new(rx))
+}
+
+// `skypier-vecdb export`: writes a stopped instance's vectors to a file
+pub async fn run(
+    config: &Config,
+    output: &str,
+    format: ExportFormat,
+    collection: Option<&str>,
+) -> Result<()> {
+    let started = Instant::now();
+    let db = Arc::new(config.open_database(&config.storage.data_dir).await?);
+    let pages = pages(db, collection.map(str::to_string));
+
+    let file =
+        std::fs::File::create(output).map_err(|e| anyhow!("Failed to create {}: {}", output, e))?;
+    let count = tokio::task::spawn_blocking(move || {
+        write_pages(pages, format, std::io::BufWriter::new(file))
+    })
+    .await??;
+    info!(
+        "Exported {} vectors to {} in {:?}",
+        count,
+        output,
+        started.elapsed()
+    );
+    Ok(())
+}
+
+#[cfg(all(test, feature = "parquet"))]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn vectors() -> Vec<Vector> {
+        vec![
+            Vector::with_id("a".to_string(), vec![1.0, 0.0])
+                .with_metadata(HashMap::from([("source".to_string(), "x.txt".to_string())])),
+            Vector::with_id("b".to_string(), vec![0.0, 1.0]).with_collection("docs".to_string()),
+        ]
+    }
+
+    #[test]
+    fn test_parquet_export_reloads() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("vectors.parquet");
+        write_pages(
+            std::iter::once(Ok(vectors())),
+            ExportFormat::Parquet,
+            std::fs::File::create(&path).unwrap(),
+        )
+        .unwrap();
+
+        let loaded = crate::dataset::load_vectors(path.to_str().unwrap()).unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(
+            (loaded[0].id.as_str(), &loaded[0].data),
+            ("a", &vec![1.0, 0.0])
+        );
+        assert_eq!(
+            loaded[0].metadata.as_ref().unwrap().get("source").unwrap(),
+            "x.txt"
+        );
+        assert_eq!(loaded[1].collection.as_deref(), Some("docs"));
+        assert_eq!(loaded[1].created_at, vectors()[1].created_at);
+    }
+
+    #[test]
+    fn test_arrow_export() {
+        let mut file = Vec::new();
+        let pages = vectors().into_iter().map(|vector| Ok(vec![vector]));
+        assert_eq!(
+            write_pages(pages, ExportFormat::Arrow, &mut file).unwrap(),
+            2
+        );
+        let reader =
+            arrow_ipc::reader::FileReader::try_new(std::io::Cursor::new(file), None).unwrap();
+        let rows: usize = reader.map(|batch| batch.unwrap().num_rows()).sum();
+        assert_eq!(rows, 2);
+
+        let mixed = [
+            Vector::with_id("a".to_string(), vec![1.0]),
+            Vector::with_id("b".to_string(), vec![1.0, 0.0]),
+        ];
+        assert!(dimensions(&mixed).is_err());
+    }
+}
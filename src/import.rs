@@ -0,0 +1,39 @@
+use anyhow::Result;
+use std::time::Instant;
+use tracing::info;
+
+use crate::config::Config;
+use crate::dataset::{self, DatasetFormat};
+
+// `skypier-vecdb import`: loads a Parquet or CSV file into a stopped
+// instance's data dir, mapping columns as `[import]` says. An empty store
+// is bulk loaded; otherwise the vectors are added to what's there.
+pub async fn run(config: &Config, input: &str, format: DatasetFormat, output: &str) -> Result<()> {
+    let started = Instant::now();
+    let vectors = dataset::load_vectors_with(input, format, &config.import)?;
+    info!(
+        "Read {} vectors from {} in {:?}",
+        vectors.len(),
+        input,
+        started.elapsed()
+    );
+
+    let started = Instant::now();
+    let db = config.open_database(output).await?;
+    let count = if db.get_stats().await?.total_vectors == 0 {
+        db.bulk_load(vectors).await?
+    } else {
+        db.load_index().await?;
+        let count = db.import_vectors(vectors).await?.len();
+        db.shutdown().await?;
+        count
+    };
+    info!(
+        "Imported {} vectors into {} in {:?}",
+        count,
+        output,
+        started.elapsed()
+    );
+
+    Ok(())
+}
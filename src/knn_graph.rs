@@ -0,0 +1,340 @@
+use anyhow::{anyhow, Result};
+use axum::body::Body;
+use serde::{Deserialize, Serialize};
+use skypier_core::{SearchFilter, SearchOptions, Vector, VectorDatabase};
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::task::JoinSet;
+use tracing::info;
+
+use crate::config::Config;
+use crate::export;
+
+// Searches run at once while a page of the graph is computed
+const SEARCH_CONCURRENCY: usize = 64;
+
+// One vector's row of the graph: its nearest neighbors, best first, leaving
+// out the vector itself
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GraphRow {
+    pub id: String,
+    pub neighbors: Vec<Neighbor>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Neighbor {
+    pub id: String,
+    pub score: f32,
+}
+
+// JSON lines of `GraphRow`, or Parquet with the columns `id`,
+// `neighbor_ids` (list of strings) and `scores` (list of f32), in the same
+// order. Parquet needs the `parquet` feature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    Jsonl,
+    Parquet,
+}
+
+impl FromStr for GraphFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "jsonl" | "ndjson" => Ok(Self::Jsonl),
+            "parquet" => Ok(Self::Parquet),
+            other => Err(anyhow!(
+                "Unknown graph format '{}', expected 'jsonl' or 'parquet'",
+                other
+            )),
+        }
+    }
+}
+
+impl GraphFormat {
+    // From a file name, JSON lines unless it ends in .parquet
+    pub fn for_path(path: &str) -> Self {
+        match std::path::Path::new(path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+        {
+            Some("parquet") => Self::Parquet,
+            _ => Self::Jsonl,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Jsonl => "application/x-ndjson",
+            Self::Parquet => "application/vnd.apache.parquet",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Jsonl => "jsonl",
+            Self::Parquet => "parquet",
+        }
+    }
+}
+
+// The rows for a page of vectors, searched against the collection they were
+// scrolled from, in page order
+pub async fn rows(
+    db: &Arc<VectorDatabase>,
+    page: Vec<Vector>,
+    neighbors: usize,
+    collection: Option<&str>,
+) -> Result<Vec<GraphRow>> {
+    let filter = SearchFilter {
+        collection: collection.map(str::to_string),
+        ..Default::default()
+    };
+    let mut rows = Vec::with_capacity(page.len());
+    let mut page = page.into_iter().peekable();
+    while page.peek().is_some() {
+        let mut searches = JoinSet::new();
+        for (position, vector) in page.by_ref().take(SEARCH_CONCURRENCY).enumerate() {
+            let db = Arc::clone(db);
+            let filter = filter.clone();
+            searches.spawn(async move {
+                // One extra, since the vector finds itself
+                let results = db
+                    .search_with(
+                        &vector.data,
+                        neighbors + 1,
+                        f32::NEG_INFINITY,
+                        &filter,
+                        &SearchOptions::default(),
+                    )
+                    .await?;
+                let neighbors = results
+                    .into_iter()
+                    .filter(|result| result.id != vector.id)
+                    .take(neighbors)
+                    .map(|result| Neighbor {
+                        id: result.id,
+                        score: result.score,
+                    })
+                    .collect();
+                Ok::<_, anyhow::Error>((
+                    position,
+                    GraphRow {
+                        id: vector.id,
+                        neighbors,
+                    },
+                ))
+            });
+        }
+        let mut chunk = Vec::with_capacity(searches.len());
+        while let Some(row) = searches.join_next().await {
+            chunk.push(row??);
+        }
+        chunk.sort_by_key(|(position, _)| *position);
+        rows.extend(chunk.into_iter().map(|(_, row)| row));
+    }
+    Ok(rows)
+}
+
+// The graph of every vector, or a collection's, a page at a time. Like
+// `export::pages`, meant to be drained on a blocking thread.
+pub fn pages(
+    db: Arc<VectorDatabase>,
+    collection: Option<String>,
+    neighbors: usize,
+) -> impl Iterator<Item = Result<Vec<GraphRow>>> + Send {
+    let runtime = tokio::runtime::Handle::current();
+    let vectors = export::pages(Arc::clone(&db), collection.clone());
+    vectors.map(move |page| runtime.block_on(rows(&db, page?, neighbors, collection.as_deref())))
+}
+
+// Writes the pages out as they come, returning how many rows there were
+pub fn write_pages<W: Write + Send>(
+    pages: impl Iterator<Item = Result<Vec<GraphRow>>>,
+    format: GraphFormat,
+    mut writer: W,
+) -> Result<u64> {
+    match format {
+        GraphFormat::Jsonl => {
+            let mut count = 0;
+            for page in pages {
+                for row in page? {
+                    serde_json::to_writer(&mut writer, &row)?;
+                    writer.write_all(b"\n")?;
+                    count += 1;
+                }
+            }
+            writer.flush()?;
+            Ok(count)
+        }
+        GraphFormat::Parquet => write_parquet(pages, writer),
+    }
+}
+
+#[cfg(not(feature = "parquet"))]
+fn write_parquet<W: Write + Send>(
+    _pages: impl Iterator<Item = Result<Vec<GraphRow>>>,
+    _writer: W,
+) -> Result<u64> {
+    Err(anyhow!(
+        "Parquet graphs require building with --features parquet"
+    ))
+}
+
+#[cfg(feature = "parquet")]
+fn write_parquet<W: Write + Send>(
+    pages: impl Iterator<Item = Result<Vec<GraphRow>>>,
+    writer: W,
+) -> Result<u64> {
+    use arrow_array::builder::{Float32Builder, ListBuilder, StringBuilder};
+    use arrow_array::{ArrayRef, RecordBatch};
+
+    let batch = |rows: &[GraphRow]| -> Result<RecordBatch> {
+        let mut ids = StringBuilder::new();
+        let mut neighbor_ids = ListBuilder::new(StringBuilder::new());
+        let mut scores = ListBuilder::new(Float32Builder::new());
+        for row in rows {
+            ids.append_value(&row.id);
+            for neighbor in &row.neighbors {
+                neighbor_ids.values().append_value(&neighbor.id);
+                scores.values().append_value(neighbor.score);
+            }
+            neighbor_ids.append(true);
+            scores.append(true);
+        }
+        Ok(RecordBatch::try_from_iter_with_nullable([
+            ("id", Arc::new(ids.finish()) as ArrayRef, false),
+            (
+                "neighbor_ids",
+                Arc::new(neighbor_ids.finish()) as ArrayRef,
+                false,
+            ),
+            ("scores", Arc::new(scores.finish()) as ArrayRef, false),
+        ])?)
+    };
+
+    let mut writer = parquet::arrow::ArrowWriter::try_new(writer, batch(&[])?.schema(), None)?;
+    let mut count = 0;
+    for page in pages {
+        let page = page?;
+        count += page.len() as u64;
+        writer.write(&batch(&page)?)?;
+    }
+    writer.into_inner()?.flush()?;
+    Ok(count)
+}
+
+// Computes and encodes on a blocking thread while the body is being sent
+pub fn stream(
+    pages: impl Iterator<Item = Result<Vec<GraphRow>>> + Send + 'static,
+    format: GraphFormat,
+) -> Body {
+    export::stream_with(move |writer| write_pages(pages, format, writer))
+}
+
+// `skypier-vecdb knn-graph`: writes a stopped instance's k-NN graph to a file
+pub async fn run(
+    config: &Config,
+    output: &str,
+    format: GraphFormat,
+    neighbors: usize,
+    collection: Option<&str>,
+) -> Result<()> {
+    let started = Instant::now();
+    let db = Arc::new(config.open_database(&config.storage.data_dir).await?);
+    let pages = pages(db, collection.map(str::to_string), neighbors);
+
+    let file =
+        std::fs::File::create(output).map_err(|e| anyhow!("Failed to create {}: {}", output, e))?;
+    let count = tokio::task::spawn_blocking(move || {
+        write_pages(pages, format, std::io::BufWriter::new(file))
+    })
+    .await??;
+    info!(
+        "Wrote the {}-nearest-neighbor graph of {} vectors to {} in {:?}",
+        neighbors,
+        count,
+        output,
+        started.elapsed()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use skypier_core::VectorDatabase;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_graph_rows_leave_out_self() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Arc::new(
+            VectorDatabase::new(dir.path().to_str().unwrap())
+                .await
+                .unwrap(),
+        );
+        db.insert_vectors(vec![
+            Vector::with_id("a".to_string(), vec![1.0, 0.0]),
+            Vector::with_id("b".to_string(), vec![0.9, 0.1]),
+            Vector::with_id("c".to_string(), vec![-1.0, 0.0]),
+            Vector::with_id("d".to_string(), vec![0.0, 1.0]).with_collection("other".to_string()),
+        ])
+        .await
+        .unwrap();
+
+        let graph = tokio::task::spawn_blocking({
+            let db = Arc::clone(&db);
+            move || {
+                let mut jsonl = Vec::new();
+                let count = write_pages(pages(db, None, 2), GraphFormat::Jsonl, &mut jsonl);
+                (count.unwrap(), jsonl)
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(graph.0, 4);
+        let rows: Vec<GraphRow> = std::str::from_utf8(&graph.1)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(rows[0].id, "a");
+        let ids: Vec<&str> = rows[0].neighbors.iter().map(|n| n.id.as_str()).collect();
+        assert_eq!(ids, vec!["b", "d"]);
+        // Even the farthest vectors are neighbors
+        assert_eq!(rows[2].neighbors.len(), 2);
+
+        let rows = rows_in(&db, "other").await;
+        assert_eq!(rows.len(), 1);
+        assert!(rows[0].neighbors.is_empty());
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_parquet_graph() {
+        use parquet::file::reader::{FileReader, SerializedFileReader};
+
+        let row = GraphRow {
+            id: "a".to_string(),
+            neighbors: vec![Neighbor {
+                id: "b".to_string(),
+                score: 0.5,
+            }],
+        };
+        let mut file = Vec::new();
+        let pages = std::iter::once(Ok(vec![row.clone(), row]));
+        assert_eq!(
+            write_pages(pages, GraphFormat::Parquet, &mut file).unwrap(),
+            2
+        );
+        let reader = SerializedFileReader::new(axum::body::Bytes::from(file)).unwrap();
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 2);
+    }
+
+    async fn rows_in(db: &Arc<VectorDatabase>, collection: &str) -> Vec<GraphRow> {
+        let page = db.scroll(Some(collection), None, 10).await.unwrap().vectors;
+        rows(db, page, 2, Some(collection)).await.unwrap()
+    }
+}
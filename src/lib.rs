@@ -0,0 +1,32 @@
+// The server's modules, so Rust applications can embed the database
+// in-process instead of talking to it over HTTP. See `embedded::Builder`.
+
+pub mod api;
+pub mod audit;
+pub mod auth;
+pub mod backup;
+pub mod bench;
+pub mod build_index;
+pub mod cluster;
+pub mod codec;
+pub mod compat;
+pub mod config;
+pub mod dataset;
+pub mod embedded;
+#[cfg(feature = "embeddings")]
+pub mod embeddings;
+pub mod export;
+pub mod import;
+pub mod knn_graph;
+pub mod namespace;
+pub mod rate_limit;
+pub mod raw;
+pub mod replication;
+pub mod replicator;
+pub mod slow_queries;
+pub mod tune;
+#[cfg(feature = "webhooks")]
+pub mod webhooks;
+
+pub use embedded::{serve, Builder};
+pub use skypier_core::{DistanceMetric, SearchResult, Vector, VectorDatabase};
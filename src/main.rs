@@ -3,11 +3,18 @@ use clap::{Arg, Command};
 use skypier_core::VectorDatabase;
 use skypier_network::P2PNode;
 use std::sync::Arc;
-use tokio;
-use tracing::{info, warn};
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{error, info, warn};
 
-mod api;
-mod config;
+#[cfg(feature = "embeddings")]
+use skypier_vecdb::embeddings;
+#[cfg(feature = "webhooks")]
+use skypier_vecdb::webhooks;
+use skypier_vecdb::{
+    api, audit, auth, backup, bench, build_index, cluster, config, dataset, export, import,
+    knn_graph, namespace, rate_limit, replication, replicator, slow_queries, tune,
+};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -31,65 +38,769 @@ async fn main() -> Result<()> {
                 .short('p')
                 .long("port")
                 .value_name("PORT")
-                .help("Sets the HTTP server port")
-                .default_value("8080"),
+                .help("Sets the HTTP server port (overrides the config file)"),
         )
         .arg(
             Arg::new("p2p-port")
                 .long("p2p-port")
                 .value_name("PORT")
-                .help("Sets the P2P network port")
-                .default_value("7777"),
+                .help("Sets the P2P network port (overrides the config file)"),
+        )
+        .arg(
+            Arg::new("follow")
+                .long("follow")
+                .value_name("ADDR")
+                .help("Follows the primary at this P2P address read-only (overrides the config file)"),
+        )
+        .subcommand(
+            Command::new("tune")
+                .about("Sweeps index parameters on a sample dataset and recommends settings")
+                .arg(
+                    Arg::new("dataset")
+                        .long("dataset")
+                        .value_name("FILE")
+                        .help("JSON file of sample vectors")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("recall")
+                        .long("recall")
+                        .value_name("RECALL")
+                        .help("Minimum recall@k, between 0 and 1")
+                        .default_value("0.95"),
+                )
+                .arg(
+                    Arg::new("latency-ms")
+                        .long("latency-ms")
+                        .value_name("MS")
+                        .help("Maximum p95 search latency in milliseconds")
+                        .default_value("10"),
+                )
+                .arg(
+                    Arg::new("k")
+                        .short('k')
+                        .value_name("K")
+                        .help("Number of neighbors per query")
+                        .default_value("10"),
+                )
+                .arg(
+                    Arg::new("queries")
+                        .long("queries")
+                        .value_name("N")
+                        .help("Vectors sampled as queries")
+                        .default_value("100"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .value_name("FILE")
+                        .help("Writes the recommended [index] section here instead of stdout"),
+                ),
+        )
+        .subcommand(
+            Command::new("bench")
+                .about("Measures a stopped instance's index on its own data")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("recall")
+                        .about("Prints recall@k and latency per ef_search, against an exact scan")
+                        .arg(
+                            Arg::new("k")
+                                .short('k')
+                                .value_name("K")
+                                .help("Number of neighbors per query")
+                                .default_value("10"),
+                        )
+                        .arg(
+                            Arg::new("queries")
+                                .long("queries")
+                                .value_name("N")
+                                .help("Stored vectors sampled as queries")
+                                .default_value("100"),
+                        )
+                        .arg(
+                            Arg::new("ef-search")
+                                .long("ef-search")
+                                .value_name("LIST")
+                                .help("Comma-separated ef_search values to try")
+                                .default_value("16,32,64,128,256"),
+                        )
+                        .arg(
+                            Arg::new("data-dir")
+                                .long("data-dir")
+                                .value_name("DIR")
+                                .help("Data dir to read (defaults to storage.data_dir)"),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("build-index")
+                .about("Loads a dataset into a new data dir and builds its index offline")
+                .arg(
+                    Arg::new("input")
+                        .long("input")
+                        .value_name("FILE")
+                        .help("Parquet (with --features parquet) or JSON file of vectors")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .value_name("DIR")
+                        .help("Data dir to create (defaults to storage.data_dir)"),
+                ),
+        )
+        .subcommand(
+            Command::new("import")
+                .about("Loads a Parquet or CSV file into a stopped instance's data dir")
+                .arg(
+                    Arg::new("input")
+                        .long("input")
+                        .value_name("FILE")
+                        .help("Parquet (with --features parquet) or CSV file; columns are mapped by [import]")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .help("parquet, csv or json (defaults from the file extension)"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .value_name("DIR")
+                        .help("Data dir to load into (defaults to storage.data_dir)"),
+                ),
+        )
+        .subcommand(
+            Command::new("export")
+                .about("Writes a stopped instance's vectors to a Parquet or Arrow file")
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .value_name("FILE")
+                        .help("File to write (needs --features parquet)")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .help("parquet or arrow (defaults from the file extension)"),
+                )
+                .arg(
+                    Arg::new("collection")
+                        .long("collection")
+                        .value_name("NAME")
+                        .help("Only exports this collection"),
+                ),
+        )
+        .subcommand(
+            Command::new("knn-graph")
+                .about("Writes each of a stopped instance's vectors' nearest neighbors to a JSONL or Parquet file")
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .value_name("FILE")
+                        .help("File to write")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("neighbors")
+                        .short('n')
+                        .long("neighbors")
+                        .value_name("N")
+                        .help("Neighbors per vector")
+                        .default_value("10"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .help("jsonl or parquet (defaults from the file extension; parquet needs --features parquet)"),
+                )
+                .arg(
+                    Arg::new("collection")
+                        .long("collection")
+                        .value_name("NAME")
+                        .help("Only this collection's vectors, and only as each other's neighbors"),
+                ),
+        )
+        .subcommand(
+            Command::new("backup")
+                .about("Backs up a stopped instance's data dir")
+                .arg(
+                    Arg::new("to")
+                        .long("to")
+                        .value_name("DEST")
+                        .help("Local directory, or s3://, gs:// or az:// URL (with --features object-store)")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("incremental")
+                        .long("incremental")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("Adds to the incremental backup set at DEST instead of copying everything"),
+                ),
+        )
+        .subcommand(
+            Command::new("verify")
+                .about("Checks every vector in a stopped instance's data dir against its checksum")
+                .arg(
+                    Arg::new("data-dir")
+                        .long("data-dir")
+                        .value_name("DIR")
+                        .help("Data dir to check (defaults to storage.data_dir)"),
+                ),
+        )
+        .subcommand(
+            Command::new("restore")
+                .about("Restores a backup into an empty data dir")
+                .arg(
+                    Arg::new("from")
+                        .long("from")
+                        .value_name("SOURCE")
+                        .help("Local directory, or s3://, gs:// or az:// URL (with --features object-store)")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .value_name("DIR")
+                        .help("Data dir to fill (defaults to storage.data_dir)"),
+                )
+                .arg(
+                    Arg::new("to-seq")
+                        .long("to-seq")
+                        .value_name("SEQ")
+                        .help("Restores an incremental backup set as of the last backup at or before SEQ"),
+                ),
         )
         .get_matches();
 
+    if let Some(tune_matches) = matches.subcommand_matches("tune") {
+        let targets = tune::TuneTargets {
+            recall: tune_matches.get_one::<String>("recall").unwrap().parse()?,
+            max_latency_ms: tune_matches
+                .get_one::<String>("latency-ms")
+                .unwrap()
+                .parse()?,
+            k: tune_matches.get_one::<String>("k").unwrap().parse()?,
+        };
+        return tune::run(
+            tune_matches.get_one::<String>("dataset").unwrap(),
+            tune_matches.get_one::<String>("queries").unwrap().parse()?,
+            &targets,
+            tune_matches.get_one::<String>("output").map(String::as_str),
+        );
+    }
+
     let config_file = matches.get_one::<String>("config").unwrap().clone();
-    let http_port = matches.get_one::<String>("port").unwrap().clone();
-    let p2p_port = matches.get_one::<String>("p2p-port").unwrap().clone();
+    let mut config = config::Config::load(&config_file)?;
+    if let Some(port) = matches.get_one::<String>("port") {
+        config.server.port = port.parse()?;
+    }
+    if let Some(port) = matches.get_one::<String>("p2p-port") {
+        config.p2p.port = port.parse()?;
+    }
+    if let Some(primary) = matches.get_one::<String>("follow") {
+        config.replication.primary = Some(primary.clone());
+    }
+
+    if let Some(("recall", recall_matches)) = matches
+        .subcommand_matches("bench")
+        .and_then(|bench| bench.subcommand())
+    {
+        let ef_searches = recall_matches
+            .get_one::<String>("ef-search")
+            .unwrap()
+            .split(',')
+            .map(|ef| ef.trim().parse())
+            .collect::<Result<Vec<usize>, _>>()?;
+        let data_dir = recall_matches
+            .get_one::<String>("data-dir")
+            .unwrap_or(&config.storage.data_dir);
+        return bench::run(
+            &config,
+            data_dir,
+            recall_matches
+                .get_one::<String>("queries")
+                .unwrap()
+                .parse()?,
+            recall_matches.get_one::<String>("k").unwrap().parse()?,
+            &ef_searches,
+        )
+        .await;
+    }
+
+    if let Some(build_matches) = matches.subcommand_matches("build-index") {
+        let output = build_matches
+            .get_one::<String>("output")
+            .unwrap_or(&config.storage.data_dir);
+        return build_index::run(
+            &config,
+            build_matches.get_one::<String>("input").unwrap(),
+            output,
+        )
+        .await;
+    }
+
+    if let Some(import_matches) = matches.subcommand_matches("import") {
+        let input = import_matches.get_one::<String>("input").unwrap();
+        let format = match import_matches.get_one::<String>("format") {
+            Some(format) => format.parse()?,
+            None => dataset::DatasetFormat::for_path(input),
+        };
+        let output = import_matches
+            .get_one::<String>("output")
+            .unwrap_or(&config.storage.data_dir);
+        return import::run(&config, input, format, output).await;
+    }
+
+    if let Some(export_matches) = matches.subcommand_matches("export") {
+        let output = export_matches.get_one::<String>("output").unwrap();
+        let format = match export_matches.get_one::<String>("format") {
+            Some(format) => format.parse()?,
+            None => export::ExportFormat::for_path(output),
+        };
+        return export::run(
+            &config,
+            output,
+            format,
+            export_matches
+                .get_one::<String>("collection")
+                .map(String::as_str),
+        )
+        .await;
+    }
+
+    if let Some(graph_matches) = matches.subcommand_matches("knn-graph") {
+        let output = graph_matches.get_one::<String>("output").unwrap();
+        let format = match graph_matches.get_one::<String>("format") {
+            Some(format) => format.parse()?,
+            None => knn_graph::GraphFormat::for_path(output),
+        };
+        return knn_graph::run(
+            &config,
+            output,
+            format,
+            graph_matches
+                .get_one::<String>("neighbors")
+                .unwrap()
+                .parse()?,
+            graph_matches
+                .get_one::<String>("collection")
+                .map(String::as_str),
+        )
+        .await;
+    }
+
+    if let Some(backup_matches) = matches.subcommand_matches("backup") {
+        let data_dir = &config.storage.data_dir;
+        let db = VectorDatabase::from_storage(config.storage.open(data_dir).await?, data_dir)?;
+        let destination = backup_matches.get_one::<String>("to").unwrap();
+        if backup_matches.get_flag("incremental") {
+            backup::backup_incremental(&db, destination).await?;
+            return Ok(());
+        }
+        return backup::backup(&db, destination).await;
+    }
+
+    if let Some(verify_matches) = matches.subcommand_matches("verify") {
+        let data_dir = verify_matches
+            .get_one::<String>("data-dir")
+            .unwrap_or(&config.storage.data_dir);
+        let report = config.storage.open(data_dir).await?.verify().await?;
+        for record in &report.corrupt {
+            error!("Vector {} is corrupt: {}", record.id, record.error);
+        }
+        info!("Checked {} vectors in {}", report.checked, data_dir);
+        if !report.corrupt.is_empty() {
+            anyhow::bail!(
+                "{} corrupt vectors; POST /admin/verify?repair=true on a running cluster node repairs them from replicas",
+                report.corrupt.len()
+            );
+        }
+        return Ok(());
+    }
+
+    if let Some(restore_matches) = matches.subcommand_matches("restore") {
+        let output = restore_matches
+            .get_one::<String>("output")
+            .unwrap_or(&config.storage.data_dir);
+        let to_seq = restore_matches
+            .get_one::<String>("to-seq")
+            .map(|seq| seq.parse())
+            .transpose()?;
+        return backup::restore(
+            &config,
+            restore_matches.get_one::<String>("from").unwrap(),
+            output,
+            to_seq,
+        )
+        .await;
+    }
 
     info!("Starting SkyPier VecDB");
     info!("Config file: {}", config_file);
-    info!("HTTP port: {}", http_port);
-    info!("P2P port: {}", p2p_port);
+    info!("HTTP port: {}", config.server.port);
+    info!("P2P port: {}", config.p2p.port);
 
     // Initialize the vector database
-    let db = Arc::new(VectorDatabase::new("./data").await?);
+    let data_dir = &config.storage.data_dir;
+    let db = Arc::new(config.open_database(data_dir).await?);
+
+    db.load_index().await?;
+    if config.index.warmup {
+        let started = std::time::Instant::now();
+        let bytes = db.warm_up().await?;
+        info!(
+            "Warmed up {} bytes of index in {:?}",
+            bytes,
+            started.elapsed()
+        );
+    }
+    if config.index.lock_memory {
+        lock_memory()?;
+        info!("Locked process memory");
+    }
+    let namespaces = Arc::new(namespace::Namespaces::from_config(Arc::clone(&db), &config).await);
+
+    // Flipped to true once to stop the HTTP server, the P2P node and
+    // background tasks
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+
+    let snapshot_handle = tokio::spawn(snapshot_index_periodically(
+        Arc::clone(&namespaces),
+        config.index.snapshot_interval_minutes,
+        wait_for_shutdown(shutdown_rx.clone()),
+    ));
 
     // Initialize P2P networking
     let network_config = skypier_network::NetworkConfig {
-        port: p2p_port.parse()?,
-        bootstrap_peers: vec![],
-        max_peers: 50,
+        port: config.p2p.port,
+        transport: config.p2p.transport,
+        bootstrap_peers: config.p2p.bootstrap_peers.clone(),
+        max_peers: config.p2p.max_peers,
+        probe_interval: Duration::from_millis(config.p2p.probe_interval_ms),
+        probe_timeout: Duration::from_millis(config.p2p.probe_timeout_ms),
+        suspicion_timeout: Duration::from_millis(config.p2p.suspicion_timeout_ms),
+        // Read-only followers take writes only once promoted, so they own
+        // nothing
+        shard_owner: config.cluster.enabled
+            && (config.replication.primary.is_none() || config.replication.writable),
+        key_file: Some(std::path::Path::new(&config.storage.data_dir).join("node.key")),
+        allowed_peers: config.p2p.allowed_peers.clone(),
+        denied_peers: config.p2p.denied_peers.clone(),
+        labels: config.p2p.labels.clone(),
+        relays: config.p2p.relays.clone(),
+        relay_server: config.p2p.relay_server,
+        mdns: config.p2p.mdns,
     };
     let mut p2p_node = P2PNode::new(network_config).await?;
-    let p2p_handle = tokio::spawn(async move {
-        if let Err(e) = p2p_node.start().await {
+    let cluster = p2p_node.handle();
+    if config.replication.serve {
+        let source = replication::ReplicationSource::new(Arc::clone(&namespaces));
+        p2p_node = p2p_node.with_handler(Arc::new(source));
+    }
+    let placement = Arc::new(config.cluster.placement());
+    let replicator = Arc::new(replicator::Replicator::from_config(
+        &config.cluster.throttle,
+    ));
+    if config.cluster.enabled {
+        let target = cluster::ForwardTarget::new(
+            Arc::clone(&namespaces),
+            cluster.clone(),
+            Arc::clone(&placement),
+            Arc::clone(&replicator),
+        );
+        p2p_node = p2p_node.with_forwarder(Arc::new(target));
+    }
+    let follower = config.replication.primary.as_ref().map(|primary| {
+        Arc::new(
+            replication::Follower::new(primary, cluster.clone(), Arc::clone(&namespaces))
+                .with_batch_size(config.replication.batch_size)
+                .with_poll_interval(Duration::from_millis(config.replication.poll_interval_ms))
+                .with_writable(config.replication.writable),
+        )
+    });
+    if follower.is_some() && !config.replication.writable {
+        namespaces.set_read_only(true).await;
+    }
+
+    let p2p_shutdown = wait_for_shutdown(shutdown_rx.clone());
+    let mut p2p_handle = tokio::spawn(async move {
+        if let Err(e) = p2p_node.run_until(p2p_shutdown).await {
             warn!("P2P node error: {}", e);
         }
     });
 
+    let replication_handle = follower
+        .clone()
+        .map(|follower| tokio::spawn(follower.run(wait_for_shutdown(shutdown_rx.clone()))));
+
+    #[cfg(feature = "webhooks")]
+    let webhook_handles = start_webhooks(&config, &namespaces, &shutdown_rx).await?;
+    #[cfg(not(feature = "webhooks"))]
+    let webhook_handles: Vec<tokio::task::JoinHandle<()>> = {
+        if !config.webhooks.is_empty() {
+            warn!("Webhooks are configured but this build lacks the webhooks feature");
+        }
+        Vec::new()
+    };
+
+    let mut state = api::AppState::new(Arc::clone(&db))
+        .with_namespaces(Arc::clone(&namespaces))
+        .with_cluster(cluster)
+        .with_cluster_mode(config.cluster.enabled)
+        .with_placement(placement)
+        .with_replicator(replicator)
+        .with_max_body_bytes(config.server.max_body_bytes)
+        .with_request_limits(
+            (config.server.request_timeout_ms > 0)
+                .then(|| Duration::from_millis(config.server.request_timeout_ms)),
+            (config.server.max_concurrent_requests > 0)
+                .then_some(config.server.max_concurrent_requests),
+        );
+    if config.auth.enabled {
+        let admin_key = match &config.auth.admin_key_env {
+            Some(var) => std::env::var(var).ok(),
+            None => None,
+        };
+        let keys = auth::ApiKeys::load(
+            Arc::clone(&db),
+            &config.auth.key_header,
+            admin_key.as_deref(),
+        )
+        .await?;
+        if admin_key.is_none() && keys.list().await.is_empty() {
+            warn!("API keys are required but none exist; set the admin key variable");
+        }
+        state = state.with_auth(Arc::new(keys));
+    }
+    if config.rate_limit.enabled {
+        let limiter = rate_limit::RateLimiter::from_config(&config.rate_limit);
+        state = state.with_rate_limiter(Arc::new(limiter));
+    }
+    if config.slow_queries.threshold_ms > 0 {
+        let log = slow_queries::SlowQueryLog::new(
+            std::time::Duration::from_millis(config.slow_queries.threshold_ms),
+            config.slow_queries.buffer_size,
+        );
+        state = state.with_slow_queries(Arc::new(log));
+    }
+    let audit_handle = if config.audit.enabled {
+        let log = Arc::new(audit::AuditLog::from_config(Arc::clone(&db), &config.audit));
+        state = state.with_audit(Arc::clone(&log));
+        (config.audit.retain_days > 0).then(|| {
+            tokio::spawn(prune_audit_periodically(
+                log,
+                wait_for_shutdown(shutdown_rx.clone()),
+            ))
+        })
+    } else {
+        None
+    };
+    if let Some(follower) = follower {
+        state = state.with_follower(follower);
+    }
+    state = state.with_text_field(&config.embeddings.text_field);
+    #[cfg(feature = "embeddings")]
+    if let Some(embedder) = embeddings::from_config(&config.embeddings)? {
+        info!("Embeddings gateway enabled ({})", config.embeddings.backend);
+        state = state.with_embedder(embedder);
+    }
+
     // Start HTTP API server
-    let api_handle = tokio::spawn({
-        let db = Arc::clone(&db);
-        async move {
-            if let Err(e) = api::start_server(db, http_port.parse().unwrap()).await {
-                warn!("API server error: {}", e);
-            }
+    let host = config.server.host.clone();
+    let port = config.server.port;
+    let tls = config.server.tls()?;
+    let api_shutdown = wait_for_shutdown(shutdown_rx);
+    let mut api_handle = tokio::spawn(async move {
+        if let Err(e) = api::start_server(state, &host, port, tls, api_shutdown).await {
+            warn!("API server error: {}", e);
         }
     });
 
-    // Wait for both services
+    // Run until a signal arrives or either service stops on its own
     tokio::select! {
-        _ = p2p_handle => {
+        _ = &mut p2p_handle => {
             info!("P2P node terminated");
         }
-        _ = api_handle => {
+        _ = &mut api_handle => {
             info!("API server terminated");
         }
-        _ = tokio::signal::ctrl_c() => {
-            info!("Received Ctrl+C, shutting down");
+        _ = shutdown_signal() => {
+            info!("Shutting down, draining in-flight requests");
         }
     }
+    let _ = shutdown_tx.send(true);
+
+    // One deadline for all of them, so the wait is bounded by the timeout
+    // however many tasks are running
+    let drain_timeout = Duration::from_secs(config.server.shutdown_timeout_secs);
+    let deadline = tokio::time::Instant::now() + drain_timeout;
+    let handles = [
+        ("API server", api_handle),
+        ("P2P node", p2p_handle),
+        ("Index snapshots", snapshot_handle),
+    ]
+    .into_iter()
+    .chain(replication_handle.map(|handle| ("Replication", handle)))
+    .chain(audit_handle.map(|handle| ("Audit retention", handle)))
+    .chain(
+        webhook_handles
+            .into_iter()
+            .map(|handle| ("Webhooks", handle)),
+    );
+    for (name, handle) in handles {
+        if handle.is_finished() {
+            continue;
+        }
+        let abort = handle.abort_handle();
+        if tokio::time::timeout_at(deadline, handle).await.is_err() {
+            warn!("{} did not stop within {:?}, aborting", name, drain_timeout);
+            abort.abort();
+        }
+    }
+
+    // No more requests are running, so nothing else holds the databases.
+    // This also takes a final index snapshot of each.
+    for (name, db) in namespaces.open().await {
+        db.shutdown().await?;
+        info!("Closed namespace '{}'", name);
+    }
+    drop((db, namespaces));
+    info!("Shutdown complete");
 
     Ok(())
 }
+
+async fn snapshot_index_periodically<F>(
+    namespaces: Arc<namespace::Namespaces>,
+    minutes: u64,
+    shutdown: F,
+) where
+    F: std::future::Future<Output = ()>,
+{
+    if minutes == 0 {
+        return;
+    }
+
+    let mut ticker = tokio::time::interval(Duration::from_secs(minutes * 60));
+    ticker.tick().await; // The first tick completes immediately
+    tokio::pin!(shutdown);
+
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                for (name, db) in namespaces.open().await {
+                    match db.snapshot_index().await {
+                        Ok(seq) => info!("Snapshotted '{}' index at WAL position {}", name, seq),
+                        Err(e) => warn!("Index snapshot of '{}' failed: {}", name, e),
+                    }
+                }
+            }
+            _ = &mut shutdown => break,
+        }
+    }
+}
+
+// Drops audit entries past their retention period at startup and hourly
+async fn prune_audit_periodically<F>(log: Arc<audit::AuditLog>, shutdown: F)
+where
+    F: std::future::Future<Output = ()>,
+{
+    let mut ticker = tokio::time::interval(Duration::from_secs(60 * 60));
+    tokio::pin!(shutdown);
+
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => match log.prune().await {
+                Ok(0) => {}
+                Ok(pruned) => info!("Pruned {} audit entries past retention", pruned),
+                Err(e) => warn!("Pruning the audit log failed: {}", e),
+            },
+            _ = &mut shutdown => break,
+        }
+    }
+}
+
+// Keeps everything mapped now and later resident, so index pages are never
+// swapped out
+#[cfg(unix)]
+fn lock_memory() -> Result<()> {
+    // SAFETY: mlockall takes no pointers and only changes paging
+    if unsafe { libc::mlockall(libc::MCL_CURRENT | libc::MCL_FUTURE) } != 0 {
+        return Err(anyhow::anyhow!(
+            "Failed to lock memory, is the memlock limit (ulimit -l) high enough? {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn lock_memory() -> Result<()> {
+    Err(anyhow::anyhow!("lock_memory is only supported on unix"))
+}
+
+// A task per configured webhook, sending its namespace's changes
+#[cfg(feature = "webhooks")]
+async fn start_webhooks(
+    config: &config::Config,
+    namespaces: &namespace::Namespaces,
+    shutdown_rx: &watch::Receiver<bool>,
+) -> Result<Vec<tokio::task::JoinHandle<()>>> {
+    let mut handles = Vec::new();
+    for webhook in &config.webhooks {
+        let webhook = webhooks::Webhook::from_config(webhook, namespaces.default_name())?;
+        let Some(db) = namespaces.get(webhook.namespace()).await? else {
+            return Err(anyhow::anyhow!(
+                "Webhook namespace '{}' isn't enabled",
+                webhook.namespace()
+            ));
+        };
+        handles.push(tokio::spawn(
+            webhook.run(db, wait_for_shutdown(shutdown_rx.clone())),
+        ));
+    }
+    Ok(handles)
+}
+
+async fn wait_for_shutdown(mut shutdown: watch::Receiver<bool>) {
+    // An error means the sender is gone, which is a shutdown too
+    let _ = shutdown.wait_for(|stop| *stop).await;
+}
+
+// Resolves on Ctrl+C, or SIGTERM on Unix (e.g. from `docker stop`)
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            warn!("Failed to listen for Ctrl+C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                warn!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
@@ -0,0 +1,244 @@
+use anyhow::{anyhow, Result};
+use skypier_core::{Quota, VectorDatabase};
+use std::collections::{BTreeSet, HashMap};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::info;
+
+use crate::config::{Config, NamespaceQuota};
+
+// Tenants sit above collections. Each namespace has its own storage and
+// index under `<data_dir>/namespaces/<name>`, opened on first use; the
+// default namespace is the data dir itself, so existing data stays put.
+// Namespaces exist once configured or created by an admin; requests name
+// them but never make them.
+pub struct Namespaces {
+    default: String,
+    header: String,
+    databases: RwLock<HashMap<String, Arc<VectorDatabase>>>,
+    // None when namespaces are disabled and only the default one exists
+    config: Option<Config>,
+    quotas: HashMap<String, NamespaceQuota>,
+    // Followers open every namespace read-only
+    read_only: AtomicBool,
+}
+
+impl Namespaces {
+    // Serves `db` as the only, default namespace
+    pub fn single(db: Arc<VectorDatabase>) -> Self {
+        let config = Config::default();
+        Self::with_default(db, &config.namespaces.default, &config.namespaces.header)
+    }
+
+    pub async fn from_config(db: Arc<VectorDatabase>, config: &Config) -> Self {
+        let mut namespaces = Self::with_default(
+            Arc::clone(&db),
+            &config.namespaces.default,
+            &config.namespaces.header,
+        );
+        namespaces.quotas = config.namespaces.quotas.clone();
+        if config.namespaces.enabled {
+            namespaces.config = Some(config.clone());
+        }
+        namespaces
+            .apply_quota(&config.namespaces.default, &db)
+            .await;
+        namespaces
+    }
+
+    fn with_default(db: Arc<VectorDatabase>, default: &str, header: &str) -> Self {
+        Self {
+            default: default.to_string(),
+            header: header.to_string(),
+            databases: RwLock::new(HashMap::from([(default.to_string(), db)])),
+            config: None,
+            quotas: HashMap::new(),
+            read_only: AtomicBool::new(false),
+        }
+    }
+
+    pub fn default_name(&self) -> &str {
+        &self.default
+    }
+
+    // Request header that selects a namespace
+    pub fn header(&self) -> &str {
+        &self.header
+    }
+
+    pub fn quota(&self, name: &str) -> Option<&NamespaceQuota> {
+        self.quotas.get(name)
+    }
+
+    // The database enforces the quota itself, under its write lock
+    async fn apply_quota(&self, name: &str, db: &VectorDatabase) {
+        let quota = self.quota(name).map(|quota| Quota {
+            max_vectors: quota.max_vectors.map(|max| max as u64),
+            max_bytes: quota.max_bytes,
+        });
+        db.set_quota(&format!("Namespace '{}'", name), quota).await;
+    }
+
+    // Opens the namespace the first time it's asked for. Ok(None) when it
+    // doesn't exist, or namespaces are disabled and `name` isn't the default.
+    pub async fn get(&self, name: &str) -> Result<Option<Arc<VectorDatabase>>> {
+        if let Some(db) = self.databases.read().await.get(name) {
+            return Ok(Some(Arc::clone(db)));
+        }
+        let Some(config) = &self.config else {
+            return Ok(None);
+        };
+        if !valid_name(name) {
+            return Err(anyhow!("Invalid namespace name '{}'", name));
+        }
+        if !config.namespaces.names.iter().any(|known| known == name)
+            && !namespace_dir(config, name).is_dir()
+        {
+            return Ok(None);
+        }
+        self.open_namespace(config, name).await.map(Some)
+    }
+
+    // Makes the namespace if it doesn't exist yet, and opens it. Ok(None)
+    // when namespaces are disabled.
+    pub async fn create(&self, name: &str) -> Result<Option<Arc<VectorDatabase>>> {
+        if let Some(db) = self.databases.read().await.get(name) {
+            return Ok(Some(Arc::clone(db)));
+        }
+        let Some(config) = &self.config else {
+            return Ok(None);
+        };
+        if !valid_name(name) {
+            return Err(anyhow!("Invalid namespace name '{}'", name));
+        }
+        self.open_namespace(config, name).await.map(Some)
+    }
+
+    async fn open_namespace(&self, config: &Config, name: &str) -> Result<Arc<VectorDatabase>> {
+        let mut databases = self.databases.write().await;
+        if let Some(db) = databases.get(name) {
+            return Ok(Arc::clone(db));
+        }
+        let data_dir = namespace_dir(config, name);
+        let db = Arc::new(config.open_database(&data_dir.to_string_lossy()).await?);
+        db.load_index().await?;
+        if config.index.warmup {
+            db.warm_up().await?;
+        }
+        db.set_read_only(self.read_only.load(Ordering::Acquire));
+        self.apply_quota(name, &db).await;
+        info!("Opened namespace '{}'", name);
+        databases.insert(name.to_string(), Arc::clone(&db));
+        Ok(db)
+    }
+
+    // Open and configured namespaces, plus any with data on disk that
+    // haven't been used yet
+    pub async fn names(&self) -> Result<Vec<String>> {
+        let mut names: BTreeSet<String> = self.databases.read().await.keys().cloned().collect();
+        if let Some(config) = &self.config {
+            names.extend(config.namespaces.names.iter().cloned());
+            let root = PathBuf::from(&config.storage.data_dir).join("namespaces");
+            if root.is_dir() {
+                for entry in std::fs::read_dir(root)? {
+                    let name = entry?.file_name().to_string_lossy().into_owned();
+                    if valid_name(&name) {
+                        names.insert(name);
+                    }
+                }
+            }
+        }
+        Ok(names.into_iter().collect())
+    }
+
+    // Applies to namespaces opened later too
+    pub async fn set_read_only(&self, read_only: bool) {
+        let databases = self.databases.read().await;
+        self.read_only.store(read_only, Ordering::Release);
+        for db in databases.values() {
+            db.set_read_only(read_only);
+        }
+    }
+
+    // Every namespace opened so far, for snapshots and shutdown
+    pub async fn open(&self) -> Vec<(String, Arc<VectorDatabase>)> {
+        self.databases
+            .read()
+            .await
+            .iter()
+            .map(|(name, db)| (name.clone(), Arc::clone(db)))
+            .collect()
+    }
+}
+
+// Names become directory names, so they're kept to a safe alphabet
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn namespace_dir(config: &Config, name: &str) -> PathBuf {
+    PathBuf::from(&config.storage.data_dir)
+        .join("namespaces")
+        .join(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_names() {
+        assert!(valid_name("acme-prod_2"));
+        assert!(!valid_name(""));
+        assert!(!valid_name("../etc"));
+        assert!(!valid_name(&"a".repeat(65)));
+    }
+
+    #[tokio::test]
+    async fn test_namespaces_open_separately() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.storage.data_dir = temp_dir.path().to_str().unwrap().to_string();
+        config.namespaces.enabled = true;
+        let default = Arc::new(
+            config
+                .open_database(&config.storage.data_dir)
+                .await
+                .unwrap(),
+        );
+        let namespaces = Namespaces::from_config(Arc::clone(&default), &config).await;
+
+        // Asking for a namespace doesn't make it
+        assert!(namespaces.get("acme").await.unwrap().is_none());
+        assert!(!temp_dir.path().join("namespaces/acme").exists());
+        let acme = namespaces.create("acme").await.unwrap().unwrap();
+        assert!(!Arc::ptr_eq(&acme, &default));
+        assert!(Arc::ptr_eq(
+            &acme,
+            &namespaces.get("acme").await.unwrap().unwrap()
+        ));
+        assert!(temp_dir.path().join("namespaces/acme").is_dir());
+        assert!(namespaces.get("../acme").await.is_err());
+        assert!(namespaces.create("../acme").await.is_err());
+        assert_eq!(namespaces.names().await.unwrap(), vec!["acme", "default"]);
+
+        // Configured ones and ones left on disk open on first use
+        drop((acme, namespaces));
+        config.namespaces.names = vec!["beta".to_string()];
+        let reopened = Namespaces::from_config(Arc::clone(&default), &config).await;
+        assert!(reopened.get("acme").await.unwrap().is_some());
+        assert!(reopened.get("beta").await.unwrap().is_some());
+        assert!(reopened.get("gamma").await.unwrap().is_none());
+
+        let single = Namespaces::single(default);
+        assert!(single.get("acme").await.unwrap().is_none());
+        assert!(single.get("default").await.unwrap().is_some());
+        assert!(single.create("acme").await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::auth;
+use crate::config::{RateLimit, RateLimitConfig};
+
+// The most buckets kept. Past it, refilled buckets are dropped first, then
+// the longest idle ones.
+const MAX_BUCKETS: usize = 10_000;
+
+// Who a request is limited as
+#[derive(Debug, Clone, Copy)]
+pub enum Client<'a> {
+    // The id of an API key that was accepted
+    Key(&'a str),
+    // Requests without a key, per address within their namespace, at the
+    // namespace's limit
+    Peer(IpAddr, &'a str),
+    // Requests without a key from an unknown address
+    Namespace(&'a str),
+}
+
+impl Client<'_> {
+    fn bucket(&self) -> String {
+        match self {
+            Client::Key(key_id) => format!("key:{}", key_id),
+            Client::Peer(ip, namespace) => format!("ip:{}:{}", namespace, ip),
+            Client::Namespace(namespace) => format!("namespace:{}", namespace),
+        }
+    }
+}
+
+// A token bucket per client. Each request takes a token; tokens come back
+// at `requests_per_second` up to `burst`.
+pub struct RateLimiter {
+    default: RateLimit,
+    // By key id, for keys listed by id or by the key itself
+    keys: HashMap<String, RateLimit>,
+    namespaces: HashMap<String, RateLimit>,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    limit: RateLimit,
+}
+
+impl Bucket {
+    fn is_full(&self, now: Instant) -> bool {
+        let refilled = self.tokens
+            + now.saturating_duration_since(self.updated).as_secs_f64()
+                * self.limit.requests_per_second;
+        refilled >= self.limit.burst.max(1) as f64
+    }
+}
+
+impl RateLimiter {
+    pub fn from_config(config: &RateLimitConfig) -> Self {
+        let mut keys = config.keys.clone();
+        keys.extend(
+            config
+                .keys
+                .iter()
+                .map(|(key, limit)| (auth::key_id(key), limit.clone())),
+        );
+        Self {
+            default: config.default.clone(),
+            keys,
+            namespaces: config.namespaces.clone(),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn limit(&self, client: Client) -> &RateLimit {
+        let limit = match client {
+            Client::Key(key_id) => self.keys.get(key_id),
+            Client::Peer(_, namespace) | Client::Namespace(namespace) => {
+                self.namespaces.get(namespace)
+            }
+        };
+        limit.unwrap_or(&self.default)
+    }
+
+    // Takes a token for `client`, or says how long until one is free
+    pub fn check(&self, client: Client, now: Instant) -> Result<(), Duration> {
+        let limit = self.limit(client);
+        let burst = limit.burst.max(1) as f64;
+        let rate = limit.requests_per_second;
+        let name = client.bucket();
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_BUCKETS && !buckets.contains_key(&name) {
+            buckets.retain(|_, bucket| !bucket.is_full(now));
+            if buckets.len() >= MAX_BUCKETS {
+                let idlest = buckets
+                    .iter()
+                    .min_by_key(|(_, bucket)| bucket.updated)
+                    .map(|(name, _)| name.clone());
+                if let Some(idlest) = idlest {
+                    buckets.remove(&idlest);
+                }
+            }
+        }
+        let bucket = buckets.entry(name).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+            limit: limit.clone(),
+        });
+
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(burst);
+        bucket.updated = now;
+        bucket.limit = limit.clone();
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+        if rate <= 0.0 {
+            return Err(Duration::MAX);
+        }
+        Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_refills_at_rate() {
+        let mut config = RateLimitConfig {
+            default: RateLimit {
+                requests_per_second: 2.0,
+                burst: 2,
+            },
+            ..Default::default()
+        };
+        let big_limit = RateLimit {
+            requests_per_second: 100.0,
+            burst: 100,
+        };
+        config.keys.insert("big".to_string(), big_limit.clone());
+        config.namespaces.insert("wide".to_string(), big_limit);
+        let limiter = RateLimiter::from_config(&config);
+        let start = Instant::now();
+        let a = Client::Namespace("a");
+
+        assert!(limiter.check(a, start).is_ok());
+        assert!(limiter.check(a, start).is_ok());
+        let wait = limiter.check(a, start).unwrap_err();
+        assert_eq!(wait, Duration::from_millis(500));
+        assert!(limiter.check(Client::Namespace("b"), start).is_ok());
+        // Keys listed as themselves are found by id
+        let big = auth::key_id("big");
+        assert!((0..100).all(|_| limiter.check(Client::Key(&big), start).is_ok()));
+        // Key limits don't apply to a namespace of the same name, or the
+        // other way round
+        assert!(limiter.check(Client::Namespace("big"), start).is_ok());
+        assert!(limiter.check(Client::Namespace("big"), start).is_ok());
+        assert!(limiter.check(Client::Namespace("big"), start).is_err());
+        let wide = auth::key_id("wide");
+        assert_eq!(limiter.limit(Client::Key(&wide)).burst, 2);
+        // One address gets a bucket per namespace, at that namespace's limit
+        let ip = IpAddr::from([10, 0, 0, 1]);
+        assert!(limiter.check(Client::Peer(ip, "b"), start).is_ok());
+        assert!(limiter.check(Client::Peer(ip, "b"), start).is_ok());
+        assert!(limiter.check(Client::Peer(ip, "b"), start).is_err());
+        assert!((0..100).all(|_| limiter.check(Client::Peer(ip, "wide"), start).is_ok()));
+
+        assert!(limiter.check(a, start + Duration::from_millis(500)).is_ok());
+        assert!(limiter
+            .check(a, start + Duration::from_millis(500))
+            .is_err());
+    }
+
+    #[test]
+    fn test_buckets_are_bounded() {
+        let config = RateLimitConfig {
+            default: RateLimit {
+                requests_per_second: 0.0,
+                burst: 1,
+            },
+            ..Default::default()
+        };
+        let limiter = RateLimiter::from_config(&config);
+        let start = Instant::now();
+        // None of them ever refill, so the idlest are evicted
+        for i in 0..MAX_BUCKETS as u32 + 10 {
+            let ip = IpAddr::from(i.to_be_bytes());
+            let now = start + Duration::from_millis(i as u64);
+            assert!(limiter.check(Client::Peer(ip, "default"), now).is_ok());
+        }
+        assert_eq!(limiter.buckets.lock().unwrap().len(), MAX_BUCKETS);
+        let last = IpAddr::from((MAX_BUCKETS as u32 + 9).to_be_bytes());
+        assert!(limiter
+            .check(
+                Client::Peer(last, "default"),
+                start + Duration::from_secs(60)
+            )
+            .is_err());
+    }
+}
@@ -0,0 +1,165 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use skypier_core::{Dtype, Vector};
+use std::collections::HashMap;
+
+// The body of POST /vectors/raw: a 20-byte header, `count * dims` packed
+// little-endian values, then optional JSON with what else the vectors
+// carry. The header is the magic "SKYV", a version byte (1), a dtype byte
+// (0 f32, 1 f16, 2 bf16), two zero bytes, then dims, count and the JSON's
+// length in bytes, each a little-endian u32.
+pub const MAGIC: &[u8; 4] = b"SKYV";
+const VERSION: u8 = 1;
+const HEADER_LEN: usize = 20;
+
+// Per-vector lists are in the same order as the vectors and, when given,
+// as long. A missing or empty id is made up on insert.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SideChannel {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ids: Option<Vec<Option<String>>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Vec<Option<HashMap<String, String>>>>,
+    // Applied to every vector in the batch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub collection: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+}
+
+fn dtype_id(dtype: Dtype) -> u8 {
+    match dtype {
+        Dtype::F32 => 0,
+        Dtype::F16 => 1,
+        Dtype::Bf16 => 2,
+    }
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> usize {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap()) as usize
+}
+
+pub fn decode(body: &[u8]) -> Result<Vec<Vector>> {
+    if body.len() < HEADER_LEN || &body[..4] != MAGIC {
+        return Err(anyhow!("Body doesn't start with a raw vector header"));
+    }
+    if body[4] != VERSION {
+        return Err(anyhow!("Unsupported raw vector format version {}", body[4]));
+    }
+    let dtype = match body[5] {
+        0 => Dtype::F32,
+        1 => Dtype::F16,
+        2 => Dtype::Bf16,
+        other => return Err(anyhow!("Unknown raw vector dtype {}", other)),
+    };
+    let dims = read_u32(body, 8);
+    let count = read_u32(body, 12);
+    let json_len = read_u32(body, 16);
+    let data_len = dims
+        .checked_mul(count)
+        .and_then(|values| values.checked_mul(dtype.bytes_per_value()))
+        .ok_or_else(|| anyhow!("Raw vector batch is too large"))?;
+    if body.len() != HEADER_LEN + data_len + json_len {
+        return Err(anyhow!(
+            "Header describes {} bytes of {} {}-dimensional vectors and {} of JSON, body has {} after it",
+            data_len,
+            count,
+            dims,
+            json_len,
+            body.len() - HEADER_LEN
+        ));
+    }
+    if dims == 0 && count > 0 {
+        return Err(anyhow!("Raw vectors need at least one dimension"));
+    }
+
+    let (data, json) = body[HEADER_LEN..].split_at(data_len);
+    let side: SideChannel = if json.is_empty() {
+        SideChannel::default()
+    } else {
+        serde_json::from_slice(json)?
+    };
+    for (name, len) in [
+        ("ids", side.ids.as_ref().map(Vec::len)),
+        ("metadata", side.metadata.as_ref().map(Vec::len)),
+    ] {
+        if let Some(len) = len.filter(|&len| len != count) {
+            return Err(anyhow!("{} {} for {} vectors", len, name, count));
+        }
+    }
+
+    let values = dtype.decode(data)?;
+    let mut ids = side.ids.map(Vec::into_iter);
+    let mut metadata = side.metadata.map(Vec::into_iter);
+    Ok(values
+        .chunks_exact(dims.max(1))
+        .take(count)
+        .map(|data| {
+            let id = ids.as_mut().and_then(Iterator::next).flatten();
+            let mut vector = Vector::with_id(id.unwrap_or_default(), data.to_vec());
+            vector.metadata = metadata.as_mut().and_then(Iterator::next).flatten();
+            vector.collection = side.collection.clone();
+            vector.model = side.model.clone();
+            vector
+        })
+        .collect())
+}
+
+// A raw body for vectors of the same dimensions, e.g. for clients and tests
+pub fn encode(data: &[Vec<f32>], dtype: Dtype, side: &SideChannel) -> Result<Vec<u8>> {
+    let dims = data.first().map_or(0, Vec::len);
+    if data.iter().any(|vector| vector.len() != dims) {
+        return Err(anyhow!("Raw vectors must all have the same dimensions"));
+    }
+    let json = serde_json::to_vec(side)?;
+    let mut body = Vec::with_capacity(HEADER_LEN + data.len() * dims * 4 + json.len());
+    body.extend_from_slice(MAGIC);
+    body.extend_from_slice(&[VERSION, dtype_id(dtype), 0, 0]);
+    for value in [dims, data.len(), json.len()] {
+        body.extend_from_slice(&(value as u32).to_le_bytes());
+    }
+    for vector in data {
+        body.extend_from_slice(&dtype.encode(vector));
+    }
+    body.extend_from_slice(&json);
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_raw_round_trip() {
+        let data = vec![vec![0.5, -1.0, 2.0], vec![0.0, 1.5, -0.25]];
+        let side = SideChannel {
+            ids: Some(vec![Some("a".to_string()), None]),
+            metadata: Some(vec![
+                None,
+                Some(HashMap::from([("k".to_string(), "v".to_string())])),
+            ]),
+            collection: Some("docs".to_string()),
+            model: None,
+        };
+        for dtype in [Dtype::F32, Dtype::F16, Dtype::Bf16] {
+            let vectors = decode(&encode(&data, dtype, &side).unwrap()).unwrap();
+            assert_eq!(vectors.len(), 2);
+            // These values are exact at every dtype
+            assert_eq!(vectors[1].data, data[1]);
+            assert_eq!(vectors[0].id, "a");
+            assert!(vectors[1].id.is_empty());
+            assert_eq!(vectors[1].metadata.as_ref().unwrap()["k"], "v");
+            assert_eq!(vectors[0].collection.as_deref(), Some("docs"));
+        }
+
+        let body = encode(&data, Dtype::F32, &SideChannel::default()).unwrap();
+        assert_eq!(decode(&body).unwrap()[0].data, data[0]);
+        assert!(decode(&body[..body.len() - 1]).is_err());
+        let short = SideChannel {
+            ids: Some(vec![None]),
+            ..Default::default()
+        };
+        assert!(decode(&encode(&data, Dtype::F32, &short).unwrap()).is_err());
+        assert!(encode(&[vec![1.0], vec![1.0, 2.0]], Dtype::F32, &side).is_err());
+    }
+}
@@ -0,0 +1,558 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use skypier_core::{ChangeSet, VectorDatabase};
+use skypier_network::{
+    MemberEvent, MemberStatus, NodeHandle, ReplicationRequest, ReplicationResponse, RequestHandler,
+    TransferRequest, TransferResponse,
+};
+use std::collections::{BTreeMap, HashSet};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+use crate::namespace::Namespaces;
+
+// Setting holding the primary's WAL seq a follower's namespace has caught
+// up to. It's missing until the first full sync finishes.
+const REPLICATION_SEQ_SETTING: &str = "replication_seq";
+// Batches of `batch_size` vectors a full sync asks for at once
+const TRANSFER_BATCHES: usize = 16;
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+// Serves this node's namespaces to followers over the P2P layer
+pub struct ReplicationSource {
+    namespaces: Arc<Namespaces>,
+}
+
+impl ReplicationSource {
+    pub fn new(namespaces: Arc<Namespaces>) -> Self {
+        Self { namespaces }
+    }
+
+    async fn database(&self, name: &str) -> Result<Arc<VectorDatabase>> {
+        self.namespaces
+            .get(name)
+            .await?
+            .ok_or_else(|| anyhow!("Namespace '{}' not found", name))
+    }
+
+    async fn answer(&self, request: ReplicationRequest) -> Result<ReplicationResponse> {
+        Ok(match request {
+            ReplicationRequest::Namespaces => {
+                ReplicationResponse::Namespaces(self.namespaces.names().await?)
+            }
+            ReplicationRequest::Snapshot {
+                namespace,
+                after,
+                limit,
+            } => {
+                let db = self.database(&namespace).await?;
+                // Read first, so replaying the changes after it covers
+                // whatever the scroll misses
+                let seq = db.wal_head().await?;
+                let page = db.scroll(None, after.as_deref(), limit).await?;
+                ReplicationResponse::Snapshot {
+                    seq,
+                    vectors: page.vectors,
+                    next: page.next,
+                }
+            }
+            ReplicationRequest::Changes {
+                namespace,
+                since,
+                limit,
+            } => {
+                let db = self.database(&namespace).await?;
+                ReplicationResponse::Changes(db.changes_page(since, limit).await?)
+            }
+        })
+    }
+
+    // Up to `request.batches` pages of the namespace after the cursor
+    async fn read_batches(&self, request: TransferRequest) -> Result<TransferResponse> {
+        let db = self.database(&request.namespace).await?;
+        let mut response = TransferResponse {
+            seq: db.wal_head().await?,
+            next: request.after,
+            ..Default::default()
+        };
+        while response.batches.len() < request.batches.max(1) {
+            let page = db
+                .scroll(None, response.next.as_deref(), request.batch_size.max(1))
+                .await?;
+            if !page.vectors.is_empty() {
+                response.batches.push(page.vectors);
+            }
+            response.next = page.next;
+            if response.next.is_none() {
+                break;
+            }
+        }
+        Ok(response)
+    }
+}
+
+#[async_trait]
+impl RequestHandler for ReplicationSource {
+    async fn handle(&self, request: ReplicationRequest) -> ReplicationResponse {
+        self.answer(request)
+            .await
+            .unwrap_or_else(|e| ReplicationResponse::Error(e.to_string()))
+    }
+
+    async fn transfer(&self, request: TransferRequest) -> TransferResponse {
+        self.read_batches(request)
+            .await
+            .unwrap_or_else(|e| TransferResponse::failed(e.to_string()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FollowerState {
+    // Copying a namespace in full
+    Syncing,
+    // Applying the primary's changes as they come
+    Following,
+    // Promoted, so taking writes itself
+    Promoted,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FollowerStatus {
+    pub primary: String,
+    pub state: FollowerState,
+    // Primary WAL seq each namespace has caught up to
+    pub namespaces: BTreeMap<String, u64>,
+    pub last_contact: Option<u64>,
+    pub last_error: Option<String>,
+    // What cluster membership last said about the primary
+    pub primary_status: Option<MemberStatus>,
+}
+
+// Keeps this node's namespaces a read-only copy of a primary's by polling
+// its changefeed over the P2P layer, until promoted
+pub struct Follower {
+    primary: String,
+    node: NodeHandle,
+    namespaces: Arc<Namespaces>,
+    batch_size: usize,
+    poll_interval: Duration,
+    // Takes local writes too, so local vectors the primary lacks are kept
+    writable: bool,
+    promoted: AtomicBool,
+    promote_notify: Notify,
+    // Held while a page is applied, so promotion waits for it
+    applying: tokio::sync::Mutex<()>,
+    status: Mutex<FollowerStatus>,
+}
+
+impl Follower {
+    pub fn new(primary: &str, node: NodeHandle, namespaces: Arc<Namespaces>) -> Self {
+        Self {
+            primary: primary.to_string(),
+            node,
+            namespaces,
+            batch_size: 256,
+            poll_interval: Duration::from_secs(1),
+            writable: false,
+            promoted: AtomicBool::new(false),
+            promote_notify: Notify::new(),
+            applying: tokio::sync::Mutex::new(()),
+            status: Mutex::new(FollowerStatus {
+                primary: primary.to_string(),
+                state: FollowerState::Syncing,
+                namespaces: BTreeMap::new(),
+                last_contact: None,
+                last_error: None,
+                primary_status: None,
+            }),
+        }
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    pub fn with_writable(mut self, writable: bool) -> Self {
+        self.writable = writable;
+        self
+    }
+
+    pub fn status(&self) -> FollowerStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    pub fn is_promoted(&self) -> bool {
+        self.promoted.load(Ordering::Acquire)
+    }
+
+    // Stops following and makes every namespace writable. Replication isn't
+    // picked up again until restart, so `[replication] primary` should be
+    // cleared before then.
+    pub async fn promote(&self) {
+        self.promoted.store(true, Ordering::Release);
+        self.promote_notify.notify_one();
+        let _applying = self.applying.lock().await;
+        self.namespaces.set_read_only(false).await;
+        self.status.lock().unwrap().state = FollowerState::Promoted;
+        info!("Promoted from follower of {}; taking writes", self.primary);
+    }
+
+    // Follows the primary until promoted or `shutdown` resolves
+    pub async fn run(self: Arc<Self>, shutdown: impl Future<Output = ()>) {
+        tokio::pin!(shutdown);
+        info!("Following primary {}", self.primary);
+        let mut members = self.node.subscribe();
+        while !self.is_promoted() {
+            let wait = match self.sync_once().await {
+                Ok(true) => self.poll_interval,
+                Ok(false) => Duration::ZERO,
+                Err(_) if self.is_promoted() => break,
+                Err(e) => {
+                    warn!("Replication from {} failed: {}", self.primary, e);
+                    self.status.lock().unwrap().last_error = Some(e.to_string());
+                    self.poll_interval
+                }
+            };
+            let next_sync = tokio::time::sleep(wait);
+            tokio::pin!(next_sync);
+            loop {
+                tokio::select! {
+                    _ = &mut shutdown => return,
+                    _ = self.promote_notify.notified() => break,
+                    _ = &mut next_sync => break,
+                    Ok(event) = members.recv() => self.member_changed(event).await,
+                }
+            }
+        }
+    }
+
+    // Tracks what cluster membership says about the primary
+    async fn member_changed(&self, event: MemberEvent) {
+        let primary = self.node.peer_id(&self.primary).await.ok().flatten();
+        if primary.as_deref() != Some(event.member.id.as_str()) {
+            return;
+        }
+        if event.member.status == MemberStatus::Dead {
+            warn!(
+                "Primary {} failed; POST /admin/replication/promote to take over from it",
+                self.primary
+            );
+        }
+        self.status.lock().unwrap().primary_status = Some(event.member.status);
+    }
+
+    async fn request(&self, request: ReplicationRequest) -> Result<ReplicationResponse> {
+        let response = self.node.request(&self.primary, request).await?;
+        self.status.lock().unwrap().last_contact = Some(unix_now());
+        match response {
+            ReplicationResponse::Error(e) => Err(anyhow!("The primary answered: {}", e)),
+            response => Ok(response),
+        }
+    }
+
+    // One round over every namespace of the primary, making the ones missing
+    // here. True when they were all caught up already.
+    async fn sync_once(&self) -> Result<bool> {
+        let names = match self.request(ReplicationRequest::Namespaces).await? {
+            ReplicationResponse::Namespaces(names) => names,
+            other => return Err(unexpected(other)),
+        };
+        let mut caught_up = true;
+        for name in names {
+            let Some(db) = self.namespaces.create(&name).await? else {
+                return Err(anyhow!(
+                    "The primary has namespace '{}', but namespaces aren't enabled here",
+                    name
+                ));
+            };
+            caught_up &= self.sync_namespace(&name, &db).await?;
+        }
+        if caught_up {
+            let mut status = self.status.lock().unwrap();
+            if status.state == FollowerState::Syncing {
+                status.state = FollowerState::Following;
+            }
+            status.last_error = None;
+        }
+        Ok(caught_up)
+    }
+
+    // Applies the next page of changes, or copies the namespace in full when
+    // there's nothing to resume from. True when there was nothing to apply.
+    async fn sync_namespace(&self, name: &str, db: &VectorDatabase) -> Result<bool> {
+        let Some(since) = db.get_setting(REPLICATION_SEQ_SETTING).await? else {
+            self.full_sync(name, db).await?;
+            return Ok(false);
+        };
+        let since: u64 = since.parse()?;
+        let request = ReplicationRequest::Changes {
+            namespace: name.to_string(),
+            since,
+            limit: self.batch_size,
+        };
+        match self.request(request).await? {
+            ReplicationResponse::Changes(Some(changes)) if changes.seq == since => {
+                self.set_seq(name, since);
+                Ok(true)
+            }
+            ReplicationResponse::Changes(Some(changes)) => {
+                self.apply(name, db, &changes).await?;
+                Ok(false)
+            }
+            ReplicationResponse::Changes(None) => {
+                warn!(
+                    "The primary can't resume namespace '{}' from seq {}; syncing it in full",
+                    name, since
+                );
+                self.full_sync(name, db).await?;
+                Ok(false)
+            }
+            other => Err(unexpected(other)),
+        }
+    }
+
+    // Copies every vector of the primary's namespace over the transfer
+    // protocol and, unless writable, drops local ones it doesn't have, then
+    // records the seq to follow its changes from. A transfer that breaks
+    // off resumes after the last batch that arrived whole.
+    async fn full_sync(&self, name: &str, db: &VectorDatabase) -> Result<()> {
+        info!("Syncing namespace '{}' in full from {}", name, self.primary);
+        self.status.lock().unwrap().state = FollowerState::Syncing;
+        let mut seq = None;
+        let mut seen = HashSet::new();
+        let mut after = None;
+        loop {
+            let request = TransferRequest {
+                namespace: name.to_string(),
+                after: after.clone(),
+                batch_size: self.batch_size,
+                batches: TRANSFER_BATCHES,
+            };
+            let response = self.node.transfer(&self.primary, request).await?;
+            self.status.lock().unwrap().last_contact = Some(unix_now());
+            let resume = response.last_id().map(str::to_string);
+            if let (Some(e), None) = (&response.error, &resume) {
+                return Err(anyhow!("The transfer from the primary failed: {}", e));
+            }
+            // Changes replayed from the first answer's seq cover anything
+            // the later ones miss
+            let seq = *seq.get_or_insert(response.seq);
+            for vectors in response.batches {
+                seen.extend(vectors.iter().map(|vector| vector.id.clone()));
+                let changes = ChangeSet {
+                    seq,
+                    upserts: vectors,
+                    deletes: Vec::new(),
+                };
+                self.apply_page(db, &changes).await?;
+            }
+            after = match (response.error, response.next) {
+                (Some(e), _) => {
+                    warn!(
+                        "Transfer of namespace '{}' from {} broke off ({}); resuming",
+                        name, self.primary, e
+                    );
+                    resume
+                }
+                (None, Some(next)) => Some(next),
+                (None, None) => break,
+            };
+        }
+
+        let stale = if self.writable {
+            Vec::new()
+        } else {
+            missing_from(db, &seen).await?
+        };
+        let changes = ChangeSet {
+            seq: seq.unwrap_or_default(),
+            upserts: Vec::new(),
+            deletes: stale,
+        };
+        self.apply(name, db, &changes).await?;
+        info!(
+            "Synced namespace '{}' ({} vectors) as of seq {}",
+            name,
+            seen.len(),
+            changes.seq
+        );
+        Ok(())
+    }
+
+    // Applies `changes` and records its seq as caught up to
+    async fn apply(&self, name: &str, db: &VectorDatabase, changes: &ChangeSet) -> Result<()> {
+        self.apply_page(db, changes).await?;
+        db.put_setting(REPLICATION_SEQ_SETTING, &changes.seq.to_string())
+            .await?;
+        self.set_seq(name, changes.seq);
+        Ok(())
+    }
+
+    async fn apply_page(&self, db: &VectorDatabase, changes: &ChangeSet) -> Result<()> {
+        let _applying = self.applying.lock().await;
+        if self.is_promoted() {
+            return Err(anyhow!("Promoted while replicating"));
+        }
+        db.apply_changes(changes).await
+    }
+
+    fn set_seq(&self, name: &str, seq: u64) {
+        self.status
+            .lock()
+            .unwrap()
+            .namespaces
+            .insert(name.to_string(), seq);
+    }
+}
+
+// Ids stored in `db` that aren't in `seen`
+async fn missing_from(db: &VectorDatabase, seen: &HashSet<String>) -> Result<Vec<String>> {
+    let mut missing = Vec::new();
+    let mut after: Option<String> = None;
+    loop {
+        let page = db.scroll(None, after.as_deref(), 1000).await?;
+        missing.extend(
+            page.vectors
+                .into_iter()
+                .map(|vector| vector.id)
+                .filter(|id| !seen.contains(id)),
+        );
+        after = match page.next {
+            Some(next) => Some(next),
+            None => return Ok(missing),
+        };
+    }
+}
+
+fn unexpected(response: ReplicationResponse) -> anyhow::Error {
+    anyhow!("Unexpected response from the primary: {:?}", response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use skypier_core::Vector;
+    use skypier_network::{NetworkConfig, P2PNode};
+
+    async fn listen_address(node: &NodeHandle) -> String {
+        loop {
+            let addresses = node.listen_addresses().await.unwrap();
+            if let Some(addr) = addresses
+                .into_iter()
+                .find(|addr| addr.contains("127.0.0.1"))
+            {
+                return addr;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    async fn until(done: impl Fn() -> bool) {
+        for _ in 0..500 {
+            if done() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("timed out");
+    }
+
+    #[tokio::test]
+    async fn test_follower_copies_primary_until_promoted() {
+        let primary_dir = tempfile::tempdir().unwrap();
+        let follower_dir = tempfile::tempdir().unwrap();
+        let primary = Arc::new(
+            VectorDatabase::new(primary_dir.path().to_str().unwrap())
+                .await
+                .unwrap(),
+        );
+        primary.load_index().await.unwrap();
+        let follower_db = Arc::new(
+            VectorDatabase::new(follower_dir.path().to_str().unwrap())
+                .await
+                .unwrap(),
+        );
+        follower_db.load_index().await.unwrap();
+        // Left over from before, and not on the primary
+        follower_db
+            .insert_vectors(vec![Vector::with_id("stale".to_string(), vec![1.0, 1.0])])
+            .await
+            .unwrap();
+        for id in ["a", "b", "c"] {
+            primary
+                .insert_vectors(vec![Vector::with_id(id.to_string(), vec![1.0, 0.0])])
+                .await
+                .unwrap();
+        }
+
+        let config = NetworkConfig {
+            port: 0,
+            ..Default::default()
+        };
+        let primary_namespaces = Arc::new(Namespaces::single(Arc::clone(&primary)));
+        let mut primary_node = P2PNode::new(config.clone())
+            .await
+            .unwrap()
+            .with_handler(Arc::new(ReplicationSource::new(primary_namespaces)));
+        let primary_handle = primary_node.handle();
+        tokio::spawn(async move { primary_node.start().await });
+        let primary_addr = listen_address(&primary_handle).await;
+        let mut follower_node = P2PNode::new(config).await.unwrap();
+        let handle = follower_node.handle();
+        tokio::spawn(async move { follower_node.start().await });
+
+        let namespaces = Arc::new(Namespaces::single(Arc::clone(&follower_db)));
+        namespaces.set_read_only(true).await;
+        let follower = Arc::new(
+            Follower::new(&primary_addr, handle, Arc::clone(&namespaces))
+                .with_batch_size(2)
+                .with_poll_interval(Duration::from_millis(10)),
+        );
+        let (_stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        tokio::spawn(Arc::clone(&follower).run(async {
+            let _ = stopped.await;
+        }));
+
+        until(|| follower.status().state == FollowerState::Following).await;
+        assert_eq!(follower_db.get_stats().await.unwrap().total_vectors, 3);
+        assert!(follower_db.get_vector("stale").await.unwrap().is_none());
+        assert!(follower_db
+            .insert_vectors(vec![Vector::with_id("x".to_string(), vec![1.0, 0.0])])
+            .await
+            .is_err());
+
+        primary
+            .insert_vectors(vec![Vector::with_id("d".to_string(), vec![0.0, 1.0])])
+            .await
+            .unwrap();
+        primary.delete_vector("a").await.unwrap();
+        until(|| follower.status().namespaces.get("default") == Some(&5)).await;
+        assert!(follower_db.get_vector("d").await.unwrap().is_some());
+        assert!(follower_db.get_vector("a").await.unwrap().is_none());
+
+        follower.promote().await;
+        assert_eq!(follower.status().state, FollowerState::Promoted);
+        follower_db
+            .insert_vectors(vec![Vector::with_id("x".to_string(), vec![1.0, 0.0])])
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,366 @@
+// Sends owners' writes on to the other replicas of their collections.
+// Writes for one replica and namespace queue up while a batch is on its
+// way and go out together as the next one, where a later write to an id
+// replaces an earlier one still waiting. Batches are held back to the
+// configured ops and bytes per second, counted across all replicas, so a
+// bulk import can't swamp the P2P links.
+
+use serde::{Deserialize, Serialize};
+use skypier_core::{ChangeSet, Vector};
+use skypier_network::{ForwardRequest, ForwardResponse, Member, NodeHandle};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+use crate::cluster;
+use crate::config::ThrottleConfig;
+
+pub struct Replicator {
+    max_batch: usize,
+    ops: Option<Mutex<Bucket>>,
+    bytes: Option<Mutex<Bucket>>,
+    // By replica id and namespace
+    queues: Mutex<HashMap<(String, String), Queue>>,
+    batches_sent: AtomicU64,
+    ops_sent: AtomicU64,
+    ops_coalesced: AtomicU64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplicationStatus {
+    pub ops_per_second: u64,
+    pub bytes_per_second: u64,
+    // Writes queued or on their way that replicas haven't acknowledged
+    pub backlog_ops: usize,
+    pub backlog_bytes: usize,
+    pub batches_sent: u64,
+    pub ops_sent: u64,
+    // Writes dropped because a later one to the same id replaced them
+    pub ops_coalesced: u64,
+    pub queues: Vec<QueueStatus>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueStatus {
+    pub replica: Member,
+    pub namespace: String,
+    pub backlog_ops: usize,
+    pub backlog_bytes: usize,
+}
+
+// Writes waiting for one replica and namespace. The backlog counts them and
+// the batch being sent.
+struct Queue {
+    replica: Member,
+    upserts: BTreeMap<String, (Vector, usize)>,
+    deletes: BTreeMap<String, usize>,
+    waiters: Vec<oneshot::Sender<Result<(), String>>>,
+    sending: bool,
+    backlog_ops: usize,
+    backlog_bytes: usize,
+}
+
+impl Queue {
+    fn new(replica: Member) -> Self {
+        Self {
+            replica,
+            upserts: BTreeMap::new(),
+            deletes: BTreeMap::new(),
+            waiters: Vec::new(),
+            sending: false,
+            backlog_ops: 0,
+            backlog_bytes: 0,
+        }
+    }
+
+    // Adds `changes`, returning how many waiting writes they replaced
+    fn push(&mut self, changes: ChangeSet) -> usize {
+        let mut replaced = Vec::new();
+        for vector in changes.upserts {
+            let size = serde_json::to_vec(&vector).map_or(0, |json| json.len());
+            replaced.push(self.deletes.remove(&vector.id));
+            replaced.push(
+                self.upserts
+                    .insert(vector.id.clone(), (vector, size))
+                    .map(|(_, size)| size),
+            );
+            self.backlog_bytes += size;
+        }
+        for id in changes.deletes {
+            let size = id.len();
+            replaced.push(self.upserts.remove(&id).map(|(_, size)| size));
+            replaced.push(self.deletes.insert(id, size));
+            self.backlog_bytes += size;
+        }
+        let added = replaced.len() / 2;
+        let replaced: Vec<usize> = replaced.into_iter().flatten().collect();
+        self.backlog_ops += added - replaced.len();
+        self.backlog_bytes -= replaced.iter().sum::<usize>();
+        replaced.len()
+    }
+
+    // Everything waiting, as batches of at most `max_batch` writes with
+    // their size in bytes
+    fn take(&mut self, max_batch: usize) -> Vec<(ChangeSet, usize)> {
+        let max_batch = max_batch.max(1);
+        let upserts: Vec<_> = std::mem::take(&mut self.upserts).into_values().collect();
+        let deletes: Vec<_> = std::mem::take(&mut self.deletes).into_iter().collect();
+        let mut batches = Vec::new();
+        for chunk in upserts.chunks(max_batch) {
+            let changes = ChangeSet {
+                upserts: chunk.iter().map(|(vector, _)| vector.clone()).collect(),
+                ..Default::default()
+            };
+            batches.push((changes, chunk.iter().map(|(_, size)| size).sum()));
+        }
+        for chunk in deletes.chunks(max_batch) {
+            let changes = ChangeSet {
+                deletes: chunk.iter().map(|(id, _)| id.clone()).collect(),
+                ..Default::default()
+            };
+            batches.push((changes, chunk.iter().map(|(_, size)| size).sum()));
+        }
+        batches
+    }
+}
+
+// Tokens come back at `rate` per second, up to one second's worth. Taking
+// more than there are leaves a debt to wait out, so batches bigger than the
+// rate still go out, just no faster than it on average.
+struct Bucket {
+    rate: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(rate: u64) -> Option<Mutex<Self>> {
+        (rate > 0).then(|| {
+            Mutex::new(Self {
+                rate: rate as f64,
+                tokens: rate as f64,
+                updated: Instant::now(),
+            })
+        })
+    }
+
+    // Takes `cost` tokens and says how long to wait before using them
+    fn take(&mut self, cost: usize, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate) - cost as f64;
+        self.updated = now;
+        Duration::from_secs_f64((-self.tokens).max(0.0) / self.rate)
+    }
+}
+
+impl Replicator {
+    pub fn from_config(config: &ThrottleConfig) -> Self {
+        Self {
+            max_batch: config.max_batch,
+            ops: Bucket::new(config.ops_per_second),
+            bytes: Bucket::new(config.bytes_per_second),
+            queues: Mutex::new(HashMap::new()),
+            batches_sent: AtomicU64::new(0),
+            ops_sent: AtomicU64::new(0),
+            ops_coalesced: AtomicU64::new(0),
+        }
+    }
+
+    // Queues `changes` for `replica` and waits until it has applied them,
+    // along with whatever else went out in the same batch
+    pub async fn send(
+        self: &Arc<Self>,
+        node: &NodeHandle,
+        replica: &Member,
+        namespace: &str,
+        changes: ChangeSet,
+    ) -> Result<(), String> {
+        let key = (replica.id.clone(), namespace.to_string());
+        let (done, sent) = oneshot::channel();
+        let start = {
+            let mut queues = self.queues.lock().unwrap();
+            let queue = queues
+                .entry(key.clone())
+                .or_insert_with(|| Queue::new(replica.clone()));
+            let replaced = queue.push(changes);
+            self.ops_coalesced
+                .fetch_add(replaced as u64, Ordering::Relaxed);
+            queue.waiters.push(done);
+            !std::mem::replace(&mut queue.sending, true)
+        };
+        if start {
+            tokio::spawn(Arc::clone(self).drain(node.clone(), key));
+        }
+        sent.await
+            .unwrap_or_else(|_| Err("The replication queue was dropped".to_string()))
+    }
+
+    // Sends batches from one queue until it's empty, then drops it
+    async fn drain(self: Arc<Self>, node: NodeHandle, key: (String, String)) {
+        loop {
+            let (replica, batches, waiters) = {
+                let mut queues = self.queues.lock().unwrap();
+                let Some(queue) = queues.get_mut(&key) else {
+                    return;
+                };
+                if queue.waiters.is_empty() {
+                    queues.remove(&key);
+                    return;
+                }
+                (
+                    queue.replica.clone(),
+                    queue.take(self.max_batch),
+                    std::mem::take(&mut queue.waiters),
+                )
+            };
+            let result = self.send_batches(&node, &key, &replica, batches).await;
+            for waiter in waiters {
+                let _ = waiter.send(result.clone());
+            }
+        }
+    }
+
+    async fn send_batches(
+        &self,
+        node: &NodeHandle,
+        key: &(String, String),
+        replica: &Member,
+        batches: Vec<(ChangeSet, usize)>,
+    ) -> Result<(), String> {
+        let mut result = Ok(());
+        for (changes, bytes) in batches {
+            let ops = changes.upserts.len() + changes.deletes.len();
+            if result.is_ok() {
+                self.throttle(ops, bytes).await;
+                let request = ForwardRequest::Replicate {
+                    namespace: key.1.clone(),
+                    changes,
+                };
+                result = match cluster::forward(node, replica, request).await {
+                    Ok(ForwardResponse::Replicated) => {
+                        self.batches_sent.fetch_add(1, Ordering::Relaxed);
+                        self.ops_sent.fetch_add(ops as u64, Ordering::Relaxed);
+                        Ok(())
+                    }
+                    Ok(_) => Err(cluster::unexpected(replica).message),
+                    Err(e) => Err(e.message),
+                };
+            }
+            // A batch that failed and the ones after it aren't retried
+            if let Some(queue) = self.queues.lock().unwrap().get_mut(key) {
+                queue.backlog_ops -= ops;
+                queue.backlog_bytes -= bytes;
+            }
+        }
+        result
+    }
+
+    async fn throttle(&self, ops: usize, bytes: usize) {
+        let now = Instant::now();
+        let wait = [(&self.ops, ops), (&self.bytes, bytes)]
+            .into_iter()
+            .filter_map(|(bucket, cost)| Some(bucket.as_ref()?.lock().unwrap().take(cost, now)))
+            .max()
+            .unwrap_or_default();
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    pub fn status(&self) -> ReplicationStatus {
+        let rate = |bucket: &Option<Mutex<Bucket>>| {
+            bucket
+                .as_ref()
+                .map_or(0, |bucket| bucket.lock().unwrap().rate as u64)
+        };
+        let queues: Vec<QueueStatus> = self
+            .queues
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|((_, namespace), queue)| QueueStatus {
+                replica: queue.replica.clone(),
+                namespace: namespace.clone(),
+                backlog_ops: queue.backlog_ops,
+                backlog_bytes: queue.backlog_bytes,
+            })
+            .collect();
+        ReplicationStatus {
+            ops_per_second: rate(&self.ops),
+            bytes_per_second: rate(&self.bytes),
+            backlog_ops: queues.iter().map(|queue| queue.backlog_ops).sum(),
+            backlog_bytes: queues.iter().map(|queue| queue.backlog_bytes).sum(),
+            batches_sent: self.batches_sent.load(Ordering::Relaxed),
+            ops_sent: self.ops_sent.load(Ordering::Relaxed),
+            ops_coalesced: self.ops_coalesced.load(Ordering::Relaxed),
+            queues,
+        }
+    }
+}
+
+impl Default for Replicator {
+    fn default() -> Self {
+        Self::from_config(&ThrottleConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_queued_writes_coalesce_and_throttle() {
+        let vector = |id: &str| Vector::with_id(id.to_string(), vec![1.0, 0.0]);
+        let mut queue = Queue::new(Member {
+            id: "peer".to_string(),
+            addr: "/memory/1".to_string(),
+            status: skypier_network::MemberStatus::Alive,
+            incarnation: 0,
+            last_seen: None,
+            shard_owner: true,
+            labels: BTreeMap::new(),
+        });
+        let changes = |upserts: Vec<Vector>, deletes: Vec<&str>| ChangeSet {
+            upserts,
+            deletes: deletes.into_iter().map(String::from).collect(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            queue.push(changes(vec![vector("a"), vector("b")], vec![])),
+            0
+        );
+        assert_eq!(queue.push(changes(vec![vector("a")], vec!["b", "c"])), 2);
+        assert_eq!(queue.backlog_ops, 3);
+        let one = serde_json::to_vec(&vector("a")).unwrap().len();
+        assert_eq!(queue.backlog_bytes, one + 2);
+
+        let batches = queue.take(1);
+        let ops: Vec<_> = batches
+            .iter()
+            .map(|(changes, bytes)| (changes.upserts.len(), changes.deletes.clone(), *bytes))
+            .collect();
+        assert_eq!(
+            ops,
+            vec![
+                (1, vec![], one),
+                (0, vec!["b".to_string()], 1),
+                (0, vec!["c".to_string()], 1)
+            ]
+        );
+        assert!(queue.upserts.is_empty() && queue.deletes.is_empty());
+
+        // A second's worth goes out at once, anything past it waits its turn
+        let start = Instant::now();
+        let mut bucket = Bucket::new(100).unwrap().into_inner().unwrap();
+        bucket.updated = start;
+        assert_eq!(bucket.take(100, start), Duration::ZERO);
+        assert_eq!(bucket.take(50, start), Duration::from_millis(500));
+        assert_eq!(
+            bucket.take(50, start + Duration::from_secs(1)),
+            Duration::ZERO
+        );
+    }
+}
@@ -0,0 +1,164 @@
+use serde::{Deserialize, Serialize};
+use skypier_core::{SearchProfile, SparseVector};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use tracing::warn;
+
+// A search that took longer than the threshold. The query itself isn't
+// kept, only a hash to spot repeats by.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlowQuery {
+    pub timestamp: u64,
+    pub namespace: String,
+    pub query_hash: String,
+    pub dimensions: usize,
+    pub k: usize,
+    // Collection and metadata keys filtered on, without their values
+    pub filter: Option<String>,
+    pub duration_ms: f64,
+    // Only dense searches are broken down
+    pub profile: Option<SearchProfile>,
+}
+
+impl SlowQuery {
+    pub fn new(
+        namespace: &str,
+        vector: &[f32],
+        sparse: Option<&SparseVector>,
+        k: usize,
+        collection: Option<&str>,
+        metadata: Option<&HashMap<String, String>>,
+    ) -> Self {
+        Self {
+            timestamp: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or(0),
+            namespace: namespace.to_string(),
+            query_hash: query_hash(vector, sparse),
+            dimensions: vector.len(),
+            k,
+            filter: filter_summary(collection, metadata),
+            duration_ms: 0.0,
+            profile: None,
+        }
+    }
+}
+
+fn query_hash(vector: &[f32], sparse: Option<&SparseVector>) -> String {
+    let mut hasher = DefaultHasher::new();
+    for value in vector {
+        value.to_bits().hash(&mut hasher);
+    }
+    if let Some(sparse) = sparse {
+        sparse.indices.hash(&mut hasher);
+        for value in &sparse.values {
+            value.to_bits().hash(&mut hasher);
+        }
+    }
+    format!("{:016x}", hasher.finish())
+}
+
+fn filter_summary(
+    collection: Option<&str>,
+    metadata: Option<&HashMap<String, String>>,
+) -> Option<String> {
+    let mut parts = Vec::new();
+    if let Some(collection) = collection {
+        parts.push(format!("collection={}", collection));
+    }
+    let mut keys: Vec<&str> = metadata
+        .into_iter()
+        .flat_map(|metadata| metadata.keys().map(String::as_str))
+        .collect();
+    if !keys.is_empty() {
+        keys.sort_unstable();
+        parts.push(format!("metadata={}", keys.join(",")));
+    }
+    (!parts.is_empty()).then(|| parts.join(" "))
+}
+
+// Logs searches slower than `threshold` and keeps the last `capacity` of
+// them for GET /admin/slow-queries
+pub struct SlowQueryLog {
+    threshold: Duration,
+    capacity: usize,
+    entries: Mutex<VecDeque<SlowQuery>>,
+}
+
+impl SlowQueryLog {
+    pub fn new(threshold: Duration, capacity: usize) -> Self {
+        Self {
+            threshold,
+            capacity,
+            entries: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    // Keeps `query` if it took at least the threshold
+    pub fn record(&self, mut query: SlowQuery, elapsed: Duration) {
+        if elapsed < self.threshold {
+            return;
+        }
+        query.duration_ms = elapsed.as_secs_f64() * 1000.0;
+        warn!(
+            "Slow search {} in namespace '{}' took {:.1}ms: dimensions={} k={} filter={} profile={:?}",
+            query.query_hash,
+            query.namespace,
+            query.duration_ms,
+            query.dimensions,
+            query.k,
+            query.filter.as_deref().unwrap_or("none"),
+            query.profile
+        );
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(query);
+    }
+
+    // Newest first
+    pub fn recent(&self) -> Vec<SlowQuery> {
+        self.entries.lock().unwrap().iter().rev().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slow_query_ring_buffer() {
+        let log = SlowQueryLog::new(Duration::from_millis(10), 2);
+        let filter = HashMap::from([
+            ("lang".to_string(), "en".to_string()),
+            ("author".to_string(), "secret".to_string()),
+        ]);
+        let query = SlowQuery::new("default", &[1.0, 0.0], None, 5, Some("docs"), Some(&filter));
+        assert_eq!(
+            query.filter.as_deref(),
+            Some("collection=docs metadata=author,lang")
+        );
+        assert_eq!(
+            query.query_hash,
+            SlowQuery::new("other", &[1.0, 0.0], None, 1, None, None).query_hash
+        );
+
+        log.record(query.clone(), Duration::from_millis(5));
+        assert!(log.recent().is_empty());
+        for k in 1..=3 {
+            let query = SlowQuery { k, ..query.clone() };
+            log.record(query, Duration::from_millis(20));
+        }
+        let recent = log.recent();
+        assert_eq!(recent.iter().map(|q| q.k).collect::<Vec<_>>(), vec![3, 2]);
+        assert!(recent[0].duration_ms >= 20.0);
+    }
+}
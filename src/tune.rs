@@ -0,0 +1,365 @@
+use anyhow::{anyhow, Result};
+use skypier_core::{DistanceMetric, Dtype, Vector};
+use skypier_index::{BinaryIndex, HnswIndex, SearchResult, VectorIndex};
+use std::fmt;
+use std::time::Instant;
+use tracing::info;
+
+use crate::bench;
+
+// Parameter grid swept by `skypier-vecdb tune`. ef_search is swept per built
+// graph since it only affects queries. The binary index has no graph, so it
+// sweeps its rescore factor instead.
+const QUANTIZATION: &[Quantization] = &[
+    Quantization::None,
+    Quantization::Scalar,
+    Quantization::Binary,
+];
+const MAX_CONNECTIONS: &[usize] = &[8, 16, 32];
+const EF_CONSTRUCTION: &[usize] = &[100, 200, 400];
+const EF_SEARCH: &[usize] = &[16, 32, 64, 128, 256];
+const RESCORE_FACTOR: &[usize] = &[2, 4, 8];
+
+// Searches the index under trial with (query, n, ef_search)
+type Search<'a> = dyn Fn(&[f32], usize, usize) -> Result<Vec<SearchResult>> + 'a;
+
+#[derive(Debug, Clone)]
+pub struct TuneTargets {
+    pub recall: f32,
+    pub max_latency_ms: f64,
+    pub k: usize,
+}
+
+// How vectors are stored for a trial: full precision, rounded to f16
+// (`[storage] dtype = "f16"`), or the binary index with rescoring
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Quantization {
+    None,
+    Scalar,
+    Binary,
+}
+
+impl fmt::Display for Quantization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Quantization::None => "none",
+            Quantization::Scalar => "scalar",
+            Quantization::Binary => "binary",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Trial {
+    pub quantization: Quantization,
+    // Graph settings; zero for binary trials
+    pub max_connections: usize,
+    pub ef_construction: usize,
+    pub ef_search: usize,
+    // Only set for binary trials
+    pub rescore_factor: usize,
+    pub recall: f32,
+    pub p95_latency_ms: f64,
+}
+
+impl Trial {
+    fn meets(&self, targets: &TuneTargets) -> bool {
+        self.recall >= targets.recall && self.p95_latency_ms <= targets.max_latency_ms
+    }
+}
+
+// Builds an index for every grid point and measures recall@k against exact
+// search over the full-precision vectors, plus p95 query latency, with the
+// `bench recall` harness. `num_queries` vectors are sampled as queries.
+pub fn sweep(dataset: &[Vec<f32>], num_queries: usize, k: usize) -> Result<Vec<Trial>> {
+    if dataset.len() < 2 {
+        return Err(anyhow!("The dataset needs at least two vectors"));
+    }
+    let vectors: Vec<Vector> = dataset
+        .iter()
+        .enumerate()
+        .map(|(i, data)| Vector::with_id(i.to_string(), data.clone()))
+        .collect();
+    let ids: Vec<&str> = vectors.iter().map(|vector| vector.id.as_str()).collect();
+    let measure = |search: &Search, settings: &[usize]| {
+        bench::measure(
+            &vectors,
+            DistanceMetric::Cosine,
+            num_queries,
+            k,
+            settings,
+            search,
+        )
+    };
+
+    let mut trials = Vec::new();
+    for &quantization in QUANTIZATION {
+        if quantization == Quantization::Binary {
+            for &rescore_factor in RESCORE_FACTOR {
+                let index = BinaryIndex::new().with_rescore(rescore_factor);
+                let data: Vec<&[f32]> = dataset.iter().map(Vec::as_slice).collect();
+                index.build_batch(&ids, &data)?;
+                let search = |query: &[f32], n, _| index.search(query, n);
+                for row in measure(&search, &[0])? {
+                    let trial = Trial {
+                        quantization,
+                        max_connections: 0,
+                        ef_construction: 0,
+                        ef_search: 0,
+                        rescore_factor,
+                        recall: row.recall,
+                        p95_latency_ms: row.p95_latency_ms,
+                    };
+                    info!(
+                        "binary rescore_factor={}: recall@{}={:.3} p95={:.3}ms",
+                        rescore_factor, k, trial.recall, trial.p95_latency_ms
+                    );
+                    trials.push(trial);
+                }
+            }
+            continue;
+        }
+
+        let mut stored = dataset.to_vec();
+        if quantization == Quantization::Scalar {
+            stored.iter_mut().for_each(|data| Dtype::F16.round(data));
+        }
+        let data: Vec<&[f32]> = stored.iter().map(Vec::as_slice).collect();
+        for &max_connections in MAX_CONNECTIONS {
+            for &ef_construction in EF_CONSTRUCTION {
+                let started = Instant::now();
+                let index = HnswIndex::new(dataset[0].len())?
+                    .with_max_connections(max_connections)
+                    .with_ef_construction(ef_construction);
+                index.build_batch(&ids, &data)?;
+                info!(
+                    "Built {} M={} ef_construction={} in {:?}",
+                    quantization,
+                    max_connections,
+                    ef_construction,
+                    started.elapsed()
+                );
+
+                let search = |query: &[f32], n, ef| Ok(index.search_with_ef(query, n, ef));
+                for row in measure(&search, EF_SEARCH)? {
+                    let trial = Trial {
+                        quantization,
+                        max_connections,
+                        ef_construction,
+                        ef_search: row.ef_search,
+                        rescore_factor: 0,
+                        recall: row.recall,
+                        p95_latency_ms: row.p95_latency_ms,
+                    };
+                    info!(
+                        "{} M={} ef_construction={} ef_search={}: recall@{}={:.3} p95={:.3}ms",
+                        quantization,
+                        max_connections,
+                        ef_construction,
+                        trial.ef_search,
+                        k,
+                        trial.recall,
+                        trial.p95_latency_ms
+                    );
+                    trials.push(trial);
+                }
+            }
+        }
+    }
+
+    Ok(trials)
+}
+
+pub(crate) fn percentile(values: &mut [f64], p: f64) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    values.sort_by(|a, b| a.total_cmp(b));
+    let rank = ((values.len() as f64 * p).ceil() as usize).clamp(1, values.len());
+    values[rank - 1]
+}
+
+// Picks the fastest trial meeting both targets, preferring cheaper graphs on
+// ties. When nothing meets them, falls back to the best recall.
+pub fn recommend<'a>(trials: &'a [Trial], targets: &TuneTargets) -> Option<(&'a Trial, bool)> {
+    let cost = |t: &Trial| {
+        (
+            t.max_connections,
+            t.ef_construction,
+            t.ef_search,
+            t.rescore_factor,
+        )
+    };
+
+    let passing = trials.iter().filter(|t| t.meets(targets)).min_by(|a, b| {
+        a.p95_latency_ms
+            .total_cmp(&b.p95_latency_ms)
+            .then_with(|| cost(a).cmp(&cost(b)))
+    });
+    if let Some(trial) = passing {
+        return Some((trial, true));
+    }
+
+    trials
+        .iter()
+        .max_by(|a, b| {
+            a.recall
+                .total_cmp(&b.recall)
+                .then_with(|| b.p95_latency_ms.total_cmp(&a.p95_latency_ms))
+        })
+        .map(|trial| (trial, false))
+}
+
+pub fn render_config(trial: &Trial, met_targets: bool, targets: &TuneTargets) -> String {
+    let mut section = String::from("# Generated by `skypier-vecdb tune`\n");
+    section.push_str(&format!(
+        "# recall@{} = {:.3} (target {:.3}), p95 latency = {:.3}ms (target {:.3}ms)\n",
+        targets.k, trial.recall, targets.recall, trial.p95_latency_ms, targets.max_latency_ms
+    ));
+    if !met_targets {
+        section.push_str("# No configuration met both targets; this one has the best recall\n");
+    }
+    section.push_str(&format!("# quantization = {}\n", trial.quantization));
+    if trial.quantization == Quantization::Binary {
+        section.push_str(&format!(
+            "[index]\nindex_type = \"binary\"\nrescore_factor = {}\n",
+            trial.rescore_factor
+        ));
+        return section;
+    }
+    section.push_str(&format!(
+        "[index]\nmax_connections = {}\nef_construction = {}\nef_search = {}\n",
+        trial.max_connections, trial.ef_construction, trial.ef_search
+    ));
+    if trial.quantization == Quantization::Scalar {
+        section.push_str("\n[storage]\ndtype = \"f16\"\n");
+    }
+    section
+}
+
+pub fn run(
+    dataset_path: &str,
+    num_queries: usize,
+    targets: &TuneTargets,
+    output: Option<&str>,
+) -> Result<()> {
+    let dataset: Vec<Vec<f32>> = crate::dataset::load_vectors(dataset_path)?
+        .into_iter()
+        .map(|vector| vector.data)
+        .collect();
+    info!(
+        "Tuning on {} vectors from {} (recall@{} >= {}, p95 <= {}ms)",
+        dataset.len(),
+        dataset_path,
+        targets.k,
+        targets.recall,
+        targets.max_latency_ms
+    );
+
+    let trials = sweep(&dataset, num_queries, targets.k)?;
+    let (trial, met_targets) =
+        recommend(&trials, targets).ok_or_else(|| anyhow!("No configurations were tried"))?;
+    let section = render_config(trial, met_targets, targets);
+
+    match output {
+        Some(path) => {
+            std::fs::write(path, &section)?;
+            info!("Wrote recommended settings to {}", path);
+        }
+        None => print!("{}", section),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trial(max_connections: usize, ef_search: usize, recall: f32, latency: f64) -> Trial {
+        Trial {
+            quantization: Quantization::None,
+            max_connections,
+            ef_construction: 200,
+            ef_search,
+            rescore_factor: 0,
+            recall,
+            p95_latency_ms: latency,
+        }
+    }
+
+    #[test]
+    fn test_recommend_fastest_passing_trial() {
+        let targets = TuneTargets {
+            recall: 0.9,
+            max_latency_ms: 2.0,
+            k: 10,
+        };
+        let trials = vec![
+            trial(8, 16, 0.80, 0.5),
+            trial(16, 32, 0.92, 1.0),
+            trial(16, 64, 0.97, 1.5),
+            trial(32, 256, 0.99, 3.0),
+        ];
+
+        let (best, met) = recommend(&trials, &targets).unwrap();
+        assert!(met);
+        assert_eq!((best.max_connections, best.ef_search), (16, 32));
+
+        let strict = TuneTargets {
+            recall: 0.999,
+            ..targets
+        };
+        let (best, met) = recommend(&trials, &strict).unwrap();
+        assert!(!met);
+        assert_eq!(best.ef_search, 256);
+        assert!(render_config(best, met, &strict).contains("ef_search = 256"));
+
+        let scalar = Trial {
+            quantization: Quantization::Scalar,
+            ..trial(16, 32, 0.95, 0.8)
+        };
+        let binary = Trial {
+            quantization: Quantization::Binary,
+            max_connections: 0,
+            ef_construction: 0,
+            ef_search: 0,
+            rescore_factor: 4,
+            recall: 0.93,
+            p95_latency_ms: 0.6,
+        };
+        let trials = [trials, vec![scalar.clone(), binary]].concat();
+        let (best, _) = recommend(&trials, &targets).unwrap();
+        assert_eq!(best.quantization, Quantization::Binary);
+        let section = render_config(best, true, &targets);
+        assert!(section.contains("index_type = \"binary\"\nrescore_factor = 4"));
+        assert!(render_config(&scalar, true, &targets).contains("[storage]\ndtype = \"f16\""));
+    }
+
+    #[test]
+    fn test_sweep_measures_recall() {
+        // Deterministic spread of 2D directions
+        let dataset: Vec<Vec<f32>> = (0..60)
+            .map(|i| {
+                let angle = i as f32 * 0.37;
+                vec![angle.cos(), angle.sin()]
+            })
+            .collect();
+
+        let trials = sweep(&dataset, 10, 5).unwrap();
+        let graphs = MAX_CONNECTIONS.len() * EF_CONSTRUCTION.len() * EF_SEARCH.len();
+        assert_eq!(trials.len(), 2 * graphs + RESCORE_FACTOR.len());
+        assert!(trials.iter().all(|t| (0.0..=1.0).contains(&t.recall)));
+        for quantization in QUANTIZATION {
+            assert!(trials.iter().any(|t| t.quantization == *quantization));
+        }
+        let best = |quantization| {
+            trials
+                .iter()
+                .filter(|t| t.quantization == quantization)
+                .map(|t| t.recall)
+                .fold(0.0, f32::max)
+        };
+        assert_eq!(best(Quantization::None), 1.0);
+        assert!(best(Quantization::Scalar) >= 0.9);
+    }
+}
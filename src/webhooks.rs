@@ -0,0 +1,313 @@
+use anyhow::{anyhow, Result};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use skypier_core::{ChangeEvent, ChangeKind, VectorDatabase};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+use crate::config::WebhookConfig;
+
+pub const SIGNATURE_HEADER: &str = "x-skypier-signature";
+pub const TIMESTAMP_HEADER: &str = "x-skypier-timestamp";
+
+// Delay before the first retry, doubling after each
+const RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+// The body of a delivery
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookPayload {
+    pub namespace: String,
+    pub events: Vec<ChangeEvent>,
+}
+
+// Hex HMAC-SHA256 of "{timestamp}.{body}". Signing the timestamp lets
+// receivers refuse replayed deliveries.
+pub fn sign(secret: &[u8], timestamp: u64, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes keys of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+// POSTs a namespace's changes to a URL in batches, after they're written
+pub struct Webhook {
+    url: String,
+    namespace: String,
+    secret: Option<Vec<u8>>,
+    events: Vec<ChangeKind>,
+    collections: Vec<String>,
+    batch_size: usize,
+    flush_interval: Duration,
+    max_retries: u32,
+    client: reqwest::Client,
+}
+
+impl Webhook {
+    pub fn from_config(config: &WebhookConfig, default_namespace: &str) -> Result<Self> {
+        if config.url.is_empty() {
+            return Err(anyhow!("Webhooks need a url"));
+        }
+        let secret = match &config.secret_env {
+            Some(var) => Some(
+                std::env::var(var)
+                    .map_err(|_| anyhow!("Webhook secret variable {} isn't set", var))?
+                    .into_bytes(),
+            ),
+            None => None,
+        };
+        Ok(Self {
+            url: config.url.clone(),
+            namespace: config
+                .namespace
+                .clone()
+                .unwrap_or_else(|| default_namespace.to_string()),
+            secret,
+            events: config.events.clone(),
+            collections: config.collections.clone(),
+            batch_size: config.batch_size.max(1),
+            flush_interval: Duration::from_millis(config.flush_interval_ms),
+            max_retries: config.max_retries,
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_millis(config.timeout_ms))
+                .build()?,
+        })
+    }
+
+    pub fn with_secret(mut self, secret: &[u8]) -> Self {
+        self.secret = Some(secret.to_vec());
+        self
+    }
+
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    fn wants(&self, event: &ChangeEvent) -> bool {
+        (self.events.is_empty() || self.events.contains(&event.kind))
+            && (self.collections.is_empty()
+                || event
+                    .collection
+                    .as_ref()
+                    .is_some_and(|collection| self.collections.contains(collection)))
+    }
+
+    // Sends one batch, retrying failures and non-2xx answers with backoff.
+    // The batch is dropped once the retries run out.
+    async fn deliver(&self, events: Vec<ChangeEvent>) {
+        let count = events.len();
+        let body = match serde_json::to_vec(&WebhookPayload {
+            namespace: self.namespace.clone(),
+            events,
+        }) {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("Failed to encode webhook batch: {}", e);
+                return;
+            }
+        };
+        let mut backoff = RETRY_BACKOFF;
+        for attempt in 0..=self.max_retries {
+            if attempt > 0 {
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or_default();
+            let mut request = self
+                .client
+                .post(&self.url)
+                .header("content-type", "application/json")
+                .header(TIMESTAMP_HEADER, timestamp.to_string());
+            if let Some(secret) = &self.secret {
+                let signature = sign(secret, timestamp, &body);
+                request = request.header(SIGNATURE_HEADER, format!("sha256={}", signature));
+            }
+            match request.body(body.clone()).send().await {
+                Ok(response) if response.status().is_success() => return,
+                Ok(response) => warn!(
+                    "Webhook {} answered {} (attempt {})",
+                    self.url,
+                    response.status(),
+                    attempt + 1
+                ),
+                Err(e) => warn!(
+                    "Webhook {} failed: {} (attempt {})",
+                    self.url,
+                    e,
+                    attempt + 1
+                ),
+            }
+        }
+        warn!(
+            "Dropped {} changes for webhook {} after {} retries",
+            count, self.url, self.max_retries
+        );
+    }
+
+    // Batches the database's changes until `batch_size` are waiting or the
+    // first has waited `flush_interval`, and delivers each batch before
+    // taking more. Changes made while deliveries are slow enough for the
+    // feed to overflow are skipped, with a warning.
+    pub async fn run(self, db: Arc<VectorDatabase>, shutdown: impl Future<Output = ()>) {
+        let mut changes = db.subscribe();
+        tokio::pin!(shutdown);
+        info!("Sending {} changes to webhook {}", self.namespace, self.url);
+        let mut batch = Vec::new();
+        let mut deadline = None;
+        loop {
+            let flush = async {
+                match deadline {
+                    Some(deadline) => tokio::time::sleep_until(deadline).await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                change = changes.recv() => match change {
+                    Ok(event) => {
+                        if self.wants(&event) {
+                            batch.push(event);
+                            deadline.get_or_insert(Instant::now() + self.flush_interval);
+                        }
+                        if batch.len() < self.batch_size {
+                            continue;
+                        }
+                    }
+                    Err(RecvError::Lagged(missed)) => {
+                        warn!("Webhook {} missed {} changes", self.url, missed);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+                _ = flush => {}
+                _ = &mut shutdown => break,
+            }
+            deadline = None;
+            self.deliver(std::mem::take(&mut batch)).await;
+        }
+        if !batch.is_empty() {
+            self.deliver(batch).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::State;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::routing::post;
+    use axum::Router;
+    use skypier_core::Vector;
+    use std::sync::Mutex;
+
+    type Received = Arc<Mutex<Vec<(HeaderMap, Vec<u8>)>>>;
+
+    // Fails the first request, then records the rest
+    async fn receive(
+        State(received): State<Received>,
+        headers: HeaderMap,
+        body: axum::body::Bytes,
+    ) -> StatusCode {
+        let mut received = received.lock().unwrap();
+        received.push((headers, body.to_vec()));
+        if received.len() == 1 {
+            StatusCode::SERVICE_UNAVAILABLE
+        } else {
+            StatusCode::OK
+        }
+    }
+
+    #[tokio::test]
+    async fn test_webhook_delivers_signed_batches() {
+        let received = Received::default();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let app = Router::new()
+            .route("/hook", post(receive))
+            .with_state(Arc::clone(&received));
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let dir = tempfile::tempdir().unwrap();
+        let db = Arc::new(
+            VectorDatabase::new(dir.path().to_str().unwrap())
+                .await
+                .unwrap(),
+        );
+        let config = WebhookConfig {
+            url,
+            collections: vec!["docs".to_string()],
+            batch_size: 2,
+            flush_interval_ms: 50,
+            ..Default::default()
+        };
+        let webhook = Webhook::from_config(&config, "default")
+            .unwrap()
+            .with_secret(b"secret");
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let task = tokio::spawn(webhook.run(Arc::clone(&db), async {
+            let _ = stopped.await;
+        }));
+        // Let it subscribe
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        db.insert_vectors(vec![
+            Vector::with_id("a".to_string(), vec![1.0]).with_collection("docs".to_string()),
+            Vector::with_id("b".to_string(), vec![1.0]).with_collection("notes".to_string()),
+            Vector::with_id("c".to_string(), vec![1.0]).with_collection("docs".to_string()),
+        ])
+        .await
+        .unwrap();
+        db.delete_vector("a").await.unwrap();
+
+        // The first batch is retried, the delete goes out on the interval
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while received.lock().unwrap().len() < 3 && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let _ = stop.send(());
+        task.await.unwrap();
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 3);
+        assert_eq!(received[0].1, received[1].1);
+        let batches: Vec<Vec<(ChangeKind, String)>> = received[1..]
+            .iter()
+            .map(|(headers, body)| {
+                let timestamp: u64 = headers[TIMESTAMP_HEADER].to_str().unwrap().parse().unwrap();
+                assert_eq!(
+                    headers[SIGNATURE_HEADER].to_str().unwrap(),
+                    format!("sha256={}", sign(b"secret", timestamp, body))
+                );
+                let payload: WebhookPayload = serde_json::from_slice(body).unwrap();
+                assert_eq!(payload.namespace, "default");
+                payload
+                    .events
+                    .into_iter()
+                    .map(|event| (event.kind, event.id))
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            batches,
+            vec![
+                vec![
+                    (ChangeKind::Insert, "a".to_string()),
+                    (ChangeKind::Insert, "c".to_string())
+                ],
+                vec![(ChangeKind::Delete, "a".to_string())],
+            ]
+        );
+    }
+}